@@ -1,41 +1,57 @@
+use std::collections::HashMap;
+
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
 use color_eyre::eyre::eyre;
 use common_x::restful::{
-    axum::{
-        Json,
-        extract::{Query, State},
-        response::IntoResponse,
-    },
+    axum::{Json, extract::State, response::IntoResponse},
     ok, ok_simple,
 };
-use sea_query::{Expr, ExprTrait, Order, PostgresQueryBuilder};
+use sea_query::{Expr, ExprTrait, Order, PostgresQueryBuilder, UnionType};
 use sea_query_sqlx::SqlxBinder;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use sqlx::{Executor, query_as_with, query_with};
 use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
 use crate::{
     AppView,
-    api::{SignedBody, SignedParam, build_author},
-    atproto::{NSID_COMMENT, NSID_POST, NSID_REPLY, NSID_SECTION},
+    api::{
+        SignedBody, SignedParam, build_author, build_moderator, check_session, is_moderator,
+        record::{self, NewRecord, indexed_view},
+        valid::{Valid, ValidQuery},
+    },
+    atproto::{Collection, NSID_POST, NSID_SECTION, get_record},
+    broadcast::{self, Audience},
+    config::LogVerbosity,
+    db,
     error::AppError,
     lexicon::{
+        PENDING_REVIEW,
         administrator::{Administrator, AdministratorView},
+        broadcast::{Broadcast, BroadcastRow, BroadcastView},
         comment::Comment,
+        like::Like,
         notify::{Notify, NotifyRow, NotifyType},
         operation::{ActionType, Operation, OperationRow, OperationView},
         post::Post,
+        removed_repo::{ACCOUNT_INACTIVE, REMOVED_BY_AUTHOR},
         reply::Reply,
         resolve_uri,
-        section::Section,
+        section::{Section, ckb_addr_or_none},
+        section_ban::SectionBan,
         whitelist::Whitelist,
     },
+    recount::{self, RecountGuard, RecountScope},
+    webhook::WebhookPayload,
 };
 
 #[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
 #[serde(default)]
 pub(crate) struct UpdateTagParams {
+    #[validate(length(min = 1))]
     pub uri: String,
     pub is_top: Option<bool>,
     pub is_announcement: Option<bool>,
@@ -50,80 +66,52 @@ impl SignedParam for UpdateTagParams {
     }
 }
 
+impl UpdateTagParams {
+    /// Only posts can be pinned or announced; comments and replies can only
+    /// be hidden.
+    fn check_fields(&self, collection: Collection) -> Result<(), AppError> {
+        let post_only = [
+            ("is_top", self.is_top.is_some()),
+            ("is_announcement", self.is_announcement.is_some()),
+        ];
+        match collection {
+            Collection::Post => Ok(()),
+            Collection::Comment | Collection::Reply => match post_only.iter().find(|(_, set)| *set)
+            {
+                Some((field, _)) => Err(AppError::ValidateFailed(format!(
+                    "{field} is not supported for {collection}"
+                ))),
+                None => Ok(()),
+            },
+            _ => Err(AppError::ValidateFailed(format!(
+                "update_tag is not supported for {collection}"
+            ))),
+        }
+    }
+}
+
 #[utoipa::path(post, path = "/api/admin/update_tag")]
 pub(crate) async fn update_tag(
     State(state): State<AppView>,
-    Json(body): Json<SignedBody<UpdateTagParams>>,
+    Valid(Json(body)): Valid<Json<SignedBody<UpdateTagParams>>>,
 ) -> Result<impl IntoResponse, AppError> {
-    body.validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
-
     let (did, nsid, _rkey) = resolve_uri(&body.params.uri)
         .map_err(|_| AppError::ValidateFailed("invalid uri".to_string()))?;
-    let section_id = match nsid {
-        NSID_POST => {
-            let (sql, values) = sea_query::Query::select()
-                .columns([(Post::Table, Post::SectionId)])
-                .from(Post::Table)
-                .and_where(Expr::col(Post::Uri).eq(body.params.uri.clone()))
-                .build_sqlx(PostgresQueryBuilder);
-            let row: (i32,) = query_as_with(&sql, values.clone())
-                .fetch_one(&state.db)
-                .await
-                .map_err(|e| {
-                    debug!("exec sql failed: {e}");
-                    AppError::NotFound
-                })?;
-            row.0
-        }
-        NSID_REPLY => {
-            let (sql, values) = sea_query::Query::select()
-                .columns([(Reply::Table, Reply::SectionId)])
-                .from(Reply::Table)
-                .and_where(Expr::col(Reply::Uri).eq(body.params.uri.clone()))
-                .build_sqlx(PostgresQueryBuilder);
-            let row: (i32,) = query_as_with(&sql, values.clone())
-                .fetch_one(&state.db)
-                .await
-                .map_err(|e| {
-                    debug!("exec sql failed: {e}");
-                    AppError::NotFound
-                })?;
-            row.0
-        }
-        NSID_COMMENT => {
-            let (sql, values) = sea_query::Query::select()
-                .columns([(Comment::Table, Comment::SectionId)])
-                .from(Comment::Table)
-                .and_where(Expr::col(Comment::Uri).eq(body.params.uri.clone()))
-                .build_sqlx(PostgresQueryBuilder);
-            let row: (i32,) = query_as_with(&sql, values.clone())
-                .fetch_one(&state.db)
-                .await
-                .map_err(|e| {
-                    debug!("exec sql failed: {e}");
-                    AppError::NotFound
-                })?;
-            row.0
-        }
-        _ => return Err(eyre!("nsid is not allowed!").into()),
-    };
-
-    let section_row = Section::select_by_id(&state.db, section_id)
-        .await
-        .map_err(|e| {
-            debug!("exec sql failed: {e}");
-            AppError::NotFound
-        })?;
+    let collection: Collection = nsid.parse().map_err(AppError::ValidateFailed)?;
+    body.params.check_fields(collection)?;
+    let section_id = section_of_target(&state.db, collection, &body.params.uri).await?;
 
-    let admins = Administrator::all_did(&state.db).await;
-
-    if section_row.owner == Some(body.did.clone()) || admins.contains(&body.did) {
-        body.verify_signature(&state.indexer)
+    if can_moderate(&state.db, section_id, &body.did).await? {
+        body.verify_signature(&state)
             .await
             .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
-        match nsid {
-            NSID_POST => {
+        // content shown for the first time is announced as created
+        let displayed = match body.params.is_disabled {
+            Some(false) => pending_review_record(&state.db, collection, &body.params.uri).await?,
+            _ => None,
+        };
+        match collection {
+            Collection::Post => {
                 Post::update_tag(
                     &state.db,
                     &body.params.uri,
@@ -134,7 +122,7 @@ pub(crate) async fn update_tag(
                 )
                 .await?;
             }
-            NSID_REPLY => {
+            Collection::Reply => {
                 Reply::update_tag(
                     &state.db,
                     &body.params.uri,
@@ -143,7 +131,7 @@ pub(crate) async fn update_tag(
                 )
                 .await?;
             }
-            NSID_COMMENT => {
+            Collection::Comment => {
                 Comment::update_tag(
                     &state.db,
                     &body.params.uri,
@@ -155,6 +143,17 @@ pub(crate) async fn update_tag(
             _ => return Err(eyre!("nsid is not allowed!").into()),
         }
 
+        if body.params.is_disabled.is_some() {
+            state
+                .webhooks
+                .emit(WebhookPayload::moderated(&body.params.uri, section_id, did));
+        }
+        if let Some(record) = displayed
+            && let Some(payload) = WebhookPayload::created(nsid, did, &body.params.uri, &record)
+        {
+            state.webhooks.emit(payload);
+        }
+
         // notify
         if let Some(true) = body.params.is_disabled {
             Notify::insert(
@@ -180,10 +179,10 @@ pub(crate) async fn update_tag(
                     id: 0,
                     section_id,
                     operator: body.did.to_string(),
-                    action_type: match nsid {
-                        NSID_POST => ActionType::DisablePost as i32,
-                        NSID_REPLY => ActionType::DisableReply as i32,
-                        NSID_COMMENT => ActionType::DisableComment as i32,
+                    action_type: match collection {
+                        Collection::Post => ActionType::DisablePost as i32,
+                        Collection::Reply => ActionType::DisableReply as i32,
+                        Collection::Comment => ActionType::DisableComment as i32,
                         _ => return Err(eyre!("nsid is not allowed!").into()),
                     },
                     action: "隐藏帖子".to_string(),
@@ -219,10 +218,10 @@ pub(crate) async fn update_tag(
                     id: 0,
                     section_id,
                     operator: body.did.to_string(),
-                    action_type: match nsid {
-                        NSID_POST => ActionType::EnablePost as i32,
-                        NSID_REPLY => ActionType::EnableReply as i32,
-                        NSID_COMMENT => ActionType::EnableComment as i32,
+                    action_type: match collection {
+                        Collection::Post => ActionType::EnablePost as i32,
+                        Collection::Reply => ActionType::EnableReply as i32,
+                        Collection::Comment => ActionType::EnableComment as i32,
                         _ => return Err(eyre!("nsid is not allowed!").into()),
                     },
                     action: "取消隐藏".to_string(),
@@ -313,9 +312,150 @@ pub(crate) async fn update_tag(
     Ok(ok_simple())
 }
 
+/// The section of the post, comment or reply at `uri`.
+async fn section_of_target(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    collection: Collection,
+    uri: &str,
+) -> Result<i32, AppError> {
+    let section_id = match collection {
+        Collection::Post => {
+            let (sql, values) = sea_query::Query::select()
+                .columns([(Post::Table, Post::SectionId)])
+                .from(Post::Table)
+                .and_where(Expr::col(Post::Uri).eq(uri))
+                .build_sqlx(PostgresQueryBuilder);
+            let row: (i32,) = db::fetch_one(db, &sql, values.clone()).await.map_err(|e| {
+                debug!("exec sql failed: {e}");
+                AppError::NotFound
+            })?;
+            row.0
+        }
+        Collection::Reply => {
+            let (sql, values) = sea_query::Query::select()
+                .columns([(Reply::Table, Reply::SectionId)])
+                .from(Reply::Table)
+                .and_where(Expr::col(Reply::Uri).eq(uri))
+                .build_sqlx(PostgresQueryBuilder);
+            let row: (i32,) = db::fetch_one(db, &sql, values.clone()).await.map_err(|e| {
+                debug!("exec sql failed: {e}");
+                AppError::NotFound
+            })?;
+            row.0
+        }
+        Collection::Comment => {
+            let (sql, values) = sea_query::Query::select()
+                .columns([(Comment::Table, Comment::SectionId)])
+                .from(Comment::Table)
+                .and_where(Expr::col(Comment::Uri).eq(uri))
+                .build_sqlx(PostgresQueryBuilder);
+            let row: (i32,) = db::fetch_one(db, &sql, values.clone()).await.map_err(|e| {
+                debug!("exec sql failed: {e}");
+                AppError::NotFound
+            })?;
+            row.0
+        }
+        _ => return Err(eyre!("nsid is not allowed!").into()),
+    };
+    Ok(section_id)
+}
+
+/// The record of content a content rule shadowed until review, in the
+/// shape `WebhookPayload::created` reads; `None` when it is shown or was
+/// hidden otherwise.
+async fn pending_review_record(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    collection: Collection,
+    uri: &str,
+) -> Result<Option<Value>, AppError> {
+    // the title of a post, the post of a comment or reply
+    let (context, query) = match collection {
+        Collection::Post => (
+            "title",
+            sea_query::Query::select()
+                .columns([
+                    Post::SectionId,
+                    Post::Title,
+                    Post::Text,
+                    Post::ReasonsForDisabled,
+                ])
+                .from(Post::Table)
+                .and_where(Expr::col(Post::Uri).eq(uri))
+                .and_where(Expr::col(Post::IsDisabled).eq(true))
+                .take(),
+        ),
+        Collection::Comment => (
+            "post",
+            sea_query::Query::select()
+                .columns([
+                    Comment::SectionId,
+                    Comment::Post,
+                    Comment::Text,
+                    Comment::ReasonsForDisabled,
+                ])
+                .from(Comment::Table)
+                .and_where(Expr::col(Comment::Uri).eq(uri))
+                .and_where(Expr::col(Comment::IsDisabled).eq(true))
+                .take(),
+        ),
+        Collection::Reply => (
+            "post",
+            sea_query::Query::select()
+                .columns([
+                    Reply::SectionId,
+                    Reply::Post,
+                    Reply::Text,
+                    Reply::ReasonsForDisabled,
+                ])
+                .from(Reply::Table)
+                .and_where(Expr::col(Reply::Uri).eq(uri))
+                .and_where(Expr::col(Reply::IsDisabled).eq(true))
+                .take(),
+        ),
+        _ => return Ok(None),
+    };
+    let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+    let row: Option<(i32, String, String, Option<String>)> =
+        db::fetch_optional(db, &sql, values).await?;
+    Ok(row.and_then(|(section_id, context_value, text, reasons)| {
+        let by = HiddenReason::parse(reasons.as_deref()).by;
+        (by == HiddenBy::ContentRule).then(|| {
+            let mut record = json!({
+                "section_id": section_id.to_string(),
+                "text": text,
+            });
+            record[context] = json!(context_value);
+            record
+        })
+    }))
+}
+
+/// Whether `did` may moderate the content of the section: its owner or an
+/// administrator, the rule `is_moderator` applies on the read paths.
+/// Sections have no administrators of their own; the administrators are
+/// the global ones of the `administrator` table.
+async fn can_moderate(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    section_id: i32,
+    did: &str,
+) -> Result<bool, AppError> {
+    let section = Section::select_by_id(db, section_id).await.map_err(|e| {
+        debug!("exec sql failed: {e}");
+        AppError::NotFound
+    })?;
+    let admins = Administrator::all_did(db).await;
+    Ok(is_moderator(
+        &Some(did.to_string()),
+        section_id,
+        &HashMap::from([(section_id, section)]),
+        &admins,
+    ))
+}
+
 #[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
 #[serde(default)]
 pub(crate) struct UpdateOwnerParams {
+    #[validate(length(min = 1))]
     pub section: String,
     pub did: Option<String>,
     pub name: Option<String>,
@@ -331,17 +471,15 @@ impl SignedParam for UpdateOwnerParams {
 #[utoipa::path(post, path = "/api/admin/update_owner")]
 pub(crate) async fn update_owner(
     State(state): State<AppView>,
-    Json(body): Json<SignedBody<UpdateOwnerParams>>,
+    Valid(Json(body)): Valid<Json<SignedBody<UpdateOwnerParams>>>,
 ) -> Result<impl IntoResponse, AppError> {
-    body.validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
     let admins = Administrator::all_did(&state.db).await;
     if !admins.contains(&body.did) {
         return Err(AppError::ValidateFailed(
             "only administrator can update section owner".to_string(),
         ));
     }
-    body.verify_signature(&state.indexer)
+    body.verify_signature(&state)
         .await
         .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
 
@@ -366,17 +504,25 @@ pub(crate) async fn update_owner(
         }
     }
 
+    let section_id = body.params.section.parse::<i32>()?;
+    let previous_owner = Section::select_by_id(&state.db, section_id)
+        .await
+        .ok()
+        .and_then(|section| section.owner);
     let (sql, values) = sea_query::Query::update()
         .table(Section::Table)
         .values([
-            (Section::Owner, body.params.did.into()),
+            (Section::Owner, body.params.did.clone().into()),
             (Section::OwnerSetTime, Expr::current_timestamp()),
         ])
-        .and_where(Expr::col(Section::Id).eq(body.params.section.parse::<i32>()?))
+        .and_where(Expr::col(Section::Id).eq(section_id))
         .build_sqlx(PostgresQueryBuilder);
-    sqlx::query_with(&sql, values.clone())
-        .execute(&state.db)
-        .await?;
+    db::execute(&state.db, &sql, values.clone()).await?;
+
+    state.caches.invalidate_section(section_id).await;
+    for owner in [body.params.did, previous_owner].into_iter().flatten() {
+        state.caches.invalidate_author(&owner).await;
+    }
 
     Ok(ok_simple())
 }
@@ -384,12 +530,25 @@ pub(crate) async fn update_owner(
 #[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
 #[serde(default)]
 pub(crate) struct UpdateSectionParams {
+    #[validate(length(min = 1))]
     pub section: String,
     pub name: Option<String>,
     pub description: Option<String>,
     pub image: Option<String>,
     pub ckb_addr: Option<String>,
     pub is_disabled: Option<bool>,
+    /// Archived sections take no new content but stay readable;
+    /// administrators only.
+    pub is_archived: Option<bool>,
+    /// Whether notifications and the operation log name the moderator
+    /// instead of the section.
+    pub reveal_moderator: Option<bool>,
+    /// Drops the choice of the section, so `reveal_moderator` of the
+    /// config applies.
+    pub reveal_moderator_default: bool,
+    /// 0 lets anyone whitelisted comment and reply; above 0 only the owner
+    /// and administrators, for announcement-only sections.
+    pub comment_permission: Option<i32>,
     pub timestamp: i64,
 }
 
@@ -402,12 +561,10 @@ impl SignedParam for UpdateSectionParams {
 #[utoipa::path(post, path = "/api/admin/update_section")]
 pub(crate) async fn update_section(
     State(state): State<AppView>,
-    Json(body): Json<SignedBody<UpdateSectionParams>>,
+    Valid(Json(body)): Valid<Json<SignedBody<UpdateSectionParams>>>,
 ) -> Result<impl IntoResponse, AppError> {
-    body.validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
     let admins = Administrator::all_did(&state.db).await;
-    body.verify_signature(&state.indexer)
+    body.verify_signature(&state)
         .await
         .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
     let section_id = body.params.section.parse::<i32>()?;
@@ -424,9 +581,7 @@ pub(crate) async fn update_section(
             .value(Section::IsDisabled, is_disabled)
             .and_where(Expr::col(Section::Id).eq(section_id))
             .build_sqlx(PostgresQueryBuilder);
-        sqlx::query_with(&sql, values.clone())
-            .execute(&state.db)
-            .await?;
+        db::execute(&state.db, &sql, values.clone()).await?;
 
         Operation::insert(
             &state.db,
@@ -452,6 +607,103 @@ pub(crate) async fn update_section(
         .await
         .ok();
     }
+    if let Some(is_archived) = body.params.is_archived {
+        if !admins.contains(&body.did) {
+            return Err(AppError::ValidateFailed(
+                "only administrator can archive section".to_string(),
+            ));
+        }
+        let (sql, values) = sea_query::Query::update()
+            .table(Section::Table)
+            .value(Section::IsArchived, is_archived)
+            .and_where(Expr::col(Section::Id).eq(section_id))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(&state.db, &sql, values.clone()).await?;
+
+        Operation::insert(
+            &state.db,
+            OperationRow {
+                id: 0,
+                section_id,
+                operator: body.did.to_string(),
+                action_type: if is_archived {
+                    ActionType::ArchiveSection as i32
+                } else {
+                    ActionType::UnarchiveSection as i32
+                },
+                action: if is_archived {
+                    "归档版区".to_string()
+                } else {
+                    "取消归档版区".to_string()
+                },
+                message: String::new(),
+                target: format!("{}/{}", NSID_SECTION, section_id),
+                created: chrono::Local::now(),
+            },
+        )
+        .await
+        .ok();
+    }
+    if body.params.reveal_moderator.is_some() || body.params.reveal_moderator_default {
+        if !admins.contains(&body.did) && section.owner != Some(body.did.clone()) {
+            return Err(AppError::ValidateFailed(
+                "only administrator or section owner can update section".to_string(),
+            ));
+        }
+        let reveal_moderator = body
+            .params
+            .reveal_moderator
+            .filter(|_| !body.params.reveal_moderator_default);
+        let (sql, values) = sea_query::Query::update()
+            .table(Section::Table)
+            .value(Section::RevealModerator, reveal_moderator)
+            .and_where(Expr::col(Section::Id).eq(section_id))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(&state.db, &sql, values.clone()).await?;
+        Operation::insert(
+            &state.db,
+            OperationRow {
+                id: 0,
+                section_id,
+                operator: body.did.to_string(),
+                action_type: ActionType::UpdateModeratorPolicy as i32,
+                action: "更新版主公开策略".to_string(),
+                message: reveal_moderator.map(|r| r.to_string()).unwrap_or_default(),
+                target: format!("{}/{}", NSID_SECTION, section_id),
+                created: chrono::Local::now(),
+            },
+        )
+        .await
+        .ok();
+    }
+    if let Some(comment_permission) = body.params.comment_permission {
+        if !admins.contains(&body.did) && section.owner != Some(body.did.clone()) {
+            return Err(AppError::ValidateFailed(
+                "only administrator or section owner can update section".to_string(),
+            ));
+        }
+        let (sql, values) = sea_query::Query::update()
+            .table(Section::Table)
+            .value(Section::CommentPermission, comment_permission)
+            .and_where(Expr::col(Section::Id).eq(section_id))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(&state.db, &sql, values.clone()).await?;
+        Operation::insert(
+            &state.db,
+            OperationRow {
+                id: 0,
+                section_id,
+                operator: body.did.to_string(),
+                action_type: ActionType::UpdateCommentPermission as i32,
+                action: "更新版区评论权限".to_string(),
+                message: comment_permission.to_string(),
+                target: format!("{}/{}", NSID_SECTION, section_id),
+                created: chrono::Local::now(),
+            },
+        )
+        .await
+        .ok();
+    }
     if let Some(name) = &body.params.name {
         if !admins.contains(&body.did) && section.owner != Some(body.did.clone()) {
             return Err(AppError::ValidateFailed(
@@ -463,9 +715,7 @@ pub(crate) async fn update_section(
             .value(Section::Name, name.clone())
             .and_where(Expr::col(Section::Id).eq(body.params.section.parse::<i32>()?))
             .build_sqlx(PostgresQueryBuilder);
-        sqlx::query_with(&sql, values.clone())
-            .execute(&state.db)
-            .await?;
+        db::execute(&state.db, &sql, values.clone()).await?;
         Operation::insert(
             &state.db,
             OperationRow {
@@ -493,9 +743,7 @@ pub(crate) async fn update_section(
             .value(Section::Description, description.clone())
             .and_where(Expr::col(Section::Id).eq(body.params.section.parse::<i32>()?))
             .build_sqlx(PostgresQueryBuilder);
-        sqlx::query_with(&sql, values.clone())
-            .execute(&state.db)
-            .await?;
+        db::execute(&state.db, &sql, values.clone()).await?;
         Operation::insert(
             &state.db,
             OperationRow {
@@ -523,9 +771,7 @@ pub(crate) async fn update_section(
             .value(Section::Image, image.clone())
             .and_where(Expr::col(Section::Id).eq(body.params.section.parse::<i32>()?))
             .build_sqlx(PostgresQueryBuilder);
-        sqlx::query_with(&sql, values.clone())
-            .execute(&state.db)
-            .await?;
+        db::execute(&state.db, &sql, values.clone()).await?;
         Operation::insert(
             &state.db,
             OperationRow {
@@ -550,12 +796,10 @@ pub(crate) async fn update_section(
         }
         let (sql, values) = sea_query::Query::update()
             .table(Section::Table)
-            .value(Section::CkbAddr, ckb_addr.clone())
+            .value(Section::CkbAddr, ckb_addr_or_none(ckb_addr))
             .and_where(Expr::col(Section::Id).eq(body.params.section.parse::<i32>()?))
             .build_sqlx(PostgresQueryBuilder);
-        sqlx::query_with(&sql, values.clone())
-            .execute(&state.db)
-            .await?;
+        db::execute(&state.db, &sql, values.clone()).await?;
         Operation::insert(
             &state.db,
             OperationRow {
@@ -572,6 +816,160 @@ pub(crate) async fn update_section(
         .await
         .ok();
     }
+    state.caches.invalidate_section(section_id).await;
+
+    Ok(ok_simple())
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct BanParams {
+    pub section: String,
+    /// The user kept from writing in the section.
+    pub did: String,
+    #[validate(range(min = 1, max = 3650))]
+    pub days: u32,
+    /// Told to the user in the notification of the ban.
+    pub reason: String,
+    pub timestamp: i64,
+}
+
+impl SignedParam for BanParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Only administrators and the section owner ban, and neither of them can
+/// be banned.
+async fn check_ban_scope(
+    state: &AppView,
+    section_id: i32,
+    operator: &str,
+    did: &str,
+) -> Result<(), AppError> {
+    if !can_moderate(&state.db, section_id, operator).await? {
+        return Err(AppError::ValidateFailed(
+            "only administrator or section owner can ban".to_string(),
+        ));
+    }
+    if can_moderate(&state.db, section_id, did).await? {
+        return Err(AppError::ValidateFailed(
+            "administrators and the section owner cannot be banned".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Keeps the user from posting, commenting and replying in the section for
+/// `days`, replacing a ban they had there. The user is notified with the
+/// reason and when the ban ends.
+#[utoipa::path(post, path = "/api/admin/ban")]
+pub(crate) async fn ban(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<BanParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let section_id = body.params.section.parse::<i32>()?;
+    let did = body.params.did.as_str();
+    check_ban_scope(&state, section_id, &body.did, did).await?;
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let until = chrono::Local::now() + chrono::Duration::days(body.params.days.into());
+    SectionBan::upsert(
+        &state.db,
+        section_id,
+        did,
+        until,
+        &body.params.reason,
+        &body.did,
+    )
+    .await?;
+
+    Notify::insert(
+        &state.db,
+        &NotifyRow {
+            id: 0,
+            title: "Be Banned".to_string(),
+            sender: body.did.to_string(),
+            receiver: did.to_string(),
+            n_type: NotifyType::BeBanned as i32,
+            target_uri: format!("{}/{}", NSID_SECTION, section_id),
+            amount: 0,
+            readed: None,
+            created: chrono::Local::now(),
+        },
+    )
+    .await
+    .ok();
+    Operation::insert(
+        &state.db,
+        OperationRow {
+            id: 0,
+            section_id,
+            operator: body.did.to_string(),
+            action_type: ActionType::BanUser as i32,
+            action: "禁言用户".to_string(),
+            message: body.params.reason.clone(),
+            target: did.to_string(),
+            created: chrono::Local::now(),
+        },
+    )
+    .await
+    .ok();
+
+    Ok(ok(json!({ "until": until })))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct UnbanParams {
+    #[validate(length(min = 1))]
+    pub section: String,
+    #[validate(length(min = 1))]
+    pub did: String,
+    pub timestamp: i64,
+}
+
+impl SignedParam for UnbanParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Ends the ban of the user in the section now; `NotFound` when they are
+/// not banned there.
+#[utoipa::path(post, path = "/api/admin/unban")]
+pub(crate) async fn unban(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<UnbanParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let section_id = body.params.section.parse::<i32>()?;
+    let did = body.params.did.as_str();
+    check_ban_scope(&state, section_id, &body.did, did).await?;
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    if !SectionBan::lift(&state.db, section_id, did, chrono::Local::now()).await? {
+        return Err(AppError::NotFound);
+    }
+    Operation::insert(
+        &state.db,
+        OperationRow {
+            id: 0,
+            section_id,
+            operator: body.did.to_string(),
+            action_type: ActionType::UnbanUser as i32,
+            action: "解除禁言".to_string(),
+            message: String::new(),
+            target: did.to_string(),
+            created: chrono::Local::now(),
+        },
+    )
+    .await
+    .ok();
 
     Ok(ok_simple())
 }
@@ -596,20 +994,19 @@ impl SignedParam for CreateSectionParams {
 #[utoipa::path(post, path = "/api/admin/create_section")]
 pub(crate) async fn create_section(
     State(state): State<AppView>,
-    Json(body): Json<SignedBody<CreateSectionParams>>,
+    Valid(Json(body)): Valid<Json<SignedBody<CreateSectionParams>>>,
 ) -> Result<impl IntoResponse, AppError> {
-    body.validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
     let admins = Administrator::all_did(&state.db).await;
     if !admins.contains(&body.did) {
         return Err(AppError::ValidateFailed(
             "only administrator can create section owner".to_string(),
         ));
     }
-    body.verify_signature(&state.indexer)
+    body.verify_signature(&state)
         .await
         .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
 
+    let owner = body.params.owner.clone();
     let (sql, values) = sea_query::Query::insert()
         .into_table(Section::Table)
         .columns([
@@ -624,13 +1021,17 @@ pub(crate) async fn create_section(
             body.params.name.into(),
             body.params.description.into(),
             body.params.image.into(),
-            body.params.ckb_addr.into(),
-            body.params.owner.into(),
+            ckb_addr_or_none(&body.params.ckb_addr).into(),
+            owner.clone().into(),
             Expr::current_timestamp(),
         ])?
         .returning_col(Section::Id)
         .build_sqlx(PostgresQueryBuilder);
-    state.db.execute(query_with(&sql, values)).await?;
+    let (section_id,): (i32,) = db::fetch_one(&state.db, &sql, values)
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+    state.caches.invalidate_section(section_id).await;
+    state.caches.invalidate_author(&owner).await;
 
     Ok(ok_simple())
 }
@@ -651,23 +1052,25 @@ impl SignedParam for WhitelistParams {
 #[utoipa::path(post, path = "/api/admin/add_whitelist")]
 pub(crate) async fn add_whitelist(
     State(state): State<AppView>,
-    Json(body): Json<SignedBody<WhitelistParams>>,
+    Valid(Json(body)): Valid<Json<SignedBody<WhitelistParams>>>,
 ) -> Result<impl IntoResponse, AppError> {
-    body.validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
     let admins = Administrator::all_did(&state.db).await;
     if !admins.contains(&body.did) {
         return Err(AppError::ValidateFailed(
             "only administrator can add whitelist".to_string(),
         ));
     }
-    body.verify_signature(&state.indexer)
+    body.verify_signature(&state)
         .await
         .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
 
     for did in &body.params.whitelist {
         Whitelist::insert(&state.db, did).await.ok();
     }
+    state
+        .caches
+        .invalidate_whitelist(&body.params.whitelist)
+        .await;
 
     Operation::insert(
         &state.db,
@@ -691,23 +1094,25 @@ pub(crate) async fn add_whitelist(
 #[utoipa::path(post, path = "/api/admin/delete_whitelist")]
 pub(crate) async fn delete_whitelist(
     State(state): State<AppView>,
-    Json(body): Json<SignedBody<WhitelistParams>>,
+    Valid(Json(body)): Valid<Json<SignedBody<WhitelistParams>>>,
 ) -> Result<impl IntoResponse, AppError> {
-    body.validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
     let admins = Administrator::all_did(&state.db).await;
     if !admins.contains(&body.did) {
         return Err(AppError::ValidateFailed(
             "only administrator can delete whitelist".to_string(),
         ));
     }
-    body.verify_signature(&state.indexer)
+    body.verify_signature(&state)
         .await
         .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
 
     for did in &body.params.whitelist {
         Whitelist::delete(&state.db, did).await.ok();
     }
+    state
+        .caches
+        .invalidate_whitelist(&body.params.whitelist)
+        .await;
 
     Operation::insert(
         &state.db,
@@ -728,16 +1133,708 @@ pub(crate) async fn delete_whitelist(
     Ok(ok_simple())
 }
 
-#[utoipa::path(get, path = "/api/admin")]
-pub(crate) async fn list(State(state): State<AppView>) -> Result<impl IntoResponse, AppError> {
-    let rows = Administrator::all(&state.db).await;
-    let mut views: Vec<AdministratorView> = vec![];
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct FlushCacheParams {
+    pub timestamp: i64,
+}
 
-    for row in rows {
-        let author = build_author(&state, &row.did).await;
-        views.push(AdministratorView {
-            did: author,
-            permission: row.permission.to_string(),
+impl SignedParam for FlushCacheParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+#[utoipa::path(post, path = "/api/admin/flush_cache")]
+pub(crate) async fn flush_cache(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<FlushCacheParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let admins = Administrator::all_did(&state.db).await;
+    if !admins.contains(&body.did) {
+        return Err(AppError::ValidateFailed(
+            "only administrator can flush cache".to_string(),
+        ));
+    }
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let stats = state.caches.stats();
+    state.caches.invalidate_all();
+    info!("cache flushed by {}: {stats}", body.did);
+
+    Ok(ok(stats))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct RecountParams {
+    /// Only this section; everything when neither it nor `repo` is set.
+    pub section_id: Option<i32>,
+    /// Only this author.
+    pub repo: Option<String>,
+    pub timestamp: i64,
+}
+
+impl SignedParam for RecountParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Rebuild the cached author stats and section statistics of a scope from
+/// the source tables. Returns the source rows in scope and runs in the
+/// background; one recount at a time.
+#[utoipa::path(post, path = "/api/admin/recount")]
+pub(crate) async fn recount(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<RecountParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let (sql, values) = sea_query::Query::select()
+        .column(Administrator::Did)
+        .from(Administrator::Table)
+        .and_where(Expr::col(Administrator::Permission).eq(0))
+        .build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<(String,)> = db::fetch_all(&state.db, &sql, values)
+        .await
+        .unwrap_or_default();
+    if !rows.iter().any(|(did,)| did == &body.did) {
+        return Err(AppError::ValidateFailed(
+            "only super administrator can recount".to_string(),
+        ));
+    }
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let scope = match (body.params.section_id, body.params.repo) {
+        (None, None) => RecountScope::All,
+        (Some(id), None) => RecountScope::Section(id),
+        (None, Some(repo)) => RecountScope::Repo(repo),
+        (Some(_), Some(_)) => {
+            return Err(AppError::ValidateFailed(
+                "recount either a section or a repo".to_string(),
+            ));
+        }
+    };
+    let guard = RecountGuard::try_acquire().ok_or(AppError::ValidateFailed(
+        "a recount is already running".to_string(),
+    ))?;
+    let rows = recount::row_counts(&state.db, &scope).await?;
+    info!("recount {scope:?} started by {}: {rows:?}", body.did);
+    tokio::spawn(recount::run(state.clone(), scope, guard));
+
+    Ok(ok(json!({ "rows": rows })))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct ResyncParams {
+    #[validate(length(min = 1))]
+    pub uri: String,
+    pub timestamp: i64,
+}
+
+impl SignedParam for ResyncParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Fetch a record from the PDS and index it again, for records the relayer
+/// missed or that were removed from the local database. Nobody is notified
+/// and no thread is bumped.
+#[utoipa::path(post, path = "/api/admin/resync_record")]
+pub(crate) async fn resync_record(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<ResyncParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let admins = Administrator::all_did(&state.db).await;
+    if !admins.contains(&body.did) {
+        return Err(AppError::ValidateFailed(
+            "only administrator can resync record".to_string(),
+        ));
+    }
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let uri = &body.params.uri;
+    let (repo, nsid, rkey) =
+        resolve_uri(uri).map_err(|_| AppError::ValidateFailed("invalid uri".to_string()))?;
+    let collection: Collection = nsid.parse().map_err(AppError::ValidateFailed)?;
+    if !matches!(
+        collection,
+        Collection::Post | Collection::Comment | Collection::Reply | Collection::Like
+    ) {
+        return Err(AppError::ValidateFailed("nsid is not allowed!".to_string()));
+    }
+
+    let record =
+        get_record(&state.pds, repo, nsid, rkey)
+            .await
+            .map_err(|e| match AppError::rpc(e) {
+                AppError::Pds(e) if e.is_not_found() => AppError::NotFound,
+                e => e,
+            })?;
+    let value = record.get("value").ok_or_else(|| {
+        debug!("get record failed: {record}");
+        AppError::NotFound
+    })?;
+    let cid = record
+        .get("cid")
+        .and_then(|cid| cid.as_str())
+        .ok_or(AppError::RpcFailed(record.to_string()))?;
+    // upserts only: a resynced record bumps no thread and notifies no one
+    match collection {
+        Collection::Post => Post::insert(&state.db, repo, value, uri, cid).await?,
+        Collection::Comment => {
+            Comment::upsert(&state.db, repo, value, uri, cid).await?;
+        }
+        Collection::Reply => {
+            Reply::upsert(&state.db, repo, value, uri, cid).await?;
+        }
+        Collection::Like => {
+            Like::upsert(&state.db, repo, value, uri, cid).await?;
+        }
+        _ => return Err(AppError::ValidateFailed("nsid is not allowed!".to_string())),
+    }
+    state.caches.invalidate_author(repo).await;
+
+    Operation::insert(
+        &state.db,
+        OperationRow {
+            id: 0,
+            section_id: value["section_id"]
+                .as_str()
+                .and_then(|s| s.parse::<i32>().ok())
+                .unwrap_or_default(),
+            operator: body.did.to_string(),
+            action_type: ActionType::ResyncRecord as i32,
+            action: "重新同步".to_string(),
+            message: cid.to_string(),
+            target: uri.to_string(),
+            created: chrono::Local::now(),
+        },
+    )
+    .await
+    .ok();
+
+    let view = indexed_view(&state, nsid, repo, uri).await?;
+    Ok(ok(view))
+}
+
+#[utoipa::path(get, path = "/api/admin/cache_stats")]
+pub(crate) async fn cache_stats(
+    State(state): State<AppView>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut stats = state.caches.stats();
+    stats["slow_queries"] = db::slow_query_stats();
+    stats["tip_expiry"] = crate::api::tip::expiry_stats();
+    stats["audit_dropped"] = crate::audit::dropped().into();
+
+    Ok(ok(stats))
+}
+
+/// State of the relayer subscription; `healthy` is what `/readyz` uses.
+#[utoipa::path(get, path = "/api/admin/relayer_status")]
+pub(crate) async fn relayer_status(
+    State(state): State<AppView>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut status = json!(state.relayer.status());
+    status["healthy"] = state.relayer.is_healthy(chrono::Local::now()).into();
+
+    Ok(ok(status))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct RelayerRestartParams {
+    pub timestamp: i64,
+}
+
+impl SignedParam for RelayerRestartParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Drop the relayer connection and connect again.
+#[utoipa::path(post, path = "/api/admin/relayer_restart")]
+pub(crate) async fn relayer_restart(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<RelayerRestartParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let admins = Administrator::all_did(&state.db).await;
+    if !admins.contains(&body.did) {
+        return Err(AppError::ValidateFailed(
+            "only administrator can restart relayer".to_string(),
+        ));
+    }
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    state.relayer.restart();
+    info!("relayer restart requested by {}", body.did);
+
+    Ok(ok_simple())
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct MaintenanceParams {
+    /// Refuse writes with `MaintenanceMode` while reads are served.
+    pub read_only: bool,
+    /// Hold the firehose commits until cleared.
+    pub relayer_paused: bool,
+    /// Shown to refused writers; `maintenance_message` of the config when
+    /// empty.
+    pub message: Option<String>,
+    pub timestamp: i64,
+}
+
+impl SignedParam for MaintenanceParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Turn the maintenance mode on or off; it is kept in memory only, so a
+/// restart turns it off. Returns the new `MaintenanceState`.
+#[utoipa::path(post, path = "/api/admin/maintenance")]
+pub(crate) async fn maintenance(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<MaintenanceParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let (sql, values) = sea_query::Query::select()
+        .column(Administrator::Did)
+        .from(Administrator::Table)
+        .and_where(Expr::col(Administrator::Permission).eq(0))
+        .build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<(String,)> = db::fetch_all(&state.db, &sql, values)
+        .await
+        .unwrap_or_default();
+    if !rows.iter().any(|(did,)| did == &body.did) {
+        return Err(AppError::ValidateFailed(
+            "only super administrator can set maintenance mode".to_string(),
+        ));
+    }
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let params = body.params;
+    state
+        .maintenance
+        .set(params.read_only, params.relayer_paused, params.message);
+    let current = state.maintenance.state();
+    info!("maintenance set by {}: {current:?}", body.did);
+    Operation::insert(
+        &state.db,
+        OperationRow {
+            id: 0,
+            section_id: 0,
+            operator: body.did,
+            action_type: ActionType::Maintenance as i32,
+            action: "维护模式".to_string(),
+            message: json!(current).to_string(),
+            target: String::default(),
+            created: chrono::Local::now(),
+        },
+    )
+    .await
+    .ok();
+
+    Ok(ok(current))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct LogLevelParams {
+    /// Level of the firehose subscription and indexing, like `debug`.
+    pub relayer: Option<String>,
+    /// Level of the request handlers.
+    pub api: Option<String>,
+    /// Level of the statements logged by sqlx and of slow queries.
+    pub sqlx: Option<String>,
+    /// Level of the calls to the PDS, the indexer, micro_pay and CKB.
+    pub upstream: Option<String>,
+    pub timestamp: i64,
+}
+
+impl SignedParam for LogLevelParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Set the log levels of the noisy modules until the next change or
+/// restart; unset ones follow `log_config.filter`. Returns the directives
+/// in effect.
+#[utoipa::path(post, path = "/api/admin/log_level")]
+pub(crate) async fn log_level(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<LogLevelParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let admins = Administrator::all_did(&state.db).await;
+    if !admins.contains(&body.did) {
+        return Err(AppError::ValidateFailed(
+            "only administrator can set log levels".to_string(),
+        ));
+    }
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let params = body.params;
+    state
+        .log_filter
+        .set(&LogVerbosity {
+            relayer: params.relayer,
+            api: params.api,
+            sqlx: params.sqlx,
+            upstream: params.upstream,
+        })
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+    let filter = state.log_filter.current();
+    info!("log filter set by {}: {filter}", body.did);
+
+    Ok(ok(json!({ "filter": filter })))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct BroadcastParams {
+    #[validate(length(min = 1))]
+    pub title: String,
+    pub body: String,
+    /// The section the announcement post is written to.
+    pub post_section_id: i32,
+    /// Only users active in this many days; everyone when neither it nor
+    /// `section_id` is set.
+    #[validate(range(min = 1))]
+    pub active_days: Option<i32>,
+    /// Only users active in this section.
+    pub section_id: Option<i32>,
+    /// Submitting a key again returns the broadcast it started.
+    #[validate(length(min = 1))]
+    pub idempotency_key: String,
+    /// Generated when empty.
+    pub rkey: String,
+    pub signing_key: String,
+    pub ckb_addr: String,
+    /// The repo commit the announcement post builds on, as for
+    /// `/api/record/create`.
+    pub root: Value,
+    pub timestamp: i64,
+}
+
+impl SignedParam for BroadcastParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+impl BroadcastParams {
+    /// The announcement post the notifications link to.
+    fn post(&self) -> Value {
+        json!({
+            "$type": NSID_POST,
+            "section_id": self.post_section_id.to_string(),
+            "title": self.title,
+            "text": self.body,
+            "is_announcement": true,
+            "created": chrono::Local::now().to_rfc3339(),
+        })
+    }
+}
+
+/// Write an announcement post and notify every user of an audience about
+/// it. Returns the id of the broadcast, which goes out in the background.
+#[utoipa::path(post, path = "/api/admin/broadcast")]
+pub(crate) async fn broadcast(
+    State(state): State<AppView>,
+    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    Valid(Json(body)): Valid<Json<SignedBody<BroadcastParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let (sql, values) = sea_query::Query::select()
+        .column(Administrator::Did)
+        .from(Administrator::Table)
+        .and_where(Expr::col(Administrator::Permission).eq(0))
+        .build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<(String,)> = db::fetch_all(&state.db, &sql, values)
+        .await
+        .unwrap_or_default();
+    if !rows.iter().any(|(did,)| did == &body.did) {
+        return Err(AppError::ValidateFailed(
+            "only super administrator can broadcast".to_string(),
+        ));
+    }
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let params = body.params;
+    Audience::new(params.active_days, params.section_id)
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    // claim the idempotency key before the post is written, so a
+    // resubmission never writes a second one
+    let (id, queued) = Broadcast::insert(
+        &state.db,
+        &BroadcastRow {
+            sender: body.did.clone(),
+            idempotency_key: params.idempotency_key.clone(),
+            title: params.title.clone(),
+            body: params.body.clone(),
+            active_days: params.active_days,
+            section_id: params.section_id,
+            ..Default::default()
+        },
+    )
+    .await?;
+    if !queued {
+        return Ok(ok(json!({ "id": id, "queued": queued })));
+    }
+
+    let uri = record::write(
+        &state,
+        &auth,
+        NewRecord {
+            repo: body.did.clone(),
+            rkey: params.rkey.clone(),
+            value: params.post(),
+            signing_key: params.signing_key.clone(),
+            ckb_addr: params.ckb_addr.clone(),
+            root: params.root.clone(),
+        },
+    )
+    .await
+    .and_then(|result| {
+        result
+            .pointer("/results/0/uri")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or(AppError::RpcFailed(result.to_string()))
+    });
+    let uri = match uri {
+        Ok(uri) => uri,
+        Err(e) => {
+            // free the key for another try
+            Broadcast::delete(&state.db, id).await.ok();
+            return Err(e);
+        }
+    };
+    Broadcast::set_post_uri(&state.db, id, &uri).await?;
+    tokio::spawn(broadcast::run(state.db.clone(), id));
+    Operation::insert(
+        &state.db,
+        OperationRow {
+            id: 0,
+            section_id: params.post_section_id,
+            operator: body.did,
+            action_type: ActionType::Broadcast as i32,
+            action: "全站广播".to_string(),
+            message: params.title,
+            target: uri,
+            created: chrono::Local::now(),
+        },
+    )
+    .await
+    .ok();
+
+    Ok(ok(json!({ "id": id, "queued": queued })))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, IntoParams)]
+#[serde(default)]
+pub struct BroadcastStatusQuery {
+    #[validate(range(min = 1))]
+    pub id: i32,
+}
+
+/// Progress of a broadcast: `queued`, `running` while `sent` grows towards
+/// `total`, then `done` or `failed`.
+#[utoipa::path(
+    get,
+    path = "/api/admin/broadcast_status",
+    params(BroadcastStatusQuery)
+)]
+pub(crate) async fn broadcast_status(
+    State(state): State<AppView>,
+    ValidQuery(query): ValidQuery<BroadcastStatusQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let row = Broadcast::select_by_id(&state.db, query.id)
+        .await
+        .map_err(|e| {
+            debug!("exec sql failed: {e}");
+            AppError::NotFound
+        })?;
+
+    Ok(ok(BroadcastView::from(row)))
+}
+
+/// Most active moderators listed by `moderation_stats`.
+const TOP_MODERATORS: u64 = 10;
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[serde(default)]
+pub(crate) struct ModerationStatsQuery {
+    /// The administrator asking; must own the bearer token.
+    #[validate(length(min = 1))]
+    pub viewer: String,
+    /// Days to cover, as `30d`; at most `365d`.
+    pub window: String,
+}
+
+impl Default for ModerationStatsQuery {
+    fn default() -> Self {
+        Self {
+            viewer: String::new(),
+            window: "30d".to_string(),
+        }
+    }
+}
+
+/// Days of a `<n>d` window.
+fn window_days(window: &str) -> Result<i32, AppError> {
+    window
+        .strip_suffix('d')
+        .and_then(|days| days.parse::<i32>().ok())
+        .filter(|days| (1..=365).contains(days))
+        .ok_or_else(|| AppError::ValidateFailed(format!("invalid window: {window}")))
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct SectionModeration {
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    #[schema(schema_with = crate::lexicon::numeric::schema)]
+    pub section_id: String,
+    pub section: String,
+    /// Moderation actions taken in the section.
+    pub actions: i64,
+    /// Targets the content rules flagged in the section.
+    pub flagged: i64,
+    /// Median seconds from a target being flagged to the first moderation
+    /// action on it; null when no flagged target was acted on.
+    pub median_response_secs: Option<f64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct ModeratorActivity {
+    /// Profile of the moderator, as built by `build_author`.
+    pub author: Value,
+    pub actions: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct ModerationStats {
+    pub window_days: i32,
+    /// Every section, the ones without activity included.
+    pub sections: Vec<SectionModeration>,
+    /// The most active moderators across sections.
+    pub moderators: Vec<ModeratorActivity>,
+}
+
+/// What `moderation_stats` caches; the view is built from it per request.
+#[derive(Debug, Clone)]
+pub(crate) struct ModerationCounts {
+    pub sections: Vec<SectionModeration>,
+    /// Dids of the most active moderators with their actions.
+    pub moderators: Vec<(String, i64)>,
+}
+
+/// Per section moderation actions and response times from the operation
+/// log, to spot under-moderated sections. Only for administrators; returns
+/// `ModerationStats`.
+#[utoipa::path(
+    get,
+    path = "/api/admin/moderation_stats",
+    params(ModerationStatsQuery)
+)]
+pub(crate) async fn moderation_stats(
+    State(state): State<AppView>,
+    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    ValidQuery(query): ValidQuery<ModerationStatsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let days = window_days(&query.window)?;
+    check_session(&state.pds, auth.token(), &query.viewer).await?;
+    if !Administrator::all_did(&state.db)
+        .await
+        .contains(&query.viewer)
+    {
+        return Err(AppError::ValidateFailed(
+            "only administrator can see moderation stats".to_string(),
+        ));
+    }
+
+    let counts = state
+        .caches
+        .moderation_stats(days, async {
+            Ok(ModerationCounts {
+                sections: section_moderation(&state.db, days).await?,
+                moderators: top_moderators(&state.db, days).await?,
+            })
+        })
+        .await?;
+    let mut moderators = vec![];
+    for (did, actions) in counts.moderators {
+        moderators.push(ModeratorActivity {
+            author: build_author(&state, &did).await,
+            actions,
+        });
+    }
+    Ok(ok(ModerationStats {
+        window_days: days,
+        sections: counts.sections,
+        moderators,
+    }))
+}
+
+async fn section_moderation(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    days: i32,
+) -> color_eyre::Result<Vec<SectionModeration>> {
+    let (sql, values) = Operation::build_response_medians(days).build_sqlx(PostgresQueryBuilder);
+    let medians: HashMap<i32, f64> = db::fetch_all::<(i32, f64), _>(db, &sql, values)
+        .await?
+        .into_iter()
+        .collect();
+    let (sql, values) = Operation::build_section_activity(days).build_sqlx(PostgresQueryBuilder);
+    let rows = db::fetch_all::<(i32, String, i64, i64), _>(db, &sql, values).await?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, section, actions, flagged)| SectionModeration {
+            section_id: id.to_string(),
+            section,
+            actions,
+            flagged,
+            median_response_secs: medians.get(&id).copied(),
+        })
+        .collect())
+}
+
+async fn top_moderators(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    days: i32,
+) -> color_eyre::Result<Vec<(String, i64)>> {
+    let (sql, values) =
+        Operation::build_top_moderators(days, TOP_MODERATORS).build_sqlx(PostgresQueryBuilder);
+    Ok(db::fetch_all(db, &sql, values).await?)
+}
+
+#[utoipa::path(get, path = "/api/admin")]
+pub(crate) async fn list(State(state): State<AppView>) -> Result<impl IntoResponse, AppError> {
+    let rows = Administrator::all(&state.db).await;
+    let mut views: Vec<AdministratorView> = vec![];
+
+    for row in rows {
+        let author = build_author(&state, &row.did).await;
+        views.push(AdministratorView {
+            did: author,
+            permission: row.permission.to_string(),
             updated: row.updated,
             created: row.created,
         });
@@ -749,6 +1846,7 @@ pub(crate) async fn list(State(state): State<AppView>) -> Result<impl IntoRespon
 #[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
 #[serde(default)]
 pub(crate) struct UpdateAdminParams {
+    #[validate(length(min = 1))]
     pub did: String,
     pub name: String,
     pub timestamp: i64,
@@ -763,17 +1861,14 @@ impl SignedParam for UpdateAdminParams {
 #[utoipa::path(post, path = "/api/admin/add")]
 pub(crate) async fn add(
     State(state): State<AppView>,
-    Json(body): Json<SignedBody<UpdateAdminParams>>,
+    Valid(Json(body)): Valid<Json<SignedBody<UpdateAdminParams>>>,
 ) -> Result<impl IntoResponse, AppError> {
-    body.validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
     let (sql, values) = sea_query::Query::select()
         .column(Administrator::Did)
         .from(Administrator::Table)
         .and_where(Expr::col(Administrator::Permission).eq(0))
         .build_sqlx(PostgresQueryBuilder);
-    let rows: Vec<(String,)> = sqlx::query_as_with(&sql, values)
-        .fetch_all(&state.db)
+    let rows: Vec<(String,)> = db::fetch_all(&state.db, &sql, values)
         .await
         .unwrap_or_default();
     let super_admins: Vec<String> = rows.into_iter().map(|r| r.0).collect();
@@ -782,7 +1877,7 @@ pub(crate) async fn add(
             "only super administrator can add administrator".to_string(),
         ));
     }
-    body.verify_signature(&state.indexer)
+    body.verify_signature(&state)
         .await
         .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
 
@@ -802,6 +1897,7 @@ pub(crate) async fn add(
     }
 
     Administrator::insert(&state.db, &body.params.did, 1).await?;
+    state.caches.invalidate_author(&body.params.did).await;
 
     Operation::insert(
         &state.db,
@@ -825,17 +1921,14 @@ pub(crate) async fn add(
 #[utoipa::path(post, path = "/api/admin/delete")]
 pub(crate) async fn delete(
     State(state): State<AppView>,
-    Json(body): Json<SignedBody<UpdateAdminParams>>,
+    Valid(Json(body)): Valid<Json<SignedBody<UpdateAdminParams>>>,
 ) -> Result<impl IntoResponse, AppError> {
-    body.validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
     let (sql, values) = sea_query::Query::select()
         .column(Administrator::Did)
         .from(Administrator::Table)
         .and_where(Expr::col(Administrator::Permission).eq(0))
         .build_sqlx(PostgresQueryBuilder);
-    let rows: Vec<(String,)> = sqlx::query_as_with(&sql, values)
-        .fetch_all(&state.db)
+    let rows: Vec<(String,)> = db::fetch_all(&state.db, &sql, values)
         .await
         .unwrap_or_default();
     let super_admins: Vec<String> = rows.into_iter().map(|r| r.0).collect();
@@ -844,11 +1937,12 @@ pub(crate) async fn delete(
             "only super administrator can delete administrator".to_string(),
         ));
     }
-    body.verify_signature(&state.indexer)
+    body.verify_signature(&state)
         .await
         .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
 
     Administrator::delete(&state.db, &body.params.did).await?;
+    state.caches.invalidate_author(&body.params.did).await;
 
     let author = build_author(&state, &body.params.did).await;
     Operation::insert(
@@ -870,6 +1964,278 @@ pub(crate) async fn delete(
     Ok(ok_simple())
 }
 
+#[derive(Debug, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct HiddenListParams {
+    /// Only this section; every section the signer moderates when unset.
+    pub section_id: Option<String>,
+    #[validate(range(min = 1))]
+    pub page: u64,
+    #[validate(range(min = 1))]
+    pub per_page: u64,
+    pub timestamp: i64,
+}
+
+impl Default for HiddenListParams {
+    fn default() -> Self {
+        Self {
+            section_id: None,
+            page: 1,
+            per_page: 20,
+            timestamp: 0,
+        }
+    }
+}
+
+impl SignedParam for HiddenListParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Who hid a hidden post, comment or reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum HiddenBy {
+    Moderator,
+    /// A content rule shadowed it until a moderator reviews it.
+    ContentRule,
+    /// Its author removed their data with `remove_me`.
+    Author,
+    /// The relayer reported the account of its author inactive.
+    Relayer,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub(crate) struct HiddenReason {
+    pub by: HiddenBy,
+    /// The moderator note, or the category of the content rule.
+    pub text: Option<String>,
+}
+
+impl HiddenReason {
+    /// Reads `reasons_for_disabled` as written by `update_tag`, the content
+    /// rules, `remove_me` and the `#account` events of the relayer.
+    pub fn parse(reasons: Option<&str>) -> Self {
+        let text = |text: &str| (!text.is_empty()).then(|| text.to_string());
+        match reasons {
+            Some(REMOVED_BY_AUTHOR) => Self {
+                by: HiddenBy::Author,
+                text: None,
+            },
+            Some(ACCOUNT_INACTIVE) => Self {
+                by: HiddenBy::Relayer,
+                text: None,
+            },
+            Some(reasons) => match reasons.strip_prefix(PENDING_REVIEW) {
+                Some(category) => Self {
+                    by: HiddenBy::ContentRule,
+                    text: text(category),
+                },
+                None => Self {
+                    by: HiddenBy::Moderator,
+                    text: text(reasons),
+                },
+            },
+            None => Self {
+                by: HiddenBy::Moderator,
+                text: None,
+            },
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct HiddenRow {
+    kind: String,
+    uri: String,
+    repo: String,
+    section_id: i32,
+    post: Option<String>,
+    post_title: Option<String>,
+    reasons_for_disabled: Option<String>,
+    moderator: Option<String>,
+    hidden_at: Option<chrono::DateTime<chrono::Local>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct HiddenPost {
+    pub uri: String,
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct HiddenItem {
+    /// `post`, `comment` or `reply`.
+    pub kind: String,
+    pub uri: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    #[schema(schema_with = crate::lexicon::numeric::schema)]
+    pub section_id: String,
+    /// Profile of the author, as built by `build_author`.
+    pub author: Value,
+    pub reason: HiddenReason,
+    /// Profile of the moderator who disabled it last, from the operation
+    /// log; null when no moderator did.
+    pub moderator: Option<Value>,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub hidden_at: Option<chrono::DateTime<chrono::Local>>,
+    /// The post a hidden comment or reply is under.
+    pub post: Option<HiddenPost>,
+}
+
+/// Hidden posts, comments and replies of `sections`, or of every section
+/// when `None`.
+fn build_hidden(sections: Option<&[i32]>) -> sea_query::SelectStatement {
+    let select = |kind: &str, table: &str, post: Option<&str>| {
+        sea_query::Query::select()
+            .expr_as(Expr::val(kind), "kind")
+            .columns(["uri", "repo", "section_id"])
+            .expr_as(post.map_or(Expr::cust("NULL"), Expr::col), "post")
+            .column("reasons_for_disabled")
+            .from(table)
+            .and_where(Expr::col("is_disabled").eq(true))
+            .and_where_option(sections.map(|ids| Expr::col("section_id").is_in(ids.to_vec())))
+            .take()
+    };
+    let mut hidden = select("post", "post", None);
+    hidden.union(UnionType::All, select("comment", "comment", Some("post")));
+    hidden.union(UnionType::All, select("reply", "reply", Some("post")));
+    sea_query::Query::select()
+        .from_subquery(hidden, "hidden")
+        .take()
+}
+
+const DISABLES: [ActionType; 3] = [
+    ActionType::DisablePost,
+    ActionType::DisableComment,
+    ActionType::DisableReply,
+];
+
+/// `column` of the last of `actions` on each hidden item in the operation
+/// log.
+fn last_operation(column: &str, actions: &[ActionType]) -> Expr {
+    Expr::cust(format!(
+        "(SELECT \"{column}\" FROM \"operation\" WHERE \"operation\".\"target\" = \"hidden\".\"uri\" AND \"operation\".\"action_type\" IN ({}) ORDER BY \"operation\".\"created\" DESC LIMIT 1)",
+        ActionType::list(actions)
+    ))
+}
+
+/// Everything hidden in the sections the signer moderates, to review again,
+/// most recently hidden first. Administrators see every section.
+#[utoipa::path(post, path = "/api/admin/hidden_list")]
+pub(crate) async fn hidden_list(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<HiddenListParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let section_id = body
+        .params
+        .section_id
+        .as_deref()
+        .map(|id| {
+            id.parse::<i32>()
+                .map_err(|_| AppError::ValidateFailed("invalid section_id".to_string()))
+        })
+        .transpose()?;
+    let sections = if Administrator::all_did(&state.db).await.contains(&body.did) {
+        section_id.map(|id| vec![id])
+    } else {
+        let sections = state.caches.sections(Section::all(&state.db)).await?;
+        let owned: Vec<i32> = sections
+            .values()
+            .filter(|section| section.owner.as_deref() == Some(body.did.as_str()))
+            .map(|section| section.id)
+            .filter(|id| section_id.is_none_or(|section_id| section_id == *id))
+            .collect();
+        if owned.is_empty() {
+            return Err(AppError::ValidateFailed(
+                "only section owner or administrator can list hidden content".to_string(),
+            ));
+        }
+        Some(owned)
+    };
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let params = &body.params;
+    let (rows, total) =
+        hidden_rows(&state.db, sections.as_deref(), params.page, params.per_page).await?;
+
+    let mut items = vec![];
+    for row in rows {
+        let moderator = match &row.moderator {
+            Some(did) => Some(build_author(&state, did).await),
+            None => None,
+        };
+        items.push(HiddenItem {
+            author: build_author(&state, &row.repo).await,
+            reason: HiddenReason::parse(row.reasons_for_disabled.as_deref()),
+            moderator,
+            hidden_at: row.hidden_at,
+            post: row.post.map(|uri| HiddenPost {
+                uri,
+                title: row.post_title,
+            }),
+            kind: row.kind,
+            uri: row.uri,
+            section_id: row.section_id.to_string(),
+        });
+    }
+
+    Ok(ok(json!({
+        "items": items,
+        "page": params.page,
+        "per_page": params.per_page,
+        "total": total,
+    })))
+}
+
+/// A page of the hidden items of `sections`, and how many there are.
+async fn hidden_rows(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    sections: Option<&[i32]>,
+    page: u64,
+    per_page: u64,
+) -> color_eyre::Result<(Vec<HiddenRow>, i64)> {
+    let (sql, values) = build_hidden(sections)
+        .columns([
+            ("hidden", "kind"),
+            ("hidden", "uri"),
+            ("hidden", "repo"),
+            ("hidden", "section_id"),
+            ("hidden", "post"),
+            ("hidden", "reasons_for_disabled"),
+        ])
+        .expr_as(Expr::col((Post::Table, Post::Title)), "post_title")
+        // the operator of a shadowing is the author, not a moderator
+        .expr_as(last_operation("operator", &DISABLES), "moderator")
+        .expr_as(
+            last_operation("created", &[DISABLES, [ActionType::ShadowContent]].concat()),
+            "hidden_at",
+        )
+        .left_join(
+            Post::Table,
+            Expr::col((Post::Table, Post::Uri)).equals(("hidden", "post")),
+        )
+        .order_by_expr_with_nulls(
+            Expr::cust("\"hidden_at\""),
+            Order::Desc,
+            sea_query::NullOrdering::Last,
+        )
+        .order_by(("hidden", "uri"), Order::Asc)
+        .offset(per_page * (page - 1))
+        .limit(per_page)
+        .build_sqlx(PostgresQueryBuilder);
+    let rows = db::fetch_all(db, &sql, values).await?;
+
+    let (sql, values) = build_hidden(sections)
+        .expr(Expr::cust("COUNT(*)"))
+        .build_sqlx(PostgresQueryBuilder);
+    let (total,): (i64,) = db::fetch_one(db, &sql, values).await?;
+    Ok((rows, total))
+}
+
 #[derive(Debug, Validate, Deserialize, IntoParams)]
 #[serde(default)]
 pub struct OperationQuery {
@@ -893,11 +2259,8 @@ impl Default for OperationQuery {
 #[utoipa::path(get, path = "/api/admin/operations", params(OperationQuery))]
 pub(crate) async fn operations(
     State(state): State<AppView>,
-    Query(query): Query<OperationQuery>,
+    ValidQuery(query): ValidQuery<OperationQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    query
-        .validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
     let offset = query.per_page * (query.page - 1);
     let (sql, values) = Operation::build_select()
         .and_where(
@@ -908,14 +2271,14 @@ pub(crate) async fn operations(
         .limit(query.per_page)
         .build_sqlx(PostgresQueryBuilder);
 
-    let rows: Vec<OperationRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    let rows: Vec<OperationRow> = db::fetch_all(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
     let mut views: Vec<OperationView> = vec![];
 
     for row in rows {
-        let operator = build_author(&state, &row.operator).await;
+        let operator = build_moderator(&state, &sections, row.section_id, &row.operator).await;
         views.push(OperationView {
             id: row.id.to_string(),
             section_id: row.section_id.to_string(),
@@ -940,8 +2303,7 @@ pub(crate) async fn operations(
         )
         .build_sqlx(PostgresQueryBuilder);
 
-    let total: (i64,) = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
+    let total: (i64,) = db::fetch_one(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
@@ -966,13 +2328,13 @@ async fn get_source(state: &AppView, uri: &str) -> Result<Value, AppError> {
                     .from(Post::Table)
                     .and_where(Expr::col(Post::Uri).eq(uri))
                     .build_sqlx(PostgresQueryBuilder);
-                let row: (String, bool, Option<String>) = query_as_with(&sql, values.clone())
-                    .fetch_one(&state.db)
-                    .await
-                    .map_err(|e| {
-                        debug!("exec sql failed: {e}");
-                        AppError::NotFound
-                    })?;
+                let row: (String, bool, Option<String>) =
+                    db::fetch_one(&state.db, &sql, values.clone())
+                        .await
+                        .map_err(|e| {
+                            debug!("exec sql failed: {e}");
+                            AppError::NotFound
+                        })?;
                 json!({
                     "nsid": nsid,
                     "uri": uri,
@@ -993,8 +2355,7 @@ async fn get_source(state: &AppView, uri: &str) -> Result<Value, AppError> {
                     .and_where(Expr::col(Comment::Uri).eq(uri))
                     .build_sqlx(PostgresQueryBuilder);
                 let row: (String, String, bool, Option<String>) =
-                    query_as_with(&sql, values.clone())
-                        .fetch_one(&state.db)
+                    db::fetch_one(&state.db, &sql, values.clone())
                         .await
                         .map_err(|e| {
                             debug!("exec sql failed: {e}");
@@ -1023,8 +2384,7 @@ async fn get_source(state: &AppView, uri: &str) -> Result<Value, AppError> {
                     .and_where(Expr::col(Reply::Uri).eq(uri))
                     .build_sqlx(PostgresQueryBuilder);
                 let row: (String, String, String, String, bool, Option<String>) =
-                    query_as_with(&sql, values.clone())
-                        .fetch_one(&state.db)
+                    db::fetch_one(&state.db, &sql, values.clone())
                         .await
                         .map_err(|e| {
                             debug!("exec sql failed: {e}");
@@ -1071,3 +2431,346 @@ async fn get_source(state: &AppView, uri: &str) -> Result<Value, AppError> {
 
     Ok(source)
 }
+
+#[test]
+fn update_tag_fields_follow_the_collection() {
+    let params = UpdateTagParams {
+        is_top: Some(true),
+        ..Default::default()
+    };
+    assert!(params.check_fields(Collection::Post).is_ok());
+    assert!(matches!(
+        params.check_fields(Collection::Reply),
+        Err(AppError::ValidateFailed(e)) if e == "is_top is not supported for app.bbs.reply"
+    ));
+    let params = UpdateTagParams {
+        is_announcement: Some(false),
+        ..Default::default()
+    };
+    assert!(matches!(
+        params.check_fields(Collection::Comment),
+        Err(AppError::ValidateFailed(e)) if e == "is_announcement is not supported for app.bbs.comment"
+    ));
+    assert!(params.check_fields(Collection::Like).is_err());
+
+    let params = UpdateTagParams {
+        is_disabled: Some(true),
+        reasons_for_disabled: Some("spam".to_string()),
+        ..Default::default()
+    };
+    for collection in [Collection::Post, Collection::Comment, Collection::Reply] {
+        assert!(params.check_fields(collection).is_ok());
+    }
+    let uri = "at://did:ckb:alice/app.bbs.post/1";
+    let update =
+        |sql: Option<sea_query::UpdateStatement>| sql.unwrap().to_string(PostgresQueryBuilder);
+    assert_eq!(
+        update(Post::build_update_tag(
+            uri,
+            None,
+            None,
+            Some(true),
+            Some("spam".to_string())
+        )),
+        format!(
+            "UPDATE \"post\" SET \"is_disabled\" = TRUE, \"reasons_for_disabled\" = 'spam' WHERE \"uri\" = '{uri}'"
+        )
+    );
+    assert!(
+        update(Comment::build_update_tag(uri, Some(true), Some("spam".to_string())))
+            .starts_with("UPDATE \"comment\" SET \"is_disabled\" = TRUE, \"reasons_for_disabled\" = 'spam', \"updated\" = CURRENT_TIMESTAMP")
+    );
+    assert_eq!(
+        update(Reply::build_update_tag(
+            uri,
+            Some(true),
+            Some("spam".to_string())
+        )),
+        format!(
+            "UPDATE \"reply\" SET \"is_disabled\" = TRUE, \"reasons_for_disabled\" = 'spam' WHERE \"uri\" = '{uri}'"
+        )
+    );
+    assert!(Reply::build_update_tag(uri, None, None).is_none());
+}
+
+#[test]
+fn moderation_windows_are_days() {
+    assert_eq!(window_days("30d").unwrap(), 30);
+    assert_eq!(window_days("365d").unwrap(), 365);
+    for window in ["0d", "366d", "30", "4w", "-1d", ""] {
+        assert!(matches!(
+            window_days(window),
+            Err(AppError::ValidateFailed(_))
+        ));
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn moderation_stats_cover_every_section() {
+    use sqlx::{Executor, query};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO section (id, name) VALUES (1, 'General'), (2, 'Market'), (3, 'Quiet')",
+        // General: two flagged posts disabled after two hours and one hour
+        "INSERT INTO operation (section_id, operator, action_type, action, target, created) VALUES
+            (1, 'did:ckb:rules', 22, 'flag', 'p1', now() - interval '10 hours'),
+            (1, 'did:ckb:alice', 1, 'disable', 'p1', now() - interval '8 hours'),
+            (1, 'did:ckb:alice', 1, 'disable', 'p1', now() - interval '7 hours'),
+            (1, 'did:ckb:rules', 22, 'flag', 'p2', now() - interval '5 hours'),
+            (1, 'did:ckb:alice', 1, 'disable', 'p2', now() - interval '4 hours'),
+            (1, 'did:ckb:root', 19, 'add admin', 'did:ckb:alice', now())",
+        // Market: a shadowed reply nobody acted on, and an action out of the window
+        "INSERT INTO operation (section_id, operator, action_type, action, target, created) VALUES
+            (2, 'did:ckb:rules', 23, 'shadow', 'r1', now() - interval '3 hours'),
+            (2, 'did:ckb:bob', 15, 'set top', 'p3', now() - interval '1 hour'),
+            (2, 'did:ckb:bob', 1, 'disable', 'p4', now() - interval '60 days')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+
+    let sections = section_moderation(&db, 30).await.unwrap();
+    assert_eq!(
+        sections
+            .iter()
+            .map(|s| (
+                s.section_id.as_str(),
+                s.section.as_str(),
+                s.actions,
+                s.flagged,
+                s.median_response_secs.map(f64::round)
+            ))
+            .collect::<Vec<_>>(),
+        [
+            ("1", "General", 3, 2, Some(5400.0)),
+            ("2", "Market", 1, 1, None),
+            ("3", "Quiet", 0, 0, None),
+        ]
+    );
+
+    let (sql, values) =
+        Operation::build_top_moderators(30, TOP_MODERATORS).build_sqlx(PostgresQueryBuilder);
+    let moderators = db::fetch_all::<(String, i64), _>(&db, &sql, values)
+        .await
+        .unwrap();
+    assert_eq!(
+        moderators,
+        [
+            ("did:ckb:alice".to_string(), 3),
+            ("did:ckb:bob".to_string(), 1)
+        ]
+    );
+}
+
+#[test]
+fn hidden_reasons() {
+    assert_eq!(
+        HiddenReason::parse(Some("spam links")),
+        HiddenReason {
+            by: HiddenBy::Moderator,
+            text: Some("spam links".to_string())
+        }
+    );
+    assert_eq!(
+        HiddenReason::parse(Some("pending review: phishing")),
+        HiddenReason {
+            by: HiddenBy::ContentRule,
+            text: Some("phishing".to_string())
+        }
+    );
+    assert_eq!(
+        HiddenReason::parse(Some(REMOVED_BY_AUTHOR)),
+        HiddenReason {
+            by: HiddenBy::Author,
+            text: None
+        }
+    );
+    assert_eq!(
+        HiddenReason::parse(Some(ACCOUNT_INACTIVE)),
+        HiddenReason {
+            by: HiddenBy::Relayer,
+            text: None
+        }
+    );
+    for reasons in [None, Some("")] {
+        assert_eq!(
+            HiddenReason::parse(reasons),
+            HiddenReason {
+                by: HiddenBy::Moderator,
+                text: None
+            }
+        );
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn hidden_list_is_scoped_to_sections() {
+    use sqlx::{Executor, query};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft, is_disabled, reasons_for_disabled) VALUES
+            ('p1', 'bafy', 'did:ckb:alice', 1, 'Hello', 't', false, true, 'spam links'),
+            ('p2', 'bafy', 'did:ckb:bob', 1, 'Visible', 't', false, false, NULL),
+            ('p3', 'bafy', 'did:ckb:carol', 2, 'Elsewhere', 't', false, true, 'off topic')",
+        "INSERT INTO comment (uri, cid, repo, section_id, post, text, is_disabled, reasons_for_disabled) VALUES
+            ('c1', 'bafy', 'did:ckb:bob', 1, 'p2', 't', true, 'pending review: phishing'),
+            ('c2', 'bafy', 'did:ckb:carol', 1, 'p2', 't', false, NULL)",
+        "INSERT INTO reply (uri, cid, repo, section_id, post, comment, text, is_disabled, reasons_for_disabled) VALUES ('r1', 'bafy', 'did:ckb:dave', 1, 'p2', 'c2', 't', true, 'removed by author')",
+        "INSERT INTO operation (section_id, operator, action_type, action, target, created) VALUES
+            (1, 'did:ckb:owner', 1, 'disable', 'p1', now() - interval '3 hours'),
+            (1, 'did:ckb:admin', 1, 'disable', 'p1', now() - interval '1 hour'),
+            (1, 'did:ckb:bob', 23, 'shadow', 'c1', now() - interval '2 hours'),
+            (2, 'did:ckb:admin', 1, 'disable', 'p3', now())",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+
+    let (rows, total) = hidden_rows(&db, Some(&[1][..]), 1, 20).await.unwrap();
+    assert_eq!(total, 3);
+    assert_eq!(
+        rows.iter()
+            .map(|r| (
+                r.kind.as_str(),
+                r.uri.as_str(),
+                r.moderator.as_deref(),
+                r.post_title.as_deref()
+            ))
+            .collect::<Vec<_>>(),
+        [
+            ("post", "p1", Some("did:ckb:admin"), None),
+            ("comment", "c1", None, Some("Visible")),
+            ("reply", "r1", None, Some("Visible")),
+        ]
+    );
+    assert!(rows[1].hidden_at.is_some());
+    assert!(rows[2].hidden_at.is_none());
+
+    let (rows, total) = hidden_rows(&db, Some(&[1][..]), 2, 2).await.unwrap();
+    assert_eq!((rows.len(), total), (1, 3));
+    assert_eq!(rows[0].uri, "r1");
+
+    let (rows, total) = hidden_rows(&db, None, 1, 20).await.unwrap();
+    assert_eq!(total, 4);
+    assert_eq!(rows[0].uri, "p3");
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn only_moderators_of_the_section_update_tags() {
+    use sqlx::{Executor, query};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO section (id, name, owner) VALUES (1, 'General', 'did:ckb:owner'), (2, 'Market', 'did:ckb:other')",
+        "INSERT INTO administrator (did, permission) VALUES ('did:ckb:admin', 0)",
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft) VALUES ('at://did:ckb:alice/app.bbs.post/p1', 'bafy', 'did:ckb:alice', 1, 't', 't', false)",
+        "INSERT INTO comment (uri, cid, repo, section_id, post, text) VALUES ('at://did:ckb:bob/app.bbs.comment/c1', 'bafy', 'did:ckb:bob', 2, 'at://did:ckb:other/app.bbs.post/p2', 't')",
+        "INSERT INTO reply (uri, cid, repo, section_id, post, comment, text) VALUES ('at://did:ckb:carol/app.bbs.reply/r1', 'bafy', 'did:ckb:carol', 1, 'at://did:ckb:alice/app.bbs.post/p1', 'at://did:ckb:bob/app.bbs.comment/c0', 't')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+
+    let post = "at://did:ckb:alice/app.bbs.post/p1";
+    let comment = "at://did:ckb:bob/app.bbs.comment/c1";
+    let reply = "at://did:ckb:carol/app.bbs.reply/r1";
+    for (collection, uri, did, allowed) in [
+        (Collection::Post, post, "did:ckb:owner", true),
+        (Collection::Post, post, "did:ckb:admin", true),
+        // the owner of another section, the author and anyone else
+        (Collection::Post, post, "did:ckb:other", false),
+        (Collection::Post, post, "did:ckb:alice", false),
+        (Collection::Post, post, "did:ckb:mallory", false),
+        (Collection::Comment, comment, "did:ckb:other", true),
+        (Collection::Comment, comment, "did:ckb:owner", false),
+        (Collection::Comment, comment, "did:ckb:admin", true),
+        (Collection::Reply, reply, "did:ckb:owner", true),
+        (Collection::Reply, reply, "did:ckb:other", false),
+    ] {
+        let section_id = section_of_target(&db, collection, uri).await.unwrap();
+        assert_eq!(
+            can_moderate(&db, section_id, did).await.unwrap(),
+            allowed,
+            "{did} on {uri}"
+        );
+    }
+
+    assert!(matches!(
+        section_of_target(
+            &db,
+            Collection::Post,
+            "at://did:ckb:alice/app.bbs.post/gone"
+        )
+        .await,
+        Err(AppError::NotFound)
+    ));
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn displaying_shadowed_content_announces_it() {
+    use sqlx::{Executor, query};
+
+    use crate::{atproto::NSID_POST, lexicon::webhook::WebhookEvent};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft, is_disabled, reasons_for_disabled) VALUES
+            ('at://did:ckb:alice/app.bbs.post/p1', 'bafy', 'did:ckb:alice', 1, 'Hello', 'world', false, true, 'pending review: phishing'),
+            ('at://did:ckb:alice/app.bbs.post/p2', 'bafy', 'did:ckb:alice', 1, 'Hidden', 't', false, true, 'spam links'),
+            ('at://did:ckb:alice/app.bbs.post/p3', 'bafy', 'did:ckb:alice', 1, 'Shown', 't', false, false, NULL)",
+        "INSERT INTO comment (uri, cid, repo, section_id, post, text, is_disabled, reasons_for_disabled) VALUES
+            ('at://did:ckb:bob/app.bbs.comment/c1', 'bafy', 'did:ckb:bob', 1, 'at://did:ckb:alice/app.bbs.post/p3', 'hi', true, 'pending review: phishing')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+
+    let post = "at://did:ckb:alice/app.bbs.post/p1";
+    let record = pending_review_record(&db, Collection::Post, post)
+        .await
+        .unwrap()
+        .unwrap();
+    let payload = WebhookPayload::created(NSID_POST, "did:ckb:alice", post, &record).unwrap();
+    assert_eq!(payload.event, WebhookEvent::PostCreated);
+    assert_eq!(payload.section_id, 1);
+    assert_eq!(payload.title.as_deref(), Some("Hello"));
+    assert_eq!(payload.snippet, "world");
+
+    let comment = "at://did:ckb:bob/app.bbs.comment/c1";
+    let record = pending_review_record(&db, Collection::Comment, comment)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record["post"], "at://did:ckb:alice/app.bbs.post/p3");
+
+    // hidden by a moderator, or never hidden: announced already
+    for uri in [
+        "at://did:ckb:alice/app.bbs.post/p2",
+        "at://did:ckb:alice/app.bbs.post/p3",
+    ] {
+        assert!(
+            pending_review_record(&db, Collection::Post, uri)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
+
+#[test]
+fn broadcasts_write_an_announcement_of_their_body() {
+    let params = BroadcastParams {
+        title: "Maintenance tonight".to_string(),
+        body: "The forum is read only from 22:00.".to_string(),
+        post_section_id: 1,
+        ..Default::default()
+    };
+    let post = params.post();
+    assert_eq!(post["$type"], NSID_POST);
+    assert_eq!(post["section_id"], "1");
+    assert_eq!(post["title"], "Maintenance tonight");
+    assert_eq!(post["text"], "The forum is read only from 22:00.");
+    assert_eq!(post["is_announcement"], true);
+}
@@ -0,0 +1,144 @@
+use color_eyre::eyre::eyre;
+use common_x::restful::{
+    axum::{Json, extract::State, response::IntoResponse},
+    ok,
+};
+use sea_query::{Cond, Expr, ExprTrait, Order, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::{
+    AppView,
+    api::{SignedBody, SignedParam, valid::Valid},
+    db,
+    error::AppError,
+    lexicon::{
+        administrator::Administrator,
+        audit_event::{AuditEvent, AuditEventRow},
+    },
+};
+
+#[derive(Debug, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct AuditQueryParams {
+    /// A DID, or `token:` and the hash of a bearer token.
+    pub actor: Option<String>,
+    pub route: Option<String>,
+    pub target: Option<String>,
+    /// HTTP status of the call.
+    pub outcome: Option<i32>,
+    #[validate(range(min = 1))]
+    pub page: u64,
+    #[validate(range(min = 1, max = 200))]
+    pub per_page: u64,
+    pub timestamp: i64,
+}
+
+impl Default for AuditQueryParams {
+    fn default() -> Self {
+        Self {
+            actor: None,
+            route: None,
+            target: None,
+            outcome: None,
+            page: 1,
+            per_page: 20,
+            timestamp: Default::default(),
+        }
+    }
+}
+
+impl SignedParam for AuditQueryParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+impl AuditQueryParams {
+    fn condition(&self) -> Cond {
+        Cond::all()
+            .add_option(
+                self.actor
+                    .as_ref()
+                    .map(|a| Expr::col(AuditEvent::Actor).eq(a)),
+            )
+            .add_option(
+                self.route
+                    .as_ref()
+                    .map(|r| Expr::col(AuditEvent::Route).eq(r)),
+            )
+            .add_option(
+                self.target
+                    .as_ref()
+                    .map(|t| Expr::col(AuditEvent::Target).eq(t)),
+            )
+            .add_option(self.outcome.map(|o| Expr::col(AuditEvent::Outcome).eq(o)))
+    }
+}
+
+/// The audit events of the write calls, newest first.
+#[utoipa::path(post, path = "/api/admin/audit_events")]
+pub(crate) async fn events(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<AuditQueryParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let admins = Administrator::all_did(&state.db).await;
+    if !admins.contains(&body.did) {
+        return Err(AppError::ValidateFailed(
+            "only administrator can read audit events".to_string(),
+        ));
+    }
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let query = body.params;
+    let offset = query.per_page * (query.page - 1);
+    let (sql, values) = AuditEvent::build_select()
+        .cond_where(query.condition())
+        .order_by(AuditEvent::Id, Order::Desc)
+        .offset(offset)
+        .limit(query.per_page)
+        .build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<AuditEventRow> = db::fetch_all(&state.db, &sql, values)
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+
+    let (sql, values) = sea_query::Query::select()
+        .expr(Expr::col(AuditEvent::Id).count())
+        .from(AuditEvent::Table)
+        .cond_where(query.condition())
+        .build_sqlx(PostgresQueryBuilder);
+    let total: (i64,) = db::fetch_one(&state.db, &sql, values)
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+
+    Ok(ok(json!({
+        "events": rows,
+        "page": query.page,
+        "per_page": query.per_page,
+        "total": total.0
+    })))
+}
+
+#[test]
+fn filters_are_combined() {
+    let query = AuditQueryParams {
+        actor: Some("did:ckb:alice".to_string()),
+        outcome: Some(200),
+        ..Default::default()
+    };
+    let (sql, values) = AuditEvent::build_select()
+        .cond_where(query.condition())
+        .build(PostgresQueryBuilder);
+    assert!(sql.ends_with("FROM \"audit_event\" WHERE \"actor\" = $1 AND \"outcome\" = $2"));
+    assert_eq!(values.0.len(), 2);
+
+    let sql = AuditEvent::build_select()
+        .cond_where(AuditQueryParams::default().condition())
+        .to_string(PostgresQueryBuilder);
+    assert!(sql.ends_with("FROM \"audit_event\""));
+}
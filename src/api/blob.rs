@@ -0,0 +1,227 @@
+use std::time::Duration;
+
+use common_x::restful::axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{
+        StatusCode,
+        header::{
+            CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_SECURITY_POLICY, CONTENT_TYPE,
+            X_CONTENT_TYPE_OPTIONS,
+        },
+    },
+    response::IntoResponse,
+};
+use ipld_core::cid::Cid;
+
+use crate::{AppView, cache::Caches, error::AppError};
+
+/// Blobs are content addressed, so clients may keep them for good.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Raster images, which browsers show without running anything in them.
+/// Any other blob, such as SVG or HTML, is served as a download.
+const INLINE_TYPES: [&str; 5] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+];
+
+/// A blob as the PDS served it.
+#[derive(Debug, Clone)]
+pub struct Blob {
+    pub content_type: String,
+    pub bytes: Bytes,
+}
+
+/// Serves a blob of a repo, such as an avatar or a post image, so clients
+/// need not reach the PDS themselves.
+#[utoipa::path(
+    get,
+    path = "/api/blob/{did}/{cid}",
+    params(
+        ("did" = String, Path, description = "Repo the blob belongs to."),
+        ("cid" = String, Path, description = "CID of the blob."),
+    ),
+    responses(
+        (status = 200, description = "The blob, with the content type the PDS gave it; only raster images are shown inline"),
+        (status = 400, description = "Invalid did or cid"),
+        (status = 404, description = "The PDS has no such blob"),
+        (status = 413, description = "The blob is over `cache.blob_max_bytes`")
+    )
+)]
+pub(crate) async fn get(
+    State(state): State<AppView>,
+    Path((did, cid)): Path<(String, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let blob = serve(&state.pds, &state.caches, &did, &cid).await?;
+    let disposition = disposition(&blob.content_type);
+    Ok((
+        [
+            (CONTENT_TYPE, blob.content_type),
+            (CACHE_CONTROL, IMMUTABLE.to_string()),
+            (CONTENT_DISPOSITION, disposition.to_string()),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (CONTENT_SECURITY_POLICY, "sandbox".to_string()),
+        ],
+        blob.bytes,
+    ))
+}
+
+/// `inline` for raster images, `attachment` for anything a browser could
+/// run as a page of this origin.
+fn disposition(content_type: &str) -> &'static str {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    if INLINE_TYPES.iter().any(|t| t.eq_ignore_ascii_case(essence)) {
+        "inline"
+    } else {
+        "attachment"
+    }
+}
+
+/// The blob from the cache, or from the PDS when it is not cached yet.
+async fn serve(pds: &str, caches: &Caches, did: &str, cid: &str) -> Result<Blob, AppError> {
+    if !did.starts_with("did:") {
+        return Err(AppError::ValidateFailed("invalid did".to_string()));
+    }
+    Cid::try_from(cid).map_err(|e| AppError::ValidateFailed(format!("invalid cid: {e}")))?;
+
+    if let Some(blob) = caches.blob(did, cid).await {
+        return Ok(blob);
+    }
+    let blob = fetch(pds, did, cid, caches.blob_max_bytes()).await?;
+    caches.insert_blob(did, cid, blob.clone()).await;
+    Ok(blob)
+}
+
+/// Reads the blob chunk by chunk, giving up as soon as it is over
+/// `max_bytes`.
+async fn fetch(pds: &str, did: &str, cid: &str, max_bytes: u64) -> Result<Blob, AppError> {
+    let too_large = || AppError::TooLarge(format!("blob is over {max_bytes} bytes"));
+    let mut response = reqwest::Client::new()
+        .get(format!("{pds}/xrpc/com.atproto.sync.getBlob"))
+        .query(&[("did", did), ("cid", cid)])
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .map_err(|e| AppError::RpcFailed(format!("call pds failed: {e}")))?;
+    match response.status() {
+        status if status.is_success() => {}
+        // the PDS answers BlobNotFound and RepoNotFound with a 400
+        StatusCode::NOT_FOUND | StatusCode::BAD_REQUEST => return Err(AppError::NotFound),
+        status => return Err(AppError::RpcFailed(format!("pds answered {status}"))),
+    }
+    if response.content_length().is_some_and(|len| len > max_bytes) {
+        return Err(too_large());
+    }
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| AppError::RpcFailed(format!("read blob failed: {e}")))?
+    {
+        if (bytes.len() + chunk.len()) as u64 > max_bytes {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Blob {
+        content_type,
+        bytes: bytes.into(),
+    })
+}
+
+#[tokio::test]
+async fn second_request_is_served_from_cache() {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use common_x::restful::axum::{
+        Router,
+        extract::Query,
+        http::{HeaderMap, HeaderValue},
+        routing,
+    };
+
+    use crate::config::CacheConfig;
+
+    const AVATAR: &str = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+    const HUGE: &str = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e";
+
+    async fn get_blob(
+        State(calls): State<Arc<AtomicUsize>>,
+        Query(query): Query<std::collections::HashMap<String, String>>,
+    ) -> Result<(HeaderMap, Vec<u8>), StatusCode> {
+        calls.fetch_add(1, Ordering::Relaxed);
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+        match query["cid"].as_str() {
+            AVATAR => Ok((headers, b"\x89PNG avatar".to_vec())),
+            HUGE => Ok((headers, vec![0; 2048])),
+            _ => Err(StatusCode::BAD_REQUEST),
+        }
+    }
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let router = Router::new()
+        .route("/xrpc/com.atproto.sync.getBlob", routing::get(get_blob))
+        .with_state(calls.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let pds = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(common_x::restful::axum::serve(listener, router));
+    let caches = Caches::new(&CacheConfig {
+        blob_max_bytes: 1024,
+        ..Default::default()
+    });
+
+    for _ in 0..2 {
+        let blob = serve(&pds, &caches, "did:ckb:alice", AVATAR).await.unwrap();
+        assert_eq!(blob.content_type, "image/png");
+        assert_eq!(&blob.bytes[..], b"\x89PNG avatar");
+    }
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+    assert_eq!(caches.stats()["blobs"]["hits"], 1);
+
+    assert!(matches!(
+        serve(&pds, &caches, "did:ckb:alice", HUGE).await,
+        Err(AppError::TooLarge(_))
+    ));
+    let cid = "bafkreiaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    assert!(matches!(
+        serve(&pds, &caches, "did:ckb:alice", cid).await,
+        Err(AppError::NotFound)
+    ));
+    // rejected before the PDS is asked
+    for (did, cid) in [("did:ckb:alice", "../../etc/passwd"), ("alice", AVATAR)] {
+        assert!(matches!(
+            serve(&pds, &caches, did, cid).await,
+            Err(AppError::ValidateFailed(_))
+        ));
+    }
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn only_raster_images_are_inline() {
+    assert_eq!(disposition("image/png"), "inline");
+    assert_eq!(disposition("IMAGE/JPEG; charset=binary"), "inline");
+    for content_type in [
+        "image/svg+xml",
+        "text/html",
+        "application/xhtml+xml",
+        "application/octet-stream",
+        "",
+    ] {
+        assert_eq!(disposition(content_type), "attachment", "{content_type}");
+    }
+}
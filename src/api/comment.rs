@@ -1,3 +1,7 @@
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
 use color_eyre::eyre::eyre;
 use common_x::restful::{
     axum::{Json, extract::State, response::IntoResponse},
@@ -7,14 +11,17 @@ use sea_query::{Expr, ExprTrait, Order, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
 use serde::Deserialize;
 use serde_json::json;
-use sqlx::query_as_with;
 use utoipa::ToSchema;
 use validator::Validate;
 
 use crate::{
     AppView,
-    api::{build_author, reply::ReplyQuery},
+    api::{
+        build_author, is_moderator, is_privileged, reply::ReplyQuery, valid::Valid,
+        verified_viewer, visible_to,
+    },
     atproto::NSID_COMMENT,
+    db,
     error::AppError,
     lexicon::{
         administrator::Administrator,
@@ -30,8 +37,11 @@ pub(crate) struct CommentQuery {
     pub post: String,
     #[validate(range(min = 1))]
     pub page: u64,
+    /// Defaults to `pagination.comment_list` of the config, capped at its
+    /// `max`.
     #[validate(range(min = 1))]
-    pub per_page: u64,
+    pub per_page: Option<u64>,
+    /// Only counts with a bearer token of the viewer's PDS session.
     pub viewer: Option<String>,
 }
 
@@ -40,7 +50,7 @@ impl Default for CommentQuery {
         Self {
             post: String::new(),
             page: 1,
-            per_page: 20,
+            per_page: None,
             viewer: None,
         }
     }
@@ -49,25 +59,34 @@ impl Default for CommentQuery {
 #[utoipa::path(post, path = "/api/comment/list")]
 pub(crate) async fn list(
     State(state): State<AppView>,
-    Json(query): Json<CommentQuery>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Valid(Json(query)): Valid<Json<CommentQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
-    query
-        .validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
-    let offset = query.per_page * (query.page - 1);
+    let query = CommentQuery {
+        viewer: verified_viewer(&state.pds, &query.viewer, auth).await?,
+        ..query
+    };
+    let per_page = state.pagination.comment_list.resolve(query.per_page);
+    let offset = per_page * (query.page - 1);
     let (sql, values) = Comment::build_select(query.viewer.clone())
         .and_where(Expr::col((Comment::Table, Comment::Post)).eq(&query.post))
+        .and_where(visible_to(
+            &query.viewer,
+            Comment::Table,
+            Comment::IsDisabled,
+            Comment::Repo,
+            Comment::SectionId,
+        ))
         .order_by(Comment::Created, Order::Asc)
         .offset(offset)
-        .limit(query.per_page)
+        .limit(per_page)
         .build_sqlx(PostgresQueryBuilder);
 
-    let rows: Vec<CommentRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    let rows: Vec<CommentRow> = db::fetch_all(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
-    let sections = Section::all(&state.db).await?;
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
     let admins = Administrator::all_did(&state.db).await;
     let mut views = vec![];
     for row in rows {
@@ -78,54 +97,50 @@ pub(crate) async fn list(
                 comment: row.uri.to_string(),
                 to: None,
                 cursor: None,
-                limit: 2,
+                limit: Some(2),
                 viewer: query.viewer.clone(),
             },
         )
         .await
         .unwrap_or(json!({}));
         let author = build_author(&state, &row.repo).await;
-        let display = if let Some(viewer) = &query.viewer {
-            &row.repo == viewer
-                || sections
-                    .get(&row.section_id)
-                    .is_some_and(|section| section.owner.as_ref() == Some(viewer))
-                || admins.contains(viewer)
-        } else {
-            false
-        };
-        if !row.is_disabled || display {
-            let tip_count = micro_pay::payment_completed_total(
-                &state.pay_url,
-                &format!("{}/{}", NSID_COMMENT, row.uri),
-            )
-            .await
-            .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
-            .unwrap_or(0);
-            views.push(CommentView::build(
-                row,
-                author,
-                replies,
-                tip_count.to_string(),
-            ));
-        }
+        let display = is_privileged(&query.viewer, &row.repo, row.section_id, &sections, &admins);
+        let moderator = is_moderator(&query.viewer, row.section_id, &sections, &admins);
+        let tip_count = micro_pay::payment_completed_total(
+            &state.pay_url,
+            &format!("{}/{}", NSID_COMMENT, row.uri),
+        )
+        .await
+        .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
+        .unwrap_or(0);
+        views.push(
+            CommentView::build(row, author, replies, tip_count.to_string())
+                .for_viewer(display)
+                .for_moderator(moderator),
+        );
     }
 
     let (sql, values) = sea_query::Query::select()
         .expr(Expr::col((Comment::Table, Comment::Uri)).count())
         .from(Comment::Table)
-        .and_where(Expr::col((Comment::Table, Comment::Post)).eq(query.post))
+        .and_where(Expr::col((Comment::Table, Comment::Post)).eq(&query.post))
+        .and_where(visible_to(
+            &query.viewer,
+            Comment::Table,
+            Comment::IsDisabled,
+            Comment::Repo,
+            Comment::SectionId,
+        ))
         .build_sqlx(PostgresQueryBuilder);
 
-    let total: (i64,) = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
+    let total: (i64,) = db::fetch_one(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
     Ok(ok(json!({
         "comments": views,
         "page": query.page,
-        "per_page": query.per_page,
+        "per_page": per_page,
         "total":  total.0
     })))
 }
@@ -0,0 +1,149 @@
+use common_x::restful::{
+    axum::{Json, extract::State, response::IntoResponse},
+    ok, ok_simple,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::{
+    AppView,
+    api::{SignedBody, SignedParam, valid::Valid},
+    content_filter::{MAX_PATTERN_LEN, Rule},
+    error::AppError,
+    lexicon::{
+        administrator::Administrator,
+        content_rule::{ContentRule, ContentRuleRow, ContentRuleView},
+    },
+};
+
+async fn check_admin<T: SignedParam>(
+    state: &AppView,
+    body: &SignedBody<T>,
+) -> Result<(), AppError> {
+    let admins = Administrator::all_did(&state.db).await;
+    if !admins.contains(&body.did) {
+        return Err(AppError::ValidateFailed(
+            "only administrator can manage content rules".to_string(),
+        ));
+    }
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct ContentRuleParams {
+    /// `word`, `regex` or `domain`.
+    pub kind: String,
+    #[validate(length(min = 1, max = MAX_PATTERN_LEN))]
+    pub pattern: String,
+    /// `reject`, `flag` or `shadow`.
+    pub action: String,
+    /// Shown to authors of rejected content instead of the pattern.
+    #[validate(length(min = 1, max = 64))]
+    pub category: String,
+    /// Only content of this section; "0" for all sections.
+    pub section_id: String,
+    pub timestamp: i64,
+}
+
+impl Default for ContentRuleParams {
+    fn default() -> Self {
+        Self {
+            kind: "word".to_string(),
+            pattern: Default::default(),
+            action: "flag".to_string(),
+            category: Default::default(),
+            section_id: "0".to_string(),
+            timestamp: Default::default(),
+        }
+    }
+}
+
+impl SignedParam for ContentRuleParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+#[utoipa::path(post, path = "/api/admin/content_rule/add")]
+pub(crate) async fn add(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<ContentRuleParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    check_admin(&state, &body).await?;
+
+    let params = body.params;
+    let row = ContentRuleRow {
+        id: 0,
+        kind: params.kind,
+        pattern: params.pattern,
+        action: params.action,
+        category: params.category,
+        section_id: params.section_id.parse::<i32>()?,
+        created: chrono::Local::now(),
+    };
+    Rule::compile(&row).map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let id = ContentRule::insert(&state.db, &row).await?;
+    state.caches.invalidate_content_rules().await;
+
+    Ok(ok(json!({ "id": id.to_string() })))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct ContentRuleIdParams {
+    #[validate(length(min = 1))]
+    pub id: String,
+    pub timestamp: i64,
+}
+
+impl SignedParam for ContentRuleIdParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+#[utoipa::path(post, path = "/api/admin/content_rule/delete")]
+pub(crate) async fn delete(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<ContentRuleIdParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    check_admin(&state, &body).await?;
+
+    ContentRule::delete(&state.db, body.params.id.parse::<i32>()?).await?;
+    state.caches.invalidate_content_rules().await;
+
+    Ok(ok_simple())
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct ContentRuleListParams {
+    pub timestamp: i64,
+}
+
+impl SignedParam for ContentRuleListParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+#[utoipa::path(post, path = "/api/admin/content_rule/list")]
+pub(crate) async fn list(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<ContentRuleListParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    check_admin(&state, &body).await?;
+
+    let rows = ContentRule::all(&state.db).await?;
+
+    Ok(ok(rows
+        .into_iter()
+        .map(ContentRuleView::build)
+        .collect::<Vec<_>>()))
+}
@@ -5,10 +5,12 @@ use serde_json::{Value, json};
 use utoipa::ToSchema;
 use validator::Validate;
 
-use crate::api::{SignedBody, SignedParam, build_author};
+use crate::api::{SignedBody, SignedParam, build_author, tip::prepare_payment, valid::Valid};
 use crate::lexicon::notify::{Notify, NotifyRow, NotifyType};
 use crate::lexicon::resolve_uri;
-use crate::lexicon::tip::{TipCategory, TipRow, TipState, TipView};
+use crate::lexicon::tip::{
+    Tip, TipCategory, TipRow, TipState, TipView, parse_shannons, shannons_to_ckb,
+};
 use crate::micro_pay;
 use crate::{AppView, error::AppError};
 
@@ -18,7 +20,13 @@ pub(crate) struct DonateParams {
     pub nsid: String,
     pub ckb_addr: String,
     pub sender: String,
+    /// Whole shannons, as a decimal string.
     pub amount: String,
+    /// Preparing again with the key is refused with the tip it prepared,
+    /// until that tip times out.
+    #[validate(length(min = 1))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
     pub timestamp: i64,
 }
 
@@ -31,11 +39,11 @@ impl SignedParam for DonateParams {
 #[utoipa::path(post, path = "/api/donate/prepare")]
 pub(crate) async fn prepare(
     State(state): State<AppView>,
-    Json(body): Json<SignedBody<DonateParams>>,
+    Valid(Json(body)): Valid<Json<SignedBody<DonateParams>>>,
 ) -> Result<impl IntoResponse, AppError> {
-    body.validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
-    body.verify_signature(&state.indexer)
+    let amount =
+        parse_shannons(&body.params.amount).map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+    body.verify_signature(&state)
         .await
         .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
 
@@ -46,39 +54,16 @@ pub(crate) async fn prepare(
         sender: body.params.sender.clone(),
         receiver: body.params.ckb_addr.clone(),
         receiver_did: body.params.ckb_addr.clone(),
-        amount: body.params.amount.parse::<i64>()?,
+        amount,
         info: format!("{}/{}", body.params.nsid, body.params.ckb_addr),
         state: TipState::Prepared as i32,
         tx_hash: None,
+        idempotency_key: body.params.idempotency_key.clone(),
         updated: chrono::Local::now(),
         created: chrono::Local::now(),
     };
 
-    let result = micro_pay::payment_prepare(
-        &state.pay_url,
-        &json!({
-            "sender": &tip_row.sender,
-            "senderDid": &tip_row.sender_did,
-            "receiver": &tip_row.receiver,
-            "receiverDid": &tip_row.receiver_did,
-            "category": &tip_row.category,
-            "amount": &tip_row.amount,
-            "info": &tip_row.info,
-            "splitReceivers": []
-        }),
-    )
-    .await?;
-
-    if let Some(err) = result.get("error") {
-        return Err(AppError::MicroPayIncomplete(
-            result.get("code").unwrap_or(err).to_string(),
-        ));
-    }
-
-    tip_row.tx_hash = result
-        .get("txHash")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
+    let result = prepare_payment(&state, &mut tip_row, &[]).await?;
 
     let author = build_author(&state, &tip_row.sender_did).await;
     let tip = TipView {
@@ -90,6 +75,7 @@ pub(crate) async fn prepare(
         receiver: tip_row.receiver.clone(),
         receiver_did: tip_row.receiver_did.clone(),
         amount: tip_row.amount.to_string(),
+        amount_ckb: shannons_to_ckb(tip_row.amount),
         info: tip_row.info.clone(),
         state: tip_row.state.to_string(),
         tx_hash: tip_row.tx_hash.clone(),
@@ -112,6 +98,12 @@ pub(crate) async fn transfer(
     if let Some(id) = result.get("paymentId").and_then(|id| id.as_i64()) {
         let payment = micro_pay::payment(&state.pay_url, id).await?;
         debug!("payment: {payment}");
+        if let Some(tx_hash) = payment.pointer("/payment/txHash").and_then(|i| i.as_str()) {
+            Tip::update_state(&state.db, tx_hash, TipState::Committed)
+                .await
+                .map_err(|e| error!("Tip::update_state failed: {e}"))
+                .ok();
+        }
         if let Some(info) = payment.pointer("/payment/info").and_then(|i| i.as_str())
             && let Some(sender) = payment
                 .pointer("/payment/senderDid")
@@ -130,7 +122,7 @@ pub(crate) async fn transfer(
                         receiver: receiver.to_string(),
                         n_type: NotifyType::NewDonate as i32,
                         target_uri: to.to_string(),
-                        amount: amount.parse::<i64>().unwrap_or(0),
+                        amount: parse_shannons(amount).unwrap_or(0),
                         readed: None,
                         created: chrono::Local::now(),
                     },
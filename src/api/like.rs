@@ -1,3 +1,7 @@
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
 use color_eyre::eyre::eyre;
 use common_x::restful::{
     axum::{Json, extract::State, response::IntoResponse},
@@ -7,23 +11,32 @@ use sea_query::{BinOper, Expr, ExprTrait, Func, Order, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
 use serde::Deserialize;
 use serde_json::{Value, json};
-use sqlx::query_as_with;
-use utoipa::ToSchema;
-use validator::Validate;
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
 
 use crate::{
     AppView,
-    api::{ToTimestamp, build_author},
+    api::{
+        ToTimestamp, build_author, known_period, likes_visible_to, period_hours,
+        valid::{Valid, ValidQuery},
+        verified_viewer,
+    },
+    db,
     error::AppError,
-    lexicon::like::{Like, LikeRow, LikeView},
+    lexicon::like::{Like, LikeRow, LikeStatsRow, LikeView},
 };
 
 #[derive(Debug, Validate, Deserialize, ToSchema)]
 #[serde(default)]
 pub(crate) struct LikeQuery {
+    /// Lists what this user liked; refused unless they made their likes
+    /// public or are the `viewer`.
     pub repo: Option<String>,
+    /// Only counts with a bearer token of the viewer's PDS session.
+    pub viewer: Option<String>,
     pub to: Option<String>,
     pub cursor: Option<String>,
+    #[validate(range(min = 1))]
     pub limit: u64,
 }
 
@@ -31,6 +44,7 @@ impl Default for LikeQuery {
     fn default() -> Self {
         Self {
             repo: None,
+            viewer: None,
             to: None,
             cursor: Default::default(),
             limit: 30,
@@ -41,16 +55,23 @@ impl Default for LikeQuery {
 #[utoipa::path(post, path = "/api/like/list")]
 pub(crate) async fn list(
     State(state): State<AppView>,
-    Json(query): Json<LikeQuery>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Valid(Json(query)): Valid<Json<LikeQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
-    let result = list_like(&state, query).await?;
+    let viewer = verified_viewer(&state.pds, &query.viewer, auth).await?;
+    let result = list_like(&state, LikeQuery { viewer, ..query }).await?;
     Ok(ok(result))
 }
 
+/// The `viewer` of `query` must have been verified.
 pub(crate) async fn list_like(state: &AppView, query: LikeQuery) -> Result<Value, AppError> {
-    query
-        .validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+    if let Some(repo) = &query.repo
+        && !likes_visible_to(state, repo, query.viewer.as_deref()).await?
+    {
+        return Err(AppError::IsDisabled(format!(
+            "the likes of {repo} are private"
+        )));
+    }
     let (sql, values) = sea_query::Query::select()
         .columns([
             (Like::Table, Like::Uri),
@@ -82,8 +103,7 @@ pub(crate) async fn list_like(state: &AppView, query: LikeQuery) -> Result<Value
         .limit(query.limit)
         .build_sqlx(PostgresQueryBuilder);
 
-    let rows: Vec<LikeRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    let rows: Vec<LikeRow> = db::fetch_all(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
@@ -113,3 +133,127 @@ pub(crate) async fn list_like(state: &AppView, query: LikeQuery) -> Result<Value
 
     Ok(result)
 }
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[serde(default)]
+pub struct LikeStatsQuery {
+    pub section_id: Option<i32>,
+    /// One of `day`, `week` or `month`.
+    #[validate(custom(function = "known_period"))]
+    pub period: String,
+    /// One of `hour`, `day` or `week`.
+    #[validate(custom(function = "known_granularity"))]
+    pub granularity: String,
+}
+
+fn known_granularity(granularity: &str) -> Result<(), ValidationError> {
+    if matches!(granularity, "hour" | "day" | "week") {
+        return Ok(());
+    }
+    let mut error = ValidationError::new("one_of");
+    error.add_param("allowed".into(), &["hour", "day", "week"]);
+    Err(error)
+}
+
+impl Default for LikeStatsQuery {
+    fn default() -> Self {
+        Self {
+            section_id: None,
+            period: "week".to_string(),
+            granularity: "day".to_string(),
+        }
+    }
+}
+
+/// Likes per `granularity` bucket over the last `period`.
+#[utoipa::path(get, path = "/api/like/stats", params(LikeStatsQuery))]
+pub(crate) async fn stats(
+    State(state): State<AppView>,
+    ValidQuery(query): ValidQuery<LikeStatsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let since = chrono::Local::now() - chrono::Duration::hours(period_hours(&query.period)?);
+    let (sql, values) = Like::build_stats(query.section_id, since, &query.granularity)
+        .build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<LikeStatsRow> = db::fetch_all(&state.db, &sql, values)
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+
+    Ok(ok(rows))
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn private_likes_are_listed_only_for_their_owner() {
+    use sqlx::{Executor, query};
+
+    use crate::lexicon::privacy_pref::PrivacyPref;
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO \"like\" (uri, cid, repo, \"to\", section_id) VALUES
+            ('at://did:ckb:alice/app.bbs.like/l1', 'cid1', 'did:ckb:alice', 'at://did:ckb:bob/app.bbs.post/p1', 1),
+            ('at://did:ckb:bob/app.bbs.like/l2', 'cid2', 'did:ckb:bob', 'at://did:ckb:bob/app.bbs.post/p1', 1)",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    let state = AppView::for_tests(db.clone());
+    let alice = "did:ckb:alice";
+    let list = async |repo: Option<&str>, viewer: Option<&str>| {
+        list_like(
+            &state,
+            LikeQuery {
+                repo: repo.map(str::to_string),
+                viewer: viewer.map(str::to_string),
+                ..Default::default()
+            },
+        )
+        .await
+        .map(|result| result["likes"].as_array().unwrap().len())
+    };
+
+    // private until she says otherwise, except to herself
+    assert!(matches!(
+        list(Some(alice), Some("did:ckb:bob")).await,
+        Err(AppError::IsDisabled(_))
+    ));
+    assert!(matches!(
+        list(Some(alice), None).await,
+        Err(AppError::IsDisabled(_))
+    ));
+    assert_eq!(list(Some(alice), Some(alice)).await.unwrap(), 1);
+    // the likes of a post are not hers to hide
+    assert_eq!(list(None, None).await.unwrap(), 2);
+
+    PrivacyPref::upsert(&db, alice, true).await.unwrap();
+    assert_eq!(list(Some(alice), Some("did:ckb:bob")).await.unwrap(), 1);
+    assert_eq!(list(Some(alice), None).await.unwrap(), 1);
+
+    PrivacyPref::upsert(&db, alice, false).await.unwrap();
+    assert!(matches!(
+        list(Some(alice), None).await,
+        Err(AppError::IsDisabled(_))
+    ));
+
+    // naming her as the viewer takes her session
+    let state = AppView {
+        pds: crate::api::mock_pds().await,
+        ..state
+    };
+    let request = |token: Option<&str>| {
+        self::list(
+            State(state.clone()),
+            token.map(|did| TypedHeader(Authorization::bearer(did).unwrap())),
+            Valid(Json(LikeQuery {
+                repo: Some(alice.to_string()),
+                viewer: Some(alice.to_string()),
+                ..Default::default()
+            })),
+        )
+    };
+    assert!(matches!(request(None).await, Err(AppError::IsDisabled(_))));
+    assert!(matches!(
+        request(Some("did:ckb:bob")).await,
+        Err(AppError::ValidateFailed(_))
+    ));
+    assert!(request(Some(alice)).await.is_ok());
+}
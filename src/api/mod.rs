@@ -1,31 +1,54 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
 use color_eyre::eyre::{OptionExt, eyre};
+use common_x::restful::axum::{Json, Router, middleware::from_fn_with_state, routing::get};
 use k256::ecdsa::{Signature, VerifyingKey, signature::Verifier};
-use sea_query::{BinOper, Expr, ExprTrait, PostgresQueryBuilder};
+use sea_query::{BinOper, Expr, ExprTrait, IntoIden, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use sqlx::query_as_with;
 use utoipa::{
     Modify, OpenApi, ToSchema,
-    openapi::security::{ApiKey, ApiKeyValue, SecurityScheme},
+    openapi::{
+        ContentBuilder, Ref, ResponseBuilder,
+        security::{ApiKey, ApiKeyValue, SecurityScheme},
+    },
 };
-use validator::Validate;
+use utoipa_scalar::{Scalar, Servable};
+use validator::{Validate, ValidationError, ValidationErrors};
 
 use crate::{
     AppView,
-    atproto::{NSID_PROFILE, get_record},
+    atproto::{NSID_PROFILE, PdsError, get_record, get_session},
     ckb::get_ckb_addr_by_did,
+    config::{ApidocConfig, ApidocMode},
+    db,
+    error::{AppError, FieldError},
+    indexer::handle_of,
     lexicon::{
         administrator::{Administrator, AdministratorRow},
         comment::Comment,
-        like::Like,
+        like::{Like, LikeReceivedRow},
+        payout_pref::PayoutPref,
         post::Post,
-        section::{Section, SectionRowSample},
+        privacy_pref::PrivacyPref,
+        profile::Profile,
+        section::{Section, SectionRow, SectionRowSample, moderator_stand_in},
+        tip::Tip,
+        whitelist::Whitelist,
     },
+    middleware::apidoc_auth::apidoc_auth,
 };
 
 pub(crate) mod admin;
+pub(crate) mod audit;
+pub(crate) mod blob;
 pub(crate) mod comment;
+pub(crate) mod content_rule;
 pub(crate) mod donate;
 pub(crate) mod like;
 pub(crate) mod notify;
@@ -33,83 +56,206 @@ pub(crate) mod post;
 pub(crate) mod record;
 pub(crate) mod reply;
 pub(crate) mod repo;
+pub(crate) mod resolve;
+pub(crate) mod search;
 pub(crate) mod section;
 pub(crate) mod tip;
+pub(crate) mod valid;
+pub(crate) mod version;
+pub(crate) mod webhook;
+pub(crate) mod well_known;
 pub(crate) mod whitelist;
+pub(crate) mod xrpc;
 
+/// The public endpoints; see `openapi` for the whole document.
 #[derive(OpenApi, Debug, Clone, Copy)]
 #[openapi(
-    modifiers(&SecurityAddon),
+    modifiers(&SecurityAddon, &ResponsesAddon),
     paths(
-        admin::update_tag,
-        admin::update_owner,
-        admin::update_section,
-        admin::create_section,
-        admin::add_whitelist,
-        admin::delete_whitelist,
-        admin::list,
-        admin::add,
-        admin::operations,
-        admin::delete,
         record::create,
         record::update,
         record::delete,
         section::list,
         section::detail,
+        section::trending,
+        section::mine,
         post::list,
         post::page,
         post::top,
         post::detail,
+        post::thread,
+        post::analytics,
+        post::engagement,
+        post::participants,
         post::commented,
         post::commented_page,
         post::list_draft,
         post::detail_draft,
+        post::save_draft,
+        post::publish,
+        post::pin,
+        post::mute,
+        post::unmute,
+        post::search_mine,
         comment::list,
         reply::list,
         reply::page,
         repo::profile,
         repo::login_info,
+        repo::quota,
+        repo::state,
+        repo::stats,
+        repo::remove_me,
+        repo::restore_me,
+        repo::payout_address,
+        repo::likes_public,
+        repo::followers,
+        repo::following,
         like::list,
+        like::stats,
+        search::global,
         tip::prepare,
         tip::transfer,
         tip::list_by_for,
         tip::expense_details,
         tip::income_details,
         tip::stats,
+        tip::pending,
         donate::prepare,
         donate::transfer,
         notify::list,
         notify::read,
         notify::unread_num,
+        resolve::resolve,
         whitelist::list,
+        well_known::did_document,
+        well_known::health,
+        well_known::readyz,
+        well_known::status,
+        well_known::time,
+        xrpc::get_posts,
+        xrpc::get_thread,
+        xrpc::list_sections,
+        blob::get,
     ),
     components(schemas(
-        SignedBody<admin::UpdateTagParams>,
-        SignedBody<admin::UpdateOwnerParams>,
-        SignedBody<admin::UpdateSectionParams>,
-        SignedBody<admin::CreateSectionParams>,
-        SignedBody<admin::WhitelistParams>,
-        SignedBody<admin::UpdateAdminParams>,
+        SignedBody<repo::RemoveMeParams>,
+        SignedBody<repo::RestoreMeParams>,
+        SignedBody<repo::PayoutAddressParams>,
+        SignedBody<repo::LikesPublicParams>,
         record::NewRecord,
+        crate::lexicon::repo_state::RepoStateRow,
+        crate::maintenance::MaintenanceState,
         post::PostQuery,
         post::PostPageQuery,
         post::TopQuery,
+        post::EngagementQuery,
+        post::EngagementView,
         post::DraftQuery,
+        SignedBody<post::SaveDraftParams>,
+        post::PublishDraft,
+        SignedBody<post::PinPostParams>,
+        SignedBody<post::MuteThreadParams>,
+        SignedBody<post::SearchMineParams>,
+        SignedBody<post::AnalyticsParams>,
         comment::CommentQuery,
         reply::ReplyQuery,
         reply::ReplyPageQuery,
+        crate::lexicon::reply::ReplyView,
         like::LikeQuery,
+        search::GlobalSearchQuery,
+        section::SectionWorkload,
         SignedBody<tip::TipParams>,
         tip::TipsQuery,
         tip::DetailQuery,
+        tip::TipStats,
         SignedBody<donate::DonateParams>,
         notify::NotifyQuery,
         notify::NotifyReadQuery,
         crate::lexicon::notify::NotifyType,
+        ApiResponse,
+        ApiErrorResponse,
+        FieldError,
     ))
 )]
 pub struct ApiDoc;
 
+/// The admin endpoints, left out of the public document.
+#[derive(OpenApi, Debug, Clone, Copy)]
+#[openapi(
+    modifiers(&ResponsesAddon),
+    paths(
+        admin::update_tag,
+        admin::update_owner,
+        admin::update_section,
+        admin::ban,
+        admin::unban,
+        admin::create_section,
+        admin::add_whitelist,
+        admin::delete_whitelist,
+        admin::list,
+        admin::add,
+        admin::operations,
+        admin::delete,
+        admin::flush_cache,
+        admin::cache_stats,
+        admin::moderation_stats,
+        admin::hidden_list,
+        admin::resync_record,
+        admin::recount,
+        admin::relayer_status,
+        admin::relayer_restart,
+        admin::broadcast,
+        admin::maintenance,
+        admin::log_level,
+        admin::broadcast_status,
+        audit::events,
+        webhook::add,
+        webhook::update,
+        webhook::delete,
+        webhook::list,
+        webhook::deliveries,
+        content_rule::add,
+        content_rule::delete,
+        content_rule::list,
+    ),
+    components(schemas(
+        SignedBody<admin::UpdateTagParams>,
+        SignedBody<admin::UpdateOwnerParams>,
+        SignedBody<admin::UpdateSectionParams>,
+        SignedBody<admin::BanParams>,
+        SignedBody<admin::UnbanParams>,
+        SignedBody<admin::CreateSectionParams>,
+        SignedBody<admin::WhitelistParams>,
+        SignedBody<admin::UpdateAdminParams>,
+        SignedBody<admin::FlushCacheParams>,
+        SignedBody<admin::ResyncParams>,
+        SignedBody<admin::RecountParams>,
+        SignedBody<admin::RelayerRestartParams>,
+        SignedBody<admin::MaintenanceParams>,
+        SignedBody<admin::LogLevelParams>,
+        SignedBody<admin::HiddenListParams>,
+        SignedBody<admin::BroadcastParams>,
+        SignedBody<audit::AuditQueryParams>,
+        SignedBody<webhook::WebhookParams>,
+        SignedBody<webhook::UpdateWebhookParams>,
+        SignedBody<webhook::WebhookIdParams>,
+        SignedBody<webhook::WebhookListParams>,
+        SignedBody<webhook::DeliveryQueryParams>,
+        SignedBody<content_rule::ContentRuleParams>,
+        SignedBody<content_rule::ContentRuleIdParams>,
+        SignedBody<content_rule::ContentRuleListParams>,
+        admin::ModerationStats,
+        admin::SectionModeration,
+        admin::ModeratorActivity,
+        admin::HiddenItem,
+        admin::HiddenReason,
+        admin::HiddenBy,
+        admin::HiddenPost,
+    ))
+)]
+pub struct AdminApiDoc;
+
 struct SecurityAddon;
 
 impl Modify for SecurityAddon {
@@ -123,6 +269,89 @@ impl Modify for SecurityAddon {
     }
 }
 
+/// Envelope produced by `common_x::restful::ok`.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub(crate) struct ApiResponse {
+    code: u16,
+    message: String,
+    data: Option<Value>,
+}
+
+/// Body produced by `AppError::into_response`.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub(crate) struct ApiErrorResponse {
+    code: u16,
+    error: String,
+    message: String,
+    /// Every field that failed validation, when `error` is
+    /// `ValidateFailed`.
+    details: Option<Vec<FieldError>>,
+}
+
+/// Documents the shared response envelopes on every operation that does not
+/// declare its own responses.
+struct ResponsesAddon;
+
+impl Modify for ResponsesAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let response = |description: &str, schema: &str| {
+            ResponseBuilder::new()
+                .description(description)
+                .content(
+                    "application/json",
+                    ContentBuilder::new()
+                        .schema(Some(Ref::from_schema_name(schema)))
+                        .build(),
+                )
+                .build()
+        };
+        for item in openapi.paths.paths.values_mut() {
+            for operation in [&mut item.get, &mut item.post].into_iter().flatten() {
+                if operation.responses.responses.is_empty() {
+                    operation
+                        .responses
+                        .responses
+                        .insert("200".to_string(), response("OK", "ApiResponse").into());
+                    operation.responses.responses.insert(
+                        "default".to_string(),
+                        response("Error", "ApiErrorResponse").into(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// The API document; `public` leaves out the admin endpoints.
+pub(crate) fn openapi(public: bool) -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    if !public {
+        doc.merge(AdminApiDoc::openapi());
+    }
+    doc
+}
+
+/// Routes serving the API document as `config` asks, behind a bearer token
+/// when one is configured.
+pub(crate) fn apidoc_router<S: Clone + Send + Sync + 'static>(config: &ApidocConfig) -> Router<S> {
+    let spec = openapi(config.public_spec);
+    let router = match config.mode {
+        ApidocMode::Off => return Router::new(),
+        ApidocMode::Spec => Router::new(),
+        ApidocMode::Full => Router::new().merge(Scalar::with_url("/apidoc", spec.clone())),
+    };
+    let router = router.route(
+        "/apidoc/openapi.json",
+        get(move || async move { Json(spec) }),
+    );
+    match config.token.as_deref().filter(|token| !token.is_empty()) {
+        Some(token) => router.route_layer(from_fn_with_state(Arc::<str>::from(token), apidoc_auth)),
+        None => router,
+    }
+}
+
 pub(crate) struct ToTimestamp;
 
 impl sea_query::Iden for ToTimestamp {
@@ -131,10 +360,236 @@ impl sea_query::Iden for ToTimestamp {
     }
 }
 
+/// Length in hours of a `day`, `week` or `month` reporting period.
+pub(crate) fn period_hours(period: &str) -> Result<i64, AppError> {
+    match period {
+        "day" => Ok(24),
+        "week" => Ok(24 * 7),
+        "month" => Ok(24 * 30),
+        period => Err(AppError::ValidateFailed(format!(
+            "unsupported period: {period}"
+        ))),
+    }
+}
+
+/// `custom` validator of the periods `period_hours` knows.
+pub(crate) fn known_period(period: &str) -> Result<(), ValidationError> {
+    period_hours(period).map(|_| ()).map_err(|_| {
+        let mut error = ValidationError::new("one_of");
+        error.add_param("allowed".into(), &["day", "week", "month"]);
+        error
+    })
+}
+
+/// Fails unless the PDS session of `token` belongs to `did`.
+pub(crate) async fn check_session(pds: &str, token: &str, did: &str) -> Result<(), AppError> {
+    let session = get_session(pds, token).await.map_err(AppError::rpc)?;
+    if session.get("did").and_then(|did| did.as_str()) != Some(did) {
+        return Err(AppError::ValidateFailed(
+            "token does not belong to did".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// The viewer a request names, once the PDS session of its bearer token
+/// shows it is theirs. Anyone can name a viewer, so one named without a
+/// token is treated as no viewer; a token of someone else fails.
+pub(crate) async fn verified_viewer(
+    pds: &str,
+    viewer: &Option<String>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<Option<String>, AppError> {
+    match (viewer, auth) {
+        (Some(viewer), Some(TypedHeader(auth))) => {
+            check_session(pds, auth.token(), viewer).await?;
+            Ok(Some(viewer.clone()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The author, the owner of the section and admins may see hidden content and
+/// the moderator notes attached to it.
+pub(crate) fn is_privileged(
+    viewer: &Option<String>,
+    repo: &str,
+    section_id: i32,
+    sections: &HashMap<i32, SectionRow>,
+    admins: &[String],
+) -> bool {
+    viewer.as_deref() == Some(repo) || is_moderator(viewer, section_id, sections, admins)
+}
+
+/// The owner of the section and admins.
+pub(crate) fn is_moderator(
+    viewer: &Option<String>,
+    section_id: i32,
+    sections: &HashMap<i32, SectionRow>,
+    admins: &[String],
+) -> bool {
+    if let Some(viewer) = viewer {
+        sections
+            .get(&section_id)
+            .is_some_and(|section| section.owner.as_ref() == Some(viewer))
+            || admins.contains(viewer)
+    } else {
+        false
+    }
+}
+
+/// `is_privileged` as a filter on `table`: visible rows, and hidden ones
+/// when `viewer` wrote them, owns their section or is an admin.
+pub(crate) fn visible_to(
+    viewer: &Option<String>,
+    table: impl IntoIden,
+    is_disabled: impl IntoIden,
+    repo: impl IntoIden,
+    section_id: impl IntoIden,
+) -> Expr {
+    let table = table.into_iden();
+    let visible = Expr::col((table.clone(), is_disabled.into_iden())).eq(false);
+    let Some(viewer) = viewer else {
+        return visible;
+    };
+    visible
+        .or(Expr::col((table.clone(), repo.into_iden())).eq(viewer))
+        .or(Expr::col((table, section_id.into_iden())).in_subquery(
+            sea_query::Query::select()
+                .column(Section::Id)
+                .from(Section::Table)
+                .and_where(Expr::col(Section::Owner).eq(viewer))
+                .take(),
+        ))
+        .or(Expr::exists(
+            sea_query::Query::select()
+                .column(Administrator::Did)
+                .from(Administrator::Table)
+                .and_where(Expr::col(Administrator::Did).eq(viewer))
+                .take(),
+        ))
+}
+
+/// The author's profile and stats, with the `handle` resolved from their
+/// did document; `null` when that fails. `membership` tells whether they
+/// are whitelisted, which `highlight: "beta"` also marks for old clients.
 pub(crate) async fn build_author(state: &AppView, repo: &str) -> Value {
     if !repo.starts_with("did:") {
         return Value::String(repo.to_string());
     }
+    let mut author = state.caches.author(repo, fetch_author(state, repo)).await;
+    if author.is_object() {
+        author["handle"] = json!(author_handle(state, repo).await);
+        let whitelisted = is_whitelisted(state, repo).await;
+        author["membership"] = json!({ "whitelisted": whitelisted });
+        if whitelisted {
+            author["highlight"] = json!("beta");
+        }
+    }
+    author
+}
+
+/// Read from the cached whitelist; false when it cannot be loaded.
+pub(crate) async fn is_whitelisted(state: &AppView, did: &str) -> bool {
+    state
+        .caches
+        .whitelist(Whitelist::all(&state.db))
+        .await
+        .map_err(|e| debug!("load whitelist failed: {e}"))
+        .is_ok_and(|whitelist| whitelist.contains(did))
+}
+
+pub(crate) async fn author_handle(state: &AppView, did: &str) -> Option<String> {
+    state
+        .caches
+        .handle(did, async {
+            handle_of(&state.indexer, did)
+                .await
+                .map_err(|e| debug!("resolve handle of {did} failed: {e}"))
+                .ok()
+                .flatten()
+        })
+        .await
+}
+
+/// Resolves the handles of the authors seen in the last day again, so that
+/// renames reach the views. A failed lookup keeps the handle known so far.
+pub(crate) async fn refresh_handles(state: &AppView) {
+    for did in state.caches.seen_handles() {
+        match handle_of(&state.indexer, &did).await {
+            Ok(handle) => {
+                if state.caches.refresh_handle(&did, handle.clone()).await {
+                    info!("handle of {did} is now {handle:?}");
+                }
+            }
+            Err(e) => debug!("resolve handle of {did} failed: {e}"),
+        }
+    }
+}
+
+/// The moderator of section `section_id` as `build_author` shows them, or
+/// what stands in for them when the section does not reveal its
+/// moderators. Decided when read, so policy changes apply to the past too.
+pub(crate) async fn build_moderator(
+    state: &AppView,
+    sections: &HashMap<i32, SectionRow>,
+    section_id: i32,
+    did: &str,
+) -> Value {
+    match moderator_stand_in(sections, section_id, state.reveal_moderator) {
+        Some(stand_in) => stand_in,
+        None => build_author(state, did).await,
+    }
+}
+
+/// The author with their ckb address, which `build_author` leaves out
+/// unless `ckb_addr_in_lists` is on. For post details and profiles.
+pub(crate) async fn build_author_with_ckb_addr(state: &AppView, repo: &str) -> Value {
+    let mut author = build_author(state, repo).await;
+    if author.is_object()
+        && author.get("ckb_addr").is_none()
+        && let Some(ckb_addr) = author_ckb_addr(state, repo).await
+    {
+        author["ckb_addr"] = Value::String(ckb_addr);
+    }
+    author
+}
+
+async fn author_ckb_addr(state: &AppView, repo: &str) -> Option<String> {
+    payout_ckb_addr(state, repo)
+        .await
+        .map_err(|e| debug!("get ckb addr of {repo} failed: {e}"))
+        .ok()
+}
+
+/// Where tips to `did` are paid: the payout address they chose, else the
+/// ckb address of their DID cell.
+pub(crate) async fn payout_ckb_addr(state: &AppView, did: &str) -> color_eyre::Result<String> {
+    state
+        .caches
+        .ckb_addr(did, async {
+            match PayoutPref::select(&state.db, did).await? {
+                Some(ckb_addr) => Ok(ckb_addr),
+                None => get_ckb_addr_by_did(&state.ckb_client, &state.ckb_net, did).await,
+            }
+        })
+        .await
+}
+
+/// Counts of what the author wrote and received, cached apart from the
+/// author since the like and tip totals scan all of their content.
+pub(crate) async fn repo_stats(state: &AppView, repo: &str) -> Value {
+    state
+        .caches
+        .repo_stats(repo, fetch_repo_stats(state, repo))
+        .await
+        .unwrap_or_else(|e| {
+            debug!("compute stats of {repo} failed: {e}");
+            json!({})
+        })
+}
+
+async fn fetch_repo_stats(state: &AppView, repo: &str) -> color_eyre::Result<Value> {
     // Get post count
     let (sql, values) = sea_query::Query::select()
         .expr(Expr::col((Post::Table, Post::Uri)).count())
@@ -143,10 +598,7 @@ pub(crate) async fn build_author(state: &AppView, repo: &str) -> Value {
         .and_where(Expr::col(Post::IsDraft).eq(false))
         .and_where(Expr::col((Post::Table, Post::SectionId)).binary(BinOper::NotEqual, 0))
         .build_sqlx(PostgresQueryBuilder);
-    let post_count_row: (i64,) = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
-        .await
-        .unwrap_or((0,));
+    let (post_count,): (i64,) = db::fetch_one(&state.db, &sql, values).await?;
 
     // Get comment count
     let (sql, values) = sea_query::Query::select()
@@ -154,42 +606,101 @@ pub(crate) async fn build_author(state: &AppView, repo: &str) -> Value {
         .from(Comment::Table)
         .and_where(Expr::col(Comment::Repo).eq(repo))
         .build_sqlx(PostgresQueryBuilder);
-    let comment_count_row: (i64,) = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
-        .await
-        .unwrap_or((0,));
+    let (comment_count,): (i64,) = db::fetch_one(&state.db, &sql, values).await?;
+
+    // Get likes on the author's content
+    let (sql, values) = Like::build_received(repo).build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<LikeReceivedRow> = db::fetch_all(&state.db, &sql, values).await?;
+    let mut likes_received = serde_json::Map::new();
+    for row in &rows {
+        likes_received.insert(row.kind.clone(), Value::String(row.count.to_string()));
+    }
+    let like_count: i64 = rows.iter().map(|row| row.count).sum();
 
-    // Get like count
+    // Get likes the author gave, shown only if they made them public
     let (sql, values) = sea_query::Query::select()
         .expr(Expr::col((Like::Table, Like::Uri)).count())
         .from(Like::Table)
-        .and_where(Expr::col(Like::To).eq(repo))
+        .and_where(Expr::col((Like::Table, Like::Repo)).eq(repo))
         .build_sqlx(PostgresQueryBuilder);
-    let like_count_row: (i64,) = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
-        .await
-        .unwrap_or((0,));
+    let (likes_given_count,): (i64,) = db::fetch_one(&state.db, &sql, values).await?;
+    let likes_public = PrivacyPref::likes_public(&state.db, repo).await?;
+
+    // Get tips received
+    let (sql, values) = Tip::build_received_total(repo).build_sqlx(PostgresQueryBuilder);
+    let (tips_received_total,): (i64,) = db::fetch_one(&state.db, &sql, values).await?;
 
-    // Get profile
-    let mut author = get_record(&state.pds, repo, NSID_PROFILE, "self")
+    Ok(json!({
+        "post_count": post_count.to_string(),
+        "comment_count": comment_count.to_string(),
+        "like_count": like_count.to_string(),
+        "likes_received": likes_received,
+        "likes_given_count": likes_given_count.to_string(),
+        "likes_public": likes_public,
+        "tips_received_total": tips_received_total.to_string(),
+    }))
+}
+
+/// Whether `viewer` may see what `repo` liked: their own likes always,
+/// anyone else's only if they made them public.
+pub(crate) async fn likes_visible_to(
+    state: &AppView,
+    repo: &str,
+    viewer: Option<&str>,
+) -> Result<bool, AppError> {
+    if viewer == Some(repo) {
+        return Ok(true);
+    }
+    Ok(PrivacyPref::likes_public(&state.db, repo).await?)
+}
+
+async fn fetch_author(state: &AppView, repo: &str) -> Value {
+    let stats = repo_stats(state, repo).await;
+
+    // Get profile, from the PDS when the relayer has not delivered it
+    let stored = Profile::select(&state.db, repo)
         .await
-        .and_then(|row| row.get("value").cloned().ok_or_eyre("NOT_FOUND"))
-        .unwrap_or(json!({
-            "did": repo
-        }));
-    if let Ok(ckb_addr) = get_ckb_addr_by_did(&state.ckb_client, &state.ckb_net, repo).await {
+        .map_err(|e| warn!("select profile of {repo} failed: {e}"))
+        .ok()
+        .flatten();
+    let mut author = match stored {
+        Some(profile) => profile,
+        None => get_record(&state.pds, repo, NSID_PROFILE, "self")
+            .await
+            .and_then(|row| row.get("value").cloned().ok_or_eyre("NOT_FOUND"))
+            .unwrap_or_else(|e| {
+                if let Some(e) = e.downcast_ref::<PdsError>()
+                    && !e.is_not_found()
+                {
+                    warn!("get profile of {repo} failed: {e}");
+                }
+                json!({
+                    "did": repo
+                })
+            }),
+    };
+    if state.ckb_addr_in_lists
+        && let Some(ckb_addr) = author_ckb_addr(state, repo).await
+    {
         author["ckb_addr"] = Value::String(ckb_addr);
     }
     author["did"] = Value::String(repo.to_owned());
-    author["post_count"] = Value::String(post_count_row.0.to_string());
-    author["comment_count"] = Value::String(comment_count_row.0.to_string());
-    author["like_count"] = Value::String(like_count_row.0.to_string());
+    for key in [
+        "post_count",
+        "comment_count",
+        "like_count",
+        "tips_received_total",
+    ] {
+        author[key] = stats.get(key).cloned().unwrap_or(json!("0"));
+    }
+    if stats["likes_public"] == true {
+        author["likes_given_count"] = stats["likes_given_count"].clone();
+    }
 
     let (sql, values) = Administrator::build_select()
         .and_where(Expr::col(Administrator::Did).eq(repo))
         .build_sqlx(PostgresQueryBuilder);
-    let row: Option<AdministratorRow> = sqlx::query_as_with(&sql, values)
-        .fetch_optional(&state.db)
+    let row: Option<AdministratorRow> = db::fetch_optional(&state.db, &sql, values)
         .await
         .unwrap_or(None);
     if let Some(admin) = row {
@@ -204,8 +715,7 @@ pub(crate) async fn build_author(state: &AppView, repo: &str) -> Value {
     let (sql, values) = Section::build_select()
         .and_where(Expr::col(Section::Owner).eq(repo))
         .build_sqlx(PostgresQueryBuilder);
-    let row: Option<SectionRowSample> = sqlx::query_as_with(&sql, values)
-        .fetch_optional(&state.db)
+    let row: Option<SectionRowSample> = db::fetch_optional(&state.db, &sql, values)
         .await
         .unwrap_or(None);
     if let Some(section) = row {
@@ -224,28 +734,48 @@ pub trait SignedParam: Default + ToSchema + Serialize + Validate {
     fn timestamp(&self) -> i64;
 }
 
-#[derive(Default, ToSchema, Serialize, Deserialize, Validate)]
+#[derive(Default, ToSchema, Serialize, Deserialize)]
 pub struct SignedBody<SignedParam> {
     pub params: SignedParam,
     pub did: String,
-    #[validate(length(equal = 57))]
     pub signing_key_did: String,
     pub signed_bytes: String,
 }
 
+/// Validates the `params` too, reporting their fields under `params.`.
+impl<T: Validate> Validate for SignedBody<T> {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if self.signing_key_did.chars().count() != 57 {
+            let mut error = ValidationError::new("length");
+            error.add_param("equal".into(), &57);
+            error.add_param("value".into(), &self.signing_key_did);
+            errors.add("signing_key_did", error);
+        }
+        errors.merge_self("params", self.params.validate());
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
 impl<T: SignedParam> SignedBody<T> {
-    pub async fn verify_signature(&self, indexer_did_url: &str) -> color_eyre::Result<()> {
+    pub async fn verify_signature(&self, state: &AppView) -> color_eyre::Result<()> {
         // verify timestamp
-        let timestamp =
-            chrono::DateTime::from_timestamp_secs(self.params.timestamp()).unwrap_or_default();
-        let now = chrono::Utc::now();
-        let delta = (now - timestamp).abs();
-        if delta > chrono::Duration::minutes(5) {
-            return Err(eyre!("timestamp is invalid"));
-        }
+        crate::limits::check_timestamp(
+            self.params.timestamp(),
+            chrono::Utc::now().timestamp(),
+            &state.clock_skew,
+        )?;
+
+        // oversized params are refused before any lookup
+        let unsigned_bytes = serde_ipld_dagcbor::to_vec(&self.params)?;
+        crate::limits::check_signed_params(&unsigned_bytes, &state.limits)?;
 
         // verify did
-        let did_doc = crate::indexer::did_document(indexer_did_url, &self.did)
+        let did_doc = crate::indexer::did_document(&state.indexer, &self.did)
             .await
             .map_err(|e| eyre!("get did doc failed: {e}"))?;
 
@@ -270,9 +800,267 @@ impl<T: SignedParam> SignedBody<T> {
         let signature = hex::decode(self.signed_bytes.clone())
             .map(|bytes| Signature::from_slice(&bytes).map_err(|e| eyre!(e)))??;
 
-        let unsigned_bytes = serde_ipld_dagcbor::to_vec(&self.params)?;
         verifying_key
             .verify(&unsigned_bytes, &signature)
-            .map_err(|e| eyre!("verify signature failed: {e}"))
+            .map_err(|e| eyre!("verify signature failed: {e}"))?;
+        crate::audit::verified_actor(&self.did);
+        Ok(())
+    }
+}
+
+/// A PDS whose sessions belong to the did their bearer token is.
+#[cfg(test)]
+pub(crate) async fn mock_pds() -> String {
+    use common_x::restful::axum::http::HeaderMap;
+
+    let router = Router::new().route(
+        "/xrpc/com.atproto.server.getSession",
+        get(|headers: HeaderMap| async move {
+            let did = headers
+                .get("authorization")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .unwrap_or_default()
+                .to_string();
+            Json(json!({ "did": did }))
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        common_x::restful::axum::serve(listener, router).await.ok();
+    });
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn only_a_session_verifies_the_viewer() {
+    let pds = mock_pds().await;
+    let bearer = |did: &str| Some(TypedHeader(Authorization::bearer(did).unwrap()));
+    let alice = Some("did:ckb:alice".to_string());
+
+    assert_eq!(verified_viewer(&pds, &alice, None).await.unwrap(), None);
+    assert_eq!(
+        verified_viewer(&pds, &alice, bearer("did:ckb:alice"))
+            .await
+            .unwrap(),
+        alice
+    );
+    assert!(matches!(
+        verified_viewer(&pds, &alice, bearer("did:ckb:mallory")).await,
+        Err(AppError::ValidateFailed(_))
+    ));
+    assert_eq!(
+        verified_viewer(&pds, &None, bearer("did:ckb:alice"))
+            .await
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn reasons_only_for_author_and_moderators() {
+    use crate::lexicon::{HIDDEN_BY_MODERATORS, reasons_for_viewer};
+
+    let now = chrono::Local::now();
+    let section = SectionRow {
+        id: 1,
+        name: "ckb".to_string(),
+        description: None,
+        image: None,
+        permission: 0,
+        comment_permission: 0,
+        owner: Some("did:ckb:owner".to_string()),
+        owner_set_time: None,
+        ckb_addr: None,
+        is_disabled: false,
+        is_archived: false,
+        reveal_moderator: None,
+        updated: now,
+        created: now,
+    };
+    let sections = HashMap::from([(1, section)]);
+    let admins = vec!["did:ckb:admin".to_string()];
+    let reasons = Some("pending police report".to_string());
+    let seen_by = |viewer: Option<&str>| {
+        let privileged = is_privileged(
+            &viewer.map(str::to_string),
+            "did:ckb:author",
+            1,
+            &sections,
+            &admins,
+        );
+        reasons_for_viewer(reasons.clone(), true, privileged)
+    };
+
+    // author
+    assert_eq!(seen_by(Some("did:ckb:author")), reasons);
+    // section moderators
+    assert_eq!(seen_by(Some("did:ckb:owner")), reasons);
+    assert_eq!(seen_by(Some("did:ckb:admin")), reasons);
+    // everyone else
+    let hidden = Some(HIDDEN_BY_MODERATORS.to_string());
+    assert_eq!(seen_by(Some("did:ckb:stranger")), hidden);
+    assert_eq!(seen_by(None), hidden);
+    assert_eq!(reasons_for_viewer(reasons.clone(), false, false), None);
+}
+
+#[test]
+fn visibility_is_filtered_with_bound_values() {
+    let filter = |viewer: Option<&str>| {
+        let (sql, values) = sea_query::Query::select()
+            .column(Comment::Uri)
+            .from(Comment::Table)
+            .and_where(visible_to(
+                &viewer.map(str::to_string),
+                Comment::Table,
+                Comment::IsDisabled,
+                Comment::Repo,
+                Comment::SectionId,
+            ))
+            .build_sqlx(PostgresQueryBuilder);
+        (sql, values.0.0.len())
+    };
+    let (sql, values) = filter(None);
+    assert!(sql.ends_with("WHERE \"comment\".\"is_disabled\" = $1"));
+    assert_eq!(values, 1);
+
+    let (sql, values) = filter(Some("did:ckb:o'neil"));
+    assert!(!sql.contains("o'neil"));
+    assert!(sql.contains("OR \"comment\".\"repo\" = $2"));
+    assert!(sql.contains(
+        "\"comment\".\"section_id\" IN (SELECT \"id\" FROM \"section\" WHERE \"owner\" = $3)"
+    ));
+    assert!(sql.contains("EXISTS"));
+    assert!(sql.contains("SELECT \"did\" FROM \"administrator\" WHERE \"did\" = $4"));
+    assert_eq!(values, 4);
+}
+
+#[test]
+fn public_spec_leaves_out_admin_endpoints() {
+    let public = openapi(true);
+    assert!(public.paths.paths.contains_key("/api/post/list"));
+    assert!(
+        public
+            .paths
+            .paths
+            .keys()
+            .all(|path| !path.starts_with("/api/admin"))
+    );
+    let schemas = public.components.unwrap().schemas;
+    assert!(schemas.contains_key("SignedBody_TipParams"));
+    assert!(!schemas.contains_key("SignedBody_UpdateTagParams"));
+
+    let full = openapi(false);
+    assert!(full.paths.paths.contains_key("/api/admin/update_tag"));
+    assert!(
+        full.paths
+            .paths
+            .keys()
+            .filter(|path| !path.starts_with("/api/admin"))
+            .eq(public.paths.paths.keys())
+    );
+}
+
+#[tokio::test]
+async fn apidoc_requires_the_token() {
+    let config = ApidocConfig {
+        mode: ApidocMode::Spec,
+        public_spec: true,
+        token: Some("secret".to_string()),
+    };
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "http://{}/apidoc/openapi.json",
+        listener.local_addr().unwrap()
+    );
+    tokio::spawn(common_x::restful::axum::serve(
+        listener,
+        apidoc_router::<()>(&config),
+    ));
+
+    let client = reqwest::Client::new();
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let response = client.get(&url).bearer_auth("wrong").send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let spec: Value = client
+        .get(&url)
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let paths = spec["paths"].as_object().unwrap();
+    assert!(paths.contains_key("/api/post/list"));
+    assert!(paths.keys().all(|path| !path.starts_with("/api/admin")));
+}
+
+#[test]
+fn openapi_fixture() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/openapi.json");
+    let spec = openapi(false)
+        .to_pretty_json()
+        .expect("serialize openapi failed");
+    if std::env::var("UPDATE_OPENAPI").is_ok() {
+        std::fs::write(path, format!("{spec}\n")).expect("write fixture failed");
+    }
+    let fixture = std::fs::read_to_string(path).unwrap_or_default();
+    assert_eq!(
+        fixture.trim_end(),
+        spec,
+        "openapi spec changed, rerun with UPDATE_OPENAPI=1 to refresh {path}"
+    );
+}
+
+#[tokio::test]
+async fn renamed_handles_show_after_the_refresh() {
+    use std::sync::Mutex;
+
+    use common_x::restful::axum::{extract::State, http::Uri};
+
+    // an indexer serving did documents; only alice has a handle
+    async fn document(State(handle): State<Arc<Mutex<String>>>, uri: Uri) -> Json<Value> {
+        if uri.path() == "/did:ckb:alice" {
+            let handle = handle.lock().unwrap().clone();
+            Json(json!({ "id": "did:ckb:alice", "alsoKnownAs": [format!("at://{handle}")] }))
+        } else {
+            Json(json!({ "error": "NotFound" }))
+        }
     }
+    let handle = Arc::new(Mutex::new("alice.bbs.dev".to_string()));
+    let router = Router::new().fallback(document).with_state(handle.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let indexer = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(common_x::restful::axum::serve(listener, router));
+
+    let state = AppView {
+        indexer,
+        ..AppView::for_tests(
+            sqlx::postgres::PgPoolOptions::new()
+                .acquire_timeout(std::time::Duration::from_millis(100))
+                .connect_lazy("postgres://127.0.0.1:9/bbs")
+                .unwrap(),
+        )
+    };
+
+    let author = build_author(&state, "did:ckb:alice").await;
+    assert_eq!(author["handle"], "alice.bbs.dev");
+    let author = build_author(&state, "did:ckb:bob").await;
+    assert_eq!(author.get("handle"), Some(&Value::Null));
+
+    *handle.lock().unwrap() = "alice.web5.dev".to_string();
+    assert_eq!(
+        build_author(&state, "did:ckb:alice").await["handle"],
+        "alice.bbs.dev",
+        "handles are cached"
+    );
+    refresh_handles(&state).await;
+    assert_eq!(
+        build_author(&state, "did:ckb:alice").await["handle"],
+        "alice.web5.dev"
+    );
 }
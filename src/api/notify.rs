@@ -1,49 +1,73 @@
-use chrono::{DateTime, Local};
+use std::collections::{BTreeMap, HashMap};
+
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
 use color_eyre::{Result, eyre::eyre};
 use common_x::restful::{
-    axum::{
-        Json,
-        extract::{Query, State},
-        response::IntoResponse,
-    },
+    axum::{Json, extract::State, response::IntoResponse},
     ok, ok_simple,
 };
-use sea_query::{BinOper, Expr, ExprTrait, Func, Order, PostgresQueryBuilder};
+use sea_query::{BinOper, Expr, ExprTrait, Func, IntoIden, Order, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
 use serde::Deserialize;
 use serde_json::{Value, json};
-use sqlx::{Executor, Pool, Postgres, query_as_with, query_with};
+use sqlx::{Pool, Postgres};
 use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
 use crate::{
     AppView,
-    api::{ToTimestamp, build_author},
-    atproto::{NSID_COMMENT, NSID_POST, NSID_REPLY},
+    api::{
+        ToTimestamp, build_author, build_moderator, is_privileged,
+        resolve::{comment_target, post_target, reply_target},
+        tip::get_source,
+        valid::{Valid, ValidQuery},
+        verified_viewer,
+    },
+    atproto::{Collection, NSID_COMMUNITY, NSID_SECTION},
+    db,
     error::AppError,
     lexicon::{
+        administrator::Administrator,
+        broadcast::Broadcast,
         comment::Comment,
-        notify::{Notify, NotifyRow, NotifyView},
+        notify::{Notify, NotifyRow, NotifyType, NotifyView},
+        operation::Operation,
         post::Post,
+        reasons_for_viewer,
         reply::Reply,
         resolve_uri,
+        section::{Section, SectionRow},
+        section_ban::SectionBan,
+        tip::shannons_to_ckb,
     },
 };
 
 #[derive(Debug, Default, Validate, Deserialize, ToSchema)]
 #[serde(default)]
 pub struct NotifyQuery {
+    /// The receiver. Hidden targets carry their moderator notes only with
+    /// a bearer token of the receiver's PDS session.
+    #[validate(length(min = 1))]
     pub repo: String,
     pub n_type: Vec<String>,
     pub cursor: Option<String>,
-    pub limit: u64,
+    /// Defaults to `pagination.notify_list` of the config, capped at its
+    /// `max`.
+    #[validate(range(min = 1))]
+    pub limit: Option<u64>,
 }
 
 #[utoipa::path(post, path = "/api/notify/list")]
 pub(crate) async fn list(
     State(state): State<AppView>,
-    Json(query): Json<NotifyQuery>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Valid(Json(query)): Valid<Json<NotifyQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
+    let viewer = verified_viewer(&state.pds, &Some(query.repo.clone()), auth).await?;
+    let limit = state.pagination.notify_list.resolve(query.limit);
     let (sql, values) = Notify::build_select()
         .and_where(Expr::col(Notify::Receiver).eq(query.repo))
         .and_where_option({
@@ -70,28 +94,68 @@ pub(crate) async fn list(
                 }),
         )
         .order_by(Notify::Created, Order::Desc)
-        .limit(query.limit)
+        .limit(limit)
         .build_sqlx(PostgresQueryBuilder);
-    let rows: Vec<NotifyRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    let rows: Vec<NotifyRow> = db::fetch_all(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
+    let admins = Administrator::all_did(&state.db).await;
     let mut views = vec![];
     for row in rows {
-        let target = get_target(&state.db, &row.target_uri)
-            .await
-            .unwrap_or_default();
+        let mut target = if row.n_type == NotifyType::BeBanned as i32 {
+            ban_target(&state.db, &row, &sections)
+                .await
+                .unwrap_or_default()
+        } else if row.target_uri.starts_with("at://") {
+            get_target(&state.db, &row.target_uri, &viewer, &sections, &admins)
+                .await
+                .unwrap_or_default()
+        } else {
+            get_source(&state, &payment_info(&row.target_uri, &state.bbs_ckb_addr))
+                .await
+                .unwrap_or_default()
+        };
+        if is_payment(row.n_type)
+            && let Some(target) = target.as_object_mut()
+        {
+            target.insert("amount".to_string(), json!(row.amount));
+            target.insert("amount_ckb".to_string(), json!(shannons_to_ckb(row.amount)));
+        }
+        if row.n_type == NotifyType::ModerationNeeded as i32
+            && let Some(target) = target.as_object_mut()
+        {
+            let snippet = target
+                .get("title")
+                .or_else(|| target.get("text"))
+                .and_then(Value::as_str)
+                .map(snippet);
+            target.insert("snippet".to_string(), json!(snippet));
+            let flags = open_flags(&state.db, &row.target_uri)
+                .await
+                .unwrap_or_default();
+            target.insert("flags".to_string(), flags);
+        }
+        if row.n_type == NotifyType::Announcement as i32
+            && let Some(target) = target.as_object_mut()
+        {
+            let body = Broadcast::body_of(&state.db, &row.target_uri)
+                .await
+                .unwrap_or_default();
+            target.insert("body".to_string(), json!(body));
+        }
 
         views.push(NotifyView {
             id: row.id.to_string(),
             title: row.title,
-            sender: build_author(&state, &row.sender).await,
+            sender: build_sender(&state, &row, &sections).await,
             receiver: build_author(&state, &row.receiver).await,
             n_type: row.n_type.to_string(),
             target_uri: row.target_uri,
             target,
             amount: row.amount,
+            amount_ckb: shannons_to_ckb(row.amount),
             readed: row.readed,
             created: row.created,
         });
@@ -101,152 +165,202 @@ pub(crate) async fn list(
     let result = if let Some(cursor) = cursor {
         json!({
             "cursor": cursor.to_string(),
-            "notifies": views
+            "notifies": views,
+            "limit": limit
         })
     } else {
         json!({
-            "notifies": views
+            "notifies": views,
+            "limit": limit
         })
     };
 
     Ok(ok(result))
 }
 
-async fn get_target(db: &Pool<Postgres>, uri: &str) -> Result<Value> {
+/// Moderators are shown as `build_moderator` decides for the section of
+/// the content they hid or displayed.
+async fn build_sender(
+    state: &AppView,
+    row: &NotifyRow,
+    sections: &HashMap<i32, SectionRow>,
+) -> Value {
+    if !is_moderation(row.n_type) {
+        return build_author(state, &row.sender).await;
+    }
+    // content deleted since follows the default policy
+    let section_id = section_of(&state.db, &row.target_uri).await.unwrap_or(0);
+    build_moderator(state, sections, section_id, &row.sender).await
+}
+
+/// The section of a ban notification with the ban: its reason and when it
+/// ends, or ended when it was lifted.
+async fn ban_target(
+    db: &Pool<Postgres>,
+    row: &NotifyRow,
+    sections: &HashMap<i32, SectionRow>,
+) -> Result<Value> {
+    let section_id = section_target(&row.target_uri)
+        .ok_or_else(|| eyre!("not a section: {}", row.target_uri))?;
+    let ban = SectionBan::select(db, section_id, &row.receiver).await?;
+    Ok(json!({
+        "nsid": NSID_SECTION,
+        "id": section_id.to_string(),
+        "name": sections.get(&section_id).map(|s| s.name.as_str()),
+        "until": ban.as_ref().map(|b| b.until),
+        "reason": ban.map(|b| b.reason),
+    }))
+}
+
+const fn is_moderation(n_type: i32) -> bool {
+    n_type == NotifyType::BeHidden as i32
+        || n_type == NotifyType::BeDisplayed as i32
+        || n_type == NotifyType::BeBanned as i32
+}
+
+/// The id of a `app.bbs.section/<id>` target.
+fn section_target(target: &str) -> Option<i32> {
+    target
+        .strip_prefix(&format!("{NSID_SECTION}/"))
+        .and_then(|id| id.parse().ok())
+}
+
+/// The section of the content at `uri`, or of a section target.
+async fn section_of(db: &Pool<Postgres>, uri: &str) -> Result<i32> {
+    if let Some(section_id) = section_target(uri) {
+        return Ok(section_id);
+    }
     let (_did, nsid, _rkey) = resolve_uri(uri)?;
+    let (table, column) = match nsid.parse() {
+        Ok(Collection::Post) => (Post::Table.into_iden(), Post::SectionId.into_iden()),
+        Ok(Collection::Comment) => (Comment::Table.into_iden(), Comment::SectionId.into_iden()),
+        Ok(Collection::Reply) => (Reply::Table.into_iden(), Reply::SectionId.into_iden()),
+        _ => return Err(eyre!("no section for {uri}")),
+    };
+    let (sql, values) = sea_query::Query::select()
+        .column(column)
+        .from(table)
+        .and_where(Expr::col("uri").eq(uri))
+        .build_sqlx(PostgresQueryBuilder);
+    let (section_id,): (i32,) = db::fetch_one(db, &sql, values).await?;
+    Ok(section_id)
+}
+
+/// The first characters of flagged content, for the moderation notice.
+pub(crate) fn snippet(text: &str) -> String {
+    const MAX_CHARS: usize = 80;
+    if text.chars().count() > MAX_CHARS {
+        format!("{}…", text.chars().take(MAX_CHARS).collect::<String>())
+    } else {
+        text.to_string()
+    }
+}
+
+/// The flags on `uri` no moderator acted on yet: how many, and how many of
+/// each category of the content rules that raised them.
+async fn open_flags(db: &Pool<Postgres>, uri: &str) -> Result<Value> {
+    let (sql, values) = Operation::build_open_flags(uri).build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<(String, i64)> = db::fetch_all(db, &sql, values).await?;
+    let mut reasons = BTreeMap::new();
+    for (message, count) in rows {
+        // "<category> (rule <id>)"
+        let reason = message
+            .rsplit_once(" (rule ")
+            .map_or(message.as_str(), |(category, _)| category);
+        *reasons.entry(reason.to_string()).or_insert(0) += count;
+    }
+    Ok(json!({
+        "count": reasons.values().sum::<i64>(),
+        "reasons": reasons,
+    }))
+}
+
+const fn is_payment(n_type: i32) -> bool {
+    n_type == NotifyType::NewTip as i32
+        || n_type == NotifyType::NewDonate as i32
+        || n_type == NotifyType::TipExpired as i32
+}
+
+/// The `nsid/uri` info of a tip or donation target that is not an at-uri:
+/// either already such an info string, or the ckb address that received it.
+fn payment_info(target: &str, bbs_ckb_addr: &str) -> String {
+    if target.contains('/') {
+        target.to_string()
+    } else if target == bbs_ckb_addr {
+        format!("{NSID_COMMUNITY}/{target}")
+    } else {
+        format!("{NSID_SECTION}/{target}")
+    }
+}
+
+async fn get_target(
+    db: &Pool<Postgres>,
+    uri: &str,
+    viewer: &Option<String>,
+    sections: &HashMap<i32, SectionRow>,
+    admins: &[String],
+) -> Result<Value> {
+    let (did, nsid, _rkey) = resolve_uri(uri)?;
+    let found = |uri: &str| eyre!("{uri} not found");
 
-    let value = match nsid {
-        NSID_POST => {
-            let (sql, values) = sea_query::Query::select()
-                .columns([
-                    (Post::Table, Post::Title),
-                    (Post::Table, Post::ReasonsForDisabled),
-                ])
-                .from(Post::Table)
-                .and_where(Expr::col(Post::Uri).eq(uri))
-                .build_sqlx(PostgresQueryBuilder);
-            let row: (String, Option<String>) =
-                query_as_with(&sql, values.clone()).fetch_one(db).await?;
+    let value = match nsid.parse() {
+        Ok(Collection::Post) => {
+            let post = post_target(db, uri).await?.ok_or_else(|| found(uri))?;
+            let privileged = is_privileged(viewer, did, post.section_id, sections, admins);
             json!({
                 "nsid": nsid,
-                "title": row.0,
-                "reasons_for_disabled": row.1,
+                "title": post.title,
+                "reasons_for_disabled": reasons_for_viewer(post.reasons_for_disabled, post.is_disabled, privileged),
             })
         }
-        NSID_COMMENT => {
-            let (sql, values) = sea_query::Query::select()
-                .columns([
-                    (Comment::Table, Comment::Text),
-                    (Comment::Table, Comment::Post),
-                    (Comment::Table, Comment::Created),
-                    (Comment::Table, Comment::ReasonsForDisabled),
-                ])
-                .from(Comment::Table)
-                .and_where(Expr::col(Comment::Uri).eq(uri))
-                .build_sqlx(PostgresQueryBuilder);
-            let row: (String, String, DateTime<Local>, Option<String>) =
-                query_as_with(&sql, values.clone()).fetch_one(db).await?;
-
-            let (sql, values) = sea_query::Query::select()
-                .expr(Expr::col((Comment::Table, Comment::Uri)).count_distinct())
-                .from(Comment::Table)
-                .and_where(Expr::col((Comment::Table, Comment::Post)).eq(&row.1))
-                .and_where(
-                    Expr::col((Comment::Table, Comment::Created))
-                        .binary(BinOper::SmallerThan, row.2),
-                )
-                .build_sqlx(PostgresQueryBuilder);
-            let count: (i64,) = query_as_with(&sql, values.clone()).fetch_one(db).await?;
-
-            let (sql, values) = sea_query::Query::select()
-                .columns([(Post::Table, Post::Title)])
-                .from(Post::Table)
-                .and_where(Expr::col(Post::Uri).eq(&row.1))
-                .build_sqlx(PostgresQueryBuilder);
-            let post: (String,) = query_as_with(&sql, values.clone()).fetch_one(db).await?;
+        Ok(Collection::Comment) => {
+            let comment = comment_target(db, uri).await?.ok_or_else(|| found(uri))?;
+            let privileged = is_privileged(viewer, did, comment.section_id, sections, admins);
+            let post = post_target(db, &comment.post)
+                .await?
+                .ok_or_else(|| found(&comment.post))?;
 
             json!({
                 "nsid": nsid,
-                "text": row.0,
-                "index": count.0 + 1,
-                "reasons_for_disabled": row.3,
+                "text": comment.text,
+                "index": comment.index,
+                "reasons_for_disabled": reasons_for_viewer(comment.reasons_for_disabled, comment.is_disabled, privileged),
                 "post": {
-                    "title": post.0,
-                    "uri": row.1
+                    "title": post.title,
+                    "uri": comment.post
                 },
             })
         }
-        NSID_REPLY => {
-            let (sql, values) = sea_query::Query::select()
-                .columns([
-                    (Reply::Table, Reply::Text),
-                    (Reply::Table, Reply::Comment),
-                    (Reply::Table, Reply::Created),
-                    (Reply::Table, Reply::ReasonsForDisabled),
-                ])
-                .from(Reply::Table)
-                .and_where(Expr::col(Reply::Uri).eq(uri))
-                .build_sqlx(PostgresQueryBuilder);
-            let reply: (String, String, DateTime<Local>, Option<String>) =
-                query_as_with(&sql, values.clone()).fetch_one(db).await?;
-
-            let (sql, values) = sea_query::Query::select()
-                .expr(Expr::col((Reply::Table, Reply::Uri)).count_distinct())
-                .from(Reply::Table)
-                .and_where(Expr::col((Reply::Table, Reply::Comment)).eq(&reply.1))
-                .and_where(
-                    Expr::col((Reply::Table, Reply::Created)).binary(BinOper::SmallerThan, reply.2),
-                )
-                .build_sqlx(PostgresQueryBuilder);
-            let reply_count: (i64,) = query_as_with(&sql, values.clone()).fetch_one(db).await?;
-
-            let (sql, values) = sea_query::Query::select()
-                .columns([
-                    (Comment::Table, Comment::Text),
-                    (Comment::Table, Comment::Post),
-                    (Comment::Table, Comment::Created),
-                ])
-                .from(Comment::Table)
-                .and_where(Expr::col(Comment::Uri).eq(&reply.1))
-                .build_sqlx(PostgresQueryBuilder);
-            let comment: (String, String, DateTime<Local>) =
-                query_as_with(&sql, values.clone()).fetch_one(db).await?;
-
-            let (sql, values) = sea_query::Query::select()
-                .expr(Expr::col((Comment::Table, Comment::Uri)).count_distinct())
-                .from(Comment::Table)
-                .and_where(Expr::col((Comment::Table, Comment::Post)).eq(&comment.1))
-                .and_where(
-                    Expr::col((Comment::Table, Comment::Created))
-                        .binary(BinOper::SmallerThan, comment.2),
-                )
-                .build_sqlx(PostgresQueryBuilder);
-            let comment_count: (i64,) = query_as_with(&sql, values.clone()).fetch_one(db).await?;
-
-            let (sql, values) = sea_query::Query::select()
-                .columns([(Post::Table, Post::Title)])
-                .from(Post::Table)
-                .and_where(Expr::col(Post::Uri).eq(&comment.1))
-                .build_sqlx(PostgresQueryBuilder);
-            let post: (String,) = query_as_with(&sql, values.clone()).fetch_one(db).await?;
+        Ok(Collection::Reply) => {
+            let reply = reply_target(db, uri).await?.ok_or_else(|| found(uri))?;
+            let privileged = is_privileged(viewer, did, reply.section_id, sections, admins);
+            let comment = comment_target(db, &reply.comment)
+                .await?
+                .ok_or_else(|| found(&reply.comment))?;
+            let post = post_target(db, &comment.post)
+                .await?
+                .ok_or_else(|| found(&comment.post))?;
 
             json!({
                 "nsid": nsid,
-                "text": reply.0,
-                "index": reply_count.0 + 1,
-                "reasons_for_disabled": reply.3,
+                "text": reply.text,
+                "index": reply.index,
+                "reasons_for_disabled": reasons_for_viewer(reply.reasons_for_disabled, reply.is_disabled, privileged),
                 "comment": {
-                    "uri": reply.1,
-                    "text": comment.0,
-                    "index": comment_count.0 + 1,
+                    "uri": reply.comment,
+                    "text": comment.text,
+                    "index": comment.index,
                 },
                 "post": {
-                    "title": post.0,
-                    "uri": comment.1
+                    "title": post.title,
+                    "uri": comment.post
                 },
             })
         }
-        _ => return Err(eyre!("nsid not supported: {nsid}")),
+        _ => json!({
+            "nsid": nsid,
+            "uri": uri,
+        }),
     };
 
     Ok(value)
@@ -255,6 +369,7 @@ async fn get_target(db: &Pool<Postgres>, uri: &str) -> Result<Value> {
 #[derive(Debug, Default, Validate, Deserialize, ToSchema)]
 #[serde(default)]
 pub struct NotifyReadQuery {
+    #[validate(length(min = 1))]
     pub repo: String,
     pub target: Option<i32>,
 }
@@ -262,7 +377,7 @@ pub struct NotifyReadQuery {
 #[utoipa::path(post, path = "/api/notify/read")]
 pub(crate) async fn read(
     State(state): State<AppView>,
-    Json(query): Json<NotifyReadQuery>,
+    Valid(Json(query)): Valid<Json<NotifyReadQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
     let (sql, values) = sea_query::Query::update()
         .table(Notify::Table)
@@ -271,20 +386,21 @@ pub(crate) async fn read(
         .and_where_option(query.target.map(|target| Expr::col(Notify::Id).eq(target)))
         .build_sqlx(PostgresQueryBuilder);
 
-    state.db.execute(query_with(&sql, values)).await?;
+    db::execute(&state.db, &sql, values).await?;
     Ok(ok_simple())
 }
 
 #[derive(Debug, Default, Validate, Deserialize, IntoParams)]
 #[serde(default)]
 pub struct NotifyUnreadQuery {
+    #[validate(length(min = 1))]
     pub repo: String,
 }
 
 #[utoipa::path(get, path = "/api/notify/unread_num", params(NotifyUnreadQuery))]
 pub(crate) async fn unread_num(
     State(state): State<AppView>,
-    Query(query): Query<NotifyUnreadQuery>,
+    ValidQuery(query): ValidQuery<NotifyUnreadQuery>,
 ) -> Result<impl IntoResponse, AppError> {
     let (sql, values) = sea_query::Query::select()
         .expr(Expr::col((Notify::Table, Notify::Id)).count_distinct())
@@ -292,10 +408,171 @@ pub(crate) async fn unread_num(
         .and_where(Expr::col(Notify::Receiver).eq(query.repo))
         .and_where(Expr::col(Notify::Readed).is_null())
         .build_sqlx(PostgresQueryBuilder);
-    let rows: (i64,) = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
+    let rows: (i64,) = db::fetch_one(&state.db, &sql, values.clone())
         .await
         .unwrap_or_default();
 
     Ok(ok(rows.0))
 }
+
+#[test]
+fn payment_targets() {
+    let bbs = "ckt1bbs";
+    assert_eq!(
+        payment_info("app.bbs.post/at://did:ckb:alice/app.bbs.post/3kabc", bbs),
+        "app.bbs.post/at://did:ckb:alice/app.bbs.post/3kabc"
+    );
+    assert_eq!(payment_info(bbs, bbs), "app.bbs.community/ckt1bbs");
+    assert_eq!(
+        payment_info("ckt1section", bbs),
+        "app.bbs.section/ckt1section"
+    );
+
+    assert!(is_payment(NotifyType::NewTip as i32));
+    assert!(is_payment(NotifyType::NewDonate as i32));
+    assert!(is_payment(NotifyType::TipExpired as i32));
+    assert!(!is_payment(NotifyType::NewLike as i32));
+
+    assert_eq!(section_target("app.bbs.section/3"), Some(3));
+    assert_eq!(section_target("app.bbs.section/ckt1section"), None);
+    assert_eq!(
+        section_target("at://did:ckb:alice/app.bbs.post/3kabc"),
+        None
+    );
+}
+
+#[tokio::test]
+async fn unknown_nsid_degrades() {
+    // never connects: unknown nsids are answered without a query
+    let db = sqlx::PgPool::connect_lazy("postgres://localhost/bbs").unwrap();
+    let uri = "at://did:ckb:alice/app.bbs.unknown/3kabc";
+    let target = get_target(
+        &db,
+        uri,
+        &Some("did:ckb:bob".to_string()),
+        &HashMap::new(),
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(target, json!({ "nsid": "app.bbs.unknown", "uri": uri }));
+}
+
+#[test]
+fn notify_list_has_a_page_by_default() {
+    let pagination = crate::config::PaginationConfig::default();
+    assert_eq!(
+        pagination.notify_list.resolve(NotifyQuery::default().limit),
+        20
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn moderators_are_shown_by_policy() {
+    use chrono::Local;
+    use sqlx::{Executor, query};
+
+    use crate::api::admin::{OperationQuery, operations};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO section (id, name, reveal_moderator) VALUES (1, 'General', NULL), (2, 'Market', false)",
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft) VALUES
+            ('at://did:ckb:alice/app.bbs.post/3kabc', 'bafy', 'did:ckb:alice', 1, 't', 't', false),
+            ('at://did:ckb:bob/app.bbs.post/3kdef', 'bafy', 'did:ckb:bob', 2, 't', 't', false)",
+        "INSERT INTO operation (section_id, operator, action_type, action, message, target) VALUES
+            (1, 'did:ckb:moderator', 1, 'disable', '', 'p1'),
+            (2, 'did:ckb:moderator', 1, 'disable', '', 'p2')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    let notify = |target_uri: &str| NotifyRow {
+        id: 1,
+        title: "Be Hidden".to_string(),
+        sender: "did:ckb:moderator".to_string(),
+        receiver: "did:ckb:alice".to_string(),
+        n_type: NotifyType::BeHidden as i32,
+        target_uri: target_uri.to_string(),
+        amount: 0,
+        readed: None,
+        created: Local::now(),
+    };
+    let operator = async |state: &AppView, section: &str| {
+        let response = operations(
+            State(state.clone()),
+            ValidQuery(OperationQuery {
+                section: section.to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        let bytes = common_x::restful::axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut body: Value = serde_json::from_slice(&bytes).unwrap();
+        body["data"]["comments"][0]["operator"].take()
+    };
+    let general = json!({ "section_id": "1", "displayName": "General" });
+    let market = json!({ "section_id": "2", "displayName": "Market" });
+
+    for reveal_moderator in [true, false] {
+        let state = AppView {
+            reveal_moderator,
+            ..AppView::for_tests(db.clone())
+        };
+        let sections = state
+            .caches
+            .sections(Section::all(&state.db))
+            .await
+            .unwrap();
+        let senders = [
+            build_sender(
+                &state,
+                &notify("at://did:ckb:alice/app.bbs.post/3kabc"),
+                &sections,
+            )
+            .await,
+            build_sender(
+                &state,
+                &notify("at://did:ckb:bob/app.bbs.post/3kdef"),
+                &sections,
+            )
+            .await,
+            // the content is gone
+            build_sender(
+                &state,
+                &notify("at://did:ckb:carol/app.bbs.post/3kxyz"),
+                &sections,
+            )
+            .await,
+        ];
+        let operators = [operator(&state, "1").await, operator(&state, "2").await];
+        if reveal_moderator {
+            assert_eq!(senders[0]["did"], "did:ckb:moderator");
+            assert_eq!(senders[1], market);
+            assert_eq!(senders[2]["did"], "did:ckb:moderator");
+            assert_eq!(operators[0]["did"], "did:ckb:moderator");
+            assert_eq!(operators[1], market);
+        } else {
+            assert_eq!(
+                senders,
+                [
+                    general.clone(),
+                    market.clone(),
+                    json!({ "displayName": "community" })
+                ]
+            );
+            assert_eq!(operators, [general.clone(), market.clone()]);
+        }
+    }
+
+    // the audit log keeps who acted
+    let operators: Vec<(String,)> = sqlx::query_as("SELECT operator FROM operation")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    assert!(operators.iter().all(|(o,)| o == "did:ckb:moderator"));
+}
@@ -1,33 +1,54 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
 use color_eyre::eyre::eyre;
 use common_x::restful::{
-    axum::{
-        Json,
-        extract::{Query, State},
-        response::IntoResponse,
-    },
-    ok,
+    axum::{Json, extract::State, response::IntoResponse},
+    ok, ok_simple,
+};
+use futures::StreamExt;
+use sea_query::{
+    Asterisk, BinOper, Expr, ExprTrait, Func, IntoColumnRef, Order, PostgresQueryBuilder, UnionType,
 };
-use sea_query::{BinOper, Expr, ExprTrait, Func, IntoColumnRef, Order, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
-use serde::Deserialize;
-use serde_json::json;
-use sqlx::{Executor, query_as_with, query_with};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
 use tokio::sync::RwLock;
 use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
 use crate::{
     AppView,
-    api::{ToTimestamp, build_author},
-    atproto::NSID_POST,
+    api::{
+        SignedBody, SignedParam, ToTimestamp, build_author, build_author_with_ckb_addr,
+        check_session, is_moderator, is_privileged,
+        record::{self, NewRecord},
+        search::{self, OWN_SEARCH_MAX, OWN_SEARCHES_PER_MINUTE, OwnHitRow},
+        valid::{Valid, ValidQuery},
+        verified_viewer, visible_to,
+    },
+    atproto::{NSID_COMMENT, NSID_POST, NSID_REPLY, resolve_handle},
+    db,
     error::AppError,
     lexicon::{
+        HIDDEN_BY_MODERATORS,
         administrator::Administrator,
-        comment::{Comment, CommentRow},
+        comment::{Comment, CommentRow, CommentView},
+        draft::{Draft, LOCAL_DRAFT_SCHEME},
+        like::Like,
         post::{Post, PostDraftRow, PostDraftView, PostRepliedView, PostRow, PostView},
+        reply::{Reply, ReplyRow, ReplyView},
+        resolve_uri,
         section::Section,
+        thread_mute::ThreadMute,
+        tip::{Tip, TipRow, shannons_to_ckb},
+        visit_source::{self, PostVisitSource, VisitSource, VisitSourceRow},
     },
     micro_pay,
 };
@@ -38,10 +59,18 @@ pub(crate) struct PostQuery {
     pub section_id: Option<String>,
     pub is_announcement: bool,
     pub cursor: Option<String>,
-    pub limit: u64,
+    /// Defaults to `pagination.post_list` of the config, capped at its `max`.
+    #[validate(range(min = 1))]
+    pub limit: Option<u64>,
     pub q: Option<String>,
     pub repo: Option<String>,
+    /// Only counts with a bearer token of the viewer's PDS session.
     pub viewer: Option<String>,
+    /// Also list the drafts of `repo`; only allowed when `viewer` is `repo`
+    /// and the request carries a bearer token of their PDS session.
+    pub include_drafts: bool,
+    /// `commented` only: also list the posts `repo` replied under.
+    pub include_replies: bool,
 }
 
 impl Default for PostQuery {
@@ -50,20 +79,56 @@ impl Default for PostQuery {
             section_id: Default::default(),
             is_announcement: false,
             cursor: Default::default(),
-            limit: 20,
+            limit: None,
             q: Default::default(),
             repo: Default::default(),
             viewer: Default::default(),
+            include_drafts: false,
+            include_replies: false,
         }
     }
 }
 
+impl PostQuery {
+    /// `verified` is the viewer `verified_viewer` vouched for.
+    fn draft_filter(&self, verified: &Option<String>) -> Result<Option<Expr>, AppError> {
+        if !self.include_drafts {
+            return Ok(Some(Expr::col((Post::Table, Post::IsDraft)).eq(false)));
+        }
+        if self.repo.is_none() || *verified != self.repo {
+            return Err(AppError::ValidateFailed(
+                "include_drafts requires a session of repo".to_string(),
+            ));
+        }
+        Ok(None)
+    }
+}
+
 #[utoipa::path(post, path = "/api/post/list")]
 pub(crate) async fn list(
     State(state): State<AppView>,
-    Json(query): Json<PostQuery>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Valid(Json(query)): Valid<Json<PostQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
-    let (sql, values) = Post::build_select(query.viewer.clone())
+    let verified = verified_viewer(&state.pds, &query.viewer, auth).await?;
+    let draft_filter = query.draft_filter(&verified)?;
+    let query = PostQuery {
+        viewer: verified,
+        ..query
+    };
+    let limit = state.pagination.post_list.resolve(query.limit);
+    // an author's own pin only applies to their post list
+    let by_author = query.repo.is_some();
+    let mut order = vec![];
+    if by_author {
+        order.push(((Post::Table, Post::IsUserPinned), Order::Desc));
+    }
+    order.extend([
+        ((Post::Table, Post::IsTop), Order::Desc),
+        ((Post::Table, Post::Updated), Order::Desc),
+    ]);
+    let (sql, values) = Post::build_select_with_drafts(query.viewer.clone())
+        .and_where_option(draft_filter)
         .and_where(Expr::col((Post::Table, Post::IsAnnouncement)).eq(query.is_announcement))
         .and_where_option(
             query
@@ -77,6 +142,10 @@ pub(crate) async fn list(
                 Expr::col((Post::Table, Post::SectionId)).binary(BinOper::NotEqual, 0)
             },
         )
+        .and_where_option(
+            (by_author && query.cursor.is_some())
+                .then(|| Expr::col((Post::Table, Post::IsUserPinned)).eq(false)),
+        )
         .and_where_option(
             query
                 .cursor
@@ -95,32 +164,28 @@ pub(crate) async fn list(
                 .into_column_ref()
                 .like(format!("%{q}%"))
         }))
-        .and_where(if let Some(viewer) = &query.viewer {
-            Expr::col((Post::Table, Post::IsDisabled))
-                .eq(false)
-                .or(Expr::col((Post::Table, Post::Repo)).eq(viewer))
-                .or(Expr::col((Section::Table, Section::Owner)).eq(viewer))
-                .or(Expr::cust(format!(
-                    "(select count(did) from administrator where did = '{viewer}') > 0"
-                )))
-        } else {
-            Expr::col((Post::Table, Post::IsDisabled)).eq(false)
-        })
-        .order_by_columns([
-            ((Post::Table, Post::IsTop), Order::Desc),
-            ((Post::Table, Post::Updated), Order::Desc),
-        ])
-        .limit(query.limit)
+        .and_where(visible_to(
+            &query.viewer,
+            Post::Table,
+            Post::IsDisabled,
+            Post::Repo,
+            Post::SectionId,
+        ))
+        .order_by_columns(order)
+        .limit(limit)
         .build_sqlx(PostgresQueryBuilder);
 
-    let rows: Vec<PostRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    let rows: Vec<PostRow> = db::fetch_all(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
+    let admins = Administrator::all_did(&state.db).await;
     let views = Arc::new(RwLock::new(vec![]));
     let mut handles = vec![];
     for row in rows {
+        let privileged =
+            is_privileged(&query.viewer, &row.repo, row.section_id, &sections, &admins);
         let state = state.clone();
         let views = views.clone();
         handles.push(tokio::spawn(async move {
@@ -132,10 +197,9 @@ pub(crate) async fn list(
             .await
             .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
             .unwrap_or(0);
-            views
-                .write()
-                .await
-                .push(PostView::build(row.clone(), author, tip_count.to_string()));
+            views.write().await.push(
+                PostView::build(row.clone(), author, tip_count.to_string()).for_viewer(privileged),
+            );
         }));
     }
     for handle in handles {
@@ -146,16 +210,21 @@ pub(crate) async fn list(
 
     views.sort_by(|a, b| b.updated.cmp(&a.updated));
     views.sort_by(|a, b| b.is_top.cmp(&a.is_top));
+    if by_author {
+        views.sort_by(|a, b| b.is_user_pinned.cmp(&a.is_user_pinned));
+    }
 
     let cursor = views.last().map(|r| r.updated.timestamp());
     let result = if let Some(cursor) = cursor {
         json!({
             "cursor": cursor.to_string(),
-            "posts": views
+            "posts": views,
+            "limit": limit
         })
     } else {
         json!({
-            "posts": views
+            "posts": views,
+            "limit": limit
         })
     };
     Ok(ok(result))
@@ -173,6 +242,7 @@ pub(crate) struct PostPageQuery {
     pub per_page: u64,
     pub q: Option<String>,
     pub repo: Option<String>,
+    /// Only counts with a bearer token of the viewer's PDS session.
     pub viewer: Option<String>,
 }
 
@@ -194,11 +264,13 @@ impl Default for PostPageQuery {
 #[utoipa::path(post, path = "/api/post/page")]
 pub(crate) async fn page(
     State(state): State<AppView>,
-    Json(query): Json<PostPageQuery>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Valid(Json(query)): Valid<Json<PostPageQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
-    query
-        .validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+    let query = PostPageQuery {
+        viewer: verified_viewer(&state.pds, &query.viewer, auth).await?,
+        ..query
+    };
     let offset = query.per_page * (query.page - 1);
     let (sql, values) = Post::build_select(query.viewer.clone())
         .and_where(Expr::col((Post::Table, Post::IsAnnouncement)).eq(query.is_announcement))
@@ -226,14 +298,17 @@ pub(crate) async fn page(
         .limit(query.per_page)
         .build_sqlx(PostgresQueryBuilder);
 
-    let rows: Vec<PostRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    let rows: Vec<PostRow> = db::fetch_all(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
+    let admins = Administrator::all_did(&state.db).await;
     let views = Arc::new(RwLock::new(vec![]));
     let mut handles = vec![];
     for row in rows {
+        let privileged =
+            is_privileged(&query.viewer, &row.repo, row.section_id, &sections, &admins);
         let state = state.clone();
         let views = views.clone();
         handles.push(tokio::spawn(async move {
@@ -245,10 +320,9 @@ pub(crate) async fn page(
             .await
             .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
             .unwrap_or(0);
-            views
-                .write()
-                .await
-                .push(PostView::build(row.clone(), author, tip_count.to_string()));
+            views.write().await.push(
+                PostView::build(row.clone(), author, tip_count.to_string()).for_viewer(privileged),
+            );
         }));
     }
     for handle in handles {
@@ -279,8 +353,7 @@ pub(crate) async fn page(
                 .like(format!("%{q}%"))
         }))
         .build_sqlx(PostgresQueryBuilder);
-    let total: (i64,) = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
+    let total: (i64,) = db::fetch_one(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
@@ -295,87 +368,208 @@ pub(crate) async fn page(
     })))
 }
 
-#[derive(Debug, Default, Validate, Deserialize, ToSchema)]
+#[derive(Debug, Validate, Deserialize, ToSchema)]
 #[serde(default)]
 pub(crate) struct TopQuery {
     pub section_id: String,
+    /// Only counts with a bearer token of the viewer's PDS session.
     pub viewer: Option<String>,
+    /// Uri of the last post of the previous page.
+    pub cursor: Option<String>,
+    #[validate(range(min = 1, max = 50))]
+    pub limit: u64,
+    /// List the section's announcements instead of its pinned posts, as this
+    /// endpoint used to.
+    pub authored_by_staff: bool,
+}
+
+impl Default for TopQuery {
+    fn default() -> Self {
+        Self {
+            section_id: Default::default(),
+            viewer: None,
+            cursor: None,
+            limit: 10,
+            authored_by_staff: false,
+        }
+    }
+}
+
+fn build_top(query: &TopQuery, section_id: i32) -> sea_query::SelectStatement {
+    Post::build_select(query.viewer.clone())
+        .and_where(Expr::col((Post::Table, Post::SectionId)).eq(section_id))
+        .and_where(if query.authored_by_staff {
+            Expr::col((Post::Table, Post::IsAnnouncement)).eq(true)
+        } else {
+            Expr::col((Post::Table, Post::IsTop)).eq(true)
+        })
+        .and_where(visible_to(
+            &query.viewer,
+            Post::Table,
+            Post::IsDisabled,
+            Post::Repo,
+            Post::SectionId,
+        ))
+        .and_where_option(query.cursor.as_ref().map(|cursor| {
+            Expr::cust_with_values(
+                "(\"post\".\"created\", \"post\".\"uri\") < (select \"created\", \"uri\" from \"post\" where \"uri\" = $1)",
+                [cursor.clone()],
+            )
+        }))
+        .order_by_columns([
+            ((Post::Table, Post::Created), Order::Desc),
+            ((Post::Table, Post::Uri), Order::Desc),
+        ])
+        .limit(query.limit)
+        .take()
 }
 
 #[utoipa::path(post, path = "/api/post/top")]
 pub(crate) async fn top(
     State(state): State<AppView>,
-    Json(query): Json<TopQuery>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Valid(Json(query)): Valid<Json<TopQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
+    let query = TopQuery {
+        viewer: verified_viewer(&state.pds, &query.viewer, auth).await?,
+        ..query
+    };
     let section_id: i32 = query.section_id.parse()?;
 
-    let (sql, values) = Post::build_select(query.viewer.clone())
-        .and_where(Expr::col((Post::Table, Post::SectionId)).eq(section_id))
-        .and_where(Expr::col((Post::Table, Post::IsAnnouncement)).eq(true))
-        .and_where(Expr::col((Post::Table, Post::IsDisabled)).eq(false))
-        .order_by_columns([
-            ((Post::Table, Post::IsTop), Order::Desc),
-            ((Post::Table, Post::Created), Order::Desc),
-        ])
-        .limit(10)
-        .build_sqlx(PostgresQueryBuilder);
+    let (sql, values) = build_top(&query, section_id).build_sqlx(PostgresQueryBuilder);
 
-    let rows: Vec<PostRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    let rows: Vec<PostRow> = db::fetch_all(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
-    let sections = Section::all(&state.db).await?;
+    let cursor = rows.last().map(|r| r.uri.clone());
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
+    let admins = Administrator::all_did(&state.db).await;
 
     let mut views = vec![];
     for row in rows {
         let author = build_author(&state, &row.repo).await;
-        let display = if let Some(viewer) = &query.viewer {
-            &row.repo == viewer
-                || sections
-                    .get(&row.section_id)
-                    .is_some_and(|section| section.owner.as_ref() == Some(viewer))
-        } else {
-            false
-        };
-
-        if !row.is_disabled || display {
-            let tip_count = micro_pay::payment_completed_total(
-                &state.pay_url,
-                &format!("{}/{}", NSID_POST, row.uri),
-            )
-            .await
-            .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
-            .unwrap_or(0);
-            views.push(PostView::build(row, author, tip_count.to_string()));
-        }
+        let display = is_privileged(&query.viewer, &row.repo, row.section_id, &sections, &admins);
+        let tip_count = micro_pay::payment_completed_total(
+            &state.pay_url,
+            &format!("{}/{}", NSID_POST, row.uri),
+        )
+        .await
+        .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
+        .unwrap_or(0);
+        views.push(PostView::build(row, author, tip_count.to_string()).for_viewer(display));
     }
-    Ok(ok(json!({
-        "posts": views
-    })))
+    let result = if let Some(cursor) = cursor {
+        json!({
+            "cursor": cursor,
+            "posts": views
+        })
+    } else {
+        json!({
+            "posts": views
+        })
+    };
+    Ok(ok(result))
 }
 
 #[derive(Debug, Default, Validate, Deserialize, IntoParams)]
 #[serde(default)]
 pub(crate) struct DetailQuery {
     pub uri: String,
+    /// Only counts with a bearer token of the viewer's PDS session.
     pub viewer: Option<String>,
+    /// With `repo_handle` or `section_id`, locates the post when `uri` is empty.
+    pub rkey: Option<String>,
+    pub repo_handle: Option<String>,
+    pub section_id: Option<String>,
+    /// Where the visit came from: `feed`, `search`, `notification`,
+    /// `external` or `direct`; anything else counts as `direct`.
+    pub source: Option<String>,
+}
+
+impl DetailQuery {
+    /// The at-uri of the requested post, from `uri` or from `rkey` plus the
+    /// author's handle or the post's section.
+    async fn resolve_uri(&self, state: &AppView) -> Result<String, AppError> {
+        if !self.uri.is_empty() {
+            return Ok(self.uri.clone());
+        }
+        let rkey = self.rkey.as_ref().ok_or(AppError::ValidateFailed(
+            "uri or rkey is required".to_string(),
+        ))?;
+        if let Some(handle) = &self.repo_handle {
+            let resolved = resolve_handle(&state.pds, handle)
+                .await
+                .map_err(AppError::rpc)?;
+            let did = did_of_handle(&resolved)?;
+            return Ok(format!("at://{did}/{NSID_POST}/{rkey}"));
+        }
+        let section_id = self
+            .section_id
+            .as_ref()
+            .and_then(|id| id.parse::<i32>().ok())
+            .ok_or(AppError::ValidateFailed(
+                "rkey requires repo_handle or section_id".to_string(),
+            ))?;
+        let (sql, values) = build_rkey_lookup(section_id, rkey).build_sqlx(PostgresQueryBuilder);
+        let uris: Vec<(String,)> = db::fetch_all(&state.db, &sql, values.clone())
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+        unique_uri(uris.into_iter().map(|(uri,)| uri).collect())
+    }
+}
+
+fn did_of_handle(resolved: &Value) -> Result<String, AppError> {
+    resolved
+        .get("did")
+        .and_then(|did| did.as_str())
+        .map(|did| did.to_string())
+        .ok_or(AppError::NotFound)
+}
+
+fn build_rkey_lookup(section_id: i32, rkey: &str) -> sea_query::SelectStatement {
+    let rkey = rkey
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    sea_query::Query::select()
+        .column(Post::Uri)
+        .from(Post::Table)
+        .and_where(Expr::col(Post::SectionId).eq(section_id))
+        .and_where(Expr::col(Post::IsDraft).eq(false))
+        .and_where(Expr::col(Post::Uri).like(format!("at://%/{NSID_POST}/{rkey}")))
+        .limit(2)
+        .take()
+}
+
+fn unique_uri(mut uris: Vec<String>) -> Result<String, AppError> {
+    match uris.len() {
+        0 => Err(AppError::NotFound),
+        1 => Ok(uris.remove(0)),
+        _ => Err(AppError::ValidateFailed(
+            "rkey is ambiguous in section, use repo_handle".to_string(),
+        )),
+    }
+}
+
+pub(crate) fn build_detail(uri: &str, viewer: Option<String>) -> sea_query::SelectStatement {
+    Post::build_select(viewer)
+        .and_where(Expr::col(Post::Uri).eq(uri))
+        .take()
 }
 
 #[utoipa::path(get, path = "/api/post/detail", params(DetailQuery))]
 pub(crate) async fn detail(
     State(state): State<AppView>,
-    Query(query): Query<DetailQuery>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    ValidQuery(query): ValidQuery<DetailQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    let uri = query.uri;
-    let viewer = query.viewer;
+    let viewer = verified_viewer(&state.pds, &query.viewer, auth).await?;
+    let uri = query.resolve_uri(&state).await?;
 
-    let (sql, values) = Post::build_select(viewer.clone())
-        .and_where(Expr::col(Post::Uri).eq(uri))
-        .build_sqlx(PostgresQueryBuilder);
+    let (sql, values) = build_detail(&uri, viewer.clone()).build_sqlx(PostgresQueryBuilder);
 
-    let row: PostRow = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
+    let row: PostRow = db::fetch_one(&state.db, &sql, values.clone())
         .await
         .map_err(|e| {
             debug!("exec sql failed: {e}");
@@ -391,20 +585,17 @@ pub(crate) async fn detail(
         ])
         .and_where(Expr::col(Post::Uri).eq(&row.uri))
         .build_sqlx(PostgresQueryBuilder);
-    state.db.execute(query_with(&sql, values)).await?;
+    db::execute(&state.db, &sql, values).await?;
+    let source = VisitSource::parse(query.source.as_deref());
+    PostVisitSource::record(&state.db, &row.uri, source)
+        .await
+        .map_err(|e| error!("PostVisitSource::record failed: {e}"))
+        .ok();
 
-    let sections = Section::all(&state.db).await?;
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
     let admins = Administrator::all_did(&state.db).await;
-    let author = build_author(&state, &row.repo).await;
-    let display = if let Some(viewer) = &viewer {
-        &row.repo == viewer
-            || sections
-                .get(&row.section_id)
-                .is_some_and(|section| section.owner.as_ref() == Some(viewer))
-            || admins.contains(viewer)
-    } else {
-        false
-    };
+    let author = build_author_with_ckb_addr(&state, &row.repo).await;
+    let display = is_privileged(&viewer, &row.repo, row.section_id, &sections, &admins);
 
     if !row.is_disabled || display {
         let tip_count = micro_pay::payment_completed_total(
@@ -414,172 +605,870 @@ pub(crate) async fn detail(
         .await
         .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
         .unwrap_or(0);
-        Ok(ok(PostView::build(row, author, tip_count.to_string())))
-    } else {
-        Err(AppError::IsDisabled(
-            row.reasons_for_disabled.unwrap_or_default(),
+        Ok(ok(
+            PostView::build(row, author, tip_count.to_string()).for_viewer(display)
         ))
+    } else {
+        Err(AppError::IsDisabled(HIDDEN_BY_MODERATORS.to_string()))
     }
 }
 
-#[utoipa::path(post, path = "/api/post/commented")]
-pub(crate) async fn commented(
+#[derive(Debug, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct AnalyticsParams {
+    #[validate(length(min = 1))]
+    pub uri: String,
+    /// Days to cover, today included.
+    #[validate(range(min = 1, max = 90))]
+    pub window: i32,
+    pub timestamp: i64,
+}
+
+impl Default for AnalyticsParams {
+    fn default() -> Self {
+        Self {
+            uri: String::new(),
+            window: 30,
+            timestamp: 0,
+        }
+    }
+}
+
+impl SignedParam for AnalyticsParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Visits of a post per source and per day, for its author and the
+/// moderators of its section, who sign the request.
+#[utoipa::path(post, path = "/api/post/analytics")]
+pub(crate) async fn analytics(
     State(state): State<AppView>,
-    Json(query): Json<PostQuery>,
+    Valid(Json(body)): Valid<Json<SignedBody<AnalyticsParams>>>,
 ) -> Result<impl IntoResponse, AppError> {
-    let (sql, values) = Comment::build_select(query.viewer.clone())
-        .and_where(Expr::col((Comment::Table, Comment::Repo)).eq(query.repo))
-        .and_where_option(
-            query
-                .cursor
-                .and_then(|cursor| cursor.parse::<i64>().ok())
-                .map(|cursor| {
-                    Expr::col((Comment::Table, Comment::Created)).binary(
-                        BinOper::SmallerThan,
-                        Func::cust(ToTimestamp).args([Expr::val(cursor)]),
-                    )
-                }),
-        )
-        .order_by(Comment::Created, Order::Desc)
-        .limit(query.limit)
-        .build_sqlx(PostgresQueryBuilder);
-
-    let comments: Vec<CommentRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    body.verify_signature(&state)
         .await
-        .map_err(|e| eyre!("exec sql failed: {e}"))?;
-    let cursor = comments.last().map(|r| r.created.timestamp());
-    let roots = comments
-        .iter()
-        .map(|r| r.post.clone())
-        .collect::<Vec<String>>();
-
-    let (sql, values) = Post::build_select(query.viewer.clone())
-        .and_where(Expr::col((Post::Table, Post::Uri)).is_in(roots))
-        .build_sqlx(PostgresQueryBuilder);
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+    let query = body.params;
+    let (sql, values) = build_detail(&query.uri, None).build_sqlx(PostgresQueryBuilder);
+    let post: PostRow = db::fetch_one(&state.db, &sql, values).await.map_err(|e| {
+        debug!("exec sql failed: {e}");
+        AppError::NotFound
+    })?;
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
+    let admins = Administrator::all_did(&state.db).await;
+    if !is_privileged(
+        &Some(body.did),
+        &post.repo,
+        post.section_id,
+        &sections,
+        &admins,
+    ) {
+        return Err(AppError::ValidateFailed(
+            "only the author and moderators may see analytics".to_string(),
+        ));
+    }
 
-    let posts: Vec<PostRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    let (sql, values) =
+        PostVisitSource::build_select(&post.uri, query.window).build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<VisitSourceRow> = db::fetch_all(&state.db, &sql, values)
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
-    let posts = posts
-        .into_iter()
-        .map(|p| (p.uri.clone(), p))
-        .collect::<HashMap<String, PostRow>>();
+    let mut result = visit_source::breakdown(&rows);
+    result["uri"] = json!(post.uri);
+    result["window"] = json!(query.window);
+    Ok(ok(result))
+}
 
-    let sections = Section::all(&state.db).await?;
-    let admins = Administrator::all_did(&state.db).await;
-    let mut views = vec![];
-    for comment in comments {
-        if let Some(post) = posts.get(&comment.post).cloned() {
-            let post_author = build_author(&state, &post.repo).await;
-            let post_display = if let Some(viewer) = &query.viewer {
-                &post.repo == viewer
-                    || sections
-                        .get(&post.section_id)
-                        .is_some_and(|section| section.owner.as_ref() == Some(viewer))
-                    || admins.contains(viewer)
-            } else {
-                false
-            };
-            let comment_display = if let Some(viewer) = &query.viewer {
-                &comment.repo == viewer
-                    || sections
-                        .get(&comment.section_id)
-                        .is_some_and(|section| section.owner.as_ref() == Some(viewer))
-                    || admins.contains(viewer)
-            } else {
-                false
-            };
-            if (!post.is_disabled || post_display) && (!comment.is_disabled || comment_display) {
-                let tip_count = micro_pay::payment_completed_total(
-                    &state.pay_url,
-                    &format!("{}/{}", NSID_POST, post.uri),
-                )
-                .await
-                .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
-                .unwrap_or(0);
-                views.push(PostRepliedView::build(
-                    post,
-                    post_author,
-                    comment,
-                    tip_count.to_string(),
-                ));
-            }
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[serde(default)]
+pub(crate) struct ParticipantsQuery {
+    pub uri: String,
+    /// Only counts with a bearer token of the viewer's PDS session.
+    pub viewer: Option<String>,
+    #[validate(range(min = 1, max = 50))]
+    pub limit: u64,
+}
+
+impl Default for ParticipantsQuery {
+    fn default() -> Self {
+        Self {
+            uri: String::new(),
+            viewer: None,
+            limit: 10,
         }
     }
-    let result = if let Some(cursor) = cursor {
-        json!({
-            "cursor": cursor.to_string(),
-            "posts": views
-        })
-    } else {
-        json!({
-            "posts": views
-        })
-    };
-    Ok(ok(result))
 }
 
-#[utoipa::path(post, path = "/api/post/commented_page")]
-pub(crate) async fn commented_page(
+/// Everyone but `author` with a comment or reply on `uri` the viewer may
+/// see, once each, by their first one.
+fn build_participants(
+    uri: &str,
+    author: &str,
+    viewer: &Option<String>,
+    limit: u64,
+) -> sea_query::SelectStatement {
+    let mut contributions = sea_query::Query::select()
+        .columns([
+            (Comment::Table, Comment::Repo),
+            (Comment::Table, Comment::Created),
+        ])
+        .from(Comment::Table)
+        .and_where(Expr::col((Comment::Table, Comment::Post)).eq(uri))
+        .and_where(visible_to(
+            viewer,
+            Comment::Table,
+            Comment::IsDisabled,
+            Comment::Repo,
+            Comment::SectionId,
+        ))
+        .take();
+    contributions.union(
+        UnionType::All,
+        sea_query::Query::select()
+            .columns([(Reply::Table, Reply::Repo), (Reply::Table, Reply::Created)])
+            .from(Reply::Table)
+            .and_where(Expr::col((Reply::Table, Reply::Post)).eq(uri))
+            .and_where(visible_to(
+                viewer,
+                Reply::Table,
+                Reply::IsDisabled,
+                Reply::Repo,
+                Reply::SectionId,
+            ))
+            .take(),
+    );
+    sea_query::Query::select()
+        .column("repo")
+        .expr_as(Func::min(Expr::col("created")), "first")
+        .from_subquery(contributions, "contribution")
+        .and_where(Expr::col("repo").ne(author))
+        .group_by_col("repo")
+        .order_by("first", Order::Asc)
+        .order_by("repo", Order::Asc)
+        .limit(limit)
+        .take()
+}
+
+/// Up to `limit` people taking part in a post as `build_author` cards,
+/// under `participants`: its author first, then whoever commented or
+/// replied, by their first contribution. Hidden comments and replies
+/// count only for viewers who may see them.
+#[utoipa::path(get, path = "/api/post/participants", params(ParticipantsQuery))]
+pub(crate) async fn participants(
     State(state): State<AppView>,
-    Json(query): Json<PostPageQuery>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    ValidQuery(query): ValidQuery<ParticipantsQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    query
-        .validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
-    let offset = query.per_page * (query.page - 1);
-    let (sql, values) = Comment::build_select(query.viewer.clone())
-        .and_where(Expr::col((Comment::Table, Comment::IsDisabled)).eq(query.is_disabled))
-        .and_where_option(
-            query
-                .repo
-                .as_ref()
-                .map(|repo| Expr::col((Comment::Table, Comment::Repo)).eq(repo)),
-        )
-        .and_where_option(
-            query
-                .section_id
-                .as_ref()
-                .and_then(|id| id.parse::<i32>().ok())
-                .map(|section| Expr::col((Comment::Table, Comment::SectionId)).eq(section)),
+    let query = ParticipantsQuery {
+        viewer: verified_viewer(&state.pds, &query.viewer, auth).await?,
+        ..query
+    };
+    let (sql, values) =
+        build_detail(&query.uri, query.viewer.clone()).build_sqlx(PostgresQueryBuilder);
+    let post: PostRow = db::fetch_one(&state.db, &sql, values).await.map_err(|e| {
+        debug!("exec sql failed: {e}");
+        AppError::NotFound
+    })?;
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
+    let admins = Administrator::all_did(&state.db).await;
+    if post.is_disabled
+        && !is_privileged(
+            &query.viewer,
+            &post.repo,
+            post.section_id,
+            &sections,
+            &admins,
         )
-        .and_where_option(query.q.as_ref().map(|q| {
-            (Comment::Table, Comment::Text)
-                .into_column_ref()
-                .like(format!("%{q}%"))
-        }))
-        .order_by(Comment::Created, Order::Desc)
-        .offset(offset)
-        .limit(query.per_page)
+    {
+        return Err(AppError::IsDisabled(HIDDEN_BY_MODERATORS.to_string()));
+    }
+
+    let (sql, values) = build_participants(&post.uri, &post.repo, &query.viewer, query.limit - 1)
         .build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<(String, chrono::DateTime<chrono::Local>)> =
+        db::fetch_all(&state.db, &sql, values)
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+    let mut participants = vec![build_author(&state, &post.repo).await];
+    for (repo, _) in rows {
+        participants.push(build_author(&state, &repo).await);
+    }
 
-    let comments: Vec<CommentRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
-        .await
-        .map_err(|e| eyre!("exec sql failed: {e}"))?;
-    let roots = comments
-        .iter()
-        .map(|r| r.post.clone())
-        .collect::<Vec<String>>();
+    Ok(ok(json!({
+        "uri": post.uri,
+        "participants": participants,
+    })))
+}
 
-    let (sql, values) = Post::build_select(query.viewer.clone())
-        .and_where(Expr::col((Post::Table, Post::Uri)).is_in(roots))
-        .build_sqlx(PostgresQueryBuilder);
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum EngagementKind {
+    #[default]
+    Likes,
+    Tips,
+}
 
-    let posts: Vec<PostRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
-        .await
-        .map_err(|e| eyre!("exec sql failed: {e}"))?;
-    let posts = posts
-        .into_iter()
+#[derive(Debug, Validate, Deserialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct EngagementQuery {
+    pub uri: String,
+    pub kind: EngagementKind,
+    /// The `cursor` of the previous page.
+    pub cursor: Option<String>,
+    #[validate(range(min = 1, max = 100))]
+    pub limit: u64,
+}
+
+impl Default for EngagementQuery {
+    fn default() -> Self {
+        Self {
+            uri: String::new(),
+            kind: EngagementKind::Likes,
+            cursor: None,
+            limit: 30,
+        }
+    }
+}
+
+/// Who liked or tipped a post.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct EngagementView {
+    /// Profile of who liked or tipped, as built by `build_author`.
+    pub author: Value,
+    #[schema(value_type = String, format = DateTime)]
+    pub created: chrono::DateTime<chrono::Local>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_amount"
+    )]
+    #[schema(schema_with = crate::lexicon::numeric::schema)]
+    pub amount: Option<String>,
+    /// Tips only: the amount in CKB, with 8 decimal places; `amount` holds
+    /// it in shannons.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount_ckb: Option<String>,
+}
+
+fn serialize_amount<S: serde::Serializer>(
+    amount: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match amount {
+        Some(amount) => crate::lexicon::numeric::serialize(amount, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// The `created` and `uri` of the last like of a page, from its cursor.
+fn like_cursor(cursor: &str) -> Option<(chrono::DateTime<chrono::Local>, &str)> {
+    let (micros, uri) = cursor.split_once(':')?;
+    let created = chrono::DateTime::from_timestamp_micros(micros.parse().ok()?)?;
+    Some((created.into(), uri))
+}
+
+/// A page of who liked or committed a tip to the post, newest first.
+/// Authors who removed their data are left out; accounts have no blocks or
+/// shadow bans to filter by. Returns the `EngagementView`s under `items`
+/// and the `cursor` of the next page.
+#[utoipa::path(post, path = "/api/post/engagement")]
+pub(crate) async fn engagement(
+    State(state): State<AppView>,
+    Valid(Json(query)): Valid<Json<EngagementQuery>>,
+) -> Result<impl IntoResponse, AppError> {
+    let invalid_cursor = || AppError::ValidateFailed("invalid cursor".to_string());
+
+    let mut items = vec![];
+    let cursor = match query.kind {
+        EngagementKind::Likes => {
+            let before = match &query.cursor {
+                Some(cursor) => Some(like_cursor(cursor).ok_or_else(invalid_cursor)?),
+                None => None,
+            };
+            let (sql, values) = Like::build_engagement(&query.uri, before, query.limit)
+                .build_sqlx(PostgresQueryBuilder);
+            let rows: Vec<(String, String, chrono::DateTime<chrono::Local>)> =
+                db::fetch_all(&state.db, &sql, values)
+                    .await
+                    .map_err(|e| eyre!("exec sql failed: {e}"))?;
+            let cursor = rows
+                .last()
+                .map(|(uri, _, created)| format!("{}:{uri}", created.timestamp_micros()));
+            for (_, repo, created) in rows {
+                items.push(EngagementView {
+                    author: build_author(&state, &repo).await,
+                    created,
+                    amount: None,
+                    amount_ckb: None,
+                });
+            }
+            cursor
+        }
+        EngagementKind::Tips => {
+            let (_did, nsid, _rkey) =
+                resolve_uri(&query.uri).map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+            let before = match &query.cursor {
+                Some(cursor) => Some(cursor.parse::<i32>().map_err(|_| invalid_cursor())?),
+                None => None,
+            };
+            let (sql, values) =
+                Tip::build_engagement(&format!("{nsid}/{}", query.uri), before, query.limit)
+                    .build_sqlx(PostgresQueryBuilder);
+            let rows: Vec<TipRow> = db::fetch_all(&state.db, &sql, values)
+                .await
+                .map_err(|e| eyre!("exec sql failed: {e}"))?;
+            let cursor = rows.last().map(|row| row.id.to_string());
+            for row in rows {
+                items.push(EngagementView {
+                    author: build_author(&state, &row.sender_did).await,
+                    created: row.created,
+                    amount: Some(row.amount.to_string()),
+                    amount_ckb: Some(shannons_to_ckb(row.amount)),
+                });
+            }
+            cursor
+        }
+    };
+
+    Ok(ok(json!({
+        "kind": query.kind,
+        "items": items,
+        "cursor": cursor,
+    })))
+}
+
+/// Concurrent tip and author lookups while assembling a thread.
+const THREAD_LOOKUPS: usize = 8;
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[serde(default)]
+pub(crate) struct ThreadQuery {
+    pub uri: String,
+    /// Only counts with a bearer token of the viewer's PDS session.
+    pub viewer: Option<String>,
+    /// Comments after this one; the `cursor` of the previous page.
+    pub cursor: Option<String>,
+    #[validate(range(min = 1, max = 100))]
+    pub comment_limit: u64,
+    /// Replies inlined per comment; page on with `/api/reply/list` and the
+    /// comment's reply `cursor`.
+    #[validate(range(min = 1, max = 20))]
+    pub reply_limit: u64,
+}
+
+impl Default for ThreadQuery {
+    fn default() -> Self {
+        Self {
+            uri: String::new(),
+            viewer: None,
+            cursor: None,
+            comment_limit: 20,
+            reply_limit: 2,
+        }
+    }
+}
+
+fn build_thread_comments(query: &ThreadQuery) -> sea_query::SelectStatement {
+    Comment::build_select(query.viewer.clone())
+        .and_where(Expr::col((Comment::Table, Comment::Post)).eq(&query.uri))
+        .and_where(visible_to(
+            &query.viewer,
+            Comment::Table,
+            Comment::IsDisabled,
+            Comment::Repo,
+            Comment::SectionId,
+        ))
+        .and_where_option(
+            query
+                .cursor
+                .as_ref()
+                .and_then(|cursor| cursor.parse::<i64>().ok())
+                .map(|cursor| {
+                    Expr::col((Comment::Table, Comment::Created)).binary(
+                        BinOper::GreaterThan,
+                        Func::cust(ToTimestamp).args([Expr::val(cursor)]),
+                    )
+                }),
+        )
+        .order_by(Comment::Created, Order::Asc)
+        .limit(query.comment_limit)
+        .take()
+}
+
+/// The first `limit` replies of every comment in `comments` in one query,
+/// oldest first.
+fn build_thread_replies(
+    comments: Vec<String>,
+    viewer: Option<String>,
+    limit: u64,
+) -> sea_query::SelectStatement {
+    let visible = visible_to(
+        &viewer,
+        Reply::Table,
+        Reply::IsDisabled,
+        Reply::Repo,
+        Reply::SectionId,
+    );
+    let ranked = Reply::build_select(viewer)
+        .expr_as(
+            Expr::cust(
+                "row_number() over (partition by \"reply\".\"comment\" order by \"reply\".\"created\")",
+            ),
+            "position",
+        )
+        .and_where(Expr::col((Reply::Table, Reply::Comment)).is_in(comments))
+        .and_where(visible)
+        .take();
+    sea_query::Query::select()
+        .column(Asterisk)
+        .from_subquery(ranked, Reply::Table)
+        .and_where(Expr::col("position").lte(limit as i64))
+        .order_by(Reply::Created, Order::Asc)
+        .take()
+}
+
+async fn tip_total(state: &AppView, nsid: &str, uri: &str) -> i64 {
+    micro_pay::payment_completed_total(&state.pay_url, &format!("{nsid}/{uri}"))
+        .await
+        .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
+        .unwrap_or(0)
+}
+
+/// A post with its first comments and their first replies in one call, as
+/// `{ "post", "comments", "cursor" }`. Every comment inlines its replies as
+/// `/api/reply/list` returns them; `cursor` pages the comments and is left
+/// out of an empty page. Hidden content follows the rules of the
+/// individual endpoints.
+#[utoipa::path(get, path = "/api/post/thread", params(ThreadQuery))]
+pub(crate) async fn thread(
+    State(state): State<AppView>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    ValidQuery(query): ValidQuery<ThreadQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let query = ThreadQuery {
+        viewer: verified_viewer(&state.pds, &query.viewer, auth).await?,
+        ..query
+    };
+    let viewer = query.viewer.clone();
+
+    let (sql, values) = build_detail(&query.uri, viewer.clone()).build_sqlx(PostgresQueryBuilder);
+    let post: PostRow = db::fetch_one(&state.db, &sql, values).await.map_err(|e| {
+        debug!("exec sql failed: {e}");
+        AppError::NotFound
+    })?;
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
+    let admins = Administrator::all_did(&state.db).await;
+    let post_display = is_privileged(&viewer, &post.repo, post.section_id, &sections, &admins);
+    if post.is_disabled && !post_display {
+        return Err(AppError::IsDisabled(HIDDEN_BY_MODERATORS.to_string()));
+    }
+
+    // update visited
+    let (sql, values) = sea_query::Query::update()
+        .table(Post::Table)
+        .values([
+            (Post::VisitedCount, (post.visited_count + 1).into()),
+            (Post::Visited, (chrono::Local::now()).into()),
+        ])
+        .and_where(Expr::col(Post::Uri).eq(&post.uri))
+        .build_sqlx(PostgresQueryBuilder);
+    db::execute(&state.db, &sql, values).await?;
+
+    let (sql, values) = build_thread_comments(&query).build_sqlx(PostgresQueryBuilder);
+    let comments: Vec<CommentRow> = db::fetch_all(&state.db, &sql, values)
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+    let cursor = comments.last().map(|row| row.created.timestamp());
+    let comments = comments
+        .into_iter()
+        .map(|row| {
+            let display = is_privileged(&viewer, &row.repo, row.section_id, &sections, &admins);
+            (row, display)
+        })
+        .collect::<Vec<_>>();
+
+    let replies: Vec<ReplyRow> = if comments.is_empty() {
+        vec![]
+    } else {
+        let uris = comments.iter().map(|(row, _)| row.uri.clone()).collect();
+        let (sql, values) = build_thread_replies(uris, viewer.clone(), query.reply_limit)
+            .build_sqlx(PostgresQueryBuilder);
+        db::fetch_all(&state.db, &sql, values)
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?
+    };
+    let replies = replies
+        .into_iter()
+        .map(|row| {
+            let display = is_privileged(&viewer, &row.repo, row.section_id, &sections, &admins);
+            (row, display)
+        })
+        .collect::<Vec<_>>();
+
+    // every author once, then every tip total, a few at a time
+    let state = &state;
+    let dids = comments
+        .iter()
+        .map(|(row, _)| &row.repo)
+        .chain(replies.iter().flat_map(|(row, _)| [&row.repo, &row.to]))
+        .collect::<HashSet<_>>();
+    let authors = futures::stream::iter(dids)
+        .map(|did| async move { (did.clone(), build_author(state, did).await) })
+        .buffer_unordered(THREAD_LOOKUPS)
+        .collect::<HashMap<_, _>>()
+        .await;
+    let tips = futures::stream::iter(
+        comments
+            .iter()
+            .map(|(row, _)| (NSID_COMMENT, &row.uri))
+            .chain(replies.iter().map(|(row, _)| (NSID_REPLY, &row.uri))),
+    )
+    .map(|(nsid, uri)| async move { (uri.clone(), tip_total(state, nsid, uri).await) })
+    .buffer_unordered(THREAD_LOOKUPS)
+    .collect::<HashMap<_, _>>()
+    .await;
+    let tip_count_of = |uri: &str| tips.get(uri).copied().unwrap_or(0).to_string();
+
+    let mut thread_replies: HashMap<String, Vec<ReplyView>> = HashMap::new();
+    for (row, display) in replies {
+        let author = authors[&row.repo].clone();
+        let to = authors[&row.to].clone();
+        let tip_count = tip_count_of(&row.uri);
+        let moderator = is_moderator(&viewer, row.section_id, &sections, &admins);
+        thread_replies.entry(row.comment.clone()).or_default().push(
+            ReplyView::build(row, author, to, tip_count)
+                .for_viewer(display)
+                .for_moderator(moderator),
+        );
+    }
+    let mut views = vec![];
+    for (row, display) in comments {
+        let replies = thread_replies.remove(&row.uri).unwrap_or_default();
+        let replies = match replies.last().map(|r| r.created.timestamp()) {
+            Some(cursor) => json!({ "cursor": cursor.to_string(), "replies": replies }),
+            None => json!({ "replies": replies }),
+        };
+        let author = authors[&row.repo].clone();
+        let tip_count = tip_count_of(&row.uri);
+        let moderator = is_moderator(&viewer, row.section_id, &sections, &admins);
+        views.push(
+            CommentView::build(row, author, replies, tip_count)
+                .for_viewer(display)
+                .for_moderator(moderator),
+        );
+    }
+
+    let author = build_author_with_ckb_addr(state, &post.repo).await;
+    let tip_count = tip_total(state, NSID_POST, &post.uri).await;
+    let mut result = json!({
+        "post": PostView::build(post, author, tip_count.to_string()).for_viewer(post_display),
+        "comments": views,
+    });
+    if let Some(cursor) = cursor {
+        result["cursor"] = json!(cursor.to_string());
+    }
+    Ok(ok(result))
+}
+
+/// A comment or reply of the user in the commented feed.
+enum Activity {
+    Comment(CommentRow),
+    Reply(ReplyRow),
+}
+
+impl Activity {
+    fn created(&self) -> chrono::DateTime<chrono::Local> {
+        match self {
+            Self::Comment(comment) => comment.created,
+            Self::Reply(reply) => reply.created,
+        }
+    }
+
+    fn post(&self) -> &str {
+        match self {
+            Self::Comment(comment) => &comment.post,
+            Self::Reply(reply) => &reply.post,
+        }
+    }
+}
+
+/// The `limit` newest of two streams that were each fetched newest first
+/// with `limit` rows after the same cursor.
+fn newest_of<T>(
+    a: Vec<T>,
+    b: Vec<T>,
+    created: impl Fn(&T) -> chrono::DateTime<chrono::Local>,
+    limit: u64,
+) -> Vec<T> {
+    let mut merged = a;
+    merged.extend(b);
+    // stable, so equal times keep comments before replies
+    merged.sort_by_key(|item| std::cmp::Reverse(created(item)));
+    merged.truncate(usize::try_from(limit).unwrap_or(usize::MAX));
+    merged
+}
+
+#[utoipa::path(post, path = "/api/post/commented")]
+pub(crate) async fn commented(
+    State(state): State<AppView>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Valid(Json(query)): Valid<Json<PostQuery>>,
+) -> Result<impl IntoResponse, AppError> {
+    let query = PostQuery {
+        viewer: verified_viewer(&state.pds, &query.viewer, auth).await?,
+        ..query
+    };
+    let limit = state.pagination.post_list.resolve(query.limit);
+    let cursor = query
+        .cursor
+        .as_ref()
+        .and_then(|cursor| cursor.parse::<i64>().ok());
+    let visible_posts = sea_query::Query::select()
+        .column((Post::Table, Post::Uri))
+        .from(Post::Table)
+        .and_where(visible_to(
+            &query.viewer,
+            Post::Table,
+            Post::IsDisabled,
+            Post::Repo,
+            Post::SectionId,
+        ))
+        .take();
+    let (sql, values) = Comment::build_select(query.viewer.clone())
+        .and_where(Expr::col((Comment::Table, Comment::Repo)).eq(query.repo.clone()))
+        .and_where(visible_to(
+            &query.viewer,
+            Comment::Table,
+            Comment::IsDisabled,
+            Comment::Repo,
+            Comment::SectionId,
+        ))
+        .and_where(Expr::col((Comment::Table, Comment::Post)).in_subquery(visible_posts.clone()))
+        .and_where_option(cursor.map(|cursor| {
+            Expr::col((Comment::Table, Comment::Created)).binary(
+                BinOper::SmallerThan,
+                Func::cust(ToTimestamp).args([Expr::val(cursor)]),
+            )
+        }))
+        .order_by(Comment::Created, Order::Desc)
+        .limit(limit)
+        .build_sqlx(PostgresQueryBuilder);
+
+    let comments: Vec<CommentRow> = db::fetch_all(&state.db, &sql, values.clone())
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+
+    let replies: Vec<ReplyRow> = if query.include_replies {
+        let (sql, values) = Reply::build_select(query.viewer.clone())
+            .and_where(Expr::col((Reply::Table, Reply::Repo)).eq(query.repo.clone()))
+            .and_where(visible_to(
+                &query.viewer,
+                Reply::Table,
+                Reply::IsDisabled,
+                Reply::Repo,
+                Reply::SectionId,
+            ))
+            .and_where(Expr::col((Reply::Table, Reply::Post)).in_subquery(visible_posts))
+            .and_where_option(cursor.map(|cursor| {
+                Expr::col((Reply::Table, Reply::Created)).binary(
+                    BinOper::SmallerThan,
+                    Func::cust(ToTimestamp).args([Expr::val(cursor)]),
+                )
+            }))
+            .order_by(Reply::Created, Order::Desc)
+            .limit(limit)
+            .build_sqlx(PostgresQueryBuilder);
+        db::fetch_all(&state.db, &sql, values)
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?
+    } else {
+        vec![]
+    };
+
+    let activities = newest_of(
+        comments.into_iter().map(Activity::Comment).collect(),
+        replies.into_iter().map(Activity::Reply).collect(),
+        Activity::created,
+        limit,
+    );
+    let cursor = activities.last().map(|a| a.created().timestamp());
+    let roots = activities
+        .iter()
+        .map(|a| a.post().to_string())
+        .collect::<Vec<String>>();
+
+    let (sql, values) = Post::build_select(query.viewer.clone())
+        .and_where(Expr::col((Post::Table, Post::Uri)).is_in(roots))
+        .build_sqlx(PostgresQueryBuilder);
+
+    let posts: Vec<PostRow> = db::fetch_all(&state.db, &sql, values.clone())
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+    let posts = posts
+        .into_iter()
+        .map(|p| (p.uri.clone(), p))
+        .collect::<HashMap<String, PostRow>>();
+
+    // the comments replied to; replies under hidden ones are left out
+    let parents = activities
+        .iter()
+        .filter_map(|a| match a {
+            Activity::Reply(reply) => Some(reply.comment.clone()),
+            Activity::Comment(_) => None,
+        })
+        .collect::<Vec<String>>();
+    let parents: HashMap<String, CommentRow> = if parents.is_empty() {
+        HashMap::new()
+    } else {
+        let (sql, values) = Comment::build_select(query.viewer.clone())
+            .and_where(Expr::col((Comment::Table, Comment::Uri)).is_in(parents))
+            .and_where(visible_to(
+                &query.viewer,
+                Comment::Table,
+                Comment::IsDisabled,
+                Comment::Repo,
+                Comment::SectionId,
+            ))
+            .build_sqlx(PostgresQueryBuilder);
+        let rows: Vec<CommentRow> = db::fetch_all(&state.db, &sql, values)
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+        rows.into_iter().map(|c| (c.uri.clone(), c)).collect()
+    };
+
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
+    let admins = Administrator::all_did(&state.db).await;
+    let mut views = vec![];
+    for activity in activities {
+        let (comment, reply) = match activity {
+            Activity::Comment(comment) => (comment, None),
+            Activity::Reply(reply) => match parents.get(&reply.comment).cloned() {
+                Some(comment) => (comment, Some(reply)),
+                None => continue,
+            },
+        };
+        if let Some(post) = posts.get(&comment.post).cloned() {
+            let post_author = build_author(&state, &post.repo).await;
+            let post_display = is_privileged(
+                &query.viewer,
+                &post.repo,
+                post.section_id,
+                &sections,
+                &admins,
+            );
+            let comment_display = is_privileged(
+                &query.viewer,
+                &comment.repo,
+                comment.section_id,
+                &sections,
+                &admins,
+            );
+            let tip_count = micro_pay::payment_completed_total(
+                &state.pay_url,
+                &format!("{}/{}", NSID_POST, post.uri),
+            )
+            .await
+            .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
+            .unwrap_or(0);
+            let mut view =
+                PostRepliedView::build(post, post_author, comment, tip_count.to_string());
+            if let Some(reply) = reply {
+                view = view.with_reply(reply);
+            }
+            views.push(view.for_viewer(post_display, comment_display));
+        }
+    }
+    let result = if let Some(cursor) = cursor {
+        json!({
+            "cursor": cursor.to_string(),
+            "posts": views,
+            "limit": limit
+        })
+    } else {
+        json!({
+            "posts": views,
+            "limit": limit
+        })
+    };
+    Ok(ok(result))
+}
+
+#[utoipa::path(post, path = "/api/post/commented_page")]
+pub(crate) async fn commented_page(
+    State(state): State<AppView>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Valid(Json(query)): Valid<Json<PostPageQuery>>,
+) -> Result<impl IntoResponse, AppError> {
+    let query = PostPageQuery {
+        viewer: verified_viewer(&state.pds, &query.viewer, auth).await?,
+        ..query
+    };
+    let offset = query.per_page * (query.page - 1);
+    let (sql, values) = Comment::build_select(query.viewer.clone())
+        .and_where(Expr::col((Comment::Table, Comment::IsDisabled)).eq(query.is_disabled))
+        .and_where_option(
+            query
+                .repo
+                .as_ref()
+                .map(|repo| Expr::col((Comment::Table, Comment::Repo)).eq(repo)),
+        )
+        .and_where_option(
+            query
+                .section_id
+                .as_ref()
+                .and_then(|id| id.parse::<i32>().ok())
+                .map(|section| Expr::col((Comment::Table, Comment::SectionId)).eq(section)),
+        )
+        .and_where_option(query.q.as_ref().map(|q| {
+            (Comment::Table, Comment::Text)
+                .into_column_ref()
+                .like(format!("%{q}%"))
+        }))
+        .order_by(Comment::Created, Order::Desc)
+        .offset(offset)
+        .limit(query.per_page)
+        .build_sqlx(PostgresQueryBuilder);
+
+    let comments: Vec<CommentRow> = db::fetch_all(&state.db, &sql, values.clone())
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+    let roots = comments
+        .iter()
+        .map(|r| r.post.clone())
+        .collect::<Vec<String>>();
+
+    let (sql, values) = Post::build_select(query.viewer.clone())
+        .and_where(Expr::col((Post::Table, Post::Uri)).is_in(roots))
+        .build_sqlx(PostgresQueryBuilder);
+
+    let posts: Vec<PostRow> = db::fetch_all(&state.db, &sql, values.clone())
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+    let posts = posts
+        .into_iter()
         .map(|p| (p.uri.clone(), p))
         .collect::<HashMap<String, PostRow>>();
 
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
+    let admins = Administrator::all_did(&state.db).await;
     let mut views = vec![];
     for comment in comments {
         if let Some(post) = posts.get(&comment.post).cloned() {
+            let post_display = is_privileged(
+                &query.viewer,
+                &post.repo,
+                post.section_id,
+                &sections,
+                &admins,
+            );
+            let comment_display = is_privileged(
+                &query.viewer,
+                &comment.repo,
+                comment.section_id,
+                &sections,
+                &admins,
+            );
             let post_author = build_author(&state, &post.repo).await;
             let tip_count = micro_pay::payment_completed_total(
                 &state.pay_url,
@@ -588,12 +1477,10 @@ pub(crate) async fn commented_page(
             .await
             .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
             .unwrap_or(0);
-            views.push(PostRepliedView::build(
-                post,
-                post_author,
-                comment,
-                tip_count.to_string(),
-            ));
+            views.push(
+                PostRepliedView::build(post, post_author, comment, tip_count.to_string())
+                    .for_viewer(post_display, comment_display),
+            );
         } else {
             warn!("post not found: {}", comment.post);
         }
@@ -621,8 +1508,7 @@ pub(crate) async fn commented_page(
                 .like(format!("%{q}%"))
         }))
         .build_sqlx(PostgresQueryBuilder);
-    let total: (i64,) = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
+    let total: (i64,) = db::fetch_one(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
@@ -642,6 +1528,9 @@ pub(crate) struct DraftQuery {
     #[validate(range(min = 1))]
     pub per_page: u64,
     pub repo: String,
+    pub section_id: Option<String>,
+    /// Matched against the draft titles.
+    pub q: Option<String>,
 }
 
 impl Default for DraftQuery {
@@ -650,25 +1539,60 @@ impl Default for DraftQuery {
             page: 1,
             per_page: 20,
             repo: Default::default(),
+            section_id: None,
+            q: None,
         }
     }
 }
 
+/// The drafts of `query.repo`, posts marked as drafts and local drafts
+/// alike, narrowed to its section and title filters.
+fn build_drafts(query: &DraftQuery) -> sea_query::SelectStatement {
+    let mut drafts = Post::build_draft_select()
+        .and_where(Expr::col((Post::Table, Post::Repo)).eq(&query.repo))
+        .take();
+    drafts.union(
+        UnionType::All,
+        Draft::build_select()
+            .and_where(Expr::col((Draft::Table, Draft::Repo)).eq(&query.repo))
+            .take(),
+    );
+    sea_query::Query::select()
+        .from_subquery(drafts, "draft")
+        .and_where_option(
+            query
+                .section_id
+                .as_ref()
+                .and_then(|id| id.parse::<i32>().ok())
+                .map(|section| Expr::col("id").eq(section)),
+        )
+        .and_where_option(
+            query
+                .q
+                .as_ref()
+                .map(|q| Expr::col("title").like(format!("%{q}%"))),
+        )
+        .take()
+}
+
+/// Lists the drafts of the repo the bearer token belongs to.
 #[utoipa::path(post, path = "/api/post/list_draft")]
 pub(crate) async fn list_draft(
     State(state): State<AppView>,
-    Json(query): Json<DraftQuery>,
+    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    Valid(Json(query)): Valid<Json<DraftQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
+    check_session(&state.pds, auth.token(), &query.repo).await?;
+
     let offset = query.per_page * (query.page - 1);
-    let (sql, values) = Post::build_draft_select()
-        .and_where(Expr::col((Post::Table, Post::Repo)).eq(&query.repo))
-        .order_by(Comment::Updated, Order::Desc)
+    let (sql, values) = build_drafts(&query)
+        .column(Asterisk)
+        .order_by("updated", Order::Desc)
         .offset(offset)
         .limit(query.per_page)
         .build_sqlx(PostgresQueryBuilder);
 
-    let rows: Vec<PostDraftRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    let rows: Vec<PostDraftRow> = db::fetch_all(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
@@ -679,15 +1603,11 @@ pub(crate) async fn list_draft(
         views.push(PostDraftView::build(row, author));
     }
 
-    let (sql, values) = sea_query::Query::select()
-        .expr(Expr::col((Post::Table, Post::Uri)).count())
-        .from(Post::Table)
-        .and_where(Expr::col((Post::Table, Post::Repo)).eq(&query.repo))
-        .and_where(Expr::col((Post::Table, Post::IsDraft)).eq(true))
+    let (sql, values) = build_drafts(&query)
+        .expr(Func::count(Expr::col("uri")))
         .build_sqlx(PostgresQueryBuilder);
 
-    let total: (i64,) = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
+    let total: (i64,) = db::fetch_one(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
@@ -699,26 +1619,1382 @@ pub(crate) async fn list_draft(
     })))
 }
 
+/// A draft of the repo the bearer token belongs to, local or on the PDS.
 #[utoipa::path(get, path = "/api/post/detail_draft", params(DetailQuery))]
 pub(crate) async fn detail_draft(
     State(state): State<AppView>,
-    Query(query): Query<DetailQuery>,
+    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    ValidQuery(query): ValidQuery<DetailQuery>,
 ) -> Result<impl IntoResponse, AppError> {
     let uri = query.uri;
 
-    let (sql, values) = Post::build_draft_select()
-        .and_where(Expr::col(Post::Uri).eq(uri))
-        .build_sqlx(PostgresQueryBuilder);
+    let (sql, values) = if Draft::is_local(&uri) {
+        Draft::build_select()
+            .and_where(Expr::col((Draft::Table, Draft::Uri)).eq(uri))
+            .build_sqlx(PostgresQueryBuilder)
+    } else {
+        Post::build_draft_select()
+            .and_where(Expr::col(Post::Uri).eq(uri))
+            .build_sqlx(PostgresQueryBuilder)
+    };
 
-    let row: PostDraftRow = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
+    let row: PostDraftRow = db::fetch_one(&state.db, &sql, values.clone())
         .await
         .map_err(|e| {
             debug!("exec sql failed: {e}");
             AppError::NotFound
         })?;
+    check_session(&state.pds, auth.token(), &row.repo).await?;
 
     let author = build_author(&state, &row.repo).await;
 
     Ok(ok(PostDraftView::build(row, author)))
 }
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct SaveDraftParams {
+    /// Local draft to update; a new one is created when absent.
+    pub uri: Option<String>,
+    pub section_id: Option<String>,
+    pub title: Option<String>,
+    pub text: Option<String>,
+    pub timestamp: i64,
+}
+
+impl SignedParam for SaveDraftParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+#[utoipa::path(post, path = "/api/post/save_draft")]
+pub(crate) async fn save_draft(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<SaveDraftParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let params = &body.params;
+    if params.section_id.is_none() && params.title.is_none() && params.text.is_none() {
+        return Err(AppError::ValidateFailed(
+            "nothing to save: set section_id, title or text".to_string(),
+        ));
+    }
+    let section_id = params
+        .section_id
+        .as_ref()
+        .map(|id| id.parse::<i32>())
+        .transpose()
+        .map_err(|e| AppError::ValidateFailed(format!("error in section_id: {e}")))?;
+
+    let uri = if let Some(uri) = &params.uri {
+        if !uri.starts_with(&format!("{LOCAL_DRAFT_SCHEME}{}/", body.did)) {
+            return Err(AppError::ValidateFailed(
+                "not a local draft of did".to_string(),
+            ));
+        }
+        uri.clone()
+    } else {
+        Draft::new_uri(&body.did)
+    };
+
+    Draft::upsert(
+        &state.db,
+        &uri,
+        &body.did,
+        section_id,
+        params.title.as_deref(),
+        params.text.as_deref(),
+    )
+    .await?;
+
+    let (sql, values) = Draft::build_select()
+        .and_where(Expr::col((Draft::Table, Draft::Uri)).eq(&uri))
+        .build_sqlx(PostgresQueryBuilder);
+    let row: PostDraftRow = db::fetch_one(&state.db, &sql, values.clone())
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+    let author = build_author(&state, &row.repo).await;
+
+    Ok(ok(PostDraftView::build(row, author)))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct PublishDraft {
+    #[validate(length(min = 1))]
+    pub uri: String,
+    #[validate(length(min = 1))]
+    pub repo: String,
+    pub rkey: String,
+    pub signing_key: String,
+    pub ckb_addr: String,
+    pub root: Value,
+}
+
+/// Write a local draft to the PDS as a post and drop the local copy.
+#[utoipa::path(post, path = "/api/post/publish")]
+pub(crate) async fn publish(
+    State(state): State<AppView>,
+    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    Valid(Json(publish)): Valid<Json<PublishDraft>>,
+) -> Result<impl IntoResponse, AppError> {
+    if !Draft::is_local(&publish.uri) {
+        return Err(AppError::ValidateFailed("not a local draft".to_string()));
+    }
+    let (sql, values) = Draft::build_select()
+        .and_where(Expr::col((Draft::Table, Draft::Uri)).eq(&publish.uri))
+        .and_where(Expr::col((Draft::Table, Draft::Repo)).eq(&publish.repo))
+        .build_sqlx(PostgresQueryBuilder);
+    let row: PostDraftRow = db::fetch_one(&state.db, &sql, values.clone())
+        .await
+        .map_err(|e| {
+            debug!("exec sql failed: {e}");
+            AppError::NotFound
+        })?;
+
+    let result = record::create(
+        State(state.clone()),
+        TypedHeader(auth),
+        Valid::check(Json(NewRecord {
+            repo: publish.repo,
+            rkey: publish.rkey,
+            value: Draft::to_record(&row),
+            signing_key: publish.signing_key,
+            ckb_addr: publish.ckb_addr,
+            root: publish.root,
+        }))?,
+    )
+    .await?;
+
+    Draft::delete(&state.db, &row.uri).await?;
+
+    Ok(result)
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct PinPostParams {
+    #[validate(length(min = 1))]
+    pub uri: String,
+    pub timestamp: i64,
+}
+
+impl SignedParam for PinPostParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Pin one of the caller's posts to the top of their own post list. Unlike
+/// `is_top`, which section moderators set, this is up to the author.
+#[utoipa::path(post, path = "/api/post/pin")]
+pub(crate) async fn pin(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<PinPostParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let row = Post::select_by_uri(&state.db, &body.params.uri)
+        .await
+        .map_err(|e| {
+            debug!("{e}");
+            AppError::NotFound
+        })?;
+    if row.repo != body.did {
+        return Err(AppError::ValidateFailed(
+            "only the author can pin a post".to_string(),
+        ));
+    }
+    Post::pin(&state.db, &row.repo, row.section_id, &row.uri).await?;
+
+    Ok(ok_simple())
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct MuteThreadParams {
+    #[validate(length(min = 1))]
+    pub uri: String,
+    pub timestamp: i64,
+}
+
+impl SignedParam for MuteThreadParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Stop notifications from the thread of a post, for the caller only. The
+/// thread stays visible in feeds.
+#[utoipa::path(post, path = "/api/post/mute")]
+pub(crate) async fn mute(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<MuteThreadParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let row = Post::select_by_uri(&state.db, &body.params.uri)
+        .await
+        .map_err(|e| {
+            debug!("{e}");
+            AppError::NotFound
+        })?;
+    ThreadMute::insert(&state.db, &body.did, &row.uri).await?;
+
+    Ok(ok_simple())
+}
+
+/// Get notifications from a muted thread again.
+#[utoipa::path(post, path = "/api/post/unmute")]
+pub(crate) async fn unmute(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<MuteThreadParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    ThreadMute::delete(&state.db, &body.did, &body.params.uri).await?;
+
+    Ok(ok_simple())
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct SearchMineParams {
+    #[validate(length(min = 1, max = 256))]
+    pub q: String,
+    /// `post`, drafts included, and `comment`; both when empty.
+    pub types: Vec<String>,
+    pub cursor: Option<String>,
+    #[validate(range(min = 1, max = OWN_SEARCH_MAX))]
+    pub limit: u64,
+    pub timestamp: i64,
+}
+
+impl Default for SearchMineParams {
+    fn default() -> Self {
+        Self {
+            q: String::new(),
+            types: vec![],
+            cursor: None,
+            limit: 20,
+            timestamp: 0,
+        }
+    }
+}
+
+impl SignedParam for SearchMineParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Search the caller's own posts, drafts and comments, hidden ones
+/// included. Each hit has a snippet and a `status` of `draft`, `hidden` or
+/// `published`.
+#[utoipa::path(post, path = "/api/post/search_mine")]
+pub(crate) async fn search_mine(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<SearchMineParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+    if !state
+        .caches
+        .allow_own_search(&body.did, OWN_SEARCHES_PER_MINUTE)
+        .await
+    {
+        return Err(AppError::ValidateFailed(
+            "too many searches, try again in a minute".to_string(),
+        ));
+    }
+
+    let params = body.params;
+    let offset = params
+        .cursor
+        .as_ref()
+        .and_then(|cursor| cursor.parse::<u64>().ok())
+        .unwrap_or(0);
+    let (sql, values) =
+        search::build_own_search(&body.did, &params.q, &params.types, offset, params.limit)?
+            .build_sqlx(PostgresQueryBuilder);
+    let hits: Vec<OwnHitRow> = db::fetch_all(&state.db, &sql, values)
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+
+    let results = hits
+        .iter()
+        .map(|hit| {
+            json!({
+                "type": hit.hit_type,
+                "uri": hit.uri,
+                "score": hit.score,
+                "status": hit.status(),
+                "title": hit.title,
+                "snippet": search::snippet(&hit.text, &params.q),
+                "created": hit.created,
+            })
+        })
+        .collect::<Vec<_>>();
+    let result = if hits.len() as u64 == params.limit {
+        json!({
+            "cursor": (offset + params.limit).to_string(),
+            "results": results
+        })
+    } else {
+        json!({
+            "results": results
+        })
+    };
+    Ok(ok(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detail_by_rkey() {
+        // handle resolution failure
+        let resolved = json!({"error": "InvalidRequest", "message": "Unable to resolve handle"});
+        assert!(matches!(did_of_handle(&resolved), Err(AppError::NotFound)));
+        let resolved = json!({"did": "did:ckb:alice"});
+        assert_eq!(did_of_handle(&resolved).unwrap(), "did:ckb:alice");
+
+        // ambiguous rkey
+        let sql = build_rkey_lookup(1, "3mbn_wjd").to_string(PostgresQueryBuilder);
+        assert!(sql.contains("\"section_id\" = 1"));
+        assert!(sql.contains("LIKE E'at://%/app.bbs.post/3mbn\\\\_wjd'"));
+        assert!(matches!(unique_uri(vec![]), Err(AppError::NotFound)));
+        assert_eq!(unique_uri(vec!["at://a".to_string()]).unwrap(), "at://a");
+        assert!(matches!(
+            unique_uri(vec!["at://a".to_string(), "at://b".to_string()]),
+            Err(AppError::ValidateFailed(_))
+        ));
+    }
+
+    #[test]
+    fn like_cursors_keep_the_uri_whole() {
+        let (created, uri) =
+            like_cursor("1700000000000001:at://did:ckb:bob/app.bbs.like/3ke6kg3wk2222").unwrap();
+        assert_eq!(created.timestamp_micros(), 1_700_000_000_000_001);
+        assert_eq!(uri, "at://did:ckb:bob/app.bbs.like/3ke6kg3wk2222");
+        assert!(like_cursor("soon:at://did:ckb:bob/app.bbs.like/1").is_none());
+        assert!(like_cursor("1700000000000001").is_none());
+    }
+
+    #[test]
+    fn commented_feed_pages_through_interleaved_replies() {
+        let at = |secs: i64| -> chrono::DateTime<chrono::Local> {
+            chrono::DateTime::from_timestamp(1_700_000_000 + secs, 0)
+                .unwrap()
+                .into()
+        };
+        // the user's comments and replies across three posts
+        let comments = [(9, "post1"), (8, "post2"), (4, "post1"), (1, "post3")]
+            .map(|(secs, post)| (at(secs), format!("comment on {post}")));
+        let replies = [
+            (10, "post3"),
+            (7, "post1"),
+            (6, "post2"),
+            (5, "post2"),
+            (2, "post1"),
+        ]
+        .map(|(secs, post)| (at(secs), format!("reply on {post}")));
+        // what the two queries return after a cursor
+        let after = |rows: &[(chrono::DateTime<chrono::Local>, String)],
+                     cursor: Option<i64>,
+                     limit: usize| {
+            rows.iter()
+                .filter(|(created, _)| cursor.is_none_or(|c| created.timestamp() < c))
+                .take(limit)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let mut cursor = None;
+        let mut pages = vec![];
+        loop {
+            let page = newest_of(
+                after(&comments, cursor, 3),
+                after(&replies, cursor, 3),
+                |(created, _)| *created,
+                3,
+            );
+            let Some((last, _)) = page.last() else {
+                break;
+            };
+            cursor = Some(last.timestamp());
+            pages.push(page.into_iter().map(|(_, item)| item).collect::<Vec<_>>());
+        }
+        assert_eq!(
+            pages,
+            [
+                vec!["reply on post3", "comment on post1", "comment on post2"],
+                vec!["reply on post1", "reply on post2", "reply on post2"],
+                vec!["comment on post1", "reply on post1", "comment on post3"],
+            ]
+        );
+    }
+
+    #[test]
+    fn drafts_listed_only_for_their_author() {
+        let alice = Some("did:ckb:alice".to_string());
+        let query = PostQuery::default();
+        let filter = query.draft_filter(&None).unwrap().unwrap();
+        let sql = sea_query::Query::select()
+            .and_where(filter)
+            .to_string(PostgresQueryBuilder);
+        assert!(sql.contains("\"post\".\"is_draft\" = FALSE"));
+
+        let query = PostQuery {
+            repo: alice.clone(),
+            viewer: alice.clone(),
+            include_drafts: true,
+            ..Default::default()
+        };
+        assert!(query.draft_filter(&alice).unwrap().is_none());
+        // naming her as the viewer is not her session
+        assert!(query.draft_filter(&None).is_err());
+        assert!(
+            query
+                .draft_filter(&Some("did:ckb:bob".to_string()))
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn listing_drafts_takes_the_session_of_the_repo() {
+        let state = AppView {
+            pds: crate::api::mock_pds().await,
+            ..AppView::for_tests(
+                sqlx::postgres::PgPoolOptions::new()
+                    .acquire_timeout(std::time::Duration::from_millis(100))
+                    .connect_lazy("postgres://127.0.0.1:9/bbs")
+                    .unwrap(),
+            )
+        };
+        let list_as = |token: Option<&str>| {
+            list(
+                State(state.clone()),
+                token.map(|did| TypedHeader(Authorization::bearer(did).unwrap())),
+                Valid(Json(PostQuery {
+                    repo: Some("did:ckb:alice".to_string()),
+                    viewer: Some("did:ckb:alice".to_string()),
+                    include_drafts: true,
+                    ..Default::default()
+                })),
+            )
+        };
+        assert!(matches!(
+            list_as(None).await,
+            Err(AppError::ValidateFailed(e)) if e == "include_drafts requires a session of repo"
+        ));
+        assert!(matches!(
+            list_as(Some("did:ckb:bob")).await,
+            Err(AppError::ValidateFailed(e)) if e == "token does not belong to did"
+        ));
+        // alice gets past the check and on to the unreachable database
+        assert!(!matches!(
+            list_as(Some("did:ckb:alice")).await,
+            Err(AppError::ValidateFailed(_))
+        ));
+    }
+
+    #[test]
+    fn top_lists_pinned_posts_not_staff_posts() {
+        // A member's pinned post is top; an admin's unpinned announcement is not.
+        let query = TopQuery {
+            section_id: "1".to_string(),
+            ..Default::default()
+        };
+        let sql = build_top(&query, 1).to_string(PostgresQueryBuilder);
+        assert!(sql.contains("\"post\".\"is_top\" = TRUE"));
+        assert!(!sql.contains("\"post\".\"is_announcement\" = TRUE"));
+        assert!(sql.contains("LIMIT 10"));
+
+        let query = TopQuery {
+            section_id: "1".to_string(),
+            authored_by_staff: true,
+            ..Default::default()
+        };
+        let sql = build_top(&query, 1).to_string(PostgresQueryBuilder);
+        assert!(sql.contains("\"post\".\"is_announcement\" = TRUE"));
+        assert!(!sql.contains("\"post\".\"is_top\" = TRUE"));
+    }
+
+    #[test]
+    fn top_pages_by_created_and_uri() {
+        let query = TopQuery {
+            section_id: "1".to_string(),
+            cursor: Some("at://did:ckb:alice/app.bbs.post/3mbnwjdssbc27".to_string()),
+            limit: 50,
+            ..Default::default()
+        };
+        assert!(query.validate().is_ok());
+        let sql = build_top(&query, 1).to_string(PostgresQueryBuilder);
+        assert!(sql.contains("(\"post\".\"created\", \"post\".\"uri\") < (select"));
+        assert!(
+            sql.ends_with("ORDER BY \"post\".\"created\" DESC, \"post\".\"uri\" DESC LIMIT 50")
+        );
+        let query = TopQuery {
+            limit: 51,
+            ..Default::default()
+        };
+        assert!(query.validate().is_err());
+    }
+
+    #[test]
+    fn thread_replies_in_one_query() {
+        let comments = vec![
+            "at://did:ckb:alice/app.bbs.comment/1".to_string(),
+            "at://did:ckb:bob/app.bbs.comment/2".to_string(),
+        ];
+        let sql = build_thread_replies(comments, None, 3).to_string(PostgresQueryBuilder);
+        assert!(sql.starts_with("SELECT * FROM (SELECT \"reply\".\"uri\""));
+        assert!(sql.contains(
+            "row_number() over (partition by \"reply\".\"comment\" order by \"reply\".\"created\") AS \"position\""
+        ));
+        assert!(sql.contains(
+            "\"reply\".\"comment\" IN ('at://did:ckb:alice/app.bbs.comment/1', 'at://did:ckb:bob/app.bbs.comment/2')"
+        ));
+        assert!(sql.ends_with(") AS \"reply\" WHERE \"position\" <= 3 ORDER BY \"created\" ASC"));
+
+        let query = ThreadQuery {
+            uri: "at://did:ckb:alice/app.bbs.post/1".to_string(),
+            cursor: Some("1700000000".to_string()),
+            comment_limit: 5,
+            ..Default::default()
+        };
+        let sql = build_thread_comments(&query).to_string(PostgresQueryBuilder);
+        assert!(sql.contains("\"comment\".\"created\" > to_timestamp(1700000000)"));
+        assert!(sql.ends_with("LIMIT 5"));
+        let query = ThreadQuery {
+            reply_limit: 21,
+            ..Default::default()
+        };
+        assert!(query.validate().is_err());
+    }
+
+    #[test]
+    fn drafts_are_filtered_and_newest_first() {
+        let query = DraftQuery {
+            repo: "did:ckb:alice".to_string(),
+            section_id: Some("3".to_string()),
+            q: Some("rust".to_string()),
+            ..Default::default()
+        };
+        let sql = build_drafts(&query)
+            .column(Asterisk)
+            .order_by("updated", Order::Desc)
+            .to_string(PostgresQueryBuilder);
+        assert!(sql.contains("\"post\".\"repo\" = 'did:ckb:alice'"));
+        assert!(sql.contains("\"draft\".\"repo\" = 'did:ckb:alice'"));
+        assert!(sql.ends_with(
+            ") AS \"draft\" WHERE \"id\" = 3 AND \"title\" LIKE '%rust%' ORDER BY \"updated\" DESC"
+        ));
+        assert!(!sql.contains("comment"));
+
+        let sql = build_drafts(&DraftQuery {
+            section_id: Some("three".to_string()),
+            ..Default::default()
+        })
+        .expr(Func::count(Expr::col("uri")))
+        .to_string(PostgresQueryBuilder);
+        assert!(sql.starts_with("SELECT COUNT(\"uri\") FROM (SELECT"));
+        assert!(sql.ends_with(") AS \"draft\""));
+    }
+
+    #[tokio::test]
+    async fn drafts_are_listed_to_their_repo_only() {
+        use common_x::restful::axum::{Router, routing::get};
+
+        let router = Router::new().route(
+            "/xrpc/com.atproto.server.getSession",
+            get(|| async { Json(json!({ "did": "did:ckb:alice" })) }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pds = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(common_x::restful::axum::serve(listener, router));
+        let state = AppView {
+            pds,
+            ..AppView::for_tests(
+                sqlx::postgres::PgPoolOptions::new()
+                    .acquire_timeout(std::time::Duration::from_millis(100))
+                    .connect_lazy("postgres://127.0.0.1:9/bbs")
+                    .unwrap(),
+            )
+        };
+
+        let list = |repo: &str| {
+            list_draft(
+                State(state.clone()),
+                TypedHeader(Authorization::bearer("alice-token").unwrap()),
+                Valid(Json(DraftQuery {
+                    repo: repo.to_string(),
+                    ..Default::default()
+                })),
+            )
+        };
+        assert!(matches!(
+            list("did:ckb:bob").await,
+            Err(AppError::ValidateFailed(e)) if e == "token does not belong to did"
+        ));
+        // alice gets past the check and on to the unreachable database
+        assert!(!matches!(
+            list("did:ckb:alice").await,
+            Err(AppError::ValidateFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn drafts_are_shown_to_their_repo_only() {
+        let db = crate::db::test_pool().await;
+        let uri = Draft::new_uri("did:ckb:alice");
+        Draft::upsert(&db, &uri, "did:ckb:alice", None, Some("secret"), None)
+            .await
+            .unwrap();
+        let state = AppView {
+            pds: crate::api::mock_pds().await,
+            ..AppView::for_tests(db)
+        };
+        let detail_as = |token: &str| {
+            detail_draft(
+                State(state.clone()),
+                TypedHeader(Authorization::bearer(token).unwrap()),
+                ValidQuery(DetailQuery {
+                    uri: uri.clone(),
+                    ..Default::default()
+                }),
+            )
+        };
+
+        assert!(matches!(
+            detail_as("did:ckb:bob").await,
+            Err(AppError::ValidateFailed(e)) if e == "token does not belong to did"
+        ));
+        let draft = data(detail_as("did:ckb:alice").await.unwrap()).await;
+        assert_eq!(draft["title"], "secret");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn hidden_posts_take_the_session_of_their_author() {
+        let db = crate::db::test_pool().await;
+        let uri = format!(
+            "at://did:ckb:alice/app.bbs.post/{}",
+            chrono::Local::now().timestamp_micros()
+        );
+        let post = json!({ "section_id": "1", "title": "t", "text": "t" });
+        Post::insert(&db, "did:ckb:alice", &post, &uri, "bafy")
+            .await
+            .unwrap();
+        Post::update_tag(&db, &uri, None, None, Some(true), Some("spam".to_string()))
+            .await
+            .unwrap();
+        let state = AppView {
+            pds: crate::api::mock_pds().await,
+            ..AppView::for_tests(db.clone())
+        };
+        let detail_as = |token: Option<&str>| {
+            detail(
+                State(state.clone()),
+                token.map(|did| TypedHeader(Authorization::bearer(did).unwrap())),
+                ValidQuery(DetailQuery {
+                    uri: uri.clone(),
+                    viewer: Some("did:ckb:alice".to_string()),
+                    ..Default::default()
+                }),
+            )
+        };
+
+        // naming the author as the viewer is not her session
+        assert!(matches!(
+            detail_as(None).await,
+            Err(AppError::IsDisabled(_))
+        ));
+        assert!(matches!(
+            detail_as(Some("did:ckb:bob")).await,
+            Err(AppError::ValidateFailed(e)) if e == "token does not belong to did"
+        ));
+        let post = data(detail_as(Some("did:ckb:alice")).await.unwrap()).await;
+        assert_eq!(post["reasons_for_disabled"], "spam");
+        Post::delete(&db, &uri).await.unwrap();
+    }
+
+    async fn data(response: impl IntoResponse) -> Value {
+        let body = response.into_response().into_body();
+        let bytes = common_x::restful::axum::body::to_bytes(body, usize::MAX)
+            .await
+            .unwrap();
+        let mut body: Value = serde_json::from_slice(&bytes).unwrap();
+        body["data"].take()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn list_pages_are_full_despite_hidden_posts() {
+        let db = crate::db::test_pool().await;
+        let (section_id,): (i32,) =
+            sqlx::query_as("INSERT INTO \"section\" (\"name\") VALUES ('pages') RETURNING \"id\"")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+
+        // seven posts a second apart, the 2nd, 4th and 5th hidden
+        let suffix = chrono::Local::now().timestamp_micros();
+        let since = chrono::Local::now().timestamp() - 60;
+        let uri = |i: i64| format!("at://did:ckb:alice/app.bbs.post/{suffix}{i}");
+        for i in 1..=7 {
+            let post = json!({
+                "section_id": section_id.to_string(), "title": "t", "text": "t",
+                "created": chrono::Local::now().to_rfc3339(),
+            });
+            Post::insert(&db, "did:ckb:alice", &post, &uri(i), "bafy")
+                .await
+                .unwrap();
+            sqlx::query("UPDATE \"post\" SET \"updated\" = to_timestamp($1) WHERE \"uri\" = $2")
+                .bind((since + i) as f64)
+                .bind(uri(i))
+                .execute(&db)
+                .await
+                .unwrap();
+            if [2, 4, 5].contains(&i) {
+                Post::update_tag(&db, &uri(i), None, None, Some(true), None)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let state = AppView {
+            pds: crate::api::mock_pds().await,
+            ..AppView::for_tests(db.clone())
+        };
+        let pages = |viewer: Option<&str>| {
+            let state = state.clone();
+            let viewer = viewer.map(str::to_string);
+            async move {
+                let mut pages = vec![];
+                let mut cursor = None;
+                loop {
+                    let page = data(
+                        list(
+                            State(state.clone()),
+                            viewer
+                                .as_deref()
+                                .map(|did| TypedHeader(Authorization::bearer(did).unwrap())),
+                            Valid(Json(PostQuery {
+                                section_id: Some(section_id.to_string()),
+                                cursor: cursor.clone(),
+                                limit: Some(2),
+                                viewer: viewer.clone(),
+                                ..Default::default()
+                            })),
+                        )
+                        .await,
+                    )
+                    .await;
+                    let uris = page["posts"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|post| post["uri"].as_str().unwrap().to_string())
+                        .collect::<Vec<_>>();
+                    if uris.is_empty() {
+                        return pages;
+                    }
+                    pages.push(uris);
+                    cursor = page["cursor"].as_str().map(str::to_string);
+                }
+            }
+        };
+        let anonymous = pages(None).await;
+        let author = pages(Some("did:ckb:alice")).await;
+        let stranger = pages(Some("did:ckb:bob")).await;
+        for i in 1..=7 {
+            Post::delete(&db, &uri(i)).await.unwrap();
+        }
+
+        assert_eq!(anonymous, [vec![uri(7), uri(6)], vec![uri(3), uri(1)]]);
+        assert_eq!(stranger, anonymous);
+        assert_eq!(
+            author,
+            [
+                vec![uri(7), uri(6)],
+                vec![uri(5), uri(4)],
+                vec![uri(3), uri(2)],
+                vec![uri(1)],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn thread_matches_composed_endpoints() {
+        let db = crate::db::test_pool().await;
+        let (section_id,): (i32,) =
+            sqlx::query_as("INSERT INTO \"section\" (\"name\") VALUES ('thread') RETURNING \"id\"")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+
+        // a post, three comments of which one is hidden, and three replies
+        // to the first comment
+        let section_id = section_id.to_string();
+        let suffix = chrono::Local::now().timestamp_micros();
+        let created = |i: i64| {
+            (chrono::Local::now() - chrono::Duration::minutes(10) + chrono::Duration::seconds(i))
+                .to_rfc3339()
+        };
+        let uri = format!("at://did:ckb:alice/app.bbs.post/{suffix}");
+        let post =
+            json!({ "section_id": section_id, "title": "t", "text": "t", "created": created(0) });
+        Post::insert(&db, "did:ckb:alice", &post, &uri, "bafy")
+            .await
+            .unwrap();
+        let comment_uri = |i: i64| format!("at://did:ckb:bob/app.bbs.comment/{suffix}{i}");
+        for i in 1..=3 {
+            let comment = json!({
+                "section_id": section_id, "post": uri, "text": "c", "created": created(i)
+            });
+            Comment::insert(&db, "did:ckb:bob", &comment, &comment_uri(i), "bafy")
+                .await
+                .unwrap();
+        }
+        Comment::update_tag(&db, &comment_uri(2), Some(true), Some("spam".to_string()))
+            .await
+            .unwrap();
+        for i in 1..=3 {
+            let reply = json!({
+                "section_id": section_id, "post": uri, "comment": comment_uri(1),
+                "to": "did:ckb:bob", "text": "r", "created": created(10 + i)
+            });
+            let reply_uri = format!("at://did:ckb:carol/app.bbs.reply/{suffix}{i}");
+            Reply::insert(&db, "did:ckb:carol", &reply, &reply_uri, "bafy")
+                .await
+                .unwrap();
+        }
+
+        let state = AppView::for_tests(db.clone());
+
+        let mut thread = data(
+            thread(
+                State(state.clone()),
+                None,
+                ValidQuery(ThreadQuery {
+                    uri: uri.clone(),
+                    ..Default::default()
+                }),
+            )
+            .await,
+        )
+        .await;
+        let mut detail = data(
+            detail(
+                State(state.clone()),
+                None,
+                ValidQuery(DetailQuery {
+                    uri: uri.clone(),
+                    ..Default::default()
+                }),
+            )
+            .await,
+        )
+        .await;
+        let comments = data(
+            crate::api::comment::list(
+                State(state.clone()),
+                None,
+                Valid(Json(crate::api::comment::CommentQuery {
+                    post: uri.clone(),
+                    ..Default::default()
+                })),
+            )
+            .await,
+        )
+        .await;
+        Post::delete(&db, &uri).await.unwrap();
+
+        // every call counts a visit
+        for post in [&mut thread["post"], &mut detail] {
+            post.as_object_mut().unwrap().remove("visited_count");
+            post.as_object_mut().unwrap().remove("visited");
+        }
+        assert_eq!(thread["post"], detail);
+        assert_eq!(thread["comments"], comments["comments"]);
+        assert_eq!(thread["comments"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            thread["comments"][0]["replies"]["replies"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(thread["cursor"].is_string());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn muted_threads_stay_visible_without_notifications() {
+        let db = crate::db::test_pool().await;
+        let (section_id,): (i32,) =
+            sqlx::query_as("INSERT INTO \"section\" (\"name\") VALUES ('muted') RETURNING \"id\"")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        let section_id = section_id.to_string();
+        let uri = "at://did:ckb:alice/app.bbs.post/3kmute";
+        let post = json!({
+            "section_id": section_id, "title": "t", "text": "t",
+            "created": chrono::Local::now().to_rfc3339(),
+        });
+        Post::insert(&db, "did:ckb:alice", &post, uri, "bafy")
+            .await
+            .unwrap();
+        ThreadMute::insert(&db, "did:ckb:alice", uri).await.unwrap();
+
+        let comment = |i: i64| {
+            let comment = json!({
+                "section_id": section_id, "post": uri, "text": "c",
+                "created": chrono::Local::now().to_rfc3339(),
+            });
+            let comment_uri = format!("at://did:ckb:bob/app.bbs.comment/3kmute{i}");
+            let db = db.clone();
+            async move {
+                Comment::insert(&db, "did:ckb:bob", &comment, &comment_uri, "bafy")
+                    .await
+                    .unwrap();
+            }
+        };
+        let notified = async || -> i64 {
+            sqlx::query_scalar("SELECT count(*) FROM \"notify\" WHERE \"receiver\" = $1")
+                .bind("did:ckb:alice")
+                .fetch_one(&db)
+                .await
+                .unwrap()
+        };
+        comment(1).await;
+        assert_eq!(notified().await, 0);
+
+        let thread = data(
+            thread(
+                State(AppView {
+                    pds: crate::api::mock_pds().await,
+                    ..AppView::for_tests(db.clone())
+                }),
+                Some(TypedHeader(Authorization::bearer("did:ckb:alice").unwrap())),
+                ValidQuery(ThreadQuery {
+                    uri: uri.to_string(),
+                    viewer: Some("did:ckb:alice".to_string()),
+                    ..Default::default()
+                }),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(thread["post"]["muted"], true);
+        assert_eq!(thread["comments"].as_array().unwrap().len(), 1);
+
+        ThreadMute::delete(&db, "did:ckb:alice", uri).await.unwrap();
+        comment(2).await;
+        assert_eq!(notified().await, 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn archived_sections_stay_readable() {
+        let db = crate::db::test_pool().await;
+        let (section_id,): (i32,) = sqlx::query_as(
+            "INSERT INTO \"section\" (\"name\") VALUES ('archive') RETURNING \"id\"",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        let uri = format!(
+            "at://did:ckb:alice/app.bbs.post/{}",
+            chrono::Local::now().timestamp_micros()
+        );
+        let post = json!({ "section_id": section_id.to_string(), "title": "t", "text": "t" });
+        Post::insert(&db, "did:ckb:alice", &post, &uri, "bafy")
+            .await
+            .unwrap();
+        sqlx::query("UPDATE \"section\" SET \"is_archived\" = true WHERE \"id\" = $1")
+            .bind(section_id)
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let state = AppView::for_tests(db.clone());
+        let section = data(
+            crate::api::section::detail(
+                State(state.clone()),
+                ValidQuery(crate::api::section::SectionIdQuery {
+                    id: section_id,
+                    viewer: None,
+                }),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(section["archived"], true);
+        assert_eq!(section["is_disabled"], false);
+
+        let (sql, values) = Post::build_select(None)
+            .and_where(Expr::col((Post::Table, Post::Uri)).eq(&uri))
+            .build_sqlx(PostgresQueryBuilder);
+        let row: PostRow = db::fetch_one(&db, &sql, values).await.unwrap();
+        let view = PostView::build(row, Value::Null, "0".to_string());
+        assert!(view.archived);
+        assert!(!view.is_disabled);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn membership_is_shown_alike_in_every_view() {
+        use crate::{
+            api::{
+                notify::{self, NotifyQuery},
+                repo::{self, ProfileQuery},
+            },
+            lexicon::{
+                notify::{Notify, NotifyRow, NotifyType},
+                whitelist::Whitelist,
+            },
+        };
+
+        let db = crate::db::test_pool().await;
+        let (section_id,): (i32,) = sqlx::query_as(
+            "INSERT INTO \"section\" (\"name\") VALUES ('membership') RETURNING \"id\"",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+
+        let suffix = chrono::Local::now().timestamp_micros();
+        let member = format!("did:ckb:member{suffix}");
+        let reader = format!("did:ckb:reader{suffix}");
+        Whitelist::insert(&db, &member).await.unwrap();
+        let post = json!({
+            "section_id": section_id.to_string(), "title": "t", "text": "t",
+            "created": chrono::Local::now().to_rfc3339(),
+        });
+        let uri = format!("at://{member}/app.bbs.post/{suffix}");
+        Post::insert(&db, &member, &post, &uri, "bafy")
+            .await
+            .unwrap();
+        Notify::insert(
+            &db,
+            &NotifyRow {
+                id: 0,
+                title: "New Like".to_string(),
+                sender: member.clone(),
+                receiver: reader.clone(),
+                n_type: NotifyType::NewLike as i32,
+                target_uri: uri.clone(),
+                amount: 0,
+                readed: None,
+                created: chrono::Local::now(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let state = AppView::for_tests(db.clone());
+        let views = async || {
+            let profile = data(
+                repo::profile(
+                    State(state.clone()),
+                    ValidQuery(ProfileQuery {
+                        repo: member.clone(),
+                    }),
+                )
+                .await,
+            )
+            .await;
+            let mut posts = data(
+                list(
+                    State(state.clone()),
+                    None,
+                    Valid(Json(PostQuery {
+                        section_id: Some(section_id.to_string()),
+                        ..Default::default()
+                    })),
+                )
+                .await,
+            )
+            .await;
+            let mut notifies = data(
+                notify::list(
+                    State(state.clone()),
+                    None,
+                    Valid(Json(NotifyQuery {
+                        repo: reader.clone(),
+                        ..Default::default()
+                    })),
+                )
+                .await,
+            )
+            .await;
+            [
+                profile,
+                posts["posts"][0]["author"].take(),
+                notifies["notifies"][0]["sender"].take(),
+            ]
+            .map(|author| (author["membership"].clone(), author["highlight"].clone()))
+        };
+
+        let listed = (json!({ "whitelisted": true }), json!("beta"));
+        assert_eq!(views().await, [listed.clone(), listed.clone(), listed]);
+
+        Whitelist::delete(&db, &member).await.unwrap();
+        state.caches.invalidate_whitelist(&[member.clone()]).await;
+        let unlisted = (json!({ "whitelisted": false }), Value::Null);
+        assert_eq!(
+            views().await,
+            [unlisted.clone(), unlisted.clone(), unlisted]
+        );
+
+        Post::delete(&db, &uri).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn participants_are_counted_once() {
+        let db = crate::db::test_pool().await;
+        let (section_id,): (i32,) = sqlx::query_as(
+            "INSERT INTO \"section\" (\"name\") VALUES ('participants') RETURNING \"id\"",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+
+        let section_id = section_id.to_string();
+        let suffix = chrono::Local::now().timestamp_micros();
+        let did = |name: &str| format!("did:ckb:{name}{suffix}");
+        let created = |i: i64| {
+            (chrono::Local::now() - chrono::Duration::minutes(10) + chrono::Duration::seconds(i))
+                .to_rfc3339()
+        };
+        let uri = format!("at://{}/app.bbs.post/{suffix}", did("alice"));
+        let post =
+            json!({ "section_id": section_id, "title": "t", "text": "t", "created": created(0) });
+        Post::insert(&db, &did("alice"), &post, &uri, "bafy")
+            .await
+            .unwrap();
+        // carol comments first, then bob, dave (hidden) and alice on their own post
+        let comment_uri = |name: &str| format!("at://{}/app.bbs.comment/{suffix}", did(name));
+        for (i, name) in ["carol", "bob", "dave", "alice"].into_iter().enumerate() {
+            let comment = json!({
+                "section_id": section_id, "post": uri, "text": "c", "created": created(1 + i as i64)
+            });
+            Comment::insert(&db, &did(name), &comment, &comment_uri(name), "bafy")
+                .await
+                .unwrap();
+        }
+        Comment::update_tag(
+            &db,
+            &comment_uri("dave"),
+            Some(true),
+            Some("spam".to_string()),
+        )
+        .await
+        .unwrap();
+        // bob also replies to carol, and erin only replies
+        for (i, name) in ["bob", "erin"].into_iter().enumerate() {
+            let reply = json!({
+                "section_id": section_id, "post": uri, "comment": comment_uri("carol"),
+                "to": did("carol"), "text": "r", "created": created(10 + i as i64)
+            });
+            let reply_uri = format!("at://{}/app.bbs.reply/{suffix}", did(name));
+            Reply::insert(&db, &did(name), &reply, &reply_uri, "bafy")
+                .await
+                .unwrap();
+        }
+
+        let (sql, values) = build_detail(&uri, None).build_sqlx(PostgresQueryBuilder);
+        let row: PostRow = db::fetch_one(&db, &sql, values).await.unwrap();
+        assert_eq!(row.participant_count, 4);
+
+        let state = AppView {
+            pds: crate::api::mock_pds().await,
+            ..AppView::for_tests(db.clone())
+        };
+        let participants = async |viewer: Option<String>, limit: u64| {
+            let page = data(
+                participants(
+                    State(state.clone()),
+                    viewer
+                        .as_deref()
+                        .map(|did| TypedHeader(Authorization::bearer(did).unwrap())),
+                    ValidQuery(ParticipantsQuery {
+                        uri: uri.clone(),
+                        viewer,
+                        limit,
+                    }),
+                )
+                .await,
+            )
+            .await;
+            page["participants"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|author| author["did"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let everyone = participants(None, 10).await;
+        let first_two = participants(None, 2).await;
+        let for_dave = participants(Some(did("dave")), 10).await;
+        Post::delete(&db, &uri).await.unwrap();
+
+        assert_eq!(
+            everyone,
+            [did("alice"), did("carol"), did("bob"), did("erin")]
+        );
+        assert_eq!(first_two, [did("alice"), did("carol")]);
+        // hidden content counts for its author
+        assert_eq!(
+            for_dave,
+            [
+                did("alice"),
+                did("carol"),
+                did("bob"),
+                did("dave"),
+                did("erin")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn edits_after_a_report_are_shown_to_moderators() {
+        use crate::{
+            api::comment::{self, CommentQuery},
+            lexicon::operation::{ActionType, Operation, OperationRow},
+        };
+
+        let db = crate::db::test_pool().await;
+        let suffix = chrono::Local::now().timestamp_micros();
+        let moderator = format!("did:ckb:moderator{suffix}");
+        let (section_id,): (i32,) = sqlx::query_as(
+            "INSERT INTO \"section\" (\"name\", \"owner\") VALUES ('reports', $1) RETURNING \"id\"",
+        )
+        .bind(&moderator)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+
+        let section_id = section_id.to_string();
+        let uri = format!("at://did:ckb:alice/app.bbs.post/{suffix}");
+        let post = json!({
+            "section_id": section_id, "title": "t", "text": "t",
+            "created": chrono::Local::now().to_rfc3339(),
+        });
+        Post::insert(&db, "did:ckb:alice", &post, &uri, "bafy")
+            .await
+            .unwrap();
+        let comment_uri = format!("at://did:ckb:bob/app.bbs.comment/{suffix}");
+        let edit = |edited: chrono::DateTime<chrono::Local>| {
+            let comment = json!({
+                "section_id": section_id, "post": uri, "text": "c",
+                "edited": edited.to_rfc3339(),
+                "created": (chrono::Local::now() - chrono::Duration::minutes(10)).to_rfc3339(),
+            });
+            let (db, comment_uri) = (db.clone(), comment_uri.clone());
+            async move {
+                Comment::insert(&db, "did:ckb:bob", &comment, &comment_uri, "bafy")
+                    .await
+                    .unwrap();
+            }
+        };
+        let operation = |action_type: ActionType| {
+            Operation::insert(
+                &db,
+                OperationRow {
+                    id: 0,
+                    section_id: section_id.parse().unwrap(),
+                    operator: "did:ckb:bob".to_string(),
+                    action_type: action_type as i32,
+                    action: String::new(),
+                    message: String::new(),
+                    target: comment_uri.clone(),
+                    created: chrono::Local::now(),
+                },
+            )
+        };
+
+        let state = AppView {
+            pds: crate::api::mock_pds().await,
+            ..AppView::for_tests(db.clone())
+        };
+        let bearer = |viewer: &Option<String>| {
+            viewer
+                .as_deref()
+                .map(|did| TypedHeader(Authorization::bearer(did).unwrap()))
+        };
+        let flags = async || {
+            let mut flags = vec![];
+            for viewer in [
+                None,
+                Some("did:ckb:bob".to_string()),
+                Some(moderator.clone()),
+            ] {
+                let mut thread = data(
+                    thread(
+                        State(state.clone()),
+                        bearer(&viewer),
+                        ValidQuery(ThreadQuery {
+                            uri: uri.clone(),
+                            viewer: viewer.clone(),
+                            ..Default::default()
+                        }),
+                    )
+                    .await,
+                )
+                .await;
+                let mut list = data(
+                    comment::list(
+                        State(state.clone()),
+                        bearer(&viewer),
+                        Valid(Json(CommentQuery {
+                            post: uri.clone(),
+                            viewer,
+                            ..Default::default()
+                        })),
+                    )
+                    .await,
+                )
+                .await;
+                let flag = thread["comments"][0]["edited_after_report"].take();
+                assert_eq!(list["comments"][0]["edited_after_report"].take(), flag);
+                flags.push(flag);
+            }
+            flags
+        };
+
+        // edited, then reported
+        edit(chrono::Local::now() - chrono::Duration::minutes(1)).await;
+        operation(ActionType::FlagContent).await.unwrap();
+        assert_eq!(flags().await, [Value::Null, Value::Null, json!(false)]);
+
+        edit(chrono::Local::now() + chrono::Duration::seconds(1)).await;
+        assert_eq!(flags().await, [Value::Null, Value::Null, json!(true)]);
+
+        // a moderator dealt with the report
+        operation(ActionType::EnableComment).await.unwrap();
+        assert_eq!(flags().await, [Value::Null, Value::Null, json!(false)]);
+
+        Post::delete(&db, &uri).await.unwrap();
+    }
+}
@@ -2,60 +2,122 @@ use axum_extra::{
     TypedHeader,
     headers::{Authorization, authorization::Bearer},
 };
-use color_eyre::eyre::{OptionExt, eyre};
+use color_eyre::{
+    Result,
+    eyre::{OptionExt, eyre},
+};
 use common_x::restful::{
     axum::{Json, extract::State, response::IntoResponse},
     ok, ok_simple,
 };
+use sea_query::{Expr, ExprTrait, IntoIden, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use utoipa::ToSchema;
+use validator::Validate;
 
 use crate::{
     AppView,
-    atproto::{NSID_COMMENT, NSID_LIKE, NSID_POST, NSID_REPLY, direct_writes},
+    api::{build_author, post::build_detail, valid::Valid},
+    atproto::{
+        Collection, NSID_COMMENT, NSID_FOLLOW, NSID_LIKE, NSID_POST, NSID_PROFILE, NSID_REPLY,
+        direct_writes, next_tid,
+    },
+    cache::Caches,
+    content_filter::RuleAction,
+    db,
     error::AppError,
     lexicon::{
+        PENDING_REVIEW, ThreadError,
         administrator::Administrator,
-        comment::Comment,
+        comment::{Comment, CommentRow, CommentView},
+        content_rule::ContentRule,
+        follow::Follow,
         like::Like,
-        post::Post,
-        reply::Reply,
-        section::{Section, SectionRow},
+        notify::{Notify, NotifyRow, NotifyType},
+        operation::{ActionType, Operation, OperationRow},
+        post::{Post, PostDraftRow, PostDraftView, PostRow, PostView},
+        profile::Profile,
+        reply::{Reply, ReplyRow, ReplyView},
+        repo_state::RepoState,
+        section::{Section, SectionRow, in_archived_section},
+        section_ban::SectionBan,
+        section_id_of,
         whitelist::Whitelist,
     },
+    limits,
+    quota::Quota,
+    webhook::WebhookPayload,
 };
 
-#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Default, Validate, Serialize, Deserialize, ToSchema)]
 #[serde(default)]
 pub(crate) struct NewRecord {
-    repo: String,
-    rkey: String,
-    value: Value,
-    signing_key: String,
-    ckb_addr: String,
-    root: Value,
+    #[validate(length(min = 1))]
+    pub repo: String,
+    /// Generated by `create` when empty.
+    pub rkey: String,
+    pub value: Value,
+    pub signing_key: String,
+    pub ckb_addr: String,
+    /// The repo commit the write builds on, passed on to `directWrites`.
+    /// `update` checks its `rev` or `cid` against `/api/repo/state`.
+    pub root: Value,
 }
 
+/// Writes the record to the PDS and indexes it. The PDS result carries the
+/// `rkey` of the record, generated when the request left it empty, and the
+/// indexed view of the record under `view` when it could be built.
 #[utoipa::path(post, path = "/api/record/create")]
 pub(crate) async fn create(
     State(state): State<AppView>,
     TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
-    Json(new_record): Json<NewRecord>,
+    Valid(Json(new_record)): Valid<Json<NewRecord>>,
 ) -> Result<impl IntoResponse, AppError> {
+    Ok(ok(write(&state, &auth, new_record).await?))
+}
+
+/// The checks and indexing of `create`, for the flows that write a record
+/// on behalf of its repo and need the PDS result.
+pub(crate) async fn write(
+    state: &AppView,
+    auth: &Authorization<Bearer>,
+    mut new_record: NewRecord,
+) -> Result<Value, AppError> {
     let record_type = new_record
         .value
         .get("$type")
         .map(|t| t.as_str())
         .ok_or_eyre("'$type' must be set")?
         .ok_or_eyre("'$type' must be set")?;
-    if !Whitelist::select_by_did(&state.db, &new_record.repo).await {
-        match record_type {
-            NSID_POST | NSID_REPLY | NSID_COMMENT => {
-                return Err(eyre!("Operation is not allowed!").into());
-            }
-            _ => {}
-        }
+    let collection: Collection = record_type.parse().map_err(AppError::ValidateFailed)?;
+    limits::check_record(&new_record.value, &state.limits)
+        .map_err(|e| AppError::TooLarge(e.to_string()))?;
+    if matches!(
+        collection,
+        Collection::Post | Collection::Reply | Collection::Comment
+    ) && !Whitelist::select_by_did(&state.db, &new_record.repo).await
+    {
+        return Err(eyre!("Operation is not allowed!").into());
+    }
+    if matches!(
+        collection,
+        Collection::Post | Collection::Reply | Collection::Comment
+    ) {
+        check_not_banned(
+            &state.db,
+            &new_record.repo,
+            &new_record.value,
+            chrono::Local::now(),
+        )
+        .await?;
+    }
+    if matches!(
+        collection,
+        Collection::Post | Collection::Reply | Collection::Comment | Collection::Like
+    ) {
+        check_not_archived(&state.db, &state.caches, &new_record.value).await?;
     }
 
     if record_type == NSID_POST {
@@ -82,6 +144,44 @@ pub(crate) async fn create(
         }
     }
 
+    let is_draft = new_record.value["is_draft"].as_bool().unwrap_or(false);
+    if (record_type == NSID_POST && !is_draft) || record_type == NSID_COMMENT {
+        let section_id = new_record.value["section_id"]
+            .as_str()
+            .and_then(|s| s.parse::<i32>().ok())
+            .ok_or_eyre("error in section_id")?;
+        Quota::compute(&state.db, &state.quota, &new_record.repo)
+            .await?
+            .check(section_id, record_type == NSID_POST)
+            .map_err(AppError::ValidateFailed)?;
+    }
+
+    let filtered = match record_type {
+        NSID_POST | NSID_COMMENT | NSID_REPLY if !is_draft => {
+            check_content(state, &new_record.value).await?
+        }
+        _ => None,
+    };
+    if let Some(Filtered {
+        action: RuleAction::Reject,
+        category,
+        ..
+    }) = &filtered
+    {
+        return Err(AppError::ValidateFailed(format!(
+            "content rejected: {category}"
+        )));
+    }
+
+    if new_record.rkey.is_empty() {
+        new_record.rkey = next_tid();
+    } else {
+        check_rkey_unused(&state.db, collection, &new_record.repo, &new_record.rkey).await?;
+    }
+    if let Some(section_id) = check_thread(&state.db, record_type, &new_record).await? {
+        check_may_comment(&state.db, section_id, &new_record.repo).await?;
+    }
+
     let result = direct_writes(
         &state.pds,
         auth.token(),
@@ -97,7 +197,8 @@ pub(crate) async fn create(
         &new_record.root,
     )
     .await
-    .map_err(|e| AppError::RpcFailed(e.to_string()))?;
+    .map_err(AppError::rpc)?;
+    RepoState::record_write(&state.db, &new_record.repo, &result).await;
     let uri = result
         .pointer("/results/0/uri")
         .and_then(|uri| uri.as_str())
@@ -119,17 +220,222 @@ pub(crate) async fn create(
         NSID_LIKE => {
             Like::insert(&state.db, &new_record.repo, &new_record.value, uri, cid).await?;
         }
+        NSID_FOLLOW => {
+            Follow::insert(&state.db, &new_record.repo, &new_record.value, uri, cid).await?;
+        }
+        NSID_PROFILE => {
+            Profile::insert(&state.db, &new_record.repo, &new_record.value, uri, cid).await?;
+        }
         _ => {}
     }
+    let published = published(is_draft, filtered.as_ref());
+    if let Some(filtered) = filtered {
+        apply_content_rule(state, record_type, &new_record.repo, uri, filtered).await?;
+    }
 
-    Ok(ok(result))
+    state.caches.invalidate_author(&new_record.repo).await;
+    if published
+        && let Some(payload) =
+            WebhookPayload::created(record_type, &new_record.repo, uri, &new_record.value)
+    {
+        state.webhooks.emit(payload);
+    }
+
+    let mut result = result.clone();
+    result["rkey"] = json!(new_record.rkey);
+    match indexed_view(state, record_type, &new_record.repo, uri).await {
+        Ok(view) => result["view"] = view,
+        Err(e) => debug!("build indexed view failed: {e}"),
+    }
+
+    Ok(result)
+}
+
+/// The content rule a post, comment or reply matched.
+struct Filtered {
+    rule_id: i32,
+    action: RuleAction,
+    category: String,
+    section_id: i32,
+}
+
+/// Whether new content is shown to everyone and announced to webhooks.
+/// Drafts are not, nor is content shadowed until a moderator displays it,
+/// which `update_tag` announces then.
+fn published(is_draft: bool, filtered: Option<&Filtered>) -> bool {
+    !is_draft && !filtered.is_some_and(|filtered| filtered.action == RuleAction::Shadow)
+}
+
+async fn check_content(state: &AppView, value: &Value) -> Result<Option<Filtered>> {
+    let section_id = value["section_id"]
+        .as_str()
+        .and_then(|s| s.parse::<i32>().ok())
+        .ok_or_eyre("error in section_id")?;
+    let text = format!(
+        "{}\n{}",
+        value["title"].as_str().unwrap_or_default(),
+        value["text"].as_str().unwrap_or_default()
+    );
+    let rules = state
+        .caches
+        .content_rules(ContentRule::ruleset(&state.db))
+        .await?;
+    Ok(rules.evaluate(section_id, &text).map(|rule| Filtered {
+        rule_id: rule.id,
+        action: rule.action,
+        category: rule.category.clone(),
+        section_id,
+    }))
 }
 
+/// Logs flagged content and tells the moderators of its section; shadowed
+/// content is also hidden until a moderator displays it again with
+/// `update_tag`.
+async fn apply_content_rule(
+    state: &AppView,
+    record_type: &str,
+    repo: &str,
+    uri: &str,
+    filtered: Filtered,
+) -> Result<()> {
+    let (action_type, action) = match filtered.action {
+        RuleAction::Flag => (ActionType::FlagContent, "自动标记"),
+        RuleAction::Shadow => (ActionType::ShadowContent, "自动隐藏"),
+        RuleAction::Reject => return Ok(()),
+    };
+    Operation::insert(
+        &state.db,
+        OperationRow {
+            id: 0,
+            section_id: filtered.section_id,
+            operator: repo.to_string(),
+            action_type: action_type as i32,
+            action: action.to_string(),
+            message: format!("{} (rule {})", filtered.category, filtered.rule_id),
+            target: uri.to_string(),
+            created: chrono::Local::now(),
+        },
+    )
+    .await
+    .ok();
+
+    if filtered.action == RuleAction::Shadow {
+        let reasons = Some(format!("{PENDING_REVIEW}{}", filtered.category));
+        match record_type {
+            NSID_POST => Post::update_tag(&state.db, uri, None, None, Some(true), reasons).await?,
+            NSID_COMMENT => Comment::update_tag(&state.db, uri, Some(true), reasons).await?,
+            NSID_REPLY => Reply::update_tag(&state.db, uri, Some(true), reasons).await?,
+            _ => {}
+        }
+    }
+
+    notify_moderators(state, filtered.section_id, repo, uri).await;
+    Ok(())
+}
+
+/// One `ModerationNeeded` notification per moderator of the section and
+/// target, however often it is flagged within the window.
+async fn notify_moderators(state: &AppView, section_id: i32, repo: &str, uri: &str) {
+    let mut moderators = Administrator::all_did(&state.db).await;
+    if let Ok(SectionRow {
+        owner: Some(owner), ..
+    }) = Section::select_by_id(&state.db, section_id).await
+    {
+        if !moderators.contains(&owner) {
+            moderators.push(owner);
+        }
+    }
+    for moderator in moderators {
+        Notify::insert_moderation(
+            &state.db,
+            &NotifyRow {
+                id: 0,
+                title: "Moderation Needed".to_string(),
+                sender: repo.to_string(),
+                receiver: moderator,
+                n_type: NotifyType::ModerationNeeded as i32,
+                target_uri: uri.to_string(),
+                amount: 0,
+                readed: None,
+                created: chrono::Local::now(),
+            },
+        )
+        .await
+        .map_err(|e| error!("notify moderators of {uri} failed: {e}"))
+        .ok();
+    }
+}
+
+/// The view of a freshly indexed record as the author would fetch it, so
+/// clients can render it without another round trip.
+pub(crate) async fn indexed_view(
+    state: &AppView,
+    record_type: &str,
+    repo: &str,
+    uri: &str,
+) -> Result<Value> {
+    let viewer = Some(repo.to_string());
+    let view = match record_type {
+        NSID_POST => {
+            let (sql, values) = build_detail(uri, viewer).build_sqlx(PostgresQueryBuilder);
+            if let Some(row) = db::fetch_optional::<PostRow, _>(&state.db, &sql, values).await? {
+                let author = build_author(state, &row.repo).await;
+                json!(PostView::build(row, author, "0".to_string()))
+            } else {
+                let (sql, values) = Post::build_draft_select()
+                    .and_where(Expr::col((Post::Table, Post::Uri)).eq(uri))
+                    .build_sqlx(PostgresQueryBuilder);
+                let row: PostDraftRow = db::fetch_one(&state.db, &sql, values).await?;
+                let author = build_author(state, &row.repo).await;
+                json!(PostDraftView::build(row, author))
+            }
+        }
+        NSID_COMMENT => {
+            let (sql, values) = Comment::build_select(viewer)
+                .and_where(Expr::col((Comment::Table, Comment::Uri)).eq(uri))
+                .build_sqlx(PostgresQueryBuilder);
+            let row: CommentRow = db::fetch_one(&state.db, &sql, values).await?;
+            let author = build_author(state, &row.repo).await;
+            json!(CommentView::build(
+                row,
+                author,
+                json!({ "replies": [] }),
+                "0".to_string()
+            ))
+        }
+        NSID_REPLY => {
+            let (sql, values) = Reply::build_select(viewer)
+                .and_where(Expr::col((Reply::Table, Reply::Uri)).eq(uri))
+                .build_sqlx(PostgresQueryBuilder);
+            let row: ReplyRow = db::fetch_one(&state.db, &sql, values).await?;
+            let author = build_author(state, &row.repo).await;
+            let to = build_author(state, &row.to).await;
+            json!(ReplyView::build(row, author, to, "0".to_string()))
+        }
+        NSID_LIKE => json!({
+            "uri": uri,
+            "liked": true,
+        }),
+        NSID_FOLLOW => json!({
+            "uri": uri,
+            "following": true,
+        }),
+        // the author cache was just invalidated, so this refills it from the
+        // PDS and later `build_author` calls see the edit right away
+        NSID_PROFILE => build_author(state, repo).await,
+        _ => return Err(eyre!("no view for {record_type}")),
+    };
+    Ok(view)
+}
+
+/// Rewrites the record on the PDS and indexes it. A `root` older than the
+/// latest commit of the repo is refused with a 409 carrying the current
+/// `RepoStateRow`.
 #[utoipa::path(post, path = "/api/record/update")]
 pub(crate) async fn update(
     State(state): State<AppView>,
     TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
-    Json(new_record): Json<NewRecord>,
+    Valid(Json(new_record)): Valid<Json<NewRecord>>,
 ) -> Result<impl IntoResponse, AppError> {
     let record_type = new_record
         .value
@@ -137,6 +443,8 @@ pub(crate) async fn update(
         .map(|t| t.as_str())
         .ok_or_eyre("'$type' must be set")?
         .ok_or_eyre("'$type' must be set")?;
+    limits::check_record(&new_record.value, &state.limits)
+        .map_err(|e| AppError::TooLarge(e.to_string()))?;
     if !Whitelist::select_by_did(&state.db, &new_record.repo).await {
         match record_type {
             NSID_POST | NSID_REPLY | NSID_COMMENT => {
@@ -165,6 +473,11 @@ pub(crate) async fn update(
         }
     }
 
+    if let Some(section_id) = check_thread(&state.db, record_type, &new_record).await? {
+        check_may_comment(&state.db, section_id, &new_record.repo).await?;
+    }
+    check_root(&state.db, &new_record.repo, &new_record.root).await?;
+
     let result = direct_writes(
         &state.pds,
         auth.token(),
@@ -180,7 +493,8 @@ pub(crate) async fn update(
         &new_record.root,
     )
     .await
-    .map_err(|e| AppError::RpcFailed(e.to_string()))?;
+    .map_err(AppError::rpc)?;
+    RepoState::record_write(&state.db, &new_record.repo, &result).await;
     let uri = result
         .pointer("/results/0/uri")
         .and_then(|uri| uri.as_str())
@@ -202,17 +516,169 @@ pub(crate) async fn update(
         NSID_LIKE => {
             Like::insert(&state.db, &new_record.repo, &new_record.value, uri, cid).await?;
         }
+        NSID_FOLLOW => {
+            Follow::insert(&state.db, &new_record.repo, &new_record.value, uri, cid).await?;
+        }
+        NSID_PROFILE => {
+            Profile::insert(&state.db, &new_record.repo, &new_record.value, uri, cid).await?;
+        }
         _ => {}
     }
+    state.caches.invalidate_author(&new_record.repo).await;
+
+    let mut result = result.clone();
+    if record_type == NSID_PROFILE {
+        result["view"] = build_author(&state, &new_record.repo).await;
+    }
 
     Ok(ok(result))
 }
 
+/// Refuses a write on top of a `root` older than the latest commit known
+/// for the repo, so edits from two devices do not silently clobber each
+/// other.
+async fn check_root(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    repo: &str,
+    root: &Value,
+) -> Result<(), AppError> {
+    match RepoState::select(db, repo).await? {
+        Some(current) if current.is_stale(root) => Err(AppError::Conflict(json!(current))),
+        _ => Ok(()),
+    }
+}
+
+/// Refuses new content in archived sections; what is there stays readable.
+async fn check_not_archived(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    caches: &Caches,
+    record: &Value,
+) -> Result<(), AppError> {
+    let sections = caches.sections(Section::all(db)).await?;
+    if in_archived_section(&sections, record) {
+        return Err(AppError::Archived(
+            "the section is archived and takes no new content".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Refuses a comment or reply whose references do not hold together, or
+/// which claims another section than the one of its post. Indexing would
+/// correct the section, but only after the record is on the PDS. Returns
+/// the section of the post for comments and replies.
+async fn check_thread(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    record_type: &str,
+    new_record: &NewRecord,
+) -> Result<Option<i32>, AppError> {
+    let uri = format!(
+        "at://{}/{}/{}",
+        new_record.repo, record_type, new_record.rkey
+    );
+    let section_id = match record_type {
+        NSID_COMMENT => Comment::check_thread(db, &new_record.value, &uri).await,
+        NSID_REPLY => Reply::check_thread(db, &new_record.value, &uri).await,
+        _ => return Ok(None),
+    }
+    .map_err(|e| match e.downcast::<ThreadError>() {
+        Ok(e) => AppError::ValidateFailed(e.to_string()),
+        Err(e) => e.into(),
+    })?;
+    let claimed = section_id_of(&new_record.value).ok();
+    if claimed != Some(section_id) {
+        return Err(AppError::ValidateFailed(
+            ThreadError::SectionMismatch {
+                claimed,
+                actual: section_id,
+            }
+            .to_string(),
+        ));
+    }
+    Ok(Some(section_id))
+}
+
+/// Refuses comments and replies of `repo` in section `section_id` when only
+/// the owner and administrators may comment there. The section is the one
+/// of the post, as `check_thread` found it.
+async fn check_may_comment(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    section_id: i32,
+    repo: &str,
+) -> Result<(), AppError> {
+    let section = Section::select_by_id(db, section_id)
+        .await
+        .map_err(|e| eyre!("error in section_id: {e}"))?;
+    if section.comment_permission > 0
+        && section.owner.as_deref() != Some(repo)
+        && !Administrator::all_did(db)
+            .await
+            .iter()
+            .any(|did| did == repo)
+    {
+        return Err(eyre!("Operation is not allowed!").into());
+    }
+    Ok(())
+}
+
+/// Refuses to create a record under the rkey of one already indexed in the
+/// collection of `repo`, which the PDS would fail confusingly.
+async fn check_rkey_unused(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    collection: Collection,
+    repo: &str,
+    rkey: &str,
+) -> Result<(), AppError> {
+    let table = match collection {
+        Collection::Post => Post::Table.into_iden(),
+        Collection::Comment => Comment::Table.into_iden(),
+        Collection::Reply => Reply::Table.into_iden(),
+        Collection::Like => Like::Table.into_iden(),
+        Collection::Follow => Follow::Table.into_iden(),
+        _ => return Ok(()),
+    };
+    let uri = format!("at://{repo}/{collection}/{rkey}");
+    let (sql, values) = sea_query::Query::select()
+        .expr(Expr::val(1))
+        .from(table)
+        .and_where(Expr::col("uri").eq(uri.as_str()))
+        .build_sqlx(PostgresQueryBuilder);
+    if db::fetch_optional::<(i32,), _>(db, &sql, values)
+        .await?
+        .is_some()
+    {
+        return Err(AppError::ValidateFailed(format!(
+            "rkey `{rkey}` is already used by {uri}, update it instead"
+        )));
+    }
+    Ok(())
+}
+
+/// Refuses content of `repo` in a section it is banned from at `now`.
+async fn check_not_banned(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    repo: &str,
+    record: &Value,
+    now: chrono::DateTime<chrono::Local>,
+) -> Result<(), AppError> {
+    let Ok(section_id) = section_id_of(record) else {
+        return Ok(());
+    };
+    if let Some(ban) = SectionBan::active(db, section_id, repo, now).await? {
+        return Err(AppError::Banned(format!(
+            "banned from the section until {}: {}",
+            ban.until.to_rfc3339(),
+            ban.reason
+        )));
+    }
+    Ok(())
+}
+
 #[utoipa::path(post, path = "/api/record/delete")]
 pub(crate) async fn delete(
     State(state): State<AppView>,
     TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
-    Json(new_record): Json<NewRecord>,
+    Valid(Json(new_record)): Valid<Json<NewRecord>>,
 ) -> Result<impl IntoResponse, AppError> {
     let record_type = new_record
         .value
@@ -233,8 +699,14 @@ pub(crate) async fn delete(
         "at://{}/{}/{}",
         new_record.repo, record_type, new_record.rkey
     );
-    Post::delete(&state.db, &uri).await?;
-    direct_writes(
+    if record_type == NSID_LIKE {
+        Like::delete(&state.db, &[uri]).await?;
+    } else if record_type == NSID_PROFILE {
+        Profile::delete(&state.db, &[new_record.repo.clone()]).await?;
+    } else {
+        Post::delete(&state.db, &uri).await?;
+    }
+    let result = direct_writes(
         &state.pds,
         auth.token(),
         &new_record.repo,
@@ -248,7 +720,374 @@ pub(crate) async fn delete(
         &new_record.root,
     )
     .await
-    .map_err(|e| AppError::RpcFailed(e.to_string()))?;
+    .map_err(AppError::rpc)?;
+    RepoState::record_write(&state.db, &new_record.repo, &result).await;
+    state.caches.invalidate_author(&new_record.repo).await;
 
     Ok(ok_simple())
 }
+
+#[test]
+fn indexed_post_view_matches_detail() {
+    let uri = "at://did:ckb:alice/app.bbs.post/3mbnwjdssbc27";
+    let viewer = Some("did:ckb:alice".to_string());
+    assert_eq!(
+        build_detail(uri, viewer.clone()).to_string(PostgresQueryBuilder),
+        Post::build_select(viewer)
+            .and_where(Expr::col(Post::Uri).eq(uri))
+            .to_string(PostgresQueryBuilder)
+    );
+
+    let now = chrono::Local::now();
+    let row = PostRow {
+        uri: uri.to_string(),
+        cid: "bafyrei".to_string(),
+        repo: "did:ckb:alice".to_string(),
+        title: "hello".to_string(),
+        text: "world".to_string(),
+        is_top: false,
+        is_announcement: false,
+        is_disabled: false,
+        is_draft: false,
+        is_user_pinned: false,
+        reasons_for_disabled: None,
+        visited_count: 0,
+        visited: now,
+        edited: None,
+        updated: now,
+        created: now,
+        section_id: 1,
+        section: "ckb".to_string(),
+        archived: false,
+        comment_count: 0,
+        like_count: 0,
+        participant_count: 1,
+        liked: false,
+        muted: false,
+    };
+    let author = json!("did:ckb:alice");
+    // detail shows the author their own post unredacted
+    assert_eq!(
+        json!(PostView::build(
+            row.clone(),
+            author.clone(),
+            "0".to_string()
+        )),
+        json!(PostView::build(row, author, "0".to_string()).for_viewer(true))
+    );
+}
+
+#[test]
+fn drafts_and_shadowed_content_are_not_published() {
+    let filtered = |action| Filtered {
+        rule_id: 1,
+        action,
+        category: "spam".to_string(),
+        section_id: 1,
+    };
+    assert!(published(false, None));
+    assert!(published(false, Some(&filtered(RuleAction::Flag))));
+    assert!(!published(true, None));
+    assert!(!published(false, Some(&filtered(RuleAction::Shadow))));
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn stale_roots_are_refused() {
+    let db = crate::db::test_pool().await;
+    let repo = "did:ckb:alice";
+    // nothing to compare with before the first commit is seen
+    check_root(&db, repo, &json!({ "rev": "3kaaa" }))
+        .await
+        .unwrap();
+
+    // a write from one device moves the repo on
+    let written = json!({
+        "commit": { "rev": "3kbbb", "cid": "bafyb" },
+        "results": [{ "uri": format!("at://{repo}/app.bbs.post/1"), "cid": "bafyp" }],
+    });
+    RepoState::record_write(&db, repo, &written).await;
+    // a firehose commit replayed late does not move it back
+    RepoState::upsert(&db, repo, "3kaaa", "bafya")
+        .await
+        .unwrap();
+
+    // the other device still writes on top of the old commit
+    match check_root(&db, repo, &json!({ "rev": "3kaaa", "cid": "bafya" })).await {
+        Err(AppError::Conflict(current)) => {
+            assert_eq!(current["rev"], "3kbbb");
+            assert_eq!(current["cid"], "bafyb");
+        }
+        other => panic!("expected a conflict, got {other:?}"),
+    }
+    check_root(&db, repo, &json!({ "rev": "3kbbb", "cid": "bafyb" }))
+        .await
+        .unwrap();
+    // a client ahead of the relayer is not held back
+    check_root(&db, repo, &json!({ "rev": "3kccc", "cid": "bafyc" }))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn archived_sections_refuse_new_content() {
+    use sqlx::{Executor, query};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO section (id, name, is_archived) VALUES (1, 'open', false), (2, 'archive', true)",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    let caches = Caches::new(&Default::default());
+    let post = "at://did:ckb:alice/app.bbs.post/1";
+    let comment = |section_id: &str| json!({ "$type": NSID_COMMENT, "section_id": section_id, "post": post, "text": "c" });
+    let like =
+        |section_id: &str| json!({ "$type": NSID_LIKE, "section_id": section_id, "to": post });
+
+    check_not_archived(&db, &caches, &comment("1"))
+        .await
+        .unwrap();
+    check_not_archived(&db, &caches, &like("1")).await.unwrap();
+    for record in [comment("2"), like("2")] {
+        assert!(matches!(
+            check_not_archived(&db, &caches, &record).await,
+            Err(AppError::Archived(_))
+        ));
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn bans_refuse_content_until_they_end() {
+    use chrono::SubsecRound;
+
+    let db = crate::db::test_pool().await;
+    // as precise as the database keeps it
+    let now = chrono::Local::now().trunc_subsecs(6);
+    let until = now + chrono::Duration::days(7);
+    SectionBan::upsert(&db, 1, "did:ckb:alice", until, "spam", "did:ckb:owner")
+        .await
+        .unwrap();
+    async fn banned(
+        db: &sqlx::PgPool,
+        repo: &str,
+        section_id: &str,
+        at: chrono::DateTime<chrono::Local>,
+    ) -> Option<String> {
+        let post = "at://did:ckb:bob/app.bbs.post/1";
+        let comment =
+            json!({ "$type": NSID_COMMENT, "section_id": section_id, "post": post, "text": "c" });
+        match check_not_banned(db, repo, &comment, at).await {
+            Ok(()) => None,
+            Err(AppError::Banned(e)) => Some(e),
+            Err(e) => panic!("{e:?}"),
+        }
+    }
+
+    let e = banned(&db, "did:ckb:alice", "1", now).await.unwrap();
+    assert!(e.contains(&until.to_rfc3339()), "{e}");
+    assert!(e.ends_with(": spam"), "{e}");
+    assert!(banned(&db, "did:ckb:alice", "2", now).await.is_none());
+    assert!(banned(&db, "did:ckb:bob", "1", now).await.is_none());
+    // the ban ends at `until` on its own
+    assert!(
+        banned(
+            &db,
+            "did:ckb:alice",
+            "1",
+            until - chrono::Duration::milliseconds(1)
+        )
+        .await
+        .is_some()
+    );
+    assert!(banned(&db, "did:ckb:alice", "1", until).await.is_none());
+
+    // lifting ends it now but keeps it for the notification
+    assert!(
+        SectionBan::lift(&db, 1, "did:ckb:alice", now)
+            .await
+            .unwrap()
+    );
+    assert!(banned(&db, "did:ckb:alice", "1", now).await.is_none());
+    assert!(
+        !SectionBan::lift(&db, 1, "did:ckb:alice", now)
+            .await
+            .unwrap()
+    );
+    let ban = SectionBan::select(&db, 1, "did:ckb:alice")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((ban.until, ban.reason.as_str()), (now, "spam"));
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn taken_rkeys_are_refused() {
+    use sqlx::{Executor, query};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO \"like\" (uri, cid, repo, section_id, \"to\") VALUES ('at://did:ckb:alice/app.bbs.like/3kabc', 'bafyl', 'did:ckb:alice', 1, 'at://did:ckb:bob/app.bbs.post/1')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+
+    let e = check_rkey_unused(&db, Collection::Like, "did:ckb:alice", "3kabc")
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&e, AppError::ValidateFailed(msg) if msg.contains("`3kabc`")),
+        "{e:?}"
+    );
+    check_rkey_unused(&db, Collection::Like, "did:ckb:bob", "3kabc")
+        .await
+        .unwrap();
+    check_rkey_unused(&db, Collection::Like, "did:ckb:alice", "3kabd")
+        .await
+        .unwrap();
+    // collections without a local table are left to the PDS
+    check_rkey_unused(&db, Collection::Profile, "did:ckb:alice", "self")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn flags_on_one_target_notify_each_moderator_once() {
+    use sqlx::{Executor, query};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO section (id, name, owner) VALUES (1, 'General', 'did:ckb:owner')",
+        "INSERT INTO administrator (did, permission) VALUES ('did:ckb:admin', 0), ('did:ckb:quiet', 0)",
+        "INSERT INTO thread_mute (did, post_uri) VALUES ('did:ckb:quiet', 'at://did:ckb:bob/app.bbs.post/3kabc')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    let state = AppView::for_tests(db.clone());
+    let uri = "at://did:ckb:bob/app.bbs.post/3kabc";
+    for (rule_id, category) in [(1, "spam"), (2, "abuse"), (1, "spam")] {
+        let filtered = Filtered {
+            rule_id,
+            action: RuleAction::Flag,
+            category: category.to_string(),
+            section_id: 1,
+        };
+        apply_content_rule(&state, NSID_POST, "did:ckb:bob", uri, filtered)
+            .await
+            .unwrap();
+    }
+
+    // the moderator who muted the thread is left out
+    let notifies: Vec<(String, i32, String)> =
+        sqlx::query_as("SELECT receiver, n_type, target_uri FROM notify ORDER BY receiver")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    let moderation_needed = NotifyType::ModerationNeeded as i32;
+    assert_eq!(
+        notifies,
+        [
+            (
+                "did:ckb:admin".to_string(),
+                moderation_needed,
+                uri.to_string()
+            ),
+            (
+                "did:ckb:owner".to_string(),
+                moderation_needed,
+                uri.to_string()
+            ),
+        ]
+    );
+
+    let (sql, values) = Operation::build_open_flags(uri).build_sqlx(PostgresQueryBuilder);
+    let flags: Vec<(String, i64)> = db::fetch_all(&db, &sql, values).await.unwrap();
+    assert_eq!(
+        flags,
+        [
+            ("abuse (rule 2)".to_string(), 1),
+            ("spam (rule 1)".to_string(), 2),
+        ]
+    );
+    assert_eq!(flags.iter().map(|(_, count)| count).sum::<i64>(), 3);
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn announcement_only_sections_take_comments_from_moderators() {
+    use sqlx::{Executor, query};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO section (id, name, owner, permission, comment_permission) VALUES (1, 'News', 'did:ckb:owner', 1, 1), (2, 'General', 'did:ckb:other', 0, 0)",
+        "INSERT INTO administrator (did, permission) VALUES ('did:ckb:admin', 0)",
+        "INSERT INTO post (uri, cid, repo, section_id, title, text) VALUES ('at://did:ckb:owner/app.bbs.post/1', 'bafyp1', 'did:ckb:owner', 1, 'news', 't'), ('at://did:ckb:other/app.bbs.post/2', 'bafyp2', 'did:ckb:other', 2, 'general', 't')",
+        "INSERT INTO comment (uri, cid, repo, section_id, post, text) VALUES ('at://did:ckb:owner/app.bbs.comment/1', 'bafyc', 'did:ckb:owner', 1, 'at://did:ckb:owner/app.bbs.post/1', 'c')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    let news = "at://did:ckb:owner/app.bbs.post/1";
+    let general = "at://did:ckb:other/app.bbs.post/2";
+    let comment = |repo: &str, post: &str, section_id: &str| NewRecord {
+        repo: repo.to_string(),
+        rkey: "3kcomment".to_string(),
+        value: json!({ "$type": NSID_COMMENT, "section_id": section_id, "post": post }),
+        ..Default::default()
+    };
+    let write = async |record_type: &str, new_record: NewRecord| match check_thread(
+        &db,
+        record_type,
+        &new_record,
+    )
+    .await?
+    {
+        Some(section_id) => check_may_comment(&db, section_id, &new_record.repo).await,
+        None => Ok(()),
+    };
+
+    // the member is turned away, the owner and administrators are not
+    assert!(
+        write(NSID_COMMENT, comment("did:ckb:member", news, "1"))
+            .await
+            .is_err()
+    );
+    assert!(
+        write(NSID_COMMENT, comment("did:ckb:owner", news, "1"))
+            .await
+            .is_ok()
+    );
+    assert!(
+        write(NSID_COMMENT, comment("did:ckb:admin", news, "1"))
+            .await
+            .is_ok()
+    );
+    assert!(
+        write(NSID_COMMENT, comment("did:ckb:member", general, "2"))
+            .await
+            .is_ok()
+    );
+    // claiming the open section does not get the member in
+    assert!(matches!(
+        write(NSID_COMMENT, comment("did:ckb:member", news, "2")).await,
+        Err(AppError::ValidateFailed(_))
+    ));
+
+    let reply = |repo: &str| NewRecord {
+        repo: repo.to_string(),
+        rkey: "3kreply".to_string(),
+        value: json!({
+            "$type": NSID_REPLY,
+            "section_id": "1",
+            "post": news,
+            "comment": "at://did:ckb:owner/app.bbs.comment/1",
+            "to": "did:ckb:owner",
+        }),
+        ..Default::default()
+    };
+    assert!(write(NSID_REPLY, reply("did:ckb:member")).await.is_err());
+    assert!(write(NSID_REPLY, reply("did:ckb:owner")).await.is_ok());
+}
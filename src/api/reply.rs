@@ -1,3 +1,7 @@
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
 use color_eyre::eyre::eyre;
 use common_x::restful::{
     axum::{Json, extract::State, response::IntoResponse},
@@ -7,17 +11,21 @@ use sea_query::{BinOper, Expr, ExprTrait, Func, Order, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
 use serde::Deserialize;
 use serde_json::{Value, json};
-use sqlx::query_as_with;
 use utoipa::ToSchema;
 use validator::Validate;
 
 use crate::{
     AppView,
-    api::{ToTimestamp, build_author},
+    api::{
+        ToTimestamp, build_author, is_moderator, is_privileged, valid::Valid, verified_viewer,
+        visible_to,
+    },
     atproto::NSID_REPLY,
+    db,
     error::AppError,
     lexicon::{
         administrator::Administrator,
+        reasons_for_viewer,
         reply::{Reply, ReplyRow, ReplySampleRow, ReplyView},
         section::Section,
     },
@@ -28,10 +36,14 @@ use crate::{
 #[serde(default)]
 pub(crate) struct ReplyQuery {
     pub post: Option<String>,
+    #[validate(length(min = 1))]
     pub comment: String,
     pub to: Option<String>,
     pub cursor: Option<String>,
-    pub limit: u64,
+    /// Defaults to `pagination.reply_list` of the config, capped at its `max`.
+    #[validate(range(min = 1))]
+    pub limit: Option<u64>,
+    /// Only counts with a bearer token of the viewer's PDS session.
     pub viewer: Option<String>,
 }
 
@@ -42,7 +54,7 @@ impl Default for ReplyQuery {
             comment: String::new(),
             to: None,
             cursor: Default::default(),
-            limit: 2,
+            limit: None,
             viewer: None,
         }
     }
@@ -51,9 +63,16 @@ impl Default for ReplyQuery {
 #[utoipa::path(post, path = "/api/reply/list")]
 pub(crate) async fn list(
     State(state): State<AppView>,
-    Json(query): Json<ReplyQuery>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Valid(Json(query)): Valid<Json<ReplyQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
-    let result = list_reply(&state, query).await?;
+    let query = ReplyQuery {
+        viewer: verified_viewer(&state.pds, &query.viewer, auth).await?,
+        ..query
+    };
+    let limit = state.pagination.reply_list.resolve(query.limit);
+    let mut result = list_reply(&state, query).await?;
+    result["limit"] = json!(limit);
     Ok(ok(result))
 }
 
@@ -67,6 +86,8 @@ pub(crate) struct ReplyPageQuery {
     pub page: u64,
     #[validate(range(min = 1))]
     pub per_page: u64,
+    /// Only counts with a bearer token of the viewer's PDS session.
+    pub viewer: Option<String>,
 }
 
 impl Default for ReplyPageQuery {
@@ -77,6 +98,7 @@ impl Default for ReplyPageQuery {
             page: 1,
             per_page: 20,
             q: Default::default(),
+            viewer: None,
         }
     }
 }
@@ -84,11 +106,13 @@ impl Default for ReplyPageQuery {
 #[utoipa::path(post, path = "/api/reply/page")]
 pub(crate) async fn page(
     State(state): State<AppView>,
-    Json(query): Json<ReplyPageQuery>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Valid(Json(query)): Valid<Json<ReplyPageQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
-    query
-        .validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+    let query = ReplyPageQuery {
+        viewer: verified_viewer(&state.pds, &query.viewer, auth).await?,
+        ..query
+    };
     let result = page_reply(&state, query).await?;
     Ok(ok(result))
 }
@@ -132,13 +156,16 @@ async fn page_reply(state: &AppView, query: ReplyPageQuery) -> Result<Value, App
         .limit(query.per_page)
         .build_sqlx(PostgresQueryBuilder);
 
-    let rows: Vec<ReplySampleRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    let rows: Vec<ReplySampleRow> = db::fetch_all(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
+    let admins = Administrator::all_did(&state.db).await;
     let mut views = vec![];
     for row in rows {
+        let privileged =
+            is_privileged(&query.viewer, &row.repo, row.section_id, &sections, &admins);
         views.push(json!({
             "uri": row.uri,
             "cid": row.cid,
@@ -150,7 +177,11 @@ async fn page_reply(state: &AppView, query: ReplyPageQuery) -> Result<Value, App
             "to": row.to,
             "text": row.text,
             "is_disabled": row.is_disabled,
-            "reasons_for_disabled": row.reasons_for_disabled,
+            "reasons_for_disabled": reasons_for_viewer(
+                row.reasons_for_disabled,
+                row.is_disabled,
+                privileged,
+            ),
             "edited": row.edited,
             "updated": row.updated,
             "created": row.created,
@@ -174,8 +205,7 @@ async fn page_reply(state: &AppView, query: ReplyPageQuery) -> Result<Value, App
                 .map(|q| Expr::col((Reply::Table, Reply::Text)).like(format!("%{q}%"))),
         )
         .build_sqlx(PostgresQueryBuilder);
-    let total: (i64,) = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
+    let total: (i64,) = db::fetch_one(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
@@ -187,93 +217,70 @@ async fn page_reply(state: &AppView, query: ReplyPageQuery) -> Result<Value, App
     }))
 }
 
+/// One page of replies under `query.comment`, oldest first, as
+/// `{ "cursor", "replies" }`; `cursor` is left out of an empty page.
+/// Hidden replies are only kept for privileged viewers. Shared by `list`
+/// and the comment list, which inlines the first replies of every comment.
 pub(crate) async fn list_reply(state: &AppView, query: ReplyQuery) -> Result<Value, AppError> {
-    query
-        .validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
-    let (sql, values) = sea_query::Query::select()
-        .columns([
-            (Reply::Table, Reply::Uri),
-            (Reply::Table, Reply::Cid),
-            (Reply::Table, Reply::Repo),
-            (Reply::Table, Reply::SectionId),
-            (Reply::Table, Reply::Post),
-            (Reply::Table, Reply::Comment),
-            (Reply::Table, Reply::To),
-            (Reply::Table, Reply::Text),
-            (Reply::Table, Reply::IsDisabled),
-            (Reply::Table, Reply::ReasonsForDisabled),
-            (Reply::Table, Reply::Edited),
-            (Reply::Table, Reply::Updated),
-            (Reply::Table, Reply::Created),
-        ])
-        .expr(Expr::cust("(select count(\"like\".\"uri\") from \"like\" where \"like\".\"to\" = \"reply\".\"uri\") as like_count"))
-        .expr(if let Some(viewer) =&query.viewer {
-            Expr::cust(format!("((select count(\"like\".\"uri\") from \"like\" where \"like\".\"repo\" = '{viewer}' and \"like\".\"to\" = \"reply\".\"uri\" ) > 0) as liked"))
-        } else {
-            Expr::cust("false as liked".to_string())
-        })
-        .from(Reply::Table)
+    query.validate().map_err(AppError::invalid)?;
+    let (sql, values) = Reply::build_select(query.viewer.clone())
         .and_where(Expr::col((Reply::Table, Reply::Comment)).eq(&query.comment))
-        .and_where_option(query.post.map(|p| Expr::col((Reply::Table, Reply::Post)).eq(&p)))
+        .and_where(visible_to(
+            &query.viewer,
+            Reply::Table,
+            Reply::IsDisabled,
+            Reply::Repo,
+            Reply::SectionId,
+        ))
+        .and_where_option(
+            query
+                .post
+                .map(|p| Expr::col((Reply::Table, Reply::Post)).eq(&p)),
+        )
         .and_where_option(
-            query.to.map(|t| Expr::col((Reply::Table, Reply::To)).eq(&t)),
+            query
+                .to
+                .map(|t| Expr::col((Reply::Table, Reply::To)).eq(&t)),
+        )
+        .and_where_option(
+            query
+                .cursor
+                .and_then(|cursor| cursor.parse::<i64>().ok())
+                .map(|cursor| {
+                    Expr::col((Reply::Table, Reply::Created)).binary(
+                        BinOper::GreaterThan,
+                        Func::cust(ToTimestamp).args([Expr::val(cursor)]),
+                    )
+                }),
         )
-        .and_where_option(query.cursor.and_then(|cursor| cursor.parse::<i64>().ok()).map(|cursor| {
-            Expr::col((Reply::Table, Reply::Created)).binary(
-                BinOper::GreaterThan,
-                Func::cust(ToTimestamp)
-                    .args([Expr::val(cursor)]),
-            )
-        }))
         .order_by(Reply::Created, Order::Asc)
-        .limit(query.limit)
+        .limit(state.pagination.reply_list.resolve(query.limit))
         .build_sqlx(PostgresQueryBuilder);
 
-    let rows: Vec<ReplyRow> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    let rows: Vec<ReplyRow> = db::fetch_all(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
-    let sections = Section::all(&state.db).await?;
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
     let admins = Administrator::all_did(&state.db).await;
     let mut views = vec![];
     for row in rows {
-        let display = if let Some(viewer) = &query.viewer {
-            &row.repo == viewer
-                || sections
-                    .get(&row.section_id)
-                    .is_some_and(|section| section.owner.as_ref() == Some(viewer))
-                || admins.contains(viewer)
-        } else {
-            false
-        };
-        if !row.is_disabled || display {
-            let tip_count = micro_pay::payment_completed_total(
-                &state.pay_url,
-                &format!("{}/{}", NSID_REPLY, row.uri),
-            )
-            .await
-            .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
-            .unwrap_or(0);
-            views.push(ReplyView {
-                uri: row.uri,
-                cid: row.cid,
-                author: build_author(state, &row.repo).await,
-                post: row.post,
-                comment: row.comment,
-                to: build_author(state, &row.to).await,
-                text: row.text,
-                is_disabled: row.is_disabled,
-                reasons_for_disabled: row.reasons_for_disabled,
-                edited: row.edited,
-                updated: row.updated,
-                created: row.created,
-                like_count: row.like_count.to_string(),
-                tip_count: tip_count.to_string(),
-                liked: row.liked,
-            });
-        }
+        let display = is_privileged(&query.viewer, &row.repo, row.section_id, &sections, &admins);
+        let moderator = is_moderator(&query.viewer, row.section_id, &sections, &admins);
+        let tip_count = micro_pay::payment_completed_total(
+            &state.pay_url,
+            &format!("{}/{}", NSID_REPLY, row.uri),
+        )
+        .await
+        .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
+        .unwrap_or(0);
+        let author = build_author(state, &row.repo).await;
+        let to = build_author(state, &row.to).await;
+        views.push(
+            ReplyView::build(row, author, to, tip_count.to_string())
+                .for_viewer(display)
+                .for_moderator(moderator),
+        );
     }
 
     let cursor = views.last().map(|r| r.created.timestamp());
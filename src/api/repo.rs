@@ -1,47 +1,131 @@
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use color_eyre::eyre::eyre;
 use common_x::restful::{
-    axum::{
-        extract::{Query, State},
-        response::IntoResponse,
-    },
+    axum::{Json, extract::State, response::IntoResponse},
     ok,
 };
-use serde::Deserialize;
-use serde_json::{Value, json};
-use utoipa::IntoParams;
+use sea_query::{Cond, Expr, ExprTrait, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
 use crate::{
-    AppView, api::build_author, atproto::index_query, error::AppError,
-    lexicon::whitelist::Whitelist,
+    AppView,
+    api::{
+        SignedBody, SignedParam, build_author, build_author_with_ckb_addr, payout_ckb_addr,
+        repo_stats,
+        valid::{Valid, ValidQuery},
+        verified_viewer,
+    },
+    atproto::index_query,
+    ckb::parse_ckb_addr,
+    db,
+    error::AppError,
+    lexicon::{
+        follow::{Follow, FollowDidRow},
+        like::Like,
+        notify::Notify,
+        payout_pref::PayoutPref,
+        privacy_pref::PrivacyPref,
+        removed_repo::{RemovedRepo, content_tables},
+        repo_state::RepoState,
+    },
+    quota::Quota,
 };
 
 #[derive(Debug, Default, Validate, Deserialize, IntoParams)]
 #[serde(default)]
 pub struct ProfileQuery {
+    #[validate(length(min = 1))]
     pub repo: String,
 }
 
 #[utoipa::path(get, path = "/api/repo/profile", params(ProfileQuery))]
 pub(crate) async fn profile(
     State(state): State<AppView>,
-    Query(query): Query<ProfileQuery>,
+    ValidQuery(query): ValidQuery<ProfileQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    let mut author = build_author(&state, &query.repo).await;
-    if Whitelist::select_by_did(&state.db, &query.repo).await {
-        author["highlight"] = Value::String("beta".to_owned());
-    }
+    let author = build_author_with_ckb_addr(&state, &query.repo).await;
 
     Ok(ok(author))
 }
 
+#[derive(Debug, Default, Validate, Deserialize, IntoParams)]
+#[serde(default)]
+pub struct StatsQuery {
+    #[validate(length(min = 1))]
+    pub repo: String,
+    /// Sees `likes_given_count` when it is the author; only counts with a
+    /// bearer token of the viewer's PDS session.
+    pub viewer: Option<String>,
+}
+
+/// What the author wrote and the likes and tips it received. Likes are
+/// also given per content type; cached for `repo_stats_ttl_secs`. How many
+/// likes the author gave is only there if they made their likes public, or
+/// for the author themselves.
+#[utoipa::path(get, path = "/api/repo/stats", params(StatsQuery))]
+pub(crate) async fn stats(
+    State(state): State<AppView>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    ValidQuery(query): ValidQuery<StatsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let viewer = verified_viewer(&state.pds, &query.viewer, auth).await?;
+    let stats = repo_stats(&state, &query.repo).await;
+    Ok(ok(stats_for(stats, &query.repo, viewer.as_deref())))
+}
+
+/// Drops `likes_given_count` from the stats of `repo` unless the verified
+/// `viewer` may see it.
+fn stats_for(mut stats: Value, repo: &str, viewer: Option<&str>) -> Value {
+    if stats["likes_public"] != true
+        && viewer != Some(repo)
+        && let Some(stats) = stats.as_object_mut()
+    {
+        stats.remove("likes_given_count");
+    }
+    stats
+}
+
+/// Posting limits of the author, what has been used of them and when they
+/// reset. `record::create` enforces the same numbers.
+#[utoipa::path(get, path = "/api/repo/quota", params(ProfileQuery))]
+pub(crate) async fn quota(
+    State(state): State<AppView>,
+    ValidQuery(query): ValidQuery<ProfileQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let quota = Quota::compute(&state.db, &state.quota, &query.repo).await?;
+
+    Ok(ok(quota))
+}
+
+/// The latest commit of the repo the appview saw, to write records on top
+/// of; `NotFound` until it saw one. Returns `RepoStateRow`.
+#[utoipa::path(get, path = "/api/repo/state", params(ProfileQuery))]
+pub(crate) async fn state(
+    State(state): State<AppView>,
+    ValidQuery(query): ValidQuery<ProfileQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let current = RepoState::select(&state.db, &query.repo)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    Ok(ok(current))
+}
+
 #[utoipa::path(get, path = "/api/repo/login_info", params(ProfileQuery))]
 pub(crate) async fn login_info(
     State(state): State<AppView>,
-    Query(query): Query<ProfileQuery>,
+    ValidQuery(query): ValidQuery<ProfileQuery>,
 ) -> Result<impl IntoResponse, AppError> {
     let first = index_query(&state.pds, &query.repo, "firstItem")
         .await
-        .map_err(|e| AppError::RpcFailed(e.to_string()))?;
+        .map_err(AppError::rpc)?;
     let first = first
         .pointer("/result/result")
         .cloned()
@@ -49,7 +133,7 @@ pub(crate) async fn login_info(
         .ok_or(AppError::RpcFailed(first.to_string()))?;
     let second = index_query(&state.pds, &query.repo, "secondItem")
         .await
-        .map_err(|e| AppError::RpcFailed(e.to_string()))?;
+        .map_err(AppError::rpc)?;
     let second = second
         .pointer("/result/result")
         .cloned()
@@ -57,7 +141,7 @@ pub(crate) async fn login_info(
         .ok_or(AppError::RpcFailed(second.to_string()))?;
     let third = index_query(&state.pds, &query.repo, "thirdItem")
         .await
-        .map_err(|e| AppError::RpcFailed(e.to_string()))?;
+        .map_err(AppError::rpc)?;
     let third = third
         .pointer("/result/result")
         .cloned()
@@ -70,3 +154,381 @@ pub(crate) async fn login_info(
         "thirdItem": third,
     })))
 }
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[serde(default)]
+pub struct FollowQuery {
+    pub repo: String,
+    #[validate(range(min = 1))]
+    pub page: u64,
+    /// Defaults to `pagination.follow_list` of the config, capped at its
+    /// `max`.
+    #[validate(range(min = 1))]
+    pub per_page: Option<u64>,
+}
+
+impl Default for FollowQuery {
+    fn default() -> Self {
+        Self {
+            repo: Default::default(),
+            page: 1,
+            per_page: None,
+        }
+    }
+}
+
+/// One page of `select` rows as authors, and the total count.
+async fn follow_page(
+    state: &AppView,
+    mut select: sea_query::SelectStatement,
+    page: u64,
+    per_page: u64,
+) -> Result<(Vec<Value>, i64), AppError> {
+    let offset = per_page * (page - 1);
+    let (sql, values) = select
+        .offset(offset)
+        .limit(per_page)
+        .build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<FollowDidRow> = db::fetch_all(&state.db, &sql, values)
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+
+    let total = rows.first().map(|row| row.total).unwrap_or_default();
+    let mut authors = vec![];
+    for row in rows {
+        authors.push(build_author(state, &row.did).await);
+    }
+    Ok((authors, total))
+}
+
+#[utoipa::path(get, path = "/api/repo/followers", params(FollowQuery))]
+pub(crate) async fn followers(
+    State(state): State<AppView>,
+    ValidQuery(query): ValidQuery<FollowQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let per_page = state.pagination.follow_list.resolve(query.per_page);
+    let (followers, total) = follow_page(
+        &state,
+        Follow::build_followers(&query.repo),
+        query.page,
+        per_page,
+    )
+    .await?;
+
+    Ok(ok(json!({
+        "followers": followers,
+        "total": total,
+        "page": query.page,
+        "per_page": per_page,
+    })))
+}
+
+#[utoipa::path(get, path = "/api/repo/following", params(FollowQuery))]
+pub(crate) async fn following(
+    State(state): State<AppView>,
+    ValidQuery(query): ValidQuery<FollowQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let per_page = state.pagination.follow_list.resolve(query.per_page);
+    let (following, total) = follow_page(
+        &state,
+        Follow::build_following(&query.repo),
+        query.page,
+        per_page,
+    )
+    .await?;
+
+    Ok(ok(json!({
+        "following": following,
+        "total": total,
+        "page": query.page,
+        "per_page": per_page,
+    })))
+}
+
+/// Must be signed in `RemoveMeParams::confirm`.
+pub const REMOVE_ME_CONFIRMATION: &str = "remove all my data";
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct RemoveMeParams {
+    /// Exactly `remove all my data`.
+    pub confirm: String,
+    pub timestamp: i64,
+}
+
+impl SignedParam for RemoveMeParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Withdraws the signer's content from the appview: posts, comments and
+/// replies are hidden as removed by their author, likes and notifications
+/// are deleted, and the relayer ignores new records of the DID for
+/// `removal.suppress_days`. `restore_me` undoes the hiding within
+/// `removal.grace_days`. Returns the affected rows per table.
+#[utoipa::path(post, path = "/api/repo/remove_me")]
+pub(crate) async fn remove_me(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<RemoveMeParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    if body.params.confirm != REMOVE_ME_CONFIRMATION {
+        return Err(AppError::ValidateFailed(format!(
+            "confirm must be '{REMOVE_ME_CONFIRMATION}'"
+        )));
+    }
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let did = body.did.as_str();
+    let now = chrono::Local::now();
+    let mut counts = Map::new();
+    let mut tx = state.db.begin().await?;
+    for (name, table) in content_tables() {
+        let (sql, values) =
+            RemovedRepo::build_tombstone(table, did).build_sqlx(PostgresQueryBuilder);
+        let result = db::execute(&mut *tx, &sql, values).await?;
+        counts.insert(name.to_string(), json!(result.rows_affected()));
+    }
+    let (sql, values) = sea_query::Query::delete()
+        .from_table(Like::Table)
+        .and_where(Expr::col(Like::Repo).eq(did))
+        .build_sqlx(PostgresQueryBuilder);
+    let result = db::execute(&mut *tx, &sql, values).await?;
+    counts.insert("like".to_string(), json!(result.rows_affected()));
+    let (sql, values) = sea_query::Query::delete()
+        .from_table(Notify::Table)
+        .cond_where(
+            Cond::any()
+                .add(Expr::col(Notify::Sender).eq(did))
+                .add(Expr::col(Notify::Receiver).eq(did)),
+        )
+        .build_sqlx(PostgresQueryBuilder);
+    let result = db::execute(&mut *tx, &sql, values).await?;
+    counts.insert("notify".to_string(), json!(result.rows_affected()));
+    let (sql, values) = RemovedRepo::build_upsert(
+        did,
+        now + chrono::Duration::days(state.removal.grace_days),
+        now + chrono::Duration::days(state.removal.suppress_days),
+    )?
+    .build_sqlx(PostgresQueryBuilder);
+    db::execute(&mut *tx, &sql, values).await?;
+    tx.commit().await?;
+
+    state.caches.invalidate_author(did).await;
+    info!("{did} removed their data: {counts:?}");
+
+    Ok(ok(counts))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct RestoreMeParams {
+    pub timestamp: i64,
+}
+
+impl SignedParam for RestoreMeParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Undoes `remove_me` within its grace window: the hidden content is
+/// shown again and the relayer indexes the DID again. Deleted likes and
+/// notifications stay deleted. Returns the restored rows per table.
+#[utoipa::path(post, path = "/api/repo/restore_me")]
+pub(crate) async fn restore_me(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<RestoreMeParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let did = body.did.as_str();
+    let removed = RemovedRepo::select(&state.db, did)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    if !removed.can_restore(chrono::Local::now()) {
+        return Err(AppError::ValidateFailed(
+            "the grace window to restore has passed".to_string(),
+        ));
+    }
+
+    let mut counts = Map::new();
+    let mut tx = state.db.begin().await?;
+    for (name, table) in content_tables() {
+        let (sql, values) = RemovedRepo::build_restore(table, did).build_sqlx(PostgresQueryBuilder);
+        let result = db::execute(&mut *tx, &sql, values).await?;
+        counts.insert(name.to_string(), json!(result.rows_affected()));
+    }
+    let (sql, values) = sea_query::Query::delete()
+        .from_table(RemovedRepo::Table)
+        .and_where(Expr::col(RemovedRepo::Did).eq(did))
+        .build_sqlx(PostgresQueryBuilder);
+    db::execute(&mut *tx, &sql, values).await?;
+    tx.commit().await?;
+
+    state.caches.invalidate_author(did).await;
+    info!("{did} restored their data: {counts:?}");
+
+    Ok(ok(counts))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct PayoutAddressParams {
+    /// Where tips to the signer are paid; empty to pay the address of
+    /// their DID cell again.
+    pub ckb_addr: String,
+    pub timestamp: i64,
+}
+
+impl SignedParam for PayoutAddressParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Sets the address tips to the signer are paid to, in place of the one
+/// resolved from their DID cell. The address must be of the configured
+/// network. Returns the address now in effect.
+#[utoipa::path(post, path = "/api/repo/payout_address")]
+pub(crate) async fn payout_address(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<PayoutAddressParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let ckb_addr = match body.params.ckb_addr.trim() {
+        "" => None,
+        ckb_addr => Some(
+            parse_ckb_addr(&state.ckb_net, ckb_addr)
+                .map_err(|e| AppError::ValidateFailed(e.to_string()))?,
+        ),
+    };
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let did = body.did.as_str();
+    match &ckb_addr {
+        Some(ckb_addr) => PayoutPref::upsert(&state.db, did, ckb_addr).await?,
+        None => PayoutPref::delete(&state.db, did).await?,
+    }
+    state.caches.invalidate_ckb_addr(did).await;
+    state.caches.invalidate_author(did).await;
+    info!("{did} set their payout address to {ckb_addr:?}");
+
+    let ckb_addr = payout_ckb_addr(&state, did).await.ok();
+    Ok(ok(json!({ "ckb_addr": ckb_addr })))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct LikesPublicParams {
+    /// Whether others may list what the signer liked and see how many.
+    pub likes_public: bool,
+    pub timestamp: i64,
+}
+
+impl SignedParam for LikesPublicParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+/// Makes what the signer liked public, or private again. Private likes are
+/// listed and counted only for the signer.
+#[utoipa::path(post, path = "/api/repo/likes_public")]
+pub(crate) async fn likes_public(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<LikesPublicParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+
+    let did = body.did.as_str();
+    let likes_public = body.params.likes_public;
+    PrivacyPref::upsert(&state.db, did, likes_public).await?;
+    state.caches.invalidate_author(did).await;
+    info!(
+        "{did} made their likes {}",
+        if likes_public { "public" } else { "private" }
+    );
+
+    Ok(ok(json!({ "likes_public": likes_public })))
+}
+
+#[test]
+fn follow_pages_are_capped() {
+    let pagination = crate::config::PaginationConfig::default();
+    assert_eq!(
+        pagination
+            .follow_list
+            .resolve(FollowQuery::default().per_page),
+        20
+    );
+    assert_eq!(pagination.follow_list.resolve(Some(100_000)), 100);
+}
+
+#[test]
+fn likes_given_count_follows_the_preference() {
+    let stats =
+        |likes_public: bool| json!({ "likes_given_count": "3", "likes_public": likes_public });
+    let alice = "did:ckb:alice";
+
+    let private = stats_for(stats(false), alice, Some("did:ckb:bob"));
+    assert_eq!(private.get("likes_given_count"), None);
+    assert_eq!(
+        stats_for(stats(false), alice, None).get("likes_given_count"),
+        None
+    );
+    // the author always sees their own
+    assert_eq!(
+        stats_for(stats(false), alice, Some(alice))["likes_given_count"],
+        "3"
+    );
+
+    assert_eq!(
+        stats_for(stats(true), alice, None)["likes_given_count"],
+        "3"
+    );
+    assert_eq!(
+        stats_for(stats(true), alice, Some("did:ckb:bob"))["likes_given_count"],
+        "3"
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn likes_given_count_takes_the_session_of_the_author() {
+    use common_x::restful::axum::body::to_bytes;
+
+    let db = crate::db::test_pool().await;
+    let state = AppView {
+        pds: crate::api::mock_pds().await,
+        ..AppView::for_tests(db)
+    };
+    let alice = "did:ckb:alice";
+    let stats_as = async |token: Option<&str>| {
+        let response = stats(
+            State(state.clone()),
+            token.map(|did| TypedHeader(Authorization::bearer(did).unwrap())),
+            ValidQuery(StatsQuery {
+                repo: alice.to_string(),
+                viewer: Some(alice.to_string()),
+            }),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut body: Value = serde_json::from_slice(&bytes).unwrap();
+        body["data"].take()
+    };
+
+    // a viewer named without their session is anyone
+    assert_eq!(stats_as(None).await.get("likes_given_count"), None);
+    assert_eq!(stats_as(Some(alice)).await["likes_given_count"], "0");
+}
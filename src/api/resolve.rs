@@ -0,0 +1,467 @@
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use chrono::{DateTime, Local};
+use color_eyre::Result;
+use common_x::restful::{
+    axum::{extract::State, response::IntoResponse},
+    ok,
+};
+use sea_query::{BinOper, Expr, ExprTrait, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sqlx::{Pool, Postgres};
+use utoipa::IntoParams;
+use validator::Validate;
+
+use crate::{
+    AppView,
+    api::{is_privileged, notify::snippet, valid::ValidQuery, verified_viewer},
+    atproto::{Collection, NSID_SECTION},
+    db,
+    error::AppError,
+    lexicon::{
+        administrator::Administrator, comment::Comment, post::Post, reasons_for_viewer,
+        reply::Reply, resolve_uri, section::Section,
+    },
+};
+
+/// A post as notifications and permalinks name it.
+pub(crate) struct PostTarget {
+    pub title: String,
+    pub reasons_for_disabled: Option<String>,
+    pub is_disabled: bool,
+    pub section_id: i32,
+}
+
+/// A comment with its place under its post.
+pub(crate) struct CommentTarget {
+    pub text: String,
+    pub post: String,
+    pub reasons_for_disabled: Option<String>,
+    pub is_disabled: bool,
+    pub section_id: i32,
+    /// Position among the comments of the post, oldest first, from 1.
+    pub index: i64,
+}
+
+/// A reply with its place under its comment.
+pub(crate) struct ReplyTarget {
+    pub text: String,
+    pub comment: String,
+    pub reasons_for_disabled: Option<String>,
+    pub is_disabled: bool,
+    pub section_id: i32,
+    /// Position among the replies of the comment, oldest first, from 1.
+    pub index: i64,
+}
+
+/// Drafts are not targets; only their author can see them.
+pub(crate) async fn post_target(db: &Pool<Postgres>, uri: &str) -> Result<Option<PostTarget>> {
+    let (sql, values) = sea_query::Query::select()
+        .columns([
+            (Post::Table, Post::Title),
+            (Post::Table, Post::ReasonsForDisabled),
+            (Post::Table, Post::IsDisabled),
+            (Post::Table, Post::SectionId),
+        ])
+        .from(Post::Table)
+        .and_where(Expr::col(Post::Uri).eq(uri))
+        .and_where(Expr::col(Post::IsDraft).eq(false))
+        .build_sqlx(PostgresQueryBuilder);
+    let row: Option<(String, Option<String>, bool, i32)> =
+        db::fetch_optional(db, &sql, values).await?;
+    Ok(row.map(
+        |(title, reasons_for_disabled, is_disabled, section_id)| PostTarget {
+            title,
+            reasons_for_disabled,
+            is_disabled,
+            section_id,
+        },
+    ))
+}
+
+pub(crate) async fn comment_target(
+    db: &Pool<Postgres>,
+    uri: &str,
+) -> Result<Option<CommentTarget>> {
+    let (sql, values) = sea_query::Query::select()
+        .columns([
+            (Comment::Table, Comment::Text),
+            (Comment::Table, Comment::Post),
+            (Comment::Table, Comment::Created),
+            (Comment::Table, Comment::ReasonsForDisabled),
+            (Comment::Table, Comment::IsDisabled),
+            (Comment::Table, Comment::SectionId),
+        ])
+        .from(Comment::Table)
+        .and_where(Expr::col(Comment::Uri).eq(uri))
+        .build_sqlx(PostgresQueryBuilder);
+    let row: Option<(String, String, DateTime<Local>, Option<String>, bool, i32)> =
+        db::fetch_optional(db, &sql, values).await?;
+    let Some((text, post, created, reasons_for_disabled, is_disabled, section_id)) = row else {
+        return Ok(None);
+    };
+
+    let (sql, values) = sea_query::Query::select()
+        .expr(Expr::col((Comment::Table, Comment::Uri)).count_distinct())
+        .from(Comment::Table)
+        .and_where(Expr::col((Comment::Table, Comment::Post)).eq(&post))
+        .and_where(
+            Expr::col((Comment::Table, Comment::Created)).binary(BinOper::SmallerThan, created),
+        )
+        .build_sqlx(PostgresQueryBuilder);
+    let (before,): (i64,) = db::fetch_one(db, &sql, values).await?;
+
+    Ok(Some(CommentTarget {
+        text,
+        post,
+        reasons_for_disabled,
+        is_disabled,
+        section_id,
+        index: before + 1,
+    }))
+}
+
+pub(crate) async fn reply_target(db: &Pool<Postgres>, uri: &str) -> Result<Option<ReplyTarget>> {
+    let (sql, values) = sea_query::Query::select()
+        .columns([
+            (Reply::Table, Reply::Text),
+            (Reply::Table, Reply::Comment),
+            (Reply::Table, Reply::Created),
+            (Reply::Table, Reply::ReasonsForDisabled),
+            (Reply::Table, Reply::IsDisabled),
+            (Reply::Table, Reply::SectionId),
+        ])
+        .from(Reply::Table)
+        .and_where(Expr::col(Reply::Uri).eq(uri))
+        .build_sqlx(PostgresQueryBuilder);
+    let row: Option<(String, String, DateTime<Local>, Option<String>, bool, i32)> =
+        db::fetch_optional(db, &sql, values).await?;
+    let Some((text, comment, created, reasons_for_disabled, is_disabled, section_id)) = row else {
+        return Ok(None);
+    };
+
+    let (sql, values) = sea_query::Query::select()
+        .expr(Expr::col((Reply::Table, Reply::Uri)).count_distinct())
+        .from(Reply::Table)
+        .and_where(Expr::col((Reply::Table, Reply::Comment)).eq(&comment))
+        .and_where(Expr::col((Reply::Table, Reply::Created)).binary(BinOper::SmallerThan, created))
+        .build_sqlx(PostgresQueryBuilder);
+    let (before,): (i64,) = db::fetch_one(db, &sql, values).await?;
+
+    Ok(Some(ReplyTarget {
+        text,
+        comment,
+        reasons_for_disabled,
+        is_disabled,
+        section_id,
+        index: before + 1,
+    }))
+}
+
+/// The page, from 1, that shows the `index`th item at `per_page` a page.
+fn page_of(index: i64, per_page: u64) -> u64 {
+    (index.max(1) as u64 - 1) / per_page.max(1) + 1
+}
+
+/// The moderator notes `privileged` viewers get; hidden content is refused
+/// to everyone else rather than named.
+fn reasons_or_hidden(
+    uri: &str,
+    reasons: Option<String>,
+    is_disabled: bool,
+    privileged: bool,
+) -> Result<Option<String>, AppError> {
+    if is_disabled && !privileged {
+        return Err(AppError::IsDisabled(format!("{uri} is hidden")));
+    }
+    Ok(reasons_for_viewer(reasons, is_disabled, privileged))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, IntoParams)]
+#[serde(default)]
+pub struct ResolveQuery {
+    /// The `at://` uri of a post, comment, reply or section, or a
+    /// `app.bbs.section/<id>` notification target.
+    #[validate(length(min = 1))]
+    pub uri: String,
+    /// Page size the pages are counted in; defaults to
+    /// `pagination.comment_list` of the config for comments and to
+    /// `pagination.reply_list` for replies.
+    #[validate(range(min = 1))]
+    pub per_page: Option<u64>,
+    /// Only counts with a bearer token of the viewer's PDS session.
+    pub viewer: Option<String>,
+}
+
+/// What `uri` is and where to show it: its `kind`, the `chain` of parent
+/// uris from the post down, the `index` and `page` of comments and replies
+/// and the title or start of each. Content hidden from `viewer` is a 403;
+/// drafts are not found.
+#[utoipa::path(get, path = "/api/resolve", params(ResolveQuery))]
+pub(crate) async fn resolve(
+    State(state): State<AppView>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    ValidQuery(query): ValidQuery<ResolveQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
+    let admins = Administrator::all_did(&state.db).await;
+    let section = |section_id: i32| {
+        json!({
+            "id": section_id.to_string(),
+            "name": sections.get(&section_id).map(|s| s.name.as_str()),
+        })
+    };
+    let uri = query.uri.as_str();
+
+    let section_id = match uri.strip_prefix(&format!("{NSID_SECTION}/")) {
+        Some(id) => Some(id),
+        None => match resolve_uri(uri) {
+            Ok((_did, NSID_SECTION, rkey)) => Some(rkey),
+            _ => None,
+        },
+    };
+    if let Some(id) = section_id {
+        let section_id: i32 = id.parse().map_err(|_| AppError::NotFound)?;
+        let Some(row) = sections.get(&section_id) else {
+            return Err(AppError::NotFound);
+        };
+        return Ok(ok(json!({
+            "kind": "section",
+            "uri": uri,
+            "chain": [],
+            "section": section(section_id),
+            "description": row.description.as_deref().map(snippet),
+        })));
+    }
+
+    let (did, nsid, _rkey) = resolve_uri(uri).map_err(|_| AppError::NotFound)?;
+    let viewer = verified_viewer(&state.pds, &query.viewer, auth).await?;
+    let privileged = |section_id: i32| is_privileged(&viewer, did, section_id, &sections, &admins);
+    let post_title = async |post: &str| -> Result<Option<String>, AppError> {
+        Ok(post_target(&state.db, post).await?.map(|p| p.title))
+    };
+    let comment_per_page = state.pagination.comment_list.resolve(query.per_page);
+    let reply_per_page = state.pagination.reply_list.resolve(query.per_page);
+
+    let view = match nsid.parse() {
+        Ok(Collection::Post) => {
+            let post = post_target(&state.db, uri)
+                .await?
+                .ok_or(AppError::NotFound)?;
+            let reasons = reasons_or_hidden(
+                uri,
+                post.reasons_for_disabled,
+                post.is_disabled,
+                privileged(post.section_id),
+            )?;
+            json!({
+                "kind": "post",
+                "uri": uri,
+                "chain": [],
+                "section": section(post.section_id),
+                "title": post.title,
+                "reasons_for_disabled": reasons,
+            })
+        }
+        Ok(Collection::Comment) => {
+            let comment = comment_target(&state.db, uri)
+                .await?
+                .ok_or(AppError::NotFound)?;
+            let reasons = reasons_or_hidden(
+                uri,
+                comment.reasons_for_disabled,
+                comment.is_disabled,
+                privileged(comment.section_id),
+            )?;
+            json!({
+                "kind": "comment",
+                "uri": uri,
+                "chain": [&comment.post],
+                "section": section(comment.section_id),
+                "text": snippet(&comment.text),
+                "index": comment.index,
+                "page": page_of(comment.index, comment_per_page),
+                "reasons_for_disabled": reasons,
+                "post": {
+                    "uri": &comment.post,
+                    "title": post_title(&comment.post).await?,
+                },
+            })
+        }
+        Ok(Collection::Reply) => {
+            let reply = reply_target(&state.db, uri)
+                .await?
+                .ok_or(AppError::NotFound)?;
+            let reasons = reasons_or_hidden(
+                uri,
+                reply.reasons_for_disabled,
+                reply.is_disabled,
+                privileged(reply.section_id),
+            )?;
+            let comment = comment_target(&state.db, &reply.comment)
+                .await?
+                .ok_or(AppError::NotFound)?;
+            json!({
+                "kind": "reply",
+                "uri": uri,
+                "chain": [&comment.post, &reply.comment],
+                "section": section(reply.section_id),
+                "text": snippet(&reply.text),
+                "index": reply.index,
+                "page": page_of(reply.index, reply_per_page),
+                "reasons_for_disabled": reasons,
+                "comment": {
+                    "uri": &reply.comment,
+                    "text": snippet(&comment.text),
+                    "index": comment.index,
+                    "page": page_of(comment.index, comment_per_page),
+                },
+                "post": {
+                    "uri": &comment.post,
+                    "title": post_title(&comment.post).await?,
+                },
+            })
+        }
+        _ => return Err(AppError::NotFound),
+    };
+    Ok(ok(view))
+}
+
+#[test]
+fn pages_count_from_one() {
+    assert_eq!(page_of(1, 20), 1);
+    assert_eq!(page_of(20, 20), 1);
+    assert_eq!(page_of(21, 20), 2);
+    assert_eq!(page_of(41, 20), 3);
+    // degenerate input still lands on a page
+    assert_eq!(page_of(0, 20), 1);
+    assert_eq!(page_of(5, 0), 5);
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn every_kind_resolves_to_its_thread() {
+    use common_x::restful::axum::body::to_bytes;
+    use sqlx::{Executor, query};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO section (id, name, description, owner) VALUES (1, 'General', 'Talk about anything', 'did:ckb:owner')",
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft) VALUES
+            ('at://did:ckb:alice/app.bbs.post/p1', 'bafy', 'did:ckb:alice', 1, 'Hello', 't', false),
+            ('at://did:ckb:alice/app.bbs.post/d1', 'bafy', 'did:ckb:alice', 1, 'Secret', 't', true)",
+        "INSERT INTO comment (uri, cid, repo, text, post, section_id, created)
+            SELECT 'at://did:ckb:bob/app.bbs.comment/c' || n, 'bafy', 'did:ckb:bob', 'comment ' || n, 'at://did:ckb:alice/app.bbs.post/p1', 1, now() - (30 - n) * interval '1 minute'
+            FROM generate_series(1, 25) AS n",
+        "INSERT INTO reply (uri, cid, repo, text, post, comment, section_id, is_disabled, reasons_for_disabled, created) VALUES
+            ('at://did:ckb:carol/app.bbs.reply/r1', 'bafy', 'did:ckb:carol', 'first', 'at://did:ckb:alice/app.bbs.post/p1', 'at://did:ckb:bob/app.bbs.comment/c22', 1, false, NULL, now() - interval '2 minutes'),
+            ('at://did:ckb:carol/app.bbs.reply/r2', 'bafy', 'did:ckb:carol', 'second', 'at://did:ckb:alice/app.bbs.post/p1', 'at://did:ckb:bob/app.bbs.comment/c22', 1, true, 'off topic', now() - interval '1 minute')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    let state = AppView {
+        pds: crate::api::mock_pds().await,
+        ..AppView::for_tests(db)
+    };
+    let resolve_as = async |uri: &str, viewer: Option<&str>| -> Result<Value, AppError> {
+        let response = resolve(
+            State(state.clone()),
+            viewer.map(|did| TypedHeader(Authorization::bearer(did).unwrap())),
+            ValidQuery(ResolveQuery {
+                uri: uri.to_string(),
+                per_page: Some(10),
+                viewer: viewer.map(str::to_string),
+            }),
+        )
+        .await?
+        .into_response();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut body: Value = serde_json::from_slice(&bytes).unwrap();
+        Ok(body["data"].take())
+    };
+    let post = "at://did:ckb:alice/app.bbs.post/p1";
+    let comment = "at://did:ckb:bob/app.bbs.comment/c22";
+
+    let view = resolve_as(post, None).await.unwrap();
+    assert_eq!(view["kind"], "post");
+    assert_eq!(view["title"], "Hello");
+    assert_eq!(view["chain"], json!([]));
+    assert_eq!(view["section"], json!({ "id": "1", "name": "General" }));
+
+    let view = resolve_as(comment, None).await.unwrap();
+    assert_eq!(view["kind"], "comment");
+    assert_eq!(view["chain"], json!([post]));
+    assert_eq!(
+        (view["index"].as_i64(), view["page"].as_u64()),
+        (Some(22), Some(3))
+    );
+    assert_eq!(view["post"], json!({ "uri": post, "title": "Hello" }));
+
+    let view = resolve_as("at://did:ckb:carol/app.bbs.reply/r1", None)
+        .await
+        .unwrap();
+    assert_eq!(view["kind"], "reply");
+    assert_eq!(view["chain"], json!([post, comment]));
+    assert_eq!(
+        (view["index"].as_i64(), view["page"].as_u64()),
+        (Some(1), Some(1))
+    );
+    assert_eq!(view["comment"]["text"], "comment 22");
+    assert_eq!(view["comment"]["page"], 3);
+    assert_eq!(view["post"]["title"], "Hello");
+
+    // hidden from everyone but its author and the moderators
+    let hidden = "at://did:ckb:carol/app.bbs.reply/r2";
+    assert!(matches!(
+        resolve_as(hidden, None).await,
+        Err(AppError::IsDisabled(_))
+    ));
+    for viewer in ["did:ckb:carol", "did:ckb:owner"] {
+        let view = resolve_as(hidden, Some(viewer)).await.unwrap();
+        assert_eq!(view["index"], 2);
+        assert_eq!(view["reasons_for_disabled"], "off topic");
+    }
+    // naming the author without their session shows nothing
+    let response = resolve(
+        State(state.clone()),
+        None,
+        ValidQuery(ResolveQuery {
+            uri: hidden.to_string(),
+            per_page: Some(10),
+            viewer: Some("did:ckb:carol".to_string()),
+        }),
+    )
+    .await;
+    assert!(matches!(response, Err(AppError::IsDisabled(_))));
+
+    // drafts are not found, not even by their author
+    assert!(matches!(
+        resolve_as("at://did:ckb:alice/app.bbs.post/d1", Some("did:ckb:alice")).await,
+        Err(AppError::NotFound)
+    ));
+
+    for uri in [
+        "app.bbs.section/1",
+        "at://did:web:bbs.example/app.bbs.section/1",
+    ] {
+        let view = resolve_as(uri, None).await.unwrap();
+        assert_eq!(view["kind"], "section");
+        assert_eq!(view["description"], "Talk about anything");
+    }
+
+    for uri in [
+        "at://did:ckb:alice/app.bbs.post/gone",
+        "at://did:ckb:alice/app.bbs.like/l1",
+        "app.bbs.section/9",
+        "not a uri",
+    ] {
+        assert!(
+            matches!(resolve_as(uri, None).await, Err(AppError::NotFound)),
+            "{uri}"
+        );
+    }
+}
@@ -0,0 +1,608 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use chrono::{DateTime, Local};
+use color_eyre::eyre::eyre;
+use common_x::restful::{
+    axum::{Json, extract::State, response::IntoResponse},
+    ok,
+};
+use regex::Regex;
+use sea_query::{Asterisk, Expr, ExprTrait, Order, PostgresQueryBuilder, UnionType};
+use sea_query_sqlx::SqlxBinder;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::{
+    AppView,
+    api::{build_author, is_privileged, valid::Valid, verified_viewer, visible_to},
+    atproto::{NSID_COMMENT, NSID_POST, NSID_REPLY},
+    db,
+    error::AppError,
+    lexicon::{
+        administrator::Administrator,
+        comment::{Comment, CommentRow, CommentView},
+        draft::Draft,
+        post::{Post, PostDraftRow, PostDraftView, PostRow, PostView},
+        reply::{Reply, ReplyRow, ReplyView},
+        section::Section,
+    },
+    micro_pay,
+};
+
+/// Minimum `pg_trgm` word similarity for a row to count as a hit.
+pub const SEARCH_THRESHOLD: f32 = 0.3;
+
+const SEARCH_TYPES: [&str; 3] = ["post", "comment", "reply"];
+
+/// Most hits `/api/post/search_mine` returns per page.
+pub const OWN_SEARCH_MAX: u64 = 50;
+/// Searches per author and minute of `/api/post/search_mine`.
+pub const OWN_SEARCHES_PER_MINUTE: u32 = 10;
+const OWN_SEARCH_TYPES: [&str; 2] = ["post", "comment"];
+/// Length of the snippets of own search hits, in characters.
+const SNIPPET_CHARS: usize = 120;
+
+static HTML_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+#[derive(Debug, Validate, Deserialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct GlobalSearchQuery {
+    #[validate(length(min = 1))]
+    pub q: String,
+    /// Marks what the viewer liked. Their drafts, and the hidden rows they
+    /// wrote or moderate, are only searched when the bearer token belongs
+    /// to them.
+    pub viewer: Option<String>,
+    pub types: Vec<String>,
+    pub cursor: Option<String>,
+    #[validate(range(min = 1, max = 100))]
+    pub limit: u64,
+}
+
+impl Default for GlobalSearchQuery {
+    fn default() -> Self {
+        Self {
+            q: String::new(),
+            viewer: None,
+            types: vec![],
+            cursor: None,
+            limit: 20,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct SearchHitRow {
+    #[sqlx(rename = "type")]
+    hit_type: String,
+    uri: String,
+    score: f32,
+}
+
+fn score_expr(q: &str, columns: &[&str]) -> Expr {
+    let similarities = columns
+        .iter()
+        .map(|column| format!("word_similarity($1, {column})"))
+        .collect::<Vec<String>>()
+        .join(", ");
+    Expr::cust_with_values(format!("greatest({similarities})"), [q.to_string()])
+}
+
+/// Drafts are only searchable by their author, once `check_session`
+/// proved who they are.
+fn drafts_visible_to(verified: &Option<String>) -> Expr {
+    let published = Expr::col((Post::Table, Post::IsDraft)).eq(false);
+    match verified {
+        Some(viewer) => published.or(Expr::col((Post::Table, Post::Repo)).eq(viewer)),
+        None => published,
+    }
+}
+
+fn build_search(
+    query: &GlobalSearchQuery,
+    verified: &Option<String>,
+) -> Result<sea_query::SelectStatement, AppError> {
+    let types = if query.types.is_empty() {
+        SEARCH_TYPES.to_vec()
+    } else {
+        query.types.iter().map(|t| t.as_str()).collect()
+    };
+
+    let mut selects = vec![];
+    for t in types {
+        let select = match t {
+            "post" => {
+                let score = score_expr(&query.q, &["\"post\".\"title\"", "\"post\".\"text\""]);
+                sea_query::Query::select()
+                    .expr_as(Expr::val("post"), "type")
+                    .column((Post::Table, Post::Uri))
+                    .expr_as(score.clone(), "score")
+                    .column((Post::Table, Post::Created))
+                    .from(Post::Table)
+                    .and_where(drafts_visible_to(verified))
+                    .and_where(visible_to(
+                        verified,
+                        Post::Table,
+                        Post::IsDisabled,
+                        Post::Repo,
+                        Post::SectionId,
+                    ))
+                    .and_where(score.gte(SEARCH_THRESHOLD))
+                    .take()
+            }
+            "comment" => {
+                let score = score_expr(&query.q, &["\"comment\".\"text\""]);
+                sea_query::Query::select()
+                    .expr_as(Expr::val("comment"), "type")
+                    .column((Comment::Table, Comment::Uri))
+                    .expr_as(score.clone(), "score")
+                    .column((Comment::Table, Comment::Created))
+                    .from(Comment::Table)
+                    .and_where(visible_to(
+                        verified,
+                        Comment::Table,
+                        Comment::IsDisabled,
+                        Comment::Repo,
+                        Comment::SectionId,
+                    ))
+                    .and_where(score.gte(SEARCH_THRESHOLD))
+                    .take()
+            }
+            "reply" => {
+                let score = score_expr(&query.q, &["\"reply\".\"text\""]);
+                sea_query::Query::select()
+                    .expr_as(Expr::val("reply"), "type")
+                    .column((Reply::Table, Reply::Uri))
+                    .expr_as(score.clone(), "score")
+                    .column((Reply::Table, Reply::Created))
+                    .from(Reply::Table)
+                    .and_where(visible_to(
+                        verified,
+                        Reply::Table,
+                        Reply::IsDisabled,
+                        Reply::Repo,
+                        Reply::SectionId,
+                    ))
+                    .and_where(score.gte(SEARCH_THRESHOLD))
+                    .take()
+            }
+            _ => {
+                return Err(AppError::ValidateFailed(format!("unsupported type: {t}")));
+            }
+        };
+        selects.push(select);
+    }
+
+    let mut selects = selects.into_iter();
+    let mut union = selects
+        .next()
+        .ok_or(AppError::ValidateFailed("types is empty".to_string()))?;
+    union.unions(selects.map(|s| (UnionType::All, s)));
+
+    let offset = query
+        .cursor
+        .as_ref()
+        .and_then(|cursor| cursor.parse::<u64>().ok())
+        .unwrap_or(0);
+
+    Ok(sea_query::Query::select()
+        .column(Asterisk)
+        .from_subquery(union, "hit")
+        .order_by("score", Order::Desc)
+        .order_by("created", Order::Desc)
+        .order_by("uri", Order::Asc)
+        .offset(offset)
+        .limit(query.limit)
+        .take())
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub(crate) struct OwnHitRow {
+    #[sqlx(rename = "type")]
+    pub hit_type: String,
+    pub uri: String,
+    pub score: f32,
+    pub title: String,
+    pub text: String,
+    pub is_draft: bool,
+    pub is_disabled: bool,
+    pub created: DateTime<Local>,
+}
+
+impl OwnHitRow {
+    pub const fn status(&self) -> &'static str {
+        if self.is_draft {
+            "draft"
+        } else if self.is_disabled {
+            "hidden"
+        } else {
+            "published"
+        }
+    }
+}
+
+/// Posts, drafts and comments of `repo` matching `q`, without the
+/// visibility filters of `build_search`: only their author searches them.
+pub(crate) fn build_own_search(
+    repo: &str,
+    q: &str,
+    types: &[String],
+    offset: u64,
+    limit: u64,
+) -> Result<sea_query::SelectStatement, AppError> {
+    let types = if types.is_empty() {
+        OWN_SEARCH_TYPES.to_vec()
+    } else {
+        types.iter().map(|t| t.as_str()).collect()
+    };
+
+    let mut selects = vec![];
+    for t in types {
+        match t {
+            "post" => {
+                let score = score_expr(q, &["\"post\".\"title\"", "\"post\".\"text\""]);
+                selects.push(
+                    sea_query::Query::select()
+                        .expr_as(Expr::val("post"), "type")
+                        .column((Post::Table, Post::Uri))
+                        .expr_as(score.clone(), "score")
+                        .columns([
+                            (Post::Table, Post::Title),
+                            (Post::Table, Post::Text),
+                            (Post::Table, Post::IsDraft),
+                            (Post::Table, Post::IsDisabled),
+                            (Post::Table, Post::Created),
+                        ])
+                        .from(Post::Table)
+                        .and_where(Expr::col((Post::Table, Post::Repo)).eq(repo))
+                        .and_where(score.gte(SEARCH_THRESHOLD))
+                        .take(),
+                );
+                // drafts that never reached the PDS
+                let score = score_expr(q, &["\"draft\".\"title\"", "\"draft\".\"text\""]);
+                selects.push(
+                    sea_query::Query::select()
+                        .expr_as(Expr::val("post"), "type")
+                        .column((Draft::Table, Draft::Uri))
+                        .expr_as(score.clone(), "score")
+                        .columns([(Draft::Table, Draft::Title), (Draft::Table, Draft::Text)])
+                        .expr_as(Expr::val(true), "is_draft")
+                        .expr_as(Expr::val(false), "is_disabled")
+                        .column((Draft::Table, Draft::Created))
+                        .from(Draft::Table)
+                        .and_where(Expr::col((Draft::Table, Draft::Repo)).eq(repo))
+                        .and_where(score.gte(SEARCH_THRESHOLD))
+                        .take(),
+                );
+            }
+            "comment" => {
+                let score = score_expr(q, &["\"comment\".\"text\""]);
+                selects.push(
+                    sea_query::Query::select()
+                        .expr_as(Expr::val("comment"), "type")
+                        .column((Comment::Table, Comment::Uri))
+                        .expr_as(score.clone(), "score")
+                        .expr_as(Expr::val(""), "title")
+                        .column((Comment::Table, Comment::Text))
+                        .expr_as(Expr::val(false), "is_draft")
+                        .columns([
+                            (Comment::Table, Comment::IsDisabled),
+                            (Comment::Table, Comment::Created),
+                        ])
+                        .from(Comment::Table)
+                        .and_where(Expr::col((Comment::Table, Comment::Repo)).eq(repo))
+                        .and_where(score.gte(SEARCH_THRESHOLD))
+                        .take(),
+                );
+            }
+            _ => {
+                return Err(AppError::ValidateFailed(format!("unsupported type: {t}")));
+            }
+        }
+    }
+
+    let mut selects = selects.into_iter();
+    let mut union = selects
+        .next()
+        .ok_or(AppError::ValidateFailed("types is empty".to_string()))?;
+    union.unions(selects.map(|s| (UnionType::All, s)));
+
+    Ok(sea_query::Query::select()
+        .column(Asterisk)
+        .from_subquery(union, "hit")
+        .order_by("score", Order::Desc)
+        .order_by("created", Order::Desc)
+        .order_by("uri", Order::Asc)
+        .offset(offset)
+        .limit(limit.min(OWN_SEARCH_MAX))
+        .take())
+}
+
+/// About `SNIPPET_CHARS` characters of `text` without markup, around the
+/// first exact match of `q`, or from the start for fuzzy matches.
+pub(crate) fn snippet(text: &str, q: &str) -> String {
+    let plain = HTML_TAG.replace_all(text, " ");
+    let chars = plain.split_whitespace().collect::<Vec<_>>().join(" ");
+    let chars = chars.chars().collect::<Vec<char>>();
+    let fold = |c: &char| c.to_lowercase().next().unwrap_or(*c);
+    let lower = chars.iter().map(fold).collect::<Vec<char>>();
+    let needle = q.trim().chars().map(|c| fold(&c)).collect::<Vec<char>>();
+    let found = (!needle.is_empty())
+        .then(|| lower.windows(needle.len()).position(|w| w == needle))
+        .flatten()
+        .unwrap_or(0);
+
+    let start = found.saturating_sub(SNIPPET_CHARS / 4);
+    let end = (start + SNIPPET_CHARS).min(chars.len());
+    let start = end.saturating_sub(SNIPPET_CHARS).min(start);
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    snippet.extend(&chars[start..end]);
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+#[utoipa::path(post, path = "/api/search/global")]
+pub(crate) async fn global(
+    State(state): State<AppView>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Valid(Json(query)): Valid<Json<GlobalSearchQuery>>,
+) -> Result<impl IntoResponse, AppError> {
+    let verified = verified_viewer(&state.pds, &query.viewer, auth).await?;
+    let (sql, values) = build_search(&query, &verified)?.build_sqlx(PostgresQueryBuilder);
+    let hits: Vec<SearchHitRow> = db::fetch_all(&state.db, &sql, values.clone())
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+
+    let uris_of = |t: &str| {
+        hits.iter()
+            .filter(|h| h.hit_type == t)
+            .map(|h| h.uri.clone())
+            .collect::<Vec<String>>()
+    };
+    let sections = state.caches.sections(Section::all(&state.db)).await?;
+    let admins = Administrator::all_did(&state.db).await;
+    let privileged = |repo: &str, section_id: i32| {
+        is_privileged(&verified, repo, section_id, &sections, &admins)
+    };
+    let mut views: HashMap<String, Value> = HashMap::new();
+
+    let post_uris = uris_of("post");
+    if !post_uris.is_empty() {
+        let (sql, values) = Post::build_select(query.viewer.clone())
+            .and_where(Expr::col((Post::Table, Post::Uri)).is_in(post_uris.clone()))
+            .build_sqlx(PostgresQueryBuilder);
+        let rows: Vec<PostRow> = db::fetch_all(&state.db, &sql, values.clone())
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+        for row in rows {
+            let author = build_author(&state, &row.repo).await;
+            let tip_count = tip_count(&state, NSID_POST, &row.uri).await;
+            let privileged = privileged(&row.repo, row.section_id);
+            views.insert(
+                row.uri.clone(),
+                json!(PostView::build(row, author, tip_count).for_viewer(privileged)),
+            );
+        }
+
+        if let Some(viewer) = &verified {
+            let (sql, values) = Post::build_draft_select()
+                .and_where(Expr::col((Post::Table, Post::Uri)).is_in(post_uris))
+                .and_where(Expr::col((Post::Table, Post::Repo)).eq(viewer))
+                .build_sqlx(PostgresQueryBuilder);
+            let rows: Vec<PostDraftRow> = db::fetch_all(&state.db, &sql, values.clone())
+                .await
+                .map_err(|e| eyre!("exec sql failed: {e}"))?;
+            for row in rows {
+                let author = build_author(&state, &row.repo).await;
+                views.insert(row.uri.clone(), json!(PostDraftView::build(row, author)));
+            }
+        }
+    }
+
+    let comment_uris = uris_of("comment");
+    if !comment_uris.is_empty() {
+        let (sql, values) = Comment::build_select(query.viewer.clone())
+            .and_where(Expr::col((Comment::Table, Comment::Uri)).is_in(comment_uris))
+            .build_sqlx(PostgresQueryBuilder);
+        let rows: Vec<CommentRow> = db::fetch_all(&state.db, &sql, values.clone())
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+        for row in rows {
+            let author = build_author(&state, &row.repo).await;
+            let tip_count = tip_count(&state, NSID_COMMENT, &row.uri).await;
+            let privileged = privileged(&row.repo, row.section_id);
+            views.insert(
+                row.uri.clone(),
+                json!(CommentView::build(row, author, json!({}), tip_count).for_viewer(privileged)),
+            );
+        }
+    }
+
+    let reply_uris = uris_of("reply");
+    if !reply_uris.is_empty() {
+        let (sql, values) = Reply::build_select(query.viewer.clone())
+            .and_where(Expr::col((Reply::Table, Reply::Uri)).is_in(reply_uris))
+            .build_sqlx(PostgresQueryBuilder);
+        let rows: Vec<ReplyRow> = db::fetch_all(&state.db, &sql, values.clone())
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+        for row in rows {
+            let author = build_author(&state, &row.repo).await;
+            let to = build_author(&state, &row.to).await;
+            let tip_count = tip_count(&state, NSID_REPLY, &row.uri).await;
+            let privileged = privileged(&row.repo, row.section_id);
+            views.insert(
+                row.uri.clone(),
+                json!(ReplyView::build(row, author, to, tip_count).for_viewer(privileged)),
+            );
+        }
+    }
+
+    let mut results = vec![];
+    for hit in &hits {
+        if let Some(view) = views.remove(&hit.uri) {
+            results.push(json!({
+                "type": hit.hit_type,
+                "score": hit.score,
+                "view": view,
+            }));
+        }
+    }
+
+    let offset = query
+        .cursor
+        .and_then(|cursor| cursor.parse::<u64>().ok())
+        .unwrap_or(0);
+    let result = if hits.len() as u64 == query.limit {
+        json!({
+            "cursor": (offset + query.limit).to_string(),
+            "results": results
+        })
+    } else {
+        json!({
+            "results": results
+        })
+    };
+    Ok(ok(result))
+}
+
+async fn tip_count(state: &AppView, nsid: &str, uri: &str) -> String {
+    micro_pay::payment_completed_total(&state.pay_url, &format!("{nsid}/{uri}"))
+        .await
+        .map(|r| r.get("total").and_then(|r| r.as_i64()).unwrap_or(0))
+        .unwrap_or(0)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_unions_requested_types() {
+        let query = GlobalSearchQuery {
+            q: "ckb".to_string(),
+            types: vec!["post".to_string(), "reply".to_string()],
+            ..Default::default()
+        };
+        let sql = build_search(&query, &None)
+            .unwrap()
+            .to_string(PostgresQueryBuilder);
+        assert!(sql.contains("FROM \"post\""));
+        assert!(sql.contains("FROM \"reply\""));
+        assert!(!sql.contains("FROM \"comment\""));
+        assert!(sql.contains("UNION ALL"));
+        assert!(sql.contains(&format!(">= {SEARCH_THRESHOLD}")));
+    }
+
+    #[test]
+    fn private_rows_need_a_verified_viewer() {
+        let query = GlobalSearchQuery {
+            q: "ckb".to_string(),
+            viewer: Some("did:ckb:alice".to_string()),
+            types: vec!["post".to_string()],
+            ..Default::default()
+        };
+        let sql = build_search(&query, &None)
+            .unwrap()
+            .to_string(PostgresQueryBuilder);
+        assert!(!sql.contains("did:ckb:alice"));
+
+        let sql = build_search(&query, &query.viewer)
+            .unwrap()
+            .to_string(PostgresQueryBuilder);
+        assert!(sql.contains("\"post\".\"repo\" = 'did:ckb:alice'"));
+        // hidden rows also show to the moderators of their section
+        assert!(sql.contains("\"owner\" = 'did:ckb:alice'"));
+    }
+
+    #[test]
+    fn own_search_has_no_visibility_filters() {
+        let sql = build_own_search("did:ckb:alice", "fee markets", &[], 0, 500)
+            .unwrap()
+            .to_string(PostgresQueryBuilder);
+        assert!(sql.contains("FROM \"post\" WHERE \"post\".\"repo\" = 'did:ckb:alice'"));
+        assert!(sql.contains("FROM \"draft\" WHERE \"draft\".\"repo\" = 'did:ckb:alice'"));
+        assert!(sql.contains("FROM \"comment\" WHERE \"comment\".\"repo\" = 'did:ckb:alice'"));
+        assert!(!sql.contains("FROM \"reply\""));
+        assert!(!sql.contains("\"is_draft\" = FALSE"));
+        assert!(!sql.contains("\"is_disabled\" = FALSE"));
+        assert!(sql.ends_with(&format!("LIMIT {OWN_SEARCH_MAX}")));
+        assert!(build_own_search("did:ckb:alice", "x", &["reply".to_string()], 0, 20).is_err());
+    }
+
+    #[test]
+    fn snippets_center_on_the_match() {
+        let text = format!(
+            "<p>{}</p><p>Fee <b>markets</b> on CKB</p>",
+            "intro ".repeat(40)
+        );
+        let snippet = snippet(&text, "fee markets");
+        assert!(snippet.starts_with('…'));
+        assert!(snippet.contains("Fee markets on CKB"));
+        assert!(snippet.chars().count() <= SNIPPET_CHARS + 2);
+
+        assert_eq!(super::snippet("<p>short</p>", "nothing like it"), "short");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn drafts_are_found_only_by_their_author() {
+        let db = crate::db::test_pool().await;
+
+        let suffix = chrono::Local::now().timestamp_micros();
+        let alice = format!("did:ckb:alice{suffix}");
+        let post = json!({
+            "section_id": "1", "title": "thoughts on fee markets", "text": "t",
+            "is_draft": true, "created": chrono::Local::now().to_rfc3339(),
+        });
+        let post_uri = format!("at://{alice}/app.bbs.post/{suffix}");
+        Post::insert(&db, &alice, &post, &post_uri, "bafy")
+            .await
+            .unwrap();
+        let draft_uri = Draft::new_uri(&alice);
+        Draft::upsert(
+            &db,
+            &draft_uri,
+            &alice,
+            Some(1),
+            Some("fee markets again"),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let search = |repo: String| {
+            let db = db.clone();
+            async move {
+                let (sql, values) = build_own_search(&repo, "fee markets", &[], 0, 20)
+                    .unwrap()
+                    .build_sqlx(PostgresQueryBuilder);
+                let hits: Vec<OwnHitRow> = db::fetch_all(&db, &sql, values).await.unwrap();
+                hits
+            }
+        };
+        let hits = search(alice.clone()).await;
+        let found = |uri: &str| {
+            hits.iter()
+                .find(|hit| hit.uri == uri)
+                .map(|hit| hit.status())
+        };
+        assert_eq!(found(&post_uri), Some("draft"));
+        assert_eq!(found(&draft_uri), Some("draft"));
+
+        let hits = search(format!("did:ckb:bob{suffix}")).await;
+        assert!(hits.is_empty());
+    }
+}
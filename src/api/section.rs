@@ -1,24 +1,29 @@
+use std::{collections::HashMap, sync::Arc};
+
 use color_eyre::eyre::eyre;
 use common_x::restful::{
-    axum::{
-        extract::{Query, State},
-        response::IntoResponse,
-    },
+    axum::{extract::State, response::IntoResponse},
     ok,
 };
 use sea_query::{Expr, ExprTrait, Order, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use sqlx::query_as_with;
-use utoipa::IntoParams;
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
 use crate::{
     AppView,
-    api::build_author,
+    api::{build_author, known_period, period_hours, valid::ValidQuery},
+    db,
     error::AppError,
-    lexicon::section::{Section, SectionRowSample, SectionView},
+    lexicon::{
+        administrator::Administrator,
+        section::{Section, SectionActivity, SectionRowSample, SectionView},
+        section_ban::SectionBan,
+        whitelist::Whitelist,
+    },
+    quota::Quota,
 };
 
 #[derive(Debug, Default, Validate, Deserialize, IntoParams)]
@@ -28,10 +33,24 @@ pub struct SectionQuery {
     pub is_disabled: Option<bool>,
 }
 
+/// The activity of every section over the last 24 hours; sections show
+/// none when computing it fails.
+async fn last_day_activity(state: &AppView) -> Arc<HashMap<i32, SectionActivity>> {
+    let since = chrono::Local::now() - chrono::Duration::hours(24);
+    state
+        .caches
+        .section_activity(Section::activity(&state.db, since))
+        .await
+        .unwrap_or_else(|e| {
+            warn!("compute section activity failed: {e}");
+            Default::default()
+        })
+}
+
 #[utoipa::path(get, path = "/api/section/list", params(SectionQuery))]
 pub(crate) async fn list(
     State(state): State<AppView>,
-    Query(query): Query<SectionQuery>,
+    ValidQuery(query): ValidQuery<SectionQuery>,
 ) -> Result<impl IntoResponse, AppError> {
     let (sql, values) =
         Section::build_select()
@@ -51,8 +70,56 @@ pub(crate) async fn list(
             .order_by(Section::Id, Order::Asc)
             .build_sqlx(PostgresQueryBuilder);
 
-    let rows: Vec<SectionRowSample> = query_as_with::<_, SectionRowSample, _>(&sql, values.clone())
-        .fetch_all(&state.db)
+    let rows: Vec<SectionRowSample> =
+        db::fetch_all::<SectionRowSample, _>(&state.db, &sql, values.clone())
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+
+    let activity = last_day_activity(&state).await;
+    let mut views = vec![];
+    for row in rows {
+        let owner_author = if let Some(owner) = &row.owner {
+            build_author(&state, owner).await
+        } else {
+            json!({})
+        };
+
+        views.push(SectionView::build(row, owner_author).with_activity(&activity));
+    }
+
+    Ok(ok(views))
+}
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+#[serde(default)]
+pub struct TrendingQuery {
+    /// One of `day`, `week` or `month`.
+    #[validate(custom(function = "known_period"))]
+    pub period: String,
+    #[validate(range(min = 1, max = 50))]
+    pub limit: u64,
+}
+
+impl Default for TrendingQuery {
+    fn default() -> Self {
+        Self {
+            period: "week".to_string(),
+            limit: 10,
+        }
+    }
+}
+
+/// Sections ranked by their posts, comments and likes in `period`.
+#[utoipa::path(get, path = "/api/section/trending", params(TrendingQuery))]
+pub(crate) async fn trending(
+    State(state): State<AppView>,
+    ValidQuery(query): ValidQuery<TrendingQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let period_hours = period_hours(&query.period)?;
+
+    let (sql, values) =
+        Section::build_trending(period_hours, query.limit).build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<SectionRowSample> = db::fetch_all(&state.db, &sql, values)
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
@@ -63,7 +130,6 @@ pub(crate) async fn list(
         } else {
             json!({})
         };
-
         views.push(SectionView::build(row, owner_author));
     }
 
@@ -74,12 +140,15 @@ pub(crate) async fn list(
 #[serde(default)]
 pub struct SectionIdQuery {
     pub id: i32,
+    /// Adds what the viewer may do in the section under `capability`,
+    /// with the ban keeping them from writing there, if any.
+    pub viewer: Option<String>,
 }
 
 #[utoipa::path(get, path = "/api/section/detail", params(SectionIdQuery))]
 pub(crate) async fn detail(
     State(state): State<AppView>,
-    Query(query): Query<SectionIdQuery>,
+    ValidQuery(query): ValidQuery<SectionIdQuery>,
 ) -> Result<impl IntoResponse, AppError> {
     let id: i32 = query.id;
 
@@ -87,13 +156,13 @@ pub(crate) async fn detail(
         .and_where(Expr::col(Section::Id).eq(id))
         .build_sqlx(PostgresQueryBuilder);
 
-    let row: SectionRowSample = query_as_with::<_, SectionRowSample, _>(&sql, values.clone())
-        .fetch_one(&state.db)
-        .await
-        .map_err(|e| {
-            debug!("exec sql failed: {e}");
-            AppError::NotFound
-        })?;
+    let row: SectionRowSample =
+        db::fetch_one::<SectionRowSample, _>(&state.db, &sql, values.clone())
+            .await
+            .map_err(|e| {
+                debug!("exec sql failed: {e}");
+                AppError::NotFound
+            })?;
 
     let owner_author = if let Some(owner) = &row.owner {
         build_author(&state, owner).await
@@ -101,5 +170,176 @@ pub(crate) async fn detail(
         json!({})
     };
 
-    Ok(ok(SectionView::build(row, owner_author)))
+    let activity = last_day_activity(&state).await;
+    let Some(viewer) = query.viewer else {
+        return Ok(ok(json!(
+            SectionView::build(row, owner_author).with_activity(&activity)
+        )));
+    };
+    let quota = Quota::compute(&state.db, &state.quota, &viewer).await?;
+    let ban = SectionBan::active(&state.db, id, &viewer, chrono::Local::now()).await?;
+    let may_write =
+        !row.is_archived && ban.is_none() && Whitelist::select_by_did(&state.db, &viewer).await;
+    let is_moderator = row.owner.as_ref() == Some(&viewer)
+        || Administrator::all_did(&state.db).await.contains(&viewer);
+    let can_post =
+        may_write && (row.permission == 0 || is_moderator) && quota.check(id, true).is_ok();
+    let can_comment = may_write
+        && (row.comment_permission == 0 || is_moderator)
+        && quota.check(id, false).is_ok();
+    let mut view = json!(SectionView::build(row, owner_author).with_activity(&activity));
+    view["capability"] = json!({
+        "can_post": can_post,
+        "can_comment": can_comment,
+        "quota": quota,
+        "ban": ban,
+    });
+
+    Ok(ok(view))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, IntoParams)]
+#[serde(default)]
+pub struct MineQuery {
+    #[validate(length(min = 1))]
+    pub repo: String,
+}
+
+/// A section the repo moderates, with the work waiting in it.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct SectionWorkload {
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    #[schema(schema_with = crate::lexicon::numeric::schema)]
+    pub section_id: String,
+    pub name: String,
+    /// `owner`, or `administrator` for sections of others.
+    pub role: String,
+    /// Targets the content rules flagged that no moderator acted on since.
+    pub open_reports: i64,
+    /// Hidden posts, comments and replies.
+    pub hidden: i64,
+    /// Posts a content rule hid for review in the last 24 hours.
+    pub pending_review: i64,
+    /// Users banned from writing in the section right now.
+    pub bans: i64,
+}
+
+/// The sections `repo` owns, or every section for an administrator, with
+/// the work waiting in each; empty for everyone else. Returns
+/// `SectionWorkload`s, cached for half a minute.
+#[utoipa::path(get, path = "/api/section/mine", params(MineQuery))]
+pub(crate) async fn mine(
+    State(state): State<AppView>,
+    ValidQuery(query): ValidQuery<MineQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let sections = state
+        .caches
+        .my_sections(&query.repo, async {
+            let is_admin = Administrator::all_did(&state.db)
+                .await
+                .contains(&query.repo);
+            let (sql, values) =
+                Section::build_workload(&query.repo, is_admin).build_sqlx(PostgresQueryBuilder);
+            let rows: Vec<(i32, String, Option<String>, i64, i64, i64, i64)> =
+                db::fetch_all(&state.db, &sql, values).await?;
+            Ok(rows
+                .into_iter()
+                .map(
+                    |(id, name, owner, open_reports, hidden, pending_review, bans)| {
+                        SectionWorkload {
+                            section_id: id.to_string(),
+                            name,
+                            role: if owner.as_ref() == Some(&query.repo) {
+                                "owner"
+                            } else {
+                                "administrator"
+                            }
+                            .to_string(),
+                            open_reports,
+                            hidden,
+                            pending_review,
+                            bans,
+                        }
+                    },
+                )
+                .collect())
+        })
+        .await?;
+
+    Ok(ok(sections))
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn moderators_see_the_workload_of_their_sections() {
+    use common_x::restful::axum::body::to_bytes;
+    use serde_json::Value;
+    use sqlx::{Executor, query};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO section (id, name, owner) VALUES (1, 'General', 'did:ckb:owner'), (2, 'Market', 'did:ckb:other')",
+        "INSERT INTO administrator (did, permission) VALUES ('did:ckb:admin', 0)",
+        // p1 shadowed for review, p2 flagged then displayed, p3 hidden by a moderator
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft, is_disabled, reasons_for_disabled, created) VALUES
+            ('p1', 'bafy', 'did:ckb:bob', 1, 't', 't', false, true, 'pending review: spam', now()),
+            ('p2', 'bafy', 'did:ckb:bob', 1, 't', 't', false, false, NULL, now()),
+            ('p3', 'bafy', 'did:ckb:bob', 1, 't', 't', false, true, 'off topic', now() - interval '3 days'),
+            ('p4', 'bafy', 'did:ckb:bob', 2, 't', 't', false, true, 'pending review: spam', now() - interval '3 days')",
+        "INSERT INTO comment (uri, cid, repo, section_id, post, text, is_disabled) VALUES ('c1', 'bafy', 'did:ckb:bob', 1, 'p2', 't', true), ('c2', 'bafy', 'did:ckb:bob', 1, 'p2', 't', false)",
+        "INSERT INTO reply (uri, cid, repo, section_id, post, comment, text, is_disabled) VALUES ('r1', 'bafy', 'did:ckb:bob', 2, 'p4', 'c1', 't', true)",
+        "INSERT INTO operation (section_id, operator, action_type, action, message, target, created) VALUES
+            (1, 'did:ckb:bob', 23, 'shadow', 'spam (rule 1)', 'p1', now() - interval '2 hours'),
+            (1, 'did:ckb:bob', 22, 'flag', 'spam (rule 1)', 'p1', now() - interval '1 hour'),
+            (1, 'did:ckb:bob', 22, 'flag', 'spam (rule 1)', 'p2', now() - interval '2 hours'),
+            (1, 'did:ckb:owner', 2, 'enable', '', 'p2', now() - interval '1 hour'),
+            (1, 'did:ckb:bob', 22, 'flag', 'abuse (rule 2)', 'c2', now())",
+        "INSERT INTO section_ban (section_id, did, until, banned_by) VALUES (1, 'did:ckb:carol', now() + interval '1 day', 'did:ckb:owner'), (1, 'did:ckb:dave', now() - interval '1 day', 'did:ckb:owner')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    let state = AppView::for_tests(db);
+    let mine_of = async |repo: &str| {
+        let response = mine(
+            State(state.clone()),
+            ValidQuery(MineQuery {
+                repo: repo.to_string(),
+            }),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut body: Value = serde_json::from_slice(&bytes).unwrap();
+        body["data"].take()
+    };
+
+    assert_eq!(mine_of("did:ckb:member").await, json!([]));
+    let general = json!({
+        "section_id": "1",
+        "name": "General",
+        "role": "owner",
+        "open_reports": 2,
+        "hidden": 3,
+        "pending_review": 1,
+        "bans": 1,
+    });
+    assert_eq!(mine_of("did:ckb:owner").await, json!([general.clone()]));
+
+    let admin = mine_of("did:ckb:admin").await;
+    assert_eq!(admin.as_array().unwrap().len(), 2);
+    assert_eq!(admin[0]["role"], "administrator");
+    assert_eq!(admin[0]["open_reports"], general["open_reports"]);
+    assert_eq!(
+        admin[1],
+        json!({
+            "section_id": "2",
+            "name": "Market",
+            "role": "administrator",
+            "open_reports": 0,
+            "hidden": 2,
+            "pending_review": 0,
+            "bans": 0,
+        })
+    );
 }
@@ -1,25 +1,32 @@
-use color_eyre::Result;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use color_eyre::{Result, eyre::eyre};
 use common_x::restful::{
-    axum::{
-        Json,
-        extract::{Query, State},
-        response::IntoResponse,
-    },
+    axum::{Json, extract::State, response::IntoResponse},
     ok,
 };
 use sea_query::{Expr, ExprTrait, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use sqlx::query_as_with;
 use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
 use crate::{
     AppView,
-    api::{SignedBody, SignedParam, build_author},
-    atproto::{NSID_COMMENT, NSID_COMMUNITY, NSID_POST, NSID_REPLY, NSID_SECTION},
-    ckb::get_ckb_addr_by_did,
+    api::{
+        SignedBody, SignedParam, build_author, check_session, payout_ckb_addr,
+        valid::{Valid, ValidQuery},
+    },
+    atproto::Collection,
+    db,
     error::AppError,
     lexicon::{
         comment::Comment,
@@ -28,18 +35,27 @@ use crate::{
         reply::Reply,
         resolve_uri,
         section::Section,
-        tip::{TipCategory, TipRow, TipState, TipView},
+        tip::{
+            PaymentItem, Tip, TipCategory, TipDetailView, TipRow, TipState, TipView,
+            parse_shannons, shannons_to_ckb,
+        },
     },
-    micro_pay,
+    micro_pay::{self, SplitError, SplitReceiver, build_split_receivers},
 };
 
 #[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
 #[serde(default)]
 pub(crate) struct TipParams {
-    pub nsid: String,
+    pub nsid: Collection,
     pub uri: String,
     pub sender: String,
+    /// Whole shannons, as a decimal string.
     pub amount: String,
+    /// Preparing again with the key is refused with the tip it prepared,
+    /// until that tip times out.
+    #[validate(length(min = 1))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
     pub timestamp: i64,
 }
 
@@ -52,43 +68,32 @@ impl SignedParam for TipParams {
 #[utoipa::path(post, path = "/api/tip/prepare")]
 pub(crate) async fn prepare(
     State(state): State<AppView>,
-    Json(body): Json<SignedBody<TipParams>>,
+    Valid(Json(body)): Valid<Json<SignedBody<TipParams>>>,
 ) -> Result<impl IntoResponse, AppError> {
-    body.validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
-    body.verify_signature(&state.indexer)
+    let amount =
+        parse_shannons(&body.params.amount).map_err(|e| AppError::ValidateFailed(e.to_string()))?;
+    body.verify_signature(&state)
         .await
         .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
 
-    let (receiver_did, section_ckb_addr, is_announcement) = match body.params.nsid.as_str() {
-        NSID_POST => {
-            let (sql, values) = sea_query::Query::select()
-                .columns([(Post::Table, Post::Repo)])
-                .columns([
-                    (Section::Table, Section::CkbAddr),
-                    (Section::Table, Section::Id),
-                ])
-                .from(Post::Table)
-                .left_join(
-                    Section::Table,
-                    Expr::col((Post::Table, Post::SectionId)).equals((Section::Table, Section::Id)),
-                )
-                .and_where(Expr::col(Post::Uri).eq(body.params.uri.clone()))
-                .build_sqlx(PostgresQueryBuilder);
-            let row: (String, String, i32) = query_as_with(&sql, values.clone())
-                .fetch_one(&state.db)
-                .await
-                .map_err(|e| {
-                    debug!("exec sql failed: {e}");
-                    AppError::NotFound
-                })?;
-            if row.2 == 0 {
-                (row.1.clone(), row.1, true)
+    let (receiver_did, section_ckb_addr, is_announcement) = match body.params.nsid {
+        Collection::Post => {
+            let (sql, values) =
+                build_post_target(&body.params.uri).build_sqlx(PostgresQueryBuilder);
+            let row: (String, Option<String>, bool) =
+                db::fetch_one(&state.db, &sql, values.clone())
+                    .await
+                    .map_err(|e| {
+                        debug!("exec sql failed: {e}");
+                        AppError::NotFound
+                    })?;
+            if row.2 {
+                (state.bbs_ckb_addr.clone(), row.1, true)
             } else {
                 (row.0, row.1, false)
             }
         }
-        NSID_COMMENT => {
+        Collection::Comment => {
             let (sql, values) = sea_query::Query::select()
                 .columns([(Comment::Table, Comment::Repo)])
                 .columns([(Section::Table, Section::CkbAddr)])
@@ -100,8 +105,7 @@ pub(crate) async fn prepare(
                 )
                 .and_where(Expr::col(Comment::Uri).eq(body.params.uri.clone()))
                 .build_sqlx(PostgresQueryBuilder);
-            let row: (String, String) = query_as_with(&sql, values.clone())
-                .fetch_one(&state.db)
+            let row: (String, Option<String>) = db::fetch_one(&state.db, &sql, values.clone())
                 .await
                 .map_err(|e| {
                     debug!("exec sql failed: {e}");
@@ -109,7 +113,7 @@ pub(crate) async fn prepare(
                 })?;
             (row.0, row.1, false)
         }
-        NSID_REPLY => {
+        Collection::Reply => {
             let (sql, values) = sea_query::Query::select()
                 .columns([(Reply::Table, Reply::Repo)])
                 .columns([(Section::Table, Section::CkbAddr)])
@@ -121,8 +125,7 @@ pub(crate) async fn prepare(
                 )
                 .and_where(Expr::col(Reply::Uri).eq(body.params.uri.clone()))
                 .build_sqlx(PostgresQueryBuilder);
-            let row: (String, String) = query_as_with(&sql, values.clone())
-                .fetch_one(&state.db)
+            let row: (String, Option<String>) = db::fetch_one(&state.db, &sql, values.clone())
                 .await
                 .map_err(|e| {
                     debug!("exec sql failed: {e}");
@@ -130,7 +133,11 @@ pub(crate) async fn prepare(
                 })?;
             (row.0, row.1, false)
         }
-        _ => {
+        Collection::Like
+        | Collection::Follow
+        | Collection::Section
+        | Collection::Community
+        | Collection::Profile => {
             return Err(AppError::ValidateFailed("unsupported nsid".to_string()));
         }
     };
@@ -138,12 +145,10 @@ pub(crate) async fn prepare(
     let receiver = if is_announcement {
         state.bbs_ckb_addr.clone()
     } else {
-        get_ckb_addr_by_did(&state.ckb_client, &state.ckb_net, &receiver_did)
-            .await
-            .map_err(|e| {
-                debug!("get ckb addr by did failed: {e}");
-                AppError::ValidateFailed("get receiver ckb addr failed".to_string())
-            })?
+        payout_ckb_addr(&state, &receiver_did).await.map_err(|e| {
+            debug!("get ckb addr by did failed: {e}");
+            AppError::ValidateFailed("get receiver ckb addr failed".to_string())
+        })?
     };
 
     let mut tip_row = TipRow {
@@ -153,64 +158,33 @@ pub(crate) async fn prepare(
         sender: body.params.sender.clone(),
         receiver,
         receiver_did,
-        amount: body.params.amount.parse::<i64>()?,
+        amount,
         info: format!("{}/{}", body.params.nsid, body.params.uri),
         state: TipState::Prepared as i32,
         tx_hash: None,
+        idempotency_key: body.params.idempotency_key.clone(),
         updated: chrono::Local::now(),
         created: chrono::Local::now(),
     };
 
-    let split_receivers = if is_announcement {
-        json!([])
-    } else if state.bbs_ckb_addr == section_ckb_addr {
-        json!([
-            {
-                "address": &state.bbs_ckb_addr,
-                "receiverDid": &state.bbs_ckb_addr,
-                "splitRate": 30
-            }
-        ])
-    } else {
-        json!([
-            {
-                "address": &state.bbs_ckb_addr,
-                "receiverDid": &state.bbs_ckb_addr,
-                "splitRate": 10
-            },
-            {
-                "address": &section_ckb_addr,
-                "receiverDid": &section_ckb_addr,
-                "splitRate": 20
-            }
-        ])
-    };
-
-    let result = micro_pay::payment_prepare(
-        &state.pay_url,
-        &json!({
-            "sender": &tip_row.sender,
-            "senderDid": &tip_row.sender_did,
-            "receiver": &tip_row.receiver,
-            "receiverDid": &tip_row.receiver_did,
-            "category": &tip_row.category,
-            "amount": &tip_row.amount,
-            "info": &tip_row.info,
-            "splitReceivers": split_receivers
-        }),
-    )
-    .await?;
-
-    if let Some(err) = result.get("error") {
-        return Err(AppError::MicroPayIncomplete(
-            result.get("code").unwrap_or(err).to_string(),
-        ));
+    if !is_announcement && section_ckb_addr.is_none() {
+        warn!(
+            "section of {} has no ckb_addr, its tip share goes to the bbs",
+            tip_row.info
+        );
     }
+    let split_receivers = split_receivers(
+        &state.bbs_ckb_addr,
+        section_ckb_addr.as_deref(),
+        is_announcement,
+        &tip_row.receiver,
+    )
+    .map_err(|e| {
+        error!("split receivers of {} are misconfigured: {e}", tip_row.info);
+        AppError::Unknown(format!("split receivers are misconfigured: {e}"))
+    })?;
 
-    tip_row.tx_hash = result
-        .get("txHash")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
+    let result = prepare_payment(&state, &mut tip_row, &split_receivers).await?;
 
     let author = build_author(&state, &tip_row.sender_did).await;
     let tip = TipView {
@@ -222,6 +196,7 @@ pub(crate) async fn prepare(
         receiver: tip_row.receiver.clone(),
         receiver_did: tip_row.receiver_did.clone(),
         amount: tip_row.amount.to_string(),
+        amount_ckb: shannons_to_ckb(tip_row.amount),
         info: tip_row.info.clone(),
         state: tip_row.state.to_string(),
         tx_hash: tip_row.tx_hash.clone(),
@@ -235,6 +210,180 @@ pub(crate) async fn prepare(
     })))
 }
 
+/// Author, section ckb_addr and announcement flag of the tipped post.
+fn build_post_target(uri: &str) -> sea_query::SelectStatement {
+    sea_query::Query::select()
+        .columns([(Post::Table, Post::Repo)])
+        .columns([(Section::Table, Section::CkbAddr)])
+        .columns([(Post::Table, Post::IsAnnouncement)])
+        .from(Post::Table)
+        .left_join(
+            Section::Table,
+            Expr::col((Post::Table, Post::SectionId)).equals((Section::Table, Section::Id)),
+        )
+        .and_where(Expr::col((Post::Table, Post::Uri)).eq(uri))
+        .take()
+}
+
+/// Shares of a tip split off to the bbs and the section. Announcements
+/// belong to the bbs and are not split; a section without a ckb_addr
+/// leaves its share to the bbs. Nothing is split off to `receiver` itself.
+fn split_receivers(
+    bbs_ckb_addr: &str,
+    section_ckb_addr: Option<&str>,
+    is_announcement: bool,
+    receiver: &str,
+) -> Result<Vec<SplitReceiver>, SplitError> {
+    match section_ckb_addr {
+        _ if is_announcement => Ok(vec![]),
+        Some(section_ckb_addr) if section_ckb_addr != bbs_ckb_addr => {
+            build_split_receivers(receiver, &[(bbs_ckb_addr, 10), (section_ckb_addr, 20)])
+        }
+        _ => build_split_receivers(receiver, &[(bbs_ckb_addr, 30)]),
+    }
+}
+
+/// Inserts the prepared tip, which takes its idempotency key, and then has
+/// micro_pay prepare its payment. A key still held by an earlier tip of the
+/// sender is refused with that tip. When the payment can't be prepared the
+/// tip is timed out, freeing the key; when the tip can't keep the payment,
+/// the payment is cancelled.
+pub(crate) async fn prepare_payment(
+    state: &AppView,
+    tip_row: &mut TipRow,
+    split_receivers: &[SplitReceiver],
+) -> Result<Value, AppError> {
+    let Some(id) = Tip::insert(&state.db, tip_row).await? else {
+        let key = tip_row.idempotency_key.as_deref().unwrap_or_default();
+        let tip = Tip::select_by_idempotency_key(&state.db, &tip_row.sender_did, key).await?;
+        return Err(AppError::Conflict(json!(tip)));
+    };
+    tip_row.id = id;
+
+    let result = micro_pay::payment_prepare(
+        &state.pay_url,
+        &json!({
+            "sender": &tip_row.sender,
+            "senderDid": &tip_row.sender_did,
+            "receiver": &tip_row.receiver,
+            "receiverDid": &tip_row.receiver_did,
+            "category": &tip_row.category,
+            "amount": &tip_row.amount,
+            "info": &tip_row.info,
+            "splitReceivers": split_receivers
+        }),
+    )
+    .await
+    .map_err(AppError::from)
+    .and_then(|result| match result.get("error") {
+        Some(err) => Err(AppError::MicroPayIncomplete(
+            result.get("code").unwrap_or(err).to_string(),
+        )),
+        None => Ok(result),
+    });
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            if let Err(e) = Tip::expire(&state.db, id).await {
+                error!("Tip::expire failed: {e}");
+            }
+            return Err(e);
+        }
+    };
+
+    tip_row.tx_hash = result
+        .get("txHash")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string());
+    if let Some(tx_hash) = tip_row.tx_hash.as_deref()
+        && let Err(e) = Tip::set_tx_hash(&state.db, id, tx_hash).await
+    {
+        error!("Tip::set_tx_hash failed: {e}");
+        if let Err(e) = micro_pay::payment_cancel(&state.pay_url, tx_hash).await {
+            error!("payment_cancel {tx_hash} failed: {e}");
+        }
+        if let Err(e) = Tip::expire(&state.db, id).await {
+            error!("Tip::expire failed: {e}");
+        }
+        return Err(AppError::Unknown(format!("record tip payment failed: {e}")));
+    }
+    Ok(result)
+}
+
+static TIPS_TIMED_OUT: AtomicU64 = AtomicU64::new(0);
+static TIPS_CANCEL_FAILED: AtomicU64 = AtomicU64::new(0);
+static TIPS_EXPIRY_NOTIFIED: AtomicU64 = AtomicU64::new(0);
+static TIPS_PURGED: AtomicU64 = AtomicU64::new(0);
+
+/// What `expire_stale` did since the start.
+pub(crate) fn expiry_stats() -> Value {
+    json!({
+        "timed_out": TIPS_TIMED_OUT.load(Ordering::Relaxed),
+        "cancel_failed": TIPS_CANCEL_FAILED.load(Ordering::Relaxed),
+        "notified": TIPS_EXPIRY_NOTIFIED.load(Ordering::Relaxed),
+        "purged": TIPS_PURGED.load(Ordering::Relaxed),
+    })
+}
+
+/// Cancels the payments of prepared tips that were never transferred and
+/// times the tips out, which frees their idempotency keys and tells each
+/// sender once. Then deletes the timed out tips past their retention.
+pub(crate) async fn expire_stale(state: &AppView) -> Result<()> {
+    let (sql, values) = Tip::build_stale_select(&state.tip_expiry).build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<TipRow> = db::fetch_all(&state.db, &sql, values)
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+    for row in rows {
+        if let Some(tx_hash) = row.tx_hash.as_deref()
+            && let Err(e) = micro_pay::payment_cancel(&state.pay_url, tx_hash).await
+        {
+            // retried on the next run
+            error!("payment_cancel {tx_hash} failed: {e}");
+            TIPS_CANCEL_FAILED.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+        match Tip::expire(&state.db, row.id).await {
+            Ok(true) => {
+                TIPS_TIMED_OUT.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(false) => continue,
+            Err(e) => {
+                error!("Tip::expire failed: {e}");
+                continue;
+            }
+        }
+        match Notify::insert(&state.db, &expiry_notify(&row)).await {
+            Ok(()) => {
+                TIPS_EXPIRY_NOTIFIED.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => error!("Notify::insert failed: {e}"),
+        }
+    }
+
+    let purged = Tip::purge(&state.db, &state.tip_expiry).await?;
+    if purged > 0 {
+        info!("purged {purged} timed out tips");
+        TIPS_PURGED.fetch_add(purged, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Tells the sender of a timed out tip that it never reached `receiver_did`.
+fn expiry_notify(row: &TipRow) -> NotifyRow {
+    let (_nsid, target) = row.info.split_once('/').unwrap_or(("", row.info.as_str()));
+    NotifyRow {
+        id: 0,
+        title: "Tip Expired".to_string(),
+        sender: row.receiver_did.clone(),
+        receiver: row.sender_did.clone(),
+        n_type: NotifyType::TipExpired as i32,
+        target_uri: target.to_string(),
+        amount: row.amount,
+        readed: None,
+        created: chrono::Local::now(),
+    }
+}
+
 #[utoipa::path(post, path = "/api/tip/transfer")]
 pub(crate) async fn transfer(
     State(state): State<AppView>,
@@ -244,6 +393,12 @@ pub(crate) async fn transfer(
     if let Some(id) = result.get("paymentId").and_then(|id| id.as_i64()) {
         let payment = micro_pay::payment(&state.pay_url, id).await?;
         debug!("payment: {payment}");
+        if let Some(tx_hash) = payment.pointer("/payment/txHash").and_then(|i| i.as_str()) {
+            Tip::update_state(&state.db, tx_hash, TipState::Committed)
+                .await
+                .map_err(|e| error!("Tip::update_state failed: {e}"))
+                .ok();
+        }
         if let Some(info) = payment.pointer("/payment/info").and_then(|i| i.as_str())
             && let Some(sender) = payment
                 .pointer("/payment/senderDid")
@@ -262,7 +417,7 @@ pub(crate) async fn transfer(
                         receiver: receiver.to_string(),
                         n_type: NotifyType::NewTip as i32,
                         target_uri: to.to_string(),
-                        amount: amount.parse::<i64>().unwrap_or(0),
+                        amount: parse_shannons(amount).unwrap_or(0),
                         readed: None,
                         created: chrono::Local::now(),
                     },
@@ -282,8 +437,9 @@ pub(crate) struct TipsQuery {
     pub uri: String,
     #[validate(range(min = 1))]
     pub page: u64,
+    /// Defaults to `pagination.tips` of the config, capped at its `max`.
     #[validate(range(min = 1))]
-    pub per_page: u64,
+    pub per_page: Option<u64>,
 }
 
 impl Default for TipsQuery {
@@ -292,7 +448,7 @@ impl Default for TipsQuery {
             nsid: String::new(),
             uri: String::new(),
             page: 1,
-            per_page: 20,
+            per_page: None,
         }
     }
 }
@@ -300,21 +456,24 @@ impl Default for TipsQuery {
 #[utoipa::path(post, path = "/api/tip/list")]
 pub(crate) async fn list_by_for(
     State(state): State<AppView>,
-    Json(query): Json<TipsQuery>,
+    Valid(Json(query)): Valid<Json<TipsQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
-    let q = format!(
-        "info={}/{}&limit={}&offset={}",
-        &query.nsid,
-        &query.uri,
-        query.per_page,
-        query.per_page * (query.page - 1)
-    );
-    let row = micro_pay::payment_completed(&state.pay_url, &q).await?;
-    let mut items: Vec<Value> = row
-        .get("items")
-        .and_then(|items| items.as_array())
-        .unwrap_or(&vec![])
-        .to_vec();
+    let per_page = state.pagination.tips.resolve(query.per_page);
+    let q = [
+        ("info", format!("{}/{}", query.nsid, query.uri)),
+        ("limit", per_page.to_string()),
+        ("offset", (per_page * (query.page - 1)).to_string()),
+    ];
+    let row = micro_pay::payment_completed(&state.pay_url, &q).await;
+    let (mut items, total, degraded) = match payment_page(row) {
+        Ok((items, total)) => (items, total, false),
+        Err(e) => {
+            let filter = Expr::col(Tip::Info).eq(format!("{}/{}", query.nsid, query.uri));
+            let offset = per_page * (query.page - 1);
+            let (items, total) = local_page(&state, Some(filter), per_page, offset, e).await?;
+            (items, total, true)
+        }
+    };
     for item in &mut items {
         if let Some(sender_did) = item.get("senderDid").and_then(|i| i.as_str()) {
             let sender_author = build_author(&state, sender_did).await;
@@ -322,16 +481,12 @@ pub(crate) async fn list_by_for(
         }
     }
 
-    let total = row
-        .pointer("/pagination/count")
-        .and_then(|i| i.as_i64())
-        .unwrap_or(0);
-
     Ok(ok(json!({
         "tips": items,
         "page": query.page,
-        "per_page": query.per_page,
-        "total":  total
+        "per_page": per_page,
+        "total":  total,
+        "degraded": degraded
     })))
 }
 
@@ -342,8 +497,9 @@ pub(crate) struct DetailQuery {
     pub end: Option<String>,
     #[validate(range(min = 1))]
     pub page: u64,
+    /// Defaults to `pagination.tips` of the config, capped at its `max`.
     #[validate(range(min = 1))]
-    pub per_page: u64,
+    pub per_page: Option<u64>,
     pub category: Option<u8>,
     pub did: String,
 }
@@ -354,43 +510,58 @@ impl Default for DetailQuery {
             start: None,
             end: None,
             page: 1,
-            per_page: 20,
+            per_page: None,
             category: None,
             did: String::new(),
         }
     }
 }
 
+impl DetailQuery {
+    /// The tips of `did` in `did_col` as the local table knows them. `None`
+    /// when a date range is asked for, since `start` and `end` are passed to
+    /// micro_pay as they are.
+    fn local_filter(&self, did_col: Tip) -> Option<Expr> {
+        if self.start.is_some() || self.end.is_some() {
+            return None;
+        }
+        let filter = Expr::col(did_col).eq(self.did.as_str());
+        Some(match self.category {
+            Some(category) => filter.and(Expr::col(Tip::Category).eq(category as i32)),
+            None => filter,
+        })
+    }
+}
+
 #[utoipa::path(post, path = "/api/tip/expense_details")]
 pub(crate) async fn expense_details(
     State(state): State<AppView>,
-    Json(query): Json<DetailQuery>,
+    Valid(Json(query)): Valid<Json<DetailQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
-    query
-        .validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
-
     let mut q: Vec<(&str, String)> = vec![];
     if let Some(category) = &query.category {
         q.push(("category", category.to_string()));
     }
-    if let Some(start) = query.start {
-        q.push(("start", start));
+    if let Some(start) = &query.start {
+        q.push(("start", start.clone()));
     }
-    if let Some(end) = query.end {
-        q.push(("end", end));
+    if let Some(end) = &query.end {
+        q.push(("end", end.clone()));
     }
-    let per_page = query.per_page.to_string();
-    q.push(("limit", per_page));
-    let offset = (query.per_page * (query.page - 1)).to_string();
-    q.push(("offset", offset));
-
-    let row = micro_pay::payment_sender_did(&state.pay_url, &query.did, &q).await?;
-    let mut items: Vec<Value> = row
-        .get("items")
-        .and_then(|items| items.as_array())
-        .unwrap_or(&vec![])
-        .to_vec();
+    let per_page = state.pagination.tips.resolve(query.per_page);
+    q.push(("limit", per_page.to_string()));
+    let offset = per_page * (query.page - 1);
+    q.push(("offset", offset.to_string()));
+
+    let row = micro_pay::payment_sender_did(&state.pay_url, &query.did, &q).await;
+    let (mut items, total, degraded) = match payment_page(row) {
+        Ok((items, total)) => (items, total, false),
+        Err(e) => {
+            let filter = query.local_filter(Tip::SenderDid);
+            let (items, total) = local_page(&state, filter, per_page, offset, e).await?;
+            (items, total, true)
+        }
+    };
     for item in &mut items {
         if let Some(info) = item.get("info").and_then(|i| i.as_str())
             && let Ok(source) = get_source(&state, info).await
@@ -403,49 +574,44 @@ pub(crate) async fn expense_details(
         }
     }
 
-    let total = row
-        .pointer("/pagination/count")
-        .and_then(|i| i.as_i64())
-        .unwrap_or(0);
-
     Ok(ok(json!({
         "tips": items,
         "page": query.page,
-        "per_page": query.per_page,
-        "total":  total
+        "per_page": per_page,
+        "total":  total,
+        "degraded": degraded
     })))
 }
 
 #[utoipa::path(post, path = "/api/tip/income_details")]
 pub(crate) async fn income_details(
     State(state): State<AppView>,
-    Json(query): Json<DetailQuery>,
+    Valid(Json(query)): Valid<Json<DetailQuery>>,
 ) -> Result<impl IntoResponse, AppError> {
-    query
-        .validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
-
     let mut q: Vec<(&str, String)> = vec![];
     if let Some(category) = &query.category {
         q.push(("category", category.to_string()));
     }
-    if let Some(start) = query.start {
-        q.push(("start", start));
+    if let Some(start) = &query.start {
+        q.push(("start", start.clone()));
     }
-    if let Some(end) = query.end {
-        q.push(("end", end));
+    if let Some(end) = &query.end {
+        q.push(("end", end.clone()));
     }
-    let per_page = query.per_page.to_string();
-    q.push(("limit", per_page));
-    let offset = (query.per_page * (query.page - 1)).to_string();
-    q.push(("offset", offset));
-
-    let row = micro_pay::payment_receiver_did(&state.pay_url, &query.did, &q).await?;
-    let mut items: Vec<Value> = row
-        .get("items")
-        .and_then(|items| items.as_array())
-        .unwrap_or(&vec![])
-        .to_vec();
+    let per_page = state.pagination.tips.resolve(query.per_page);
+    q.push(("limit", per_page.to_string()));
+    let offset = per_page * (query.page - 1);
+    q.push(("offset", offset.to_string()));
+
+    let row = micro_pay::payment_receiver_did(&state.pay_url, &query.did, &q).await;
+    let (mut items, total, degraded) = match payment_page(row) {
+        Ok((items, total)) => (items, total, false),
+        Err(e) => {
+            let filter = query.local_filter(Tip::ReceiverDid);
+            let (items, total) = local_page(&state, filter, per_page, offset, e).await?;
+            (items, total, true)
+        }
+    };
     for item in &mut items {
         if let Some(info) = item.get("info").and_then(|i| i.as_str())
             && let Ok(source) = get_source(&state, info).await
@@ -458,45 +624,213 @@ pub(crate) async fn income_details(
         }
     }
 
-    let total = row
-        .pointer("/pagination/count")
-        .and_then(|i| i.as_i64())
-        .unwrap_or(0);
-
     Ok(ok(json!({
         "tips": items,
         "page": query.page,
-        "per_page": query.per_page,
-        "total":  total
+        "per_page": per_page,
+        "total":  total,
+        "degraded": degraded
     })))
 }
 
 #[derive(Debug, Default, Validate, Deserialize, IntoParams)]
 #[serde(default)]
 pub struct DidQuery {
+    #[validate(length(min = 1))]
     pub did: String,
 }
 
+/// Tip stats of a did: micro_pay's own, with what the local tips add. Each
+/// part is null while its source is unavailable.
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub(crate) struct TipStats {
+    /// The micro_pay `did-stats` of the did.
+    pub upstream: Option<Value>,
+    /// Distinct senders of the committed tips the did received.
+    pub supporters: Option<i64>,
+    /// Place of the did among the tip receivers by amount received, from 1;
+    /// null when it received none.
+    pub rank: Option<u64>,
+    /// What the did was tipped the most for, as the tip lists describe it,
+    /// with the `amount` received for it.
+    pub top_target: Option<Value>,
+}
+
+/// Returns `TipStats`.
 #[utoipa::path(get, path = "/api/tip/stats", params(DidQuery))]
 pub(crate) async fn stats(
     State(state): State<AppView>,
-    Query(query): Query<DidQuery>,
+    ValidQuery(query): ValidQuery<DidQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    let result = micro_pay::payment_did_stats(&state.pay_url, &query.did).await?;
-    Ok(ok(result))
+    let upstream = micro_pay::payment_did_stats(&state.pay_url, &query.did)
+        .await
+        .inspect_err(|e| warn!("micro_pay did-stats of {} failed: {e}", query.did))
+        .ok();
+    Ok(ok(tip_stats(&state, &query.did, upstream).await))
 }
 
-async fn get_source(state: &AppView, info: &str) -> Result<Value, AppError> {
+async fn tip_stats(state: &AppView, did: &str, upstream: Option<Value>) -> TipStats {
+    let (sql, values) = Tip::build_supporter_count(did).build_sqlx(PostgresQueryBuilder);
+    let supporters = db::fetch_one::<(i64,), _>(&state.db, &sql, values)
+        .await
+        .inspect_err(|e| debug!("exec sql failed: {e}"))
+        .ok()
+        .map(|(count,)| count);
+
+    let rank = state
+        .caches
+        .tip_ranks(tip_ranks(state))
+        .await
+        .inspect_err(|e| debug!("exec sql failed: {e}"))
+        .ok()
+        .and_then(|ranks| ranks.get(did).copied());
+
+    let (sql, values) = Tip::build_top_target(did).build_sqlx(PostgresQueryBuilder);
+    let top_target = match db::fetch_optional::<(String, i64), _>(&state.db, &sql, values).await {
+        Ok(Some((info, amount))) => {
+            let mut source = get_source(state, &info).await.unwrap_or_else(|_| {
+                let (nsid, uri) = info.split_once("/").unwrap_or(("", ""));
+                json!({ "nsid": nsid, "uri": uri })
+            });
+            source["amount"] = json!(amount);
+            Some(source)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            debug!("exec sql failed: {e}");
+            None
+        }
+    };
+
+    TipStats {
+        upstream,
+        supporters,
+        rank,
+        top_target,
+    }
+}
+
+async fn tip_ranks(state: &AppView) -> Result<HashMap<String, u64>> {
+    let (sql, values) = Tip::build_leaderboard().build_sqlx(PostgresQueryBuilder);
+    let rows = db::fetch_all::<(String, i64), _>(&state.db, &sql, values).await?;
+    Ok(rows
+        .into_iter()
+        .zip(1..)
+        .map(|((did, _amount), rank)| (did, rank))
+        .collect())
+}
+
+#[utoipa::path(get, path = "/api/tip/pending", params(DidQuery))]
+pub(crate) async fn pending(
+    State(state): State<AppView>,
+    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    ValidQuery(query): ValidQuery<DidQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    check_session(&state.pds, auth.token(), &query.did).await?;
+
+    let (sql, values) =
+        Tip::build_pending_select(&query.did, &state.tip_expiry).build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<TipRow> = db::fetch_all(&state.db, &sql, values.clone())
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+
+    let mut views = vec![];
+    for row in rows {
+        views.push(detail_view(&state, row).await);
+    }
+
+    Ok(ok(json!({
+        "tips": views
+    })))
+}
+
+async fn detail_view(state: &AppView, row: TipRow) -> TipDetailView {
+    let source = get_source(state, &row.info).await.unwrap_or_default();
+    TipDetailView {
+        id: row.id.to_string(),
+        category: row.category.to_string(),
+        sender_author: build_author(state, &row.sender_did).await,
+        receiver_author: build_author(state, &row.receiver_did).await,
+        sender: row.sender,
+        sender_did: row.sender_did,
+        receiver: row.receiver,
+        receiver_did: row.receiver_did,
+        amount: row.amount.to_string(),
+        amount_ckb: shannons_to_ckb(row.amount),
+        info: row.info,
+        source,
+        state: row.state.to_string(),
+        tx_hash: row.tx_hash,
+        updated: row.updated,
+        created: row.created,
+    }
+}
+
+/// Items and total of a page listed by micro_pay. Anything but `items` with
+/// a `pagination.count` is an incomplete answer, not an empty page.
+fn payment_page(row: Result<Value>) -> Result<(Vec<Value>, i64), AppError> {
+    let row = row.map_err(|e| AppError::MicroPayIncomplete(e.to_string()))?;
+    match (
+        row.get("items").and_then(|items| items.as_array()),
+        row.pointer("/pagination/count").and_then(|i| i.as_i64()),
+    ) {
+        (Some(items), Some(total)) => Ok((items.clone(), total)),
+        _ => Err(AppError::MicroPayIncomplete(match row.get("error") {
+            Some(err) => row.get("code").unwrap_or(err).to_string(),
+            None => "malformed page".to_string(),
+        })),
+    }
+}
+
+/// The committed tips of the local table matching `filter`, for when
+/// micro_pay could not page them, as `PaymentItem`s the handlers decorate
+/// like micro_pay items. `err` when there is nothing to serve.
+async fn local_page(
+    state: &AppView,
+    filter: Option<Expr>,
+    limit: u64,
+    offset: u64,
+    err: AppError,
+) -> Result<(Vec<Value>, i64), AppError> {
+    warn!("micro_pay page incomplete: {err:?}");
+    let Some(filter) = filter else {
+        return Err(err);
+    };
+    let (sql, values) = Tip::build_committed_count(filter.clone()).build_sqlx(PostgresQueryBuilder);
+    let total = match db::fetch_one::<(i64,), _>(&state.db, &sql, values).await {
+        Ok((total,)) if total > 0 => total,
+        Ok(_) => return Err(err),
+        Err(e) => {
+            error!("count local tips failed: {e}");
+            return Err(err);
+        }
+    };
+    let (sql, values) =
+        Tip::build_committed_page(filter, limit, offset).build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<TipRow> = match db::fetch_all(&state.db, &sql, values).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("list local tips failed: {e}");
+            return Err(err);
+        }
+    };
+    let items = rows
+        .into_iter()
+        .map(|row| json!(PaymentItem::from(row)))
+        .collect();
+    Ok((items, total))
+}
+
+pub(crate) async fn get_source(state: &AppView, info: &str) -> Result<Value, AppError> {
     let (nsid, uri) = info.split_once("/").unwrap_or(("", ""));
-    let source = match nsid {
-        NSID_POST => {
+    let source = match nsid.parse() {
+        Ok(Collection::Post) => {
             let (sql, values) = sea_query::Query::select()
                 .columns([(Post::Table, Post::Title)])
                 .from(Post::Table)
                 .and_where(Expr::col(Post::Uri).eq(uri))
                 .build_sqlx(PostgresQueryBuilder);
-            let row: (String,) = query_as_with(&sql, values.clone())
-                .fetch_one(&state.db)
+            let row: (String,) = db::fetch_one(&state.db, &sql, values.clone())
                 .await
                 .map_err(|e| {
                     debug!("exec sql failed: {e}");
@@ -508,7 +842,7 @@ async fn get_source(state: &AppView, info: &str) -> Result<Value, AppError> {
                 "title": row.0,
             })
         }
-        NSID_COMMENT => {
+        Ok(Collection::Comment) => {
             let (sql, values) = sea_query::Query::select()
                 .columns([
                     (Comment::Table, Comment::Text),
@@ -517,8 +851,7 @@ async fn get_source(state: &AppView, info: &str) -> Result<Value, AppError> {
                 .from(Comment::Table)
                 .and_where(Expr::col(Comment::Uri).eq(uri))
                 .build_sqlx(PostgresQueryBuilder);
-            let row: (String, String) = query_as_with(&sql, values.clone())
-                .fetch_one(&state.db)
+            let row: (String, String) = db::fetch_one(&state.db, &sql, values.clone())
                 .await
                 .map_err(|e| {
                     debug!("exec sql failed: {e}");
@@ -531,7 +864,7 @@ async fn get_source(state: &AppView, info: &str) -> Result<Value, AppError> {
                 "text": row.0,
             })
         }
-        NSID_REPLY => {
+        Ok(Collection::Reply) => {
             let (sql, values) = sea_query::Query::select()
                 .columns([
                     (Reply::Table, Reply::Text),
@@ -542,13 +875,13 @@ async fn get_source(state: &AppView, info: &str) -> Result<Value, AppError> {
                 .from(Reply::Table)
                 .and_where(Expr::col(Reply::Uri).eq(uri))
                 .build_sqlx(PostgresQueryBuilder);
-            let row: (String, String, String, String) = query_as_with(&sql, values.clone())
-                .fetch_one(&state.db)
-                .await
-                .map_err(|e| {
-                    debug!("exec sql failed: {e}");
-                    AppError::NotFound
-                })?;
+            let row: (String, String, String, String) =
+                db::fetch_one(&state.db, &sql, values.clone())
+                    .await
+                    .map_err(|e| {
+                        debug!("exec sql failed: {e}");
+                        AppError::NotFound
+                    })?;
             json!({
                 "nsid": nsid,
                 "uri": uri,
@@ -558,14 +891,13 @@ async fn get_source(state: &AppView, info: &str) -> Result<Value, AppError> {
                 "to": row.3,
             })
         }
-        NSID_SECTION => {
+        Ok(Collection::Section) => {
             let (sql, values) = sea_query::Query::select()
                 .columns([(Section::Table, Section::Name)])
                 .from(Section::Table)
                 .and_where(Expr::col(Section::CkbAddr).eq(uri))
                 .build_sqlx(PostgresQueryBuilder);
-            let row: (String,) = query_as_with(&sql, values.clone())
-                .fetch_one(&state.db)
+            let row: (String,) = db::fetch_one(&state.db, &sql, values.clone())
                 .await
                 .map_err(|e| {
                     debug!("exec sql failed: {e}");
@@ -577,7 +909,7 @@ async fn get_source(state: &AppView, info: &str) -> Result<Value, AppError> {
                 "name": row.0,
             })
         }
-        NSID_COMMUNITY => {
+        Ok(Collection::Community) => {
             json!({
                 "nsid": nsid,
                 "name": "BBS 社区",
@@ -603,3 +935,432 @@ fn test() {
     println!("five_minutes_ago: {}", five_minutes_ago);
     assert!(a >= five_minutes_ago);
 }
+
+#[test]
+fn section_without_ckb_addr_leaves_share_to_bbs() {
+    let splits = json!(split_receivers("ckt1bbs", None, false, "ckt1alice").unwrap());
+    assert_eq!(splits.as_array().unwrap().len(), 1);
+    assert_eq!(splits[0]["address"], "ckt1bbs");
+    assert_eq!(splits[0]["splitRate"], 30);
+
+    let splits =
+        json!(split_receivers("ckt1bbs", Some("ckt1section"), false, "ckt1alice").unwrap());
+    assert_eq!(splits[1]["address"], "ckt1section");
+    assert_eq!(splits[1]["splitRate"], 20);
+
+    // an author paid out to the section address gets the section share
+    let splits =
+        json!(split_receivers("ckt1bbs", Some("ckt1section"), false, "ckt1section").unwrap());
+    assert_eq!(
+        splits,
+        json!([{ "address": "ckt1bbs", "receiverDid": "ckt1bbs", "splitRate": 10 }])
+    );
+}
+
+#[test]
+fn announcement_is_detected_by_flag_not_section() {
+    // an announcement in a real section with its own ckb_addr
+    assert_eq!(
+        split_receivers("ckt1bbs", Some("ckt1section"), true, "ckt1bbs"),
+        Ok(vec![])
+    );
+
+    let sql = build_post_target("at://did:ckb:alice/app.bbs.post/3mbnwjdssbc27")
+        .to_string(PostgresQueryBuilder);
+    assert!(sql.starts_with(
+        "SELECT \"post\".\"repo\", \"section\".\"ckb_addr\", \"post\".\"is_announcement\" FROM \"post\""
+    ));
+}
+
+/// A micro_pay whose `/api/payment/completed` answers by the `info` asked
+/// for, and which knows the did stats of anyone. Empty pages and totals
+/// echo the query they were asked with under `query`.
+async fn mock_micro_pay() -> String {
+    use common_x::restful::axum::{
+        Router,
+        extract::{Path, Query},
+        routing::{get, post},
+    };
+
+    async fn completed(Query(query): Query<std::collections::HashMap<String, String>>) -> String {
+        match query.get("info").map(String::as_str) {
+            Some("error") => json!({"error": "database unavailable", "code": 503}).to_string(),
+            Some("garbage") => "<html>upstream timed out</html>".to_string(),
+            _ => json!({"items": [], "pagination": {"count": 0}, "query": query}).to_string(),
+        }
+    }
+
+    let router = Router::new()
+        .route("/api/payment/completed", get(completed))
+        .route(
+            "/api/payment/completed-total",
+            get(
+                |Query(query): Query<std::collections::HashMap<String, String>>| async move {
+                    Json(json!({ "total": 0, "query": query }))
+                },
+            ),
+        )
+        .route("/api/payment/sender-did/{did}", get(completed))
+        .route(
+            "/api/payment/did-stats/{did}",
+            get(|Path(did): Path<String>| async move {
+                Json(json!({ "did": did, "received": 350, "sent": 0 }))
+            }),
+        )
+        .route(
+            "/api/payment/cancel/{tx_hash}",
+            post(|Path(tx_hash): Path<String>| async move { Json(json!({ "txHash": tx_hash })) }),
+        )
+        .route(
+            "/api/payment/prepare",
+            post(|| async { Json(json!({ "txHash": "tx-prepared" })) }),
+        )
+        .route(
+            "/api/payment/transfer",
+            post(|| async { Json(json!({ "paymentId": 1 })) }),
+        )
+        .route(
+            "/api/payment/id/{id}",
+            get(|| async {
+                Json(json!({ "payment": {
+                    "txHash": "tx1",
+                    "info": "app.bbs.post/at://did:ckb:alice/app.bbs.post/1",
+                    "senderDid": "did:ckb:bob",
+                    "amount": "100",
+                } }))
+            }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        common_x::restful::axum::serve(listener, router).await.ok();
+    });
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn incomplete_pages_are_not_empty_lists() {
+    let url = mock_micro_pay().await;
+
+    let page =
+        payment_page(micro_pay::payment_completed(&url, &[("info", "empty".to_string())]).await);
+    assert!(matches!(page, Ok((items, 0)) if items.is_empty()));
+
+    let page =
+        payment_page(micro_pay::payment_completed(&url, &[("info", "error".to_string())]).await);
+    assert!(matches!(page, Err(AppError::MicroPayIncomplete(msg)) if msg == "503"));
+
+    let page =
+        payment_page(micro_pay::payment_completed(&url, &[("info", "garbage".to_string())]).await);
+    assert!(matches!(page, Err(AppError::MicroPayIncomplete(msg)) if msg.starts_with("decode")));
+
+    let status = AppError::MicroPayIncomplete("503".to_string())
+        .into_response()
+        .status();
+    assert_eq!(
+        status,
+        common_x::restful::axum::http::StatusCode::BAD_GATEWAY
+    );
+}
+
+#[tokio::test]
+async fn detail_pages_are_capped() {
+    let state = AppView {
+        pay_url: mock_micro_pay().await,
+        ..AppView::for_tests(
+            sqlx::postgres::PgPoolOptions::new()
+                .acquire_timeout(std::time::Duration::from_millis(100))
+                .connect_lazy("postgres://127.0.0.1:9/bbs")
+                .unwrap(),
+        )
+    };
+    let per_page = async |per_page: Option<u64>| {
+        let response = expense_details(
+            State(state.clone()),
+            Valid(Json(DetailQuery {
+                did: "did:ckb:alice".to_string(),
+                per_page,
+                ..Default::default()
+            })),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        let bytes = common_x::restful::axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        body["data"]["per_page"].clone()
+    };
+    assert_eq!(per_page(None).await, 20);
+    assert_eq!(per_page(Some(100_000)).await, 100);
+}
+
+#[tokio::test]
+async fn infos_are_encoded_once() {
+    let url = mock_micro_pay().await;
+    let info = "app.bbs.post/at://did:ckb:alice/app.bbs.post/3k?a=1&b=2#c 帖子%20";
+
+    let total = micro_pay::payment_completed_total(&url, info)
+        .await
+        .unwrap();
+    assert_eq!(total["query"], json!({ "info": info }));
+
+    let page = micro_pay::payment_completed(
+        &url,
+        &[("info", info.to_string()), ("limit", "20".to_string())],
+    )
+    .await
+    .unwrap();
+    assert_eq!(page["query"], json!({ "info": info, "limit": "20" }));
+}
+
+#[test]
+fn tips_name_a_known_collection() {
+    let params: TipParams = serde_json::from_value(json!({ "nsid": "app.bbs.reply" })).unwrap();
+    assert_eq!(params.nsid, Collection::Reply);
+    let e = serde_json::from_value::<TipParams>(json!({ "nsid": "app.bbs.unknown" })).unwrap_err();
+    assert!(
+        e.to_string().contains("expected one of `app.bbs.post`"),
+        "{e}"
+    );
+}
+
+#[cfg(test)]
+async fn stats_of(state: AppView, did: &str) -> Value {
+    let response = stats(
+        State(state),
+        ValidQuery(DidQuery {
+            did: did.to_string(),
+        }),
+    )
+    .await
+    .unwrap()
+    .into_response();
+    let bytes = common_x::restful::axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let mut body: Value = serde_json::from_slice(&bytes).unwrap();
+    body["data"].take()
+}
+
+#[tokio::test]
+async fn stats_degrade_without_the_database() {
+    let db = sqlx::postgres::PgPoolOptions::new()
+        .acquire_timeout(std::time::Duration::from_millis(100))
+        .connect_lazy("postgres://127.0.0.1:9/bbs")
+        .unwrap();
+    let stats = stats_of(
+        AppView {
+            pay_url: mock_micro_pay().await,
+            ..AppView::for_tests(db)
+        },
+        "did:ckb:alice",
+    )
+    .await;
+    assert_eq!(
+        stats,
+        json!({
+            "upstream": { "did": "did:ckb:alice", "received": 350, "sent": 0 },
+            "supporters": null,
+            "rank": null,
+            "top_target": null,
+        })
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn stats_merge_upstream_and_local_tips() {
+    use sqlx::{Executor, query};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft) VALUES ('at://did:ckb:alice/app.bbs.post/1', 'bafy', 'did:ckb:alice', 1, 'Hello', 't', false)",
+        "INSERT INTO tip (category, sender, sender_did, receiver, receiver_did, amount, info, state) VALUES \
+         (0, 'ckt1bob', 'did:ckb:bob', 'ckt1alice', 'did:ckb:alice', 100, 'app.bbs.post/at://did:ckb:alice/app.bbs.post/1', 1), \
+         (0, 'ckt1carol', 'did:ckb:carol', 'ckt1alice', 'did:ckb:alice', 200, 'app.bbs.post/at://did:ckb:alice/app.bbs.post/1', 1), \
+         (0, 'ckt1bob', 'did:ckb:bob', 'ckt1alice', 'did:ckb:alice', 50, 'app.bbs.post/at://did:ckb:alice/app.bbs.post/2', 1), \
+         (0, 'ckt1erin', 'did:ckb:erin', 'ckt1alice', 'did:ckb:alice', 900, 'app.bbs.post/at://did:ckb:alice/app.bbs.post/2', 0), \
+         (1, 'ckt1erin', 'did:ckb:erin', 'ckt1alice', 'did:ckb:alice', 900, 'app.bbs.community/', 1), \
+         (0, 'ckt1bob', 'did:ckb:bob', 'ckt1dave', 'did:ckb:dave', 1000, 'app.bbs.post/at://did:ckb:dave/app.bbs.post/1', 1)",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+
+    let stats = stats_of(
+        AppView {
+            pay_url: mock_micro_pay().await,
+            ..AppView::for_tests(db.clone())
+        },
+        "did:ckb:alice",
+    )
+    .await;
+    assert_eq!(
+        stats,
+        json!({
+            "upstream": { "did": "did:ckb:alice", "received": 350, "sent": 0 },
+            "supporters": 2,
+            "rank": 2,
+            "top_target": {
+                "nsid": "app.bbs.post",
+                "uri": "at://did:ckb:alice/app.bbs.post/1",
+                "title": "Hello",
+                "amount": 300,
+            },
+        })
+    );
+
+    // micro_pay down
+    let stats = stats_of(AppView::for_tests(db), "did:ckb:dave").await;
+    assert_eq!(stats["upstream"], Value::Null);
+    assert_eq!(stats["supporters"], 1);
+    assert_eq!(stats["rank"], 1);
+    assert_eq!(stats["top_target"]["amount"], 1000);
+    assert_eq!(
+        stats["top_target"]["uri"],
+        "at://did:ckb:dave/app.bbs.post/1"
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn stale_tips_time_out_and_free_their_key() {
+    use sqlx::{Executor, query};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO tip (category, sender, sender_did, receiver, receiver_did, amount, info, state, tx_hash, idempotency_key, created) VALUES \
+         (0, 'ckt1bob', 'did:ckb:bob', 'ckt1alice', 'did:ckb:alice', 100, 'app.bbs.post/at://did:ckb:alice/app.bbs.post/1', 0, 'tx1', 'k1', now() - interval '1 hour'), \
+         (0, 'ckt1bob', 'did:ckb:bob', 'ckt1alice', 'did:ckb:alice', 200, 'app.bbs.post/at://did:ckb:alice/app.bbs.post/1', 0, 'tx2', 'k2', now())",
+        "INSERT INTO tip (category, sender, sender_did, receiver, receiver_did, amount, info, state, updated) VALUES \
+         (0, 'ckt1carol', 'did:ckb:carol', 'ckt1alice', 'did:ckb:alice', 300, 'app.bbs.post/at://did:ckb:alice/app.bbs.post/1', 2, now() - interval '60 days')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    let state = AppView {
+        pay_url: mock_micro_pay().await,
+        ..AppView::for_tests(db.clone())
+    };
+
+    let retried = TipRow {
+        id: -1,
+        category: TipCategory::Tip as i32,
+        sender: "ckt1bob".to_string(),
+        sender_did: "did:ckb:bob".to_string(),
+        receiver: "ckt1alice".to_string(),
+        receiver_did: "did:ckb:alice".to_string(),
+        amount: 100,
+        info: "app.bbs.post/at://did:ckb:alice/app.bbs.post/1".to_string(),
+        state: TipState::Prepared as i32,
+        tx_hash: None,
+        idempotency_key: Some("k1".to_string()),
+        updated: chrono::Local::now(),
+        created: chrono::Local::now(),
+    };
+
+    // the key is held while the tip is prepared
+    assert!(matches!(
+        prepare_payment(&state, &mut retried.clone(), &[]).await,
+        Err(AppError::Conflict(tip)) if tip["tx_hash"] == "tx1"
+    ));
+
+    // runs again, as the maintenance task does, without notifying twice
+    expire_stale(&state).await.unwrap();
+    expire_stale(&state).await.unwrap();
+
+    let tips: Vec<(String, i32, Option<String>)> =
+        sqlx::query_as("SELECT tx_hash, state, idempotency_key FROM tip ORDER BY id")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    assert_eq!(
+        tips,
+        [
+            ("tx1".to_string(), TipState::Timeout as i32, None),
+            (
+                "tx2".to_string(),
+                TipState::Prepared as i32,
+                Some("k2".to_string())
+            ),
+        ]
+    );
+    let notifies: Vec<(String, String, i32, String, i64)> =
+        sqlx::query_as("SELECT sender, receiver, n_type, target_uri, amount FROM notify")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+    assert_eq!(
+        notifies,
+        [(
+            "did:ckb:alice".to_string(),
+            "did:ckb:bob".to_string(),
+            NotifyType::TipExpired as i32,
+            "at://did:ckb:alice/app.bbs.post/1".to_string(),
+            100,
+        )]
+    );
+    let stats = expiry_stats();
+    assert!(stats["timed_out"].as_u64().unwrap() >= 1);
+    assert!(stats["purged"].as_u64().unwrap() >= 1);
+
+    // the key prepares a new tip now, the other one is still taken
+    let mut tip = retried.clone();
+    let payment = prepare_payment(&state, &mut tip, &[]).await.unwrap();
+    assert_eq!(tip.tx_hash.as_deref(), payment["txHash"].as_str());
+    assert!(
+        Tip::insert(
+            &db,
+            &TipRow {
+                idempotency_key: Some("k2".to_string()),
+                ..retried
+            }
+        )
+        .await
+        .unwrap()
+        .is_none()
+    );
+    let held: (Option<String>, i32) =
+        sqlx::query_as("SELECT tx_hash, state FROM tip WHERE id = $1")
+            .bind(tip.id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+    assert_eq!(held, (tip.tx_hash, TipState::Prepared as i32));
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn tips_on_muted_threads_are_not_notified() {
+    use crate::lexicon::thread_mute::ThreadMute;
+
+    let db = crate::db::test_pool().await;
+    let state = AppView {
+        pay_url: mock_micro_pay().await,
+        ..AppView::for_tests(db.clone())
+    };
+    let post = "at://did:ckb:alice/app.bbs.post/1";
+    let tip = async || {
+        transfer(State(state.clone()), Json(json!({})))
+            .await
+            .unwrap();
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT count(*) FROM \"notify\" WHERE \"receiver\" = $1 AND \"n_type\" = $2",
+        )
+        .bind("did:ckb:alice")
+        .bind(NotifyType::NewTip as i32)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        count
+    };
+
+    ThreadMute::insert(&db, "did:ckb:alice", post)
+        .await
+        .unwrap();
+    assert_eq!(tip().await, 0);
+    ThreadMute::delete(&db, "did:ckb:alice", post)
+        .await
+        .unwrap();
+    assert_eq!(tip().await, 1);
+}
@@ -0,0 +1,158 @@
+use common_x::restful::axum::{
+    Json,
+    extract::{FromRequest, FromRequestParts, Query, Request},
+    http::request::Parts,
+};
+use serde::de::DeserializeOwned;
+use validator::Validate;
+
+use crate::error::AppError;
+
+/// A JSON body that passed its `Validate` rules. It wraps the `Json` rather
+/// than the payload so that `utoipa::path` still finds the request body.
+pub(crate) struct Valid<E>(pub E);
+
+impl<T: Validate> Valid<Json<T>> {
+    /// Validates a body built by hand, for handlers called from elsewhere.
+    pub(crate) fn check(json: Json<T>) -> Result<Self, AppError> {
+        json.0.validate().map_err(AppError::invalid)?;
+        Ok(Self(json))
+    }
+}
+
+impl<S, T> FromRequest<S> for Valid<Json<T>>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let json = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|e| AppError::ValidateFailed(e.body_text()))?;
+        Self::check(json)
+    }
+}
+
+/// Query parameters that passed their `Validate` rules. Not a
+/// `Valid<Query<T>>`: `utoipa::path` would look for the `IntoParams` of the
+/// `Query` itself, so handlers name their `params` instead.
+pub(crate) struct ValidQuery<T>(pub T);
+
+impl<T: Validate> ValidQuery<T> {
+    /// Validates parameters built by hand, for handlers called from elsewhere.
+    pub(crate) fn check(query: T) -> Result<Self, AppError> {
+        query.validate().map_err(AppError::invalid)?;
+        Ok(Self(query))
+    }
+}
+
+impl<S, T> FromRequestParts<S> for ValidQuery<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| AppError::ValidateFailed(e.body_text()))?;
+        Self::check(query)
+    }
+}
+
+#[tokio::test]
+async fn every_failing_field_is_reported() {
+    use common_x::restful::axum::{
+        body::{Body, to_bytes},
+        http::{Request, StatusCode},
+        response::IntoResponse,
+    };
+    use serde_json::{Value, json};
+
+    use crate::api::SignedBody;
+
+    #[derive(Default, serde::Deserialize, Validate)]
+    #[serde(default)]
+    struct Params {
+        #[validate(length(min = 1))]
+        title: String,
+        #[validate(range(min = 1, max = 50))]
+        limit: u64,
+    }
+
+    let details = async |e: AppError| -> Value {
+        let response = e.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    };
+
+    let body = json!({
+        "params": { "title": "", "limit": 51 },
+        "did": "did:plc:alice",
+        "signing_key_did": "did:key:z",
+        "signed_bytes": "",
+    });
+    let request = Request::post("/")
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let Err(e) = Valid::<Json<SignedBody<Params>>>::from_request(request, &()).await else {
+        panic!("invalid body was accepted");
+    };
+    let body = details(e).await;
+    assert_eq!(body["error"], "ValidateFailed");
+    assert_eq!(
+        body["message"],
+        "invalid fields: params.limit, params.title, signing_key_did"
+    );
+    let fields: Vec<(&str, &str)> = body["details"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["field"].as_str().unwrap(), e["code"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        fields,
+        [
+            ("params.limit", "range"),
+            ("params.title", "length"),
+            ("signing_key_did", "length"),
+        ]
+    );
+    assert_eq!(
+        body["details"][2]["params"],
+        json!({ "equal": 57, "value": "did:key:z" })
+    );
+
+    let (mut parts, _) = Request::get("/api/section/trending?period=year&limit=0")
+        .body(())
+        .unwrap()
+        .into_parts();
+    let Err(e) =
+        ValidQuery::<crate::api::section::TrendingQuery>::from_request_parts(&mut parts, &()).await
+    else {
+        panic!("invalid query was accepted");
+    };
+    let body = details(e).await;
+    assert_eq!(body["details"][0]["field"], "limit");
+    assert_eq!(body["details"][1]["field"], "period");
+    assert_eq!(body["details"][1]["code"], "one_of");
+    assert_eq!(
+        body["details"][1]["params"]["allowed"],
+        json!(["day", "week", "month"])
+    );
+
+    // what does not parse is refused before any rule runs
+    let request = Request::post("/")
+        .header("content-type", "application/json")
+        .body(Body::from("{"))
+        .unwrap();
+    assert!(matches!(
+        Valid::<Json<Params>>::from_request(request, &()).await,
+        Err(AppError::ValidateFailed(_))
+    ));
+}
@@ -0,0 +1,159 @@
+use std::future::Future;
+
+use chrono::{DateTime, Local, Utc};
+use common_x::restful::axum::{
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::error::AppError;
+
+/// Request header naming the API version a client was written for.
+pub(crate) const HEADER: &str = "x-bbs-api-version";
+
+/// The query parameter naming it, for clients that cannot set headers.
+#[derive(Deserialize)]
+struct VersionQuery {
+    v: Option<String>,
+}
+
+/// The response shape a client was written for. Views read the version of
+/// the request they are serialized for through the toggles below, so the
+/// differences between versions live here rather than in each handler.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum ApiVersion {
+    /// What clients that name no version get: counts as strings, local
+    /// timestamps and the old field names next to the new ones.
+    #[default]
+    V1,
+    /// Counts as numbers, UTC timestamps and only the new field names.
+    V2,
+}
+
+impl ApiVersion {
+    pub(crate) const LATEST: Self = Self::V2;
+
+    fn parse(version: &str) -> Result<Self, AppError> {
+        match version.trim().trim_start_matches(['v', 'V']) {
+            "1" => Ok(Self::V1),
+            "2" => Ok(Self::V2),
+            _ => Err(AppError::ValidateFailed(format!(
+                "unknown api version `{version}`, expected 1 or 2"
+            ))),
+        }
+    }
+
+    /// Whether the response keeps a shape that is going away.
+    pub(crate) fn is_deprecated(self) -> bool {
+        self < Self::LATEST
+    }
+
+    /// The header wins over the `v` query parameter.
+    pub(crate) fn of(parts: &Parts) -> Result<Self, AppError> {
+        if let Some(version) = parts.headers.get(HEADER) {
+            let version = version
+                .to_str()
+                .map_err(|_| AppError::ValidateFailed("invalid api version".to_string()))?;
+            return Self::parse(version);
+        }
+        match Query::<VersionQuery>::try_from_uri(&parts.uri) {
+            Ok(Query(VersionQuery { v: Some(version) })) => Self::parse(&version),
+            _ => Ok(Self::default()),
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ApiVersion {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Self::of(parts)
+    }
+}
+
+tokio::task_local! {
+    static VERSION: ApiVersion;
+    static NUMERIC_JSON: bool;
+}
+
+/// Runs `f` with views serialized for `version`; with `numeric_json` the
+/// counts of `V1` go out as numbers too.
+pub(crate) async fn scope<F: Future>(version: ApiVersion, numeric_json: bool, f: F) -> F::Output {
+    VERSION
+        .scope(version, NUMERIC_JSON.scope(numeric_json, f))
+        .await
+}
+
+/// The version views are serialized for; `V1` outside of a request.
+pub(crate) fn current() -> ApiVersion {
+    VERSION.try_with(|version| *version).unwrap_or_default()
+}
+
+/// Counts, ids and amounts go out as JSON numbers.
+pub(crate) fn numeric_counts() -> bool {
+    current() >= ApiVersion::V2 || NUMERIC_JSON.try_with(|on| *on).unwrap_or_default()
+}
+
+/// Timestamps go out in UTC rather than the appview's offset.
+pub(crate) fn utc_timestamps() -> bool {
+    current() >= ApiVersion::V2
+}
+
+/// `skip_serializing_if` of the old name of a renamed field, which `V1`
+/// gets next to the new one.
+pub(crate) fn renamed<T>(_: &T) -> bool {
+    current() >= ApiVersion::V2
+}
+
+/// `serialize_with` of view timestamps, following `utc_timestamps`.
+pub(crate) fn timestamp<S: Serializer>(
+    time: &DateTime<Local>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    if utc_timestamps() {
+        time.with_timezone(&Utc).serialize(serializer)
+    } else {
+        time.serialize(serializer)
+    }
+}
+
+/// `timestamp` of optional ones.
+pub(crate) fn timestamp_opt<S: Serializer>(
+    time: &Option<DateTime<Local>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match time {
+        Some(time) => timestamp(time, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[test]
+fn versions_come_from_the_header_or_query() {
+    use common_x::restful::axum::http::Request;
+
+    let version = |request: Request<()>| ApiVersion::of(&request.into_parts().0);
+    let request = |uri: &str| Request::get(uri).body(()).unwrap();
+    assert_eq!(version(request("/api/post/list")).unwrap(), ApiVersion::V1);
+    assert_eq!(
+        version(request("/api/post/list?v=2")).unwrap(),
+        ApiVersion::V2
+    );
+    assert_eq!(
+        version(request("/api/post/detail?uri=at%3A%2F%2Fa&v=v1")).unwrap(),
+        ApiVersion::V1
+    );
+    let mut with_header = request("/api/post/list?v=1");
+    with_header
+        .headers_mut()
+        .insert(HEADER, "2".parse().unwrap());
+    assert_eq!(version(with_header).unwrap(), ApiVersion::V2);
+    assert!(matches!(
+        version(request("/api/post/list?v=3")),
+        Err(AppError::ValidateFailed(msg)) if msg.contains("`3`")
+    ));
+
+    assert!(ApiVersion::V1.is_deprecated());
+    assert!(!ApiVersion::LATEST.is_deprecated());
+}
@@ -0,0 +1,258 @@
+use color_eyre::eyre::eyre;
+use common_x::restful::{
+    axum::{Json, extract::State, response::IntoResponse},
+    ok, ok_simple,
+};
+use sea_query::{Expr, ExprTrait, Order, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::{
+    AppView,
+    api::{SignedBody, SignedParam, valid::Valid},
+    db,
+    error::AppError,
+    lexicon::{
+        administrator::Administrator,
+        webhook::{Webhook, WebhookDelivery, WebhookDeliveryRow, WebhookRow, WebhookView},
+    },
+};
+
+async fn check_admin<T: SignedParam>(
+    state: &AppView,
+    body: &SignedBody<T>,
+) -> Result<(), AppError> {
+    let admins = Administrator::all_did(&state.db).await;
+    if !admins.contains(&body.did) {
+        return Err(AppError::ValidateFailed(
+            "only administrator can manage webhooks".to_string(),
+        ));
+    }
+    body.verify_signature(&state)
+        .await
+        .map_err(|e| AppError::ValidateFailed(e.to_string()))
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct WebhookParams {
+    #[validate(url)]
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature sent in `X-BBS-Signature`.
+    #[validate(length(min = 16))]
+    pub secret: String,
+    /// Bit mask: 1 post created, 2 comment created, 4 reply created, 8 moderated.
+    #[validate(range(min = 1, max = 15))]
+    pub events: i32,
+    /// Only events of this section; "0" for all sections.
+    pub section_id: String,
+    pub timestamp: i64,
+}
+
+impl Default for WebhookParams {
+    fn default() -> Self {
+        Self {
+            url: Default::default(),
+            secret: Default::default(),
+            events: 15,
+            section_id: "0".to_string(),
+            timestamp: Default::default(),
+        }
+    }
+}
+
+impl SignedParam for WebhookParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+#[utoipa::path(post, path = "/api/admin/webhook/add")]
+pub(crate) async fn add(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<WebhookParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    check_admin(&state, &body).await?;
+
+    let id = Webhook::insert(
+        &state.db,
+        &body.params.url,
+        &body.params.secret,
+        body.params.events,
+        body.params.section_id.parse::<i32>()?,
+    )
+    .await?;
+
+    Ok(ok(json!({ "id": id.to_string() })))
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct UpdateWebhookParams {
+    pub id: String,
+    #[validate(url)]
+    pub url: Option<String>,
+    #[validate(length(min = 16))]
+    pub secret: Option<String>,
+    #[validate(range(min = 1, max = 15))]
+    pub events: Option<i32>,
+    pub section_id: Option<String>,
+    /// Re-enabling a webhook also clears its failure count.
+    pub enabled: Option<bool>,
+    pub timestamp: i64,
+}
+
+impl SignedParam for UpdateWebhookParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+#[utoipa::path(post, path = "/api/admin/webhook/update")]
+pub(crate) async fn update(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<UpdateWebhookParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    check_admin(&state, &body).await?;
+
+    let params = body.params;
+    let section_id = params.section_id.map(|id| id.parse::<i32>()).transpose()?;
+    Webhook::update(
+        &state.db,
+        params.id.parse::<i32>()?,
+        params.url,
+        params.secret,
+        params.events,
+        section_id,
+        params.enabled,
+    )
+    .await?;
+
+    Ok(ok_simple())
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct WebhookIdParams {
+    #[validate(length(min = 1))]
+    pub id: String,
+    pub timestamp: i64,
+}
+
+impl SignedParam for WebhookIdParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+#[utoipa::path(post, path = "/api/admin/webhook/delete")]
+pub(crate) async fn delete(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<WebhookIdParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    check_admin(&state, &body).await?;
+
+    Webhook::delete(&state.db, body.params.id.parse::<i32>()?).await?;
+
+    Ok(ok_simple())
+}
+
+#[derive(Debug, Default, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct WebhookListParams {
+    pub timestamp: i64,
+}
+
+impl SignedParam for WebhookListParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+#[utoipa::path(post, path = "/api/admin/webhook/list")]
+pub(crate) async fn list(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<WebhookListParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    check_admin(&state, &body).await?;
+
+    let (sql, values) = Webhook::build_select()
+        .order_by(Webhook::Id, Order::Asc)
+        .build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<WebhookRow> = db::fetch_all(&state.db, &sql, values)
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+
+    Ok(ok(rows
+        .into_iter()
+        .map(WebhookView::build)
+        .collect::<Vec<_>>()))
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct DeliveryQueryParams {
+    pub webhook_id: Option<String>,
+    #[validate(range(min = 1))]
+    pub page: u64,
+    #[validate(range(min = 1))]
+    pub per_page: u64,
+    pub timestamp: i64,
+}
+
+impl Default for DeliveryQueryParams {
+    fn default() -> Self {
+        Self {
+            webhook_id: None,
+            page: 1,
+            per_page: 20,
+            timestamp: Default::default(),
+        }
+    }
+}
+
+impl SignedParam for DeliveryQueryParams {
+    fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+#[utoipa::path(post, path = "/api/admin/webhook/deliveries")]
+pub(crate) async fn deliveries(
+    State(state): State<AppView>,
+    Valid(Json(body)): Valid<Json<SignedBody<DeliveryQueryParams>>>,
+) -> Result<impl IntoResponse, AppError> {
+    check_admin(&state, &body).await?;
+
+    let query = body.params;
+    let webhook_id = query.webhook_id.map(|id| id.parse::<i32>()).transpose()?;
+    let offset = query.per_page * (query.page - 1);
+    let (sql, values) = WebhookDelivery::build_select()
+        .and_where_option(webhook_id.map(|id| Expr::col(WebhookDelivery::WebhookId).eq(id)))
+        .order_by(WebhookDelivery::Id, Order::Desc)
+        .offset(offset)
+        .limit(query.per_page)
+        .build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<WebhookDeliveryRow> = db::fetch_all(&state.db, &sql, values)
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+
+    let (sql, values) = sea_query::Query::select()
+        .expr(Expr::col(WebhookDelivery::Id).count())
+        .from(WebhookDelivery::Table)
+        .and_where_option(webhook_id.map(|id| Expr::col(WebhookDelivery::WebhookId).eq(id)))
+        .build_sqlx(PostgresQueryBuilder);
+    let total: (i64,) = db::fetch_one(&state.db, &sql, values)
+        .await
+        .map_err(|e| eyre!("exec sql failed: {e}"))?;
+
+    Ok(ok(json!({
+        "deliveries": rows,
+        "page": query.page,
+        "per_page": query.per_page,
+        "total": total.0
+    })))
+}
@@ -0,0 +1,201 @@
+use color_eyre::{Result, eyre::eyre};
+use common_x::restful::{
+    axum::{Json, extract::State, http::StatusCode, response::IntoResponse},
+    ok,
+};
+use serde_json::{Value, json};
+use sqlx::{Executor, query};
+
+use crate::{AppView, config::AppConfig, error::AppError};
+
+/// The `did:web` document of the appview, or `None` when `service_did` is
+/// not configured. Fails when the DID does not name the host of
+/// `public_url`, so a misconfigured appview does not start.
+pub fn build_did_document(config: &AppConfig) -> Result<Option<Value>> {
+    if config.service_did.is_empty() {
+        return Ok(None);
+    }
+    let host = config
+        .service_did
+        .strip_prefix("did:web:")
+        .ok_or_else(|| eyre!("service_did must be a did:web, got {}", config.service_did))?
+        .replace("%3A", ":");
+    let public_url = reqwest::Url::parse(&config.public_url)
+        .map_err(|e| eyre!("invalid public_url {}: {e}", config.public_url))?;
+    let public_host = match (public_url.host_str(), public_url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(eyre!("public_url {} has no host", config.public_url)),
+    };
+    if host != public_host {
+        return Err(eyre!(
+            "service_did {} does not match the host of public_url {}",
+            config.service_did,
+            config.public_url
+        ));
+    }
+
+    let did = &config.service_did;
+    let mut document = json!({
+        "@context": [
+            "https://www.w3.org/ns/did/v1",
+            "https://w3id.org/security/multikey/v1"
+        ],
+        "id": did,
+        "service": [{
+            "id": "#bbs_appview",
+            "type": "BbsAppView",
+            "serviceEndpoint": config.public_url.trim_end_matches('/'),
+        }],
+    });
+    if let Some(key) = &config.service_signing_key {
+        document["verificationMethod"] = json!([{
+            "id": format!("{did}#atproto"),
+            "type": "Multikey",
+            "controller": did,
+            "publicKeyMultibase": key,
+        }]);
+    }
+    Ok(Some(document))
+}
+
+#[utoipa::path(
+    get,
+    path = "/.well-known/did.json",
+    responses(
+        (status = 200, description = "DID document of the appview"),
+        (status = 404, description = "service_did is not configured")
+    )
+)]
+pub(crate) async fn did_document(
+    State(state): State<AppView>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .did_document
+        .as_ref()
+        .map(|document| Json(document.clone()))
+        .ok_or(AppError::NotFound)
+}
+
+#[utoipa::path(
+    get,
+    path = "/xrpc/_health",
+    responses((status = 200, description = "Version of the running appview"))
+)]
+pub(crate) async fn health() -> impl IntoResponse {
+    Json(json!({ "version": env!("CARGO_PKG_VERSION") }))
+}
+
+/// Readiness for load balancers: not ready while the database does not
+/// answer, or the relayer is unhealthy when `readiness.relayer_lag_unready`
+/// is set.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "Ready to serve"),
+        (status = 503, description = "Not ready")
+    )
+)]
+pub(crate) async fn readyz(State(state): State<AppView>) -> impl IntoResponse {
+    let database = state.db.execute(query("SELECT 1")).await.is_ok();
+    let relayer = state.relayer.is_healthy(chrono::Local::now());
+    let ready = database && (relayer || !state.relayer.lag_marks_unready());
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(json!({ "ready": ready, "database": database, "relayer": relayer })),
+    )
+}
+
+/// Version of the appview and its `MaintenanceState`, so clients can tell
+/// users why writes are refused.
+#[utoipa::path(get, path = "/api/status")]
+pub(crate) async fn status(State(state): State<AppView>) -> impl IntoResponse {
+    ok(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "maintenance": state.maintenance.state(),
+    }))
+}
+
+/// The server time in Unix seconds under `now`, with the clock skew
+/// signed timestamps may have, so clients with a wrong clock can sign with
+/// an offset.
+#[utoipa::path(get, path = "/api/time")]
+pub(crate) async fn time(State(state): State<AppView>) -> impl IntoResponse {
+    let skew = &state.clock_skew;
+    ok(json!({
+        "now": chrono::Utc::now().timestamp(),
+        "past_skew_secs": skew.past_secs,
+        "future_skew_secs": skew.future_secs,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(service_did: &str, public_url: &str, key: Option<&str>) -> AppConfig {
+        AppConfig {
+            service_did: service_did.to_string(),
+            public_url: public_url.to_string(),
+            service_signing_key: key.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn did_document_shape() {
+        let document = build_did_document(&config(
+            "did:web:bbs.example.com",
+            "https://bbs.example.com/",
+            Some("zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF"),
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(document["id"], "did:web:bbs.example.com");
+        assert_eq!(document["service"][0]["id"], "#bbs_appview");
+        assert_eq!(
+            document["service"][0]["serviceEndpoint"],
+            "https://bbs.example.com"
+        );
+        assert_eq!(
+            document["verificationMethod"][0]["id"],
+            "did:web:bbs.example.com#atproto"
+        );
+        assert_eq!(
+            document["verificationMethod"][0]["controller"],
+            "did:web:bbs.example.com"
+        );
+
+        let document = build_did_document(&config(
+            "did:web:localhost%3A8080",
+            "http://localhost:8080",
+            None,
+        ))
+        .unwrap()
+        .unwrap();
+        assert!(document.get("verificationMethod").is_none());
+    }
+
+    #[test]
+    fn did_must_match_public_url() {
+        assert!(build_did_document(&config("", "", None)).unwrap().is_none());
+        assert!(
+            build_did_document(&config(
+                "did:web:bbs.example.com",
+                "https://other.example.com",
+                None
+            ))
+            .is_err()
+        );
+        assert!(
+            build_did_document(&config("did:plc:abc", "https://bbs.example.com", None)).is_err()
+        );
+        assert!(build_did_document(&config("did:web:bbs.example.com", "not a url", None)).is_err());
+    }
+}
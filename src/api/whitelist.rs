@@ -1,20 +1,16 @@
 use color_eyre::eyre::eyre;
 use common_x::restful::{
-    axum::{
-        extract::{Query, State},
-        response::IntoResponse,
-    },
+    axum::{extract::State, response::IntoResponse},
     ok,
 };
 use sea_query::{Expr, ExprTrait, Order, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
 use serde::Deserialize;
 use serde_json::json;
-use sqlx::query_as_with;
 use utoipa::IntoParams;
 use validator::Validate;
 
-use crate::{AppView, error::AppError, lexicon::whitelist::Whitelist};
+use crate::{AppView, api::valid::ValidQuery, db, error::AppError, lexicon::whitelist::Whitelist};
 
 #[derive(Debug, Validate, Deserialize, IntoParams)]
 #[serde(default)]
@@ -37,11 +33,8 @@ impl Default for WhitelistQuery {
 #[utoipa::path(get, path = "/api/whitelist", params(WhitelistQuery))]
 pub(crate) async fn list(
     State(state): State<AppView>,
-    Query(query): Query<WhitelistQuery>,
+    ValidQuery(query): ValidQuery<WhitelistQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    query
-        .validate()
-        .map_err(|e| AppError::ValidateFailed(e.to_string()))?;
     let offset = query.per_page * (query.page - 1);
     let (sql, values) = sea_query::Query::select()
         .columns([Whitelist::Did])
@@ -51,8 +44,7 @@ pub(crate) async fn list(
         .limit(query.per_page)
         .build_sqlx(PostgresQueryBuilder);
 
-    let rows: Vec<(String,)> = query_as_with(&sql, values.clone())
-        .fetch_all(&state.db)
+    let rows: Vec<(String,)> = db::fetch_all(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
     let views: Vec<String> = rows.iter().map(|row| row.0.clone()).collect();
@@ -62,8 +54,7 @@ pub(crate) async fn list(
         .from(Whitelist::Table)
         .build_sqlx(PostgresQueryBuilder);
 
-    let total: (i64,) = query_as_with(&sql, values.clone())
-        .fetch_one(&state.db)
+    let total: (i64,) = db::fetch_one(&state.db, &sql, values.clone())
         .await
         .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
@@ -0,0 +1,331 @@
+//! XRPC forms of the main read endpoints, for atproto clients.
+//!
+//! Each method takes camelCase query parameters, translates them into the
+//! query struct of its REST twin and runs the same handler:
+//!
+//! | XRPC method               | REST endpoint            |
+//! |---------------------------|--------------------------|
+//! | `app.bbs.feed.getPosts`   | `POST /api/post/list`    |
+//! | `app.bbs.feed.getThread`  | `GET /api/post/detail`   |
+//! | `app.bbs.section.list`    | `GET /api/section/list`  |
+//!
+//! A success returns the REST `data` without the `{code, message, data}`
+//! envelope; a failure returns `{"error", "message"}` with the REST status.
+
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use common_x::restful::axum::{
+    Json,
+    body::{Body, to_bytes},
+    extract::{Query, State, rejection::QueryRejection},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use utoipa::IntoParams;
+
+use crate::{
+    AppView,
+    api::{
+        post, section,
+        valid::{Valid, ValidQuery},
+    },
+    error::AppError,
+};
+
+/// Parameters of `app.bbs.feed.getPosts`, see `post::PostQuery`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct GetPostsParams {
+    /// `section_id`
+    pub section_id: Option<String>,
+    /// `is_announcement`
+    pub is_announcement: bool,
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+    pub q: Option<String>,
+    pub repo: Option<String>,
+    /// Only counts with a bearer token of the viewer's PDS session.
+    pub viewer: Option<String>,
+    /// `include_drafts`
+    pub include_drafts: bool,
+    /// `include_replies`
+    pub include_replies: bool,
+}
+
+impl Default for GetPostsParams {
+    fn default() -> Self {
+        post::PostQuery::default().into()
+    }
+}
+
+impl From<post::PostQuery> for GetPostsParams {
+    fn from(query: post::PostQuery) -> Self {
+        Self {
+            section_id: query.section_id,
+            is_announcement: query.is_announcement,
+            cursor: query.cursor,
+            limit: query.limit,
+            q: query.q,
+            repo: query.repo,
+            viewer: query.viewer,
+            include_drafts: query.include_drafts,
+            include_replies: query.include_replies,
+        }
+    }
+}
+
+impl From<GetPostsParams> for post::PostQuery {
+    fn from(params: GetPostsParams) -> Self {
+        Self {
+            section_id: params.section_id,
+            is_announcement: params.is_announcement,
+            cursor: params.cursor,
+            limit: params.limit,
+            q: params.q,
+            repo: params.repo,
+            viewer: params.viewer,
+            include_drafts: params.include_drafts,
+            include_replies: params.include_replies,
+        }
+    }
+}
+
+/// Parameters of `app.bbs.feed.getThread`, see `post::DetailQuery`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct GetThreadParams {
+    pub uri: String,
+    /// Only counts with a bearer token of the viewer's PDS session.
+    pub viewer: Option<String>,
+    pub rkey: Option<String>,
+    /// `repo_handle`
+    pub repo_handle: Option<String>,
+    /// `section_id`
+    pub section_id: Option<String>,
+    pub source: Option<String>,
+}
+
+impl From<GetThreadParams> for post::DetailQuery {
+    fn from(params: GetThreadParams) -> Self {
+        Self {
+            uri: params.uri,
+            viewer: params.viewer,
+            rkey: params.rkey,
+            repo_handle: params.repo_handle,
+            section_id: params.section_id,
+            source: params.source,
+        }
+    }
+}
+
+/// Parameters of `app.bbs.section.list`, see `section::SectionQuery`.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct ListSectionsParams {
+    pub repo: Option<String>,
+    /// `is_disabled`
+    pub is_disabled: Option<bool>,
+}
+
+impl From<ListSectionsParams> for section::SectionQuery {
+    fn from(params: ListSectionsParams) -> Self {
+        Self {
+            repo: params.repo,
+            is_disabled: params.is_disabled,
+        }
+    }
+}
+
+fn xrpc_error(status: StatusCode, error: &str, message: &str) -> Response {
+    (status, Json(json!({ "error": error, "message": message }))).into_response()
+}
+
+/// Rewrites a REST response into its XRPC form.
+async fn into_xrpc(response: Response) -> Response {
+    let (parts, body) = response.into_parts();
+    let body: Value = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+        Err(e) => {
+            return xrpc_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "InternalServerError",
+                &e.to_string(),
+            );
+        }
+    };
+    if !parts.status.is_success() {
+        return xrpc_error(
+            parts.status,
+            body["error"].as_str().unwrap_or("InternalServerError"),
+            body["message"].as_str().unwrap_or_default(),
+        );
+    }
+    let mut response = Response::from_parts(parts, Body::from(body["data"].to_string()));
+    response.headers_mut().remove(header::CONTENT_LENGTH);
+    response
+}
+
+async fn xrpc<R: IntoResponse>(result: Result<R, AppError>) -> Response {
+    into_xrpc(result.into_response()).await
+}
+
+fn invalid_request(rejection: QueryRejection) -> Response {
+    xrpc_error(
+        StatusCode::BAD_REQUEST,
+        "InvalidRequest",
+        &rejection.body_text(),
+    )
+}
+
+/// Posts of a section or an author; the XRPC form of `/api/post/list`.
+#[utoipa::path(
+    get,
+    path = "/xrpc/app.bbs.feed.getPosts",
+    params(GetPostsParams),
+    responses(
+        (status = 200, description = "The `data` of `/api/post/list`"),
+        (status = 400, description = "`{\"error\", \"message\"}`")
+    )
+)]
+pub(crate) async fn get_posts(
+    State(state): State<AppView>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    params: Result<Query<GetPostsParams>, QueryRejection>,
+) -> Response {
+    match params {
+        Ok(Query(params)) => match Valid::check(Json(post::PostQuery::from(params))) {
+            Ok(query) => xrpc(post::list(State(state), auth, query).await).await,
+            Err(e) => into_xrpc(e.into_response()).await,
+        },
+        Err(rejection) => invalid_request(rejection),
+    }
+}
+
+/// A post by uri; the XRPC form of `/api/post/detail`.
+#[utoipa::path(
+    get,
+    path = "/xrpc/app.bbs.feed.getThread",
+    params(GetThreadParams),
+    responses(
+        (status = 200, description = "The `data` of `/api/post/detail`"),
+        (status = 404, description = "`{\"error\", \"message\"}`")
+    )
+)]
+pub(crate) async fn get_thread(
+    State(state): State<AppView>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    params: Result<Query<GetThreadParams>, QueryRejection>,
+) -> Response {
+    match params {
+        Ok(Query(params)) => match ValidQuery::check(post::DetailQuery::from(params)) {
+            Ok(query) => xrpc(post::detail(State(state), auth, query).await).await,
+            Err(e) => into_xrpc(e.into_response()).await,
+        },
+        Err(rejection) => invalid_request(rejection),
+    }
+}
+
+/// Sections visible to `repo`; the XRPC form of `/api/section/list`.
+#[utoipa::path(
+    get,
+    path = "/xrpc/app.bbs.section.list",
+    params(ListSectionsParams),
+    responses(
+        (status = 200, description = "The `data` of `/api/section/list`"),
+        (status = 400, description = "`{\"error\", \"message\"}`")
+    )
+)]
+pub(crate) async fn list_sections(
+    State(state): State<AppView>,
+    params: Result<Query<ListSectionsParams>, QueryRejection>,
+) -> Response {
+    match params {
+        Ok(Query(params)) => match ValidQuery::check(section::SectionQuery::from(params)) {
+            Ok(query) => xrpc(section::list(State(state), query).await).await,
+            Err(e) => into_xrpc(e.into_response()).await,
+        },
+        Err(rejection) => invalid_request(rejection),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common_x::restful::{axum::http::Uri, ok};
+
+    use super::*;
+
+    fn query<T: serde::de::DeserializeOwned>(uri: &str) -> T {
+        Query::<T>::try_from_uri(&uri.parse::<Uri>().unwrap())
+            .unwrap()
+            .0
+    }
+
+    async fn body(response: Response) -> (StatusCode, Value) {
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn params_match_rest_queries() {
+        let rest: post::PostQuery = serde_json::from_value(json!({
+            "section_id": "1",
+            "is_announcement": true,
+            "cursor": "1700000000",
+            "limit": 5,
+            "repo": "did:ckb:alice",
+        }))
+        .unwrap();
+        let xrpc: GetPostsParams = query(
+            "/xrpc/app.bbs.feed.getPosts?sectionId=1&isAnnouncement=true&cursor=1700000000&limit=5&repo=did:ckb:alice",
+        );
+        assert_eq!(
+            format!("{rest:?}"),
+            format!("{:?}", post::PostQuery::from(xrpc))
+        );
+        let xrpc: GetPostsParams = query("/xrpc/app.bbs.feed.getPosts");
+        assert_eq!(
+            format!("{:?}", post::PostQuery::default()),
+            format!("{:?}", post::PostQuery::from(xrpc))
+        );
+
+        let rest: post::DetailQuery =
+            query("/api/post/detail?rkey=3kabc&repo_handle=alice.bbs&viewer=did:ckb:bob");
+        let xrpc: GetThreadParams = query(
+            "/xrpc/app.bbs.feed.getThread?rkey=3kabc&repoHandle=alice.bbs&viewer=did:ckb:bob",
+        );
+        assert_eq!(
+            format!("{rest:?}"),
+            format!("{:?}", post::DetailQuery::from(xrpc))
+        );
+
+        let rest: section::SectionQuery =
+            query("/api/section/list?repo=did:ckb:alice&is_disabled=false");
+        let xrpc: ListSectionsParams =
+            query("/xrpc/app.bbs.section.list?repo=did:ckb:alice&isDisabled=false");
+        assert_eq!(
+            format!("{rest:?}"),
+            format!("{:?}", section::SectionQuery::from(xrpc))
+        );
+    }
+
+    #[tokio::test]
+    async fn payload_matches_rest_data() {
+        let data = json!([{ "id": "1", "name": "ckb" }]);
+        let (rest_status, rest) = body(ok(data.clone()).into_response()).await;
+        let (status, payload) = body(xrpc(Ok::<_, AppError>(ok(data))).await).await;
+        assert_eq!(rest_status, status);
+        assert_eq!(rest["data"], payload);
+
+        let (status, payload) = body(xrpc(Err::<(), _>(AppError::NotFound)).await).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            payload,
+            json!({ "error": "NotFound", "message": "NOT_FOUND" })
+        );
+    }
+}
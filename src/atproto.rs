@@ -1,16 +1,179 @@
-use std::time::Duration;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use color_eyre::{Result, eyre::eyre};
+use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
+use utoipa::ToSchema;
 
 pub const NSID_POST: &str = "app.bbs.post";
 pub const NSID_COMMENT: &str = "app.bbs.comment";
 pub const NSID_REPLY: &str = "app.bbs.reply";
 pub const NSID_LIKE: &str = "app.bbs.like";
+pub const NSID_FOLLOW: &str = "app.bbs.follow";
 pub const NSID_SECTION: &str = "app.bbs.section";
 pub const NSID_COMMUNITY: &str = "app.bbs.community";
 pub const NSID_PROFILE: &str = "app.actor.profile";
 
+/// The record collections of the forum, serialized as their NSID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+pub enum Collection {
+    #[default]
+    #[serde(rename = "app.bbs.post")]
+    Post,
+    #[serde(rename = "app.bbs.comment")]
+    Comment,
+    #[serde(rename = "app.bbs.reply")]
+    Reply,
+    #[serde(rename = "app.bbs.like")]
+    Like,
+    #[serde(rename = "app.bbs.follow")]
+    Follow,
+    #[serde(rename = "app.bbs.section")]
+    Section,
+    #[serde(rename = "app.bbs.community")]
+    Community,
+    #[serde(rename = "app.actor.profile")]
+    Profile,
+}
+
+impl Collection {
+    pub const ALL: [Self; 8] = [
+        Self::Post,
+        Self::Comment,
+        Self::Reply,
+        Self::Like,
+        Self::Follow,
+        Self::Section,
+        Self::Community,
+        Self::Profile,
+    ];
+
+    pub const fn nsid(self) -> &'static str {
+        match self {
+            Self::Post => NSID_POST,
+            Self::Comment => NSID_COMMENT,
+            Self::Reply => NSID_REPLY,
+            Self::Like => NSID_LIKE,
+            Self::Follow => NSID_FOLLOW,
+            Self::Section => NSID_SECTION,
+            Self::Community => NSID_COMMUNITY,
+            Self::Profile => NSID_PROFILE,
+        }
+    }
+}
+
+impl FromStr for Collection {
+    type Err = String;
+
+    fn from_str(nsid: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|c| c.nsid() == nsid)
+            .ok_or_else(|| {
+                let valid: Vec<_> = Self::ALL.iter().map(|c| c.nsid()).collect();
+                format!(
+                    "unknown collection `{nsid}`, expected one of {}",
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for Collection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.nsid())
+    }
+}
+
+/// The sortable base32 alphabet of TIDs.
+const TID_ALPHABET: &[u8; 32] = b"234567abcdefghijklmnopqrstuvwxyz";
+
+/// Encodes a TID: 53 bits of microseconds since the Unix epoch followed by
+/// 10 bits of clock id, as 13 characters that sort like the timestamp.
+pub fn encode_tid(micros: u64, clock_id: u16) -> String {
+    let n = ((micros & ((1 << 53) - 1)) << 10) | u64::from(clock_id & 0x3ff);
+    (0..13)
+        .rev()
+        .map(|i| TID_ALPHABET[((n >> (i * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+static LAST_TID_MICROS: AtomicU64 = AtomicU64::new(0);
+
+/// A record key for a new record: later calls sort after earlier ones, even
+/// within the same microsecond.
+pub fn next_tid() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_micros()).unwrap_or(u64::MAX));
+    let last = LAST_TID_MICROS
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+            Some(now.max(last + 1))
+        })
+        .unwrap_or_default();
+    encode_tid(now.max(last + 1), (std::process::id() & 0x3ff) as u16)
+}
+
+/// A non-2xx response of the PDS, or of the did indexer next to it, with
+/// the XRPC `error` and `message` of its body when it has them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PdsError {
+    pub status: u16,
+    pub error: String,
+    pub message: String,
+}
+
+impl PdsError {
+    /// Reads the XRPC error body `{"error": .., "message": ..}`; anything
+    /// else, like the HTML page of a proxy, becomes the message as is.
+    pub fn from_body(status: u16, body: &str) -> Self {
+        let json = serde_json::from_str::<Value>(body).unwrap_or_default();
+        let field = |key: &str| json.get(key).and_then(|v| v.as_str()).map(str::to_owned);
+        let reason = reqwest::StatusCode::from_u16(status)
+            .ok()
+            .and_then(|status| status.canonical_reason())
+            .unwrap_or("Unknown");
+        Self {
+            status,
+            error: field("error").unwrap_or_else(|| reason.to_string()),
+            message: field("message").unwrap_or_else(|| body.trim().chars().take(200).collect()),
+        }
+    }
+
+    /// The credentials sent along were refused, rather than the PDS failing.
+    pub fn is_auth(&self) -> bool {
+        self.status == 401
+    }
+
+    /// The record asked for does not exist.
+    pub fn is_not_found(&self) -> bool {
+        self.status == 404 || self.error == "RecordNotFound"
+    }
+}
+
+impl fmt::Display for PdsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.status, self.error, self.message)
+    }
+}
+
+impl std::error::Error for PdsError {}
+
+/// Passes a 2xx `response` on and turns any other into a `PdsError`.
+pub async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(PdsError::from_body(status.as_u16(), &body).into())
+}
+
 #[allow(dead_code)]
 pub async fn create_record(
     url: &str,
@@ -19,7 +182,7 @@ pub async fn create_record(
     nsid: &str,
     record: &Value,
 ) -> Result<Value> {
-    reqwest::Client::new()
+    let response = reqwest::Client::new()
         .post(format!("{url}/xrpc/com.atproto.repo.createRecord"))
         .bearer_auth(auth)
         .header("Content-Type", "application/json; charset=utf-8")
@@ -35,21 +198,52 @@ pub async fn create_record(
         )
         .send()
         .await
-        .map_err(|e| eyre!("call pds failed: {e}"))?
+        .map_err(|e| eyre!("call pds failed: {e}"))?;
+    check_status(response)
+        .await?
         .json::<Value>()
         .await
         .map_err(|e| eyre!("decode pds response failed: {e}"))
 }
 
 pub async fn get_record(url: &str, repo: &str, nsid: &str, rkey: &str) -> Result<Value> {
-    reqwest::Client::new()
+    let response = reqwest::Client::new()
         .get(format!("{url}/xrpc/com.atproto.repo.getRecord"))
         .query(&[("repo", repo), ("collection", nsid), ("rkey", rkey)])
         .header("Content-Type", "application/json; charset=utf-8")
         .timeout(Duration::from_secs(5))
         .send()
         .await
-        .map_err(|e| eyre!("call pds failed: {e}"))?
+        .map_err(|e| eyre!("call pds failed: {e}"))?;
+    check_status(response)
+        .await?
+        .json::<Value>()
+        .await
+        .map_err(|e| eyre!("decode pds response failed: {e}"))
+}
+
+/// A page of the records of a collection, with the `cursor` of the next
+/// page while there is one.
+pub async fn list_records(
+    url: &str,
+    repo: &str,
+    nsid: &str,
+    cursor: Option<&str>,
+) -> Result<Value> {
+    let mut query = vec![("repo", repo), ("collection", nsid), ("limit", "100")];
+    if let Some(cursor) = cursor {
+        query.push(("cursor", cursor));
+    }
+    let response = reqwest::Client::new()
+        .get(format!("{url}/xrpc/com.atproto.repo.listRecords"))
+        .query(&query)
+        .header("Content-Type", "application/json; charset=utf-8")
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+        .map_err(|e| eyre!("call pds failed: {e}"))?;
+    check_status(response)
+        .await?
         .json::<Value>()
         .await
         .map_err(|e| eyre!("decode pds response failed: {e}"))
@@ -64,7 +258,7 @@ pub async fn put_record(
     rkey: &str,
     record: &Value,
 ) -> Result<Value> {
-    reqwest::Client::new()
+    let response = reqwest::Client::new()
         .post(format!("{url}/xrpc/com.atproto.repo.putRecord"))
         .bearer_auth(auth)
         .header("Content-Type", "application/json; charset=utf-8")
@@ -81,7 +275,42 @@ pub async fn put_record(
         )
         .send()
         .await
-        .map_err(|e| eyre!("call pds failed: {e}"))?
+        .map_err(|e| eyre!("call pds failed: {e}"))?;
+    check_status(response)
+        .await?
+        .json::<Value>()
+        .await
+        .map_err(|e| eyre!("decode pds response failed: {e}"))
+}
+
+pub async fn resolve_handle(url: &str, handle: &str) -> Result<Value> {
+    let response = reqwest::Client::new()
+        .get(format!("{url}/xrpc/com.atproto.identity.resolveHandle"))
+        .query(&[("handle", handle)])
+        .header("Content-Type", "application/json; charset=utf-8")
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+        .map_err(|e| eyre!("call pds failed: {e}"))?;
+    check_status(response)
+        .await?
+        .json::<Value>()
+        .await
+        .map_err(|e| eyre!("decode pds response failed: {e}"))
+}
+
+/// Resolve the account behind a bearer token.
+pub async fn get_session(url: &str, auth: &str) -> Result<Value> {
+    let response = reqwest::Client::new()
+        .get(format!("{url}/xrpc/com.atproto.server.getSession"))
+        .bearer_auth(auth)
+        .header("Content-Type", "application/json; charset=utf-8")
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+        .map_err(|e| eyre!("call pds failed: {e}"))?;
+    check_status(response)
+        .await?
         .json::<Value>()
         .await
         .map_err(|e| eyre!("decode pds response failed: {e}"))
@@ -96,7 +325,7 @@ pub async fn direct_writes(
     ckb_addr: &str,
     root: &Value,
 ) -> Result<Value> {
-    reqwest::Client::new()
+    let response = reqwest::Client::new()
         .post(format!("{url}/xrpc/fans.web5.ckb.directWrites"))
         .bearer_auth(auth)
         .header("Content-Type", "application/json; charset=utf-8")
@@ -114,14 +343,19 @@ pub async fn direct_writes(
         )
         .send()
         .await
-        .map_err(|e| eyre!("call pds failed: {e}"))?
+        .map_err(|e| eyre!("call pds failed: {e}"))?;
+    let written = check_status(response)
+        .await?
         .json()
         .await
-        .map_err(|e| eyre!("read pds response failed: {e}"))
+        .map_err(|e| eyre!("read pds response failed: {e}"))?;
+    // the PDS took the write for `repo` with this token
+    crate::audit::verified_actor(repo);
+    Ok(written)
 }
 
 pub async fn index_query(url: &str, did: &str, item: &str) -> Result<Value> {
-    reqwest::Client::new()
+    let response = reqwest::Client::new()
         .post(format!("{url}/xrpc/fans.web5.ckb.indexQuery"))
         .header("Content-Type", "application/json; charset=utf-8")
         .timeout(Duration::from_secs(5))
@@ -136,8 +370,119 @@ pub async fn index_query(url: &str, did: &str, item: &str) -> Result<Value> {
         )
         .send()
         .await
-        .map_err(|e| eyre!("call pds failed: {e}"))?
+        .map_err(|e| eyre!("call pds failed: {e}"))?;
+    check_status(response)
+        .await?
         .json()
         .await
         .map_err(|e| eyre!("read pds response failed: {e}"))
 }
+
+#[test]
+fn collections_round_trip_as_nsids() {
+    for collection in Collection::ALL {
+        let nsid = json!(collection.nsid());
+        assert_eq!(serde_json::to_value(collection).unwrap(), nsid);
+        assert_eq!(
+            serde_json::from_value::<Collection>(nsid).unwrap(),
+            collection
+        );
+        assert_eq!(collection.to_string().parse::<Collection>(), Ok(collection));
+    }
+
+    let e = serde_json::from_value::<Collection>(json!("app.bbs.unknown")).unwrap_err();
+    assert!(e.to_string().contains("`app.bbs.post`"), "{e}");
+    let e = "app.bbs.unknown".parse::<Collection>().unwrap_err();
+    assert!(e.starts_with("unknown collection `app.bbs.unknown`"));
+    assert!(e.contains(NSID_PROFILE));
+}
+
+#[test]
+fn tids_sort_by_time() {
+    assert_eq!(encode_tid(0, 0), "2222222222222");
+    assert_eq!(encode_tid(1, 0), "2222222222322");
+    assert_eq!(encode_tid(1_700_000_000_000_000, 0), "3ke6kg3wk2222");
+    assert_eq!(encode_tid(1_700_000_000_000_000, 1023), "3ke6kg3wk22zz");
+    // the top bit stays clear
+    assert_eq!(encode_tid(u64::MAX, u16::MAX), "bzzzzzzzzzzzz");
+    assert!(encode_tid(1, 0) > encode_tid(0, 1023));
+
+    let mut last = next_tid();
+    for _ in 0..1000 {
+        let tid = next_tid();
+        assert_eq!(tid.len(), 13);
+        assert!(tid > last, "{tid} <= {last}");
+        last = tid;
+    }
+}
+
+#[tokio::test]
+async fn pds_errors_keep_their_status() {
+    use common_x::restful::axum::{
+        Json, Router, http::StatusCode, response::IntoResponse, routing::get,
+    };
+
+    use crate::error::AppError;
+
+    let router = Router::new()
+        .route(
+            "/xrpc/com.atproto.server.getSession",
+            get(|| async {
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(json!({ "error": "AuthRequired", "message": "Bad token" })),
+                )
+            }),
+        )
+        .route(
+            "/xrpc/com.atproto.repo.getRecord",
+            get(|| async {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "<html><body>upstream down</body></html>",
+                )
+            }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let pds = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(common_x::restful::axum::serve(listener, router));
+
+    let e = crate::api::check_session(&pds, "token", "did:ckb:alice")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        &e,
+        AppError::Pds(PdsError { status: 401, error, message })
+            if error == "AuthRequired" && message == "Bad token"
+    ));
+    assert_eq!(e.into_response().status(), StatusCode::UNAUTHORIZED);
+
+    let e = get_record(&pds, "did:ckb:alice", NSID_PROFILE, "self")
+        .await
+        .unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<PdsError>(),
+        Some(&PdsError {
+            status: 503,
+            error: "Service Unavailable".to_string(),
+            message: "<html><body>upstream down</body></html>".to_string(),
+        })
+    );
+    let response = AppError::rpc(e).into_response();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+    // errors that never reached the PDS stay as they were
+    let e = get_record("http://127.0.0.1:9", "did:ckb:alice", NSID_PROFILE, "self")
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(AppError::rpc(e), AppError::RpcFailed(msg) if msg.starts_with("call pds failed"))
+    );
+    assert!(
+        PdsError::from_body(
+            400,
+            r#"{"error":"RecordNotFound","message":"Could not locate record"}"#
+        )
+        .is_not_found()
+    );
+}
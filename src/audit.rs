@@ -0,0 +1,113 @@
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use sqlx::{Pool, Postgres};
+use tokio::sync::mpsc;
+
+use crate::{
+    lexicon::audit_event::{AuditEvent, AuditEventRow},
+    maintenance::WRITES,
+};
+
+/// Events waiting for the writer task; past this they are dropped rather
+/// than slowing the requests down.
+pub const QUEUE_CAPACITY: usize = 1024;
+
+/// Events dropped because the queue was full or the writer task was gone.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+tokio::task_local! {
+    /// The actor an audited request proved to be, while it is handled.
+    static VERIFIED_ACTOR: RefCell<Option<String>>;
+}
+
+/// Whether calls to `path` leave an audit event: the `maintenance::WRITES`,
+/// and the maintenance and log level switches they leave out.
+pub fn is_audited(path: &str) -> bool {
+    WRITES.contains(&path) || matches!(path, "/api/admin/maintenance" | "/api/admin/log_level")
+}
+
+/// Names `did` as the actor of the audited request being handled, once its
+/// signature or its write to the PDS checked out; outside of one it does
+/// nothing.
+pub fn verified_actor(did: &str) {
+    VERIFIED_ACTOR
+        .try_with(|actor| *actor.borrow_mut() = Some(did.to_owned()))
+        .ok();
+}
+
+/// Runs `handle`, and returns its output with the actor it verified.
+pub async fn with_verified_actor<F: Future>(handle: F) -> (F::Output, Option<String>) {
+    VERIFIED_ACTOR
+        .scope(RefCell::new(None), async {
+            let output = handle.await;
+            (
+                output,
+                VERIFIED_ACTOR.with(|actor| actor.borrow_mut().take()),
+            )
+        })
+        .await
+}
+
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Queues audit events for the writer task, never waiting for room.
+#[derive(Debug, Clone)]
+pub struct Audit {
+    tx: mpsc::Sender<AuditEventRow>,
+}
+
+impl Audit {
+    pub fn channel(capacity: usize) -> (Self, mpsc::Receiver<AuditEventRow>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        (Self { tx }, rx)
+    }
+
+    pub fn record(&self, event: AuditEventRow) {
+        if let Err(e) = self.tx.try_send(event) {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            let event = e.into_inner();
+            warn!(
+                "audit queue refused the event of {} {}, dropped",
+                event.route, event.request_id
+            );
+        }
+    }
+}
+
+pub async fn run(db: Pool<Postgres>, mut rx: mpsc::Receiver<AuditEventRow>) {
+    while let Some(event) = rx.recv().await {
+        if let Err(e) = AuditEvent::insert(&db, &event).await {
+            error!("insert audit event {} failed: {e}", event.request_id);
+        }
+    }
+}
+
+#[test]
+fn full_queue_drops_and_counts() {
+    let (audit, mut rx) = Audit::channel(1);
+    let event = |request_id: &str| AuditEventRow {
+        id: 0,
+        actor: "did:ckb:alice".to_string(),
+        route: "/api/record/create".to_string(),
+        target: None,
+        outcome: 200,
+        latency_ms: 1,
+        request_id: request_id.to_string(),
+        created: chrono::Local::now(),
+    };
+    let before = dropped();
+    audit.record(event("1"));
+    audit.record(event("2"));
+    assert!(dropped() > before);
+    assert_eq!(rx.try_recv().unwrap().request_id, "1");
+    assert!(rx.try_recv().is_err());
+
+    assert!(is_audited("/api/tip/transfer"));
+    assert!(is_audited("/api/admin/log_level"));
+    assert!(!is_audited("/api/post/list"));
+}
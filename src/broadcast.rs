@@ -0,0 +1,255 @@
+use std::collections::HashSet;
+
+use color_eyre::{Result, eyre::eyre};
+use sea_query::{Expr, ExprTrait, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use sqlx::{Pool, Postgres};
+
+use crate::{
+    db,
+    lexicon::{
+        broadcast::{Broadcast, BroadcastRow, BroadcastState},
+        notify::{Notify, NotifyType},
+    },
+    recount,
+};
+
+/// Notifications written per insert.
+const BATCH_SIZE: usize = 500;
+
+/// Who a broadcast goes to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Audience {
+    All,
+    /// Authors of anything in the last this many days.
+    ActiveWithin(i32),
+    /// Authors of anything in this section.
+    Section(i32),
+}
+
+impl Audience {
+    pub fn new(active_days: Option<i32>, section_id: Option<i32>) -> Result<Self> {
+        match (active_days, section_id) {
+            (None, None) => Ok(Self::All),
+            (Some(days), None) => Ok(Self::ActiveWithin(days)),
+            (None, Some(id)) => Ok(Self::Section(id)),
+            (Some(_), Some(_)) => Err(eyre!("broadcast either to the active users or a section")),
+        }
+    }
+
+    fn filter(&self) -> Option<Expr> {
+        match self {
+            Self::All => None,
+            Self::ActiveWithin(days) => Some(Expr::col("created").gt(Expr::cust_with_values(
+                "now() - make_interval(days => $1)",
+                [*days],
+            ))),
+            Self::Section(id) => Some(Expr::col("section_id").eq(*id)),
+        }
+    }
+}
+
+/// Everyone the broadcast goes to.
+pub fn build_recipients(audience: &Audience) -> sea_query::SelectStatement {
+    recount::build_authors_matching(audience.filter())
+}
+
+/// Fans the broadcast out to its audience, recording the progress on its
+/// row. Failures leave it `failed` with the notifications sent so far.
+pub async fn run(db: Pool<Postgres>, id: i32) {
+    if let Err(e) = fan_out(&db, id).await {
+        error!("broadcast {id} failed: {e}");
+        Broadcast::update_state(&db, id, BroadcastState::Failed)
+            .await
+            .ok();
+    }
+}
+
+/// Picks up the broadcasts a restart interrupted. Those that never got
+/// their announcement post written fail; the others go on from the users
+/// they had not notified yet.
+pub async fn resume(db: &Pool<Postgres>) -> Result<()> {
+    for row in Broadcast::select_unfinished(db).await? {
+        if row.post_uri.is_empty() {
+            warn!("broadcast {} has no announcement post, failing it", row.id);
+            Broadcast::update_state(db, row.id, BroadcastState::Failed).await?;
+        } else {
+            info!("resuming broadcast {}", row.id);
+            tokio::spawn(run(db.clone(), row.id));
+        }
+    }
+    Ok(())
+}
+
+/// Users an earlier run of the broadcast announcing `post_uri` notified.
+pub fn build_notified(post_uri: &str) -> sea_query::SelectStatement {
+    sea_query::Query::select()
+        .column(Notify::Receiver)
+        .from(Notify::Table)
+        .and_where(Expr::col(Notify::NType).eq(NotifyType::Announcement as i32))
+        .and_where(Expr::col(Notify::TargetUri).eq(post_uri))
+        .take()
+}
+
+async fn fan_out(db: &Pool<Postgres>, id: i32) -> Result<()> {
+    let row: BroadcastRow = Broadcast::select_by_id(db, id).await?;
+    let audience = Audience::new(row.active_days, row.section_id)?;
+    let (sql, values) = build_recipients(&audience).build_sqlx(PostgresQueryBuilder);
+    let recipients: Vec<String> = db::fetch_all::<(String,), _>(db, &sql, values)
+        .await?
+        .into_iter()
+        .map(|(did,)| did)
+        .filter(|did| did != &row.sender)
+        .collect();
+    let (sql, values) = build_notified(&row.post_uri).build_sqlx(PostgresQueryBuilder);
+    let notified: HashSet<String> = db::fetch_all::<(String,), _>(db, &sql, values)
+        .await?
+        .into_iter()
+        .map(|(did,)| did)
+        .collect();
+    let (done, pending): (Vec<String>, Vec<String>) = recipients
+        .into_iter()
+        .partition(|did| notified.contains(did));
+    let total = (done.len() + pending.len()) as i64;
+    Broadcast::start(db, id, total, done.len() as i64).await?;
+    info!(
+        "broadcast {id} to {audience:?}: {total} users, {} left",
+        pending.len()
+    );
+
+    for batch in pending.chunks(BATCH_SIZE) {
+        let (sql, values) =
+            Notify::build_announcements(&row.title, &row.sender, &row.post_uri, batch)?
+                .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Broadcast::add_sent(db, id, batch.len() as i64).await?;
+    }
+    Broadcast::update_state(db, id, BroadcastState::Done).await?;
+    info!("broadcast {id} done");
+    Ok(())
+}
+
+#[test]
+fn audiences() {
+    assert!(Audience::new(Some(30), Some(3)).is_err());
+
+    let sql = build_recipients(&Audience::All).to_string(PostgresQueryBuilder);
+    assert!(sql.starts_with("SELECT \"repo\" FROM \"post\" UNION"));
+    let sql = build_recipients(&Audience::Section(3)).to_string(PostgresQueryBuilder);
+    assert_eq!(sql.matches("WHERE \"section_id\" = 3").count(), 4);
+    let (sql, values) =
+        build_recipients(&Audience::ActiveWithin(30)).build_sqlx(PostgresQueryBuilder);
+    assert!(sql.contains("WHERE \"created\" > now() - make_interval(days => $4)"));
+    assert_eq!(values.0.0, vec![30.into(); 4]);
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn broadcast_reaches_every_author_once() {
+    use sqlx::{Executor, query};
+
+    use crate::lexicon::notify::NotifyRow;
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft) VALUES ('p1', 'bafy', 'did:ckb:alice', 1, 't', 't', false), ('p2', 'bafy', 'did:ckb:root', 1, 't', 't', false)",
+        "INSERT INTO comment (uri, cid, repo, section_id, post, text) VALUES ('c1', 'bafy', 'did:ckb:alice', 1, 'p2', 't'), ('c2', 'bafy', 'did:ckb:bob', 2, 'p3', 't')",
+        "INSERT INTO \"like\" (uri, cid, repo, section_id, \"to\", created) VALUES ('l1', 'bafy', 'did:ckb:carol', 1, 'p1', now() - interval '90 days')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+
+    let row = BroadcastRow {
+        sender: "did:ckb:root".to_string(),
+        idempotency_key: "maintenance-1".to_string(),
+        title: "Maintenance tonight".to_string(),
+        body: "The forum is read only from 22:00.".to_string(),
+        post_uri: "at://did:ckb:root/app.bbs.post/1".to_string(),
+        active_days: Some(30),
+        ..Default::default()
+    };
+    let (id, queued) = Broadcast::insert(&db, &row).await.unwrap();
+    assert!(queued);
+    assert_eq!(Broadcast::insert(&db, &row).await.unwrap(), (id, false));
+    let state = |row: BroadcastRow| (BroadcastState::name(row.state), row.total, row.sent);
+    assert_eq!(
+        state(Broadcast::select_by_id(&db, id).await.unwrap()),
+        ("queued", 0, 0)
+    );
+
+    run(db.clone(), id).await;
+    assert_eq!(
+        state(Broadcast::select_by_id(&db, id).await.unwrap()),
+        ("done", 2, 2)
+    );
+
+    // a restart halfway goes on from the users not notified yet
+    db.execute(query("DELETE FROM notify WHERE receiver = 'did:ckb:alice'"))
+        .await
+        .unwrap();
+    Broadcast::start(&db, id, 2, 1).await.unwrap();
+    resume(&db).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert_eq!(
+        state(Broadcast::select_by_id(&db, id).await.unwrap()),
+        ("done", 2, 2)
+    );
+    let (sql, values) = Notify::build_select()
+        .order_by(Notify::Receiver, sea_query::Order::Asc)
+        .build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<NotifyRow> = db::fetch_all(&db, &sql, values).await.unwrap();
+    assert_eq!(
+        rows.iter()
+            .map(|row| (row.receiver.as_str(), row.n_type, row.title.as_str()))
+            .collect::<Vec<_>>(),
+        [
+            (
+                "did:ckb:alice",
+                NotifyType::Announcement as i32,
+                "Maintenance tonight"
+            ),
+            (
+                "did:ckb:bob",
+                NotifyType::Announcement as i32,
+                "Maintenance tonight"
+            ),
+        ]
+    );
+
+    // notifications that can't be written fail the broadcast
+    db.execute(query("ALTER TABLE notify ADD CHECK (n_type <> 8)"))
+        .await
+        .unwrap();
+    let (id, _) = Broadcast::insert(
+        &db,
+        &BroadcastRow {
+            idempotency_key: "maintenance-2".to_string(),
+            post_uri: "at://did:ckb:root/app.bbs.post/2".to_string(),
+            ..row.clone()
+        },
+    )
+    .await
+    .unwrap();
+    run(db.clone(), id).await;
+    assert_eq!(
+        state(Broadcast::select_by_id(&db, id).await.unwrap()),
+        ("failed", 2, 0)
+    );
+
+    // nor is one whose announcement post was never written resumed
+    let (id, _) = Broadcast::insert(
+        &db,
+        &BroadcastRow {
+            idempotency_key: "maintenance-3".to_string(),
+            post_uri: String::new(),
+            ..row
+        },
+    )
+    .await
+    .unwrap();
+    resume(&db).await.unwrap();
+    assert_eq!(
+        state(Broadcast::select_by_id(&db, id).await.unwrap()),
+        ("failed", 0, 0)
+    );
+}
@@ -0,0 +1,643 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    hash::Hash,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU32, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use color_eyre::{Result, eyre::eyre};
+use moka::{future::Cache, policy::EvictionPolicy};
+use serde_json::{Value, json};
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+use crate::{
+    api::{admin::ModerationCounts, blob::Blob, section::SectionWorkload},
+    config::CacheConfig,
+    content_filter::Ruleset,
+    lexicon::section::{SectionActivity, SectionRow},
+};
+
+/// How long an author counts as seen for the handle refresh.
+const HANDLE_SEEN_SECS: u64 = 24 * 3600;
+/// Moderation stats scan the operation log, so they are kept a while.
+const MODERATION_STATS_SECS: u64 = 300;
+/// Section activity scans a day of comments, so it is kept a while too.
+const SECTION_ACTIVITY_SECS: u64 = 300;
+/// The workload of a moderator's sections should follow their work closely.
+const MY_SECTIONS_SECS: u64 = 30;
+
+/// A moka cache that counts its hits and misses.
+#[derive(Clone)]
+struct Counted<K, V> {
+    cache: Cache<K, V>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl<K, V> Counted<K, V>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn new(max_capacity: u64, ttl_secs: u64) -> Self {
+        Self {
+            cache: Cache::builder()
+                .max_capacity(max_capacity)
+                .time_to_live(Duration::from_secs(ttl_secs))
+                .build(),
+            hits: Default::default(),
+            misses: Default::default(),
+        }
+    }
+
+    async fn get_or_try_insert(&self, key: K, init: impl Future<Output = Result<V>>) -> Result<V> {
+        if let Some(value) = self.cache.get(&key).await {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        self.cache
+            .try_get_with(key, init)
+            .await
+            .map_err(|e| eyre!("{e}"))
+    }
+
+    fn stats(&self) -> Value {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let total = hits + misses;
+        json!({
+            "size": self.cache.entry_count(),
+            "hits": hits,
+            "misses": misses,
+            "hit_rate": if total == 0 { 0.0 } else { hits as f64 / total as f64 },
+        })
+    }
+}
+
+/// Read-through caches shared by all handlers. Write paths must call the
+/// matching `invalidate_*` method so the next read sees the change.
+#[derive(Clone)]
+pub struct Caches {
+    authors: Counted<String, Value>,
+    repo_stats: Counted<String, Value>,
+    sections: Counted<(), Arc<HashMap<i32, SectionRow>>>,
+    /// The last section map loaded, served when loading it again fails.
+    last_sections: Arc<RwLock<Option<Arc<HashMap<i32, SectionRow>>>>>,
+    /// Failed section loads, after which `last_sections` stayed in use.
+    stale_sections: Arc<AtomicU64>,
+    ckb_addrs: Counted<String, String>,
+    /// Errors of recent ckb address lookups, so a failing did is not
+    /// looked up again on every request.
+    ckb_addr_failures: Cache<String, String>,
+    /// Bounds the CKB RPCs of ckb address lookups; public nodes rate limit.
+    ckb_lookups: Arc<Semaphore>,
+    /// `uri@cid` of recently indexed firehose ops; hits are replays.
+    indexed_ops: Counted<String, ()>,
+    content_rules: Counted<(), Arc<Ruleset>>,
+    /// Whitelisted dids, which `build_author` marks as members.
+    whitelist: Counted<(), Arc<HashSet<String>>>,
+    /// Searches of each author in the current minute.
+    own_searches: Cache<String, Arc<AtomicU32>>,
+    /// Resolved handles; `None` when a did has none or resolving failed.
+    handles: Counted<String, Option<String>>,
+    /// Dids whose handle was read in the last day, which the handle
+    /// refresh resolves again.
+    handles_seen: Cache<String, ()>,
+    /// Places of the tip receivers by committed amount, from 1.
+    tip_ranks: Counted<(), Arc<HashMap<String, u64>>>,
+    /// Blobs by `did/cid`, weighed by their size.
+    blobs: Counted<String, Blob>,
+    blob_max_bytes: u64,
+    /// Moderation stats by their window in days.
+    moderation_stats: Counted<i32, ModerationCounts>,
+    /// Activity of the last day by section.
+    section_activity: Counted<(), Arc<HashMap<i32, SectionActivity>>>,
+    /// The sections each moderator owns or administers, with their workload.
+    my_sections: Counted<String, Vec<SectionWorkload>>,
+}
+
+impl Caches {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            authors: Counted::new(config.max_capacity, config.author_ttl_secs),
+            repo_stats: Counted::new(config.max_capacity, config.repo_stats_ttl_secs),
+            sections: Counted::new(1, config.section_ttl_secs),
+            last_sections: Default::default(),
+            stale_sections: Default::default(),
+            ckb_addrs: Counted::new(config.max_capacity, config.ckb_addr_ttl_secs),
+            ckb_addr_failures: Cache::builder()
+                .max_capacity(config.max_capacity)
+                .time_to_live(Duration::from_secs(config.ckb_addr_failure_ttl_secs))
+                .build(),
+            ckb_lookups: Arc::new(Semaphore::new(config.ckb_lookup_concurrency.max(1))),
+            indexed_ops: Counted::new(config.max_capacity, config.indexed_op_ttl_secs),
+            content_rules: Counted::new(1, config.section_ttl_secs),
+            whitelist: Counted::new(1, config.section_ttl_secs),
+            own_searches: Cache::builder()
+                .max_capacity(config.max_capacity)
+                .time_to_live(Duration::from_secs(60))
+                .build(),
+            handles: Counted::new(config.max_capacity, HANDLE_SEEN_SECS),
+            handles_seen: Cache::builder()
+                .max_capacity(config.max_capacity)
+                .time_to_idle(Duration::from_secs(HANDLE_SEEN_SECS))
+                .build(),
+            tip_ranks: Counted::new(1, config.repo_stats_ttl_secs),
+            blobs: Counted {
+                cache: Cache::builder()
+                    .max_capacity(config.blob_budget_bytes)
+                    .weigher(|_, blob: &Blob| blob.bytes.len().try_into().unwrap_or(u32::MAX))
+                    .eviction_policy(EvictionPolicy::lru())
+                    .build(),
+                hits: Default::default(),
+                misses: Default::default(),
+            },
+            blob_max_bytes: config.blob_max_bytes,
+            moderation_stats: Counted::new(366, MODERATION_STATS_SECS),
+            section_activity: Counted::new(1, SECTION_ACTIVITY_SECS),
+            my_sections: Counted::new(config.max_capacity, MY_SECTIONS_SECS),
+        }
+    }
+
+    pub async fn author(&self, did: &str, init: impl Future<Output = Value>) -> Value {
+        self.authors
+            .get_or_try_insert(did.to_string(), async { Ok(init.await) })
+            .await
+            .unwrap_or_else(|_| json!({ "did": did }))
+    }
+
+    pub async fn repo_stats(
+        &self,
+        did: &str,
+        init: impl Future<Output = Result<Value>>,
+    ) -> Result<Value> {
+        self.repo_stats
+            .get_or_try_insert(did.to_string(), init)
+            .await
+    }
+
+    /// When loading the sections fails, the last map loaded is served
+    /// instead; it is only an error before any map was loaded.
+    pub async fn sections(
+        &self,
+        init: impl Future<Output = Result<HashMap<i32, SectionRow>>>,
+    ) -> Result<Arc<HashMap<i32, SectionRow>>> {
+        match self
+            .sections
+            .get_or_try_insert((), async { init.await.map(Arc::new) })
+            .await
+        {
+            Ok(sections) => {
+                self.keep_sections(&sections);
+                Ok(sections)
+            }
+            Err(e) => self.stale_sections(e),
+        }
+    }
+
+    /// Loads the sections ahead of requests, so they rarely wait for it.
+    /// A failure leaves the cached map in place.
+    pub async fn refresh_sections(
+        &self,
+        init: impl Future<Output = Result<HashMap<i32, SectionRow>>>,
+    ) {
+        match init.await {
+            Ok(sections) => {
+                let sections = Arc::new(sections);
+                self.keep_sections(&sections);
+                self.sections.cache.insert((), sections).await;
+            }
+            Err(e) => {
+                self.stale_sections.fetch_add(1, Ordering::Relaxed);
+                warn!("refresh sections failed: {e}");
+            }
+        }
+    }
+
+    fn keep_sections(&self, sections: &Arc<HashMap<i32, SectionRow>>) {
+        if let Ok(mut last) = self.last_sections.write() {
+            *last = Some(sections.clone());
+        }
+    }
+
+    fn stale_sections(&self, e: color_eyre::Report) -> Result<Arc<HashMap<i32, SectionRow>>> {
+        let Some(last) = self.last_sections.read().ok().and_then(|last| last.clone()) else {
+            return Err(e);
+        };
+        self.stale_sections.fetch_add(1, Ordering::Relaxed);
+        warn!("load sections failed, serving the last ones loaded: {e}");
+        Ok(last)
+    }
+
+    /// The compiled content rules; regexes compile once per invalidation.
+    pub async fn content_rules(
+        &self,
+        init: impl Future<Output = Result<Ruleset>>,
+    ) -> Result<Arc<Ruleset>> {
+        self.content_rules
+            .get_or_try_insert((), async { init.await.map(Arc::new) })
+            .await
+    }
+
+    pub async fn whitelist(
+        &self,
+        init: impl Future<Output = Result<HashSet<String>>>,
+    ) -> Result<Arc<HashSet<String>>> {
+        self.whitelist
+            .get_or_try_insert((), async { init.await.map(Arc::new) })
+            .await
+    }
+
+    /// The tip leaderboard is recomputed at most once per `repo_stats_ttl_secs`.
+    pub async fn tip_ranks(
+        &self,
+        init: impl Future<Output = Result<HashMap<String, u64>>>,
+    ) -> Result<Arc<HashMap<String, u64>>> {
+        self.tip_ranks
+            .get_or_try_insert((), async { init.await.map(Arc::new) })
+            .await
+    }
+
+    /// Cached as counts, so each request serializes them for its own
+    /// version.
+    pub async fn moderation_stats(
+        &self,
+        days: i32,
+        init: impl Future<Output = Result<ModerationCounts>>,
+    ) -> Result<ModerationCounts> {
+        self.moderation_stats.get_or_try_insert(days, init).await
+    }
+
+    /// Recomputed on the first read after it expires, not on writes.
+    pub async fn section_activity(
+        &self,
+        init: impl Future<Output = Result<HashMap<i32, SectionActivity>>>,
+    ) -> Result<Arc<HashMap<i32, SectionActivity>>> {
+        self.section_activity
+            .get_or_try_insert((), async { init.await.map(Arc::new) })
+            .await
+    }
+
+    /// Moderation does not invalidate it; it expires within a minute.
+    pub async fn my_sections(
+        &self,
+        did: &str,
+        init: impl Future<Output = Result<Vec<SectionWorkload>>>,
+    ) -> Result<Vec<SectionWorkload>> {
+        self.my_sections
+            .get_or_try_insert(did.to_string(), init)
+            .await
+    }
+
+    /// Blobs never change, so a cached one is served until it is evicted.
+    pub async fn blob(&self, did: &str, cid: &str) -> Option<Blob> {
+        let blob = self.blobs.cache.get(&format!("{did}/{cid}")).await;
+        let counter = if blob.is_some() {
+            &self.blobs.hits
+        } else {
+            &self.blobs.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        blob
+    }
+
+    pub async fn insert_blob(&self, did: &str, cid: &str, blob: Blob) {
+        self.blobs.cache.insert(format!("{did}/{cid}"), blob).await;
+    }
+
+    pub const fn blob_max_bytes(&self) -> u64 {
+        self.blob_max_bytes
+    }
+
+    /// Concurrent lookups of one did share a single `init`, which runs
+    /// under the lookup semaphore. Failures are remembered briefly.
+    pub async fn ckb_addr(
+        &self,
+        did: &str,
+        init: impl Future<Output = Result<String>>,
+    ) -> Result<String> {
+        if let Some(e) = self.ckb_addr_failures.get(did).await {
+            return Err(eyre!("{e}"));
+        }
+        let result = self
+            .ckb_addrs
+            .get_or_try_insert(did.to_string(), async {
+                let _permit = self.ckb_lookups.acquire().await?;
+                init.await
+            })
+            .await;
+        if let Err(e) = &result {
+            self.ckb_addr_failures
+                .insert(did.to_string(), e.to_string())
+                .await;
+        }
+        result
+    }
+
+    pub async fn handle(
+        &self,
+        did: &str,
+        init: impl Future<Output = Option<String>>,
+    ) -> Option<String> {
+        self.handles_seen.insert(did.to_string(), ()).await;
+        self.handles
+            .get_or_try_insert(did.to_string(), async { Ok(init.await) })
+            .await
+            .unwrap_or(None)
+    }
+
+    /// The dids whose handle was read in the last day.
+    pub fn seen_handles(&self) -> Vec<String> {
+        self.handles_seen
+            .iter()
+            .map(|(did, _)| did.as_ref().clone())
+            .collect()
+    }
+
+    /// Stores a handle resolved again; true when it changed.
+    pub async fn refresh_handle(&self, did: &str, handle: Option<String>) -> bool {
+        let changed = self.handles.cache.get(did).await != Some(handle.clone());
+        self.handles.cache.insert(did.to_string(), handle).await;
+        changed
+    }
+
+    /// Counts a search of `did` over its own content; false once it made
+    /// `limit` searches within a minute.
+    pub async fn allow_own_search(&self, did: &str, limit: u32) -> bool {
+        let count = self
+            .own_searches
+            .get_with(did.to_string(), async { Default::default() })
+            .await;
+        count.fetch_add(1, Ordering::Relaxed) < limit
+    }
+
+    /// Whether the op on `uri` at `cid` was already indexed, e.g. because
+    /// the relayer replayed its commit. Counted as a hit when it was.
+    pub async fn is_replayed_op(&self, uri: &str, cid: &str) -> bool {
+        if self.indexed_ops.cache.contains_key(&format!("{uri}@{cid}")) {
+            self.indexed_ops.hits.fetch_add(1, Ordering::Relaxed);
+            true
+        } else {
+            self.indexed_ops.misses.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+
+    pub async fn mark_indexed_op(&self, uri: &str, cid: &str) {
+        self.indexed_ops
+            .cache
+            .insert(format!("{uri}@{cid}"), ())
+            .await;
+    }
+
+    /// Also drops the author's stats, which `build_author` is built from.
+    pub async fn invalidate_author(&self, did: &str) {
+        self.authors.cache.invalidate(did).await;
+        self.repo_stats.cache.invalidate(did).await;
+    }
+
+    /// The handle is resolved again on its next read.
+    pub async fn invalidate_handle(&self, did: &str) {
+        self.handles.cache.invalidate(did).await;
+    }
+
+    /// Also forgets a failed lookup, so the next one runs right away.
+    pub async fn invalidate_ckb_addr(&self, did: &str) {
+        self.ckb_addrs.cache.invalidate(did).await;
+        self.ckb_addr_failures.invalidate(did).await;
+    }
+
+    /// Sections are cached as one map, so any section change drops it whole.
+    pub async fn invalidate_section(&self, _id: i32) {
+        self.sections.cache.invalidate(&()).await;
+    }
+
+    pub async fn invalidate_content_rules(&self) {
+        self.content_rules.cache.invalidate(&()).await;
+    }
+
+    /// Also drops the cached authors of `dids`, whose membership changed.
+    pub async fn invalidate_whitelist(&self, dids: &[String]) {
+        self.whitelist.cache.invalidate(&()).await;
+        for did in dids {
+            self.invalidate_author(did).await;
+        }
+    }
+
+    /// Keeps the last section map, which is only served when loading fails.
+    pub fn invalidate_all(&self) {
+        self.authors.cache.invalidate_all();
+        self.repo_stats.cache.invalidate_all();
+        self.sections.cache.invalidate_all();
+        self.ckb_addrs.cache.invalidate_all();
+        self.ckb_addr_failures.invalidate_all();
+        self.indexed_ops.cache.invalidate_all();
+        self.content_rules.cache.invalidate_all();
+        self.whitelist.cache.invalidate_all();
+        self.own_searches.invalidate_all();
+        self.handles.cache.invalidate_all();
+        self.tip_ranks.cache.invalidate_all();
+        self.blobs.cache.invalidate_all();
+        self.moderation_stats.cache.invalidate_all();
+        self.section_activity.cache.invalidate_all();
+        self.my_sections.cache.invalidate_all();
+    }
+
+    pub fn stats(&self) -> Value {
+        json!({
+            "authors": self.authors.stats(),
+            "repo_stats": self.repo_stats.stats(),
+            "sections": self.sections.stats(),
+            "stale_sections": self.stale_sections.load(Ordering::Relaxed),
+            "ckb_addrs": self.ckb_addrs.stats(),
+            "ckb_addr_failures": self.ckb_addr_failures.entry_count(),
+            "indexed_ops": self.indexed_ops.stats(),
+            "content_rules": self.content_rules.stats(),
+            "whitelist": self.whitelist.stats(),
+            "own_searches": self.own_searches.entry_count(),
+            "handles": self.handles.stats(),
+            "tip_ranks": self.tip_ranks.stats(),
+            "blobs": self.blobs.stats(),
+            "moderation_stats": self.moderation_stats.stats(),
+            "section_activity": self.section_activity.stats(),
+            "my_sections": self.my_sections.stats(),
+        })
+    }
+}
+
+impl std::fmt::Debug for Caches {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Caches({})", self.stats())
+    }
+}
+
+#[tokio::test]
+async fn section_rename_is_visible_after_invalidation() {
+    let caches = Caches::new(&CacheConfig::default());
+    let section = |name: &str| {
+        let row = SectionRow {
+            id: 1,
+            permission: 0,
+            comment_permission: 0,
+            name: name.to_string(),
+            description: None,
+            image: None,
+            owner: None,
+            owner_set_time: None,
+            ckb_addr: None,
+            is_disabled: false,
+            is_archived: false,
+            reveal_moderator: None,
+            updated: chrono::Local::now(),
+            created: chrono::Local::now(),
+        };
+        async move { Ok(HashMap::from([(1, row)])) }
+    };
+
+    let sections = caches.sections(section("before")).await.unwrap();
+    assert_eq!(sections[&1].name, "before");
+    // cached until invalidated
+    let sections = caches.sections(section("after")).await.unwrap();
+    assert_eq!(sections[&1].name, "before");
+
+    caches.invalidate_section(1).await;
+    let sections = caches.sections(section("after")).await.unwrap();
+    assert_eq!(sections[&1].name, "after");
+
+    let stats = caches.stats();
+    assert_eq!(stats["sections"]["hits"], 1);
+    assert_eq!(stats["sections"]["misses"], 2);
+}
+
+#[tokio::test]
+async fn failed_section_loads_serve_the_last_map() {
+    let caches = Caches::new(&CacheConfig::default());
+    let failing = || async { Err(eyre!("connection refused")) };
+    // nothing to fall back to yet
+    let e = caches.sections(failing()).await.unwrap_err();
+    assert!(e.to_string().contains("connection refused"), "{e}");
+
+    let row = SectionRow {
+        id: 1,
+        permission: 0,
+        comment_permission: 0,
+        name: "general".to_string(),
+        description: None,
+        image: None,
+        owner: None,
+        owner_set_time: None,
+        ckb_addr: None,
+        is_disabled: false,
+        is_archived: false,
+        reveal_moderator: None,
+        updated: chrono::Local::now(),
+        created: chrono::Local::now(),
+    };
+    caches
+        .refresh_sections(async { Ok(HashMap::from([(1, row)])) })
+        .await;
+    caches.refresh_sections(failing()).await;
+    // the refreshed map is still cached
+    let sections = caches.sections(failing()).await.unwrap();
+    assert_eq!(sections[&1].name, "general");
+
+    // expired or flushed, the next load fails
+    caches.invalidate_all();
+    for _ in 0..3 {
+        let sections = caches.sections(failing()).await.unwrap();
+        assert_eq!(sections[&1].name, "general");
+    }
+    assert_eq!(caches.stats()["stale_sections"], 4);
+}
+
+#[tokio::test]
+async fn own_searches_are_limited_per_author() {
+    let caches = Caches::new(&CacheConfig::default());
+    for _ in 0..3 {
+        assert!(caches.allow_own_search("did:ckb:alice", 3).await);
+    }
+    assert!(!caches.allow_own_search("did:ckb:alice", 3).await);
+    assert!(caches.allow_own_search("did:ckb:bob", 3).await);
+}
+
+#[tokio::test]
+async fn replayed_op_is_detected() {
+    let caches = Caches::new(&CacheConfig::default());
+    let uri = "at://did:ckb:alice/app.bbs.comment/3kabc";
+
+    assert!(!caches.is_replayed_op(uri, "bafy1").await);
+    caches.mark_indexed_op(uri, "bafy1").await;
+    // the same commit replayed
+    assert!(caches.is_replayed_op(uri, "bafy1").await);
+    // an update of the record is a new op
+    assert!(!caches.is_replayed_op(uri, "bafy2").await);
+
+    let stats = caches.stats();
+    assert_eq!(stats["indexed_ops"]["hits"], 1);
+    assert_eq!(stats["indexed_ops"]["misses"], 2);
+}
+
+#[tokio::test]
+async fn ckb_lookups_are_bounded() {
+    let caches = Caches::new(&CacheConfig {
+        ckb_lookup_concurrency: 2,
+        ..Default::default()
+    });
+    let in_flight = Arc::new(AtomicU64::new(0));
+    let max_in_flight = Arc::new(AtomicU64::new(0));
+    let calls = Arc::new(AtomicU64::new(0));
+    // a mocked rpc that takes a while
+    let rpc = |did: String| {
+        let (in_flight, max_in_flight, calls) =
+            (in_flight.clone(), max_in_flight.clone(), calls.clone());
+        async move {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            max_in_flight.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(format!("ckt1{did}"))
+        }
+    };
+
+    let lookups = (0..8).map(|i| {
+        // every did is looked up twice at once
+        let did = format!("did:ckb:{}", i / 2);
+        let caches = caches.clone();
+        let init = rpc(did.clone());
+        async move { caches.ckb_addr(&did, init).await }
+    });
+    let addrs = futures::future::join_all(lookups).await;
+    assert!(addrs.iter().all(|addr| addr.is_ok()));
+    assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn ckb_lookup_failure_is_cached() {
+    let caches = Caches::new(&CacheConfig::default());
+    let did = "did:ckb:alice";
+    assert!(
+        caches
+            .ckb_addr(did, async { Err(eyre!("rate limited")) })
+            .await
+            .is_err()
+    );
+    // not looked up again while the failure is cached
+    let addr = caches
+        .ckb_addr(did, async { Ok("ckt1alice".to_string()) })
+        .await;
+    assert!(addr.unwrap_err().to_string().contains("rate limited"));
+
+    caches.invalidate_all();
+    let addr = caches
+        .ckb_addr(did, async { Ok("ckt1alice".to_string()) })
+        .await;
+    assert_eq!(addr.unwrap(), "ckt1alice");
+}
@@ -1,9 +1,22 @@
 use ckb_sdk::{CkbRpcAsyncClient, NetworkType};
 use color_eyre::{
     Result,
-    eyre::{OptionExt, eyre},
+    eyre::{OptionExt, bail, eyre},
 };
 
+/// Checks that `ckb_addr` is an address of `ckb_net`; the test networks
+/// share the `ckt` prefix.
+pub fn parse_ckb_addr(ckb_net: &NetworkType, ckb_addr: &str) -> Result<String> {
+    let address: ckb_sdk::Address = ckb_addr
+        .trim()
+        .parse()
+        .map_err(|e| eyre!("invalid ckb address: {e}"))?;
+    if address.network().to_prefix() != ckb_net.to_prefix() {
+        bail!("ckb address is not of the {ckb_net:?} network");
+    }
+    Ok(address.to_string())
+}
+
 pub async fn get_ckb_addr_by_did(
     ckb_client: &CkbRpcAsyncClient,
     ckb_net: &NetworkType,
@@ -59,3 +72,29 @@ pub async fn get_tx_status(
         .ok_or_eyre("get tx error")
         .map(|t| t.tx_status.status)
 }
+
+#[test]
+fn payout_addresses_must_match_the_network() {
+    let script: ckb_types::packed::Script = ckb_jsonrpc_types::Script {
+        code_hash: ckb_types::H256([1; 32]),
+        hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+        args: ckb_jsonrpc_types::JsonBytes::from_vec(vec![2; 20]),
+    }
+    .into();
+    let address = |net| ckb_sdk::Address::new(net, script.clone().into(), true).to_string();
+    let mainnet = address(NetworkType::Mainnet);
+    let testnet = address(NetworkType::Testnet);
+
+    assert_eq!(
+        parse_ckb_addr(&NetworkType::Mainnet, &mainnet).unwrap(),
+        mainnet
+    );
+    assert_eq!(
+        parse_ckb_addr(&NetworkType::Testnet, &format!(" {testnet} ")).unwrap(),
+        testnet
+    );
+    assert!(parse_ckb_addr(&NetworkType::Dev, &testnet).is_ok());
+    assert!(parse_ckb_addr(&NetworkType::Mainnet, &testnet).is_err());
+    assert!(parse_ckb_addr(&NetworkType::Testnet, &mainnet).is_err());
+    assert!(parse_ckb_addr(&NetworkType::Testnet, "ckt1notanaddress").is_err());
+}
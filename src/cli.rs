@@ -0,0 +1,414 @@
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use color_eyre::{Result, eyre::eyre};
+use sea_query::PostgresQueryBuilder;
+use sea_query_sqlx::SqlxBinder;
+use serde_json::Value;
+use sqlx::{Pool, Postgres};
+
+use crate::{
+    atproto::{Collection, list_records},
+    config::AppConfig,
+    db,
+    lexicon::{
+        administrator::Administrator, comment::Comment, like::Like, post::Post, reply::Reply,
+        section_stats::SectionStats, whitelist::Whitelist,
+    },
+    recount::{self, RecountScope},
+};
+
+/// The collections a backfill indexes, parents first.
+const BACKFILLED: [Collection; 4] = [
+    Collection::Post,
+    Collection::Comment,
+    Collection::Reply,
+    Collection::Like,
+];
+
+/// One-off operations for when the admin endpoints are not at hand. Each
+/// runs after the tables are migrated, prints a summary and exits.
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Serve the appview.
+    Serve,
+    /// Manage administrators and the whitelist.
+    Admin {
+        #[command(subcommand)]
+        command: AdminCommand,
+    },
+    /// Index the posts, comments, replies and likes of a repo again from
+    /// the PDS; `all` does so for everyone with content here.
+    Backfill {
+        #[arg(value_parser = parse_backfill_target)]
+        target: BackfillTarget,
+    },
+    /// Only migrate the tables.
+    Migrate,
+    /// Recompute the stored section counters and count the rows a recount
+    /// covers. The cached author stats live in the serving process, so
+    /// `/api/admin/recount` rebuilds those.
+    Recount {
+        #[arg(long)]
+        section_id: Option<i32>,
+        #[arg(long, value_parser = parse_did, conflicts_with = "section_id")]
+        repo: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum AdminCommand {
+    /// Add a super administrator, e.g. the first one.
+    AddAdmin {
+        #[arg(value_parser = parse_did)]
+        did: String,
+    },
+    /// Add the dids in the first column of a CSV file to the whitelist.
+    ImportWhitelist { file: PathBuf },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackfillTarget {
+    All,
+    Repo(String),
+}
+
+fn parse_did(did: &str) -> Result<String, String> {
+    if did.starts_with("did:") && did.len() > "did:".len() {
+        Ok(did.to_string())
+    } else {
+        Err(format!("`{did}` is not a did"))
+    }
+}
+
+fn parse_backfill_target(target: &str) -> Result<BackfillTarget, String> {
+    match target {
+        "all" => Ok(BackfillTarget::All),
+        repo => parse_did(repo)
+            .map(BackfillTarget::Repo)
+            .map_err(|e| format!("{e}, expected a did or `all`")),
+    }
+}
+
+pub async fn run(command: Command, db: &Pool<Postgres>, config: &AppConfig) -> Result<()> {
+    match command {
+        Command::Serve => unreachable!("serving is not a one-off operation"),
+        Command::Admin {
+            command: AdminCommand::AddAdmin { did },
+        } => {
+            add_admin(db, &did).await?;
+            println!("{did} is a super administrator");
+        }
+        Command::Admin {
+            command: AdminCommand::ImportWhitelist { file },
+        } => {
+            let csv = std::fs::read_to_string(&file)
+                .map_err(|e| eyre!("read {} failed: {e}", file.display()))?;
+            let (added, listed) = import_whitelist(db, &csv).await?;
+            println!("whitelist: {added} added, {listed} already listed");
+        }
+        Command::Backfill { target } => {
+            let repos = match target {
+                BackfillTarget::Repo(repo) => vec![repo],
+                BackfillTarget::All => {
+                    let (sql, values) =
+                        recount::build_authors(&RecountScope::All).build_sqlx(PostgresQueryBuilder);
+                    db::fetch_all::<(String,), _>(db, &sql, values)
+                        .await
+                        .map_err(|e| eyre!("exec sql failed: {e}"))?
+                        .into_iter()
+                        .map(|(did,)| did)
+                        .collect()
+                }
+            };
+            let (mut indexed, mut failed) = (0, 0);
+            for repo in &repos {
+                let (i, f) = backfill(db, &config.pds, repo).await?;
+                println!("{repo}: {i} records indexed, {f} failed");
+                indexed += i;
+                failed += f;
+            }
+            println!(
+                "backfill: {} repos, {indexed} records indexed, {failed} failed",
+                repos.len()
+            );
+            if failed > 0 {
+                return Err(eyre!("{failed} records failed to index"));
+            }
+        }
+        Command::Migrate => println!("tables are migrated"),
+        Command::Recount { section_id, repo } => {
+            let scope = match (section_id, repo) {
+                (Some(id), _) => RecountScope::Section(id),
+                (None, Some(repo)) => RecountScope::Repo(repo),
+                (None, None) => RecountScope::All,
+            };
+            let sections = SectionStats::refresh(db).await?;
+            println!("recount: counters of {sections} sections recomputed");
+            let rows = recount::row_counts(db, &scope).await?;
+            println!("recount {scope:?}: {}", Value::Object(rows));
+        }
+    }
+    Ok(())
+}
+
+/// Adds `did` with the permission of a super administrator.
+pub async fn add_admin(db: &Pool<Postgres>, did: &str) -> Result<()> {
+    parse_did(did).map_err(|e| eyre!(e))?;
+    Administrator::insert(db, did, 0).await
+}
+
+/// The dids of a CSV file: its first column, skipping empty lines and a
+/// `did` header. Fails on the first line that is not a did.
+fn parse_whitelist(csv: &str) -> Result<Vec<String>> {
+    let mut dids = vec![];
+    for (i, line) in csv.lines().enumerate() {
+        let did = line.split(',').next().unwrap_or_default().trim();
+        let did = did.trim_matches('"');
+        if did.is_empty() || (i == 0 && did == "did") {
+            continue;
+        }
+        dids.push(parse_did(did).map_err(|e| eyre!("line {}: {e}", i + 1))?);
+    }
+    Ok(dids)
+}
+
+/// Adds the dids of a CSV file to the whitelist once all of them parsed.
+/// Returns how many were added and how many were listed already.
+pub async fn import_whitelist(db: &Pool<Postgres>, csv: &str) -> Result<(usize, usize)> {
+    let (mut added, mut listed) = (0, 0);
+    for did in parse_whitelist(csv)? {
+        if Whitelist::select_by_did(db, &did).await {
+            listed += 1;
+        } else {
+            Whitelist::insert(db, &did).await?;
+            added += 1;
+        }
+    }
+    Ok((added, listed))
+}
+
+/// Indexes every post, comment, reply and like the PDS lists for `repo`,
+/// as `/api/admin/resync_record` does for one, without notifying or
+/// bumping threads. Returns how many records were indexed and how many
+/// failed to.
+pub async fn backfill(db: &Pool<Postgres>, pds: &str, repo: &str) -> Result<(usize, usize)> {
+    let (mut indexed, mut failed) = (0, 0);
+    for collection in BACKFILLED {
+        let mut cursor: Option<String> = None;
+        loop {
+            let page = list_records(pds, repo, collection.nsid(), cursor.as_deref()).await?;
+            let records = page["records"]
+                .as_array()
+                .ok_or_else(|| eyre!("list {collection} of {repo} failed: {page}"))?;
+            for record in records {
+                let (Some(uri), Some(cid)) = (record["uri"].as_str(), record["cid"].as_str())
+                else {
+                    failed += 1;
+                    continue;
+                };
+                let value = &record["value"];
+                // upserts only: old records bump no thread and notify no one
+                let result = match collection {
+                    Collection::Post => Post::insert(db, repo, value, uri, cid).await,
+                    Collection::Comment => {
+                        Comment::upsert(db, repo, value, uri, cid).await.map(drop)
+                    }
+                    Collection::Reply => Reply::upsert(db, repo, value, uri, cid).await.map(drop),
+                    _ => Like::upsert(db, repo, value, uri, cid).await.map(drop),
+                };
+                match result {
+                    Ok(()) => indexed += 1,
+                    Err(e) => {
+                        warn!("backfill {uri} failed: {e}");
+                        failed += 1;
+                    }
+                }
+            }
+            cursor = page["cursor"].as_str().map(str::to_string);
+            if records.is_empty() || cursor.is_none() {
+                break;
+            }
+        }
+    }
+    Ok((indexed, failed))
+}
+
+#[test]
+fn subcommands_are_validated() {
+    use clap::Parser;
+
+    let command = |args: &[&str]| {
+        crate::Args::try_parse_from([&["bbs"], args].concat()).map(|args| args.command)
+    };
+    assert_eq!(command(&[]).unwrap(), None);
+    assert_eq!(command(&["serve"]).unwrap(), Some(Command::Serve));
+    assert_eq!(
+        command(&["admin", "add-admin", "did:ckb:alice"]).unwrap(),
+        Some(Command::Admin {
+            command: AdminCommand::AddAdmin {
+                did: "did:ckb:alice".to_string()
+            }
+        })
+    );
+    assert_eq!(
+        command(&["backfill", "all"]).unwrap(),
+        Some(Command::Backfill {
+            target: BackfillTarget::All
+        })
+    );
+    assert_eq!(
+        command(&["-c", "other.toml", "backfill", "did:ckb:bob"]).unwrap(),
+        Some(Command::Backfill {
+            target: BackfillTarget::Repo("did:ckb:bob".to_string())
+        })
+    );
+
+    let e = command(&["admin", "add-admin", "alice"]).unwrap_err();
+    assert!(e.to_string().contains("`alice` is not a did"), "{e}");
+    let e = command(&["backfill", "everyone"]).unwrap_err();
+    assert!(e.to_string().contains("expected a did or `all`"), "{e}");
+    assert!(command(&["admin", "import-whitelist"]).is_err());
+    assert!(command(&["recount", "--section-id", "1", "--repo", "did:ckb:bob"]).is_err());
+    assert_eq!(
+        command(&["recount", "--section-id", "1"]).unwrap(),
+        Some(Command::Recount {
+            section_id: Some(1),
+            repo: None
+        })
+    );
+}
+
+#[test]
+fn whitelist_csv_is_checked_before_importing() {
+    let csv = "did,name\ndid:ckb:alice,Alice\n\n\"did:ckb:bob\"\n";
+    assert_eq!(
+        parse_whitelist(csv).unwrap(),
+        ["did:ckb:alice", "did:ckb:bob"]
+    );
+    let e = parse_whitelist("did:ckb:alice\nbob,Bob\n").unwrap_err();
+    assert!(
+        e.to_string().starts_with("line 2: `bob` is not a did"),
+        "{e}"
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn admins_and_whitelist_are_added() {
+    let db = crate::db::test_pool().await;
+    let suffix = chrono::Local::now().timestamp_micros();
+    let alice = format!("did:ckb:alice{suffix}");
+    let bob = format!("did:ckb:bob{suffix}");
+
+    add_admin(&db, &alice).await.unwrap();
+    assert!(add_admin(&db, "alice").await.is_err());
+    assert!(Administrator::all_did(&db).await.contains(&alice));
+
+    let csv = format!("did\n{alice}\n{bob}\n");
+    assert_eq!(import_whitelist(&db, &csv).await.unwrap(), (2, 0));
+    assert_eq!(import_whitelist(&db, &csv).await.unwrap(), (0, 2));
+    // nothing is imported from a file with a bad line
+    let carol = format!("did:ckb:carol{suffix}");
+    assert!(
+        import_whitelist(&db, &format!("{carol}\ncarol\n"))
+            .await
+            .is_err()
+    );
+    let carol_listed = Whitelist::select_by_did(&db, &carol).await;
+
+    Administrator::delete(&db, &alice).await.unwrap();
+    Whitelist::delete(&db, &alice).await.unwrap();
+    Whitelist::delete(&db, &bob).await.unwrap();
+    assert!(!carol_listed);
+}
+
+/// A PDS listing two pages of likes of `did:ckb:alice`, the second with a
+/// broken record, and nothing else.
+#[cfg(test)]
+async fn mock_pds() -> String {
+    use std::collections::HashMap;
+
+    use common_x::restful::axum::{Json, Router, extract::Query, routing::get};
+    use serde_json::json;
+
+    async fn list(Query(query): Query<HashMap<String, String>>) -> Json<Value> {
+        let like = |rkey: &str| {
+            json!({
+                "uri": format!("at://did:ckb:alice/app.bbs.like/{rkey}"),
+                "cid": "bafy",
+                "value": {
+                    "$type": "app.bbs.like",
+                    "section_id": "1",
+                    "to": "at://did:ckb:bob/app.bbs.post/3kabc",
+                    "created": "2026-01-01T00:00:00Z",
+                },
+            })
+        };
+        if query["collection"] != "app.bbs.like" {
+            return Json(json!({ "records": [] }));
+        }
+        Json(match query.get("cursor").map(String::as_str) {
+            None => json!({ "records": [like("3kaaa"), like("3kaab")], "cursor": "3kaab" }),
+            Some(_) => json!({ "records": [like("3kaac"), { "uri": "at://broken" }] }),
+        })
+    }
+
+    let router = Router::new().route("/xrpc/com.atproto.repo.listRecords", get(list));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        common_x::restful::axum::serve(listener, router).await.ok();
+    });
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn backfill_pages_through_the_pds() {
+    let db = crate::db::test_pool().await;
+
+    let pds = mock_pds().await;
+    assert_eq!(backfill(&db, &pds, "did:ckb:alice").await.unwrap(), (3, 1));
+    let (likes,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM \"like\"")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(likes, 3);
+    // old likes are indexed without notifying anyone
+    let (notified,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM notify")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(notified, 0);
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn recount_recomputes_the_section_counters() {
+    let db = crate::db::test_pool().await;
+    let (section,): (i32,) =
+        sqlx::query_as("INSERT INTO section (name) VALUES ('recount') RETURNING id")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+    sqlx::query(&format!(
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft) VALUES ('at://did:ckb:alice/app.bbs.post/1', 'bafy', 'did:ckb:alice', {section}, 't', 't', false)"
+    ))
+    .execute(&db)
+    .await
+    .unwrap();
+
+    let recount = Command::Recount {
+        section_id: Some(section),
+        repo: None,
+    };
+    run(recount, &db, &Default::default()).await.unwrap();
+    let (posts,): (i64,) =
+        sqlx::query_as("SELECT post_count FROM section_stats WHERE section_id = $1")
+            .bind(section)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+    assert_eq!(posts, 1);
+}
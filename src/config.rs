@@ -1,34 +1,472 @@
-use common_x::log::LogConfig;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(default)]
 pub struct AppConfig {
     pub log_config: LogConfig,
+    pub log_verbosity: LogVerbosity,
     pub port: u16,
     pub db_url: String,
     pub pds: String,
     pub relayer: String,
+    /// Indexes what the relayer delivers; off for appviews that only serve
+    /// the API from a database another one keeps up to date.
+    pub subscribe_relayer: bool,
     pub bbs_ckb_addr: String,
     pub pay_url: String,
     pub indexer: String,
     pub ckb_url: String,
     pub ckb_net: ckb_sdk::NetworkType,
+    pub debug_mode: bool,
+    pub cache: CacheConfig,
+    pub quota: QuotaConfig,
+    /// Queries slower than this are logged and counted as slow.
+    pub slow_query_ms: u64,
+    /// `did:web` of the appview, served at `/.well-known/did.json`; empty
+    /// disables the document.
+    pub service_did: String,
+    /// Public base URL of the appview; its host must match `service_did`.
+    pub public_url: String,
+    /// Multibase public key published as the `#atproto` verification method.
+    pub service_signing_key: Option<String>,
+    /// Adds each author's ckb address in lists too, not only in post
+    /// details and profiles; every first-seen author costs a CKB RPC.
+    pub ckb_addr_in_lists: bool,
+    /// Serializes counts, ids and amounts of views as JSON numbers; off
+    /// keeps the strings existing clients parse.
+    pub numeric_json: bool,
+    /// HTTP date sent as `Sunset` with responses in the shape of an old API
+    /// version, e.g. `Sat, 01 May 2027 00:00:00 GMT`.
+    pub legacy_api_sunset: Option<String>,
+    pub removal: RemovalConfig,
+    pub readiness: ReadinessConfig,
+    pub relayer_reconnect: ReconnectConfig,
+    pub apidoc: ApidocConfig,
+    pub pagination: PaginationConfig,
+    /// Refusal message of writes in maintenance mode, when the toggle gives
+    /// none.
+    pub maintenance_message: String,
+    /// Tip and notify amounts of this deployment were stored in CKB; they
+    /// are converted to shannons once, on the next start.
+    pub amounts_in_ckb: bool,
+    /// Notifications and the operation log name the moderator who acted;
+    /// off shows the section instead. Sections may decide otherwise.
+    pub reveal_moderator: bool,
+    pub limits: PayloadLimits,
+    pub clock_skew: ClockSkew,
+    pub tip_expiry: TipExpiry,
+    pub section_stats: SectionStatsConfig,
+}
+
+/// What is logged and where; the keys of `common_x::log`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LogConfig {
+    /// `EnvFilter` directives.
+    pub filter: String,
+    /// Directory and file name prefix of daily log files; stdout when unset.
+    pub rolling_file: Option<(String, String)>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            filter: "info".to_string(),
+            rolling_file: None,
+        }
+    }
+}
+
+/// Levels of the noisy modules on top of `log_config.filter`, which the
+/// unset ones follow. `/api/admin/log_level` changes them at runtime.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct LogVerbosity {
+    /// The firehose subscription and indexing.
+    pub relayer: Option<String>,
+    /// The request handlers.
+    pub api: Option<String>,
+    /// Statements logged by sqlx and slow queries.
+    pub sqlx: Option<String>,
+    /// Calls to the PDS, the indexer, micro_pay and CKB.
+    pub upstream: Option<String>,
+}
+
+impl LogVerbosity {
+    /// `filter` with a directive for each module that has a level.
+    pub fn directives(&self, filter: &str) -> String {
+        let modules: [(&Option<String>, &[&str]); 4] = [
+            (&self.relayer, &["bbs::relayer"]),
+            (&self.api, &["bbs::api"]),
+            (&self.sqlx, &["sqlx", "bbs::db"]),
+            (
+                &self.upstream,
+                &["bbs::atproto", "bbs::indexer", "bbs::micro_pay", "bbs::ckb"],
+            ),
+        ];
+        let mut directives = vec![filter.to_string()];
+        for (level, targets) in modules {
+            if let Some(level) = level {
+                directives.extend(targets.iter().map(|target| format!("{target}={level}")));
+            }
+        }
+        directives.retain(|d| !d.is_empty());
+        directives.join(",")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub max_capacity: u64,
+    pub author_ttl_secs: u64,
+    pub section_ttl_secs: u64,
+    pub ckb_addr_ttl_secs: u64,
+    /// Failed ckb address lookups are retried after this long.
+    pub ckb_addr_failure_ttl_secs: u64,
+    /// Most CKB RPCs in flight for ckb address lookups.
+    pub ckb_lookup_concurrency: usize,
+    /// How long an indexed firehose op is remembered to absorb replays.
+    pub indexed_op_ttl_secs: u64,
+    /// Author stats aggregate likes and tips over all of their content.
+    pub repo_stats_ttl_secs: u64,
+    /// Handles of the authors seen in the last day are resolved again this
+    /// often, so renames show up.
+    pub handle_refresh_secs: u64,
+    /// Bytes of blobs the blob proxy keeps in memory, least recently used
+    /// dropped first.
+    pub blob_budget_bytes: u64,
+    /// Larger blobs are refused by the blob proxy.
+    pub blob_max_bytes: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_capacity: 10_000,
+            author_ttl_secs: 60,
+            section_ttl_secs: 300,
+            ckb_addr_ttl_secs: 3600,
+            ckb_addr_failure_ttl_secs: 30,
+            ckb_lookup_concurrency: 4,
+            indexed_op_ttl_secs: 600,
+            repo_stats_ttl_secs: 300,
+            handle_refresh_secs: 3600,
+            blob_budget_bytes: 64 * 1024 * 1024,
+            blob_max_bytes: 5 * 1024 * 1024,
+        }
+    }
+}
+
+/// Self-service removal of an author's data with `/api/repo/remove_me`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RemovalConfig {
+    /// How long `restore_me` can bring the content back.
+    pub grace_days: i64,
+    /// How long the relayer ignores new records of a removed author.
+    pub suppress_days: i64,
+}
+
+impl Default for RemovalConfig {
+    fn default() -> Self {
+        RemovalConfig {
+            grace_days: 14,
+            suppress_days: 90,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApidocMode {
+    Off,
+    /// Only `/apidoc/openapi.json`.
+    #[default]
+    Spec,
+    /// The spec and the Scalar UI at `/apidoc`.
+    Full,
+}
+
+/// How the API document is served; `--apidoc` forces `full`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ApidocConfig {
+    pub mode: ApidocMode,
+    /// Leaves the admin endpoints out of the served document.
+    pub public_spec: bool,
+    /// When set, the apidoc routes require `Authorization: Bearer <token>`.
+    pub token: Option<String>,
+}
+
+/// What `/readyz` takes into account besides the database.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ReadinessConfig {
+    /// Not ready while the relayer is disconnected or lagging.
+    pub relayer_lag_unready: bool,
+    /// The relayer lags when no frame arrived for this long.
+    pub relayer_max_lag_secs: u64,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        ReadinessConfig {
+            relayer_lag_unready: false,
+            relayer_max_lag_secs: 300,
+        }
+    }
+}
+
+/// How the relayer subscription connects again after losing its connection,
+/// how often it saves where to resume, and how its commits are handled.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// Wait before the first attempt; it doubles with every failed one.
+    pub initial_delay_ms: u64,
+    /// The wait stops doubling here.
+    pub max_delay_secs: u64,
+    /// Failed attempts in a row before the subscription gives up; unset
+    /// retries forever.
+    pub max_retries: Option<u32>,
+    /// Commits handled between two saves of the cursor, which is also saved
+    /// when a connection ends. A crash replays at most this many.
+    pub cursor_save_every: u64,
+    /// Workers handling commits at the same time; those of one repo always
+    /// go to the same worker, in the order they came.
+    pub workers: usize,
+    /// Commits each worker queues before the reader waits for it.
+    pub worker_queue: usize,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            initial_delay_ms: 1000,
+            max_delay_secs: 60,
+            max_retries: None,
+            cursor_save_every: 100,
+            workers: 8,
+            worker_queue: 64,
+        }
+    }
+}
+
+/// Page size of a list endpoint when the request leaves it out, and the
+/// largest it may ask for.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct PageLimit {
+    pub default: u64,
+    pub max: u64,
+}
+
+impl Default for PageLimit {
+    fn default() -> Self {
+        PageLimit {
+            default: 20,
+            max: 100,
+        }
+    }
+}
+
+impl PageLimit {
+    /// The page size to serve for a request asking for `requested`.
+    pub fn resolve(&self, requested: Option<u64>) -> u64 {
+        requested.unwrap_or(self.default).clamp(1, self.max.max(1))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct PaginationConfig {
+    pub post_list: PageLimit,
+    pub comment_list: PageLimit,
+    pub reply_list: PageLimit,
+    pub notify_list: PageLimit,
+    pub tips: PageLimit,
+    pub follow_list: PageLimit,
+}
+
+/// Bounds of what clients and the firehose may send, checked before any
+/// signature verification or storage.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PayloadLimits {
+    /// Largest CBOR encoding of the params of a signed body.
+    pub signed_params_max_bytes: usize,
+    /// Deepest nesting of arrays and objects in a record value.
+    pub record_max_depth: usize,
+    /// Most object keys in a record value, counted at every level.
+    pub record_max_keys: usize,
+}
+
+impl Default for PayloadLimits {
+    fn default() -> Self {
+        PayloadLimits {
+            signed_params_max_bytes: 64 * 1024,
+            record_max_depth: 32,
+            record_max_keys: 1024,
+        }
+    }
+}
+
+/// How far the `timestamp` of a signed body may be from the server time.
+/// Clients can learn the server time from `/api/time`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ClockSkew {
+    /// Oldest accepted timestamp, in seconds before now.
+    pub past_secs: i64,
+    /// Newest accepted timestamp, in seconds after now.
+    pub future_secs: i64,
+}
+
+impl Default for ClockSkew {
+    fn default() -> Self {
+        ClockSkew {
+            past_secs: 300,
+            future_secs: 300,
+        }
+    }
+}
+
+/// How long prepared tips and donations wait for their transfer, and how
+/// long the ones that never got it are kept.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TipExpiry {
+    /// Prepared tips older than this time out and free their idempotency key.
+    pub pending_minutes: i64,
+    /// Timed out tips are deleted this long after they timed out.
+    pub retention_days: i64,
+}
+
+impl Default for TipExpiry {
+    fn default() -> Self {
+        TipExpiry {
+            pending_minutes: 30,
+            retention_days: 30,
+        }
+    }
+}
+
+/// The snapshot of the section counters that section lists read.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SectionStatsConfig {
+    /// How often the snapshot is taken.
+    pub refresh_secs: u64,
+    /// Older snapshots are ignored and the counters computed live.
+    pub max_age_secs: i64,
+}
+
+impl Default for SectionStatsConfig {
+    fn default() -> Self {
+        SectionStatsConfig {
+            refresh_secs: 60,
+            max_age_secs: 300,
+        }
+    }
+}
+
+/// Posting limits of whitelisted authors; 0 disables a limit, and all are
+/// off by default. Operators opt in under `[quota]`, e.g.
+/// `daily_posts = 10`, `weekly_posts = 50` and `section_cooldown_secs = 30`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct QuotaConfig {
+    pub daily_posts: u32,
+    pub weekly_posts: u32,
+    pub section_cooldown_secs: u64,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         AppConfig {
             log_config: Default::default(),
+            log_verbosity: Default::default(),
             port: 8080,
             db_url: Default::default(),
             pds: Default::default(),
             relayer: Default::default(),
+            subscribe_relayer: true,
             ckb_url: Default::default(),
             bbs_ckb_addr: Default::default(),
             pay_url: Default::default(),
             indexer: Default::default(),
             ckb_net: ckb_sdk::NetworkType::Testnet,
+            debug_mode: false,
+            cache: Default::default(),
+            quota: Default::default(),
+            slow_query_ms: 500,
+            service_did: Default::default(),
+            public_url: Default::default(),
+            service_signing_key: None,
+            ckb_addr_in_lists: true,
+            numeric_json: false,
+            legacy_api_sunset: None,
+            removal: Default::default(),
+            readiness: Default::default(),
+            relayer_reconnect: Default::default(),
+            apidoc: Default::default(),
+            pagination: Default::default(),
+            maintenance_message: "The forum is under maintenance and read only for now."
+                .to_string(),
+            amounts_in_ckb: false,
+            reveal_moderator: true,
+            limits: Default::default(),
+            clock_skew: Default::default(),
+            tip_expiry: Default::default(),
+            section_stats: Default::default(),
         }
     }
 }
+
+#[test]
+fn page_limits_are_clamped() {
+    let limit = PageLimit {
+        default: 20,
+        max: 50,
+    };
+    assert_eq!(limit.resolve(None), 20);
+    assert_eq!(limit.resolve(Some(0)), 1);
+    assert_eq!(limit.resolve(Some(30)), 30);
+    assert_eq!(limit.resolve(Some(500)), 50);
+    // a zero cap still serves one item
+    let limit = PageLimit {
+        default: 80,
+        max: 0,
+    };
+    assert_eq!(limit.resolve(None), 1);
+}
+
+#[test]
+fn page_limits_can_be_overridden() {
+    let config: AppConfig = serde_json::from_value(serde_json::json!({
+        "pagination": { "reply_list": { "max": 5 }, "notify_list": { "default": 50 } }
+    }))
+    .unwrap();
+    assert_eq!(config.pagination.reply_list.resolve(None), 5);
+    assert_eq!(config.pagination.notify_list.resolve(None), 50);
+    assert_eq!(config.pagination.post_list.resolve(None), 20);
+}
+
+#[test]
+fn log_verbosity_adds_module_directives() {
+    assert_eq!(LogVerbosity::default().directives("info"), "info");
+    let verbosity = LogVerbosity {
+        relayer: Some("warn".to_string()),
+        sqlx: Some("debug".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        verbosity.directives("info"),
+        "info,bbs::relayer=warn,sqlx=debug,bbs::db=debug"
+    );
+    assert_eq!(
+        verbosity.directives(""),
+        "bbs::relayer=warn,sqlx=debug,bbs::db=debug"
+    );
+}
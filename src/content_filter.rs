@@ -0,0 +1,257 @@
+use std::sync::LazyLock;
+
+use color_eyre::{Result, eyre::eyre};
+use regex::{Regex, RegexBuilder};
+
+use crate::lexicon::content_rule::ContentRuleRow;
+
+/// Longest pattern a rule may have.
+pub const MAX_PATTERN_LEN: usize = 256;
+/// Compiled size limit of a regex rule. The regex crate matches in linear
+/// time, so this and the nesting limit are what keep a hostile pattern
+/// from eating memory at compile time.
+const REGEX_SIZE_LIMIT: usize = 1 << 16;
+const REGEX_DFA_SIZE_LIMIT: usize = 1 << 20;
+const REGEX_NEST_LIMIT: u32 = 16;
+
+static URL_HOST: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\b[a-z][a-z0-9+.-]*://([^/\s:?#@]+)").unwrap());
+
+/// What happens to content matching a rule, from the mildest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RuleAction {
+    /// Published, with an entry in the operation log for moderators.
+    Flag,
+    /// Published hidden until a moderator displays it.
+    Shadow,
+    /// Not published at all.
+    Reject,
+}
+
+impl RuleAction {
+    pub fn parse(action: &str) -> Result<Self> {
+        match action {
+            "flag" => Ok(Self::Flag),
+            "shadow" => Ok(Self::Shadow),
+            "reject" => Ok(Self::Reject),
+            _ => Err(eyre!("action must be reject, flag or shadow")),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Matcher {
+    Regex(Regex),
+    /// Links to the domain or one of its subdomains.
+    Domain(String),
+}
+
+impl Matcher {
+    fn is_match(&self, text: &str) -> bool {
+        match self {
+            Self::Regex(regex) => regex.is_match(text),
+            Self::Domain(domain) => URL_HOST.captures_iter(text).any(|caps| {
+                let host = caps[1].trim_end_matches('.').to_lowercase();
+                host == *domain
+                    || host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|sub| sub.ends_with('.'))
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub id: i32,
+    pub action: RuleAction,
+    pub category: String,
+    /// 0 applies to every section.
+    pub section_id: i32,
+    matcher: Matcher,
+}
+
+impl Rule {
+    pub fn compile(row: &ContentRuleRow) -> Result<Self> {
+        let pattern = row.pattern.trim();
+        if pattern.is_empty() || pattern.len() > MAX_PATTERN_LEN {
+            return Err(eyre!("pattern must be 1 to {MAX_PATTERN_LEN} bytes"));
+        }
+        let matcher = match row.kind.as_str() {
+            "word" => {
+                // word boundaries only make sense next to ascii words; CJK
+                // text has no spaces between words
+                let boundary = |c: Option<char>| {
+                    if c.is_some_and(|c| c.is_ascii_alphanumeric()) {
+                        r"\b"
+                    } else {
+                        ""
+                    }
+                };
+                Matcher::Regex(build_regex(&format!(
+                    "{}{}{}",
+                    boundary(pattern.chars().next()),
+                    regex::escape(pattern),
+                    boundary(pattern.chars().last()),
+                ))?)
+            }
+            "regex" => Matcher::Regex(build_regex(pattern)?),
+            "domain" => {
+                let domain = pattern.trim_matches('.').to_lowercase();
+                if domain.is_empty() || domain.contains(['/', ':', ' ']) {
+                    return Err(eyre!("domain must be a bare host name"));
+                }
+                Matcher::Domain(domain)
+            }
+            _ => return Err(eyre!("kind must be word, regex or domain")),
+        };
+        Ok(Self {
+            id: row.id,
+            action: RuleAction::parse(&row.action)?,
+            category: row.category.clone(),
+            section_id: row.section_id,
+            matcher,
+        })
+    }
+
+    const fn applies_to(&self, section_id: i32) -> bool {
+        self.section_id == 0 || self.section_id == section_id
+    }
+}
+
+fn build_regex(pattern: &str) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_DFA_SIZE_LIMIT)
+        .nest_limit(REGEX_NEST_LIMIT)
+        .build()
+        .map_err(|e| eyre!("invalid regex: {e}"))
+}
+
+/// The compiled content rules, cached in `Caches` until a rule changes.
+#[derive(Debug, Default)]
+pub struct Ruleset {
+    rules: Vec<Rule>,
+}
+
+impl Ruleset {
+    /// Rules that no longer compile are skipped rather than blocking posts.
+    pub fn compile(rows: &[ContentRuleRow]) -> Self {
+        let rules = rows
+            .iter()
+            .filter_map(|row| {
+                Rule::compile(row)
+                    .map_err(|e| warn!("skip content rule {}: {e}", row.id))
+                    .ok()
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// The most severe rule of `section_id` that `text` matches.
+    pub fn evaluate(&self, section_id: i32, text: &str) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.applies_to(section_id) && rule.matcher.is_match(text))
+            .max_by_key(|rule| (rule.action, -rule.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(id: i32, kind: &str, pattern: &str, action: &str, section_id: i32) -> ContentRuleRow {
+        ContentRuleRow {
+            id,
+            kind: kind.to_string(),
+            pattern: pattern.to_string(),
+            action: action.to_string(),
+            category: format!("category {id}"),
+            section_id,
+            created: chrono::Local::now(),
+        }
+    }
+
+    #[test]
+    fn rules_match_by_kind() {
+        let ruleset = Ruleset::compile(&[
+            row(1, "word", "scam", "flag", 0),
+            row(2, "word", "广告", "shadow", 0),
+            row(3, "regex", r"\d{3}-\d{4}", "flag", 0),
+            row(4, "domain", "spam.example", "reject", 0),
+        ]);
+        let matched = |text| ruleset.evaluate(1, text).map(|rule| rule.id);
+
+        assert_eq!(matched("this is a SCAM!"), Some(1));
+        // whole words only
+        assert_eq!(matched("scampi for dinner"), None);
+        assert_eq!(matched("欢迎点击广告链接"), Some(2));
+        assert_eq!(matched("call 555-1234"), Some(3));
+        assert_eq!(matched("see https://spam.example/x"), Some(4));
+        assert_eq!(matched("see http://www.SPAM.example."), Some(4));
+        assert_eq!(matched("see https://notspam.example"), None);
+        assert_eq!(matched("spam.example without a link"), None);
+    }
+
+    #[test]
+    fn most_severe_rule_wins() {
+        let ruleset = Ruleset::compile(&[
+            row(1, "word", "buy", "flag", 0),
+            row(2, "word", "now", "reject", 0),
+            row(3, "word", "cheap", "shadow", 0),
+            row(4, "word", "cheap", "shadow", 0),
+        ]);
+        assert_eq!(ruleset.evaluate(1, "buy now").unwrap().id, 2);
+        // ties go to the oldest rule
+        assert_eq!(ruleset.evaluate(1, "buy cheap").unwrap().id, 3);
+    }
+
+    #[test]
+    fn section_rules_stay_in_their_section() {
+        let ruleset = Ruleset::compile(&[row(1, "word", "offtopic", "reject", 2)]);
+        assert!(ruleset.evaluate(1, "offtopic").is_none());
+        assert_eq!(ruleset.evaluate(2, "offtopic").unwrap().id, 1);
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        assert!(Rule::compile(&row(1, "regex", "(", "flag", 0)).is_err());
+        assert!(Rule::compile(&row(1, "word", "x", "delete", 0)).is_err());
+        assert!(Rule::compile(&row(1, "phrase", "x", "flag", 0)).is_err());
+        assert!(Rule::compile(&row(1, "domain", "https://a.example", "flag", 0)).is_err());
+        assert!(
+            Rule::compile(&row(1, "word", &"x".repeat(MAX_PATTERN_LEN + 1), "flag", 0)).is_err()
+        );
+        // the bad rule is skipped, the good one kept
+        let ruleset = Ruleset::compile(&[
+            row(1, "regex", "(", "reject", 0),
+            row(2, "word", "x", "flag", 0),
+        ]);
+        assert_eq!(ruleset.evaluate(1, "x").unwrap().id, 2);
+    }
+
+    #[test]
+    fn malicious_regex_is_bounded() {
+        // blows up the compiled program
+        assert!(Rule::compile(&row(1, "regex", "(((a{100}){100}){100})", "flag", 0)).is_err());
+        assert!(
+            Rule::compile(&row(
+                1,
+                "regex",
+                &"(".repeat(20) + &")".repeat(20),
+                "flag",
+                0
+            ))
+            .is_err()
+        );
+
+        // catastrophic for backtracking engines, linear here
+        let rule = Rule::compile(&row(1, "regex", "^(a+)+$", "flag", 0)).unwrap();
+        let text = "a".repeat(100_000) + "!";
+        let started = std::time::Instant::now();
+        assert!(!rule.matcher.is_match(&text));
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+}
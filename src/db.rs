@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        LazyLock, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use sea_query_sqlx::SqlxValues;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use sqlx::{
+    Executor, FromRow, Postgres,
+    postgres::{PgQueryResult, PgRow},
+    query_as_with, query_with,
+};
+
+/// Queries slower than this are logged with their SQL and counted.
+static SLOW_QUERY_MS: AtomicU64 = AtomicU64::new(500);
+const MAX_PARAMS_CHARS: usize = 200;
+
+/// Slow query counts by fingerprint.
+static SLOW_QUERIES: LazyLock<Mutex<HashMap<String, SlowQuery>>> = LazyLock::new(Default::default);
+
+#[derive(Debug, Clone)]
+struct SlowQuery {
+    sql: String,
+    count: u64,
+    max_ms: u128,
+}
+
+pub fn set_slow_query_threshold(threshold: Duration) {
+    SLOW_QUERY_MS.store(threshold.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Short id shared by all runs of a statement, whatever its bind values or
+/// inlined string literals.
+pub fn fingerprint(sql: &str) -> String {
+    let mut normalized = String::with_capacity(sql.len());
+    let mut in_literal = false;
+    for c in sql.chars() {
+        if c == '\'' {
+            in_literal = !in_literal;
+            if in_literal {
+                normalized.push('?');
+            }
+        } else if !in_literal {
+            normalized.push(c);
+        }
+    }
+    hex::encode(&Sha256::digest(normalized.as_bytes())[..4])
+}
+
+fn params_summary(values: &SqlxValues) -> String {
+    let params = format!("{:?}", values.0.0);
+    if params.chars().count() > MAX_PARAMS_CHARS {
+        let params: String = params.chars().take(MAX_PARAMS_CHARS).collect();
+        format!("{params}... ({} params)", values.0.0.len())
+    } else {
+        params
+    }
+}
+
+/// Times `exec`, which runs `sql` with `values`, and reports it when slow.
+pub async fn exec_traced<T>(sql: &str, values: &SqlxValues, exec: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let result = exec.await;
+    let elapsed = start.elapsed();
+    if elapsed.as_millis() >= SLOW_QUERY_MS.load(Ordering::Relaxed) as u128 {
+        let fingerprint = fingerprint(sql);
+        warn!(
+            fingerprint = %fingerprint,
+            elapsed_ms = elapsed.as_millis() as u64,
+            params = %params_summary(values),
+            "slow query: {sql}"
+        );
+        let mut slow_queries = SLOW_QUERIES.lock().unwrap_or_else(|e| e.into_inner());
+        let slow = slow_queries
+            .entry(fingerprint)
+            .or_insert_with(|| SlowQuery {
+                sql: sql.to_string(),
+                count: 0,
+                max_ms: 0,
+            });
+        slow.count += 1;
+        slow.max_ms = slow.max_ms.max(elapsed.as_millis());
+    }
+    result
+}
+
+pub fn slow_query_stats() -> Value {
+    let slow_queries = SLOW_QUERIES.lock().unwrap_or_else(|e| e.into_inner());
+    json!({
+        "threshold_ms": SLOW_QUERY_MS.load(Ordering::Relaxed),
+        "queries": slow_queries
+            .iter()
+            .map(|(fingerprint, slow)| {
+                (
+                    fingerprint.clone(),
+                    json!({ "sql": slow.sql, "count": slow.count, "max_ms": slow.max_ms }),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>(),
+    })
+}
+
+pub async fn fetch_all<'c, T, E>(db: E, sql: &str, values: SqlxValues) -> sqlx::Result<Vec<T>>
+where
+    T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    E: Executor<'c, Database = Postgres>,
+{
+    let params = values.clone();
+    exec_traced(sql, &params, query_as_with(sql, values).fetch_all(db)).await
+}
+
+pub async fn fetch_one<'c, T, E>(db: E, sql: &str, values: SqlxValues) -> sqlx::Result<T>
+where
+    T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    E: Executor<'c, Database = Postgres>,
+{
+    let params = values.clone();
+    exec_traced(sql, &params, query_as_with(sql, values).fetch_one(db)).await
+}
+
+pub async fn fetch_optional<'c, T, E>(
+    db: E,
+    sql: &str,
+    values: SqlxValues,
+) -> sqlx::Result<Option<T>>
+where
+    T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    E: Executor<'c, Database = Postgres>,
+{
+    let params = values.clone();
+    exec_traced(sql, &params, query_as_with(sql, values).fetch_optional(db)).await
+}
+
+pub async fn execute<'c, E>(db: E, sql: &str, values: SqlxValues) -> sqlx::Result<PgQueryResult>
+where
+    E: Executor<'c, Database = Postgres>,
+{
+    let params = values.clone();
+    exec_traced(sql, &params, query_with(sql, values).execute(db)).await
+}
+
+/// One connection to `DATABASE_URL` with the whole schema created as
+/// temporary tables, which shadow the real ones and go away with it. The
+/// tests using it are ignored by default; run them against a scratch
+/// database with `cargo test -- --ignored`.
+#[cfg(test)]
+pub async fn test_pool() -> sqlx::PgPool {
+    let url = std::env::var("DATABASE_URL").expect("database tests need DATABASE_URL");
+    let db = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&url)
+        .await
+        .unwrap();
+    // extensions can't be temporary, so this one goes in first
+    for sql in [
+        "CREATE EXTENSION IF NOT EXISTS pg_trgm",
+        "SET search_path = pg_temp, public",
+    ] {
+        db.execute(sqlx::query(sql)).await.unwrap();
+    }
+    crate::migrate(&db, &Default::default()).await.unwrap();
+    db
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_query::Values;
+
+    use super::*;
+
+    fn slow_count(sql: &str) -> u64 {
+        SLOW_QUERIES
+            .lock()
+            .unwrap()
+            .get(&fingerprint(sql))
+            .map(|slow| slow.count)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn fingerprint_ignores_literals() {
+        assert_eq!(
+            fingerprint("SELECT 1 FROM \"like\" WHERE \"repo\" = 'did:ckb:alice'"),
+            fingerprint("SELECT 1 FROM \"like\" WHERE \"repo\" = 'did:ckb:bob'")
+        );
+        assert_ne!(
+            fingerprint("SELECT 1 FROM \"like\""),
+            fingerprint("SELECT 1 FROM \"post\"")
+        );
+        assert_eq!(fingerprint("SELECT 1").len(), 8);
+    }
+
+    #[tokio::test]
+    async fn slow_query_is_reported() {
+        set_slow_query_threshold(Duration::from_millis(20));
+        let values = SqlxValues(Values(vec![]));
+        let sql = "SELECT pg_sleep(0.05) /* simulated */";
+        exec_traced(sql, &values, tokio::time::sleep(Duration::from_millis(50))).await;
+        assert_eq!(slow_count(sql), 1);
+
+        let sql = "SELECT 1 /* simulated */";
+        exec_traced(sql, &values, async {}).await;
+        assert_eq!(slow_count(sql), 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn pg_sleep_is_reported() {
+        let db = test_pool().await;
+        set_slow_query_threshold(Duration::from_millis(20));
+        let sql = "SELECT pg_sleep(0.05)::text AS slept";
+        execute(&db, sql, SqlxValues(Values(vec![]))).await.unwrap();
+        assert_eq!(slow_count(sql), 1);
+    }
+}
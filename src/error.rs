@@ -4,26 +4,59 @@ use common_x::restful::axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
-use serde_json::json;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use utoipa::ToSchema;
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::atproto::PdsError;
 
 #[derive(Debug)]
 pub(crate) enum AppError {
     ValidateFailed(String),
+    /// The request failed its `Validate` rules; every failing field is
+    /// listed under `details`.
+    Invalid(Vec<FieldError>),
     NotFound,
     IsDisabled(String),
     RpcFailed(String),
+    /// The PDS answered with an error status: refused credentials reach
+    /// the client as 401, anything else as 502.
+    Pds(PdsError),
     MicroPayIncomplete(String),
+    TooLarge(String),
+    /// The request was based on state that changed since; carries the
+    /// current state.
+    Conflict(Value),
+    /// Writes are refused while the appview is read only.
+    Maintenance(String),
+    /// New content was sent to an archived section.
+    Archived(String),
+    /// The author is banned from writing in the section for now.
+    Banned(String),
     Unknown(String),
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let mut details = None;
         let (status, error, error_message) = match self {
             AppError::ValidateFailed(msg) => (
                 StatusCode::BAD_REQUEST,
                 "ValidateFailed",
                 string_to_static_str(msg),
             ),
+            AppError::Invalid(errors) => {
+                let mut fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+                fields.dedup();
+                let msg = format!("invalid fields: {}", fields.join(", "));
+                details = Some(errors);
+                (
+                    StatusCode::BAD_REQUEST,
+                    "ValidateFailed",
+                    string_to_static_str(msg),
+                )
+            }
             AppError::NotFound => (
                 StatusCode::NOT_FOUND,
                 "NotFound",
@@ -39,23 +72,85 @@ impl IntoResponse for AppError {
                 "RpcFailed",
                 string_to_static_str(json!({"rpc": msg}).to_string()),
             ),
+            AppError::Pds(e) => (
+                if e.is_auth() {
+                    StatusCode::UNAUTHORIZED
+                } else {
+                    StatusCode::BAD_GATEWAY
+                },
+                "RpcFailed",
+                string_to_static_str(
+                    json!({"rpc": {
+                        "status": e.status,
+                        "error": e.error,
+                        "message": e.message,
+                    }})
+                    .to_string(),
+                ),
+            ),
             AppError::MicroPayIncomplete(msg) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
                 "MicroPayIncomplete",
                 string_to_static_str(json!({"micro_pay": msg}).to_string()),
             ),
+            AppError::TooLarge(msg) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "TooLarge",
+                string_to_static_str(msg),
+            ),
+            AppError::Conflict(current) => (
+                StatusCode::CONFLICT,
+                "Conflict",
+                string_to_static_str(json!({"current": current}).to_string()),
+            ),
+            AppError::Maintenance(msg) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "MaintenanceMode",
+                string_to_static_str(msg),
+            ),
+            AppError::Archived(msg) => (
+                StatusCode::FORBIDDEN,
+                "SectionArchived",
+                string_to_static_str(msg),
+            ),
+            AppError::Banned(msg) => (
+                StatusCode::FORBIDDEN,
+                "SectionBanned",
+                string_to_static_str(msg),
+            ),
             AppError::Unknown(msg) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Unknown",
                 string_to_static_str(msg),
             ),
         };
-        let body = Json(json!({
+        let mut body = json!({
             "code": status.as_u16(),
             "error": error,
             "message": error_message,
-        }));
-        (status, body).into_response()
+        });
+        if let Some(details) = details {
+            body["details"] = json!(details);
+        }
+        (status, Json(body)).into_response()
+    }
+}
+
+impl AppError {
+    /// Every field of `errors`, nested ones included.
+    pub(crate) fn invalid(errors: ValidationErrors) -> Self {
+        let mut fields = vec![];
+        collect_field_errors(&errors, "", &mut fields);
+        fields.sort_by(|a, b| a.field.cmp(&b.field));
+        Self::Invalid(fields)
+    }
+
+    /// A failed call to the PDS, keeping the status it answered with.
+    pub(crate) fn rpc(err: Error) -> Self {
+        match err.downcast::<PdsError>() {
+            Ok(e) => Self::Pds(e),
+            Err(e) => Self::RpcFailed(e.to_string()),
+        }
     }
 }
 
@@ -64,7 +159,52 @@ where
     E: Into<Error>,
 {
     fn from(err: E) -> Self {
-        Self::Unknown(err.into().to_string())
+        match err.into().downcast::<PdsError>() {
+            Ok(e) => Self::Pds(e),
+            Err(e) => Self::Unknown(e.to_string()),
+        }
+    }
+}
+
+/// A field that failed one of its `Validate` rules.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct FieldError {
+    /// Path of the field in the request, like `params.title` or
+    /// `items[2].amount`.
+    pub field: String,
+    /// The rule that failed, like `length`, `range` or `url`.
+    pub code: String,
+    /// The bounds of the rule and the rejected `value`.
+    #[schema(value_type = Object)]
+    pub params: Map<String, Value>,
+}
+
+fn collect_field_errors(errors: &ValidationErrors, prefix: &str, fields: &mut Vec<FieldError>) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{prefix}.{field}")
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => fields.extend(errors.iter().map(|e| {
+                FieldError {
+                    field: path.clone(),
+                    code: e.code.to_string(),
+                    params: e
+                        .params
+                        .iter()
+                        .map(|(name, value)| (name.to_string(), value.clone()))
+                        .collect(),
+                }
+            })),
+            ValidationErrorsKind::Struct(errors) => collect_field_errors(errors, &path, fields),
+            ValidationErrorsKind::List(items) => {
+                for (i, errors) in items {
+                    collect_field_errors(errors, &format!("{path}[{i}]"), fields);
+                }
+            }
+        }
     }
 }
 
@@ -3,28 +3,43 @@ use std::time::Duration;
 use color_eyre::{Result, eyre::eyre};
 use serde_json::Value;
 
+use crate::atproto::check_status;
+
 pub async fn did_document(url: &str, did: &str) -> Result<Value> {
-    reqwest::Client::new()
+    let response = reqwest::Client::new()
         .get(format!("{url}/{did}"))
         .header("Content-Type", "application/json; charset=utf-8")
         .timeout(Duration::from_secs(5))
         .send()
         .await
-        .map_err(|e| eyre!("call indexer failed: {e}"))?
+        .map_err(|e| eyre!("call indexer failed: {e}"))?;
+    check_status(response)
+        .await?
         .json::<Value>()
         .await
         .map_err(|e| eyre!("decode indexer response failed: {e}"))
 }
 
+/// The handle of `did`: the first `alsoKnownAs` of its did document.
+pub async fn handle_of(url: &str, did: &str) -> Result<Option<String>> {
+    Ok(did_document(url, did)
+        .await?
+        .pointer("/alsoKnownAs/0")
+        .and_then(|aka| aka.as_str())
+        .map(|aka| aka.trim_start_matches("at://").to_string()))
+}
+
 #[allow(dead_code)]
 pub async fn ckb_did(url: &str, ckb_addr: &str) -> Result<String> {
-    reqwest::Client::new()
+    let response = reqwest::Client::new()
         .get(format!("{url}/resolve-ckb-addr/{ckb_addr}"))
         .header("Content-Type", "application/json; charset=utf-8")
         .timeout(Duration::from_secs(5))
         .send()
         .await
-        .map_err(|e| eyre!("call indexer failed: {e}"))?
+        .map_err(|e| eyre!("call indexer failed: {e}"))?;
+    check_status(response)
+        .await?
         .text()
         .await
         .map_err(|e| eyre!("decode indexer response failed: {e}"))
@@ -4,7 +4,9 @@ use sea_query::{ColumnDef, Expr, ExprTrait, Iden, OnConflict, PostgresQueryBuild
 use sea_query_sqlx::SqlxBinder;
 use serde::Serialize;
 use serde_json::Value;
-use sqlx::{Executor, Pool, Postgres, query, query_with};
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::db;
 
 #[derive(Iden, Debug, Clone, Copy)]
 pub enum Administrator {
@@ -57,7 +59,7 @@ impl Administrator {
             )
             .build_sqlx(PostgresQueryBuilder);
 
-        db.execute(query_with(&sql, values)).await?;
+        db::execute(db, &sql, values).await?;
         Ok(())
     }
 
@@ -66,7 +68,7 @@ impl Administrator {
             .from_table(Self::Table)
             .and_where(Expr::col(Self::Did).eq(did))
             .build_sqlx(PostgresQueryBuilder);
-        db.execute(query_with(&sql, values)).await?;
+        db::execute(db, &sql, values).await?;
         Ok(())
     }
 
@@ -84,10 +86,7 @@ impl Administrator {
 
     pub async fn all(db: &Pool<Postgres>) -> Vec<AdministratorRow> {
         let (sql, values) = Self::build_select().build_sqlx(PostgresQueryBuilder);
-        sqlx::query_as_with(&sql, values)
-            .fetch_all(db)
-            .await
-            .unwrap_or_default()
+        db::fetch_all(db, &sql, values).await.unwrap_or_default()
     }
 
     pub async fn all_did(db: &Pool<Postgres>) -> Vec<String> {
@@ -95,10 +94,7 @@ impl Administrator {
             .column(Administrator::Did)
             .from(Administrator::Table)
             .build_sqlx(PostgresQueryBuilder);
-        let rows: Vec<(String,)> = sqlx::query_as_with(&sql, values)
-            .fetch_all(db)
-            .await
-            .unwrap_or_default();
+        let rows: Vec<(String,)> = db::fetch_all(db, &sql, values).await.unwrap_or_default();
         rows.into_iter().map(|r| r.0).collect()
     }
 }
@@ -0,0 +1,199 @@
+use chrono::{DateTime, Local};
+use color_eyre::Result;
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, Index, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde::Serialize;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::db;
+
+/// Days of audit events kept by `purge`.
+pub const RETENTION_DAYS: i32 = 180;
+
+/// One state-changing API call, for incident forensics. Rows are only ever
+/// inserted, by the task behind `audit::Audit`, and purged when old.
+#[derive(Iden)]
+pub enum AuditEvent {
+    Table,
+    Id,
+    /// DID of the caller, or `token:` and a hash of its bearer token.
+    Actor,
+    Route,
+    /// The uri or id the call was about, when it names one.
+    Target,
+    /// HTTP status of the response.
+    Outcome,
+    LatencyMs,
+    RequestId,
+    Created,
+}
+
+impl AuditEvent {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Self::Id)
+                    .big_integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(Self::Actor).string().not_null())
+            .col(ColumnDef::new(Self::Route).string().not_null())
+            .col(ColumnDef::new(Self::Target).string())
+            .col(ColumnDef::new(Self::Outcome).integer().not_null())
+            .col(ColumnDef::new(Self::LatencyMs).big_integer().not_null())
+            .col(ColumnDef::new(Self::RequestId).string().not_null())
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
+        for (name, column) in [
+            ("idx_audit_event_created", Self::Created),
+            ("idx_audit_event_actor", Self::Actor),
+        ] {
+            let sql = Index::create()
+                .if_not_exists()
+                .name(name)
+                .table(Self::Table)
+                .col(column)
+                .build(PostgresQueryBuilder);
+            db.execute(query(&sql)).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn insert(db: &Pool<Postgres>, row: &AuditEventRow) -> Result<()> {
+        let (sql, values) = sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([
+                Self::Actor,
+                Self::Route,
+                Self::Target,
+                Self::Outcome,
+                Self::LatencyMs,
+                Self::RequestId,
+                Self::Created,
+            ])
+            .values([
+                row.actor.clone().into(),
+                row.route.clone().into(),
+                row.target.clone().into(),
+                row.outcome.into(),
+                row.latency_ms.into(),
+                row.request_id.clone().into(),
+                row.created.into(),
+            ])?
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    pub fn build_select() -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .columns([
+                Self::Id,
+                Self::Actor,
+                Self::Route,
+                Self::Target,
+                Self::Outcome,
+                Self::LatencyMs,
+                Self::RequestId,
+                Self::Created,
+            ])
+            .from(Self::Table)
+            .take()
+    }
+
+    pub fn build_purge() -> sea_query::DeleteStatement {
+        sea_query::Query::delete()
+            .from_table(Self::Table)
+            .and_where(Expr::col(Self::Created).lt(Expr::cust_with_values(
+                "now() - make_interval(days => $1)",
+                [RETENTION_DAYS],
+            )))
+            .take()
+    }
+
+    /// Drops the events older than `RETENTION_DAYS`, returning how many.
+    pub async fn purge(db: &Pool<Postgres>) -> Result<u64> {
+        let (sql, values) = Self::build_purge().build_sqlx(PostgresQueryBuilder);
+        Ok(db::execute(db, &sql, values).await?.rows_affected())
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Serialize)]
+pub struct AuditEventRow {
+    pub id: i64,
+    pub actor: String,
+    pub route: String,
+    pub target: Option<String>,
+    pub outcome: i32,
+    pub latency_ms: i64,
+    pub request_id: String,
+    pub created: DateTime<Local>,
+}
+
+#[test]
+fn purge_keeps_the_retention() {
+    let (sql, _) = AuditEvent::build_purge().build(PostgresQueryBuilder);
+    assert_eq!(
+        sql,
+        "DELETE FROM \"audit_event\" WHERE \"created\" < now() - make_interval(days => $1)"
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn events_round_trip_and_expire() {
+    let db = crate::db::test_pool().await;
+
+    let event = |actor: &str, route: &str, days_ago: i64| AuditEventRow {
+        id: 0,
+        actor: actor.to_string(),
+        route: route.to_string(),
+        target: Some("at://did:ckb:alice/app.bbs.post/1".to_string()),
+        outcome: 200,
+        latency_ms: 12,
+        request_id: format!("{actor}{route}"),
+        created: Local::now() - chrono::Duration::days(days_ago),
+    };
+    for row in [
+        event("did:ckb:alice", "/api/record/create", 0),
+        event("did:ckb:admin", "/api/admin/ban", 1),
+        event(
+            "did:ckb:alice",
+            "/api/record/delete",
+            RETENTION_DAYS as i64 + 1,
+        ),
+    ] {
+        AuditEvent::insert(&db, &row).await.unwrap();
+    }
+
+    let select = async |actor: Option<&str>| -> Vec<AuditEventRow> {
+        let (sql, values) = AuditEvent::build_select()
+            .and_where_option(actor.map(|actor| Expr::col(AuditEvent::Actor).eq(actor)))
+            .order_by(AuditEvent::Id, sea_query::Order::Asc)
+            .build_sqlx(PostgresQueryBuilder);
+        db::fetch_all(&db, &sql, values).await.unwrap()
+    };
+    let alice = select(Some("did:ckb:alice")).await;
+    assert_eq!(alice.len(), 2);
+    assert_eq!(alice[0].route, "/api/record/create");
+
+    assert_eq!(AuditEvent::purge(&db).await.unwrap(), 1);
+    let routes: Vec<String> = select(None)
+        .await
+        .into_iter()
+        .map(|row| row.route)
+        .collect();
+    assert_eq!(routes, ["/api/record/create", "/api/admin/ban"]);
+}
@@ -0,0 +1,353 @@
+use chrono::{DateTime, Local};
+use color_eyre::Result;
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, Index, OnConflict, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde::Serialize;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::db;
+
+/// Where a broadcast stands; `sent` grows batch by batch while `Running`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BroadcastState {
+    Queued = 0,
+    Running = 1,
+    Done = 2,
+    Failed = 3,
+}
+
+impl BroadcastState {
+    pub const fn name(state: i32) -> &'static str {
+        match state {
+            0 => "queued",
+            1 => "running",
+            2 => "done",
+            _ => "failed",
+        }
+    }
+}
+
+/// Announcements fanned out to the notify inbox of every user of an
+/// audience by a super administrator.
+#[derive(Iden)]
+pub enum Broadcast {
+    Table,
+    Id,
+    Sender,
+    IdempotencyKey,
+    Title,
+    Body,
+    PostUri,
+    ActiveDays,
+    SectionId,
+    State,
+    Total,
+    Sent,
+    Updated,
+    Created,
+}
+
+impl Broadcast {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Self::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(Self::Sender).string().not_null())
+            .col(ColumnDef::new(Self::IdempotencyKey).string().not_null())
+            .col(ColumnDef::new(Self::Title).string().not_null())
+            .col(ColumnDef::new(Self::Body).string().not_null())
+            .col(ColumnDef::new(Self::PostUri).string().not_null())
+            .col(ColumnDef::new(Self::ActiveDays).integer())
+            .col(ColumnDef::new(Self::SectionId).integer())
+            .col(
+                ColumnDef::new(Self::State)
+                    .integer()
+                    .not_null()
+                    .default(BroadcastState::Queued as i32),
+            )
+            .col(
+                ColumnDef::new(Self::Total)
+                    .big_integer()
+                    .not_null()
+                    .default(0),
+            )
+            .col(
+                ColumnDef::new(Self::Sent)
+                    .big_integer()
+                    .not_null()
+                    .default(0),
+            )
+            .col(
+                ColumnDef::new(Self::Updated)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .index(
+                Index::create()
+                    .unique()
+                    .col(Self::Sender)
+                    .col(Self::IdempotencyKey),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
+        Ok(())
+    }
+
+    pub fn build_select() -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .columns([
+                Broadcast::Id,
+                Broadcast::Sender,
+                Broadcast::IdempotencyKey,
+                Broadcast::Title,
+                Broadcast::Body,
+                Broadcast::PostUri,
+                Broadcast::ActiveDays,
+                Broadcast::SectionId,
+                Broadcast::State,
+                Broadcast::Total,
+                Broadcast::Sent,
+                Broadcast::Updated,
+                Broadcast::Created,
+            ])
+            .from(Broadcast::Table)
+            .take()
+    }
+
+    /// Queues a broadcast; nothing is inserted when its sender already used
+    /// the idempotency key.
+    pub fn build_insert(row: &BroadcastRow) -> Result<sea_query::InsertStatement> {
+        Ok(sea_query::Query::insert()
+            .into_table(Broadcast::Table)
+            .columns([
+                Broadcast::Sender,
+                Broadcast::IdempotencyKey,
+                Broadcast::Title,
+                Broadcast::Body,
+                Broadcast::PostUri,
+                Broadcast::ActiveDays,
+                Broadcast::SectionId,
+            ])
+            .values([
+                row.sender.clone().into(),
+                row.idempotency_key.clone().into(),
+                row.title.clone().into(),
+                row.body.clone().into(),
+                row.post_uri.clone().into(),
+                row.active_days.into(),
+                row.section_id.into(),
+            ])?
+            .on_conflict(
+                OnConflict::columns([Broadcast::Sender, Broadcast::IdempotencyKey])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .returning_col(Broadcast::Id)
+            .take())
+    }
+
+    /// The id of the broadcast and whether this call queued it, rather than
+    /// an earlier submission with the same idempotency key.
+    pub async fn insert(db: &Pool<Postgres>, row: &BroadcastRow) -> Result<(i32, bool)> {
+        let (sql, values) = Self::build_insert(row)?.build_sqlx(PostgresQueryBuilder);
+        if let Some((id,)) = db::fetch_optional::<(i32,), _>(db, &sql, values).await? {
+            return Ok((id, true));
+        }
+        let (sql, values) = sea_query::Query::select()
+            .column(Broadcast::Id)
+            .from(Broadcast::Table)
+            .and_where(Expr::col(Broadcast::Sender).eq(row.sender.as_str()))
+            .and_where(Expr::col(Broadcast::IdempotencyKey).eq(row.idempotency_key.as_str()))
+            .build_sqlx(PostgresQueryBuilder);
+        let (id,): (i32,) = db::fetch_one(db, &sql, values).await?;
+        Ok((id, false))
+    }
+
+    pub async fn select_by_id(db: &Pool<Postgres>, id: i32) -> Result<BroadcastRow> {
+        let (sql, values) = Self::build_select()
+            .and_where(Expr::col(Broadcast::Id).eq(id))
+            .build_sqlx(PostgresQueryBuilder);
+        Ok(db::fetch_one(db, &sql, values).await?)
+    }
+
+    /// Broadcasts a restart interrupted before they were done.
+    pub async fn select_unfinished(db: &Pool<Postgres>) -> Result<Vec<BroadcastRow>> {
+        let (sql, values) = Self::build_select()
+            .and_where(Expr::col(Broadcast::State).is_in([
+                BroadcastState::Queued as i32,
+                BroadcastState::Running as i32,
+            ]))
+            .order_by(Broadcast::Id, sea_query::Order::Asc)
+            .build_sqlx(PostgresQueryBuilder);
+        Ok(db::fetch_all(db, &sql, values).await?)
+    }
+
+    /// The body of the broadcast that announced `post_uri`.
+    pub async fn body_of(db: &Pool<Postgres>, post_uri: &str) -> Result<Option<String>> {
+        let (sql, values) = sea_query::Query::select()
+            .column(Broadcast::Body)
+            .from(Broadcast::Table)
+            .and_where(Expr::col(Broadcast::PostUri).eq(post_uri))
+            .limit(1)
+            .build_sqlx(PostgresQueryBuilder);
+        let row: Option<(String,)> = db::fetch_optional(db, &sql, values).await?;
+        Ok(row.map(|(body,)| body))
+    }
+
+    /// Links the queued broadcast to the announcement post it was written.
+    pub async fn set_post_uri(db: &Pool<Postgres>, id: i32, post_uri: &str) -> Result<()> {
+        let (sql, values) = sea_query::Query::update()
+            .table(Broadcast::Table)
+            .values([
+                (Broadcast::PostUri, post_uri.into()),
+                (Broadcast::Updated, Expr::current_timestamp()),
+            ])
+            .and_where(Expr::col(Broadcast::Id).eq(id))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    /// Drops a queued broadcast whose announcement post could not be
+    /// written, so its idempotency key can be submitted again.
+    pub async fn delete(db: &Pool<Postgres>, id: i32) -> Result<()> {
+        let (sql, values) = sea_query::Query::delete()
+            .from_table(Broadcast::Table)
+            .and_where(Expr::col(Broadcast::Id).eq(id))
+            .and_where(Expr::col(Broadcast::State).eq(BroadcastState::Queued as i32))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    /// Marks the broadcast `Running` to `total` recipients, `sent` of whom
+    /// an earlier run already notified.
+    pub async fn start(db: &Pool<Postgres>, id: i32, total: i64, sent: i64) -> Result<()> {
+        let (sql, values) = sea_query::Query::update()
+            .table(Broadcast::Table)
+            .values([
+                (Broadcast::State, (BroadcastState::Running as i32).into()),
+                (Broadcast::Total, total.into()),
+                (Broadcast::Sent, sent.into()),
+                (Broadcast::Updated, Expr::current_timestamp()),
+            ])
+            .and_where(Expr::col(Broadcast::Id).eq(id))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    pub async fn update_state(db: &Pool<Postgres>, id: i32, state: BroadcastState) -> Result<()> {
+        let (sql, values) = sea_query::Query::update()
+            .table(Broadcast::Table)
+            .values([
+                (Broadcast::State, (state as i32).into()),
+                (Broadcast::Updated, Expr::current_timestamp()),
+            ])
+            .and_where(Expr::col(Broadcast::Id).eq(id))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    pub async fn add_sent(db: &Pool<Postgres>, id: i32, sent: i64) -> Result<()> {
+        let (sql, values) = sea_query::Query::update()
+            .table(Broadcast::Table)
+            .values([
+                (Broadcast::Sent, Expr::col(Broadcast::Sent).add(sent)),
+                (Broadcast::Updated, Expr::current_timestamp()),
+            ])
+            .and_where(Expr::col(Broadcast::Id).eq(id))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, Default)]
+pub struct BroadcastRow {
+    pub id: i32,
+    pub sender: String,
+    pub idempotency_key: String,
+    pub title: String,
+    pub body: String,
+    pub post_uri: String,
+    pub active_days: Option<i32>,
+    pub section_id: Option<i32>,
+    pub state: i32,
+    pub total: i64,
+    pub sent: i64,
+    pub updated: DateTime<Local>,
+    pub created: DateTime<Local>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BroadcastView {
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    pub id: String,
+    pub sender: String,
+    pub title: String,
+    pub body: String,
+    pub post_uri: String,
+    pub active_days: Option<i32>,
+    pub section_id: Option<i32>,
+    pub state: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    pub total: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    pub sent: String,
+    pub updated: DateTime<Local>,
+    pub created: DateTime<Local>,
+}
+
+impl From<BroadcastRow> for BroadcastView {
+    fn from(row: BroadcastRow) -> Self {
+        Self {
+            id: row.id.to_string(),
+            sender: row.sender,
+            title: row.title,
+            body: row.body,
+            post_uri: row.post_uri,
+            active_days: row.active_days,
+            section_id: row.section_id,
+            state: BroadcastState::name(row.state).to_string(),
+            total: row.total.to_string(),
+            sent: row.sent.to_string(),
+            updated: row.updated,
+            created: row.created,
+        }
+    }
+}
+
+#[test]
+fn resubmitted_keys_insert_nothing() {
+    let row = BroadcastRow {
+        sender: "did:ckb:root".to_string(),
+        idempotency_key: "maintenance-1".to_string(),
+        title: "Maintenance".to_string(),
+        post_uri: "at://did:ckb:root/app.bbs.post/1".to_string(),
+        active_days: Some(30),
+        ..Default::default()
+    };
+    let sql = Broadcast::build_insert(&row)
+        .unwrap()
+        .to_string(PostgresQueryBuilder);
+    assert!(
+        sql.ends_with("ON CONFLICT (\"sender\", \"idempotency_key\") DO NOTHING RETURNING \"id\"")
+    );
+}
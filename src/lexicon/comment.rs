@@ -1,15 +1,24 @@
 use chrono::{DateTime, Local};
-use color_eyre::{Result, eyre::OptionExt};
+use color_eyre::{
+    Result,
+    eyre::{OptionExt, eyre},
+};
 use sea_query::{ColumnDef, Expr, ExprTrait, Iden, OnConflict, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
 use serde::Serialize;
 use serde_json::Value;
-use sqlx::{Executor, Pool, Postgres, query, query_with};
+use sqlx::{Executor, Pool, Postgres, query};
 
-use crate::lexicon::{
-    notify::{Notify, NotifyRow, NotifyType},
-    post::Post,
-    resolve_uri,
+use crate::{
+    atproto::NSID_POST,
+    db,
+    lexicon::{
+        ThreadError, edited_after_report, normalize_record,
+        notify::{Notify, NotifyRow, NotifyType},
+        operation::Operation,
+        post::Post,
+        reasons_for_viewer, resolve_uri, section_id_of, with_section_id,
+    },
 };
 
 #[derive(Iden)]
@@ -70,21 +79,17 @@ impl Comment {
         Ok(())
     }
 
-    pub async fn insert(
-        db: &Pool<Postgres>,
+    /// Upserts the comment record, read with `normalize_record` so the field
+    /// names of early clients are accepted.
+    pub fn build_insert(
         repo: &str,
         comment: &Value,
         uri: &str,
         cid: &str,
-    ) -> Result<()> {
-        let section_id = comment["section_id"]
-            .as_str()
-            .and_then(|s| s.parse::<i32>().ok())
-            .ok_or_eyre("error in section_id")?;
-        let post = comment["post"]
-            .as_str()
-            .map(|s| s.trim_matches('\"'))
-            .ok_or_eyre("error in post")?;
+    ) -> Result<sea_query::InsertStatement> {
+        let comment = &*normalize_record(comment, uri);
+        let section_id = section_id_of(comment)?;
+        let (post, _) = post_of(comment)?;
         let text = comment["text"]
             .as_str()
             .map(|s| s.trim_matches('\"'))
@@ -96,7 +101,7 @@ impl Comment {
             .as_str()
             .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
             .ok_or_eyre("error in created")?;
-        let (sql, values) = sea_query::Query::insert()
+        Ok(sea_query::Query::insert()
             .into_table(Self::Table)
             .columns([
                 Self::Uri,
@@ -131,23 +136,64 @@ impl Comment {
                         Self::Text,
                         Self::Edited,
                     ])
+                    .action_and_where(
+                        Expr::col((Self::Table, Self::Cid)).ne(Expr::col(("excluded", Self::Cid))),
+                    )
                     .to_owned(),
             )
-            .build_sqlx(PostgresQueryBuilder);
-        db.execute(query_with(&sql, values)).await?;
+            .take())
+    }
+
+    /// The section of the post the comment is on, failing with a
+    /// `ThreadError` when that post is not indexed.
+    pub async fn check_thread(db: &Pool<Postgres>, comment: &Value, uri: &str) -> Result<i32> {
+        let comment = &*normalize_record(comment, uri);
+        let (post, _) = post_of(comment)?;
+        Post::section_of(db, post)
+            .await?
+            .ok_or_else(|| ThreadError::PostNotFound(post.to_string()).into())
+    }
+
+    /// Indexes the comment under the section of its post, whatever section
+    /// it claims, and refuses it when that post is not indexed. Whether the
+    /// row changed; a replayed record was indexed with the same cid already.
+    pub async fn upsert(
+        db: &Pool<Postgres>,
+        repo: &str,
+        comment: &Value,
+        uri: &str,
+        cid: &str,
+    ) -> Result<bool> {
+        let section_id = Self::check_thread(db, comment, uri).await?;
+        let comment = &*with_section_id(comment, uri, section_id);
+        let (sql, values) =
+            Self::build_insert(repo, comment, uri, cid)?.build_sqlx(PostgresQueryBuilder);
+        Ok(db::execute(db, &sql, values).await?.rows_affected() > 0)
+    }
+
+    /// `upsert`, then bumps the post and notifies of a new comment once.
+    pub async fn insert(
+        db: &Pool<Postgres>,
+        repo: &str,
+        comment: &Value,
+        uri: &str,
+        cid: &str,
+    ) -> Result<()> {
+        if !Self::upsert(db, repo, comment, uri, cid).await? {
+            return Ok(());
+        }
+        let (post, receiver) = post_of(comment)?;
 
         // update Post::Updated
-        let (sql, values) = sea_query::Query::update()
-            .table(Post::Table)
-            .values([(Post::Updated, (chrono::Local::now()).into())])
-            .and_where(Expr::col(Post::Uri).eq(post))
-            .build_sqlx(PostgresQueryBuilder);
-        db.execute(query_with(&sql, values)).await.ok();
+        Post::bump(db, post)
+            .await
+            .map_err(|e| error!("Post::bump failed: {e}"))
+            .ok();
 
         // notify
-        let (receiver, _nsid, _rkey) = resolve_uri(post)?;
-        Notify::insert(
+        Notify::insert_once(
             db,
+            uri,
             &NotifyRow {
                 id: 0,
                 title: "New Comment".to_string(),
@@ -183,19 +229,20 @@ impl Comment {
         .expr(Expr::cust("(select count(\"like\".\"uri\") from \"like\" where \"like\".\"to\" = \"comment\".\"uri\") as like_count"))
         .expr(Expr::cust("(select count(\"reply\".\"uri\") from \"reply\" where \"reply\".\"comment\" = \"comment\".\"uri\") as reply_count"))
         .expr(if let Some(viewer) = &viewer {
-            Expr::cust(format!("((select count(\"like\".\"uri\") from \"like\" where \"like\".\"repo\" = '{viewer}' and \"like\".\"to\" = \"comment\".\"uri\" ) > 0) as liked"))
+            Expr::cust_with_values("((select count(\"like\".\"uri\") from \"like\" where \"like\".\"repo\" = $1 and \"like\".\"to\" = \"comment\".\"uri\" ) > 0) as liked", [viewer.as_str()])
         } else {
             Expr::cust("false as liked".to_string())
         })
+        .expr(Operation::open_report("comment", &viewer))
         .from(Self::Table).take()
     }
 
-    pub async fn update_tag(
-        db: &Pool<Postgres>,
+    /// Sets the given flags of the comment; `None` when none is given.
+    pub fn build_update_tag(
         uri: &str,
         is_disabled: Option<bool>,
         reasons_for_disabled: Option<String>,
-    ) -> Result<()> {
+    ) -> Option<sea_query::UpdateStatement> {
         let mut values = Vec::new();
         if let Some(is_disabled) = is_disabled {
             values.push((Self::IsDisabled, is_disabled.into()));
@@ -204,21 +251,49 @@ impl Comment {
             values.push((Self::ReasonsForDisabled, reasons_for_disabled.into()));
         }
         if values.is_empty() {
-            return Ok(());
+            return None;
         }
 
         values.push((Self::Updated, Expr::current_timestamp()));
 
-        let (sql, values) = sea_query::Query::update()
-            .table(Self::Table)
-            .values(values)
-            .and_where(Expr::col(Self::Uri).eq(uri))
-            .build_sqlx(PostgresQueryBuilder);
-        db.execute(query_with(&sql, values)).await?;
+        Some(
+            sea_query::Query::update()
+                .table(Self::Table)
+                .values(values)
+                .and_where(Expr::col(Self::Uri).eq(uri))
+                .take(),
+        )
+    }
+
+    pub async fn update_tag(
+        db: &Pool<Postgres>,
+        uri: &str,
+        is_disabled: Option<bool>,
+        reasons_for_disabled: Option<String>,
+    ) -> Result<()> {
+        if let Some(update) = Self::build_update_tag(uri, is_disabled, reasons_for_disabled) {
+            let (sql, values) = update.build_sqlx(PostgresQueryBuilder);
+            db::execute(db, &sql, values).await?;
+        }
         Ok(())
     }
 }
 
+/// The post uri of a comment record and the post's author. A comment only
+/// ever belongs to a post, so anything else is refused before it is stored.
+fn post_of(comment: &Value) -> Result<(&str, &str)> {
+    let post = comment["post"]
+        .as_str()
+        .map(|s| s.trim_matches('\"'))
+        .ok_or_eyre("error in post")?;
+    match resolve_uri(post) {
+        Ok((author, NSID_POST, rkey)) if post.starts_with("at://") && !rkey.is_empty() => {
+            Ok((post, author))
+        }
+        _ => Err(eyre!("error in post: {post} is not a post uri")),
+    }
+}
+
 #[derive(sqlx::FromRow, Debug, Serialize, Clone)]
 pub struct CommentRow {
     pub uri: String,
@@ -235,6 +310,7 @@ pub struct CommentRow {
     pub like_count: i64,
     pub liked: bool,
     pub reply_count: i64,
+    pub reported: Option<DateTime<Local>>,
 }
 
 #[derive(Debug, Serialize)]
@@ -249,11 +325,20 @@ pub struct CommentView {
     pub edited: Option<DateTime<Local>>,
     pub updated: DateTime<Local>,
     pub created: DateTime<Local>,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub like_count: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub tip_count: String,
     pub replies: Value,
     pub liked: bool,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub reply_count: String,
+    /// Moderators only: whether `edited` is later than the earliest flag
+    /// on the comment no moderator acted on yet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edited_after_report: Option<bool>,
+    #[serde(skip)]
+    reported: Option<DateTime<Local>>,
 }
 
 impl CommentView {
@@ -274,6 +359,70 @@ impl CommentView {
             replies,
             liked: row.liked,
             reply_count: row.reply_count.to_string(),
+            edited_after_report: None,
+            reported: row.reported,
+        }
+    }
+
+    /// Redact the moderator note unless the viewer may read it.
+    pub fn for_viewer(mut self, privileged: bool) -> Self {
+        self.reasons_for_disabled =
+            reasons_for_viewer(self.reasons_for_disabled, self.is_disabled, privileged);
+        self
+    }
+
+    /// Tell moderators whether the comment was edited after it was reported.
+    pub fn for_moderator(mut self, moderator: bool) -> Self {
+        self.edited_after_report = edited_after_report(self.edited, self.reported, moderator);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn comments_belong_to_posts() {
+        let comment = json!({ "post": "at://did:ckb:alice/app.bbs.post/3kabc" });
+        assert_eq!(
+            post_of(&comment).unwrap(),
+            ("at://did:ckb:alice/app.bbs.post/3kabc", "did:ckb:alice")
+        );
+
+        for post in [
+            json!("did:ckb:alice"),
+            json!("at://did:ckb:alice/app.bbs.comment/3kabc"),
+            json!("at://did:ckb:alice/app.bbs.post/"),
+            json!(1),
+        ] {
+            assert!(post_of(&json!({ "post": post })).is_err(), "{post}");
         }
+        assert!(post_of(&json!({})).is_err());
+    }
+
+    #[test]
+    fn legacy_comments_insert_the_same_row() {
+        let uri = "at://did:ckb:bob/app.bbs.comment/3kdef";
+        let insert = |comment: Value| {
+            Comment::build_insert("did:ckb:bob", &comment, uri, "bafy")
+                .unwrap()
+                .to_string(PostgresQueryBuilder)
+        };
+        let current = insert(json!({
+            "section_id": "2",
+            "post": "at://did:ckb:alice/app.bbs.post/3kabc",
+            "text": "Nice",
+            "created": "2024-05-01T08:00:00+08:00",
+        }));
+        let legacy = json!({
+            "sectionId": 2,
+            "post": "at://did:ckb:alice/app.bbs.post/3kabc",
+            "content": "Nice",
+            "created": "2024-05-01T08:00:00+08:00",
+        });
+        assert_eq!(insert(legacy), current);
     }
 }
@@ -0,0 +1,157 @@
+use chrono::{DateTime, Local};
+use color_eyre::{Result, eyre::eyre};
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, Order, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde::Serialize;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::{content_filter::Ruleset, db};
+
+/// Automated filter rules checked when content is created.
+#[derive(Iden)]
+pub enum ContentRule {
+    Table,
+    Id,
+    /// `word`, `regex` or `domain`.
+    Kind,
+    Pattern,
+    /// `reject`, `flag` or `shadow`.
+    Action,
+    /// Shown to authors instead of the pattern.
+    Category,
+    /// 0 applies to every section.
+    SectionId,
+    Created,
+}
+
+impl ContentRule {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Self::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(Self::Kind).string().not_null())
+            .col(ColumnDef::new(Self::Pattern).string().not_null())
+            .col(ColumnDef::new(Self::Action).string().not_null())
+            .col(ColumnDef::new(Self::Category).string().not_null())
+            .col(
+                ColumnDef::new(Self::SectionId)
+                    .integer()
+                    .not_null()
+                    .default(0),
+            )
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
+        Ok(())
+    }
+
+    pub fn build_select() -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .columns([
+                Self::Id,
+                Self::Kind,
+                Self::Pattern,
+                Self::Action,
+                Self::Category,
+                Self::SectionId,
+                Self::Created,
+            ])
+            .from(Self::Table)
+            .order_by(Self::Id, Order::Asc)
+            .take()
+    }
+
+    pub async fn all(db: &Pool<Postgres>) -> Result<Vec<ContentRuleRow>> {
+        let (sql, values) = Self::build_select().build_sqlx(PostgresQueryBuilder);
+        db::fetch_all(db, &sql, values)
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))
+    }
+
+    pub async fn ruleset(db: &Pool<Postgres>) -> Result<Ruleset> {
+        Ok(Ruleset::compile(&Self::all(db).await?))
+    }
+
+    pub async fn insert(db: &Pool<Postgres>, rule: &ContentRuleRow) -> Result<i32> {
+        let (sql, values) = sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([
+                Self::Kind,
+                Self::Pattern,
+                Self::Action,
+                Self::Category,
+                Self::SectionId,
+            ])
+            .values([
+                rule.kind.as_str().into(),
+                rule.pattern.as_str().into(),
+                rule.action.as_str().into(),
+                rule.category.as_str().into(),
+                rule.section_id.into(),
+            ])?
+            .returning_col(Self::Id)
+            .build_sqlx(PostgresQueryBuilder);
+        let (id,): (i32,) = db::fetch_one(db, &sql, values).await?;
+        Ok(id)
+    }
+
+    pub async fn delete(db: &Pool<Postgres>, id: i32) -> Result<()> {
+        let (sql, values) = sea_query::Query::delete()
+            .from_table(Self::Table)
+            .and_where(Expr::col(Self::Id).eq(id))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, Serialize)]
+pub struct ContentRuleRow {
+    pub id: i32,
+    pub kind: String,
+    pub pattern: String,
+    pub action: String,
+    pub category: String,
+    pub section_id: i32,
+    pub created: DateTime<Local>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContentRuleView {
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    pub id: String,
+    pub kind: String,
+    pub pattern: String,
+    pub action: String,
+    pub category: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    pub section_id: String,
+    pub created: DateTime<Local>,
+}
+
+impl ContentRuleView {
+    pub fn build(row: ContentRuleRow) -> Self {
+        Self {
+            id: row.id.to_string(),
+            kind: row.kind,
+            pattern: row.pattern,
+            action: row.action,
+            category: row.category,
+            section_id: row.section_id.to_string(),
+            created: row.created,
+        }
+    }
+}
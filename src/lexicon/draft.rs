@@ -0,0 +1,226 @@
+use color_eyre::Result;
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, OnConflict, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde_json::{Value, json};
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::{
+    atproto::{NSID_POST, next_tid},
+    db,
+    lexicon::{post::PostDraftRow, section::Section},
+};
+
+/// Uri scheme of drafts that only live in the app view and were never
+/// written to the PDS.
+pub const LOCAL_DRAFT_SCHEME: &str = "draft://";
+
+#[derive(Iden)]
+pub enum Draft {
+    Table,
+    Uri,
+    Repo,
+    SectionId,
+    Title,
+    Text,
+    Updated,
+    Created,
+}
+
+impl Draft {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Self::Uri).string().not_null().primary_key())
+            .col(ColumnDef::new(Self::Repo).string().not_null())
+            .col(
+                ColumnDef::new(Self::SectionId)
+                    .integer()
+                    .not_null()
+                    .default(0),
+            )
+            .col(ColumnDef::new(Self::Title).string().not_null().default(""))
+            .col(ColumnDef::new(Self::Text).string().not_null().default(""))
+            .col(
+                ColumnDef::new(Self::Updated)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
+        Ok(())
+    }
+
+    /// Keyed by a TID like a record, so two drafts saved in the same
+    /// microsecond still get apart uris.
+    pub fn new_uri(repo: &str) -> String {
+        format!("{LOCAL_DRAFT_SCHEME}{repo}/{}", next_tid())
+    }
+
+    pub fn is_local(uri: &str) -> bool {
+        uri.starts_with(LOCAL_DRAFT_SCHEME)
+    }
+
+    /// Insert a draft or update it in place. Only the fields that are `Some`
+    /// overwrite an existing row, so auto-save can send title or text alone.
+    pub fn build_upsert(
+        uri: &str,
+        repo: &str,
+        section_id: Option<i32>,
+        title: Option<&str>,
+        text: Option<&str>,
+    ) -> sea_query::InsertStatement {
+        let mut update_columns = vec![Self::Updated];
+        if section_id.is_some() {
+            update_columns.push(Self::SectionId);
+        }
+        if title.is_some() {
+            update_columns.push(Self::Title);
+        }
+        if text.is_some() {
+            update_columns.push(Self::Text);
+        }
+        sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([
+                Self::Uri,
+                Self::Repo,
+                Self::SectionId,
+                Self::Title,
+                Self::Text,
+                Self::Updated,
+            ])
+            .values_panic([
+                uri.into(),
+                repo.into(),
+                section_id.unwrap_or_default().into(),
+                title.unwrap_or_default().into(),
+                text.unwrap_or_default().into(),
+                Expr::current_timestamp(),
+            ])
+            .on_conflict(
+                OnConflict::column(Self::Uri)
+                    .update_columns(update_columns)
+                    .to_owned(),
+            )
+            .take()
+    }
+
+    pub async fn upsert(
+        db: &Pool<Postgres>,
+        uri: &str,
+        repo: &str,
+        section_id: Option<i32>,
+        title: Option<&str>,
+        text: Option<&str>,
+    ) -> Result<()> {
+        let (sql, values) =
+            Self::build_upsert(uri, repo, section_id, title, text).build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    /// Select local drafts in the same shape as `Post::build_draft_select`,
+    /// so both can be read as `PostDraftRow` and unioned together.
+    pub fn build_select() -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .column((Draft::Table, Draft::Uri))
+            .expr_as(Expr::val(""), "cid")
+            .columns([
+                (Draft::Table, Draft::Repo),
+                (Draft::Table, Draft::Title),
+                (Draft::Table, Draft::Text),
+            ])
+            .expr_as(Expr::val(true), "is_draft")
+            .expr_as(Expr::cust("null::timestamptz"), "edited")
+            .columns([
+                (Draft::Table, Draft::Updated),
+                (Draft::Table, Draft::Created),
+            ])
+            .expr_as(Expr::col((Draft::Table, Draft::SectionId)), "id")
+            .expr_as(Expr::cust("coalesce(\"section\".\"name\", '')"), "name")
+            .from(Draft::Table)
+            .left_join(
+                Section::Table,
+                Expr::col((Draft::Table, Draft::SectionId)).equals((Section::Table, Section::Id)),
+            )
+            .take()
+    }
+
+    /// The post record a local draft is published as.
+    pub fn to_record(row: &PostDraftRow) -> Value {
+        json!({
+            "$type": NSID_POST,
+            "section_id": row.section_id.to_string(),
+            "title": row.title,
+            "text": row.text,
+            "is_draft": false,
+            "created": chrono::Local::now().to_rfc3339(),
+        })
+    }
+
+    pub async fn delete(db: &Pool<Postgres>, uri: &str) -> Result<()> {
+        let (sql, values) = sea_query::Query::delete()
+            .from_table(Self::Table)
+            .and_where(Expr::col(Self::Uri).eq(uri))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_save_only_overwrites_given_fields() {
+        let uri = Draft::new_uri("did:ckb:alice");
+        assert!(Draft::is_local(&uri));
+        assert_ne!(uri, Draft::new_uri("did:ckb:alice"));
+        let sql = Draft::build_upsert(&uri, "did:ckb:alice", None, Some("hello"), None)
+            .to_string(PostgresQueryBuilder);
+        let (_, conflict) = sql.split_once("ON CONFLICT").unwrap();
+        assert!(conflict.contains("\"title\" = \"excluded\".\"title\""));
+        assert!(conflict.contains("\"updated\" = \"excluded\".\"updated\""));
+        assert!(!conflict.contains("\"text\""));
+        assert!(!conflict.contains("\"section_id\""));
+    }
+
+    #[test]
+    fn publish_promotes_draft_to_post_record() {
+        let row = PostDraftRow {
+            uri: Draft::new_uri("did:ckb:alice"),
+            cid: String::new(),
+            repo: "did:ckb:alice".to_string(),
+            title: "hello".to_string(),
+            text: "world".to_string(),
+            is_draft: true,
+            edited: None,
+            updated: chrono::Local::now(),
+            created: chrono::Local::now(),
+            section_id: 3,
+            section: "ckb".to_string(),
+        };
+        let record = Draft::to_record(&row);
+        assert_eq!(record["$type"], NSID_POST);
+        assert_eq!(record["section_id"], "3");
+        assert_eq!(record["title"], "hello");
+        assert_eq!(record["text"], "world");
+        assert_eq!(record["is_draft"], false);
+        assert!(
+            record["created"]
+                .as_str()
+                .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
+                .is_some()
+        );
+    }
+}
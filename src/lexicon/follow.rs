@@ -0,0 +1,130 @@
+use color_eyre::{Result, eyre::OptionExt};
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, OnConflict, Order, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::db;
+
+#[derive(Iden)]
+pub enum Follow {
+    Table,
+    Uri,
+    Cid,
+    Repo,
+    ToDid,
+    Updated,
+    Created,
+}
+
+impl Follow {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Self::Uri).string().not_null().primary_key())
+            .col(ColumnDef::new(Self::Cid).string().not_null())
+            .col(ColumnDef::new(Self::Repo).string().not_null())
+            .col(ColumnDef::new(Self::ToDid).string().not_null())
+            .col(
+                ColumnDef::new(Self::Updated)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+        Ok(())
+    }
+
+    pub async fn insert(
+        db: &Pool<Postgres>,
+        repo: &str,
+        follow: &Value,
+        uri: &str,
+        cid: &str,
+    ) -> Result<()> {
+        let to = follow["to"]
+            .as_str()
+            .map(|s| s.trim_matches('\"'))
+            .ok_or_eyre("error in to")?;
+        let created = follow["created"]
+            .as_str()
+            .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
+            .ok_or_eyre("error in created")?;
+        let (sql, values) = sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([
+                Self::Uri,
+                Self::Cid,
+                Self::Repo,
+                Self::ToDid,
+                Self::Updated,
+                Self::Created,
+            ])
+            .values([
+                uri.into(),
+                cid.into(),
+                repo.into(),
+                to.into(),
+                Expr::current_timestamp(),
+                created.into(),
+            ])?
+            .returning_col(Self::Uri)
+            .on_conflict(
+                OnConflict::column(Self::Uri)
+                    .update_columns([Self::Cid, Self::Repo, Self::ToDid, Self::Updated])
+                    .to_owned(),
+            )
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    /// Who follows `did`, newest first, with the total on every row.
+    pub fn build_followers(did: &str) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .expr_as(Expr::col(Self::Repo), "did")
+            .expr(Expr::cust("COUNT(*) OVER() as total"))
+            .from(Self::Table)
+            .and_where(Expr::col(Self::ToDid).eq(did))
+            .order_by(Self::Created, Order::Desc)
+            .take()
+    }
+
+    /// Who `did` follows, newest first, with the total on every row.
+    pub fn build_following(did: &str) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .expr_as(Expr::col(Self::ToDid), "did")
+            .expr(Expr::cust("COUNT(*) OVER() as total"))
+            .from(Self::Table)
+            .and_where(Expr::col(Self::Repo).eq(did))
+            .order_by(Self::Created, Order::Desc)
+            .take()
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize)]
+pub struct FollowDidRow {
+    pub did: String,
+    pub total: i64,
+}
+
+#[test]
+fn follow_lists() {
+    assert_eq!(
+        Follow::build_followers("did:ckb:alice").to_string(PostgresQueryBuilder),
+        "SELECT \"repo\" AS \"did\", COUNT(*) OVER() as total FROM \"follow\" WHERE \"to_did\" = 'did:ckb:alice' ORDER BY \"created\" DESC"
+    );
+    assert_eq!(
+        Follow::build_following("did:ckb:alice").to_string(PostgresQueryBuilder),
+        "SELECT \"to_did\" AS \"did\", COUNT(*) OVER() as total FROM \"follow\" WHERE \"repo\" = 'did:ckb:alice' ORDER BY \"created\" DESC"
+    );
+}
@@ -1,14 +1,24 @@
 use chrono::{DateTime, Local};
 use color_eyre::{Result, eyre::OptionExt};
-use sea_query::{ColumnDef, Expr, Iden, OnConflict, PostgresQueryBuilder};
+use sea_query::{
+    ColumnDef, Expr, ExprTrait, Func, Iden, IntoIden, OnConflict, Order, PostgresQueryBuilder,
+    UnionType,
+};
 use sea_query_sqlx::SqlxBinder;
 use serde::Serialize;
 use serde_json::Value;
-use sqlx::{Executor, Pool, Postgres, query, query_with};
+use sqlx::{Executor, Pool, Postgres, query};
 
-use crate::lexicon::{
-    notify::{Notify, NotifyRow, NotifyType},
-    resolve_uri,
+use crate::{
+    db,
+    lexicon::{
+        comment::Comment,
+        notify::{Notify, NotifyRow, NotifyType},
+        post::Post,
+        removed_repo::RemovedRepo,
+        reply::Reply,
+        resolve_uri,
+    },
 };
 
 #[derive(Iden)]
@@ -23,6 +33,14 @@ pub enum Like {
     Created,
 }
 
+/// The uri of what the like is for.
+fn target_of(like: &Value) -> Result<&str> {
+    like["to"]
+        .as_str()
+        .map(|s| s.trim_matches('\"'))
+        .ok_or_eyre("error in to")
+}
+
 impl Like {
     pub async fn init(db: &Pool<Postgres>) -> Result<()> {
         let sql = sea_query::Table::create()
@@ -50,21 +68,20 @@ impl Like {
         Ok(())
     }
 
-    pub async fn insert(
+    /// Whether the row changed; a replayed record was indexed with the same
+    /// cid already.
+    pub async fn upsert(
         db: &Pool<Postgres>,
         repo: &str,
         like: &Value,
         uri: &str,
         cid: &str,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         let section_id = like["section_id"]
             .as_str()
             .and_then(|s| s.parse::<i32>().ok())
             .ok_or_eyre("error in section_id")?;
-        let to = like["to"]
-            .as_str()
-            .map(|s| s.trim_matches('\"'))
-            .ok_or_eyre("error in to")?;
+        let to = target_of(like)?;
         let created = like["created"]
             .as_str()
             .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
@@ -99,15 +116,33 @@ impl Like {
                         Self::To,
                         Self::Updated,
                     ])
+                    .action_and_where(
+                        Expr::col((Self::Table, Self::Cid)).ne(Expr::col(("excluded", Self::Cid))),
+                    )
                     .to_owned(),
             )
             .build_sqlx(PostgresQueryBuilder);
-        db.execute(query_with(&sql, values)).await?;
+        Ok(db::execute(db, &sql, values).await?.rows_affected() > 0)
+    }
+
+    /// `upsert`, then notifies the author of what was liked once.
+    pub async fn insert(
+        db: &Pool<Postgres>,
+        repo: &str,
+        like: &Value,
+        uri: &str,
+        cid: &str,
+    ) -> Result<()> {
+        if !Self::upsert(db, repo, like, uri, cid).await? {
+            return Ok(());
+        }
 
         // notify
+        let to = target_of(like)?;
         let (receiver, _nsid, _rkey) = resolve_uri(to)?;
-        Notify::insert(
+        Notify::insert_once(
             db,
+            uri,
             &NotifyRow {
                 id: 0,
                 title: "New Like".to_string(),
@@ -124,6 +159,145 @@ impl Like {
         .ok();
         Ok(())
     }
+
+    /// A page of the likes of `to`, newest first, without the ones of
+    /// authors who removed their data. `before` is the `created` and `uri`
+    /// of the last like of the previous page.
+    pub fn build_engagement(
+        to: &str,
+        before: Option<(DateTime<Local>, &str)>,
+        limit: u64,
+    ) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .columns([Self::Uri, Self::Repo, Self::Created])
+            .from(Self::Table)
+            .and_where(Expr::col(Self::To).eq(to))
+            .and_where(Expr::col(Self::Repo).not_in_subquery(RemovedRepo::build_suppressed()))
+            .and_where_option(before.map(|(created, uri)| {
+                Expr::col(Self::Created)
+                    .lt(created)
+                    .or(Expr::col(Self::Created)
+                        .eq(created)
+                        .and(Expr::col(Self::Uri).lt(uri)))
+            }))
+            .order_by(Self::Created, Order::Desc)
+            .order_by(Self::Uri, Order::Desc)
+            .limit(limit)
+            .take()
+    }
+
+    /// Likes per `granularity` bucket since `since`, oldest bucket first.
+    /// `granularity` is bound, and `date_trunc` rejects anything but a unit.
+    pub fn build_stats(
+        section_id: Option<i32>,
+        since: DateTime<Local>,
+        granularity: &str,
+    ) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .expr_as(
+                Func::cust("date_trunc").args([Expr::val(granularity), Expr::col(Self::Created)]),
+                "bucket",
+            )
+            .expr_as(Expr::col(Self::Uri).count(), "count")
+            .from(Self::Table)
+            .and_where(Expr::col(Self::Created).gt(since))
+            .and_where_option(section_id.map(|id| Expr::col(Self::SectionId).eq(id)))
+            .group_by_col("bucket")
+            .order_by("bucket", Order::Asc)
+            .take()
+    }
+
+    /// Likes on the posts, comments and replies `did` wrote, one row per
+    /// content type. `to` is the liked uri, never a did.
+    pub fn build_received(did: &str) -> sea_query::SelectStatement {
+        let content = [
+            (
+                "post",
+                Post::Table.into_iden(),
+                Post::Uri.into_iden(),
+                Post::Repo.into_iden(),
+            ),
+            (
+                "comment",
+                Comment::Table.into_iden(),
+                Comment::Uri.into_iden(),
+                Comment::Repo.into_iden(),
+            ),
+            (
+                "reply",
+                Reply::Table.into_iden(),
+                Reply::Uri.into_iden(),
+                Reply::Repo.into_iden(),
+            ),
+        ];
+        let mut selects = content.into_iter().map(|(kind, table, uri, repo)| {
+            sea_query::Query::select()
+                .expr_as(Expr::val(kind), "kind")
+                .expr_as(Expr::col((Self::Table, Self::Uri)).count(), "count")
+                .from(Self::Table)
+                .inner_join(
+                    table.clone(),
+                    Expr::col((Self::Table, Self::To)).equals((table.clone(), uri)),
+                )
+                .and_where(Expr::col((table, repo)).eq(did))
+                .take()
+        });
+        let mut received = selects.next().expect("content tables are not empty");
+        for select in selects {
+            received.union(UnionType::All, select);
+        }
+        received
+    }
+
+    /// Unread like notifications go with their likes, so an undone like
+    /// leaves the receiver's inbox as it was; read ones are history.
+    pub fn build_delete(uris: &[String]) -> [sea_query::DeleteStatement; 2] {
+        [
+            sea_query::Query::delete()
+                .from_table(Notify::Table)
+                .and_where(Expr::col(Notify::NType).eq(NotifyType::NewLike as i32))
+                .and_where(Expr::col(Notify::Readed).is_null())
+                .and_where(
+                    Expr::tuple([Expr::col(Notify::Sender), Expr::col(Notify::TargetUri)])
+                        .in_subquery(
+                            sea_query::Query::select()
+                                .columns([Self::Repo, Self::To])
+                                .from(Self::Table)
+                                .and_where(
+                                    Expr::col(Self::Uri).is_in(uris.iter().map(String::as_str)),
+                                )
+                                .take(),
+                        ),
+                )
+                .take(),
+            sea_query::Query::delete()
+                .from_table(Self::Table)
+                .and_where(Expr::col(Self::Uri).is_in(uris.iter().map(String::as_str)))
+                .take(),
+        ]
+    }
+
+    pub async fn delete(db: &Pool<Postgres>, uris: &[String]) -> Result<()> {
+        let mut tx = db.begin().await?;
+        for statement in Self::build_delete(uris) {
+            let (sql, values) = statement.build_sqlx(PostgresQueryBuilder);
+            db::execute(&mut *tx, &sql, values).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize)]
+pub struct LikeStatsRow {
+    pub bucket: DateTime<Local>,
+    pub count: i64,
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize)]
+pub struct LikeReceivedRow {
+    pub kind: String,
+    pub count: i64,
 }
 
 #[derive(sqlx::FromRow, Debug, Serialize)]
@@ -147,3 +321,159 @@ pub struct LikeView {
     pub updated: DateTime<Local>,
     pub created: DateTime<Local>,
 }
+
+#[test]
+fn like_stats() {
+    let since = chrono::TimeZone::timestamp_opt(&Local, 1_700_000_000, 0).unwrap();
+    let sql = Like::build_stats(Some(3), since, "day").to_string(PostgresQueryBuilder);
+    assert!(sql.starts_with(
+        "SELECT date_trunc('day', \"created\") AS \"bucket\", COUNT(\"uri\") AS \"count\" FROM \"like\""
+    ));
+    assert!(sql.contains("AND \"section_id\" = 3"));
+    assert!(sql.ends_with("GROUP BY \"bucket\" ORDER BY \"bucket\" ASC"));
+    let sql = Like::build_stats(None, since, "hour").to_string(PostgresQueryBuilder);
+    assert!(!sql.contains("section_id"));
+}
+
+#[test]
+fn engagement_likes_page_newest_first() {
+    let created = chrono::TimeZone::timestamp_opt(&Local, 1_700_000_000, 0).unwrap();
+    let sql = Like::build_engagement(
+        "at://did:ckb:alice/app.bbs.post/1",
+        Some((created, "at://did:ckb:bob/app.bbs.like/1")),
+        20,
+    )
+    .to_string(PostgresQueryBuilder);
+    assert!(sql.starts_with(
+        "SELECT \"uri\", \"repo\", \"created\" FROM \"like\" WHERE \"to\" = 'at://did:ckb:alice/app.bbs.post/1' AND \"repo\" NOT IN (SELECT \"did\" FROM \"removed_repo\" WHERE \"suppress_until\" > CURRENT_TIMESTAMP)"
+    ));
+    assert!(sql.contains("\"uri\" < 'at://did:ckb:bob/app.bbs.like/1'"));
+    assert!(sql.ends_with("ORDER BY \"created\" DESC, \"uri\" DESC LIMIT 20"));
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn engagement_likes_skip_removed_authors() {
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO removed_repo VALUES ('did:ckb:carol', now() + interval '1 day', now() + interval '1 day'), ('did:ckb:dave', now() - interval '2 days', now() - interval '1 day')",
+        "INSERT INTO \"like\" (uri, cid, repo, section_id, \"to\", created) VALUES ('at://did:ckb:alice/app.bbs.like/1', 'bafy', 'did:ckb:alice', 1, 'at://did:ckb:erin/app.bbs.post/1', '2024-01-01T00:00:00Z'), ('at://did:ckb:bob/app.bbs.like/1', 'bafy', 'did:ckb:bob', 1, 'at://did:ckb:erin/app.bbs.post/1', '2024-01-02T00:00:00Z'), ('at://did:ckb:carol/app.bbs.like/1', 'bafy', 'did:ckb:carol', 1, 'at://did:ckb:erin/app.bbs.post/1', '2024-01-02T00:00:00Z'), ('at://did:ckb:dave/app.bbs.like/1', 'bafy', 'did:ckb:dave', 1, 'at://did:ckb:erin/app.bbs.post/1', '2024-01-02T00:00:00Z'), ('at://did:ckb:bob/app.bbs.like/2', 'bafy', 'did:ckb:bob', 1, 'at://did:ckb:erin/app.bbs.post/2', '2024-01-03T00:00:00Z')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+
+    let mut repos = vec![];
+    let mut before: Option<(DateTime<Local>, String)> = None;
+    loop {
+        let (sql, values) = Like::build_engagement(
+            "at://did:ckb:erin/app.bbs.post/1",
+            before
+                .as_ref()
+                .map(|(created, uri)| (*created, uri.as_str())),
+            1,
+        )
+        .build_sqlx(PostgresQueryBuilder);
+        let rows: Vec<(String, String, DateTime<Local>)> =
+            db::fetch_all(&db, &sql, values).await.unwrap();
+        let Some((uri, repo, created)) = rows.into_iter().next() else {
+            break;
+        };
+        repos.push(repo);
+        before = Some((created, uri));
+    }
+    // dave's suppression is over; carol's is not
+    assert_eq!(repos, ["did:ckb:dave", "did:ckb:bob", "did:ckb:alice"]);
+}
+
+#[test]
+fn likes_received_join_content() {
+    let sql = Like::build_received("did:ckb:alice").to_string(PostgresQueryBuilder);
+    assert!(sql.starts_with(
+        "SELECT 'post' AS \"kind\", COUNT(\"like\".\"uri\") AS \"count\" FROM \"like\" INNER JOIN \"post\" ON \"like\".\"to\" = \"post\".\"uri\" WHERE \"post\".\"repo\" = 'did:ckb:alice'"
+    ));
+    assert!(sql.contains("INNER JOIN \"comment\" ON \"like\".\"to\" = \"comment\".\"uri\""));
+    assert!(sql.contains("WHERE \"reply\".\"repo\" = 'did:ckb:alice'"));
+    assert_eq!(sql.matches(" UNION ALL ").count(), 2);
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn likes_received_count_likes_on_content() {
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft) VALUES ('at://did:ckb:alice/app.bbs.post/1', 'bafy', 'did:ckb:alice', 1, 't', 't', false)",
+        "INSERT INTO \"like\" (uri, cid, repo, section_id, \"to\") VALUES ('at://did:ckb:bob/app.bbs.like/1', 'bafy', 'did:ckb:bob', 1, 'at://did:ckb:alice/app.bbs.post/1')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+
+    // what author stats used to count
+    let (sql, values) = sea_query::Query::select()
+        .expr(Expr::col((Like::Table, Like::Uri)).count())
+        .from(Like::Table)
+        .and_where(Expr::col(Like::To).eq("did:ckb:alice"))
+        .build_sqlx(PostgresQueryBuilder);
+    let (old,): (i64,) = db::fetch_one(&db, &sql, values).await.unwrap();
+    assert_eq!(old, 0);
+
+    let (sql, values) = Like::build_received("did:ckb:alice").build_sqlx(PostgresQueryBuilder);
+    let rows: Vec<LikeReceivedRow> = db::fetch_all(&db, &sql, values).await.unwrap();
+    let count = |kind: &str| rows.iter().find(|row| row.kind == kind).unwrap().count;
+    assert_eq!(count("post"), 1);
+    assert_eq!(count("comment"), 0);
+    assert_eq!(count("reply"), 0);
+}
+
+#[test]
+fn like_delete_takes_its_unread_notification() {
+    let uris = ["at://did:ckb:bob/app.bbs.like/1".to_string()];
+    let [notify, like] = Like::build_delete(&uris).map(|s| s.to_string(PostgresQueryBuilder));
+    assert_eq!(
+        notify,
+        "DELETE FROM \"notify\" WHERE \"n_type\" = 2 AND \"readed\" IS NULL AND (\"sender\", \"target_uri\") IN (SELECT \"repo\", \"to\" FROM \"like\" WHERE \"uri\" IN ('at://did:ckb:bob/app.bbs.like/1'))"
+    );
+    assert_eq!(
+        like,
+        "DELETE FROM \"like\" WHERE \"uri\" IN ('at://did:ckb:bob/app.bbs.like/1')"
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn unlike_restores_the_inbox() {
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO notify (title, sender, receiver, n_type, target_uri, amount) VALUES ('New Comment', 'did:ckb:carol', 'did:ckb:alice', 0, 'at://did:ckb:alice/app.bbs.post/1', 0)",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    async fn inbox(db: &Pool<Postgres>) -> Vec<(i32, String)> {
+        let (sql, values) = Notify::build_select()
+            .and_where(Expr::col(Notify::Receiver).eq("did:ckb:alice"))
+            .order_by(Notify::Id, Order::Asc)
+            .build_sqlx(PostgresQueryBuilder);
+        let rows: Vec<NotifyRow> = db::fetch_all(db, &sql, values).await.unwrap();
+        rows.into_iter().map(|row| (row.id, row.title)).collect()
+    }
+    let before = inbox(&db).await;
+
+    let uri = "at://did:ckb:bob/app.bbs.like/1";
+    let like = serde_json::json!({
+        "section_id": "1",
+        "to": "at://did:ckb:alice/app.bbs.post/1",
+        "created": "2024-01-01T00:00:00Z",
+    });
+    Like::insert(&db, "did:ckb:bob", &like, uri, "bafyrei")
+        .await
+        .unwrap();
+    assert_eq!(inbox(&db).await.len(), before.len() + 1);
+
+    Like::delete(&db, &[uri.to_string()]).await.unwrap();
+    assert_eq!(inbox(&db).await, before);
+    let (sql, values) = sea_query::Query::select()
+        .expr(Expr::col(Like::Uri).count())
+        .from(Like::Table)
+        .build_sqlx(PostgresQueryBuilder);
+    let (likes,): (i64,) = db::fetch_one(&db, &sql, values).await.unwrap();
+    assert_eq!(likes, 0);
+}
@@ -1,17 +1,189 @@
+use std::{borrow::Cow, fmt};
+
+use chrono::{DateTime, Local};
 use color_eyre::{Result, eyre::OptionExt};
+use serde_json::Value;
 
 pub(crate) mod administrator;
+pub(crate) mod audit_event;
+pub(crate) mod broadcast;
 pub(crate) mod comment;
+pub(crate) mod content_rule;
+pub(crate) mod draft;
+pub(crate) mod follow;
 pub(crate) mod like;
 pub(crate) mod notify;
+pub(crate) mod numeric;
 pub(crate) mod operation;
+pub(crate) mod parked_record;
+pub(crate) mod payout_pref;
 pub(crate) mod post;
+pub(crate) mod privacy_pref;
+pub(crate) mod profile;
+pub(crate) mod removed_repo;
 pub(crate) mod reply;
+pub(crate) mod repo_state;
 pub(crate) mod section;
+pub(crate) mod section_ban;
+pub(crate) mod section_stats;
 pub(crate) mod status;
+pub(crate) mod thread_mute;
 pub(crate) mod tip;
+pub(crate) mod visit_source;
+pub(crate) mod webhook;
 pub(crate) mod whitelist;
 
+/// Shown instead of the moderator note to viewers that may not read it.
+pub const HIDDEN_BY_MODERATORS: &str = "content hidden by moderators";
+
+/// Reason of content a content rule shadowed, followed by the rule category.
+pub const PENDING_REVIEW: &str = "pending review: ";
+
+/// Moderator notes are only for the author and section moderators. Everyone
+/// else learns that hidden content is hidden, and nothing about visible content.
+pub fn reasons_for_viewer(
+    reasons: Option<String>,
+    is_disabled: bool,
+    privileged: bool,
+) -> Option<String> {
+    if privileged {
+        reasons
+    } else if is_disabled {
+        Some(HIDDEN_BY_MODERATORS.to_string())
+    } else {
+        None
+    }
+}
+
+/// Whether content was edited after its earliest open report, for
+/// moderators only.
+pub fn edited_after_report(
+    edited: Option<DateTime<Local>>,
+    reported: Option<DateTime<Local>>,
+    moderator: bool,
+) -> Option<bool> {
+    moderator
+        .then(|| matches!((edited, reported), (Some(edited), Some(reported)) if edited > reported))
+}
+
+/// Fields early clients wrote under another name, with their current name.
+const LEGACY_FIELDS: [(&str, &str); 1] = [("content", "text")];
+
+fn snake_case(key: &str) -> String {
+    let mut snake = String::with_capacity(key.len() + 4);
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            snake.push('_');
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+/// The record of `uri` with the keys of early clients renamed: camelCase
+/// ones to snake_case and the `LEGACY_FIELDS` to their current name. A
+/// current key wins over a legacy one for the same field.
+pub fn normalize_record<'a>(record: &'a Value, uri: &str) -> Cow<'a, Value> {
+    let Some(fields) = record.as_object() else {
+        return Cow::Borrowed(record);
+    };
+    let legacy = |key: &str| {
+        let snake = snake_case(key);
+        LEGACY_FIELDS
+            .iter()
+            .find(|(alias, _)| *alias == snake)
+            .map_or(snake, |(_, field)| field.to_string())
+    };
+    if fields.keys().all(|key| legacy(key) == *key) {
+        return Cow::Borrowed(record);
+    }
+    let mut normalized = fields.clone();
+    for (key, value) in fields {
+        let field = legacy(key);
+        if field != *key {
+            normalized.remove(key);
+            if fields.contains_key(&field) {
+                continue;
+            }
+            info!("{uri}: legacy field `{key}` read as `{field}`");
+            normalized.insert(field, value.clone());
+        }
+    }
+    Cow::Owned(Value::Object(normalized))
+}
+
+/// The `section_id` of a record, written as a string or, by early clients,
+/// as a number.
+pub fn section_id_of(record: &Value) -> Result<i32> {
+    match &record["section_id"] {
+        Value::String(s) => s.parse().ok(),
+        Value::Number(n) => n.as_i64().and_then(|n| n.try_into().ok()),
+        _ => None,
+    }
+    .ok_or_eyre("error in section_id")
+}
+
+/// A comment or reply whose references do not hold together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThreadError {
+    /// The post it is on is not indexed.
+    PostNotFound(String),
+    /// The comment a reply answers is not indexed.
+    CommentNotFound(String),
+    /// The comment a reply answers is on another post than the reply.
+    CommentOnOtherPost { comment: String, post: String },
+    /// The `to` uri of a reply is neither its comment nor a reply to it,
+    /// or is the reply itself.
+    TargetOutsideThread(String),
+    /// The `section_id` of the record is not the one of its post.
+    SectionMismatch { claimed: Option<i32>, actual: i32 },
+}
+
+impl fmt::Display for ThreadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PostNotFound(post) => write!(f, "post {post} not found"),
+            Self::CommentNotFound(comment) => write!(f, "comment {comment} not found"),
+            Self::CommentOnOtherPost { comment, post } => {
+                write!(f, "comment {comment} is not on post {post}")
+            }
+            Self::TargetOutsideThread(to) => {
+                write!(f, "{to} is not the comment or a reply in this thread")
+            }
+            Self::SectionMismatch { claimed, actual } => match claimed {
+                Some(claimed) => write!(f, "section_id {claimed} is not the post's {actual}"),
+                None => write!(f, "section_id is not the post's {actual}"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for ThreadError {}
+
+/// The record with the `section_id` of its post, in place of whatever it
+/// claimed.
+pub fn with_section_id<'a>(record: &'a Value, uri: &str, section_id: i32) -> Cow<'a, Value> {
+    let claimed = section_id_of(record).ok();
+    if claimed == Some(section_id) {
+        return Cow::Borrowed(record);
+    }
+    warn!(
+        "{uri}: {}, indexing it there",
+        ThreadError::SectionMismatch {
+            claimed,
+            actual: section_id
+        }
+    );
+    let mut record = record.clone();
+    if let Some(fields) = record.as_object_mut() {
+        fields.remove("sectionId");
+        fields.insert("section_id".to_string(), section_id.to_string().into());
+    }
+    Cow::Owned(record)
+}
+
 pub fn resolve_uri(uri: &str) -> Result<(&str, &str, &str)> {
     let uri_split = uri.split('/').collect::<Vec<&str>>();
     let did = uri_split.get(2).ok_or_eyre("uri format error")?;
@@ -32,6 +204,62 @@ pub fn _resolve_client_uri(client_uri: &str) -> Result<(String, String, String,
     Ok((uri, did, nsid, rkey))
 }
 
+#[test]
+fn legacy_record_fields() {
+    use serde_json::json;
+
+    let record = json!({ "section_id": "1", "text": "hi" });
+    assert!(matches!(
+        normalize_record(&record, "at://x"),
+        Cow::Borrowed(_)
+    ));
+    let legacy = json!({ "sectionId": 1, "content": "hi", "isDraft": true });
+    assert_eq!(
+        *normalize_record(&legacy, "at://x"),
+        json!({ "section_id": 1, "text": "hi", "is_draft": true })
+    );
+    // the current field wins
+    let mixed = json!({ "section_id": "2", "sectionId": "1", "text": "new", "content": "old" });
+    assert_eq!(
+        *normalize_record(&mixed, "at://x"),
+        json!({ "section_id": "2", "text": "new" })
+    );
+
+    assert_eq!(section_id_of(&json!({ "section_id": "7" })).unwrap(), 7);
+    assert_eq!(section_id_of(&json!({ "section_id": 7 })).unwrap(), 7);
+    for section_id in [json!("seven"), json!(7.5), json!(1i64 << 40), json!(null)] {
+        assert!(section_id_of(&json!({ "section_id": section_id })).is_err());
+    }
+}
+
+#[test]
+fn records_take_the_section_of_their_post() {
+    use serde_json::json;
+
+    let record = json!({ "section_id": "3", "text": "hi" });
+    assert!(matches!(
+        with_section_id(&record, "at://x", 3),
+        Cow::Borrowed(_)
+    ));
+    assert_eq!(
+        *with_section_id(&record, "at://x", 4),
+        json!({ "section_id": "4", "text": "hi" })
+    );
+    let legacy = json!({ "sectionId": 3, "text": "hi" });
+    assert_eq!(
+        *with_section_id(&legacy, "at://x", 4),
+        json!({ "section_id": "4", "text": "hi" })
+    );
+    assert_eq!(
+        ThreadError::SectionMismatch {
+            claimed: Some(3),
+            actual: 4
+        }
+        .to_string(),
+        "section_id 3 is not the post's 4"
+    );
+}
+
 #[test]
 fn uri() {
     let uri = "at://did:ckb:52vmubyl4y3al5k246owb7nhkmwhwgx7/app.bbs.post/3mbnwjdssbc27";
@@ -1,12 +1,14 @@
 use chrono::{DateTime, Local};
 use color_eyre::Result;
-use sea_query::{ColumnDef, Expr, Iden, PostgresQueryBuilder};
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, Index, OnConflict, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
 use serde::Serialize;
 use serde_json::Value;
-use sqlx::{Executor, Pool, Postgres, query, query_with};
+use sqlx::{Executor, Pool, Postgres, query};
 use utoipa::ToSchema;
 
+use crate::{db, lexicon::thread_mute::ThreadMute};
+
 #[derive(Debug, Clone, Copy, ToSchema)]
 pub enum NotifyType {
     NewComment = 0,
@@ -16,8 +18,24 @@ pub enum NotifyType {
     NewDonate = 4,
     BeHidden = 5,
     BeDisplayed = 6,
+    // hidden by a content rule until a moderator reviews it; superseded by
+    // ModerationNeeded, kept for the notifications sent before
+    #[allow(dead_code)]
+    PendingReview = 7,
+    // a broadcast of the super administrators
+    Announcement = 8,
+    // kept from writing in a section for a while
+    BeBanned = 9,
+    // a prepared tip or donation of the receiver timed out untransferred
+    TipExpired = 10,
+    // content of a section the receiver moderates was flagged
+    ModerationNeeded = 11,
 }
 
+/// Flags on one target within this many hours of the last one update the
+/// moderator's notification about it rather than adding another.
+pub const MODERATION_WINDOW_HOURS: i64 = 24;
+
 #[derive(Iden, Debug, Clone, Copy)]
 pub enum Notify {
     Table,
@@ -30,6 +48,8 @@ pub enum Notify {
     Amount,
     Readed,
     Created,
+    /// The record that caused an activity notification.
+    SourceUri,
 }
 
 impl Notify {
@@ -65,6 +85,22 @@ impl Notify {
             .build(PostgresQueryBuilder);
         db.execute(query(&sql)).await?;
 
+        let sql = sea_query::Table::alter()
+            .table(Self::Table)
+            .add_column_if_not_exists(ColumnDef::new(Self::SourceUri).string())
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
+        // NULL sources, of notifications no record caused, never conflict
+        let sql = Index::create()
+            .if_not_exists()
+            .name("notify_source_uri")
+            .table(Self::Table)
+            .col(Self::SourceUri)
+            .unique()
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
         Ok(())
     }
 
@@ -85,7 +121,39 @@ impl Notify {
             .take()
     }
 
+    /// Skips activity notifications from threads the receiver muted;
+    /// moderation notices always go out.
     pub async fn insert(db: &Pool<Postgres>, notify: &NotifyRow) -> Result<()> {
+        Self::insert_from(db, notify, None).await
+    }
+
+    /// Like `insert`, for the notification the record `source_uri` causes.
+    /// It goes out once, however often the record is replayed or edited.
+    pub async fn insert_once(
+        db: &Pool<Postgres>,
+        source_uri: &str,
+        notify: &NotifyRow,
+    ) -> Result<()> {
+        Self::insert_from(db, notify, Some(source_uri)).await
+    }
+
+    async fn insert_from(
+        db: &Pool<Postgres>,
+        notify: &NotifyRow,
+        source_uri: Option<&str>,
+    ) -> Result<()> {
+        let activity = [
+            NotifyType::NewComment,
+            NotifyType::NewReply,
+            NotifyType::NewLike,
+            NotifyType::NewTip,
+        ];
+        if activity.iter().any(|t| *t as i32 == notify.n_type)
+            && ThreadMute::is_muted(db, &notify.receiver, &notify.target_uri).await
+        {
+            debug!("thread muted by {}: {}", notify.receiver, notify.target_uri);
+            return Ok(());
+        }
         let (sql, values) = sea_query::Query::insert()
             .into_table(Notify::Table)
             .columns([
@@ -97,6 +165,7 @@ impl Notify {
                 Notify::Amount,
                 Notify::Readed,
                 Notify::Created,
+                Notify::SourceUri,
             ])
             .values([
                 notify.title.clone().into(),
@@ -107,13 +176,87 @@ impl Notify {
                 notify.amount.into(),
                 notify.readed.into(),
                 Expr::current_timestamp(),
+                source_uri.into(),
             ])?
+            .on_conflict(
+                OnConflict::column(Notify::SourceUri)
+                    .do_nothing()
+                    .to_owned(),
+            )
             .returning_col(Self::Id)
             .build_sqlx(PostgresQueryBuilder);
 
-        db.execute(query_with(&sql, values)).await?;
+        db::execute(db, &sql, values).await?;
         Ok(())
     }
+
+    /// Moves the receiver's recent notification of the same type about the
+    /// same target to the top again, unread.
+    pub fn build_refresh(notify: &NotifyRow, window_hours: i64) -> sea_query::UpdateStatement {
+        sea_query::Query::update()
+            .table(Notify::Table)
+            .values([
+                (Notify::Sender, notify.sender.clone().into()),
+                (Notify::Readed, Option::<DateTime<Local>>::None.into()),
+                (Notify::Created, Expr::current_timestamp()),
+            ])
+            .and_where(Expr::col(Notify::Receiver).eq(notify.receiver.as_str()))
+            .and_where(Expr::col(Notify::NType).eq(notify.n_type))
+            .and_where(Expr::col(Notify::TargetUri).eq(notify.target_uri.as_str()))
+            .and_where(Expr::col(Notify::Created).gt(Expr::cust(format!(
+                "now() - interval '{window_hours} hours'"
+            ))))
+            .take()
+    }
+
+    /// Tells a moderator about flagged content, once per target within
+    /// `MODERATION_WINDOW_HOURS`. Moderators who muted the thread are left
+    /// out like for activity.
+    pub async fn insert_moderation(db: &Pool<Postgres>, notify: &NotifyRow) -> Result<()> {
+        if ThreadMute::is_muted(db, &notify.receiver, &notify.target_uri).await {
+            debug!("thread muted by {}: {}", notify.receiver, notify.target_uri);
+            return Ok(());
+        }
+        let (sql, values) =
+            Self::build_refresh(notify, MODERATION_WINDOW_HOURS).build_sqlx(PostgresQueryBuilder);
+        if db::execute(db, &sql, values).await?.rows_affected() > 0 {
+            return Ok(());
+        }
+        Self::insert(db, notify).await
+    }
+
+    /// One announcement notification per receiver, in a single insert.
+    pub fn build_announcements(
+        title: &str,
+        sender: &str,
+        target_uri: &str,
+        receivers: &[String],
+    ) -> Result<sea_query::InsertStatement> {
+        let mut insert = sea_query::Query::insert()
+            .into_table(Notify::Table)
+            .columns([
+                Notify::Title,
+                Notify::Sender,
+                Notify::Receiver,
+                Notify::NType,
+                Notify::TargetUri,
+                Notify::Amount,
+                Notify::Created,
+            ])
+            .take();
+        for receiver in receivers {
+            insert.values([
+                title.into(),
+                sender.into(),
+                receiver.as_str().into(),
+                (NotifyType::Announcement as i32).into(),
+                target_uri.into(),
+                0.into(),
+                Expr::current_timestamp(),
+            ])?;
+        }
+        Ok(insert)
+    }
 }
 
 #[derive(sqlx::FromRow, Debug, Serialize)]
@@ -125,6 +268,7 @@ pub struct NotifyRow {
     pub receiver: String,
     pub n_type: i32,
     pub target_uri: String,
+    /// Shannons of a tip or donation; 0 for other notifications.
     pub amount: i64,
     pub readed: Option<DateTime<Local>>,
     pub created: DateTime<Local>,
@@ -132,6 +276,7 @@ pub struct NotifyRow {
 
 #[derive(Debug, Serialize)]
 pub struct NotifyView {
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub id: String,
     pub title: String,
     pub sender: Value,
@@ -139,7 +284,33 @@ pub struct NotifyView {
     pub n_type: String,
     pub target_uri: String,
     pub target: Value,
+    /// In shannons.
     pub amount: i64,
+    /// `amount` in CKB, with 8 decimal places.
+    pub amount_ckb: String,
     pub readed: Option<DateTime<Local>>,
     pub created: DateTime<Local>,
 }
+
+#[test]
+fn moderation_notifies_refresh_within_the_window() {
+    let notify = NotifyRow {
+        id: 0,
+        title: "Moderation Needed".to_string(),
+        sender: "did:ckb:bob".to_string(),
+        receiver: "did:ckb:alice".to_string(),
+        n_type: NotifyType::ModerationNeeded as i32,
+        target_uri: "at://did:ckb:bob/app.bbs.post/3kabc".to_string(),
+        amount: 0,
+        readed: None,
+        created: Local::now(),
+    };
+    let sql = Notify::build_refresh(&notify, 24).to_string(PostgresQueryBuilder);
+    assert!(sql.starts_with(
+        "UPDATE \"notify\" SET \"sender\" = 'did:ckb:bob', \"readed\" = NULL, \"created\" = CURRENT_TIMESTAMP"
+    ));
+    assert!(sql.ends_with(&format!(
+        "WHERE \"receiver\" = 'did:ckb:alice' AND \"n_type\" = {} AND \"target_uri\" = 'at://did:ckb:bob/app.bbs.post/3kabc' AND \"created\" > (now() - interval '24 hours')",
+        NotifyType::ModerationNeeded as i32
+    )));
+}
@@ -0,0 +1,126 @@
+use serde::Serializer;
+use utoipa::openapi::schema::{KnownFormat, Object, ObjectBuilder, SchemaFormat, SchemaType, Type};
+
+/// `serialize_with` for the stringified integers of views: a number when
+/// `numeric_json` is on or the request asked for API version 2, else the
+/// string as before. Values that are not an integer stay strings either way.
+pub fn serialize<S: Serializer>(value: &impl AsRef<str>, serializer: S) -> Result<S::Ok, S::Error> {
+    let value = value.as_ref();
+    match value.parse::<i64>() {
+        Ok(n) if crate::api::version::numeric_counts() => serializer.serialize_i64(n),
+        _ => serializer.serialize_str(value),
+    }
+}
+
+/// Schema of the fields using `serialize`.
+pub fn schema() -> Object {
+    ObjectBuilder::new()
+        .schema_type(SchemaType::from_iter([Type::String, Type::Integer]))
+        .format(Some(SchemaFormat::KnownFormat(KnownFormat::Int64)))
+        .description(Some(
+            "An integer, as a string unless `numeric_json` is on or the request asks for API version 2.",
+        ))
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+    use serde_json::{Value, json};
+
+    use crate::{
+        api::version::{self, ApiVersion},
+        lexicon::{section::SectionView, tip::TipView},
+    };
+
+    fn views() -> Value {
+        let created = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        let section = SectionView {
+            id: "3".to_string(),
+            name: "General".to_string(),
+            description: None,
+            image: None,
+            owner: Value::Null,
+            owner_set_time: None,
+            ckb_addr: None,
+            permission: "0".to_string(),
+            is_disabled: false,
+            archived: false,
+            reveal_moderator: None,
+            updated: created,
+            created,
+            visited_count: "120".to_string(),
+            post_count: "12".to_string(),
+            announcement_count: "1".to_string(),
+            top_count: "2".to_string(),
+            comment_count: "30".to_string(),
+            like_count: "45".to_string(),
+            active_post_count_24h: "4".to_string(),
+            most_active_post: None,
+            stats_age_secs: None,
+        };
+        let tip = TipView {
+            id: "7".to_string(),
+            category: "0".to_string(),
+            sender: "ckt1sender".to_string(),
+            sender_did: "did:plc:sender".to_string(),
+            sender_author: Value::Null,
+            receiver: "ckt1receiver".to_string(),
+            receiver_did: "did:plc:receiver".to_string(),
+            amount: "10000000000".to_string(),
+            amount_ckb: "100.00000000".to_string(),
+            info: "at://did:plc:receiver/app.bbs.post/1".to_string(),
+            state: "0".to_string(),
+            tx_hash: None,
+            updated: created,
+            created,
+        };
+        let pick = |view: Value, fields: &[&str]| -> Value {
+            fields
+                .iter()
+                .map(|f| (f.to_string(), view[f].clone()))
+                .collect()
+        };
+        json!({
+            "section": pick(
+                json!(section),
+                &["id", "permission", "visited_count", "post_count", "announcement_count",
+                  "top_count", "comment_count", "like_count", "active_post_count_24h"],
+            ),
+            "tip": pick(json!(tip), &["id", "category", "sender", "amount", "state"]),
+        })
+    }
+
+    #[tokio::test]
+    async fn counts_ids_and_amounts_in_each_mode() {
+        assert_eq!(
+            version::scope(ApiVersion::V1, false, async { views() }).await,
+            json!({
+                "section": {
+                    "id": "3", "permission": "0", "visited_count": "120", "post_count": "12",
+                    "announcement_count": "1", "top_count": "2", "comment_count": "30",
+                    "like_count": "45", "active_post_count_24h": "4",
+                },
+                "tip": {
+                    "id": "7", "category": "0", "sender": "ckt1sender",
+                    "amount": "10000000000", "state": "0",
+                },
+            })
+        );
+
+        assert_eq!(
+            version::scope(ApiVersion::V1, true, async { views() }).await,
+            json!({
+                "section": {
+                    "id": 3, "permission": "0", "visited_count": 120, "post_count": 12,
+                    "announcement_count": 1, "top_count": 2, "comment_count": 30,
+                    "like_count": 45, "active_post_count_24h": 4,
+                },
+                "tip": {
+                    "id": 7, "category": "0", "sender": "ckt1sender",
+                    "amount": 10000000000_i64, "state": "0",
+                },
+            })
+        );
+    }
+}
@@ -1,12 +1,14 @@
 use chrono::{DateTime, Local};
 use color_eyre::Result;
-use sea_query::{ColumnDef, Expr, Iden, PostgresQueryBuilder};
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, Order, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
 use serde::Serialize;
 use serde_json::Value;
-use sqlx::{Executor, Pool, Postgres, query, query_with};
+use sqlx::{Executor, Pool, Postgres, query};
 use utoipa::ToSchema;
 
+use crate::{db, lexicon::section::Section};
+
 #[derive(Iden, Debug, Clone, Copy)]
 pub enum Operation {
     Table,
@@ -44,6 +46,43 @@ pub enum ActionType {
     DeleteWhitelist,
     AddAdmin,
     DeleteAdmin,
+    ResyncRecord,
+    FlagContent,
+    ShadowContent,
+    Broadcast,
+    Maintenance,
+    ArchiveSection,
+    UnarchiveSection,
+    UpdateModeratorPolicy,
+    BanUser,
+    UnbanUser,
+    UpdateCommentPermission,
+}
+
+impl ActionType {
+    /// What moderators do to content of a section.
+    pub const MODERATION: [Self; 10] = [
+        Self::DisablePost,
+        Self::EnablePost,
+        Self::DisableComment,
+        Self::EnableComment,
+        Self::DisableReply,
+        Self::EnableReply,
+        Self::SetAnnouncement,
+        Self::CancelAnnouncement,
+        Self::SetTop,
+        Self::CancelTop,
+    ];
+    /// Content the content rules reported to moderators.
+    pub const FLAGS: [Self; 2] = [Self::FlagContent, Self::ShadowContent];
+
+    pub fn list(types: &[Self]) -> String {
+        types
+            .iter()
+            .map(|t| (*t as i32).to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
 }
 
 impl Operation {
@@ -104,10 +143,42 @@ impl Operation {
             .returning_col(Self::Id)
             .build_sqlx(PostgresQueryBuilder);
 
-        db.execute(query_with(&sql, values)).await?;
+        db::execute(db, &sql, values).await?;
         Ok(())
     }
 
+    /// `reported`: when the earliest flag on the row of `table` was raised
+    /// that no moderator acted on since, or null. Only viewers can be
+    /// moderators, so anonymous reads skip the lookup.
+    pub fn open_report(table: &str, viewer: &Option<String>) -> Expr {
+        if viewer.is_none() {
+            return Expr::cust("null::timestamptz as reported");
+        }
+        Expr::cust(format!(
+            "(select min(\"flag\".\"created\") from \"operation\" as \"flag\" \
+             where \"flag\".\"target\" = \"{table}\".\"uri\" and \"flag\".\"action_type\" in ({}) \
+             and not exists (select 1 from \"operation\" as \"handled\" \
+             where \"handled\".\"target\" = \"flag\".\"target\" and \"handled\".\"action_type\" in ({}) \
+             and \"handled\".\"created\" > \"flag\".\"created\")) as reported",
+            ActionType::list(&ActionType::FLAGS),
+            ActionType::list(&ActionType::MODERATION),
+        ))
+    }
+
+    /// The flags on `target` no moderator acted on since, counted by their
+    /// message.
+    pub fn build_open_flags(target: &str) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .expr(Expr::cust("COALESCE(\"flag\".\"message\", '')"))
+            .expr(Expr::cust("COUNT(*)"))
+            .from_as(Operation::Table, "flag")
+            .and_where(Expr::cust_with_values("\"flag\".\"target\" = $1", [target]))
+            .and_where(Expr::cust(open_flag()))
+            .add_group_by([Expr::cust("1")])
+            .order_by_expr(Expr::cust("1"), Order::Asc)
+            .take()
+    }
+
     pub fn build_select() -> sea_query::SelectStatement {
         sea_query::Query::select()
             .columns([
@@ -125,6 +196,131 @@ impl Operation {
     }
 }
 
+/// Whether the operation aliased `flag` is a flag no moderator acted on
+/// since.
+pub fn open_flag() -> String {
+    format!(
+        "\"flag\".\"action_type\" IN ({}) AND NOT EXISTS (SELECT 1 FROM \"operation\" AS \"handled\" \
+         WHERE \"handled\".\"target\" = \"flag\".\"target\" AND \"handled\".\"action_type\" IN ({}) \
+         AND \"handled\".\"created\" > \"flag\".\"created\")",
+        ActionType::list(&ActionType::FLAGS),
+        ActionType::list(&ActionType::MODERATION)
+    )
+}
+
+/// Operations of the last `days` days.
+fn within(days: i32) -> Expr {
+    Expr::col((Operation::Table, Operation::Created)).gt(Expr::cust_with_values(
+        "now() - make_interval(days => $1)",
+        [days],
+    ))
+}
+
+impl Operation {
+    /// Every section with its moderation actions and flagged targets in the
+    /// last `days` days, sections without any included.
+    pub fn build_section_activity(days: i32) -> sea_query::SelectStatement {
+        let moderation = ActionType::list(&ActionType::MODERATION);
+        let flags = ActionType::list(&ActionType::FLAGS);
+        sea_query::Query::select()
+            .columns([(Section::Table, Section::Id), (Section::Table, Section::Name)])
+            .expr_as(
+                Expr::cust(format!(
+                    "COUNT(\"operation\".\"id\") FILTER (WHERE \"operation\".\"action_type\" IN ({moderation}))"
+                )),
+                "actions",
+            )
+            .expr_as(
+                Expr::cust(format!(
+                    "COUNT(DISTINCT \"operation\".\"target\") FILTER (WHERE \"operation\".\"action_type\" IN ({flags}))"
+                )),
+                "flagged",
+            )
+            .from(Section::Table)
+            .left_join(
+                Operation::Table,
+                Expr::col((Operation::Table, Operation::SectionId))
+                    .equals((Section::Table, Section::Id))
+                    .and(within(days)),
+            )
+            .group_by_col((Section::Table, Section::Id))
+            .group_by_col((Section::Table, Section::Name))
+            .order_by((Section::Table, Section::Id), Order::Asc)
+            .take()
+    }
+
+    /// Per section, the median seconds from the first flag of a target in
+    /// the last `days` days to the first moderation action on it after.
+    /// Targets no moderator acted on yet are left out.
+    pub fn build_response_medians(days: i32) -> sea_query::SelectStatement {
+        let flags = sea_query::Query::select()
+            .columns([Operation::SectionId, Operation::Target])
+            .expr_as(Expr::col(Operation::Created).min(), "flagged")
+            .from(Operation::Table)
+            .and_where(
+                Expr::col((Operation::Table, Operation::ActionType))
+                    .is_in(ActionType::FLAGS.map(|t| t as i32)),
+            )
+            .and_where(within(days))
+            .group_by_col(Operation::SectionId)
+            .group_by_col(Operation::Target)
+            .take();
+        let responses = sea_query::Query::select()
+            .expr_as(Expr::cust("\"flag\".\"section_id\""), "section_id")
+            .expr_as(
+                Expr::cust(
+                    "EXTRACT(EPOCH FROM MIN(\"operation\".\"created\") - \"flag\".\"flagged\")",
+                ),
+                "seconds",
+            )
+            .from_subquery(flags, "flag")
+            .inner_join(
+                Operation::Table,
+                Expr::cust("\"operation\".\"target\" = \"flag\".\"target\"")
+                    .and(
+                        Expr::col((Operation::Table, Operation::ActionType))
+                            .is_in(ActionType::MODERATION.map(|t| t as i32)),
+                    )
+                    .and(Expr::cust(
+                        "\"operation\".\"created\" >= \"flag\".\"flagged\"",
+                    )),
+            )
+            .add_group_by([
+                Expr::cust("\"flag\".\"section_id\""),
+                Expr::cust("\"flag\".\"target\""),
+                Expr::cust("\"flag\".\"flagged\""),
+            ])
+            .take();
+        sea_query::Query::select()
+            .column("section_id")
+            .expr(Expr::cust(
+                "CAST(percentile_cont(0.5) WITHIN GROUP (ORDER BY \"seconds\") AS DOUBLE PRECISION)",
+            ))
+            .from_subquery(responses, "response")
+            .group_by_col("section_id")
+            .take()
+    }
+
+    /// The operators with the most moderation actions in the last `days`
+    /// days.
+    pub fn build_top_moderators(days: i32, limit: u64) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .column(Operation::Operator)
+            .expr(Expr::col(Operation::Id).count())
+            .from(Operation::Table)
+            .and_where(
+                Expr::col((Operation::Table, Operation::ActionType))
+                    .is_in(ActionType::MODERATION.map(|t| t as i32)),
+            )
+            .and_where(within(days))
+            .group_by_col(Operation::Operator)
+            .order_by_expr(Expr::col(Operation::Id).count(), Order::Desc)
+            .order_by(Operation::Operator, Order::Asc)
+            .limit(limit)
+            .take()
+    }
+}
+
 #[derive(sqlx::FromRow, Debug, Serialize)]
 pub struct OperationRow {
     pub id: i32,
@@ -139,7 +335,9 @@ pub struct OperationRow {
 
 #[derive(Debug, Serialize)]
 pub struct OperationView {
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub id: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub section_id: String,
     pub operator: Value,
     pub action_type: String,
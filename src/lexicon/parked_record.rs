@@ -0,0 +1,156 @@
+use chrono::{DateTime, Local};
+use color_eyre::Result;
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, Index, OnConflict, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde_json::Value;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::db;
+
+/// Days a parked record waits for its parent before it is dropped.
+const PARKED_DAYS: i64 = 7;
+
+/// Comments and replies the relayer got before the post or comment they
+/// are on, which another repo may commit later. They are indexed once that
+/// parent is.
+#[derive(Iden)]
+pub enum ParkedRecord {
+    Table,
+    Uri,
+    /// The uri of the post or comment the record waits for.
+    Awaits,
+    Repo,
+    Cid,
+    Record,
+    Created,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct ParkedRow {
+    pub uri: String,
+    pub awaits: String,
+    pub repo: String,
+    pub cid: String,
+    pub record: String,
+    pub created: DateTime<Local>,
+}
+
+impl ParkedRow {
+    /// The collection of the record, from its uri.
+    pub fn collection(&self) -> &str {
+        self.uri
+            .strip_prefix("at://")
+            .and_then(|path| path.split('/').nth(1))
+            .unwrap_or_default()
+    }
+
+    pub fn record(&self) -> Result<Value> {
+        Ok(serde_json::from_str(&self.record)?)
+    }
+}
+
+impl ParkedRecord {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Self::Uri).string().not_null().primary_key())
+            .col(ColumnDef::new(Self::Awaits).string().not_null())
+            .col(ColumnDef::new(Self::Repo).string().not_null())
+            .col(ColumnDef::new(Self::Cid).string().not_null())
+            .col(ColumnDef::new(Self::Record).text().not_null())
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
+        let sql = Index::create()
+            .if_not_exists()
+            .name("idx_parked_record_awaits")
+            .table(Self::Table)
+            .col(Self::Awaits)
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+        Ok(())
+    }
+
+    /// Keeps the record until `awaits` is indexed; a later version of it
+    /// replaces the earlier one. Records that waited too long are dropped.
+    pub async fn park(
+        db: &Pool<Postgres>,
+        awaits: &str,
+        repo: &str,
+        uri: &str,
+        cid: &str,
+        record: &Value,
+    ) -> Result<()> {
+        let (sql, values) = sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([Self::Uri, Self::Awaits, Self::Repo, Self::Cid, Self::Record])
+            .values([
+                uri.into(),
+                awaits.into(),
+                repo.into(),
+                cid.into(),
+                record.to_string().into(),
+            ])?
+            .on_conflict(
+                OnConflict::column(Self::Uri)
+                    .update_columns([Self::Awaits, Self::Repo, Self::Cid, Self::Record])
+                    .value(Self::Created, Expr::current_timestamp())
+                    .to_owned(),
+            )
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+
+        let (sql, values) = sea_query::Query::delete()
+            .from_table(Self::Table)
+            .and_where(
+                Expr::col(Self::Created)
+                    .lt(Expr::cust(format!("now() - interval '{PARKED_DAYS} days'"))),
+            )
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    /// Removes and returns the records waiting for `awaits`. Each one goes
+    /// to a single caller, however many take at once.
+    pub async fn take(db: &Pool<Postgres>, awaits: &str) -> Result<Vec<ParkedRow>> {
+        let (sql, values) = sea_query::Query::delete()
+            .from_table(Self::Table)
+            .and_where(Expr::col(Self::Awaits).eq(awaits))
+            .returning_all()
+            .build_sqlx(PostgresQueryBuilder);
+        let mut rows: Vec<ParkedRow> = db::fetch_all(db, &sql, values).await?;
+        rows.sort_by_key(|row| row.created);
+        Ok(rows)
+    }
+
+    /// Forgets a parked record its author deleted.
+    pub async fn delete(db: &Pool<Postgres>, uri: &str) -> Result<()> {
+        let (sql, values) = sea_query::Query::delete()
+            .from_table(Self::Table)
+            .and_where(Expr::col(Self::Uri).eq(uri))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+}
+
+#[test]
+fn parked_rows_know_their_collection() {
+    let row = ParkedRow {
+        uri: "at://did:ckb:bob/app.bbs.reply/3kabc".to_string(),
+        awaits: "at://did:ckb:alice/app.bbs.post/3kpost".to_string(),
+        repo: "did:ckb:bob".to_string(),
+        cid: String::new(),
+        record: "{}".to_string(),
+        created: Local::now(),
+    };
+    assert_eq!(row.collection(), "app.bbs.reply");
+}
@@ -0,0 +1,84 @@
+use color_eyre::Result;
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, OnConflict, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::db;
+
+/// The CKB address a user wants their tips paid to, instead of the one of
+/// their DID cell.
+#[derive(Iden)]
+pub enum PayoutPref {
+    Table,
+    Did,
+    CkbAddr,
+    Updated,
+}
+
+impl PayoutPref {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Self::Did).string().not_null().primary_key())
+            .col(ColumnDef::new(Self::CkbAddr).string().not_null())
+            .col(
+                ColumnDef::new(Self::Updated)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+        Ok(())
+    }
+
+    pub fn build_upsert(did: &str, ckb_addr: &str) -> Result<sea_query::InsertStatement> {
+        Ok(sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([Self::Did, Self::CkbAddr, Self::Updated])
+            .values([did.into(), ckb_addr.into(), Expr::current_timestamp()])?
+            .on_conflict(
+                OnConflict::column(Self::Did)
+                    .update_columns([Self::CkbAddr, Self::Updated])
+                    .to_owned(),
+            )
+            .take())
+    }
+
+    pub async fn upsert(db: &Pool<Postgres>, did: &str, ckb_addr: &str) -> Result<()> {
+        let (sql, values) = Self::build_upsert(did, ckb_addr)?.build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    /// Tips go to the address of the DID cell again.
+    pub async fn delete(db: &Pool<Postgres>, did: &str) -> Result<()> {
+        let (sql, values) = sea_query::Query::delete()
+            .from_table(Self::Table)
+            .and_where(Expr::col(Self::Did).eq(did))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    pub async fn select(db: &Pool<Postgres>, did: &str) -> Result<Option<String>> {
+        let (sql, values) = sea_query::Query::select()
+            .column(Self::CkbAddr)
+            .from(Self::Table)
+            .and_where(Expr::col(Self::Did).eq(did))
+            .build_sqlx(PostgresQueryBuilder);
+        let row: Option<(String,)> = db::fetch_optional(db, &sql, values).await?;
+        Ok(row.map(|(ckb_addr,)| ckb_addr))
+    }
+}
+
+#[test]
+fn payout_address_is_replaced() {
+    let sql = PayoutPref::build_upsert("did:ckb:alice", "ckt1alice")
+        .unwrap()
+        .to_string(PostgresQueryBuilder);
+    assert!(sql.ends_with(
+        "ON CONFLICT (\"did\") DO UPDATE SET \"ckb_addr\" = \"excluded\".\"ckb_addr\", \"updated\" = \"excluded\".\"updated\""
+    ));
+}
@@ -1,12 +1,32 @@
 use chrono::{DateTime, Local};
-use color_eyre::{Result, eyre::OptionExt};
+use color_eyre::{
+    Result,
+    eyre::{OptionExt, eyre},
+};
 use sea_query::{ColumnDef, Expr, ExprTrait, Iden, OnConflict, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
 use serde::Serialize;
 use serde_json::Value;
-use sqlx::{Executor, Pool, Postgres, query, query_with};
+use sqlx::{Executor, Pool, Postgres, query};
 
-use crate::lexicon::{comment::CommentRow, section::Section};
+use crate::{
+    db,
+    lexicon::{
+        comment::{Comment, CommentRow},
+        like::Like,
+        normalize_record,
+        notify::Notify,
+        reasons_for_viewer,
+        reply::{Reply, ReplyRow},
+        section::Section,
+        section_id_of,
+    },
+};
+
+pub const BUMP_THROTTLE_SECS: i64 = 30;
+/// How much of the parent comment a `reply` item of the commented feed
+/// carries.
+const PARENT_SNIPPET_CHARS: usize = 140;
 
 #[derive(Iden)]
 pub enum Post {
@@ -21,6 +41,7 @@ pub enum Post {
     IsAnnouncement,
     IsDisabled,
     IsDraft,
+    IsUserPinned,
     ReasonsForDisabled,
     VisitedCount,
     Visited,
@@ -64,6 +85,12 @@ impl Post {
                     .not_null()
                     .default(true),
             )
+            .col(
+                ColumnDef::new(Self::IsUserPinned)
+                    .boolean()
+                    .not_null()
+                    .default(false),
+            )
             .col(ColumnDef::new(Self::ReasonsForDisabled).string())
             .col(
                 ColumnDef::new(Self::VisitedCount)
@@ -101,23 +128,28 @@ impl Post {
                     .not_null()
                     .default(false),
             )
+            .add_column_if_not_exists(
+                ColumnDef::new(Self::IsUserPinned)
+                    .boolean()
+                    .not_null()
+                    .default(false),
+            )
             .build(PostgresQueryBuilder);
         db.execute(query(&sql)).await?;
 
         Ok(())
     }
 
-    pub async fn insert(
-        db: &Pool<Postgres>,
+    /// Upserts the post record, read with `normalize_record` so the field
+    /// names of early clients are accepted.
+    pub fn build_insert(
         repo: &str,
         post: &Value,
         uri: &str,
         cid: &str,
-    ) -> Result<()> {
-        let section_id = post["section_id"]
-            .as_str()
-            .and_then(|s| s.parse::<i32>().ok())
-            .ok_or_eyre("error in section_id")?;
+    ) -> Result<sea_query::InsertStatement> {
+        let post = &*normalize_record(post, uri);
+        let section_id = section_id_of(post)?;
         let title = post["title"]
             .as_str()
             .map(|s| s.trim_matches('\"'))
@@ -136,7 +168,7 @@ impl Post {
         let is_draft = post["is_draft"].as_bool().unwrap_or(false);
         let is_announcement = post["is_announcement"].as_bool().unwrap_or(false);
         let is_top = post["is_top"].as_bool().unwrap_or(false);
-        let (sql, values) = sea_query::Query::insert()
+        Ok(sea_query::Query::insert()
             .into_table(Self::Table)
             .columns([
                 Self::Uri,
@@ -183,20 +215,30 @@ impl Post {
                     ])
                     .to_owned(),
             )
-            .build_sqlx(PostgresQueryBuilder);
+            .take())
+    }
 
-        db.execute(query_with(&sql, values)).await?;
+    pub async fn insert(
+        db: &Pool<Postgres>,
+        repo: &str,
+        post: &Value,
+        uri: &str,
+        cid: &str,
+    ) -> Result<()> {
+        let (sql, values) =
+            Self::build_insert(repo, post, uri, cid)?.build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
         Ok(())
     }
 
-    pub async fn update_tag(
-        db: &Pool<Postgres>,
+    /// Sets the given flags of the post; `None` when none is given.
+    pub fn build_update_tag(
         uri: &str,
         is_top: Option<bool>,
         is_announcement: Option<bool>,
         is_disabled: Option<bool>,
         reasons_for_disabled: Option<String>,
-    ) -> Result<()> {
+    ) -> Option<sea_query::UpdateStatement> {
         let mut values = Vec::new();
         if let Some(is_top) = is_top {
             values.push((Post::IsTop, is_top.into()));
@@ -211,19 +253,48 @@ impl Post {
             values.push((Post::ReasonsForDisabled, reasons_for_disabled.into()));
         }
         if values.is_empty() {
-            return Ok(());
+            return None;
         }
 
-        let (sql, values) = sea_query::Query::update()
-            .table(Self::Table)
-            .values(values)
-            .and_where(Expr::col(Self::Uri).eq(uri))
-            .build_sqlx(PostgresQueryBuilder);
-        db.execute(query_with(&sql, values)).await?;
+        Some(
+            sea_query::Query::update()
+                .table(Self::Table)
+                .values(values)
+                .and_where(Expr::col(Self::Uri).eq(uri))
+                .take(),
+        )
+    }
+
+    pub async fn update_tag(
+        db: &Pool<Postgres>,
+        uri: &str,
+        is_top: Option<bool>,
+        is_announcement: Option<bool>,
+        is_disabled: Option<bool>,
+        reasons_for_disabled: Option<String>,
+    ) -> Result<()> {
+        if let Some(update) = Self::build_update_tag(
+            uri,
+            is_top,
+            is_announcement,
+            is_disabled,
+            reasons_for_disabled,
+        ) {
+            let (sql, values) = update.build_sqlx(PostgresQueryBuilder);
+            db::execute(db, &sql, values).await?;
+        }
         Ok(())
     }
 
     pub fn build_select(viewer: Option<String>) -> sea_query::SelectStatement {
+        Self::build_select_with_drafts(viewer)
+            .and_where(Expr::col((Post::Table, Post::IsDraft)).eq(false))
+            .take()
+    }
+
+    /// `build_select` without the published-only filter, for callers that
+    /// decide themselves whether drafts may be shown.
+    pub fn build_select_with_drafts(viewer: Option<String>) -> sea_query::SelectStatement {
         sea_query::Query::select()
         .columns([
             (Post::Table, Post::Uri),
@@ -235,6 +306,7 @@ impl Post {
             (Post::Table, Post::IsAnnouncement),
             (Post::Table, Post::IsDisabled),
             (Post::Table, Post::IsDraft),
+            (Post::Table, Post::IsUserPinned),
             (Post::Table, Post::ReasonsForDisabled),
             (Post::Table, Post::VisitedCount),
             (Post::Table, Post::Visited),
@@ -245,22 +317,32 @@ impl Post {
         .columns([
             (Section::Table, Section::Id),
             (Section::Table, Section::Name),
+            (Section::Table, Section::IsArchived),
         ])
         .expr(Expr::cust("(select count(\"comment\".\"uri\") from \"comment\" where \"comment\".\"is_disabled\" is false and \"comment\".\"post\" = \"post\".\"uri\") as comment_count"))
         .expr(Expr::cust("(select count(\"like\".\"uri\") from \"like\" where \"like\".\"to\" = \"post\".\"uri\") as like_count"))
-        .expr(if let Some(viewer) = viewer {
-            Expr::cust(format!("((select count(\"like\".\"uri\") from \"like\" where \"like\".\"repo\" = '{viewer}' and \"like\".\"to\" = \"post\".\"uri\" ) > 0) as liked"))
+        // the author and whoever wrote a visible comment or reply, once each
+        .expr(Expr::cust("(select count(*) from (select \"post\".\"repo\" union select \"comment\".\"repo\" from \"comment\" where \"comment\".\"is_disabled\" is false and \"comment\".\"post\" = \"post\".\"uri\" union select \"reply\".\"repo\" from \"reply\" where \"reply\".\"is_disabled\" is false and \"reply\".\"post\" = \"post\".\"uri\") as \"participant\") as participant_count"))
+        .expr(if let Some(viewer) = &viewer {
+            Expr::cust_with_values("((select count(\"like\".\"uri\") from \"like\" where \"like\".\"repo\" = $1 and \"like\".\"to\" = \"post\".\"uri\" ) > 0) as liked", [viewer.as_str()])
         } else {
             Expr::cust("false as liked".to_string())
         })
+        .expr(if let Some(viewer) = &viewer {
+            Expr::cust_with_values("(exists (select 1 from \"thread_mute\" where \"thread_mute\".\"did\" = $1 and \"thread_mute\".\"post_uri\" = \"post\".\"uri\")) as muted", [viewer.as_str()])
+        } else {
+            Expr::cust("false as muted".to_string())
+        })
         .from(Post::Table)
         .left_join(
             Section::Table,
             Expr::col((Post::Table, Post::SectionId)).equals((Section::Table, Section::Id)),
         )
-        .and_where(Expr::col((Post::Table, Post::IsDraft)).eq(false)).take()
+        .take()
     }
 
+    /// Drafts only carry what the editor needs: no moderation flags or public
+    /// counters. Kept column-compatible with `Draft::build_select`.
     pub fn build_draft_select() -> sea_query::SelectStatement {
         sea_query::Query::select()
             .columns([
@@ -287,12 +369,137 @@ impl Post {
             .take()
     }
 
-    pub async fn delete(db: &Pool<Postgres>, uri: &str) -> Result<()> {
-        let (sql, values) = sea_query::Query::delete()
-            .from_table(Self::Table)
+    /// Bump `updated` so the post surfaces in activity ordering. Rapid comments
+    /// and replies on the same post are coalesced: the row is only touched when
+    /// the last bump is older than `BUMP_THROTTLE_SECS`.
+    pub fn build_bump(uri: &str) -> sea_query::UpdateStatement {
+        sea_query::Query::update()
+            .table(Self::Table)
+            .values([(Self::Updated, Expr::current_timestamp())])
             .and_where(Expr::col(Self::Uri).eq(uri))
+            .and_where(Expr::col(Self::Updated).lt(Expr::cust(format!(
+                "now() - interval '{BUMP_THROTTLE_SECS} seconds'"
+            ))))
+            .take()
+    }
+
+    pub async fn bump(db: &Pool<Postgres>, uri: &str) -> Result<()> {
+        let (sql, values) = Self::build_bump(uri).build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    pub async fn select_by_uri(db: &Pool<Postgres>, uri: &str) -> Result<PostRow> {
+        let (sql, values) = Self::build_select(None)
+            .and_where(Expr::col((Self::Table, Self::Uri)).eq(uri))
             .build_sqlx(PostgresQueryBuilder);
-        db.execute(query_with(&sql, values)).await?;
+        db::fetch_one(db, &sql, values)
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))
+    }
+
+    /// The section of the indexed post `uri`, if there is one.
+    pub async fn section_of(db: &Pool<Postgres>, uri: &str) -> Result<Option<i32>> {
+        let (sql, values) = sea_query::Query::select()
+            .column(Self::SectionId)
+            .from(Self::Table)
+            .and_where(Expr::col(Self::Uri).eq(uri))
+            .build_sqlx(PostgresQueryBuilder);
+        let row: Option<(i32,)> = db::fetch_optional(db, &sql, values)
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+        Ok(row.map(|(section_id,)| section_id))
+    }
+
+    /// An author keeps at most one pinned post per section: pinning `uri`
+    /// unpins whatever they had pinned there before.
+    pub fn build_pin(repo: &str, section_id: i32, uri: &str) -> [sea_query::UpdateStatement; 2] {
+        [
+            sea_query::Query::update()
+                .table(Self::Table)
+                .values([(Self::IsUserPinned, false.into())])
+                .and_where(Expr::col(Self::Repo).eq(repo))
+                .and_where(Expr::col(Self::SectionId).eq(section_id))
+                .and_where(Expr::col(Self::IsUserPinned).eq(true))
+                .take(),
+            sea_query::Query::update()
+                .table(Self::Table)
+                .values([(Self::IsUserPinned, true.into())])
+                .and_where(Expr::col(Self::Uri).eq(uri))
+                .take(),
+        ]
+    }
+
+    pub async fn pin(db: &Pool<Postgres>, repo: &str, section_id: i32, uri: &str) -> Result<()> {
+        let mut tx = db.begin().await?;
+        for statement in Self::build_pin(repo, section_id, uri) {
+            let (sql, values) = statement.build_sqlx(PostgresQueryBuilder);
+            db::execute(&mut *tx, &sql, values).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Comments, replies, likes and notifications reference their post by
+    /// uri only, so they are removed together with it instead of being left
+    /// orphaned; the likes and notifications of its comments and replies
+    /// too, before those go.
+    pub fn build_delete(uris: &[String]) -> [sea_query::DeleteStatement; 5] {
+        let uris = || uris.iter().map(String::as_str);
+        let of_thread = |col: Expr| {
+            col.clone()
+                .is_in(uris())
+                .or(col.clone().in_subquery(
+                    sea_query::Query::select()
+                        .column(Comment::Uri)
+                        .from(Comment::Table)
+                        .and_where(Expr::col(Comment::Post).is_in(uris()))
+                        .take(),
+                ))
+                .or(col.in_subquery(
+                    sea_query::Query::select()
+                        .column(Reply::Uri)
+                        .from(Reply::Table)
+                        .and_where(Expr::col(Reply::Post).is_in(uris()))
+                        .take(),
+                ))
+        };
+        [
+            sea_query::Query::delete()
+                .from_table(Notify::Table)
+                .and_where(of_thread(Expr::col(Notify::TargetUri)))
+                .take(),
+            sea_query::Query::delete()
+                .from_table(Like::Table)
+                .and_where(of_thread(Expr::col(Like::To)))
+                .take(),
+            sea_query::Query::delete()
+                .from_table(Reply::Table)
+                .and_where(Expr::col(Reply::Post).is_in(uris()))
+                .take(),
+            sea_query::Query::delete()
+                .from_table(Comment::Table)
+                .and_where(Expr::col(Comment::Post).is_in(uris()))
+                .take(),
+            sea_query::Query::delete()
+                .from_table(Self::Table)
+                .and_where(Expr::col(Self::Uri).is_in(uris()))
+                .take(),
+        ]
+    }
+
+    pub async fn delete(db: &Pool<Postgres>, uri: &str) -> Result<()> {
+        Self::delete_all(db, &[uri.to_string()]).await
+    }
+
+    /// Deletes the posts in one transaction, with all that hangs off them.
+    pub async fn delete_all(db: &Pool<Postgres>, uris: &[String]) -> Result<()> {
+        let mut tx = db.begin().await?;
+        for statement in Self::build_delete(uris) {
+            let (sql, values) = statement.build_sqlx(PostgresQueryBuilder);
+            db::execute(&mut *tx, &sql, values).await?;
+        }
+        tx.commit().await?;
         Ok(())
     }
 }
@@ -308,6 +515,7 @@ pub struct PostRow {
     pub is_announcement: bool,
     pub is_disabled: bool,
     pub is_draft: bool,
+    pub is_user_pinned: bool,
     pub reasons_for_disabled: Option<String>,
     pub visited_count: i32,
     pub visited: DateTime<Local>,
@@ -318,9 +526,13 @@ pub struct PostRow {
     pub section_id: i32,
     #[sqlx(rename = "name")]
     pub section: String,
+    #[sqlx(rename = "is_archived")]
+    pub archived: bool,
     pub comment_count: i64,
     pub like_count: i64,
+    pub participant_count: i64,
     pub liked: bool,
+    pub muted: bool,
 }
 
 #[derive(sqlx::FromRow, Debug, Serialize)]
@@ -351,6 +563,7 @@ pub struct PostDraftView {
     pub edited: Option<DateTime<Local>>,
     pub updated: DateTime<Local>,
     pub created: DateTime<Local>,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub section_id: String,
     pub section: String,
 }
@@ -384,18 +597,40 @@ pub struct PostView {
     pub is_announcement: bool,
     pub is_disabled: bool,
     pub is_draft: bool,
+    pub is_user_pinned: bool,
     pub reasons_for_disabled: Option<String>,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub visited_count: String,
+    #[serde(serialize_with = "crate::api::version::timestamp")]
     pub visited: DateTime<Local>,
+    #[serde(serialize_with = "crate::api::version::timestamp_opt")]
     pub edited: Option<DateTime<Local>>,
+    #[serde(serialize_with = "crate::api::version::timestamp")]
     pub updated: DateTime<Local>,
+    #[serde(serialize_with = "crate::api::version::timestamp")]
     pub created: DateTime<Local>,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub section_id: String,
+    /// `section_name` under its old name, for API version 1 only.
+    #[serde(skip_serializing_if = "crate::api::version::renamed")]
     pub section: String,
+    pub section_name: String,
+    /// The section is archived: the post stays readable but takes no new
+    /// comments, replies or likes.
+    pub archived: bool,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub comment_count: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub like_count: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub tip_count: String,
+    /// The author and everyone with a visible comment or reply, counted
+    /// once each; `/api/post/participants` lists them.
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    pub participant_count: String,
     pub liked: bool,
+    /// Whether the viewer muted notifications from this thread.
+    pub muted: bool,
 }
 
 impl PostView {
@@ -410,6 +645,7 @@ impl PostView {
             is_announcement: row.is_announcement,
             is_disabled: row.is_disabled,
             is_draft: row.is_draft,
+            is_user_pinned: row.is_user_pinned,
             reasons_for_disabled: row.reasons_for_disabled,
             visited_count: row.visited_count.to_string(),
             visited: row.visited,
@@ -417,17 +653,51 @@ impl PostView {
             updated: row.updated,
             created: row.created,
             section_id: row.section_id.to_string(),
-            section: row.section,
+            section: row.section.clone(),
+            section_name: row.section,
+            archived: row.archived,
             comment_count: row.comment_count.to_string(),
             like_count: row.like_count.to_string(),
             tip_count,
+            participant_count: row.participant_count.to_string(),
             liked: row.liked,
+            muted: row.muted,
         }
     }
+
+    /// Redact the moderator note unless the viewer may read it.
+    pub fn for_viewer(mut self, privileged: bool) -> Self {
+        self.reasons_for_disabled =
+            reasons_for_viewer(self.reasons_for_disabled, self.is_disabled, privileged);
+        self
+    }
+}
+
+/// What the user wrote under the post of a commented feed item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepliedKind {
+    Comment,
+    Reply,
+}
+
+/// The user's reply of a `reply` item of the commented feed.
+#[derive(Debug, Serialize)]
+pub struct RepliedReply {
+    pub uri: String,
+    pub text: String,
+    pub is_disabled: bool,
+    pub updated: DateTime<Local>,
+    pub created: DateTime<Local>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct PostRepliedView {
+    pub kind: RepliedKind,
+    /// Only on `reply` items, whose `comment_*` fields describe the
+    /// replied comment with `comment_text` cut to a snippet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply: Option<RepliedReply>,
     pub uri: String,
     pub cid: String,
     pub author: Value,
@@ -444,15 +714,20 @@ pub struct PostRepliedView {
     pub comment_created: DateTime<Local>,
     pub comment_disabled: bool,
     pub comment_reasons_for_disabled: Option<String>,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub visited_count: String,
     pub visited: DateTime<Local>,
     pub edited: Option<DateTime<Local>>,
     pub updated: DateTime<Local>,
     pub created: DateTime<Local>,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub section_id: String,
     pub section: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub comment_count: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub like_count: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub tip_count: String,
     pub liked: bool,
 }
@@ -460,6 +735,8 @@ pub struct PostRepliedView {
 impl PostRepliedView {
     pub fn build(row: PostRow, author: Value, comment: CommentRow, tip_count: String) -> Self {
         Self {
+            kind: RepliedKind::Comment,
+            reply: None,
             comment_uri: comment.uri,
             comment_text: comment.text,
             comment_updated: comment.updated,
@@ -489,4 +766,225 @@ impl PostRepliedView {
             liked: row.liked,
         }
     }
+
+    /// Turns the item into a `reply` item for the user's reply under the
+    /// comment it was built with.
+    pub fn with_reply(mut self, reply: ReplyRow) -> Self {
+        self.kind = RepliedKind::Reply;
+        self.comment_text = self
+            .comment_text
+            .chars()
+            .take(PARENT_SNIPPET_CHARS)
+            .collect();
+        self.reply = Some(RepliedReply {
+            uri: reply.uri,
+            text: reply.text,
+            is_disabled: reply.is_disabled,
+            updated: reply.updated,
+            created: reply.created,
+        });
+        self
+    }
+
+    /// Redact the post and comment moderator notes for viewers that may not
+    /// read them; the two can be moderated in different sections.
+    pub fn for_viewer(mut self, post_privileged: bool, comment_privileged: bool) -> Self {
+        self.reasons_for_disabled =
+            reasons_for_viewer(self.reasons_for_disabled, self.is_disabled, post_privileged);
+        self.comment_reasons_for_disabled = reasons_for_viewer(
+            self.comment_reasons_for_disabled,
+            self.comment_disabled,
+            comment_privileged,
+        );
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn legacy_posts_insert_the_same_row() {
+        let uri = "at://did:ckb:alice/app.bbs.post/3mbnwjdssbc27";
+        let insert = |post: Value| {
+            Post::build_insert("did:ckb:alice", &post, uri, "bafy")
+                .unwrap()
+                .to_string(PostgresQueryBuilder)
+        };
+        let current = insert(json!({
+            "section_id": "1",
+            "title": "Hello",
+            "text": "First post",
+            "is_top": true,
+            "created": "2024-05-01T08:00:00+08:00",
+        }));
+        for legacy in [
+            json!({
+                "sectionId": "1",
+                "title": "Hello",
+                "content": "First post",
+                "isTop": true,
+                "created": "2024-05-01T08:00:00+08:00",
+            }),
+            json!({
+                "section_id": 1,
+                "title": "Hello",
+                "content": "First post",
+                "is_top": true,
+                "created": "2024-05-01T08:00:00+08:00",
+            }),
+        ] {
+            assert_eq!(insert(legacy), current);
+        }
+    }
+
+    #[test]
+    fn bump_targets_post_and_is_throttled() {
+        let post = "at://did:ckb:alice/app.bbs.post/3mbnwjdssbc27";
+        let sql = Post::build_bump(post).to_string(PostgresQueryBuilder);
+        assert!(sql.contains(&format!("\"uri\" = '{post}'")));
+        assert!(sql.contains(&format!(
+            "\"updated\" < (now() - interval '{BUMP_THROTTLE_SECS} seconds')"
+        )));
+    }
+
+    #[test]
+    fn delete_cascades_to_comments_and_replies() {
+        let post = "at://did:ckb:alice/app.bbs.post/3mbnwjdssbc27";
+        let sql =
+            Post::build_delete(&[post.to_string()]).map(|s| s.to_string(PostgresQueryBuilder));
+        // likes and notifications of the post, its comments and its replies
+        for (statement, column) in [(&sql[0], "target_uri"), (&sql[1], "to")] {
+            for target in [
+                format!("\"{column}\" IN ('{post}')"),
+                format!(
+                    "\"{column}\" IN (SELECT \"uri\" FROM \"comment\" WHERE \"post\" IN ('{post}'))"
+                ),
+                format!(
+                    "\"{column}\" IN (SELECT \"uri\" FROM \"reply\" WHERE \"post\" IN ('{post}'))"
+                ),
+            ] {
+                assert!(statement.contains(&target), "{statement}");
+            }
+        }
+        assert!(sql[0].starts_with("DELETE FROM \"notify\" WHERE "));
+        assert!(sql[1].starts_with("DELETE FROM \"like\" WHERE "));
+        assert_eq!(
+            sql[2],
+            format!("DELETE FROM \"reply\" WHERE \"post\" IN ('{post}')")
+        );
+        assert_eq!(
+            sql[3],
+            format!("DELETE FROM \"comment\" WHERE \"post\" IN ('{post}')")
+        );
+        assert_eq!(
+            sql[4],
+            format!("DELETE FROM \"post\" WHERE \"uri\" IN ('{post}')")
+        );
+    }
+
+    #[test]
+    fn deleted_uris_are_bound_not_inlined() {
+        // a hostile rkey must not end the string literal
+        let uri = "at://did:ckb:mallory/app.bbs.post/x') OR ('1'='1";
+        for statement in Post::build_delete(&[uri.to_string()]) {
+            let (sql, values) = statement.build(PostgresQueryBuilder);
+            assert!(!sql.contains("mallory"), "{sql}");
+            assert!(values.0.contains(&sea_query::Value::from(uri)), "{sql}");
+        }
+    }
+
+    #[test]
+    fn pin_replaces_previous_pin_in_section() {
+        let post = "at://did:ckb:alice/app.bbs.post/3mbnwjdssbc27";
+        let sql =
+            Post::build_pin("did:ckb:alice", 1, post).map(|s| s.to_string(PostgresQueryBuilder));
+        assert_eq!(
+            sql[0],
+            "UPDATE \"post\" SET \"is_user_pinned\" = FALSE WHERE \"repo\" = 'did:ckb:alice' AND \"section_id\" = 1 AND \"is_user_pinned\" = TRUE"
+        );
+        assert_eq!(
+            sql[1],
+            format!("UPDATE \"post\" SET \"is_user_pinned\" = TRUE WHERE \"uri\" = '{post}'")
+        );
+    }
+
+    #[test]
+    fn draft_select_only_returns_drafts() {
+        let sql = Post::build_draft_select()
+            .and_where(Expr::col((Post::Table, Post::Repo)).eq("did:ckb:alice"))
+            .to_string(PostgresQueryBuilder);
+        assert!(sql.contains("\"post\".\"is_draft\", "));
+        assert!(sql.contains(
+            "WHERE \"post\".\"is_draft\" = TRUE AND \"post\".\"repo\" = 'did:ckb:alice'"
+        ));
+        assert!(!sql.contains("count("));
+    }
+
+    #[tokio::test]
+    async fn post_views_follow_the_api_version() {
+        use chrono::TimeZone;
+
+        use crate::api::version::{self, ApiVersion};
+
+        let created = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        let row = PostRow {
+            uri: "at://did:ckb:alice/app.bbs.post/3kabc".to_string(),
+            cid: "bafy".to_string(),
+            repo: "did:ckb:alice".to_string(),
+            title: "hello".to_string(),
+            text: "world".to_string(),
+            is_top: false,
+            is_announcement: false,
+            is_disabled: false,
+            is_draft: false,
+            is_user_pinned: false,
+            reasons_for_disabled: None,
+            visited_count: 5,
+            visited: created,
+            edited: None,
+            updated: created,
+            created,
+            section_id: 3,
+            section: "General".to_string(),
+            archived: false,
+            comment_count: 2,
+            like_count: 7,
+            participant_count: 2,
+            liked: false,
+            muted: false,
+        };
+        let view = PostView::build(row, json!("did:ckb:alice"), "0".to_string());
+        let serialize = |api_version| {
+            let view = view.clone();
+            version::scope(api_version, false, async move {
+                serde_json::to_value(view).unwrap()
+            })
+        };
+
+        let v1 = serialize(ApiVersion::V1).await;
+        assert_eq!(v1["like_count"], "7");
+        assert_eq!(v1["section"], "General");
+        assert_eq!(v1["section_name"], "General");
+        assert_eq!(v1["created"], json!(created));
+        assert!(!v1["created"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(v1["edited"], Value::Null);
+
+        let v2 = serialize(ApiVersion::V2).await;
+        assert!(v2.get("section").is_none());
+        assert_eq!(v2["section_name"], "General");
+        assert_eq!(v2["created"], "2023-11-14T22:13:20Z");
+        assert_eq!(v2["updated"], v2["created"]);
+        assert_eq!(v2["edited"], Value::Null);
+        assert_eq!(v2["section_id"], 3);
+        assert_eq!(v2["like_count"], 7);
+        assert_eq!(v2["comment_count"], 2);
+        assert_eq!(v2["tip_count"], 0);
+
+        // outside of a request views keep the v1 shape
+        assert_eq!(serde_json::to_value(&view).unwrap()["section"], "General");
+    }
 }
@@ -0,0 +1,80 @@
+use color_eyre::Result;
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, OnConflict, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::db;
+
+/// What a user lets others see of their activity. Users without a row keep
+/// everything private.
+#[derive(Iden)]
+pub enum PrivacyPref {
+    Table,
+    Did,
+    /// Whether others may list what the user liked and see how many.
+    LikesPublic,
+    Updated,
+}
+
+impl PrivacyPref {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Self::Did).string().not_null().primary_key())
+            .col(
+                ColumnDef::new(Self::LikesPublic)
+                    .boolean()
+                    .not_null()
+                    .default(false),
+            )
+            .col(
+                ColumnDef::new(Self::Updated)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+        Ok(())
+    }
+
+    pub fn build_upsert(did: &str, likes_public: bool) -> Result<sea_query::InsertStatement> {
+        Ok(sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([Self::Did, Self::LikesPublic, Self::Updated])
+            .values([did.into(), likes_public.into(), Expr::current_timestamp()])?
+            .on_conflict(
+                OnConflict::column(Self::Did)
+                    .update_columns([Self::LikesPublic, Self::Updated])
+                    .to_owned(),
+            )
+            .take())
+    }
+
+    pub async fn upsert(db: &Pool<Postgres>, did: &str, likes_public: bool) -> Result<()> {
+        let (sql, values) = Self::build_upsert(did, likes_public)?.build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    pub async fn likes_public(db: &Pool<Postgres>, did: &str) -> Result<bool> {
+        let (sql, values) = sea_query::Query::select()
+            .column(Self::LikesPublic)
+            .from(Self::Table)
+            .and_where(Expr::col(Self::Did).eq(did))
+            .build_sqlx(PostgresQueryBuilder);
+        let row: Option<(bool,)> = db::fetch_optional(db, &sql, values).await?;
+        Ok(row.is_some_and(|(likes_public,)| likes_public))
+    }
+}
+
+#[test]
+fn likes_public_is_replaced() {
+    let sql = PrivacyPref::build_upsert("did:ckb:alice", true)
+        .unwrap()
+        .to_string(PostgresQueryBuilder);
+    assert!(sql.ends_with(
+        "ON CONFLICT (\"did\") DO UPDATE SET \"likes_public\" = \"excluded\".\"likes_public\", \"updated\" = \"excluded\".\"updated\""
+    ));
+}
@@ -0,0 +1,131 @@
+use color_eyre::Result;
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, OnConflict, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde_json::Value;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::db;
+
+/// The profile record of each repo as the relayer last delivered it, so
+/// authors are built without asking their PDS. A repo has one profile, at
+/// rkey `self`.
+#[derive(Iden)]
+pub enum Profile {
+    Table,
+    Repo,
+    Uri,
+    Cid,
+    /// The record as JSON text.
+    Record,
+    Updated,
+    Created,
+}
+
+impl Profile {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Self::Repo).string().not_null().primary_key())
+            .col(ColumnDef::new(Self::Uri).string().not_null())
+            .col(ColumnDef::new(Self::Cid).string().not_null())
+            .col(ColumnDef::new(Self::Record).string().not_null())
+            .col(
+                ColumnDef::new(Self::Updated)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+        Ok(())
+    }
+
+    pub async fn insert(
+        db: &Pool<Postgres>,
+        repo: &str,
+        profile: &Value,
+        uri: &str,
+        cid: &str,
+    ) -> Result<()> {
+        let (sql, values) = sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([
+                Self::Repo,
+                Self::Uri,
+                Self::Cid,
+                Self::Record,
+                Self::Updated,
+            ])
+            .values([
+                repo.into(),
+                uri.into(),
+                cid.into(),
+                profile.to_string().into(),
+                Expr::current_timestamp(),
+            ])?
+            .on_conflict(
+                OnConflict::column(Self::Repo)
+                    .update_columns([Self::Uri, Self::Cid, Self::Record, Self::Updated])
+                    .to_owned(),
+            )
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    /// The stored profile record of `repo`, if the relayer delivered one.
+    pub async fn select(db: &Pool<Postgres>, repo: &str) -> Result<Option<Value>> {
+        let (sql, values) = sea_query::Query::select()
+            .column(Self::Record)
+            .from(Self::Table)
+            .and_where(Expr::col(Self::Repo).eq(repo))
+            .build_sqlx(PostgresQueryBuilder);
+        let row: Option<(String,)> = db::fetch_optional(db, &sql, values).await?;
+        Ok(match row {
+            Some((record,)) => Some(serde_json::from_str(&record)?),
+            None => None,
+        })
+    }
+
+    /// Forgets the profiles of `repos`, whose profile records were deleted.
+    pub async fn delete(db: &Pool<Postgres>, repos: &[String]) -> Result<()> {
+        let (sql, values) = sea_query::Query::delete()
+            .from_table(Self::Table)
+            .and_where(Expr::col(Self::Repo).is_in(repos.iter().map(String::as_str)))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn profiles_round_trip() {
+    let db = crate::db::test_pool().await;
+
+    let repo = "did:ckb:alice";
+    let uri = "at://did:ckb:alice/app.actor.profile/self";
+    assert_eq!(Profile::select(&db, repo).await.unwrap(), None);
+    let profile = serde_json::json!({ "$type": "app.actor.profile", "displayName": "Alice" });
+    Profile::insert(&db, repo, &profile, uri, "cid1")
+        .await
+        .unwrap();
+    assert_eq!(Profile::select(&db, repo).await.unwrap(), Some(profile));
+
+    // an update replaces the record
+    let renamed = serde_json::json!({ "$type": "app.actor.profile", "displayName": "Ally" });
+    Profile::insert(&db, repo, &renamed, uri, "cid2")
+        .await
+        .unwrap();
+    assert_eq!(Profile::select(&db, repo).await.unwrap(), Some(renamed));
+
+    Profile::delete(&db, &[repo.to_string()]).await.unwrap();
+    assert_eq!(Profile::select(&db, repo).await.unwrap(), None);
+}
@@ -0,0 +1,214 @@
+use chrono::{DateTime, Local};
+use color_eyre::Result;
+use sea_query::{
+    ColumnDef, DynIden, Expr, ExprTrait, Iden, IntoIden, OnConflict, PostgresQueryBuilder,
+};
+use sea_query_sqlx::SqlxBinder;
+use serde::Serialize;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::{
+    db,
+    lexicon::{comment::Comment, post::Post, reply::Reply},
+};
+
+/// Reason of content its author removed from the appview. `restore_me`
+/// only brings back content hidden with this reason, never content that
+/// moderators hid.
+pub const REMOVED_BY_AUTHOR: &str = "removed by author";
+/// Reason of content whose account the relayer reported deactivated, taken
+/// down or suspended; it is shown again once the account is active.
+pub const ACCOUNT_INACTIVE: &str = "account inactive";
+
+/// DIDs that removed their data from the appview. The relayer does not
+/// index their records again until `suppress_until`; until `restore_until`
+/// they may undo the removal.
+#[derive(Iden)]
+pub enum RemovedRepo {
+    Table,
+    Did,
+    RestoreUntil,
+    SuppressUntil,
+    Created,
+}
+
+/// The tables whose rows are tombstoned, by name.
+pub fn content_tables() -> [(&'static str, DynIden); 3] {
+    [
+        ("post", Post::Table.into_iden()),
+        ("comment", Comment::Table.into_iden()),
+        ("reply", Reply::Table.into_iden()),
+    ]
+}
+
+impl RemovedRepo {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Self::Did).string().not_null().primary_key())
+            .col(
+                ColumnDef::new(Self::RestoreUntil)
+                    .timestamp_with_time_zone()
+                    .not_null(),
+            )
+            .col(
+                ColumnDef::new(Self::SuppressUntil)
+                    .timestamp_with_time_zone()
+                    .not_null(),
+            )
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+        Ok(())
+    }
+
+    /// Hides the author's visible content as removed by its author.
+    pub fn build_tombstone(table: DynIden, did: &str) -> sea_query::UpdateStatement {
+        Self::build_hide(table, did, REMOVED_BY_AUTHOR)
+    }
+
+    /// Undoes `build_tombstone`, leaving content hidden by moderators hidden.
+    pub fn build_restore(table: DynIden, did: &str) -> sea_query::UpdateStatement {
+        Self::build_unhide(table, did, REMOVED_BY_AUTHOR)
+    }
+
+    /// Hides the visible content of `did` with `reason`.
+    pub fn build_hide(table: DynIden, did: &str, reason: &str) -> sea_query::UpdateStatement {
+        sea_query::Query::update()
+            .table(table)
+            .values([
+                ("is_disabled", true.into()),
+                ("reasons_for_disabled", reason.into()),
+            ])
+            .and_where(Expr::col("repo").eq(did))
+            .and_where(Expr::col("is_disabled").eq(false))
+            .take()
+    }
+
+    /// Shows again the content of `did` hidden with `reason`, and only that.
+    pub fn build_unhide(table: DynIden, did: &str, reason: &str) -> sea_query::UpdateStatement {
+        sea_query::Query::update()
+            .table(table)
+            .values([
+                ("is_disabled", false.into()),
+                ("reasons_for_disabled", Option::<String>::None.into()),
+            ])
+            .and_where(Expr::col("repo").eq(did))
+            .and_where(Expr::col("is_disabled").eq(true))
+            .and_where(Expr::col("reasons_for_disabled").eq(reason))
+            .take()
+    }
+
+    pub fn build_upsert(
+        did: &str,
+        restore_until: DateTime<Local>,
+        suppress_until: DateTime<Local>,
+    ) -> Result<sea_query::InsertStatement> {
+        Ok(sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([Self::Did, Self::RestoreUntil, Self::SuppressUntil])
+            .values([did.into(), restore_until.into(), suppress_until.into()])?
+            .on_conflict(
+                OnConflict::column(Self::Did)
+                    .update_columns([Self::RestoreUntil, Self::SuppressUntil])
+                    .to_owned(),
+            )
+            .take())
+    }
+
+    /// DIDs whose records are suppressed now, for leaving them out of lists.
+    pub fn build_suppressed() -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .column(Self::Did)
+            .from(Self::Table)
+            .and_where(Expr::col(Self::SuppressUntil).gt(Expr::current_timestamp()))
+            .take()
+    }
+
+    pub async fn select(db: &Pool<Postgres>, did: &str) -> Result<Option<RemovedRepoRow>> {
+        let (sql, values) = sea_query::Query::select()
+            .columns([
+                Self::Did,
+                Self::RestoreUntil,
+                Self::SuppressUntil,
+                Self::Created,
+            ])
+            .from(Self::Table)
+            .and_where(Expr::col(Self::Did).eq(did))
+            .build_sqlx(PostgresQueryBuilder);
+        Ok(db::fetch_optional(db, &sql, values).await?)
+    }
+
+    /// Whether records of `did` must not be indexed now. Lookup failures
+    /// count as not suppressed, so they never drop records.
+    pub async fn is_suppressed(db: &Pool<Postgres>, did: &str) -> bool {
+        Self::select(db, did)
+            .await
+            .map_err(|e| error!("exec sql failed: {e}"))
+            .ok()
+            .flatten()
+            .is_some_and(|row| row.is_suppressed(Local::now()))
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, Serialize)]
+pub struct RemovedRepoRow {
+    pub did: String,
+    pub restore_until: DateTime<Local>,
+    pub suppress_until: DateTime<Local>,
+    pub created: DateTime<Local>,
+}
+
+impl RemovedRepoRow {
+    pub fn is_suppressed(&self, now: DateTime<Local>) -> bool {
+        now < self.suppress_until
+    }
+
+    pub fn can_restore(&self, now: DateTime<Local>) -> bool {
+        now < self.restore_until
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tombstones_are_reversible() {
+        let sql = RemovedRepo::build_tombstone(Post::Table.into_iden(), "did:ckb:alice")
+            .to_string(PostgresQueryBuilder);
+        assert_eq!(
+            sql,
+            "UPDATE \"post\" SET \"is_disabled\" = TRUE, \"reasons_for_disabled\" = 'removed by author' WHERE \"repo\" = 'did:ckb:alice' AND \"is_disabled\" = FALSE"
+        );
+        // content hidden by moderators stays hidden
+        let sql = RemovedRepo::build_restore(Reply::Table.into_iden(), "did:ckb:alice")
+            .to_string(PostgresQueryBuilder);
+        assert_eq!(
+            sql,
+            "UPDATE \"reply\" SET \"is_disabled\" = FALSE, \"reasons_for_disabled\" = NULL WHERE \"repo\" = 'did:ckb:alice' AND \"is_disabled\" = TRUE AND \"reasons_for_disabled\" = 'removed by author'"
+        );
+    }
+
+    #[test]
+    fn restore_within_grace_window() {
+        let removed = Local::now();
+        let row = RemovedRepoRow {
+            did: "did:ckb:alice".to_string(),
+            restore_until: removed + chrono::Duration::days(14),
+            suppress_until: removed + chrono::Duration::days(90),
+            created: removed,
+        };
+        assert!(row.can_restore(removed + chrono::Duration::days(13)));
+        assert!(!row.can_restore(removed + chrono::Duration::days(15)));
+        // firehose re-creations are dropped for the whole period
+        assert!(row.is_suppressed(removed + chrono::Duration::days(15)));
+        assert!(!row.is_suppressed(removed + chrono::Duration::days(91)));
+    }
+}
@@ -1,15 +1,26 @@
 use chrono::{DateTime, Local};
-use color_eyre::{Result, eyre::OptionExt};
+use color_eyre::{
+    Result,
+    eyre::{OptionExt, eyre},
+};
 use sea_query::{ColumnDef, Expr, ExprTrait, Iden, OnConflict, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
 use serde::Serialize;
 use serde_json::Value;
-use sqlx::{Executor, Pool, Postgres, query, query_with};
+use sqlx::{Executor, Pool, Postgres, query};
+use utoipa::ToSchema;
 
-use crate::lexicon::{
-    notify::{Notify, NotifyRow, NotifyType},
-    post::Post,
-    resolve_uri,
+use crate::{
+    db,
+    lexicon::{
+        ThreadError,
+        comment::Comment,
+        edited_after_report, normalize_record,
+        notify::{Notify, NotifyRow, NotifyType},
+        operation::Operation,
+        post::Post,
+        reasons_for_viewer, resolve_uri, section_id_of, with_section_id,
+    },
 };
 
 #[derive(Iden)]
@@ -79,29 +90,17 @@ impl Reply {
         Ok(())
     }
 
-    pub async fn insert(
-        db: &Pool<Postgres>,
+    /// Upserts the reply record, read with `normalize_record` so the field
+    /// names of early clients are accepted.
+    pub fn build_insert(
         repo: &str,
         reply: &Value,
         uri: &str,
         cid: &str,
-    ) -> Result<()> {
-        let section_id = reply["section_id"]
-            .as_str()
-            .and_then(|s| s.parse::<i32>().ok())
-            .ok_or_eyre("error in section_id")?;
-        let post = reply["post"]
-            .as_str()
-            .map(|s| s.trim_matches('\"'))
-            .ok_or_eyre("error in post")?;
-        let comment = reply["comment"]
-            .as_str()
-            .map(|s| s.trim_matches('\"'))
-            .ok_or_eyre("error in comment")?;
-        let to = reply["to"]
-            .as_str()
-            .map(|s| s.trim_matches('\"'))
-            .unwrap_or_default();
+    ) -> Result<sea_query::InsertStatement> {
+        let reply = &*normalize_record(reply, uri);
+        let section_id = section_id_of(reply)?;
+        let (post, comment, to) = thread_of(reply)?;
         let text = reply["text"]
             .as_str()
             .map(|s| s.trim_matches('\"'))
@@ -113,7 +112,7 @@ impl Reply {
             .as_str()
             .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
             .ok_or_eyre("error in created")?;
-        let (sql, values) = sea_query::Query::insert()
+        Ok(sea_query::Query::insert()
             .into_table(Self::Table)
             .columns([
                 Self::Uri,
@@ -155,45 +154,110 @@ impl Reply {
                         Self::Edited,
                         Self::Updated,
                     ])
+                    .action_and_where(
+                        Expr::col((Self::Table, Self::Cid)).ne(Expr::col(("excluded", Self::Cid))),
+                    )
                     .to_owned(),
             )
+            .take())
+    }
+
+    /// The section of the post the reply is on, failing with a
+    /// `ThreadError` when that post or the comment is not indexed, the
+    /// comment is on another post, or a `to` uri points out of the thread.
+    pub async fn check_thread(db: &Pool<Postgres>, reply: &Value, uri: &str) -> Result<i32> {
+        let reply = &*normalize_record(reply, uri);
+        let (post, comment, to) = thread_of(reply)?;
+        let section_id = Post::section_of(db, post)
+            .await?
+            .ok_or_else(|| ThreadError::PostNotFound(post.to_string()))?;
+
+        let (sql, values) = sea_query::Query::select()
+            .column(Comment::Post)
+            .from(Comment::Table)
+            .and_where(Expr::col(Comment::Uri).eq(comment))
             .build_sqlx(PostgresQueryBuilder);
-        db.execute(query_with(&sql, values)).await?;
+        let comment_post: Option<(String,)> = db::fetch_optional(db, &sql, values)
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+        match comment_post {
+            None => return Err(ThreadError::CommentNotFound(comment.to_string()).into()),
+            Some((comment_post,)) if comment_post != post => {
+                return Err(ThreadError::CommentOnOtherPost {
+                    comment: comment.to_string(),
+                    post: post.to_string(),
+                }
+                .into());
+            }
+            Some(_) => {}
+        }
+
+        // a did names the author answered; a uri must stay in the thread
+        if to.starts_with("at://") && to != comment {
+            let (sql, values) = sea_query::Query::select()
+                .column(Self::Uri)
+                .from(Self::Table)
+                .and_where(Expr::col(Self::Uri).eq(to))
+                .and_where(Expr::col(Self::Comment).eq(comment))
+                .build_sqlx(PostgresQueryBuilder);
+            let target: Option<(String,)> = db::fetch_optional(db, &sql, values)
+                .await
+                .map_err(|e| eyre!("exec sql failed: {e}"))?;
+            if to == uri || target.is_none() {
+                return Err(ThreadError::TargetOutsideThread(to.to_string()).into());
+            }
+        }
+        Ok(section_id)
+    }
+
+    /// Indexes the reply under the section of its post, whatever section it
+    /// claims, and refuses it when its references do not hold together.
+    /// Whether the row changed; a replayed record was indexed with the same
+    /// cid already.
+    pub async fn upsert(
+        db: &Pool<Postgres>,
+        repo: &str,
+        reply: &Value,
+        uri: &str,
+        cid: &str,
+    ) -> Result<bool> {
+        let section_id = Self::check_thread(db, reply, uri).await?;
+        let reply = &*with_section_id(reply, uri, section_id);
+        let (sql, values) =
+            Self::build_insert(repo, reply, uri, cid)?.build_sqlx(PostgresQueryBuilder);
+        Ok(db::execute(db, &sql, values).await?.rows_affected() > 0)
+    }
+
+    /// `upsert`, then bumps the post and notifies of a new reply once.
+    pub async fn insert(
+        db: &Pool<Postgres>,
+        repo: &str,
+        reply: &Value,
+        uri: &str,
+        cid: &str,
+    ) -> Result<()> {
+        if !Self::upsert(db, repo, reply, uri, cid).await? {
+            return Ok(());
+        }
+        let (post, comment, to) = thread_of(reply)?;
 
         // update Post::Updated
-        let (sql, values) = sea_query::Query::update()
-            .table(Post::Table)
-            .values([(Post::Updated, (chrono::Local::now()).into())])
-            .and_where(Expr::col(Post::Uri).eq(post))
-            .build_sqlx(PostgresQueryBuilder);
-        db.execute(query_with(&sql, values)).await.ok();
+        Post::bump(db, post)
+            .await
+            .map_err(|e| error!("Post::bump failed: {e}"))
+            .ok();
 
         // notify
-        let (receiver, _nsid, _rkey) = resolve_uri(comment)?;
-        Notify::insert(
-            db,
-            &NotifyRow {
-                id: 0,
-                title: "New Reply".to_string(),
-                sender: repo.to_string(),
-                receiver: receiver.to_string(),
-                n_type: NotifyType::NewReply as i32,
-                target_uri: uri.to_string(),
-                amount: 0,
-                readed: None,
-                created: chrono::Local::now(),
-            },
-        )
-        .await
-        .ok();
-        if !to.is_empty() {
-            Notify::insert(
+        let (comment_author, _nsid, _rkey) = resolve_uri(comment)?;
+        if let Some(receiver) = reply_receiver(repo, comment_author, to) {
+            Notify::insert_once(
                 db,
+                uri,
                 &NotifyRow {
                     id: 0,
                     title: "New Reply".to_string(),
                     sender: repo.to_string(),
-                    receiver: to.to_string(),
+                    receiver: receiver.to_string(),
                     n_type: NotifyType::NewReply as i32,
                     target_uri: uri.to_string(),
                     amount: 0,
@@ -208,33 +272,104 @@ impl Reply {
         Ok(())
     }
 
-    pub async fn update_tag(
-        db: &Pool<Postgres>,
+    pub fn build_select(viewer: Option<String>) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+        .columns([
+            (Self::Table, Self::Uri),
+            (Self::Table, Self::Cid),
+            (Self::Table, Self::Repo),
+            (Self::Table, Self::SectionId),
+            (Self::Table, Self::Post),
+            (Self::Table, Self::Comment),
+            (Self::Table, Self::To),
+            (Self::Table, Self::Text),
+            (Self::Table, Self::IsDisabled),
+            (Self::Table, Self::ReasonsForDisabled),
+            (Self::Table, Self::Edited),
+            (Self::Table, Self::Updated),
+            (Self::Table, Self::Created),
+        ])
+        .expr(Expr::cust("(select count(\"like\".\"uri\") from \"like\" where \"like\".\"to\" = \"reply\".\"uri\") as like_count"))
+        .expr(if let Some(viewer) = &viewer {
+            Expr::cust_with_values("((select count(\"like\".\"uri\") from \"like\" where \"like\".\"repo\" = $1 and \"like\".\"to\" = \"reply\".\"uri\" ) > 0) as liked", [viewer.as_str()])
+        } else {
+            Expr::cust("false as liked".to_string())
+        })
+        .expr(Operation::open_report("reply", &viewer))
+        .from(Self::Table).take()
+    }
+
+    /// Sets the given flags of the reply; `None` when none is given.
+    pub fn build_update_tag(
         uri: &str,
         is_disabled: Option<bool>,
         reasons_for_disabled: Option<String>,
-    ) -> Result<()> {
+    ) -> Option<sea_query::UpdateStatement> {
         let mut values = Vec::new();
         if let Some(is_disabled) = is_disabled {
-            values.push((Post::IsDisabled, is_disabled.into()));
+            values.push((Self::IsDisabled, is_disabled.into()));
         }
         if let Some(reasons_for_disabled) = reasons_for_disabled {
-            values.push((Post::ReasonsForDisabled, reasons_for_disabled.into()));
+            values.push((Self::ReasonsForDisabled, reasons_for_disabled.into()));
         }
         if values.is_empty() {
-            return Ok(());
+            return None;
         }
 
-        let (sql, values) = sea_query::Query::update()
-            .table(Self::Table)
-            .values(values)
-            .and_where(Expr::col(Self::Uri).eq(uri))
-            .build_sqlx(PostgresQueryBuilder);
-        db.execute(query_with(&sql, values)).await?;
+        Some(
+            sea_query::Query::update()
+                .table(Self::Table)
+                .values(values)
+                .and_where(Expr::col(Self::Uri).eq(uri))
+                .take(),
+        )
+    }
+
+    pub async fn update_tag(
+        db: &Pool<Postgres>,
+        uri: &str,
+        is_disabled: Option<bool>,
+        reasons_for_disabled: Option<String>,
+    ) -> Result<()> {
+        if let Some(update) = Self::build_update_tag(uri, is_disabled, reasons_for_disabled) {
+            let (sql, values) = update.build_sqlx(PostgresQueryBuilder);
+            db::execute(db, &sql, values).await?;
+        }
         Ok(())
     }
 }
 
+/// Who is notified of a reply by `replier`: the author it answers when `to`
+/// is set, the comment author only for a direct reply to the comment, so
+/// long exchanges under a comment do not notify its author of every reply.
+/// Nobody is notified of their own reply.
+fn reply_receiver<'a>(replier: &str, comment_author: &'a str, to: &'a str) -> Option<&'a str> {
+    let receiver = match to {
+        "" => comment_author,
+        // `to` names the author, or by some clients the reply answered
+        to => resolve_uri(to).map_or(to, |(did, _, _)| did),
+    };
+    (receiver != replier).then_some(receiver)
+}
+
+/// The post and comment a reply record belongs to, and the author it
+/// answers, if any.
+fn thread_of(reply: &Value) -> Result<(&str, &str, &str)> {
+    let post = reply["post"]
+        .as_str()
+        .map(|s| s.trim_matches('\"'))
+        .ok_or_eyre("error in post")?;
+    let comment = reply["comment"]
+        .as_str()
+        .map(|s| s.trim_matches('\"'))
+        .ok_or_eyre("error in comment")?;
+    let to = reply["to"]
+        .as_str()
+        .map(|s| s.trim_matches('\"'))
+        .unwrap_or_default();
+    Ok((post, comment, to))
+}
+
 #[derive(sqlx::FromRow, Debug, Serialize)]
 pub struct ReplySampleRow {
     pub uri: String,
@@ -270,23 +405,285 @@ pub struct ReplyRow {
     pub created: DateTime<Local>,
     pub like_count: i64,
     pub liked: bool,
+    pub reported: Option<DateTime<Local>>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ReplyView {
     pub uri: String,
     pub cid: String,
+    /// Profile of the reply author, as built by `build_author`.
     pub author: Value,
     pub post: String,
     pub comment: String,
+    /// Profile of the replied user.
     pub to: Value,
     pub text: String,
     pub is_disabled: bool,
+    /// Only shown to the author and moderators while the reply is hidden.
     pub reasons_for_disabled: Option<String>,
+    #[schema(value_type = Option<String>, format = DateTime)]
     pub edited: Option<DateTime<Local>>,
+    #[schema(value_type = String, format = DateTime)]
     pub updated: DateTime<Local>,
+    #[schema(value_type = String, format = DateTime)]
     pub created: DateTime<Local>,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    #[schema(schema_with = crate::lexicon::numeric::schema)]
     pub like_count: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    #[schema(schema_with = crate::lexicon::numeric::schema)]
     pub tip_count: String,
     pub liked: bool,
+    /// Moderators only: whether `edited` is later than the earliest flag
+    /// on the reply no moderator acted on yet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edited_after_report: Option<bool>,
+    #[serde(skip)]
+    reported: Option<DateTime<Local>>,
+}
+
+impl ReplyView {
+    pub fn build(row: ReplyRow, author: Value, to: Value, tip_count: String) -> Self {
+        Self {
+            uri: row.uri,
+            cid: row.cid,
+            author,
+            post: row.post,
+            comment: row.comment,
+            to,
+            text: row.text,
+            is_disabled: row.is_disabled,
+            reasons_for_disabled: row.reasons_for_disabled,
+            edited: row.edited,
+            updated: row.updated,
+            created: row.created,
+            like_count: row.like_count.to_string(),
+            tip_count,
+            liked: row.liked,
+            edited_after_report: None,
+            reported: row.reported,
+        }
+    }
+
+    /// Redact the moderator note unless the viewer may read it.
+    pub fn for_viewer(mut self, privileged: bool) -> Self {
+        self.reasons_for_disabled =
+            reasons_for_viewer(self.reasons_for_disabled, self.is_disabled, privileged);
+        self
+    }
+
+    /// Tell moderators whether the reply was edited after it was reported.
+    pub fn for_moderator(mut self, moderator: bool) -> Self {
+        self.edited_after_report = edited_after_report(self.edited, self.reported, moderator);
+        self
+    }
+}
+
+#[test]
+fn legacy_replies_insert_the_same_row() {
+    use serde_json::json;
+
+    let uri = "at://did:ckb:carol/app.bbs.reply/3kghi";
+    let insert = |reply: Value| {
+        Reply::build_insert("did:ckb:carol", &reply, uri, "bafy")
+            .unwrap()
+            .to_string(PostgresQueryBuilder)
+    };
+    let current = insert(json!({
+        "section_id": "2",
+        "post": "at://did:ckb:alice/app.bbs.post/3kabc",
+        "comment": "at://did:ckb:bob/app.bbs.comment/3kdef",
+        "to": "did:ckb:bob",
+        "text": "Agreed",
+        "created": "2024-05-01T08:00:00+08:00",
+    }));
+    let legacy = json!({
+        "sectionId": "2",
+        "post": "at://did:ckb:alice/app.bbs.post/3kabc",
+        "comment": "at://did:ckb:bob/app.bbs.comment/3kdef",
+        "to": "did:ckb:bob",
+        "content": "Agreed",
+        "created": "2024-05-01T08:00:00+08:00",
+    });
+    assert_eq!(insert(legacy), current);
+    assert!(
+        Reply::build_insert("did:ckb:carol", &json!({ "sectionId": "two" }), uri, "bafy").is_err()
+    );
+}
+
+#[test]
+fn replies_notify_who_they_answer() {
+    let (alice, bob, carol) = ("did:ckb:alice", "did:ckb:bob", "did:ckb:carol");
+    // alice wrote the comment the three of them reply under
+    let chain = [
+        // bob replies to the comment itself
+        (bob, "", Some(alice)),
+        // carol answers bob, alice is left alone
+        (carol, bob, Some(bob)),
+        // bob answers carol
+        (bob, carol, Some(carol)),
+        // alice joins, answering carol
+        (alice, carol, Some(carol)),
+        // carol answers alice, who wrote the comment too: one notification
+        (carol, alice, Some(alice)),
+        // nobody hears of their own reply
+        (alice, "", None),
+        (bob, bob, None),
+        // a reply uri in `to` names its author
+        (carol, "at://did:ckb:bob/app.bbs.reply/3kghi", Some(bob)),
+    ];
+    for (replier, to, receiver) in chain {
+        assert_eq!(
+            reply_receiver(replier, alice, to),
+            receiver,
+            "{replier} to {to:?}"
+        );
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn inconsistent_threads_are_refused() {
+    use serde_json::json;
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft) VALUES ('at://a/app.bbs.post/1', 'bafy', 'a', 1, 't', 't', false), ('at://a/app.bbs.post/2', 'bafy', 'a', 2, 't', 't', false)",
+        "INSERT INTO comment (uri, cid, repo, section_id, post, text) VALUES ('at://b/app.bbs.comment/1', 'bafy', 'b', 1, 'at://a/app.bbs.post/1', 't'), ('at://b/app.bbs.comment/2', 'bafy', 'b', 2, 'at://a/app.bbs.post/2', 't')",
+        "INSERT INTO reply (uri, cid, repo, section_id, post, comment, text) VALUES ('at://c/app.bbs.reply/1', 'bafy', 'c', 1, 'at://a/app.bbs.post/1', 'at://b/app.bbs.comment/1', 't'), ('at://c/app.bbs.reply/2', 'bafy', 'c', 2, 'at://a/app.bbs.post/2', 'at://b/app.bbs.comment/2', 't')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    let thread_error = |e: color_eyre::Report| e.downcast::<ThreadError>().unwrap();
+
+    // a comment takes the section of its post, which must be indexed
+    let comment = json!({ "section_id": "2", "post": "at://a/app.bbs.post/1" });
+    assert_eq!(
+        Comment::check_thread(&db, &comment, "at://b/app.bbs.comment/3")
+            .await
+            .unwrap(),
+        1
+    );
+    let comment = json!({ "section_id": "1", "post": "at://a/app.bbs.post/9" });
+    assert_eq!(
+        thread_error(
+            Comment::check_thread(&db, &comment, "at://b/app.bbs.comment/3")
+                .await
+                .unwrap_err()
+        ),
+        ThreadError::PostNotFound("at://a/app.bbs.post/9".to_string())
+    );
+
+    let uri = "at://c/app.bbs.reply/3";
+    let reply = |post: &str, comment: &str, to: &str| json!({ "section_id": "1", "post": post, "comment": comment, "to": to });
+    let (post, comment) = ("at://a/app.bbs.post/1", "at://b/app.bbs.comment/1");
+    for to in ["", "did:ckb:b", comment, "at://c/app.bbs.reply/1"] {
+        assert_eq!(
+            Reply::check_thread(&db, &reply(post, comment, to), uri)
+                .await
+                .unwrap(),
+            1,
+            "{to}"
+        );
+    }
+    let refused = [
+        (
+            reply("at://a/app.bbs.post/9", comment, ""),
+            uri,
+            ThreadError::PostNotFound("at://a/app.bbs.post/9".to_string()),
+        ),
+        (
+            reply(post, "at://b/app.bbs.comment/9", ""),
+            uri,
+            ThreadError::CommentNotFound("at://b/app.bbs.comment/9".to_string()),
+        ),
+        (
+            reply(post, "at://b/app.bbs.comment/2", ""),
+            uri,
+            ThreadError::CommentOnOtherPost {
+                comment: "at://b/app.bbs.comment/2".to_string(),
+                post: post.to_string(),
+            },
+        ),
+        // a reply under another comment
+        (
+            reply(post, comment, "at://c/app.bbs.reply/2"),
+            uri,
+            ThreadError::TargetOutsideThread("at://c/app.bbs.reply/2".to_string()),
+        ),
+        // a reply that is not indexed
+        (
+            reply(post, comment, "at://c/app.bbs.reply/9"),
+            uri,
+            ThreadError::TargetOutsideThread("at://c/app.bbs.reply/9".to_string()),
+        ),
+        // the reply itself, even once it is indexed
+        (
+            reply(post, comment, "at://c/app.bbs.reply/1"),
+            "at://c/app.bbs.reply/1",
+            ThreadError::TargetOutsideThread("at://c/app.bbs.reply/1".to_string()),
+        ),
+    ];
+    for (reply, uri, error) in refused {
+        assert_eq!(
+            thread_error(Reply::check_thread(&db, &reply, uri).await.unwrap_err()),
+            error,
+            "{reply}"
+        );
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn replies_bump_their_post_once_per_throttle() {
+    use serde_json::json;
+
+    use crate::lexicon::post::BUMP_THROTTLE_SECS;
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft, updated) VALUES ('at://a/app.bbs.post/1', 'bafy', 'a', 1, 't', 't', false, now() - interval '1 hour')",
+        "INSERT INTO comment (uri, cid, repo, section_id, post, text, updated) VALUES ('at://b/app.bbs.comment/1', 'bafy', 'b', 1, 'at://a/app.bbs.post/1', 't', now() - interval '1 hour')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    let updated = async |table: &str, uri: &str| -> DateTime<Local> {
+        sqlx::query_scalar(&format!("SELECT updated FROM {table} WHERE uri = $1"))
+            .bind(uri)
+            .fetch_one(&db)
+            .await
+            .unwrap()
+    };
+    let (post, comment) = ("at://a/app.bbs.post/1", "at://b/app.bbs.comment/1");
+    let before = updated("comment", comment).await;
+    let reply = |text: &str| {
+        json!({
+            "section_id": "1",
+            "post": post,
+            "comment": comment,
+            "text": text,
+            "created": "2024-05-01T08:00:00+08:00",
+        })
+    };
+
+    // the post is bumped, not the comment the reply is under
+    Reply::insert(&db, "c", &reply("first"), "at://c/app.bbs.reply/1", "bafy1")
+        .await
+        .unwrap();
+    let bumped = updated("post", post).await;
+    assert!(Local::now() - bumped < chrono::Duration::seconds(BUMP_THROTTLE_SECS));
+    assert_eq!(updated("comment", comment).await, before);
+
+    // a second reply within the throttle leaves the bump as it was
+    Reply::insert(
+        &db,
+        "d",
+        &reply("second"),
+        "at://d/app.bbs.reply/1",
+        "bafy2",
+    )
+    .await
+    .unwrap();
+    assert_eq!(updated("post", post).await, bumped);
 }
@@ -0,0 +1,152 @@
+use chrono::{DateTime, Local};
+use color_eyre::Result;
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, OnConflict, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Executor, Pool, Postgres, query};
+use utoipa::ToSchema;
+
+use crate::db;
+
+/// The latest commit of each repo the appview saw, from the firehose or
+/// from the `directWrites` it made. Clients read it to detect that another
+/// device wrote to the repo since they last looked.
+#[derive(Iden)]
+pub enum RepoState {
+    Table,
+    Repo,
+    Rev,
+    Cid,
+    Updated,
+}
+
+impl RepoState {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Self::Repo).string().not_null().primary_key())
+            .col(ColumnDef::new(Self::Rev).string().not_null())
+            .col(ColumnDef::new(Self::Cid).string().not_null())
+            .col(
+                ColumnDef::new(Self::Updated)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+        Ok(())
+    }
+
+    /// Records the commit unless a later one is known already; revs are
+    /// TIDs, so they sort in commit order.
+    pub fn build_upsert(repo: &str, rev: &str, cid: &str) -> Result<sea_query::InsertStatement> {
+        Ok(sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([Self::Repo, Self::Rev, Self::Cid, Self::Updated])
+            .values([
+                repo.into(),
+                rev.into(),
+                cid.into(),
+                Expr::current_timestamp(),
+            ])?
+            .on_conflict(
+                OnConflict::column(Self::Repo)
+                    .update_columns([Self::Rev, Self::Cid, Self::Updated])
+                    .action_and_where(
+                        Expr::col((Self::Table, Self::Rev)).lt(Expr::col(("excluded", Self::Rev))),
+                    )
+                    .to_owned(),
+            )
+            .take())
+    }
+
+    pub async fn upsert(db: &Pool<Postgres>, repo: &str, rev: &str, cid: &str) -> Result<()> {
+        let (sql, values) = Self::build_upsert(repo, rev, cid)?.build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    /// Records the commit a `directWrites` response reports. Failures are
+    /// only logged: the write itself went through.
+    pub async fn record_write(db: &Pool<Postgres>, repo: &str, result: &Value) {
+        let commit = &result["commit"];
+        let (Some(rev), Some(cid)) = (commit["rev"].as_str(), commit["cid"].as_str()) else {
+            debug!("no commit in directWrites result of {repo}");
+            return;
+        };
+        Self::upsert(db, repo, rev, cid)
+            .await
+            .map_err(|e| error!("exec sql failed: {e}"))
+            .ok();
+    }
+
+    pub async fn select(db: &Pool<Postgres>, repo: &str) -> Result<Option<RepoStateRow>> {
+        let (sql, values) = sea_query::Query::select()
+            .columns([Self::Repo, Self::Rev, Self::Cid, Self::Updated])
+            .from(Self::Table)
+            .and_where(Expr::col(Self::Repo).eq(repo))
+            .build_sqlx(PostgresQueryBuilder);
+        Ok(db::fetch_optional(db, &sql, values).await?)
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, ToSchema)]
+pub struct RepoStateRow {
+    pub repo: String,
+    /// Rev of the latest commit.
+    pub rev: String,
+    /// Cid of the latest commit.
+    pub cid: String,
+    #[schema(value_type = String, format = DateTime)]
+    pub updated: DateTime<Local>,
+}
+
+impl RepoStateRow {
+    /// Whether the `root` a client writes on top of is older than this
+    /// state. Revs are TIDs, which sort by time, so a client ahead of the
+    /// appview is not refused. A root with only a `cid` is not checked, as
+    /// another cid may be older or newer than this one.
+    pub fn is_stale(&self, root: &Value) -> bool {
+        root["rev"]
+            .as_str()
+            .is_some_and(|rev| rev < self.rev.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn later_commits_win() {
+        let sql = RepoState::build_upsert("did:ckb:alice", "3kabc", "bafyrei")
+            .unwrap()
+            .to_string(PostgresQueryBuilder);
+        assert!(sql.ends_with(
+            "ON CONFLICT (\"repo\") DO UPDATE SET \"rev\" = \"excluded\".\"rev\", \"cid\" = \"excluded\".\"cid\", \"updated\" = \"excluded\".\"updated\" WHERE \"repo_state\".\"rev\" < \"excluded\".\"rev\""
+        ));
+    }
+
+    #[test]
+    fn stale_roots() {
+        let state = RepoStateRow {
+            repo: "did:ckb:alice".to_string(),
+            rev: "3kabc".to_string(),
+            cid: "bafyrei".to_string(),
+            updated: Local::now(),
+        };
+        assert!(!state.is_stale(&json!({ "rev": "3kabc", "cid": "bafyold" })));
+        assert!(state.is_stale(&json!({ "rev": "3kaaa", "cid": "bafyrei" })));
+        // the PDS took a commit the relayer has not brought yet
+        assert!(!state.is_stale(&json!({ "rev": "3kabd", "cid": "bafynew" })));
+        assert!(!state.is_stale(&json!({ "cid": "bafyrei" })));
+        assert!(!state.is_stale(&json!({ "cid": "bafyold" })));
+        assert!(!state.is_stale(&json!(null)));
+        assert!(!state.is_stale(&json!({ "data": "bafydata" })));
+    }
+}
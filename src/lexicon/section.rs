@@ -2,17 +2,36 @@ use std::collections::HashMap;
 
 use chrono::{DateTime, Local};
 use color_eyre::{Result, eyre::eyre};
-use sea_query::{ColumnDef, Expr, ExprTrait, Iden, PostgresQueryBuilder};
+use sea_query::{
+    ColumnDef, CommonTableExpression, Expr, ExprTrait, Iden, JoinType, Order, PostgresQueryBuilder,
+    UnionType, WithClause, WithQuery,
+};
 use sea_query_sqlx::SqlxBinder;
 use serde::Serialize;
-use serde_json::Value;
-use sqlx::{Executor, Pool, Postgres, query, query_as_with};
+use serde_json::{Value, json};
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::{
+    db,
+    lexicon::{
+        PENDING_REVIEW,
+        comment::Comment,
+        like::Like,
+        operation::open_flag,
+        post::Post,
+        section_id_of,
+        section_stats::{self, SectionStats},
+    },
+};
 
 #[derive(Iden)]
 pub enum Section {
     Table,
     Id,
     Permission,
+    /// 0 lets anyone whitelisted comment and reply; above 0 only the owner
+    /// and administrators, as `Permission` does for posts.
+    CommentPermission,
     Name,
     Description,
     Image,
@@ -20,6 +39,11 @@ pub enum Section {
     OwnerSetTime,
     CkbAddr,
     IsDisabled,
+    /// Archived sections take no new content but stay readable.
+    IsArchived,
+    /// Whether moderation is shown under the moderator's DID; NULL follows
+    /// `reveal_moderator` of the config.
+    RevealModerator,
     Updated,
     Created,
 }
@@ -46,12 +70,7 @@ impl Section {
             .col(ColumnDef::new(Self::Description).string())
             .col(ColumnDef::new(Self::Image).string())
             .col(ColumnDef::new(Self::Owner).string())
-            .col(
-                ColumnDef::new(Self::CkbAddr)
-                    .string()
-                    .not_null()
-                    .default("".to_string()),
-            )
+            .col(ColumnDef::new(Self::CkbAddr).string())
             .col(ColumnDef::new(Self::OwnerSetTime).timestamp_with_time_zone())
             .col(
                 ColumnDef::new(Self::IsDisabled)
@@ -74,14 +93,49 @@ impl Section {
             .build(PostgresQueryBuilder);
         db.execute(query(&sql)).await?;
 
+        let sql = sea_query::Table::alter()
+            .table(Self::Table)
+            .add_column_if_not_exists(
+                ColumnDef::new(Self::IsArchived)
+                    .boolean()
+                    .not_null()
+                    .default(false),
+            )
+            .add_column_if_not_exists(ColumnDef::new(Self::RevealModerator).boolean())
+            .add_column_if_not_exists(
+                ColumnDef::new(Self::CommentPermission)
+                    .integer()
+                    .not_null()
+                    .default(0),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
+        for sql in Self::build_ckb_addr_migration() {
+            db.execute(query(&sql)).await?;
+        }
+
         Ok(())
     }
 
+    /// Sections without a ckb_addr used to store "", now they store NULL.
+    pub fn build_ckb_addr_migration() -> [String; 2] {
+        [
+            "ALTER TABLE \"section\" ALTER COLUMN \"ckb_addr\" DROP NOT NULL, ALTER COLUMN \"ckb_addr\" DROP DEFAULT".to_string(),
+            sea_query::Query::update()
+                .table(Self::Table)
+                .value(Self::CkbAddr, Option::<String>::None)
+                .and_where(Expr::col(Self::CkbAddr).eq(""))
+                .to_string(PostgresQueryBuilder),
+        ]
+    }
+
     pub async fn all(db: &Pool<Postgres>) -> Result<HashMap<i32, SectionRow>> {
         let (sql, values) = sea_query::Query::select()
             .columns([
                 Section::Id,
                 Section::Permission,
+                Section::CommentPermission,
                 Section::Name,
                 Section::Description,
                 Section::Image,
@@ -89,14 +143,15 @@ impl Section {
                 Section::OwnerSetTime,
                 Section::CkbAddr,
                 Section::IsDisabled,
+                Section::IsArchived,
+                Section::RevealModerator,
                 Section::Updated,
                 Section::Created,
             ])
             .and_where(Expr::col(Section::IsDisabled).eq(false))
             .from(Section::Table)
             .build_sqlx(PostgresQueryBuilder);
-        let list: Vec<SectionRow> = query_as_with(&sql, values.clone())
-            .fetch_all(db)
+        let list: Vec<SectionRow> = db::fetch_all(db, &sql, values.clone())
             .await
             .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
@@ -114,6 +169,7 @@ impl Section {
             .columns([
                 Section::Id,
                 Section::Permission,
+                Section::CommentPermission,
                 Section::Name,
                 Section::Description,
                 Section::Image,
@@ -121,40 +177,279 @@ impl Section {
                 Section::OwnerSetTime,
                 Section::CkbAddr,
                 Section::IsDisabled,
+                Section::IsArchived,
+                Section::RevealModerator,
                 Section::Updated,
                 Section::Created,
             ])
             .from(Section::Table)
             .and_where(Expr::col(Section::Id).eq(id))
             .build_sqlx(PostgresQueryBuilder);
-        query_as_with(&sql, values.clone())
-            .fetch_one(db)
+        db::fetch_one(db, &sql, values.clone())
             .await
             .map_err(|e| eyre!("exec sql failed: {e}"))
     }
 
+    /// The counters of a section as subqueries, with their column names.
+    pub fn live_counts() -> [(&'static str, &'static str); 6] {
+        [
+            (
+                "visited_count",
+                "(select sum(\"post\".\"visited_count\") from \"post\" where \"post\".\"is_disabled\" is false and \"post\".\"section_id\" = \"section\".\"id\")",
+            ),
+            (
+                "post_count",
+                "(select count(\"post\".\"uri\") from \"post\" where \"post\".\"is_disabled\" is false and \"post\".\"section_id\" = \"section\".\"id\")",
+            ),
+            (
+                "announcement_count",
+                "(select count(\"post\".\"uri\") from \"post\" where \"post\".\"is_disabled\" is false and \"post\".\"section_id\" = \"section\".\"id\" and \"post\".\"is_announcement\")",
+            ),
+            (
+                "top_count",
+                "(select count(\"post\".\"uri\") from \"post\" where \"post\".\"is_disabled\" is false and \"post\".\"section_id\" = \"section\".\"id\" and \"post\".\"is_top\")",
+            ),
+            (
+                "comment_count",
+                "(select count(\"comment\".\"uri\") from \"comment\" where \"comment\".\"is_disabled\" is false and \"comment\".\"section_id\" = \"section\".\"id\")",
+            ),
+            (
+                "like_count",
+                "(select count(\"like\".\"uri\") from \"like\" where \"like\".\"section_id\" = \"section\".\"id\")",
+            ),
+        ]
+    }
+
+    /// Sections with their counters, read from the `SectionStats` snapshot
+    /// while it is fresh and counted live otherwise. `stats_refreshed` is
+    /// the time of the snapshot read, NULL when counted live.
     pub fn build_select() -> sea_query::SelectStatement {
+        let fresh = format!(
+            "\"section_stats\".\"refreshed\" > now() - interval '{} seconds'",
+            section_stats::max_age_secs()
+        );
+        let mut select = sea_query::Query::select();
+        select
+            .columns([
+                Section::Id,
+                Section::Permission,
+                Section::CommentPermission,
+                Section::Name,
+                Section::Description,
+                Section::Image,
+                Section::Owner,
+                Section::OwnerSetTime,
+                Section::CkbAddr,
+                Section::IsDisabled,
+                Section::IsArchived,
+                Section::RevealModerator,
+                Section::Updated,
+                Section::Created,
+            ])
+            .from(Section::Table)
+            .join(
+                JoinType::LeftJoin,
+                SectionStats::Table,
+                Expr::col((SectionStats::Table, SectionStats::SectionId))
+                    .equals((Section::Table, Section::Id)),
+            );
+        for (name, live) in Self::live_counts() {
+            select.expr(Expr::cust(format!(
+                "case when {fresh} then \"section_stats\".\"{name}\" else {live} end as {name}"
+            )));
+        }
+        select
+            .expr(Expr::cust(format!(
+                "case when {fresh} then \"section_stats\".\"refreshed\" end as stats_refreshed"
+            )))
+            .take()
+    }
+
+    /// Sections with the most activity in the last `period_hours`, best
+    /// first. Posts weigh 3, comments 2 and likes 1, and the sum decays with
+    /// the age in hours of the oldest new post:
+    /// `(posts * 3 + comments * 2 + likes) / sqrt(age + 2)`.
+    pub fn build_trending(period_hours: i64, limit: u64) -> WithQuery {
+        let since = Local::now() - chrono::Duration::hours(period_hours);
+        let mut activity = sea_query::Query::select()
+            .columns([Post::SectionId, Post::Created])
+            .expr_as(Expr::val(3), "weight")
+            .from(Post::Table)
+            .and_where(Expr::col(Post::IsDraft).eq(false))
+            .and_where(Expr::col(Post::IsDisabled).eq(false))
+            .and_where(Expr::col(Post::Created).gte(since))
+            .take();
+        activity.union(
+            UnionType::All,
+            sea_query::Query::select()
+                .columns([Comment::SectionId, Comment::Created])
+                .expr_as(Expr::val(2), "weight")
+                .from(Comment::Table)
+                .and_where(Expr::col(Comment::IsDisabled).eq(false))
+                .and_where(Expr::col(Comment::Created).gte(since))
+                .take(),
+        );
+        activity.union(
+            UnionType::All,
+            sea_query::Query::select()
+                .columns([Like::SectionId, Like::Created])
+                .expr_as(Expr::val(1), "weight")
+                .from(Like::Table)
+                .and_where(Expr::col(Like::Created).gte(since))
+                .take(),
+        );
+
+        let scores = sea_query::Query::select()
+            .column("section_id")
+            .expr_as(
+                Expr::cust(format!(
+                    "sum(\"weight\") / power(coalesce(extract(epoch from now() - min(case when \"weight\" = 3 then \"created\" end)) / 3600, {period_hours}) + 2, 0.5)"
+                )),
+                "score",
+            )
+            .from("activity")
+            .group_by_col("section_id")
+            .take();
+
+        Self::build_select()
+            .join_subquery(
+                JoinType::InnerJoin,
+                scores,
+                "trending",
+                Expr::col(("trending", "section_id")).equals((Section::Table, Section::Id)),
+            )
+            .and_where(Expr::col((Section::Table, Section::IsDisabled)).eq(false))
+            .order_by(("trending", "score"), Order::Desc)
+            .order_by((Section::Table, Section::Id), Order::Asc)
+            .limit(limit)
+            .take()
+            .with(
+                WithClause::new()
+                    .cte(
+                        CommonTableExpression::new()
+                            .query(activity)
+                            .table_name("activity")
+                            .to_owned(),
+                    )
+                    .to_owned(),
+            )
+    }
+
+    /// Posts commented on since `since` with the comments they got there,
+    /// leaving out hidden comments and hidden or draft posts.
+    pub fn build_activity(since: DateTime<Local>) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .columns([
+                (Post::Table, Post::SectionId),
+                (Post::Table, Post::Uri),
+                (Post::Table, Post::Title),
+            ])
+            .expr(Expr::cust("COUNT(*)"))
+            .from(Comment::Table)
+            .inner_join(
+                Post::Table,
+                Expr::col((Post::Table, Post::Uri)).equals((Comment::Table, Comment::Post)),
+            )
+            .and_where(Expr::col((Comment::Table, Comment::IsDisabled)).eq(false))
+            .and_where(Expr::col((Post::Table, Post::IsDisabled)).eq(false))
+            .and_where(Expr::col((Post::Table, Post::IsDraft)).eq(false))
+            .and_where(Expr::col((Comment::Table, Comment::Created)).gte(since))
+            .group_by_columns([
+                (Post::Table, Post::SectionId),
+                (Post::Table, Post::Uri),
+                (Post::Table, Post::Title),
+            ])
+            .take()
+    }
+
+    /// `(id, name, owner, open_flags, hidden, pending_review, bans)` of the
+    /// sections `repo` owns, or of every section for an administrator:
+    /// targets with open flags, hidden posts, comments and replies, posts
+    /// shadowed for review in the last day and bans in force.
+    pub fn build_workload(repo: &str, is_admin: bool) -> sea_query::SelectStatement {
+        let hidden = |table: &str| {
+            format!(
+                "(SELECT COUNT(*) FROM \"{table}\" WHERE \"{table}\".\"section_id\" = \"section\".\"id\" AND \"{table}\".\"is_disabled\")"
+            )
+        };
         sea_query::Query::select()
-        .columns([
-            Section::Id,
-            Section::Permission,
-            Section::Name,
-            Section::Description,
-            Section::Image,
-            Section::Owner,
-            Section::OwnerSetTime,
-            Section::CkbAddr,
-            Section::IsDisabled,
-            Section::Updated,
-            Section::Created,
-        ])
-        .expr(Expr::cust("(select sum(\"post\".\"visited_count\") from \"post\" where \"post\".\"is_disabled\" is false and \"post\".\"section_id\" = \"section\".\"id\") as visited_count"))
-        .expr(Expr::cust("(select count(\"post\".\"uri\") from \"post\" where \"post\".\"is_disabled\" is false and \"post\".\"section_id\" = \"section\".\"id\") as post_count"))
-        .expr(Expr::cust("(select count(\"post\".\"uri\") from \"post\" where \"post\".\"is_disabled\" is false and \"post\".\"section_id\" = \"section\".\"id\" and \"post\".\"is_announcement\") as announcement_count"))
-        .expr(Expr::cust("(select count(\"post\".\"uri\") from \"post\" where \"post\".\"is_disabled\" is false and \"post\".\"section_id\" = \"section\".\"id\" and \"post\".\"is_top\") as top_count"))
-        .expr(Expr::cust("(select count(\"comment\".\"uri\") from \"comment\" where \"comment\".\"is_disabled\" is false and \"comment\".\"section_id\" = \"section\".\"id\") as comment_count"))
-        .expr(Expr::cust("(select count(\"like\".\"uri\") from \"like\" where \"like\".\"section_id\" = \"section\".\"id\") as like_count"))
-        .from(Section::Table).take()
+            .columns([
+                (Section::Table, Section::Id),
+                (Section::Table, Section::Name),
+                (Section::Table, Section::Owner),
+            ])
+            .expr(Expr::cust(format!(
+                "(SELECT COUNT(DISTINCT \"flag\".\"target\") FROM \"operation\" AS \"flag\" \
+                 WHERE \"flag\".\"section_id\" = \"section\".\"id\" AND {})",
+                open_flag()
+            )))
+            .expr(Expr::cust(format!(
+                "{} + {} + {}",
+                hidden("post"),
+                hidden("comment"),
+                hidden("reply")
+            )))
+            .expr(Expr::cust(format!(
+                "(SELECT COUNT(*) FROM \"post\" WHERE \"post\".\"section_id\" = \"section\".\"id\" \
+                 AND \"post\".\"is_disabled\" AND \"post\".\"reasons_for_disabled\" LIKE '{PENDING_REVIEW}%' \
+                 AND \"post\".\"created\" > now() - interval '24 hours')"
+            )))
+            .expr(Expr::cust(
+                "(SELECT COUNT(*) FROM \"section_ban\" WHERE \"section_ban\".\"section_id\" = \"section\".\"id\" \
+                 AND \"section_ban\".\"until\" > now())",
+            ))
+            .from(Section::Table)
+            .and_where_option(
+                (!is_admin).then(|| Expr::col((Section::Table, Section::Owner)).eq(repo)),
+            )
+            .order_by((Section::Table, Section::Id), Order::Asc)
+            .take()
+    }
+
+    /// The activity of every section with comments since `since`.
+    pub async fn activity(
+        db: &Pool<Postgres>,
+        since: DateTime<Local>,
+    ) -> Result<HashMap<i32, SectionActivity>> {
+        let (sql, values) = Self::build_activity(since).build_sqlx(PostgresQueryBuilder);
+        let rows: Vec<(i32, String, String, i64)> = db::fetch_all(db, &sql, values)
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+        Ok(SectionActivity::fold(rows))
+    }
+}
+
+/// How lively a section was over a window, for the section cards.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SectionActivity {
+    /// Posts commented on in the window.
+    pub active_post_count: i64,
+    /// The post with the most comments in the window, with their count.
+    pub most_active: Option<(ActivePost, i64)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ActivePost {
+    pub uri: String,
+    pub title: String,
+}
+
+impl SectionActivity {
+    /// Sums the `(section_id, uri, title, comments)` rows of
+    /// `Section::build_activity` up per section. Ties go to the lower uri.
+    fn fold(rows: Vec<(i32, String, String, i64)>) -> HashMap<i32, Self> {
+        let mut sections = HashMap::<i32, Self>::new();
+        for (section_id, uri, title, comments) in rows {
+            let section = sections.entry(section_id).or_default();
+            section.active_post_count += 1;
+            let busier = section.most_active.as_ref().is_none_or(|(post, most)| {
+                comments > *most || (comments == *most && uri < post.uri)
+            });
+            if busier {
+                section.most_active = Some((ActivePost { uri, title }, comments));
+            }
+        }
+        sections
     }
 }
 
@@ -165,10 +460,13 @@ pub struct SectionRow {
     pub description: Option<String>,
     pub image: Option<String>,
     pub permission: i32,
+    pub comment_permission: i32,
     pub owner: Option<String>,
     pub owner_set_time: Option<DateTime<Local>>,
-    pub ckb_addr: String,
+    pub ckb_addr: Option<String>,
     pub is_disabled: bool,
+    pub is_archived: bool,
+    pub reveal_moderator: Option<bool>,
     pub updated: DateTime<Local>,
     pub created: DateTime<Local>,
 }
@@ -178,12 +476,15 @@ pub struct SectionRowSample {
     pub id: i32,
     pub name: String,
     pub permission: i32,
+    pub comment_permission: i32,
     pub description: Option<String>,
     pub image: Option<String>,
     pub owner: Option<String>,
     pub owner_set_time: Option<DateTime<Local>>,
-    pub ckb_addr: String,
+    pub ckb_addr: Option<String>,
     pub is_disabled: bool,
+    pub is_archived: bool,
+    pub reveal_moderator: Option<bool>,
     pub updated: DateTime<Local>,
     pub created: DateTime<Local>,
     pub visited_count: Option<i64>,
@@ -192,27 +493,79 @@ pub struct SectionRowSample {
     pub top_count: Option<i64>,
     pub comment_count: Option<i64>,
     pub like_count: Option<i64>,
+    pub stats_refreshed: Option<DateTime<Local>>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct SectionView {
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub id: String,
     pub name: String,
     pub description: Option<String>,
     pub image: Option<String>,
     pub owner: Value,
     pub owner_set_time: Option<DateTime<Local>>,
-    pub ckb_addr: String,
+    pub ckb_addr: Option<String>,
     pub permission: String,
+    pub comment_permission: String,
     pub is_disabled: bool,
+    pub archived: bool,
+    pub reveal_moderator: Option<bool>,
     pub updated: DateTime<Local>,
     pub created: DateTime<Local>,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub visited_count: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub post_count: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub announcement_count: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub top_count: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub comment_count: String,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub like_count: String,
+    /// Posts with new comments in the last 24 hours.
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    pub active_post_count_24h: String,
+    /// The post with the most new comments in the last 24 hours.
+    pub most_active_post: Option<ActivePost>,
+    /// Seconds since the counters were snapshotted; null when they were
+    /// counted for this response.
+    pub stats_age_secs: Option<i64>,
+}
+
+/// Whether `record` is new content of an archived section. Records without
+/// a section are left to the checks of their collection.
+pub fn in_archived_section(sections: &HashMap<i32, SectionRow>, record: &Value) -> bool {
+    section_id_of(record)
+        .ok()
+        .and_then(|id| sections.get(&id))
+        .is_some_and(|section| section.is_archived)
+}
+
+/// Who stands in for the moderator of section `section_id`: `None` when
+/// the moderator is shown, else the section. Sections not in `sections`
+/// follow `reveal_default` and are shown as the community.
+pub fn moderator_stand_in(
+    sections: &HashMap<i32, SectionRow>,
+    section_id: i32,
+    reveal_default: bool,
+) -> Option<Value> {
+    match sections.get(&section_id) {
+        Some(section) if !section.reveal_moderator.unwrap_or(reveal_default) => Some(json!({
+            "section_id": section.id.to_string(),
+            "displayName": section.name,
+        })),
+        Some(_) => None,
+        None => (!reveal_default).then(|| json!({ "displayName": "community" })),
+    }
+}
+
+/// The ckb_addr to store for a section; blank means the section has none.
+pub fn ckb_addr_or_none(ckb_addr: &str) -> Option<String> {
+    let ckb_addr = ckb_addr.trim();
+    (!ckb_addr.is_empty()).then(|| ckb_addr.to_string())
 }
 
 impl SectionView {
@@ -222,11 +575,14 @@ impl SectionView {
             name: row.name,
             description: row.description,
             permission: row.permission.to_string(),
+            comment_permission: row.comment_permission.to_string(),
             owner,
             owner_set_time: row.owner_set_time,
             image: row.image,
             ckb_addr: row.ckb_addr,
             is_disabled: row.is_disabled,
+            archived: row.is_archived,
+            reveal_moderator: row.reveal_moderator,
             updated: row.updated,
             created: row.created,
             visited_count: row.visited_count.unwrap_or_default().to_string(),
@@ -235,6 +591,209 @@ impl SectionView {
             top_count: row.top_count.unwrap_or_default().to_string(),
             comment_count: row.comment_count.unwrap_or_default().to_string(),
             like_count: row.like_count.unwrap_or_default().to_string(),
+            active_post_count_24h: "0".to_string(),
+            most_active_post: None,
+            stats_age_secs: row
+                .stats_refreshed
+                .map(|refreshed| (Local::now() - refreshed).num_seconds().max(0)),
         }
     }
+
+    /// Adds the activity of the section, if it had any.
+    pub fn with_activity(mut self, activity: &HashMap<i32, SectionActivity>) -> Self {
+        if let Some(activity) = self.id.parse().ok().and_then(|id| activity.get(&id)) {
+            self.active_post_count_24h = activity.active_post_count.to_string();
+            self.most_active_post = activity.most_active.clone().map(|(post, _)| post);
+        }
+        self
+    }
+}
+
+#[test]
+fn blank_ckb_addr_is_stored_as_null() {
+    assert_eq!(ckb_addr_or_none(""), None);
+    assert_eq!(ckb_addr_or_none("  "), None);
+    assert_eq!(
+        ckb_addr_or_none("ckt1section"),
+        Some("ckt1section".to_string())
+    );
+
+    let [alter, update] = Section::build_ckb_addr_migration();
+    assert_eq!(
+        alter,
+        "ALTER TABLE \"section\" ALTER COLUMN \"ckb_addr\" DROP NOT NULL, ALTER COLUMN \"ckb_addr\" DROP DEFAULT"
+    );
+    assert_eq!(
+        update,
+        "UPDATE \"section\" SET \"ckb_addr\" = NULL WHERE \"ckb_addr\" = ''"
+    );
+}
+
+#[test]
+fn trending_ranks_sections_by_weighted_activity() {
+    let sql = Section::build_trending(24 * 7, 5).to_string(PostgresQueryBuilder);
+    assert!(sql.starts_with("WITH \"activity\" AS ("));
+    assert!(sql.contains("3 AS \"weight\" FROM \"post\""));
+    assert!(sql.contains("2 AS \"weight\" FROM \"comment\""));
+    assert!(sql.contains("1 AS \"weight\" FROM \"like\""));
+    assert!(sql.contains("/ 3600, 168) + 2, 0.5) AS \"score\" FROM \"activity\""));
+    assert!(sql.ends_with("ORDER BY \"trending\".\"score\" DESC, \"section\".\"id\" ASC LIMIT 5"));
+}
+
+#[test]
+fn activity_sums_commented_posts_per_section() {
+    let since = Local::now();
+    let sql = Section::build_activity(since).to_string(PostgresQueryBuilder);
+    assert!(sql.contains(
+        "FROM \"comment\" INNER JOIN \"post\" ON \"post\".\"uri\" = \"comment\".\"post\""
+    ));
+    assert!(
+        sql.ends_with("GROUP BY \"post\".\"section_id\", \"post\".\"uri\", \"post\".\"title\"")
+    );
+
+    let row = |section_id: i32, uri: &str, comments: i64| {
+        (
+            section_id,
+            uri.to_string(),
+            format!("title of {uri}"),
+            comments,
+        )
+    };
+    let activity = SectionActivity::fold(vec![
+        row(1, "at://b", 3),
+        row(1, "at://c", 5),
+        row(1, "at://a", 5),
+        row(2, "at://d", 1),
+    ]);
+    assert_eq!(activity[&1].active_post_count, 3);
+    let (post, comments) = activity[&1].most_active.clone().unwrap();
+    assert_eq!((post.uri.as_str(), comments), ("at://a", 5));
+    assert_eq!(post.title, "title of at://a");
+    assert_eq!(activity[&2].active_post_count, 1);
+    assert!(!activity.contains_key(&3));
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn activity_window_starts_at_since() {
+    let db = crate::db::test_pool().await;
+    let since = Local::now() - chrono::Duration::hours(24);
+    for sql in [
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft) VALUES ('at://p1', 'bafy', 'did:ckb:alice', 1, 'busy', 't', false), ('at://p2', 'bafy', 'did:ckb:alice', 1, 'quiet', 't', false), ('at://p3', 'bafy', 'did:ckb:alice', 1, 'stale', 't', false), ('at://p4', 'bafy', 'did:ckb:alice', 2, 'other', 't', false)",
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft, is_disabled) VALUES ('at://hidden', 'bafy', 'did:ckb:alice', 1, 'hidden', 't', false, true)",
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft) VALUES ('at://draft', 'bafy', 'did:ckb:alice', 1, 'draft', 't', true)",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    // seconds after `since`, or before it when negative
+    let comments = [
+        ("at://p1", 0, false),
+        ("at://p1", 60, false),
+        ("at://p1", 3600, false),
+        ("at://p2", 10, false),
+        ("at://p2", 20, true),
+        ("at://p3", -1, false),
+        ("at://p3", -3600, false),
+        ("at://p4", 5, false),
+        ("at://hidden", 5, false),
+        ("at://draft", 5, false),
+    ];
+    for (i, (post, secs, hidden)) in comments.into_iter().enumerate() {
+        sqlx::query("INSERT INTO comment (uri, cid, repo, section_id, post, text, is_disabled, created) VALUES ($1, 'bafy', 'did:ckb:bob', 1, $2, 't', $3, $4)")
+            .bind(format!("at://c{i}"))
+            .bind(post)
+            .bind(hidden)
+            .bind(since + chrono::Duration::seconds(secs))
+            .execute(&db)
+            .await
+            .unwrap();
+    }
+
+    let activity = Section::activity(&db, since).await.unwrap();
+    assert_eq!(activity.len(), 2);
+    assert_eq!(activity[&1].active_post_count, 2);
+    assert_eq!(
+        activity[&1].most_active,
+        Some((
+            ActivePost {
+                uri: "at://p1".to_string(),
+                title: "busy".to_string()
+            },
+            3
+        ))
+    );
+    assert_eq!(activity[&2].active_post_count, 1);
+}
+
+#[test]
+fn archived_sections_take_no_new_content() {
+    let section = |id: i32, is_archived: bool| SectionRow {
+        id,
+        name: format!("section {id}"),
+        description: None,
+        image: None,
+        permission: 0,
+        comment_permission: 0,
+        owner: None,
+        owner_set_time: None,
+        ckb_addr: None,
+        is_disabled: false,
+        is_archived,
+        reveal_moderator: None,
+        updated: Local::now(),
+        created: Local::now(),
+    };
+    let sections = HashMap::from([(1, section(1, false)), (2, section(2, true))]);
+    let in_archived = |record: Value| in_archived_section(&sections, &record);
+    assert!(in_archived(json!({ "section_id": "2" })));
+    assert!(in_archived(json!({ "section_id": 2 })));
+    assert!(!in_archived(json!({ "section_id": "1" })));
+    // unknown and disabled sections are refused elsewhere
+    assert!(!in_archived(json!({ "section_id": "9" })));
+    assert!(!in_archived(json!({ "subject": "did:ckb:alice" })));
+}
+
+#[test]
+fn moderators_stand_in_by_policy() {
+    let section = |id: i32, reveal_moderator: Option<bool>| SectionRow {
+        id,
+        name: format!("section {id}"),
+        description: None,
+        image: None,
+        permission: 0,
+        comment_permission: 0,
+        owner: None,
+        owner_set_time: None,
+        ckb_addr: None,
+        is_disabled: false,
+        is_archived: false,
+        reveal_moderator,
+        updated: Local::now(),
+        created: Local::now(),
+    };
+    let sections = HashMap::from([
+        (1, section(1, None)),
+        (2, section(2, Some(false))),
+        (3, section(3, Some(true))),
+    ]);
+    let section_2 = json!({ "section_id": "2", "displayName": "section 2" });
+
+    assert_eq!(moderator_stand_in(&sections, 1, true), None);
+    assert_eq!(
+        moderator_stand_in(&sections, 2, true),
+        Some(section_2.clone())
+    );
+    assert_eq!(moderator_stand_in(&sections, 3, true), None);
+    assert_eq!(moderator_stand_in(&sections, 9, true), None);
+
+    assert_eq!(
+        moderator_stand_in(&sections, 1, false),
+        Some(json!({ "section_id": "1", "displayName": "section 1" }))
+    );
+    assert_eq!(moderator_stand_in(&sections, 2, false), Some(section_2));
+    assert_eq!(moderator_stand_in(&sections, 3, false), None);
+    assert_eq!(
+        moderator_stand_in(&sections, 9, false),
+        Some(json!({ "displayName": "community" }))
+    );
 }
@@ -0,0 +1,197 @@
+use chrono::{DateTime, Local};
+use color_eyre::Result;
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, Index, OnConflict, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde::Serialize;
+use sqlx::{Executor, Pool, Postgres, query};
+use utoipa::ToSchema;
+
+use crate::db;
+
+/// Users kept from writing in a section until a point in time. Bans end
+/// by `until` passing; nothing cleans them up.
+#[derive(Iden)]
+pub enum SectionBan {
+    Table,
+    SectionId,
+    Did,
+    Until,
+    Reason,
+    BannedBy,
+    Created,
+}
+
+impl SectionBan {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Self::SectionId).integer().not_null())
+            .col(ColumnDef::new(Self::Did).string().not_null())
+            .col(
+                ColumnDef::new(Self::Until)
+                    .timestamp_with_time_zone()
+                    .not_null(),
+            )
+            .col(ColumnDef::new(Self::Reason).string().not_null().default(""))
+            .col(ColumnDef::new(Self::BannedBy).string().not_null())
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .primary_key(Index::create().col(Self::SectionId).col(Self::Did))
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+        Ok(())
+    }
+
+    /// A new ban replaces the one the user had in the section.
+    pub fn build_upsert(
+        section_id: i32,
+        did: &str,
+        until: DateTime<Local>,
+        reason: &str,
+        banned_by: &str,
+    ) -> Result<sea_query::InsertStatement> {
+        Ok(sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([
+                Self::SectionId,
+                Self::Did,
+                Self::Until,
+                Self::Reason,
+                Self::BannedBy,
+                Self::Created,
+            ])
+            .values([
+                section_id.into(),
+                did.into(),
+                until.into(),
+                reason.into(),
+                banned_by.into(),
+                Expr::current_timestamp(),
+            ])?
+            .on_conflict(
+                OnConflict::columns([Self::SectionId, Self::Did])
+                    .update_columns([Self::Until, Self::Reason, Self::BannedBy, Self::Created])
+                    .to_owned(),
+            )
+            .take())
+    }
+
+    pub async fn upsert(
+        db: &Pool<Postgres>,
+        section_id: i32,
+        did: &str,
+        until: DateTime<Local>,
+        reason: &str,
+        banned_by: &str,
+    ) -> Result<()> {
+        let (sql, values) = Self::build_upsert(section_id, did, until, reason, banned_by)?
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    /// Ends an active ban at `now`; the row stays for the notification of
+    /// the ban. Returns whether there was one.
+    pub async fn lift(
+        db: &Pool<Postgres>,
+        section_id: i32,
+        did: &str,
+        now: DateTime<Local>,
+    ) -> Result<bool> {
+        let (sql, values) = sea_query::Query::update()
+            .table(Self::Table)
+            .value(Self::Until, now)
+            .and_where(Expr::col(Self::SectionId).eq(section_id))
+            .and_where(Expr::col(Self::Did).eq(did))
+            .and_where(Expr::col(Self::Until).gt(now))
+            .build_sqlx(PostgresQueryBuilder);
+        let result = db::execute(db, &sql, values).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub fn build_select() -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .columns([
+                Self::SectionId,
+                Self::Did,
+                Self::Until,
+                Self::Reason,
+                Self::BannedBy,
+                Self::Created,
+            ])
+            .from(Self::Table)
+            .take()
+    }
+
+    /// The ban of `did` in the section, expired or not.
+    pub async fn select(
+        db: &Pool<Postgres>,
+        section_id: i32,
+        did: &str,
+    ) -> Result<Option<SectionBanRow>> {
+        let (sql, values) = Self::build_select()
+            .and_where(Expr::col(Self::SectionId).eq(section_id))
+            .and_where(Expr::col(Self::Did).eq(did))
+            .build_sqlx(PostgresQueryBuilder);
+        Ok(db::fetch_optional(db, &sql, values).await?)
+    }
+
+    /// The ban keeping `did` from writing in the section at `now`.
+    pub async fn active(
+        db: &Pool<Postgres>,
+        section_id: i32,
+        did: &str,
+        now: DateTime<Local>,
+    ) -> Result<Option<SectionBanRow>> {
+        Ok(Self::select(db, section_id, did)
+            .await?
+            .filter(|ban| ban.is_active(now)))
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, ToSchema)]
+pub struct SectionBanRow {
+    pub section_id: i32,
+    pub did: String,
+    /// The user may write in the section again from this time on.
+    #[schema(value_type = String, format = DateTime)]
+    pub until: DateTime<Local>,
+    pub reason: String,
+    pub banned_by: String,
+    #[schema(value_type = String, format = DateTime)]
+    pub created: DateTime<Local>,
+}
+
+impl SectionBanRow {
+    pub fn is_active(&self, now: DateTime<Local>) -> bool {
+        now < self.until
+    }
+}
+
+#[test]
+fn bans_end_at_until() {
+    let until = Local::now();
+    let ban = SectionBanRow {
+        section_id: 1,
+        did: "did:ckb:alice".to_string(),
+        until,
+        reason: "spam".to_string(),
+        banned_by: "did:ckb:owner".to_string(),
+        created: until - chrono::Duration::days(7),
+    };
+    assert!(ban.is_active(until - chrono::Duration::milliseconds(1)));
+    assert!(!ban.is_active(until));
+    assert!(!ban.is_active(until + chrono::Duration::days(1)));
+
+    let sql = SectionBan::build_upsert(1, "did:ckb:alice", until, "spam", "did:ckb:owner")
+        .unwrap()
+        .to_string(PostgresQueryBuilder);
+    assert!(sql.ends_with(
+        "ON CONFLICT (\"section_id\", \"did\") DO UPDATE SET \"until\" = \"excluded\".\"until\", \"reason\" = \"excluded\".\"reason\", \"banned_by\" = \"excluded\".\"banned_by\", \"created\" = \"excluded\".\"created\""
+    ));
+}
@@ -0,0 +1,227 @@
+use std::{
+    sync::atomic::{AtomicI64, Ordering},
+    time::Duration,
+};
+
+use color_eyre::Result;
+use sea_query::{ColumnDef, Expr, Iden, OnConflict, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::{config::SectionStatsConfig, db, lexicon::section::Section};
+
+static MAX_AGE_SECS: AtomicI64 = AtomicI64::new(300);
+
+pub fn set_section_stats(config: &SectionStatsConfig) {
+    MAX_AGE_SECS.store(config.max_age_secs, Ordering::Relaxed);
+}
+
+/// Snapshots older than this many seconds are not read.
+pub fn max_age_secs() -> i64 {
+    MAX_AGE_SECS.load(Ordering::Relaxed)
+}
+
+/// The counters of each section as of `refreshed`, so that section lists
+/// do not count the posts, comments and likes of every section each time.
+#[derive(Iden)]
+pub enum SectionStats {
+    Table,
+    SectionId,
+    VisitedCount,
+    PostCount,
+    AnnouncementCount,
+    TopCount,
+    CommentCount,
+    LikeCount,
+    Refreshed,
+}
+
+impl SectionStats {
+    const COUNTS: [Self; 6] = [
+        Self::VisitedCount,
+        Self::PostCount,
+        Self::AnnouncementCount,
+        Self::TopCount,
+        Self::CommentCount,
+        Self::LikeCount,
+    ];
+
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let mut table = sea_query::Table::create();
+        table.table(Self::Table).if_not_exists().col(
+            ColumnDef::new(Self::SectionId)
+                .integer()
+                .not_null()
+                .primary_key(),
+        );
+        for count in Self::COUNTS {
+            table.col(ColumnDef::new(count).big_integer());
+        }
+        let sql = table
+            .col(
+                ColumnDef::new(Self::Refreshed)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+        Ok(())
+    }
+
+    /// Counts every section live, the way `Section::build_select` does
+    /// without a snapshot, and stores the result.
+    pub fn build_refresh() -> Result<sea_query::InsertStatement> {
+        let mut select = sea_query::Query::select();
+        select.column((Section::Table, Section::Id));
+        for (_, live) in Section::live_counts() {
+            select.expr(Expr::cust(live));
+        }
+        select.expr(Expr::current_timestamp()).from(Section::Table);
+
+        let mut columns = vec![Self::SectionId];
+        columns.extend(Self::COUNTS);
+        columns.push(Self::Refreshed);
+        Ok(sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns(columns)
+            .select_from(select)?
+            .on_conflict(
+                OnConflict::column(Self::SectionId)
+                    .update_columns(Self::COUNTS)
+                    .update_column(Self::Refreshed)
+                    .to_owned(),
+            )
+            .take())
+    }
+
+    /// Takes a new snapshot, returning the sections in it.
+    pub async fn refresh(db: &Pool<Postgres>) -> Result<u64> {
+        let (sql, values) = Self::build_refresh()?.build_sqlx(PostgresQueryBuilder);
+        Ok(db::execute(db, &sql, values).await?.rows_affected())
+    }
+
+    /// Takes a snapshot every `every`, forever.
+    pub async fn keep_refreshed(db: Pool<Postgres>, every: Duration) {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            if let Err(e) = Self::refresh(&db).await {
+                error!("refresh section stats failed: {e}");
+            }
+        }
+    }
+}
+
+#[test]
+fn refresh_counts_like_the_live_select() {
+    let sql = SectionStats::build_refresh()
+        .unwrap()
+        .to_string(PostgresQueryBuilder);
+    assert!(sql.starts_with(
+        "INSERT INTO \"section_stats\" (\"section_id\", \"visited_count\", \"post_count\", \"announcement_count\", \"top_count\", \"comment_count\", \"like_count\", \"refreshed\") SELECT \"section\".\"id\""
+    ));
+    for (_, live) in Section::live_counts() {
+        assert!(sql.contains(live), "{live}");
+    }
+    assert!(sql.ends_with(
+        "ON CONFLICT (\"section_id\") DO UPDATE SET \"visited_count\" = \"excluded\".\"visited_count\", \"post_count\" = \"excluded\".\"post_count\", \"announcement_count\" = \"excluded\".\"announcement_count\", \"top_count\" = \"excluded\".\"top_count\", \"comment_count\" = \"excluded\".\"comment_count\", \"like_count\" = \"excluded\".\"like_count\", \"refreshed\" = \"excluded\".\"refreshed\""
+    ));
+
+    let sql = Section::build_select().to_string(PostgresQueryBuilder);
+    assert!(sql.contains(
+        "LEFT JOIN \"section_stats\" ON \"section_stats\".\"section_id\" = \"section\".\"id\""
+    ));
+    assert!(sql.contains("then \"section_stats\".\"post_count\" else (select count"));
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn snapshots_match_the_live_counts() {
+    use crate::lexicon::section::SectionRowSample;
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO section (id, name) VALUES (1, 'General'), (2, 'Empty')",
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft, visited_count, is_disabled, is_announcement, is_top) VALUES
+            ('p1', 'bafy', 'did:ckb:alice', 1, 't', 't', false, 5, false, true, false),
+            ('p2', 'bafy', 'did:ckb:alice', 1, 't', 't', false, 7, false, false, true),
+            ('p3', 'bafy', 'did:ckb:alice', 1, 't', 't', false, 100, true, false, false)",
+        "INSERT INTO comment (uri, cid, repo, section_id, post, text, is_disabled) VALUES ('c1', 'bafy', 'did:ckb:bob', 1, 'p1', 't', false), ('c2', 'bafy', 'did:ckb:bob', 1, 'p1', 't', true)",
+        "INSERT INTO \"like\" (uri, cid, repo, section_id, \"to\") VALUES ('l1', 'bafy', 'did:ckb:bob', 1, 'p1'), ('l2', 'bafy', 'did:ckb:carol', 1, 'p1')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    let select = async || -> Vec<SectionRowSample> {
+        let (sql, values) = Section::build_select()
+            .order_by(Section::Id, sea_query::Order::Asc)
+            .build_sqlx(PostgresQueryBuilder);
+        db::fetch_all(&db, &sql, values).await.unwrap()
+    };
+    let counts = |rows: &[SectionRowSample]| -> Vec<[Option<i64>; 6]> {
+        rows.iter()
+            .map(|row| {
+                [
+                    row.visited_count,
+                    row.post_count,
+                    row.announcement_count,
+                    row.top_count,
+                    row.comment_count,
+                    row.like_count,
+                ]
+            })
+            .collect()
+    };
+
+    // no snapshot yet: counted live
+    let live = select().await;
+    assert!(live.iter().all(|row| row.stats_refreshed.is_none()));
+    assert_eq!(
+        counts(&live),
+        [
+            [Some(12), Some(2), Some(1), Some(1), Some(1), Some(2)],
+            [None, Some(0), Some(0), Some(0), Some(0), Some(0)],
+        ]
+    );
+
+    assert_eq!(SectionStats::refresh(&db).await.unwrap(), 2);
+    let snapshot = select().await;
+    assert!(snapshot.iter().all(|row| row.stats_refreshed.is_some()));
+    assert_eq!(counts(&snapshot), counts(&live));
+
+    // new posts wait for the next snapshot, unless it is stale
+    db.execute(query(
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft, visited_count) VALUES ('p4', 'bafy', 'did:ckb:alice', 2, 't', 't', false, 1)",
+    ))
+    .await
+    .unwrap();
+    assert_eq!(select().await[1].post_count, Some(0));
+    db.execute(query(
+        "UPDATE section_stats SET post_count = 99, refreshed = now() - interval '1 day'",
+    ))
+    .await
+    .unwrap();
+    let stale = select().await;
+    assert!(stale.iter().all(|row| row.stats_refreshed.is_none()));
+    assert_eq!(stale[0].post_count, Some(2));
+    assert_eq!(stale[1].post_count, Some(1));
+
+    // the loop takes them in
+    let refresher = tokio::spawn(SectionStats::keep_refreshed(
+        db.clone(),
+        Duration::from_millis(50),
+    ));
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let rows = select().await;
+            if rows[1].stats_refreshed.is_some() {
+                assert_eq!(counts(&rows), counts(&stale));
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("snapshot was not refreshed");
+    refresher.abort();
+}
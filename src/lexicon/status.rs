@@ -1,8 +1,10 @@
 use chrono::{DateTime, Local};
 use color_eyre::Result;
-use sea_query::{ColumnDef, Expr, Iden, OnConflict, PostgresQueryBuilder};
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, OnConflict, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
-use sqlx::{Executor, Pool, Postgres, query, query_with};
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::db;
 
 #[derive(Iden)]
 pub enum Status {
@@ -10,6 +12,8 @@ pub enum Status {
     Id,
     OnlineCount,
     VisitedCount,
+    /// Seq of the last relayer commit handled, saved by the subscription.
+    FirehoseCursor,
     Updated,
     Created,
 }
@@ -53,6 +57,12 @@ impl Status {
             .build(PostgresQueryBuilder);
         db.execute(query(&sql)).await?;
 
+        let sql = sea_query::Table::alter()
+            .table(Self::Table)
+            .add_column_if_not_exists(ColumnDef::new(Self::FirehoseCursor).big_integer())
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
         let (sql, values) = sea_query::Query::insert()
             .into_table(Self::Table)
             .columns([Self::Id])
@@ -63,7 +73,29 @@ impl Status {
                     .to_owned(),
             )
             .build_sqlx(PostgresQueryBuilder);
-        db.execute(query_with(&sql, values)).await?;
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    /// The saved relayer cursor, if any.
+    pub async fn firehose_cursor(db: &Pool<Postgres>) -> Result<Option<i64>> {
+        let (sql, values) = sea_query::Query::select()
+            .column(Self::FirehoseCursor)
+            .from(Self::Table)
+            .and_where(Expr::col(Self::Id).eq(0))
+            .build_sqlx(PostgresQueryBuilder);
+        let cursor: Option<(Option<i64>,)> = db::fetch_optional(db, &sql, values).await?;
+        Ok(cursor.and_then(|(cursor,)| cursor))
+    }
+
+    pub async fn save_firehose_cursor(db: &Pool<Postgres>, seq: i64) -> Result<()> {
+        let (sql, values) = sea_query::Query::update()
+            .table(Self::Table)
+            .value(Self::FirehoseCursor, seq)
+            .value(Self::Updated, Expr::current_timestamp())
+            .and_where(Expr::col(Self::Id).eq(0))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
         Ok(())
     }
 }
@@ -74,6 +106,7 @@ pub struct StatusRow {
     id: i32,
     online_count: i32,
     visited_count: i32,
+    firehose_cursor: Option<i64>,
     updated: DateTime<Local>,
     created: DateTime<Local>,
 }
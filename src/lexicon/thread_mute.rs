@@ -0,0 +1,122 @@
+use color_eyre::Result;
+use sea_query::{ColumnDef, Cond, Expr, ExprTrait, Iden, Index, OnConflict, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::{
+    db,
+    lexicon::{comment::Comment, reply::Reply},
+};
+
+/// Threads a user gets no more notifications from. Muting leaves the thread
+/// visible in feeds.
+#[derive(Iden)]
+pub enum ThreadMute {
+    Table,
+    Did,
+    PostUri,
+    Created,
+}
+
+impl ThreadMute {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Self::Did).string().not_null())
+            .col(ColumnDef::new(Self::PostUri).string().not_null())
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .primary_key(Index::create().col(Self::Did).col(Self::PostUri))
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+        Ok(())
+    }
+
+    pub async fn insert(db: &Pool<Postgres>, did: &str, post_uri: &str) -> Result<()> {
+        let (sql, values) = sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([Self::Did, Self::PostUri])
+            .values([did.into(), post_uri.into()])?
+            .on_conflict(
+                OnConflict::columns([Self::Did, Self::PostUri])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    pub async fn delete(db: &Pool<Postgres>, did: &str, post_uri: &str) -> Result<()> {
+        let (sql, values) = sea_query::Query::delete()
+            .from_table(Self::Table)
+            .and_where(Expr::col(Self::Did).eq(did))
+            .and_where(Expr::col(Self::PostUri).eq(post_uri))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    /// Whether `did` muted the thread of `target_uri`: the post itself, or
+    /// the post a comment or reply belongs to.
+    pub fn build_muted(did: &str, target_uri: &str) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .column(Self::PostUri)
+            .from(Self::Table)
+            .and_where(Expr::col(Self::Did).eq(did))
+            .cond_where(
+                Cond::any()
+                    .add(Expr::col(Self::PostUri).eq(target_uri))
+                    .add(
+                        Expr::col(Self::PostUri).in_subquery(
+                            sea_query::Query::select()
+                                .column(Comment::Post)
+                                .from(Comment::Table)
+                                .and_where(Expr::col(Comment::Uri).eq(target_uri))
+                                .take(),
+                        ),
+                    )
+                    .add(
+                        Expr::col(Self::PostUri).in_subquery(
+                            sea_query::Query::select()
+                                .column(Reply::Post)
+                                .from(Reply::Table)
+                                .and_where(Expr::col(Reply::Uri).eq(target_uri))
+                                .take(),
+                        ),
+                    ),
+            )
+            .limit(1)
+            .take()
+    }
+
+    pub async fn is_muted(db: &Pool<Postgres>, did: &str, target_uri: &str) -> bool {
+        let (sql, values) = Self::build_muted(did, target_uri).build_sqlx(PostgresQueryBuilder);
+        db::fetch_optional::<(String,), _>(db, &sql, values)
+            .await
+            .map_err(|e| error!("exec sql failed: {e}"))
+            .ok()
+            .flatten()
+            .is_some()
+    }
+}
+
+#[test]
+fn muted_covers_the_whole_thread() {
+    let sql = ThreadMute::build_muted("did:ckb:alice", "at://did:ckb:bob/app.bbs.comment/3kabc")
+        .to_string(PostgresQueryBuilder);
+    assert!(sql.starts_with(
+        "SELECT \"post_uri\" FROM \"thread_mute\" WHERE \"did\" = 'did:ckb:alice' AND ("
+    ));
+    assert!(sql.contains(
+        "\"post_uri\" IN (SELECT \"post\" FROM \"comment\" WHERE \"uri\" = 'at://did:ckb:bob/app.bbs.comment/3kabc')"
+    ));
+    assert!(sql.contains(
+        "\"post_uri\" IN (SELECT \"post\" FROM \"reply\" WHERE \"uri\" = 'at://did:ckb:bob/app.bbs.comment/3kabc')"
+    ));
+}
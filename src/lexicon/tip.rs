@@ -1,6 +1,48 @@
 use chrono::{DateTime, Local};
+use color_eyre::{
+    Result,
+    eyre::{bail, eyre},
+};
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, Index, OnConflict, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
 use serde::Serialize;
 use serde_json::Value;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::{
+    config::TipExpiry,
+    db,
+    lexicon::{
+        notify::{Notify, NotifyType},
+        removed_repo::RemovedRepo,
+    },
+};
+
+/// Every amount of tips, donations and their notifications is in shannons.
+pub const SHANNONS_PER_CKB: i64 = 100_000_000;
+/// The genesis CKB plus all of the primary issuance; no amount is larger.
+pub const MAX_SHANNONS: i64 = 67_200_000_000 * SHANNONS_PER_CKB;
+
+/// Parses an amount given as a decimal string of whole shannons, from one
+/// shannon up to `MAX_SHANNONS`.
+pub fn parse_shannons(amount: &str) -> Result<i64> {
+    let shannons: Decimal = amount
+        .parse()
+        .map_err(|_| eyre!("amount is not a number: {amount}"))?;
+    if !shannons.fract().is_zero() {
+        bail!("amount must be whole shannons: {amount}");
+    }
+    match shannons.to_i64() {
+        Some(shannons) if (1..=MAX_SHANNONS).contains(&shannons) => Ok(shannons),
+        _ => bail!("amount must be from 1 to {MAX_SHANNONS} shannons: {amount}"),
+    }
+}
+
+/// The amount in CKB with all 8 decimal places, for display.
+pub fn shannons_to_ckb(shannons: i64) -> String {
+    Decimal::new(shannons, 8).to_string()
+}
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
@@ -17,7 +59,405 @@ pub enum TipCategory {
     Donate = 1,
 }
 
-#[derive(sqlx::FromRow, Debug, Serialize)]
+#[derive(Iden)]
+pub enum Tip {
+    Table,
+    Id,
+    Category,
+    Sender,
+    SenderDid,
+    Receiver,
+    ReceiverDid,
+    Amount,
+    Info,
+    State,
+    TxHash,
+    /// Chosen by the sender so a retried prepare is not a second tip; freed
+    /// when the tip times out.
+    IdempotencyKey,
+    Updated,
+    Created,
+}
+
+impl Tip {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Self::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(Self::Category).integer().not_null())
+            .col(ColumnDef::new(Self::Sender).string().not_null())
+            .col(ColumnDef::new(Self::SenderDid).string().not_null())
+            .col(ColumnDef::new(Self::Receiver).string().not_null())
+            .col(ColumnDef::new(Self::ReceiverDid).string().not_null())
+            .col(ColumnDef::new(Self::Amount).big_integer().not_null())
+            .col(ColumnDef::new(Self::Info).string().not_null())
+            .col(ColumnDef::new(Self::State).integer().not_null())
+            .col(ColumnDef::new(Self::TxHash).string())
+            .col(
+                ColumnDef::new(Self::Updated)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
+        let sql = sea_query::Table::alter()
+            .table(Self::Table)
+            .add_column_if_not_exists(ColumnDef::new(Self::IdempotencyKey).string())
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
+        // NULL keys, of tips without one or timed out, never conflict
+        let sql = Index::create()
+            .if_not_exists()
+            .name("tip_idempotency_key")
+            .table(Self::Table)
+            .col(Self::SenderDid)
+            .col(Self::IdempotencyKey)
+            .unique()
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
+        Ok(())
+    }
+
+    /// Converts the amounts of a deployment that stored them in CKB to
+    /// shannons. The conversion is recorded in `amount_migration`, so later
+    /// starts leave the amounts alone.
+    pub async fn migrate_ckb_amounts(db: &Pool<Postgres>) -> Result<()> {
+        let mut tx = db.begin().await?;
+        tx.execute(query(
+            "CREATE TABLE IF NOT EXISTS \"amount_migration\" (\"migrated\" timestamptz NOT NULL DEFAULT now())",
+        ))
+        .await?;
+        // a second appview starting meanwhile waits here, then sees the record
+        tx.execute(query("LOCK TABLE \"amount_migration\"")).await?;
+        let (done,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM \"amount_migration\"")
+            .fetch_one(&mut *tx)
+            .await?;
+        if done > 0 {
+            return Ok(());
+        }
+        for sql in Self::build_ckb_amount_migration() {
+            tx.execute(query(&sql)).await?;
+        }
+        tx.execute(query("INSERT INTO \"amount_migration\" DEFAULT VALUES"))
+            .await?;
+        tx.commit().await?;
+        info!("converted the tip and notify amounts from CKB to shannons");
+        Ok(())
+    }
+
+    /// Tips, and the notifications of tips and donations, carry amounts.
+    pub fn build_ckb_amount_migration() -> [String; 2] {
+        [
+            sea_query::Query::update()
+                .table(Self::Table)
+                .value(Self::Amount, Expr::col(Self::Amount).mul(SHANNONS_PER_CKB))
+                .to_string(PostgresQueryBuilder),
+            sea_query::Query::update()
+                .table(Notify::Table)
+                .value(
+                    Notify::Amount,
+                    Expr::col(Notify::Amount).mul(SHANNONS_PER_CKB),
+                )
+                .and_where(
+                    Expr::col(Notify::NType)
+                        .is_in([NotifyType::NewTip as i32, NotifyType::NewDonate as i32]),
+                )
+                .to_string(PostgresQueryBuilder),
+        ]
+    }
+
+    pub fn build_select() -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .columns([
+                Tip::Id,
+                Tip::Category,
+                Tip::Sender,
+                Tip::SenderDid,
+                Tip::Receiver,
+                Tip::ReceiverDid,
+                Tip::Amount,
+                Tip::Info,
+                Tip::State,
+                Tip::TxHash,
+                Tip::IdempotencyKey,
+                Tip::Updated,
+                Tip::Created,
+            ])
+            .from(Tip::Table)
+            .take()
+    }
+
+    /// Prepared, not yet transferred tips sent or received by `did`, still
+    /// resumable under `expiry`.
+    pub fn build_pending_select(did: &str, expiry: &TipExpiry) -> sea_query::SelectStatement {
+        Self::build_select()
+            .and_where(
+                Expr::col(Tip::SenderDid)
+                    .eq(did)
+                    .or(Expr::col(Tip::ReceiverDid).eq(did)),
+            )
+            .and_where(Expr::col(Tip::State).eq(TipState::Prepared as i32))
+            .and_where(Expr::col(Tip::Created).gt(Expr::cust(format!(
+                "now() - interval '{} minutes'",
+                expiry.pending_minutes
+            ))))
+            .order_by(Tip::Created, sea_query::Order::Desc)
+            .take()
+    }
+
+    /// Prepared tips that were never transferred and can no longer resume.
+    pub fn build_stale_select(expiry: &TipExpiry) -> sea_query::SelectStatement {
+        Self::build_select()
+            .and_where(Expr::col(Tip::State).eq(TipState::Prepared as i32))
+            .and_where(Expr::col(Tip::Created).lte(Expr::cust(format!(
+                "now() - interval '{} minutes'",
+                expiry.pending_minutes
+            ))))
+            .take()
+    }
+
+    /// Times out the tip while it is still prepared, freeing its
+    /// idempotency key.
+    pub fn build_expire(id: i32) -> sea_query::UpdateStatement {
+        sea_query::Query::update()
+            .table(Self::Table)
+            .values([
+                (Self::State, (TipState::Timeout as i32).into()),
+                (Self::IdempotencyKey, Option::<String>::None.into()),
+                (Self::Updated, Expr::current_timestamp()),
+            ])
+            .and_where(Expr::col(Self::Id).eq(id))
+            .and_where(Expr::col(Self::State).eq(TipState::Prepared as i32))
+            .take()
+    }
+
+    /// Whether this call timed the tip out, rather than an earlier one or
+    /// its transfer getting there first.
+    pub async fn expire(db: &Pool<Postgres>, id: i32) -> Result<bool> {
+        let (sql, values) = Self::build_expire(id).build_sqlx(PostgresQueryBuilder);
+        Ok(db::execute(db, &sql, values).await?.rows_affected() > 0)
+    }
+
+    /// Timed out tips past the retention.
+    pub fn build_purge(expiry: &TipExpiry) -> sea_query::DeleteStatement {
+        sea_query::Query::delete()
+            .from_table(Self::Table)
+            .and_where(Expr::col(Self::State).eq(TipState::Timeout as i32))
+            .and_where(Expr::col(Self::Updated).lte(Expr::cust(format!(
+                "now() - interval '{} days'",
+                expiry.retention_days
+            ))))
+            .take()
+    }
+
+    /// Deletes the timed out tips past the retention; returns how many.
+    pub async fn purge(db: &Pool<Postgres>, expiry: &TipExpiry) -> Result<u64> {
+        let (sql, values) = Self::build_purge(expiry).build_sqlx(PostgresQueryBuilder);
+        Ok(db::execute(db, &sql, values).await?.rows_affected())
+    }
+
+    /// The tip `sender_did` prepared with the idempotency key, while the key
+    /// is taken.
+    pub async fn select_by_idempotency_key(
+        db: &Pool<Postgres>,
+        sender_did: &str,
+        key: &str,
+    ) -> Result<Option<TipRow>> {
+        let (sql, values) = Self::build_select()
+            .and_where(Expr::col(Self::SenderDid).eq(sender_did))
+            .and_where(Expr::col(Self::IdempotencyKey).eq(key))
+            .build_sqlx(PostgresQueryBuilder);
+        Ok(db::fetch_optional(db, &sql, values).await?)
+    }
+
+    /// A page of the committed tips matching `filter`, newest first: what the
+    /// tip lists fall back to while micro_pay can't page them.
+    pub fn build_committed_page(
+        filter: Expr,
+        limit: u64,
+        offset: u64,
+    ) -> sea_query::SelectStatement {
+        Self::build_select()
+            .and_where(filter)
+            .and_where(Expr::col(Tip::State).eq(TipState::Committed as i32))
+            .order_by(Tip::Created, sea_query::Order::Desc)
+            .limit(limit)
+            .offset(offset)
+            .take()
+    }
+
+    /// A page of the committed tips of the `nsid/uri` in `info`, newest
+    /// first, without the ones of senders who removed their data. `before`
+    /// is the id of the last tip of the previous page.
+    pub fn build_engagement(
+        info: &str,
+        before: Option<i32>,
+        limit: u64,
+    ) -> sea_query::SelectStatement {
+        Self::build_select()
+            .and_where(Expr::col(Tip::Info).eq(info))
+            .and_where(Expr::col(Tip::Category).eq(TipCategory::Tip as i32))
+            .and_where(Expr::col(Tip::State).eq(TipState::Committed as i32))
+            .and_where(Expr::col(Tip::SenderDid).not_in_subquery(RemovedRepo::build_suppressed()))
+            .and_where_option(before.map(|id| Expr::col(Tip::Id).lt(id)))
+            .order_by(Tip::Id, sea_query::Order::Desc)
+            .limit(limit)
+            .take()
+    }
+
+    pub fn build_committed_count(filter: Expr) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .expr(Expr::col(Tip::Id).count())
+            .from(Tip::Table)
+            .and_where(filter)
+            .and_where(Expr::col(Tip::State).eq(TipState::Committed as i32))
+            .take()
+    }
+
+    /// Total amount of the committed tips `did` received.
+    pub fn build_received_total(did: &str) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .expr(Expr::cust("CAST(COALESCE(SUM(\"amount\"), 0) AS BIGINT)"))
+            .from(Tip::Table)
+            .and_where(Expr::col(Tip::ReceiverDid).eq(did))
+            .and_where(Expr::col(Tip::Category).eq(TipCategory::Tip as i32))
+            .and_where(Expr::col(Tip::State).eq(TipState::Committed as i32))
+            .take()
+    }
+
+    /// Distinct senders of the committed tips `did` received.
+    pub fn build_supporter_count(did: &str) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .expr(Expr::col(Tip::SenderDid).count_distinct())
+            .from(Tip::Table)
+            .and_where(Expr::col(Tip::ReceiverDid).eq(did))
+            .and_where(Expr::col(Tip::Category).eq(TipCategory::Tip as i32))
+            .and_where(Expr::col(Tip::State).eq(TipState::Committed as i32))
+            .take()
+    }
+
+    /// The `info` of what `did` received the most committed tips for, with
+    /// their total amount.
+    pub fn build_top_target(did: &str) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .column(Tip::Info)
+            .expr(Expr::cust("CAST(SUM(\"amount\") AS BIGINT)"))
+            .from(Tip::Table)
+            .and_where(Expr::col(Tip::ReceiverDid).eq(did))
+            .and_where(Expr::col(Tip::Category).eq(TipCategory::Tip as i32))
+            .and_where(Expr::col(Tip::State).eq(TipState::Committed as i32))
+            .group_by_col(Tip::Info)
+            .order_by_expr(Expr::cust("2"), sea_query::Order::Desc)
+            .order_by(Tip::Info, sea_query::Order::Asc)
+            .limit(1)
+            .take()
+    }
+
+    /// Tip receivers by the committed amount they received, most first.
+    pub fn build_leaderboard() -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .column(Tip::ReceiverDid)
+            .expr(Expr::cust("CAST(SUM(\"amount\") AS BIGINT)"))
+            .from(Tip::Table)
+            .and_where(Expr::col(Tip::Category).eq(TipCategory::Tip as i32))
+            .and_where(Expr::col(Tip::State).eq(TipState::Committed as i32))
+            .group_by_col(Tip::ReceiverDid)
+            .order_by_expr(Expr::cust("2"), sea_query::Order::Desc)
+            .order_by(Tip::ReceiverDid, sea_query::Order::Asc)
+            .take()
+    }
+
+    /// Inserts the tip; nothing is inserted when its sender's idempotency
+    /// key is taken.
+    pub fn build_insert(tip: &TipRow) -> Result<sea_query::InsertStatement> {
+        Ok(sea_query::Query::insert()
+            .into_table(Tip::Table)
+            .columns([
+                Tip::Category,
+                Tip::Sender,
+                Tip::SenderDid,
+                Tip::Receiver,
+                Tip::ReceiverDid,
+                Tip::Amount,
+                Tip::Info,
+                Tip::State,
+                Tip::TxHash,
+                Tip::IdempotencyKey,
+            ])
+            .values([
+                tip.category.into(),
+                tip.sender.clone().into(),
+                tip.sender_did.clone().into(),
+                tip.receiver.clone().into(),
+                tip.receiver_did.clone().into(),
+                tip.amount.into(),
+                tip.info.clone().into(),
+                tip.state.into(),
+                tip.tx_hash.clone().into(),
+                tip.idempotency_key.clone().into(),
+            ])?
+            .on_conflict(
+                OnConflict::columns([Tip::SenderDid, Tip::IdempotencyKey])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .returning_col(Self::Id)
+            .take())
+    }
+
+    /// The id of the inserted tip, `None` when its idempotency key is taken.
+    pub async fn insert(db: &Pool<Postgres>, tip: &TipRow) -> Result<Option<i32>> {
+        let (sql, values) = Self::build_insert(tip)?.build_sqlx(PostgresQueryBuilder);
+        let id: Option<(i32,)> = db::fetch_optional(db, &sql, values).await?;
+        Ok(id.map(|(id,)| id))
+    }
+
+    /// Records the payment micro_pay prepared for the tip.
+    pub async fn set_tx_hash(db: &Pool<Postgres>, id: i32, tx_hash: &str) -> Result<()> {
+        let (sql, values) = sea_query::Query::update()
+            .table(Self::Table)
+            .values([
+                (Self::TxHash, tx_hash.into()),
+                (Self::Updated, Expr::current_timestamp()),
+            ])
+            .and_where(Expr::col(Self::Id).eq(id))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    pub async fn update_state(db: &Pool<Postgres>, tx_hash: &str, state: TipState) -> Result<()> {
+        let (sql, values) = sea_query::Query::update()
+            .table(Self::Table)
+            .values([
+                (Self::State, (state as i32).into()),
+                (Self::Updated, Expr::current_timestamp()),
+            ])
+            .and_where(Expr::col(Self::TxHash).eq(tx_hash))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, Serialize)]
 #[allow(dead_code)]
 pub struct TipRow {
     pub id: i32,
@@ -26,10 +466,12 @@ pub struct TipRow {
     pub sender_did: String,
     pub receiver: String,
     pub receiver_did: String,
+    /// In shannons.
     pub amount: i64,
     pub info: String,
     pub state: i32,
     pub tx_hash: Option<String>,
+    pub idempotency_key: Option<String>,
     pub updated: DateTime<Local>,
     pub created: DateTime<Local>,
 }
@@ -37,6 +479,7 @@ pub struct TipRow {
 #[derive(Debug, Serialize)]
 #[allow(dead_code)]
 pub struct TipView {
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub id: String,
     pub category: String,
     pub sender: String,
@@ -44,7 +487,11 @@ pub struct TipView {
     pub sender_author: Value,
     pub receiver: String,
     pub receiver_did: String,
+    /// In shannons.
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub amount: String,
+    /// `amount` in CKB, with 8 decimal places.
+    pub amount_ckb: String,
     pub info: String,
     pub state: String,
     pub tx_hash: Option<String>,
@@ -55,6 +502,7 @@ pub struct TipView {
 #[derive(Debug, Serialize)]
 #[allow(dead_code)]
 pub struct TipDetailView {
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub id: String,
     pub category: String,
     pub sender: String,
@@ -63,7 +511,11 @@ pub struct TipDetailView {
     pub receiver: String,
     pub receiver_did: String,
     pub receiver_author: Value,
+    /// In shannons.
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
     pub amount: String,
+    /// `amount` in CKB, with 8 decimal places.
+    pub amount_ckb: String,
     pub info: String,
     pub source: Value,
     pub state: String,
@@ -71,3 +523,222 @@ pub struct TipDetailView {
     pub updated: DateTime<Local>,
     pub created: DateTime<Local>,
 }
+
+/// A committed local tip in the camelCase shape of a micro_pay payment, so
+/// the pages served while micro_pay can't list them read the same.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentItem {
+    pub sender: String,
+    pub sender_did: String,
+    pub receiver: String,
+    pub receiver_did: String,
+    pub category: i32,
+    /// In shannons.
+    pub amount: String,
+    pub info: String,
+    pub tx_hash: Option<String>,
+    pub created_at: DateTime<Local>,
+}
+
+impl From<TipRow> for PaymentItem {
+    fn from(row: TipRow) -> Self {
+        Self {
+            sender: row.sender,
+            sender_did: row.sender_did,
+            receiver: row.receiver,
+            receiver_did: row.receiver_did,
+            category: row.category,
+            amount: row.amount.to_string(),
+            info: row.info,
+            tx_hash: row.tx_hash,
+            created_at: row.created,
+        }
+    }
+}
+
+#[test]
+fn stale_tips_are_prepared_and_expired() {
+    let sql = Tip::build_stale_select(&TipExpiry::default()).to_string(PostgresQueryBuilder);
+    assert!(sql.contains(&format!("\"state\" = {}", TipState::Prepared as i32)));
+    assert!(sql.contains("\"created\" <= (now() - interval '30 minutes')"));
+}
+
+#[test]
+fn pending_tips_are_prepared_and_recent() {
+    let sql = Tip::build_pending_select("did:ckb:alice", &TipExpiry::default())
+        .to_string(PostgresQueryBuilder);
+    assert!(
+        sql.contains("(\"sender_did\" = 'did:ckb:alice' OR \"receiver_did\" = 'did:ckb:alice')")
+    );
+    assert!(sql.contains(&format!("\"state\" = {}", TipState::Prepared as i32)));
+    assert!(sql.contains("\"created\" > (now() - interval '30 minutes')"));
+}
+
+#[test]
+fn received_total_counts_committed_tips() {
+    let sql = Tip::build_received_total("did:ckb:alice").to_string(PostgresQueryBuilder);
+    assert!(sql.starts_with("SELECT CAST(COALESCE(SUM(\"amount\"), 0) AS BIGINT) FROM \"tip\""));
+    assert!(sql.contains("\"receiver_did\" = 'did:ckb:alice'"));
+    assert!(sql.contains(&format!("\"category\" = {}", TipCategory::Tip as i32)));
+    assert!(sql.contains(&format!("\"state\" = {}", TipState::Committed as i32)));
+}
+
+#[test]
+fn committed_page_is_newest_first() {
+    let sql = Tip::build_committed_page(Expr::col(Tip::SenderDid).eq("did:ckb:alice"), 20, 40)
+        .to_string(PostgresQueryBuilder);
+    assert!(sql.contains(&format!(
+        "WHERE \"sender_did\" = 'did:ckb:alice' AND \"state\" = {}",
+        TipState::Committed as i32
+    )));
+    assert!(sql.ends_with("ORDER BY \"created\" DESC LIMIT 20 OFFSET 40"));
+}
+
+#[test]
+fn stats_aggregate_committed_tips() {
+    let committed = format!(
+        "\"category\" = {} AND \"state\" = {}",
+        TipCategory::Tip as i32,
+        TipState::Committed as i32
+    );
+    let sql = Tip::build_supporter_count("did:ckb:alice").to_string(PostgresQueryBuilder);
+    assert!(sql.starts_with("SELECT COUNT(DISTINCT \"sender_did\") FROM \"tip\""));
+    assert!(sql.ends_with(&format!(
+        "WHERE \"receiver_did\" = 'did:ckb:alice' AND {committed}"
+    )));
+    let sql = Tip::build_top_target("did:ckb:alice").to_string(PostgresQueryBuilder);
+    assert!(sql.ends_with(&format!(
+        "WHERE \"receiver_did\" = 'did:ckb:alice' AND {committed} GROUP BY \"info\" ORDER BY 2 DESC, \"info\" ASC LIMIT 1"
+    )));
+    let sql = Tip::build_leaderboard().to_string(PostgresQueryBuilder);
+    assert!(sql.ends_with(&format!(
+        "WHERE {committed} GROUP BY \"receiver_did\" ORDER BY 2 DESC, \"receiver_did\" ASC"
+    )));
+}
+
+#[test]
+fn amounts_format_as_ckb() {
+    assert_eq!(shannons_to_ckb(0), "0.00000000");
+    assert_eq!(shannons_to_ckb(1), "0.00000001");
+    assert_eq!(shannons_to_ckb(SHANNONS_PER_CKB), "1.00000000");
+    assert_eq!(shannons_to_ckb(10_000_000_000), "100.00000000");
+    assert_eq!(shannons_to_ckb(12_345_678_901), "123.45678901");
+    assert_eq!(shannons_to_ckb(MAX_SHANNONS), "67200000000.00000000");
+}
+
+#[test]
+fn amounts_are_whole_shannons_in_supply() {
+    assert_eq!(parse_shannons("1").unwrap(), 1);
+    assert_eq!(parse_shannons("10000000000").unwrap(), 10_000_000_000);
+    assert_eq!(parse_shannons("100.0").unwrap(), 100);
+    assert_eq!(
+        parse_shannons(&MAX_SHANNONS.to_string()).unwrap(),
+        MAX_SHANNONS
+    );
+    for amount in [
+        "",
+        "ten",
+        "1.5",
+        "0.00000001",
+        "0",
+        "-100",
+        "6720000000000000001",
+        "99999999999999999999",
+    ] {
+        assert!(parse_shannons(amount).is_err(), "{amount}");
+    }
+}
+
+#[test]
+fn ckb_amounts_migrate_to_shannons() {
+    let [tips, notifies] = Tip::build_ckb_amount_migration();
+    assert_eq!(
+        tips,
+        "UPDATE \"tip\" SET \"amount\" = \"amount\" * 100000000"
+    );
+    assert_eq!(
+        notifies,
+        "UPDATE \"notify\" SET \"amount\" = \"amount\" * 100000000 WHERE \"n_type\" IN (3, 4)"
+    );
+}
+
+#[test]
+fn engagement_tips_leave_out_removed_senders() {
+    let sql = Tip::build_engagement(
+        "app.bbs.post/at://did:ckb:alice/app.bbs.post/1",
+        Some(42),
+        20,
+    )
+    .to_string(PostgresQueryBuilder);
+    assert!(sql.contains("\"info\" = 'app.bbs.post/at://did:ckb:alice/app.bbs.post/1'"));
+    assert!(sql.contains(&format!("\"category\" = {}", TipCategory::Tip as i32)));
+    assert!(sql.contains(&format!("\"state\" = {}", TipState::Committed as i32)));
+    assert!(sql.contains(
+        "\"sender_did\" NOT IN (SELECT \"did\" FROM \"removed_repo\" WHERE \"suppress_until\" > CURRENT_TIMESTAMP)"
+    ));
+    assert!(sql.ends_with("\"id\" < 42 ORDER BY \"id\" DESC LIMIT 20"));
+}
+
+#[test]
+fn expiry_frees_the_key_of_prepared_tips_only() {
+    let sql = Tip::build_expire(7).to_string(PostgresQueryBuilder);
+    assert!(sql.contains(&format!(
+        "SET \"state\" = {}, \"idempotency_key\" = NULL",
+        TipState::Timeout as i32
+    )));
+    assert!(sql.ends_with(&format!(
+        "WHERE \"id\" = 7 AND \"state\" = {}",
+        TipState::Prepared as i32
+    )));
+
+    let expiry = TipExpiry {
+        pending_minutes: 30,
+        retention_days: 7,
+    };
+    let sql = Tip::build_purge(&expiry).to_string(PostgresQueryBuilder);
+    assert_eq!(
+        sql,
+        format!(
+            "DELETE FROM \"tip\" WHERE \"state\" = {} AND \"updated\" <= (now() - interval '7 days')",
+            TipState::Timeout as i32
+        )
+    );
+}
+
+#[test]
+fn local_tips_page_like_micro_pay() {
+    let item = serde_json::to_value(PaymentItem::from(TipRow {
+        id: 1,
+        category: TipCategory::Tip as i32,
+        sender: "ckt1bob".to_string(),
+        sender_did: "did:ckb:bob".to_string(),
+        receiver: "ckt1alice".to_string(),
+        receiver_did: "did:ckb:alice".to_string(),
+        amount: 100,
+        info: "app.bbs.post/at://did:ckb:alice/app.bbs.post/1".to_string(),
+        state: TipState::Committed as i32,
+        tx_hash: Some("tx1".to_string()),
+        idempotency_key: None,
+        updated: Local::now(),
+        created: Local::now(),
+    }))
+    .unwrap();
+    let mut keys = item.as_object().unwrap().keys().collect::<Vec<_>>();
+    keys.sort();
+    assert_eq!(
+        keys,
+        [
+            "amount",
+            "category",
+            "createdAt",
+            "info",
+            "receiver",
+            "receiverDid",
+            "sender",
+            "senderDid",
+            "txHash"
+        ]
+    );
+    assert_eq!(item["amount"], "100");
+}
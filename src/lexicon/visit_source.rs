@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use color_eyre::Result;
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, Index, OnConflict, Order, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde_json::{Map, Value, json};
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::db;
+
+/// Days of visit sources kept by `prune`.
+pub const RETENTION_DAYS: i32 = 90;
+
+/// Where a visit of a post came from, as told by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisitSource {
+    Direct = 0,
+    Feed = 1,
+    Search = 2,
+    Notification = 3,
+    External = 4,
+}
+
+impl VisitSource {
+    pub const ALL: [Self; 5] = [
+        Self::Direct,
+        Self::Feed,
+        Self::Search,
+        Self::Notification,
+        Self::External,
+    ];
+
+    /// Unknown and missing sources count as direct.
+    pub fn parse(source: Option<&str>) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|s| source == Some(s.name()))
+            .unwrap_or(Self::Direct)
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Direct => "direct",
+            Self::Feed => "feed",
+            Self::Search => "search",
+            Self::Notification => "notification",
+            Self::External => "external",
+        }
+    }
+
+    fn from_i32(source: i32) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|s| *s as i32 == source)
+            .unwrap_or(Self::Direct)
+    }
+}
+
+/// Visits per post, source and day.
+#[derive(Iden)]
+pub enum PostVisitSource {
+    Table,
+    Post,
+    Source,
+    Day,
+    Count,
+}
+
+impl PostVisitSource {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(ColumnDef::new(Self::Post).string().not_null())
+            .col(ColumnDef::new(Self::Source).integer().not_null())
+            .col(ColumnDef::new(Self::Day).date().not_null())
+            .col(
+                ColumnDef::new(Self::Count)
+                    .big_integer()
+                    .not_null()
+                    .default(0),
+            )
+            .primary_key(
+                Index::create()
+                    .col(Self::Post)
+                    .col(Self::Source)
+                    .col(Self::Day),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+        Ok(())
+    }
+
+    /// Counts one visit of `post` from `source` today.
+    pub fn build_record(post: &str, source: VisitSource) -> Result<sea_query::InsertStatement> {
+        Ok(sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([Self::Post, Self::Source, Self::Day, Self::Count])
+            .values([
+                post.into(),
+                (source as i32).into(),
+                Expr::current_date(),
+                1.into(),
+            ])?
+            .on_conflict(
+                OnConflict::columns([Self::Post, Self::Source, Self::Day])
+                    .value(Self::Count, Expr::col((Self::Table, Self::Count)).add(1))
+                    .to_owned(),
+            )
+            .take())
+    }
+
+    pub async fn record(db: &Pool<Postgres>, post: &str, source: VisitSource) -> Result<()> {
+        let (sql, values) = Self::build_record(post, source)?.build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    /// The counts of `post` over the last `days` days, today included.
+    pub fn build_select(post: &str, days: i32) -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .columns([Self::Day, Self::Source, Self::Count])
+            .from(Self::Table)
+            .and_where(Expr::col(Self::Post).eq(post))
+            .and_where(Expr::col(Self::Day).gt(Expr::cust_with_values("current_date - $1", [days])))
+            .order_by(Self::Day, Order::Asc)
+            .order_by(Self::Source, Order::Asc)
+            .take()
+    }
+
+    pub fn build_prune() -> sea_query::DeleteStatement {
+        sea_query::Query::delete()
+            .from_table(Self::Table)
+            .and_where(Expr::col(Self::Day).lte(Expr::cust_with_values(
+                "current_date - $1",
+                [RETENTION_DAYS],
+            )))
+            .take()
+    }
+
+    /// Drops the counts older than `RETENTION_DAYS`.
+    pub async fn prune(db: &Pool<Postgres>) -> Result<()> {
+        let (sql, values) = Self::build_prune().build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct VisitSourceRow {
+    pub day: NaiveDate,
+    pub source: i32,
+    pub count: i64,
+}
+
+/// `{ "total", "sources", "days" }`: visits per source, every source
+/// listed, and per day with the sources seen that day, oldest day first.
+pub fn breakdown(rows: &[VisitSourceRow]) -> Value {
+    let mut sources: Map<String, Value> = VisitSource::ALL
+        .into_iter()
+        .map(|s| (s.name().to_string(), json!(0)))
+        .collect();
+    let mut days: BTreeMap<NaiveDate, (i64, Map<String, Value>)> = BTreeMap::new();
+    let mut total = 0;
+    for row in rows {
+        let name = VisitSource::from_i32(row.source).name();
+        total += row.count;
+        sources[name] = json!(sources[name].as_i64().unwrap_or(0) + row.count);
+        let (day_total, day_sources) = days.entry(row.day).or_default();
+        *day_total += row.count;
+        let count = day_sources.get(name).and_then(Value::as_i64).unwrap_or(0);
+        day_sources.insert(name.to_string(), json!(count + row.count));
+    }
+    json!({
+        "total": total,
+        "sources": sources,
+        "days": days
+            .into_iter()
+            .map(|(day, (total, sources))| json!({
+                "day": day.to_string(),
+                "total": total,
+                "sources": sources,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_sources_are_direct() {
+        assert_eq!(VisitSource::parse(Some("search")), VisitSource::Search);
+        assert_eq!(VisitSource::parse(Some("Search")), VisitSource::Direct);
+        assert_eq!(VisitSource::parse(Some("newsletter")), VisitSource::Direct);
+        assert_eq!(VisitSource::parse(None), VisitSource::Direct);
+    }
+
+    #[test]
+    fn visits_are_counted_per_day() {
+        let uri = "at://did:ckb:alice/app.bbs.post/1";
+        let sql = PostVisitSource::build_record(uri, VisitSource::Feed)
+            .unwrap()
+            .to_string(PostgresQueryBuilder);
+        assert_eq!(
+            sql,
+            "INSERT INTO \"post_visit_source\" (\"post\", \"source\", \"day\", \"count\") VALUES ('at://did:ckb:alice/app.bbs.post/1', 1, CURRENT_DATE, 1) ON CONFLICT (\"post\", \"source\", \"day\") DO UPDATE SET \"count\" = \"post_visit_source\".\"count\" + 1"
+        );
+        let sql = PostVisitSource::build_prune().to_string(PostgresQueryBuilder);
+        assert!(sql.ends_with("WHERE \"day\" <= current_date - 90"));
+    }
+
+    #[test]
+    fn breakdown_per_source_and_day() {
+        let day = |d: u32| NaiveDate::from_ymd_opt(2026, 10, d).unwrap();
+        let rows = [
+            VisitSourceRow {
+                day: day(1),
+                source: VisitSource::Feed as i32,
+                count: 3,
+            },
+            VisitSourceRow {
+                day: day(1),
+                source: VisitSource::Search as i32,
+                count: 1,
+            },
+            VisitSourceRow {
+                day: day(2),
+                source: VisitSource::Feed as i32,
+                count: 2,
+            },
+            // a source this build does not know
+            VisitSourceRow {
+                day: day(2),
+                source: 42,
+                count: 5,
+            },
+        ];
+        assert_eq!(
+            breakdown(&rows),
+            json!({
+                "total": 11,
+                "sources": {
+                    "direct": 5, "feed": 5, "search": 1, "notification": 0, "external": 0,
+                },
+                "days": [
+                    { "day": "2026-10-01", "total": 4, "sources": { "feed": 3, "search": 1 } },
+                    { "day": "2026-10-02", "total": 7, "sources": { "feed": 2, "direct": 5 } },
+                ],
+            })
+        );
+        assert_eq!(
+            breakdown(&[])["sources"]["feed"],
+            json!(0),
+            "every source is listed"
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use chrono::{DateTime, Local};
+use color_eyre::Result;
+use sea_query::{ColumnDef, Expr, ExprTrait, Iden, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
+use serde::Serialize;
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::db;
+
+/// Endpoints are disabled after this many deliveries in a row failed.
+pub const DISABLE_AFTER_FAILURES: i32 = 10;
+
+/// Forum events a webhook can subscribe to. The discriminants are bits of the
+/// `events` mask stored with each webhook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    PostCreated = 1,
+    CommentCreated = 2,
+    ReplyCreated = 4,
+    Moderated = 8,
+}
+
+impl WebhookEvent {
+    pub const fn mask(self) -> i32 {
+        self as i32
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            WebhookEvent::PostCreated => "post_created",
+            WebhookEvent::CommentCreated => "comment_created",
+            WebhookEvent::ReplyCreated => "reply_created",
+            WebhookEvent::Moderated => "moderated",
+        }
+    }
+}
+
+#[derive(Iden)]
+pub enum Webhook {
+    Table,
+    Id,
+    Url,
+    Secret,
+    Events,
+    SectionId,
+    Enabled,
+    Failures,
+    Updated,
+    Created,
+}
+
+impl Webhook {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Self::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(Self::Url).string().not_null())
+            .col(ColumnDef::new(Self::Secret).string().not_null())
+            .col(ColumnDef::new(Self::Events).integer().not_null())
+            .col(
+                ColumnDef::new(Self::SectionId)
+                    .integer()
+                    .not_null()
+                    .default(0),
+            )
+            .col(
+                ColumnDef::new(Self::Enabled)
+                    .boolean()
+                    .not_null()
+                    .default(true),
+            )
+            .col(
+                ColumnDef::new(Self::Failures)
+                    .integer()
+                    .not_null()
+                    .default(0),
+            )
+            .col(
+                ColumnDef::new(Self::Updated)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
+        Ok(())
+    }
+
+    pub fn build_select() -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .columns([
+                Webhook::Id,
+                Webhook::Url,
+                Webhook::Secret,
+                Webhook::Events,
+                Webhook::SectionId,
+                Webhook::Enabled,
+                Webhook::Failures,
+                Webhook::Updated,
+                Webhook::Created,
+            ])
+            .from(Webhook::Table)
+            .take()
+    }
+
+    /// Enabled webhooks subscribed to `event` in `section_id`. Section 0
+    /// subscribes to every section.
+    pub fn build_matching(event: WebhookEvent, section_id: i32) -> sea_query::SelectStatement {
+        Self::build_select()
+            .and_where(Expr::col(Webhook::Enabled).eq(true))
+            .and_where(Expr::cust_with_values(
+                "(\"events\" & $1) <> 0",
+                [event.mask()],
+            ))
+            .and_where(
+                Expr::col(Webhook::SectionId)
+                    .eq(0)
+                    .or(Expr::col(Webhook::SectionId).eq(section_id)),
+            )
+            .take()
+    }
+
+    pub async fn matching(
+        db: &Pool<Postgres>,
+        event: WebhookEvent,
+        section_id: i32,
+    ) -> Result<Vec<WebhookRow>> {
+        let (sql, values) =
+            Self::build_matching(event, section_id).build_sqlx(PostgresQueryBuilder);
+        Ok(db::fetch_all(db, &sql, values).await?)
+    }
+
+    pub async fn insert(
+        db: &Pool<Postgres>,
+        url: &str,
+        secret: &str,
+        events: i32,
+        section_id: i32,
+    ) -> Result<i32> {
+        let (sql, values) = sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([Self::Url, Self::Secret, Self::Events, Self::SectionId])
+            .values([url.into(), secret.into(), events.into(), section_id.into()])?
+            .returning_col(Self::Id)
+            .build_sqlx(PostgresQueryBuilder);
+
+        let (id,): (i32,) = db::fetch_one(db, &sql, values).await?;
+        Ok(id)
+    }
+
+    pub async fn update(
+        db: &Pool<Postgres>,
+        id: i32,
+        url: Option<String>,
+        secret: Option<String>,
+        events: Option<i32>,
+        section_id: Option<i32>,
+        enabled: Option<bool>,
+    ) -> Result<()> {
+        let mut values = vec![(Self::Updated, Expr::current_timestamp())];
+        if let Some(url) = url {
+            values.push((Self::Url, url.into()));
+        }
+        if let Some(secret) = secret {
+            values.push((Self::Secret, secret.into()));
+        }
+        if let Some(events) = events {
+            values.push((Self::Events, events.into()));
+        }
+        if let Some(section_id) = section_id {
+            values.push((Self::SectionId, section_id.into()));
+        }
+        if let Some(enabled) = enabled {
+            values.push((Self::Enabled, enabled.into()));
+            // re-enabling starts a new failure streak
+            if enabled {
+                values.push((Self::Failures, 0.into()));
+            }
+        }
+
+        let (sql, values) = sea_query::Query::update()
+            .table(Self::Table)
+            .values(values)
+            .and_where(Expr::col(Self::Id).eq(id))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+
+    pub async fn delete(db: &Pool<Postgres>, id: i32) -> Result<()> {
+        let mut tx = db.begin().await?;
+        let (sql, values) = sea_query::Query::delete()
+            .from_table(WebhookDelivery::Table)
+            .and_where(Expr::col(WebhookDelivery::WebhookId).eq(id))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(&mut *tx, &sql, values).await?;
+        let (sql, values) = sea_query::Query::delete()
+            .from_table(Self::Table)
+            .and_where(Expr::col(Self::Id).eq(id))
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(&mut *tx, &sql, values).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// A delivery resets the failure streak; a failed one extends it and
+    /// disables the webhook once it reaches `DISABLE_AFTER_FAILURES`.
+    pub fn build_record_result(id: i32, delivered: bool) -> sea_query::UpdateStatement {
+        let values = if delivered {
+            vec![(Self::Failures, 0.into())]
+        } else {
+            vec![
+                (Self::Failures, Expr::cust("\"failures\" + 1")),
+                (
+                    Self::Enabled,
+                    Expr::cust(format!("\"failures\" + 1 < {DISABLE_AFTER_FAILURES}")),
+                ),
+            ]
+        };
+        sea_query::Query::update()
+            .table(Self::Table)
+            .values(values)
+            .and_where(Expr::col(Self::Id).eq(id))
+            .take()
+    }
+
+    pub async fn record_result(db: &Pool<Postgres>, id: i32, delivered: bool) -> Result<()> {
+        let (sql, values) =
+            Self::build_record_result(id, delivered).build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, Serialize)]
+pub struct WebhookRow {
+    pub id: i32,
+    pub url: String,
+    pub secret: String,
+    pub events: i32,
+    pub section_id: i32,
+    pub enabled: bool,
+    pub failures: i32,
+    pub updated: DateTime<Local>,
+    pub created: DateTime<Local>,
+}
+
+/// `WebhookRow` without the signing secret.
+#[derive(Debug, Serialize)]
+pub struct WebhookView {
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    pub id: String,
+    pub url: String,
+    pub events: i32,
+    #[serde(serialize_with = "crate::lexicon::numeric::serialize")]
+    pub section_id: String,
+    pub enabled: bool,
+    pub failures: i32,
+    pub updated: DateTime<Local>,
+    pub created: DateTime<Local>,
+}
+
+impl WebhookView {
+    pub fn build(row: WebhookRow) -> Self {
+        Self {
+            id: row.id.to_string(),
+            url: row.url,
+            events: row.events,
+            section_id: row.section_id.to_string(),
+            enabled: row.enabled,
+            failures: row.failures,
+            updated: row.updated,
+            created: row.created,
+        }
+    }
+}
+
+#[derive(Iden)]
+pub enum WebhookDelivery {
+    Table,
+    Id,
+    WebhookId,
+    Event,
+    Payload,
+    StatusCode,
+    Attempts,
+    Error,
+    Created,
+}
+
+impl WebhookDelivery {
+    pub async fn init(db: &Pool<Postgres>) -> Result<()> {
+        let sql = sea_query::Table::create()
+            .table(Self::Table)
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Self::Id)
+                    .integer()
+                    .not_null()
+                    .auto_increment()
+                    .primary_key(),
+            )
+            .col(ColumnDef::new(Self::WebhookId).integer().not_null())
+            .col(ColumnDef::new(Self::Event).string().not_null())
+            .col(ColumnDef::new(Self::Payload).string().not_null())
+            .col(ColumnDef::new(Self::StatusCode).integer())
+            .col(ColumnDef::new(Self::Attempts).integer().not_null())
+            .col(ColumnDef::new(Self::Error).string())
+            .col(
+                ColumnDef::new(Self::Created)
+                    .timestamp_with_time_zone()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .build(PostgresQueryBuilder);
+        db.execute(query(&sql)).await?;
+
+        Ok(())
+    }
+
+    pub fn build_select() -> sea_query::SelectStatement {
+        sea_query::Query::select()
+            .columns([
+                WebhookDelivery::Id,
+                WebhookDelivery::WebhookId,
+                WebhookDelivery::Event,
+                WebhookDelivery::Payload,
+                WebhookDelivery::StatusCode,
+                WebhookDelivery::Attempts,
+                WebhookDelivery::Error,
+                WebhookDelivery::Created,
+            ])
+            .from(WebhookDelivery::Table)
+            .take()
+    }
+
+    pub async fn insert(db: &Pool<Postgres>, row: &WebhookDeliveryRow) -> Result<()> {
+        let (sql, values) = sea_query::Query::insert()
+            .into_table(Self::Table)
+            .columns([
+                Self::WebhookId,
+                Self::Event,
+                Self::Payload,
+                Self::StatusCode,
+                Self::Attempts,
+                Self::Error,
+            ])
+            .values([
+                row.webhook_id.into(),
+                row.event.clone().into(),
+                row.payload.clone().into(),
+                row.status_code.into(),
+                row.attempts.into(),
+                row.error.clone().into(),
+            ])?
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(db, &sql, values).await?;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize)]
+pub struct WebhookDeliveryRow {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: String,
+    pub payload: String,
+    pub status_code: Option<i32>,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub created: DateTime<Local>,
+}
+
+#[test]
+fn webhooks_match_event_bit_and_section() {
+    let sql =
+        Webhook::build_matching(WebhookEvent::CommentCreated, 3).to_string(PostgresQueryBuilder);
+    assert!(sql.contains("\"enabled\" = TRUE"));
+    assert!(sql.contains("(\"events\" & 2) <> 0"));
+    assert!(sql.contains("(\"section_id\" = 0 OR \"section_id\" = 3)"));
+}
+
+#[test]
+fn failed_deliveries_disable_webhook() {
+    let sql = Webhook::build_record_result(7, false).to_string(PostgresQueryBuilder);
+    assert!(sql.contains("\"failures\" = \"failures\" + 1"));
+    assert!(sql.contains(&format!(
+        "\"enabled\" = \"failures\" + 1 < {DISABLE_AFTER_FAILURES}"
+    )));
+    assert!(sql.ends_with("WHERE \"id\" = 7"));
+    let sql = Webhook::build_record_result(7, true).to_string(PostgresQueryBuilder);
+    assert_eq!(
+        sql,
+        "UPDATE \"webhook\" SET \"failures\" = 0 WHERE \"id\" = 7"
+    );
+}
@@ -1,7 +1,11 @@
+use std::collections::HashSet;
+
 use color_eyre::{Result, eyre::eyre};
 use sea_query::{ColumnDef, Expr, ExprTrait, Iden, PostgresQueryBuilder};
 use sea_query_sqlx::SqlxBinder;
-use sqlx::{Executor, Pool, Postgres, query, query_as_with, query_with};
+use sqlx::{Executor, Pool, Postgres, query};
+
+use crate::db;
 
 #[derive(Iden)]
 pub enum Whitelist {
@@ -21,20 +25,16 @@ impl Whitelist {
         Ok(())
     }
 
-    pub async fn _all(db: &Pool<Postgres>) -> Result<Vec<String>> {
+    pub async fn all(db: &Pool<Postgres>) -> Result<HashSet<String>> {
         let (sql, values) = sea_query::Query::select()
             .columns([Whitelist::Did])
             .from(Whitelist::Table)
             .build_sqlx(PostgresQueryBuilder);
-        let list: Vec<(String,)> = query_as_with(&sql, values.clone())
-            .fetch_all(db)
+        let list: Vec<(String,)> = db::fetch_all(db, &sql, values.clone())
             .await
             .map_err(|e| eyre!("exec sql failed: {e}"))?;
 
-        // list to map
-        let result = list.iter().map(|did| did.0.clone()).collect();
-
-        Ok(result)
+        Ok(list.into_iter().map(|(did,)| did).collect())
     }
 
     pub async fn select_by_did(db: &Pool<Postgres>, did: &str) -> bool {
@@ -43,8 +43,7 @@ impl Whitelist {
             .from(Whitelist::Table)
             .and_where(Expr::col(Whitelist::Did).eq(did))
             .build_sqlx(PostgresQueryBuilder);
-        query_as_with::<_, (String,), _>(&sql, values.clone())
-            .fetch_one(db)
+        db::fetch_one::<(String,), _>(db, &sql, values.clone())
             .await
             .ok()
             .is_some()
@@ -58,7 +57,7 @@ impl Whitelist {
             .returning_col(Self::Did)
             .build_sqlx(PostgresQueryBuilder);
 
-        db.execute(query_with(&sql, values)).await?;
+        db::execute(db, &sql, values).await?;
         Ok(())
     }
 
@@ -67,7 +66,7 @@ impl Whitelist {
             .from_table(Whitelist::Table)
             .and_where(Expr::col(Whitelist::Did).eq(did))
             .build_sqlx(PostgresQueryBuilder);
-        db.execute(query_with(&sql, values)).await?;
+        db::execute(db, &sql, values).await?;
         Ok(())
     }
 }
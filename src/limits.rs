@@ -0,0 +1,205 @@
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::config::{ClockSkew, PayloadLimits};
+
+/// A payload past one of the `PayloadLimits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadError {
+    /// Signed params whose CBOR encoding is larger than the limit.
+    SignedParamsTooLarge { size: usize, max: usize },
+    /// A record value nested deeper than the limit.
+    TooDeep { max: usize },
+    /// A record value with more object keys than the limit.
+    TooManyKeys { max: usize },
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SignedParamsTooLarge { size, max } => {
+                write!(f, "signed params take {size} bytes, at most {max} allowed")
+            }
+            Self::TooDeep { max } => write!(f, "record is nested deeper than {max} levels"),
+            Self::TooManyKeys { max } => write!(f, "record has more than {max} keys"),
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+/// A signed timestamp further from the server time than the `ClockSkew`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampError {
+    InPast { secs: i64, max: i64 },
+    InFuture { secs: i64, max: i64 },
+}
+
+impl fmt::Display for TimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InPast { secs, max } => {
+                write!(f, "timestamp is {secs}s in the past, allowed {max}s")
+            }
+            Self::InFuture { secs, max } => {
+                write!(f, "timestamp is {secs}s in the future, allowed {max}s")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TimestampError {}
+
+/// Refuses a signed `timestamp` outside the `ClockSkew` around `now`, both
+/// in Unix seconds.
+pub fn check_timestamp(timestamp: i64, now: i64, skew: &ClockSkew) -> Result<(), TimestampError> {
+    let behind = now.saturating_sub(timestamp);
+    if behind > skew.past_secs {
+        return Err(TimestampError::InPast {
+            secs: behind,
+            max: skew.past_secs,
+        });
+    }
+    let ahead = timestamp.saturating_sub(now);
+    if ahead > skew.future_secs {
+        return Err(TimestampError::InFuture {
+            secs: ahead,
+            max: skew.future_secs,
+        });
+    }
+    Ok(())
+}
+
+/// Refuses the CBOR encoding of signed params past the limit.
+pub fn check_signed_params(cbor: &[u8], limits: &PayloadLimits) -> Result<(), PayloadError> {
+    let max = limits.signed_params_max_bytes;
+    if cbor.len() > max {
+        return Err(PayloadError::SignedParamsTooLarge {
+            size: cbor.len(),
+            max,
+        });
+    }
+    Ok(())
+}
+
+/// Refuses a record value nested too deep or with too many keys, before
+/// anything else is done with it.
+pub fn check_record(value: &Value, limits: &PayloadLimits) -> Result<(), PayloadError> {
+    check_value(value, limits.record_max_depth, limits.record_max_keys)
+}
+
+/// Walks `value` without recursing, stopping at the first limit passed.
+fn check_value(value: &Value, max_depth: usize, max_keys: usize) -> Result<(), PayloadError> {
+    let mut keys = 0;
+    let mut pending = vec![(value, 1)];
+    while let Some((value, depth)) = pending.pop() {
+        if !(value.is_array() || value.is_object()) {
+            continue;
+        }
+        if depth > max_depth {
+            return Err(PayloadError::TooDeep { max: max_depth });
+        }
+        if let Value::Object(map) = value {
+            keys += map.len();
+            if keys > max_keys {
+                return Err(PayloadError::TooManyKeys { max: max_keys });
+            }
+            pending.extend(map.values().map(|child| (child, depth + 1)));
+        } else if let Value::Array(items) = value {
+            pending.extend(items.iter().map(|child| (child, depth + 1)));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn deep_and_wide_records_are_refused() {
+    let mut deep = Value::Null;
+    for _ in 0..64 {
+        deep = Value::Array(vec![deep]);
+    }
+    assert_eq!(
+        check_value(&deep, 32, 1024),
+        Err(PayloadError::TooDeep { max: 32 })
+    );
+
+    let post = serde_json::json!({
+        "$type": "app.bbs.post",
+        "section_id": "1",
+        "title": "hello",
+        "text": "world",
+        "tags": [["a"], ["b", { "c": 1 }]],
+    });
+    assert_eq!(check_value(&post, 4, 6), Ok(()));
+    assert_eq!(
+        check_value(&post, 3, 6),
+        Err(PayloadError::TooDeep { max: 3 })
+    );
+    assert_eq!(
+        check_value(&post, 4, 5),
+        Err(PayloadError::TooManyKeys { max: 5 })
+    );
+}
+
+#[test]
+fn large_signed_params_are_refused() {
+    #[derive(serde::Serialize)]
+    struct Params {
+        text: String,
+        timestamp: i64,
+    }
+
+    let small = serde_ipld_dagcbor::to_vec(&Params {
+        text: "hello".to_string(),
+        timestamp: 0,
+    })
+    .unwrap();
+    let limits = PayloadLimits::default();
+    assert_eq!(check_signed_params(&small, &limits), Ok(()));
+
+    let blob = serde_ipld_dagcbor::to_vec(&Params {
+        text: "x".repeat(1024 * 1024),
+        timestamp: 0,
+    })
+    .unwrap();
+    assert!(matches!(
+        check_signed_params(&blob, &limits),
+        Err(PayloadError::SignedParamsTooLarge { size, max: 65536 }) if size > 1024 * 1024
+    ));
+}
+
+#[test]
+fn timestamps_within_the_skew_pass() {
+    let skew = ClockSkew {
+        past_secs: 300,
+        future_secs: 60,
+    };
+    let now = 1_700_000_000;
+    assert_eq!(check_timestamp(now, now, &skew), Ok(()));
+    assert_eq!(check_timestamp(now - 300, now, &skew), Ok(()));
+    assert_eq!(check_timestamp(now + 60, now, &skew), Ok(()));
+
+    let e = check_timestamp(now - 301, now, &skew).unwrap_err();
+    assert_eq!(
+        e,
+        TimestampError::InPast {
+            secs: 301,
+            max: 300
+        }
+    );
+    assert_eq!(e.to_string(), "timestamp is 301s in the past, allowed 300s");
+    let e = check_timestamp(now + 61, now, &skew).unwrap_err();
+    assert_eq!(e, TimestampError::InFuture { secs: 61, max: 60 });
+    assert_eq!(e.to_string(), "timestamp is 61s in the future, allowed 60s");
+
+    // an unset or absurd timestamp does not overflow
+    assert!(matches!(
+        check_timestamp(i64::MIN, now, &skew),
+        Err(TimestampError::InPast { .. })
+    ));
+    assert!(matches!(
+        check_timestamp(i64::MAX, now, &skew),
+        Err(TimestampError::InFuture { .. })
+    ));
+}
@@ -0,0 +1,131 @@
+use color_eyre::{Result, eyre::eyre};
+use tracing_subscriber::{
+    EnvFilter, Registry,
+    fmt::{format::Writer, time::FormatTime, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    reload,
+    util::SubscriberInitExt,
+};
+
+use crate::config::{LogConfig, LogVerbosity};
+
+/// The filter of the installed subscriber, changed at runtime by
+/// `/api/admin/log_level`.
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// `log_config.filter`, which the module levels are added to.
+    base: String,
+}
+
+impl LogFilter {
+    /// The filter of `base` with the levels of `verbosity`, and the layer
+    /// applying it. Changes fail once the layer is dropped.
+    pub fn new(
+        base: &str,
+        verbosity: &LogVerbosity,
+    ) -> Result<(reload::Layer<EnvFilter, Registry>, Self)> {
+        let filter = parse(&verbosity.directives(base))?;
+        let (layer, handle) = reload::Layer::new(filter);
+        Ok((
+            layer,
+            Self {
+                handle,
+                base: base.to_string(),
+            },
+        ))
+    }
+
+    /// Replaces the module levels; the ones `verbosity` leaves unset follow
+    /// `log_config.filter` again.
+    pub fn set(&self, verbosity: &LogVerbosity) -> Result<()> {
+        let filter = parse(&verbosity.directives(&self.base))?;
+        self.handle
+            .reload(filter)
+            .map_err(|e| eyre!("reload log filter failed: {e}"))
+    }
+
+    /// The directives in effect.
+    pub fn current(&self) -> String {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .unwrap_or_default()
+    }
+}
+
+fn parse(directives: &str) -> Result<EnvFilter> {
+    EnvFilter::try_new(directives).map_err(|e| eyre!("invalid log filter `{directives}`: {e}"))
+}
+
+struct LocalTimer;
+
+impl FormatTime for LocalTimer {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        write!(w, "{}", chrono::Local::now().format("%m-%d %T%.3f"))
+    }
+}
+
+/// Installs the global subscriber, formatted as `common_x::log` does.
+pub fn init(config: &LogConfig, verbosity: &LogVerbosity) -> Result<LogFilter> {
+    let (filter_layer, filter) = LogFilter::new(&config.filter, verbosity)?;
+    let (writer, ansi) = match &config.rolling_file {
+        Some((directory, file_name_prefix)) => (
+            BoxMakeWriter::new(tracing_appender::rolling::daily(
+                directory,
+                file_name_prefix,
+            )),
+            false,
+        ),
+        None => (BoxMakeWriter::new(std::io::stdout), true),
+    };
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .compact()
+                .with_timer(LocalTimer)
+                .with_thread_ids(true)
+                .with_ansi(ansi)
+                .with_writer(writer),
+        )
+        .try_init()
+        .map_err(|e| eyre!("install log subscriber failed: {e}"))?;
+    Ok(filter)
+}
+
+#[test]
+fn reloading_changes_the_effective_filter() {
+    let (layer, filter) = LogFilter::new("info", &LogVerbosity::default()).unwrap();
+    let subscriber = tracing_subscriber::registry().with(layer);
+    tracing::subscriber::with_default(subscriber, || {
+        assert!(tracing::enabled!(target: "bbs::relayer", tracing::Level::INFO));
+        assert!(!tracing::enabled!(target: "bbs::relayer", tracing::Level::DEBUG));
+
+        filter
+            .set(&LogVerbosity {
+                relayer: Some("debug".to_string()),
+                sqlx: Some("warn".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(tracing::enabled!(target: "bbs::relayer::stream", tracing::Level::DEBUG));
+        assert!(!tracing::enabled!(target: "sqlx::query", tracing::Level::INFO));
+        assert!(tracing::enabled!(target: "bbs::api", tracing::Level::INFO));
+        assert!(!tracing::enabled!(target: "bbs::api", tracing::Level::DEBUG));
+        assert!(filter.current().contains("bbs::relayer=debug"));
+
+        // unset modules follow the base filter again
+        filter.set(&LogVerbosity::default()).unwrap();
+        assert!(!tracing::enabled!(target: "bbs::relayer", tracing::Level::DEBUG));
+        assert!(tracing::enabled!(target: "sqlx::query", tracing::Level::INFO));
+
+        let e = filter
+            .set(&LogVerbosity {
+                api: Some("loud".to_string()),
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert!(e.to_string().contains("bbs::api=loud"), "{e}");
+        assert_eq!(filter.current(), "info");
+    });
+}
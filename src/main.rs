@@ -1,12 +1,25 @@
 mod api;
 mod atproto;
+mod audit;
+mod broadcast;
+mod cache;
 mod ckb;
+mod cli;
 mod config;
+mod content_filter;
+mod db;
 mod error;
 mod indexer;
 mod lexicon;
+mod limits;
+mod log_filter;
+mod maintenance;
 mod micro_pay;
+mod middleware;
+mod quota;
+mod recount;
 mod relayer;
+mod webhook;
 
 #[macro_use]
 extern crate tracing as logger;
@@ -17,26 +30,43 @@ use ckb_sdk::CkbRpcAsyncClient;
 use clap::Parser;
 use color_eyre::{Result, eyre::eyre};
 use common_x::restful::axum::routing::get;
-use common_x::restful::axum::{Router, routing::post};
-use sqlx::{Pool, Postgres, postgres::PgPoolOptions};
+use common_x::restful::axum::{
+    Router,
+    middleware::{from_fn, from_fn_with_state},
+    routing::post,
+};
+use sqlx::{Executor, Pool, Postgres, postgres::PgPoolOptions};
 use tower_http::cors::CorsLayer;
 use tower_http::timeout::TimeoutLayer;
-use utoipa::OpenApi;
-use utoipa_scalar::{Scalar, Servable};
 
-use crate::api::ApiDoc;
 use crate::config::AppConfig;
 use crate::lexicon::administrator::Administrator;
+use crate::lexicon::audit_event::AuditEvent;
+use crate::lexicon::broadcast::Broadcast;
 use crate::lexicon::comment::Comment;
+use crate::lexicon::content_rule::ContentRule;
+use crate::lexicon::draft::Draft;
+use crate::lexicon::follow::Follow;
 use crate::lexicon::like::Like;
 use crate::lexicon::notify::Notify;
 use crate::lexicon::operation::Operation;
+use crate::lexicon::parked_record::ParkedRecord;
+use crate::lexicon::payout_pref::PayoutPref;
 use crate::lexicon::post::Post;
+use crate::lexicon::privacy_pref::PrivacyPref;
+use crate::lexicon::profile::Profile;
+use crate::lexicon::removed_repo::RemovedRepo;
 use crate::lexicon::reply::Reply;
+use crate::lexicon::repo_state::RepoState;
 use crate::lexicon::section::Section;
+use crate::lexicon::section_ban::SectionBan;
+use crate::lexicon::section_stats::SectionStats;
 use crate::lexicon::status::Status;
+use crate::lexicon::thread_mute::ThreadMute;
+use crate::lexicon::tip::Tip;
+use crate::lexicon::visit_source::PostVisitSource;
+use crate::lexicon::webhook::{Webhook, WebhookDelivery};
 use crate::lexicon::whitelist::Whitelist;
-use crate::relayer::subscription::RepoSubscription;
 
 #[derive(Clone)]
 struct AppView {
@@ -47,6 +77,53 @@ struct AppView {
     pay_url: String,
     bbs_ckb_addr: String,
     ckb_net: ckb_sdk::NetworkType,
+    caches: cache::Caches,
+    webhooks: webhook::Webhooks,
+    quota: config::QuotaConfig,
+    did_document: Option<serde_json::Value>,
+    ckb_addr_in_lists: bool,
+    reveal_moderator: bool,
+    removal: config::RemovalConfig,
+    relayer: relayer::health::RelayerHealth,
+    pagination: config::PaginationConfig,
+    limits: config::PayloadLimits,
+    clock_skew: config::ClockSkew,
+    tip_expiry: config::TipExpiry,
+    maintenance: maintenance::Maintenance,
+    log_filter: log_filter::LogFilter,
+}
+
+#[cfg(test)]
+impl AppView {
+    /// An appview on `db` whose other services are unreachable.
+    fn for_tests(db: Pool<Postgres>) -> Self {
+        let (webhooks, _webhook_rx) = webhook::Webhooks::channel();
+        AppView {
+            db,
+            pds: "http://127.0.0.1:9".to_string(),
+            ckb_client: CkbRpcAsyncClient::new("http://127.0.0.1:9"),
+            indexer: String::new(),
+            pay_url: "http://127.0.0.1:9".to_string(),
+            bbs_ckb_addr: String::new(),
+            ckb_net: ckb_sdk::NetworkType::Testnet,
+            caches: cache::Caches::new(&Default::default()),
+            webhooks,
+            quota: Default::default(),
+            did_document: None,
+            ckb_addr_in_lists: false,
+            reveal_moderator: true,
+            removal: Default::default(),
+            relayer: relayer::health::RelayerHealth::new("", &Default::default()),
+            pagination: Default::default(),
+            limits: Default::default(),
+            clock_skew: Default::default(),
+            tip_expiry: Default::default(),
+            maintenance: maintenance::Maintenance::new(""),
+            log_filter: log_filter::LogFilter::new("info", &Default::default())
+                .unwrap()
+                .1,
+        }
+    }
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -56,6 +133,9 @@ pub struct Args {
     config_path: String,
     #[clap(short, long, default_value = "false")]
     apidoc: bool,
+    /// Serves when none is given.
+    #[command(subcommand)]
+    command: Option<cli::Command>,
 }
 
 #[tokio::main]
@@ -63,25 +143,26 @@ async fn main() -> Result<()> {
     let args = Args::parse();
     let config: AppConfig = common_x::configure::file_config(&args.config_path)?;
 
-    common_x::log::init_log(config.log_config.clone());
+    let log_filter = log_filter::init(&config.log_config, &config.log_verbosity)?;
     info!("config: {:?}", config);
+    db::set_slow_query_threshold(Duration::from_millis(config.slow_query_ms));
+    lexicon::section_stats::set_section_stats(&config.section_stats);
     let db = PgPoolOptions::new()
         .max_connections(5)
         .connect(&config.db_url)
         .await?;
 
-    // initialize the database
-    Status::init(&db).await?;
-    Section::init(&db).await?;
-    Post::init(&db).await?;
-    Comment::init(&db).await?;
-    Reply::init(&db).await?;
-    Like::init(&db).await?;
-    Whitelist::init(&db).await?;
-    Notify::init(&db).await?;
-    Administrator::init(&db).await?;
-    Operation::init(&db).await?;
+    migrate(&db, &config).await?;
+    match args.command {
+        None | Some(cli::Command::Serve) => {}
+        Some(command) => return cli::run(command, &db, &config).await,
+    }
 
+    let did_document = api::well_known::build_did_document(&config)?;
+    // an appview that does not subscribe is ready without the relayer
+    let mut readiness = config.readiness.clone();
+    readiness.relayer_lag_unready &= config.subscribe_relayer;
+    let (webhooks, webhook_rx) = webhook::Webhooks::channel();
     let bbs = AppView {
         db,
         pds: config.pds.clone(),
@@ -90,37 +171,132 @@ async fn main() -> Result<()> {
         indexer: config.indexer.clone(),
         pay_url: config.pay_url.clone(),
         ckb_net: config.ckb_net,
+        caches: cache::Caches::new(&config.cache),
+        webhooks,
+        quota: config.quota.clone(),
+        did_document,
+        ckb_addr_in_lists: config.ckb_addr_in_lists,
+        reveal_moderator: config.reveal_moderator,
+        removal: config.removal.clone(),
+        relayer: relayer::health::RelayerHealth::new(&config.relayer, &readiness),
+        pagination: config.pagination.clone(),
+        limits: config.limits.clone(),
+        clock_skew: config.clock_skew.clone(),
+        tip_expiry: config.tip_expiry.clone(),
+        maintenance: maintenance::Maintenance::new(&config.maintenance_message),
+        log_filter,
     };
+    tokio::spawn(webhook::run(bbs.clone(), webhook_rx));
+    let (audit, audit_rx) = audit::Audit::channel(audit::QUEUE_CAPACITY);
+    tokio::spawn(audit::run(bbs.db.clone(), audit_rx));
+    // broadcasts the last run left queued or running
+    broadcast::resume(&bbs.db).await?;
+
+    // reconnect, resuming where the last run stopped
+    let subscription = if config.subscribe_relayer {
+        bbs.relayer
+            .resume_from(Status::firehose_cursor(&bbs.db).await?);
+        Some(tokio::spawn(relayer::supervise(
+            bbs.clone(),
+            config.relayer.clone(),
+            config.relayer_reconnect.clone(),
+        )))
+    } else {
+        info!("Relayer subscription is disabled, serving the API only.");
+        None
+    };
+
+    // the per-op relayer logs are debug; sum them up once a minute
+    let relayer_ = bbs.relayer.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        let mut last = relayer_.activity();
+        loop {
+            interval.tick().await;
+            let (commits, records) = relayer_.activity();
+            if commits > last.0 {
+                info!(
+                    "relayer: {} commits, {} records in the last minute, {} queued",
+                    commits - last.0,
+                    records - last.1,
+                    relayer_.status().queued
+                );
+            }
+            last = (commits, records);
+        }
+    });
 
-    // reconnect
+    // time out stale prepared tips and purge the old timed out ones
     let bbs_ = bbs.clone();
-    let relayer = config.relayer.clone();
     tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
         loop {
-            match RepoSubscription::new(&relayer).await {
-                Ok(mut sub) => match sub.run(bbs_.clone()).await {
-                    Ok(_) => info!("Subscription ended successfully."),
-                    Err(e) => error!("{e}"),
-                },
-                Err(e) => error!("{e}"),
+            interval.tick().await;
+            if let Err(e) = api::tip::expire_stale(&bbs_).await {
+                error!("expire stale tips failed: {e}");
             }
-            info!("Reconnecting in 1 seconds...");
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
         }
     });
 
-    let router = if args.apidoc {
-        Router::new().merge(Scalar::with_url("/apidoc", ApiDoc::openapi()))
-    } else {
-        Router::new()
-    };
-    let router = router
+    // drop visit sources and audit events past their retention
+    let db = bbs.db.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            if let Err(e) = PostVisitSource::prune(&db).await {
+                error!("prune visit sources failed: {e}");
+            }
+            match AuditEvent::purge(&db).await {
+                Ok(0) => {}
+                Ok(purged) => info!("purged {purged} expired audit events"),
+                Err(e) => error!("purge audit events failed: {e}"),
+            }
+        }
+    });
+
+    // resolve the handles of recent authors again so renames show up
+    let bbs_ = bbs.clone();
+    let every = Duration::from_secs(config.cache.handle_refresh_secs.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            api::refresh_handles(&bbs_).await;
+        }
+    });
+
+    // keep the section map warm; list requests fall back to the last one
+    let bbs_ = bbs.clone();
+    let every = Duration::from_secs((config.cache.section_ttl_secs / 2).max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            bbs_.caches.refresh_sections(Section::all(&bbs_.db)).await;
+        }
+    });
+
+    // snapshot the section counters the section lists read
+    tokio::spawn(SectionStats::keep_refreshed(
+        bbs.db.clone(),
+        Duration::from_secs(config.section_stats.refresh_secs.max(1)),
+    ));
+
+    let mut apidoc = config.apidoc.clone();
+    if args.apidoc {
+        apidoc.mode = config::ApidocMode::Full;
+    }
+    let router = api::apidoc_router(&apidoc)
         .route("/api/admin/update_tag", post(api::admin::update_tag))
         .route("/api/admin/update_owner", post(api::admin::update_owner))
         .route(
             "/api/admin/update_section",
             post(api::admin::update_section),
         )
+        .route("/api/admin/ban", post(api::admin::ban))
+        .route("/api/admin/unban", post(api::admin::unban))
         .route(
             "/api/admin/create_section",
             post(api::admin::create_section),
@@ -134,44 +310,186 @@ async fn main() -> Result<()> {
         .route("/api/admin/add", post(api::admin::add))
         .route("/api/admin/delete", post(api::admin::delete))
         .route("/api/admin/operations", get(api::admin::operations))
+        .route("/api/admin/flush_cache", post(api::admin::flush_cache))
+        .route("/api/admin/cache_stats", get(api::admin::cache_stats))
+        .route(
+            "/api/admin/moderation_stats",
+            get(api::admin::moderation_stats),
+        )
+        .route("/api/admin/hidden_list", post(api::admin::hidden_list))
+        .route("/api/admin/resync_record", post(api::admin::resync_record))
+        .route("/api/admin/recount", post(api::admin::recount))
+        .route("/api/admin/relayer_status", get(api::admin::relayer_status))
+        .route(
+            "/api/admin/relayer_restart",
+            post(api::admin::relayer_restart),
+        )
+        .route("/api/admin/broadcast", post(api::admin::broadcast))
+        .route("/api/admin/maintenance", post(api::admin::maintenance))
+        .route("/api/admin/log_level", post(api::admin::log_level))
+        .route("/api/admin/audit_events", post(api::audit::events))
+        .route(
+            "/api/admin/broadcast_status",
+            get(api::admin::broadcast_status),
+        )
+        .route("/api/admin/webhook/add", post(api::webhook::add))
+        .route("/api/admin/webhook/update", post(api::webhook::update))
+        .route("/api/admin/webhook/delete", post(api::webhook::delete))
+        .route("/api/admin/webhook/list", post(api::webhook::list))
+        .route(
+            "/api/admin/webhook/deliveries",
+            post(api::webhook::deliveries),
+        )
+        .route("/api/admin/content_rule/add", post(api::content_rule::add))
+        .route(
+            "/api/admin/content_rule/delete",
+            post(api::content_rule::delete),
+        )
+        .route(
+            "/api/admin/content_rule/list",
+            post(api::content_rule::list),
+        )
         .route("/api/record/create", post(api::record::create))
         .route("/api/record/update", post(api::record::update))
         .route("/api/record/delete", post(api::record::delete))
+        .route("/.well-known/did.json", get(api::well_known::did_document))
+        .route("/xrpc/_health", get(api::well_known::health))
+        .route("/readyz", get(api::well_known::readyz))
+        .route("/api/status", get(api::well_known::status))
+        .route("/api/time", get(api::well_known::time))
+        .route("/xrpc/app.bbs.feed.getPosts", get(api::xrpc::get_posts))
+        .route("/xrpc/app.bbs.feed.getThread", get(api::xrpc::get_thread))
+        .route("/xrpc/app.bbs.section.list", get(api::xrpc::list_sections))
         .route("/api/section/list", get(api::section::list))
         .route("/api/section/detail", get(api::section::detail))
+        .route("/api/section/trending", get(api::section::trending))
+        .route("/api/section/mine", get(api::section::mine))
         .route("/api/post/list", post(api::post::list))
         .route("/api/post/page", post(api::post::page))
         .route("/api/post/top", post(api::post::top))
         .route("/api/post/detail", get(api::post::detail))
+        .route("/api/post/thread", get(api::post::thread))
+        .route("/api/post/analytics", post(api::post::analytics))
+        .route("/api/post/engagement", post(api::post::engagement))
+        .route("/api/post/participants", get(api::post::participants))
         .route("/api/post/commented", post(api::post::commented))
         .route("/api/post/commented_page", post(api::post::commented_page))
         .route("/api/post/list_draft", post(api::post::list_draft))
         .route("/api/post/detail_draft", get(api::post::detail_draft))
+        .route("/api/post/save_draft", post(api::post::save_draft))
+        .route("/api/post/publish", post(api::post::publish))
+        .route("/api/post/pin", post(api::post::pin))
+        .route("/api/post/mute", post(api::post::mute))
+        .route("/api/post/unmute", post(api::post::unmute))
+        .route("/api/post/search_mine", post(api::post::search_mine))
         .route("/api/comment/list", post(api::comment::list))
         .route("/api/reply/list", post(api::reply::list))
         .route("/api/reply/page", post(api::reply::page))
         .route("/api/repo/profile", get(api::repo::profile))
         .route("/api/repo/login_info", get(api::repo::login_info))
+        .route("/api/repo/quota", get(api::repo::quota))
+        .route("/api/repo/state", get(api::repo::state))
+        .route("/api/repo/stats", get(api::repo::stats))
+        .route("/api/repo/remove_me", post(api::repo::remove_me))
+        .route("/api/repo/restore_me", post(api::repo::restore_me))
+        .route("/api/repo/payout_address", post(api::repo::payout_address))
+        .route("/api/repo/likes_public", post(api::repo::likes_public))
+        .route("/api/repo/followers", get(api::repo::followers))
+        .route("/api/repo/following", get(api::repo::following))
         .route("/api/like/list", post(api::like::list))
+        .route("/api/like/stats", get(api::like::stats))
+        .route("/api/search/global", post(api::search::global))
         .route("/api/tip/prepare", post(api::tip::prepare))
         .route("/api/tip/transfer", post(api::tip::transfer))
         .route("/api/tip/list", post(api::tip::list_by_for))
         .route("/api/tip/expense_details", post(api::tip::expense_details))
         .route("/api/tip/income_details", post(api::tip::income_details))
         .route("/api/tip/stats", get(api::tip::stats))
+        .route("/api/tip/pending", get(api::tip::pending))
         .route("/api/donate/prepare", post(api::donate::prepare))
         .route("/api/donate/transfer", post(api::donate::transfer))
         .route("/api/notify/list", post(api::notify::list))
         .route("/api/notify/read", post(api::notify::read))
         .route("/api/notify/unread_num", get(api::notify::unread_num))
-        .route("/api/whitelist", get(api::whitelist::list))
+        .route("/api/resolve", get(api::resolve::resolve))
+        .route("/api/blob/{did}/{cid}", get(api::blob::get))
+        .route("/api/whitelist", get(api::whitelist::list));
+    let router = if config.debug_mode {
+        router.layer(from_fn(middleware::body_log::body_log))
+    } else {
+        router
+    };
+    let router = router
         .layer((TimeoutLayer::with_status_code(
             reqwest::StatusCode::REQUEST_TIMEOUT,
             Duration::from_secs(10),
         ),))
+        .layer(from_fn_with_state(
+            bbs.maintenance.clone(),
+            middleware::maintenance::read_only,
+        ))
+        .layer(from_fn_with_state(audit, middleware::audit::record))
+        .layer(from_fn_with_state(
+            middleware::api_version::Negotiation {
+                sunset: config.legacy_api_sunset.clone(),
+                numeric_json: config.numeric_json,
+            },
+            middleware::api_version::negotiate,
+        ))
         .layer(CorsLayer::permissive())
-        .with_state(bbs);
-    common_x::restful::http_serve(config.port, router)
+        .with_state(bbs.clone());
+    let served = common_x::restful::http_serve(config.port, router)
         .await
-        .map_err(|e| eyre!("{e}"))
+        .map_err(|e| eyre!("{e}"));
+
+    // the server stopped on a signal; let the subscription save its cursor
+    if let Some(subscription) = subscription {
+        bbs.relayer.shut_down();
+        if tokio::time::timeout(Duration::from_secs(10), subscription)
+            .await
+            .is_err()
+        {
+            warn!("Relayer subscription did not stop in time.");
+        }
+    }
+    served
+}
+
+/// Creates the tables and runs the migrations; every statement is safe to
+/// run again.
+async fn migrate(db: &Pool<Postgres>, config: &AppConfig) -> Result<()> {
+    db.execute(sqlx::query("CREATE EXTENSION IF NOT EXISTS pg_trgm"))
+        .await?;
+    Status::init(db).await?;
+    Section::init(db).await?;
+    SectionStats::init(db).await?;
+    SectionBan::init(db).await?;
+    Post::init(db).await?;
+    Draft::init(db).await?;
+    Comment::init(db).await?;
+    Reply::init(db).await?;
+    ParkedRecord::init(db).await?;
+    Like::init(db).await?;
+    Follow::init(db).await?;
+    Profile::init(db).await?;
+    Whitelist::init(db).await?;
+    Notify::init(db).await?;
+    Broadcast::init(db).await?;
+    ThreadMute::init(db).await?;
+    Administrator::init(db).await?;
+    Operation::init(db).await?;
+    Tip::init(db).await?;
+    Webhook::init(db).await?;
+    WebhookDelivery::init(db).await?;
+    ContentRule::init(db).await?;
+    RemovedRepo::init(db).await?;
+    RepoState::init(db).await?;
+    PayoutPref::init(db).await?;
+    PrivacyPref::init(db).await?;
+    PostVisitSource::init(db).await?;
+    AuditEvent::init(db).await?;
+    if config.amounts_in_ckb {
+        Tip::migrate_ckb_amounts(db).await?;
+    }
+    Ok(())
 }
@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use tokio::sync::watch;
+use utoipa::ToSchema;
+
+/// Endpoints that change something, refused while the appview is read only.
+/// `/api/admin/maintenance` is left out so the mode can be turned off again.
+pub const WRITES: [&str; 37] = [
+    "/api/record/create",
+    "/api/record/update",
+    "/api/record/delete",
+    "/api/post/save_draft",
+    "/api/post/publish",
+    "/api/post/pin",
+    "/api/post/mute",
+    "/api/post/unmute",
+    "/api/repo/remove_me",
+    "/api/repo/restore_me",
+    "/api/repo/payout_address",
+    "/api/repo/likes_public",
+    "/api/tip/prepare",
+    "/api/tip/transfer",
+    "/api/donate/prepare",
+    "/api/donate/transfer",
+    "/api/notify/read",
+    "/api/admin/update_tag",
+    "/api/admin/update_owner",
+    "/api/admin/update_section",
+    "/api/admin/create_section",
+    "/api/admin/ban",
+    "/api/admin/unban",
+    "/api/admin/add_whitelist",
+    "/api/admin/delete_whitelist",
+    "/api/admin/add",
+    "/api/admin/delete",
+    "/api/admin/flush_cache",
+    "/api/admin/resync_record",
+    "/api/admin/recount",
+    "/api/admin/relayer_restart",
+    "/api/admin/broadcast",
+    "/api/admin/webhook/add",
+    "/api/admin/webhook/update",
+    "/api/admin/webhook/delete",
+    "/api/admin/content_rule/add",
+    "/api/admin/content_rule/delete",
+];
+
+/// The maintenance mode as set by a super administrator.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct MaintenanceState {
+    /// Writes are refused with `MaintenanceMode`; reads are served.
+    pub read_only: bool,
+    /// The relayer holds the next commit until this is cleared.
+    pub relayer_paused: bool,
+    /// Shown to clients whose writes are refused.
+    pub message: String,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub since: Option<DateTime<Local>>,
+}
+
+/// Shared maintenance mode, changed at runtime and read by the write guard
+/// and the relayer.
+#[derive(Clone)]
+pub struct Maintenance {
+    state: Arc<watch::Sender<MaintenanceState>>,
+    default_message: Arc<str>,
+}
+
+impl Maintenance {
+    /// `default_message` is used when a toggle gives none.
+    pub fn new(default_message: &str) -> Self {
+        let (state, _) = watch::channel(MaintenanceState::default());
+        Self {
+            state: Arc::new(state),
+            default_message: default_message.into(),
+        }
+    }
+
+    pub fn state(&self) -> MaintenanceState {
+        self.state.borrow().clone()
+    }
+
+    pub fn set(&self, read_only: bool, relayer_paused: bool, message: Option<String>) {
+        self.state.send_modify(|state| {
+            let on = read_only || relayer_paused;
+            if on && state.since.is_none() {
+                state.since = Some(Local::now());
+            } else if !on {
+                state.since = None;
+            }
+            state.read_only = read_only;
+            state.relayer_paused = relayer_paused;
+            state.message = message
+                .filter(|m| !m.is_empty())
+                .unwrap_or_else(|| self.default_message.to_string());
+        });
+    }
+
+    /// The message to refuse the write to `path` with, if it is refused.
+    pub fn refuses(&self, path: &str) -> Option<String> {
+        let state = self.state.borrow();
+        (state.read_only && WRITES.contains(&path)).then(|| state.message.clone())
+    }
+
+    /// Returns once the relayer is not paused.
+    pub async fn relayer_resumed(&self) {
+        let mut state = self.state.subscribe();
+        state.wait_for(|state| !state.relayer_paused).await.ok();
+    }
+}
+
+#[test]
+fn read_only_refuses_writes_only() {
+    let maintenance = Maintenance::new("back soon");
+    assert_eq!(maintenance.refuses("/api/record/create"), None);
+
+    maintenance.set(true, false, None);
+    assert!(maintenance.state().since.is_some());
+    for path in [
+        "/api/record/create",
+        "/api/record/update",
+        "/api/record/delete",
+        "/api/tip/prepare",
+        "/api/tip/transfer",
+        "/api/donate/prepare",
+        "/api/donate/transfer",
+        "/api/notify/read",
+        "/api/admin/update_tag",
+        "/api/admin/content_rule/delete",
+    ] {
+        assert_eq!(
+            maintenance.refuses(path).as_deref(),
+            Some("back soon"),
+            "{path}"
+        );
+    }
+    for path in [
+        "/api/admin/maintenance",
+        "/api/post/list",
+        "/api/post/detail",
+        "/api/notify/list",
+        "/api/notify/unread_num",
+        "/api/tip/stats",
+        "/api/status",
+    ] {
+        assert_eq!(maintenance.refuses(path), None, "{path}");
+    }
+
+    maintenance.set(true, false, Some("migrating the database".to_string()));
+    assert_eq!(
+        maintenance.refuses("/api/notify/read").as_deref(),
+        Some("migrating the database")
+    );
+    maintenance.set(false, false, None);
+    assert_eq!(maintenance.state().since, None);
+    assert_eq!(maintenance.refuses("/api/record/create"), None);
+}
+
+#[tokio::test]
+async fn paused_relayer_waits_for_resume() {
+    let maintenance = Maintenance::new("back soon");
+    maintenance.relayer_resumed().await;
+
+    maintenance.set(false, true, None);
+    let waiting = tokio::spawn({
+        let maintenance = maintenance.clone();
+        async move { maintenance.relayer_resumed().await }
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert!(!waiting.is_finished());
+    maintenance.set(false, false, None);
+    tokio::time::timeout(std::time::Duration::from_secs(5), waiting)
+        .await
+        .unwrap()
+        .unwrap();
+}
@@ -1,8 +1,92 @@
-use std::time::Duration;
+use std::{collections::HashSet, fmt, time::Duration};
 
 use color_eyre::{Result, eyre::eyre};
+use serde::Serialize;
 use serde_json::Value;
 
+/// A share of a payment micro_pay pays out to `address`. `split_rate` is in
+/// whole percent of the amount.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SplitReceiver {
+    pub address: String,
+    pub receiver_did: String,
+    pub split_rate: i64,
+}
+
+/// Split receivers micro_pay would refuse, naming the offending entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitError {
+    RateOutOfRange {
+        address: String,
+        rate: i64,
+    },
+    DuplicateAddress(String),
+    /// The rates up to and including `address` add up to `total`.
+    TotalTooLarge {
+        address: String,
+        total: i64,
+    },
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RateOutOfRange { address, rate } => {
+                write!(f, "split rate {rate} of {address} is not from 1 to 99")
+            }
+            Self::DuplicateAddress(address) => write!(f, "{address} is split to more than once"),
+            Self::TotalTooLarge { address, total } => {
+                write!(
+                    f,
+                    "split rates reach {total} with {address}, must stay under 100"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SplitError {}
+
+/// The split receivers of a payment to `receiver` from `(address, rate)`
+/// shares. Shares to the receiver itself are dropped, the others must each
+/// be from 1 to 99 percent, to distinct addresses and under 100 together.
+pub fn build_split_receivers(
+    receiver: &str,
+    shares: &[(&str, i64)],
+) -> Result<Vec<SplitReceiver>, SplitError> {
+    let mut addresses = HashSet::new();
+    let mut total = 0;
+    let mut receivers = vec![];
+    for &(address, rate) in shares {
+        if address == receiver {
+            continue;
+        }
+        if !(1..=99).contains(&rate) {
+            return Err(SplitError::RateOutOfRange {
+                address: address.to_string(),
+                rate,
+            });
+        }
+        if !addresses.insert(address) {
+            return Err(SplitError::DuplicateAddress(address.to_string()));
+        }
+        total += rate;
+        if total >= 100 {
+            return Err(SplitError::TotalTooLarge {
+                address: address.to_string(),
+                total,
+            });
+        }
+        receivers.push(SplitReceiver {
+            address: address.to_string(),
+            receiver_did: address.to_string(),
+            split_rate: rate,
+        });
+    }
+    Ok(receivers)
+}
+
 pub async fn payment_prepare(url: &str, body: &Value) -> Result<Value> {
     reqwest::Client::new()
         .post(format!("{url}/api/payment/prepare"))
@@ -31,22 +115,50 @@ pub async fn payment_transfer(url: &str, body: &Value) -> Result<Value> {
         .map_err(|e| eyre!("decode micro_pay response failed: {e}"))
 }
 
-pub async fn payment_completed_total(url: &str, info: &str) -> Result<Value> {
-    reqwest::Client::new()
-        .get(format!("{url}/api/payment/completed-total?info={info}"))
+/// Abandon a prepared payment. A 404 means the service already expired it,
+/// which is logged and treated as success. Any other error status, or an
+/// answer carrying an `error`, leaves the payment as it was.
+pub async fn payment_cancel(url: &str, tx_hash: &str) -> Result<Value> {
+    let response = reqwest::Client::new()
+        .post(format!("{url}/api/payment/cancel/{tx_hash}"))
         .header("Content-Type", "application/json; charset=utf-8")
         .timeout(Duration::from_secs(5))
         .send()
         .await
-        .map_err(|e| eyre!("call micro_pay failed: {e}"))?
-        .json::<Value>()
+        .map_err(|e| eyre!("call micro_pay failed: {e}"))?;
+    let status = response.status();
+    if status == reqwest::StatusCode::NOT_FOUND {
+        warn!("payment_cancel: {tx_hash} not found on micro_pay, already expired");
+        return Ok(Value::Null);
+    }
+    let body = response.text().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(eyre!("micro_pay answered {status}: {body}"));
+    }
+    let result: Value =
+        serde_json::from_str(&body).map_err(|e| eyre!("decode micro_pay response failed: {e}"))?;
+    if let Some(err) = result.get("error") {
+        return Err(eyre!("micro_pay refused the cancel: {err}"));
+    }
+    Ok(result)
+}
+
+/// The body of a paginated listing, logged raw so that a malformed answer
+/// can be told apart from an empty page.
+async fn decode(response: reqwest::Response) -> Result<Value> {
+    let body = response
+        .text()
         .await
-        .map_err(|e| eyre!("decode micro_pay response failed: {e}"))
+        .map_err(|e| eyre!("read micro_pay response failed: {e}"))?;
+    debug!("micro_pay response: {body}");
+    serde_json::from_str(&body).map_err(|e| eyre!("decode micro_pay response failed: {e}"))
 }
 
-pub async fn payment_completed(url: &str, query: &str) -> Result<Value> {
+/// `info` is the `nsid/uri` of the target, encoded here.
+pub async fn payment_completed_total(url: &str, info: &str) -> Result<Value> {
     reqwest::Client::new()
-        .get(format!("{url}/api/payment/completed?{query}"))
+        .get(format!("{url}/api/payment/completed-total"))
+        .query(&[("info", info)])
         .header("Content-Type", "application/json; charset=utf-8")
         .timeout(Duration::from_secs(5))
         .send()
@@ -57,22 +169,32 @@ pub async fn payment_completed(url: &str, query: &str) -> Result<Value> {
         .map_err(|e| eyre!("decode micro_pay response failed: {e}"))
 }
 
+pub async fn payment_completed(url: &str, query: &[(&str, String)]) -> Result<Value> {
+    let response = reqwest::Client::new()
+        .get(format!("{url}/api/payment/completed"))
+        .query(query)
+        .header("Content-Type", "application/json; charset=utf-8")
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+        .map_err(|e| eyre!("call micro_pay failed: {e}"))?;
+    decode(response).await
+}
+
 pub async fn payment_sender_did(
     url: &str,
     sender_did: &str,
     query: &[(&str, String)],
 ) -> Result<Value> {
-    reqwest::Client::new()
+    let response = reqwest::Client::new()
         .get(format!("{url}/api/payment/sender-did/{sender_did}"))
         .query(query)
         .header("Content-Type", "application/json; charset=utf-8")
         .timeout(Duration::from_secs(5))
         .send()
         .await
-        .map_err(|e| eyre!("call micro_pay failed: {e}"))?
-        .json::<Value>()
-        .await
-        .map_err(|e| eyre!("decode micro_pay response failed: {e}"))
+        .map_err(|e| eyre!("call micro_pay failed: {e}"))?;
+    decode(response).await
 }
 
 pub async fn payment_receiver_did(
@@ -80,17 +202,15 @@ pub async fn payment_receiver_did(
     receiver_did: &str,
     query: &[(&str, String)],
 ) -> Result<Value> {
-    reqwest::Client::new()
+    let response = reqwest::Client::new()
         .get(format!("{url}/api/payment/receiver-did/{receiver_did}"))
         .query(query)
         .header("Content-Type", "application/json; charset=utf-8")
         .timeout(Duration::from_secs(5))
         .send()
         .await
-        .map_err(|e| eyre!("call micro_pay failed: {e}"))?
-        .json::<Value>()
-        .await
-        .map_err(|e| eyre!("decode micro_pay response failed: {e}"))
+        .map_err(|e| eyre!("call micro_pay failed: {e}"))?;
+    decode(response).await
 }
 
 pub async fn payment_did_stats(url: &str, did: &str) -> Result<Value> {
@@ -118,3 +238,99 @@ pub async fn payment(url: &str, id: i64) -> Result<Value> {
         .await
         .map_err(|e| eyre!("decode micro_pay response failed: {e}"))
 }
+
+#[test]
+fn split_receivers_follow_the_contract() {
+    let split = |address: &str, rate| SplitReceiver {
+        address: address.to_string(),
+        receiver_did: address.to_string(),
+        split_rate: rate,
+    };
+    assert_eq!(build_split_receivers("ckt1alice", &[]), Ok(vec![]));
+    assert_eq!(
+        build_split_receivers("ckt1alice", &[("ckt1bbs", 10), ("ckt1section", 20)]),
+        Ok(vec![split("ckt1bbs", 10), split("ckt1section", 20)])
+    );
+    // up to 99 together
+    assert_eq!(
+        build_split_receivers("ckt1alice", &[("ckt1bbs", 1), ("ckt1section", 98)]),
+        Ok(vec![split("ckt1bbs", 1), split("ckt1section", 98)])
+    );
+    // the receiver is not split to, whatever its rate
+    assert_eq!(
+        build_split_receivers("ckt1section", &[("ckt1bbs", 10), ("ckt1section", 0)]),
+        Ok(vec![split("ckt1bbs", 10)])
+    );
+
+    for rate in [0, -5, 100] {
+        assert_eq!(
+            build_split_receivers("ckt1alice", &[("ckt1bbs", 10), ("ckt1section", rate)]),
+            Err(SplitError::RateOutOfRange {
+                address: "ckt1section".to_string(),
+                rate
+            })
+        );
+    }
+    assert_eq!(
+        build_split_receivers("ckt1alice", &[("ckt1bbs", 10), ("ckt1bbs", 20)]),
+        Err(SplitError::DuplicateAddress("ckt1bbs".to_string()))
+    );
+    let e = build_split_receivers(
+        "ckt1alice",
+        &[("ckt1bbs", 60), ("ckt1section", 40), ("ckt1other", 5)],
+    )
+    .unwrap_err();
+    assert_eq!(
+        e,
+        SplitError::TotalTooLarge {
+            address: "ckt1section".to_string(),
+            total: 100
+        }
+    );
+    assert_eq!(
+        e.to_string(),
+        "split rates reach 100 with ckt1section, must stay under 100"
+    );
+
+    // the shape micro_pay reads
+    assert_eq!(
+        serde_json::to_value(split("ckt1bbs", 30)).unwrap(),
+        serde_json::json!({ "address": "ckt1bbs", "receiverDid": "ckt1bbs", "splitRate": 30 })
+    );
+}
+
+#[tokio::test]
+async fn cancel_fails_unless_micro_pay_cancelled() {
+    use common_x::restful::axum::{Json, Router, extract::Path, http::StatusCode, routing::post};
+    use serde_json::json;
+
+    let router = Router::new().route(
+        "/api/payment/cancel/{tx_hash}",
+        post(|Path(tx_hash): Path<String>| async move {
+            match tx_hash.as_str() {
+                "gone" => (StatusCode::NOT_FOUND, Json(json!({}))),
+                "broken" => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({ "message": "database unavailable" })),
+                ),
+                "refused" => (StatusCode::OK, Json(json!({ "error": "already paid" }))),
+                _ => (StatusCode::OK, Json(json!({ "txHash": tx_hash }))),
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        common_x::restful::axum::serve(listener, router).await.ok();
+    });
+
+    assert_eq!(
+        payment_cancel(&url, "tx1").await.unwrap(),
+        json!({ "txHash": "tx1" })
+    );
+    assert_eq!(payment_cancel(&url, "gone").await.unwrap(), Value::Null);
+    let e = payment_cancel(&url, "broken").await.unwrap_err();
+    assert!(e.to_string().contains("500"), "{e}");
+    let e = payment_cancel(&url, "refused").await.unwrap_err();
+    assert!(e.to_string().contains("already paid"), "{e}");
+}
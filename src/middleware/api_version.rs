@@ -0,0 +1,89 @@
+use common_x::restful::axum::{
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::api::version::{self, ApiVersion};
+
+/// What `negotiate` takes from the config.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Negotiation {
+    /// `legacy_api_sunset`.
+    pub sunset: Option<String>,
+    /// `numeric_json`.
+    pub numeric_json: bool,
+}
+
+/// Serializes the views of the request for the API version it names, and
+/// marks responses in a deprecated shape with `Deprecation`, plus `Sunset`
+/// once `legacy_api_sunset` is set.
+pub(crate) async fn negotiate(
+    State(negotiation): State<Negotiation>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let api_version = match ApiVersion::of(&parts) {
+        Ok(api_version) => api_version,
+        Err(e) => return e.into_response(),
+    };
+    let request = Request::from_parts(parts, body);
+    let mut response =
+        version::scope(api_version, negotiation.numeric_json, next.run(request)).await;
+    if api_version.is_deprecated() {
+        let headers = response.headers_mut();
+        headers.insert("deprecation", HeaderValue::from_static("true"));
+        if let Some(sunset) = negotiation
+            .sunset
+            .and_then(|sunset| HeaderValue::from_str(&sunset).ok())
+        {
+            headers.insert("sunset", sunset);
+        }
+    }
+    response
+}
+
+#[tokio::test]
+async fn legacy_responses_are_marked_deprecated() {
+    use common_x::restful::axum::{Router, middleware::from_fn_with_state, routing::get};
+
+    let router = Router::new()
+        .route(
+            "/api/post/list",
+            get(|| async { format!("{:?}", version::current()) }),
+        )
+        .layer(from_fn_with_state(
+            Negotiation {
+                sunset: Some("Sat, 01 May 2027 00:00:00 GMT".to_string()),
+                numeric_json: false,
+            },
+            negotiate,
+        ));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/api/post/list", listener.local_addr().unwrap());
+    tokio::spawn(common_x::restful::axum::serve(listener, router));
+
+    let client = reqwest::Client::new();
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.headers()["deprecation"], "true");
+    assert_eq!(
+        response.headers()["sunset"],
+        "Sat, 01 May 2027 00:00:00 GMT"
+    );
+    assert_eq!(response.text().await.unwrap(), "V1");
+
+    let response = client
+        .get(&url)
+        .header(version::HEADER, "2")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.headers().get("deprecation").is_none());
+    assert!(response.headers().get("sunset").is_none());
+    assert_eq!(response.text().await.unwrap(), "V2");
+
+    let response = client.get(format!("{url}?v=9")).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,274 @@
+use std::time::Instant;
+
+use common_x::restful::axum::{
+    body::{Body, HttpBody, to_bytes},
+    extract::{Request, State},
+    http::{HeaderValue, header::AUTHORIZATION},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    atproto::next_tid, audit::Audit, error::AppError, lexicon::audit_event::AuditEventRow,
+};
+
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+/// What `Json` accepts by default; larger bodies are refused here already.
+const BODY_LIMIT: usize = 2 * 1024 * 1024;
+/// Largest response read for the uri of the record a write created.
+const RESULT_LIMIT: u64 = 64 * 1024;
+/// Fields of the signed `params` naming what a call is about, in order.
+const TARGET_FIELDS: [&str; 7] = [
+    "uri",
+    "target",
+    "post",
+    "did",
+    "id",
+    "section_id",
+    "section",
+];
+
+/// Leaves an audit event for every successful call of an
+/// `audit::is_audited` route. The actor is the one the handler verified,
+/// else the claimed one marked `unverified:`. The request id comes from
+/// `x-request-id` or is generated, and is returned in the same header.
+pub(crate) async fn record(State(audit): State<Audit>, request: Request, next: Next) -> Response {
+    let route = request.uri().path().to_owned();
+    if !crate::audit::is_audited(&route) {
+        return next.run(request).await;
+    }
+    let started = Instant::now();
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .map(str::to_owned)
+        .unwrap_or_else(next_tid);
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|auth| auth.to_str().ok())
+        .and_then(|auth| auth.strip_prefix("Bearer "))
+        .map(token_id);
+
+    let (parts, body) = request.into_parts();
+    let bytes = match to_bytes(body, BODY_LIMIT).await {
+        Ok(bytes) => bytes,
+        Err(e) => return AppError::TooLarge(e.to_string()).into_response(),
+    };
+    let params: Value = serde_json::from_slice(&bytes).unwrap_or_default();
+    let (response, verified) =
+        crate::audit::with_verified_actor(next.run(Request::from_parts(parts, Body::from(bytes))))
+            .await;
+
+    let (mut parts, body) = response.into_parts();
+    if let Ok(id) = HeaderValue::from_str(&request_id) {
+        parts.headers.insert(REQUEST_ID_HEADER, id);
+    }
+    if !parts.status.is_success() {
+        return Response::from_parts(parts, body);
+    }
+    // larger or streamed responses pass through unread
+    let (body, result) = if body
+        .size_hint()
+        .upper()
+        .is_some_and(|len| len <= RESULT_LIMIT)
+    {
+        match to_bytes(body, RESULT_LIMIT as usize).await {
+            Ok(bytes) => {
+                let result = serde_json::from_slice(&bytes).unwrap_or_default();
+                (Body::from(bytes), result)
+            }
+            Err(e) => {
+                return (reqwest::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+            }
+        }
+    } else {
+        (body, Value::Null)
+    };
+
+    audit.record(AuditEventRow {
+        id: 0,
+        actor: actor(verified, &params, token),
+        route,
+        target: target(&params, &result),
+        outcome: parts.status.as_u16().into(),
+        latency_ms: started.elapsed().as_millis() as i64,
+        request_id,
+        created: chrono::Local::now(),
+    });
+    Response::from_parts(parts, body)
+}
+
+/// The signer or repo the handler verified; else the signer of a signed
+/// body or the repo written to, as `unverified:` claims, or the bearer
+/// token.
+fn actor(verified: Option<String>, params: &Value, token: Option<String>) -> String {
+    if let Some(verified) = verified {
+        return verified;
+    }
+    ["did", "repo"]
+        .iter()
+        .find_map(|field| params[field].as_str().filter(|s| !s.is_empty()))
+        .map(|claimed| format!("unverified:{claimed}"))
+        .or(token)
+        .unwrap_or_else(|| "anonymous".to_string())
+}
+
+/// Names a token in the events without storing it.
+fn token_id(token: &str) -> String {
+    format!(
+        "token:{}",
+        hex::encode(&Sha256::digest(token.as_bytes())[..8])
+    )
+}
+
+fn target(params: &Value, result: &Value) -> Option<String> {
+    TARGET_FIELDS
+        .iter()
+        .find_map(|field| match &params["params"][field] {
+            Value::String(s) if !s.is_empty() => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        })
+        .or_else(|| {
+            // a record write names its record by repo, collection and rkey
+            let repo = params["repo"].as_str()?;
+            let nsid = params["value"]["$type"].as_str()?;
+            let rkey = params["rkey"].as_str().filter(|rkey| !rkey.is_empty())?;
+            Some(format!("at://{repo}/{nsid}/{rkey}"))
+        })
+        .or_else(|| {
+            // or, when the rkey was generated, by the uri the PDS returned
+            result
+                .pointer("/data/results/0/uri")
+                .and_then(Value::as_str)
+                .map(str::to_owned)
+        })
+}
+
+#[tokio::test]
+async fn writes_are_attributed_to_their_actor() {
+    use common_x::restful::axum::{
+        Json, Router,
+        middleware::from_fn_with_state,
+        routing::{get, post},
+    };
+    use serde_json::json;
+
+    let (audit, mut rx) = Audit::channel(16);
+    let router = Router::new()
+        .route(
+            "/api/record/create",
+            post(|| async {
+                crate::audit::verified_actor("did:ckb:alice");
+                Json(json!({
+                    "code": 200,
+                    "data": { "results": [{ "uri": "at://did:ckb:alice/app.bbs.post/3abc" }] }
+                }))
+            }),
+        )
+        .route(
+            "/api/admin/ban",
+            post(|| async { AppError::ValidateFailed("not an admin".to_string()) }),
+        )
+        .route("/api/admin/unban", post(|| async { "unbanned" }))
+        .route(
+            "/api/admin/flush_cache",
+            post(|| async { "x".repeat(RESULT_LIMIT as usize + 1) }),
+        )
+        .route("/api/post/detail", get(|| async { "post" }))
+        .layer(from_fn_with_state(audit, record));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(common_x::restful::axum::serve(listener, router));
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{url}/api/record/create"))
+        .bearer_auth("access-token")
+        .json(&json!({
+            "repo": "did:ckb:alice",
+            "rkey": "",
+            "value": { "$type": "app.bbs.post", "title": "hi" },
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let request_id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+    let event = rx.recv().await.unwrap();
+    assert_eq!(event.actor, "did:ckb:alice");
+    assert_eq!(event.route, "/api/record/create");
+    assert_eq!(
+        event.target.as_deref(),
+        Some("at://did:ckb:alice/app.bbs.post/3abc")
+    );
+    assert_eq!(event.outcome, 200);
+    assert_eq!(event.request_id, request_id);
+
+    // refused calls leave no event
+    let response = client
+        .post(format!("{url}/api/admin/ban"))
+        .header(REQUEST_ID_HEADER, "req-1")
+        .json(&json!({ "did": "did:ckb:admin" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    assert_eq!(response.headers()[REQUEST_ID_HEADER], "req-1");
+    assert!(rx.try_recv().is_err());
+
+    // a signer the handler did not verify is only a claim
+    client
+        .post(format!("{url}/api/admin/unban"))
+        .header(REQUEST_ID_HEADER, "req-2")
+        .json(&json!({
+            "params": { "section": "1", "did": "did:ckb:mallory" },
+            "did": "did:ckb:admin",
+            "signing_key_did": "did:key:z",
+            "signed_bytes": "",
+        }))
+        .send()
+        .await
+        .unwrap();
+    let event = rx.recv().await.unwrap();
+    assert_eq!(event.actor, "unverified:did:ckb:admin");
+    assert_eq!(event.route, "/api/admin/unban");
+    assert_eq!(event.target.as_deref(), Some("did:ckb:mallory"));
+    assert_eq!(event.outcome, 200);
+    assert_eq!(event.request_id, "req-2");
+
+    // a large response is passed on without being read
+    let response = client
+        .post(format!("{url}/api/admin/flush_cache"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.text().await.unwrap().len(),
+        RESULT_LIMIT as usize + 1
+    );
+    let event = rx.recv().await.unwrap();
+    assert_eq!(event.actor, "anonymous");
+    assert_eq!(event.target, None);
+
+    // reads are not audited
+    client
+        .get(format!("{url}/api/post/detail"))
+        .send()
+        .await
+        .unwrap();
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn bearer_tokens_are_hashed() {
+    let token = actor(None, &Value::Null, Some(token_id("secret")));
+    assert!(token.starts_with("token:"));
+    assert_eq!(token.len(), "token:".len() + 16);
+    assert!(!token.contains("secret"));
+    assert_eq!(actor(None, &Value::Null, None), "anonymous");
+}
@@ -0,0 +1,76 @@
+use common_x::restful::axum::{
+    body::{Body, Bytes, to_bytes},
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+
+/// Fields whose values never reach the logs.
+const REDACTED_FIELDS: [&str; 3] = ["signing_key", "signed_bytes", "password"];
+
+/// Log request and response bodies at DEBUG level. Only attached to the router
+/// when `AppConfig::debug_mode` is on, so production pays nothing for it.
+pub(crate) async fn body_log(request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_owned();
+
+    let (parts, body) = request.into_parts();
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => return (reqwest::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    debug!("request {path}: {}", render(&bytes));
+    let response = next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await;
+
+    let (parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return (reqwest::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+        }
+    };
+    debug!("response {path}: {}", render(&bytes));
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+fn render(bytes: &Bytes) -> String {
+    match serde_json::from_slice::<Value>(bytes) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        Err(_) => format!("<{} bytes>", bytes.len()),
+    }
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if REDACTED_FIELDS.contains(&key.as_str()) {
+                    *value = Value::String("<redacted>".to_owned());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+#[test]
+fn redacts_secrets_at_any_depth() {
+    let body = Bytes::from(
+        r#"{"did":"did:ckb:alice","signed_bytes":"00ff","params":{"password":"x","title":"hi"},"writes":[{"signing_key":"k"}]}"#,
+    );
+    let rendered = render(&body);
+    assert!(rendered.contains("did:ckb:alice"));
+    assert!(rendered.contains("\"title\":\"hi\""));
+    assert!(!rendered.contains("00ff"));
+    assert!(!rendered.contains("\"x\""));
+    assert!(!rendered.contains("\"k\""));
+    assert_eq!(render(&Bytes::from("not json")), "<8 bytes>");
+}
@@ -0,0 +1,81 @@
+use common_x::restful::axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{error::AppError, maintenance::Maintenance};
+
+/// Refuse the `maintenance::WRITES` while the appview is read only.
+pub(crate) async fn read_only(
+    State(maintenance): State<Maintenance>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(message) = maintenance.refuses(request.uri().path()) {
+        return AppError::Maintenance(message).into_response();
+    }
+    next.run(request).await
+}
+
+#[tokio::test]
+async fn writes_fail_while_read_only() {
+    use common_x::restful::axum::{
+        Router,
+        middleware::from_fn_with_state,
+        routing::{get, post},
+    };
+    use serde_json::Value;
+
+    let maintenance = Maintenance::new("back soon");
+    let router = Router::new()
+        .route("/api/record/create", post(|| async { "created" }))
+        .route("/api/notify/read", post(|| async { "read" }))
+        .route("/api/post/list", post(|| async { "posts" }))
+        .route("/api/post/detail", get(|| async { "post" }))
+        .route("/api/admin/maintenance", post(|| async { "toggled" }))
+        .layer(from_fn_with_state(maintenance.clone(), read_only));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(common_x::restful::axum::serve(listener, router));
+
+    let client = reqwest::Client::new();
+    let call =
+        |method: reqwest::Method, path: &str| client.request(method, format!("{url}{path}")).send();
+    let writes = [
+        (reqwest::Method::POST, "/api/record/create"),
+        (reqwest::Method::POST, "/api/notify/read"),
+    ];
+    let reads = [
+        (reqwest::Method::POST, "/api/post/list"),
+        (reqwest::Method::GET, "/api/post/detail"),
+        (reqwest::Method::POST, "/api/admin/maintenance"),
+    ];
+    for (method, path) in writes.iter().chain(&reads) {
+        let response = call(method.clone(), path).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK, "{path}");
+    }
+
+    maintenance.set(true, false, None);
+    for (method, path) in &writes {
+        let response = call(method.clone(), path).await.unwrap();
+        assert_eq!(
+            response.status(),
+            reqwest::StatusCode::SERVICE_UNAVAILABLE,
+            "{path}"
+        );
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "MaintenanceMode");
+        assert_eq!(body["message"], "back soon");
+    }
+    for (method, path) in &reads {
+        let response = call(method.clone(), path).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK, "{path}");
+    }
+
+    maintenance.set(false, false, None);
+    let response = call(reqwest::Method::POST, "/api/record/create")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+}
@@ -0,0 +1,5 @@
+pub(crate) mod api_version;
+pub(crate) mod apidoc_auth;
+pub(crate) mod audit;
+pub(crate) mod body_log;
+pub(crate) mod maintenance;
@@ -0,0 +1,242 @@
+use chrono::{DateTime, Datelike, Duration, Local, NaiveTime};
+use color_eyre::{Result, eyre::eyre};
+use sea_query::{Expr, ExprTrait, Func, PostgresQueryBuilder, UnionType};
+use sea_query_sqlx::SqlxBinder;
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+
+use crate::{
+    config::QuotaConfig,
+    db,
+    lexicon::{comment::Comment, post::Post},
+};
+
+/// Posting quota of one author. `record::create` enforces exactly what
+/// `/api/repo/quota` reports, so both go through `Quota::compute`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Quota {
+    pub daily_posts: QuotaWindow,
+    pub weekly_posts: QuotaWindow,
+    pub section_cooldown_secs: u64,
+    /// Sections the author has to wait for before posting or commenting.
+    pub cooldowns: Vec<Cooldown>,
+}
+
+/// A limit of 0 is unlimited and has no `remaining`.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaWindow {
+    pub limit: u32,
+    pub used: i64,
+    pub remaining: Option<i64>,
+    pub reset: DateTime<Local>,
+}
+
+impl QuotaWindow {
+    fn build(limit: u32, used: i64, reset: DateTime<Local>) -> Self {
+        Self {
+            limit,
+            used,
+            remaining: (limit > 0).then(|| (limit as i64 - used).max(0)),
+            reset,
+        }
+    }
+
+    const fn exhausted(&self) -> bool {
+        matches!(self.remaining, Some(0))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Cooldown {
+    pub section_id: String,
+    pub available: DateTime<Local>,
+}
+
+fn day_start(now: DateTime<Local>) -> DateTime<Local> {
+    now.date_naive()
+        .and_time(NaiveTime::MIN)
+        .and_local_timezone(Local)
+        .earliest()
+        .unwrap_or(now)
+}
+
+fn week_start(now: DateTime<Local>) -> DateTime<Local> {
+    day_start(now) - Duration::days(now.weekday().num_days_from_monday() as i64)
+}
+
+impl Quota {
+    pub fn build(
+        config: &QuotaConfig,
+        now: DateTime<Local>,
+        daily_used: i64,
+        weekly_used: i64,
+        last_activity: Vec<(i32, DateTime<Local>)>,
+    ) -> Self {
+        let cooldown = Duration::seconds(config.section_cooldown_secs as i64);
+        Self {
+            daily_posts: QuotaWindow::build(
+                config.daily_posts,
+                daily_used,
+                day_start(now) + Duration::days(1),
+            ),
+            weekly_posts: QuotaWindow::build(
+                config.weekly_posts,
+                weekly_used,
+                week_start(now) + Duration::days(7),
+            ),
+            section_cooldown_secs: config.section_cooldown_secs,
+            cooldowns: last_activity
+                .into_iter()
+                .map(|(section_id, last)| Cooldown {
+                    section_id: section_id.to_string(),
+                    available: last + cooldown,
+                })
+                .filter(|cooldown| cooldown.available > now)
+                .collect(),
+        }
+    }
+
+    pub async fn compute(db: &Pool<Postgres>, config: &QuotaConfig, repo: &str) -> Result<Self> {
+        let now = Local::now();
+        let daily_used = Self::count_posts(db, repo, day_start(now)).await?;
+        let weekly_used = Self::count_posts(db, repo, week_start(now)).await?;
+        let since = now - Duration::seconds(config.section_cooldown_secs as i64);
+        let (sql, values) = Self::build_last_activity(repo, since).build_sqlx(PostgresQueryBuilder);
+        let last_activity: Vec<(i32, DateTime<Local>)> = db::fetch_all(db, &sql, values)
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+        Ok(Self::build(
+            config,
+            now,
+            daily_used,
+            weekly_used,
+            last_activity,
+        ))
+    }
+
+    async fn count_posts(db: &Pool<Postgres>, repo: &str, since: DateTime<Local>) -> Result<i64> {
+        let (sql, values) = sea_query::Query::select()
+            .expr(Expr::col(Post::Uri).count())
+            .from(Post::Table)
+            .and_where(Expr::col(Post::Repo).eq(repo))
+            .and_where(Expr::col(Post::IsDraft).eq(false))
+            .and_where(Expr::col(Post::Created).gte(since))
+            .build_sqlx(PostgresQueryBuilder);
+        let (count,): (i64,) = db::fetch_one(db, &sql, values)
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+        Ok(count)
+    }
+
+    /// Latest post or comment of `repo` per section since `since`.
+    pub fn build_last_activity(repo: &str, since: DateTime<Local>) -> sea_query::SelectStatement {
+        let mut activity = sea_query::Query::select()
+            .columns([Post::SectionId, Post::Created])
+            .from(Post::Table)
+            .and_where(Expr::col(Post::Repo).eq(repo))
+            .and_where(Expr::col(Post::IsDraft).eq(false))
+            .and_where(Expr::col(Post::Created).gte(since))
+            .take();
+        activity.union(
+            UnionType::All,
+            sea_query::Query::select()
+                .columns([Comment::SectionId, Comment::Created])
+                .from(Comment::Table)
+                .and_where(Expr::col(Comment::Repo).eq(repo))
+                .and_where(Expr::col(Comment::Created).gte(since))
+                .take(),
+        );
+        sea_query::Query::select()
+            .column("section_id")
+            .expr(Func::max(Expr::col("created")))
+            .from_subquery(activity, "activity")
+            .group_by_col("section_id")
+            .take()
+    }
+
+    /// Why the author may not publish in `section_id` now, if anything.
+    /// Comments only wait for the section cooldown; posts also use up the
+    /// daily and weekly quota.
+    pub fn check(&self, section_id: i32, is_post: bool) -> Result<(), String> {
+        if is_post && self.daily_posts.exhausted() {
+            return Err(format!(
+                "daily post quota of {} used up, resets at {}",
+                self.daily_posts.limit,
+                self.daily_posts.reset.to_rfc3339()
+            ));
+        }
+        if is_post && self.weekly_posts.exhausted() {
+            return Err(format!(
+                "weekly post quota of {} used up, resets at {}",
+                self.weekly_posts.limit,
+                self.weekly_posts.reset.to_rfc3339()
+            ));
+        }
+        let section_id = section_id.to_string();
+        if let Some(cooldown) = self.cooldowns.iter().find(|c| c.section_id == section_id) {
+            return Err(format!(
+                "section cooldown, next post at {}",
+                cooldown.available.to_rfc3339()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn quota_runs_out_at_the_limit() {
+    let config = QuotaConfig {
+        daily_posts: 2,
+        weekly_posts: 5,
+        section_cooldown_secs: 30,
+    };
+    let now = Local::now();
+
+    let quota = Quota::build(&config, now, 1, 1, vec![]);
+    assert_eq!(quota.daily_posts.remaining, Some(1));
+    assert!(quota.check(1, true).is_ok());
+
+    let quota = Quota::build(&config, now, 2, 2, vec![]);
+    assert_eq!(quota.daily_posts.remaining, Some(0));
+    assert!(quota.check(1, true).is_err());
+    // comments are not counted against the post quota
+    assert!(quota.check(1, false).is_ok());
+    assert!(quota.daily_posts.reset > now);
+    assert!(quota.weekly_posts.reset >= quota.daily_posts.reset);
+
+    let quota = Quota::build(&config, now, 0, 5, vec![]);
+    assert_eq!(quota.weekly_posts.remaining, Some(0));
+    assert!(quota.check(1, true).is_err());
+
+    let unlimited = QuotaConfig {
+        daily_posts: 0,
+        weekly_posts: 0,
+        section_cooldown_secs: 0,
+    };
+    let quota = Quota::build(&unlimited, now, 100, 100, vec![]);
+    assert_eq!(quota.daily_posts.remaining, None);
+    assert!(quota.check(1, true).is_ok());
+}
+
+#[test]
+fn cooldown_is_per_section() {
+    let config = QuotaConfig {
+        daily_posts: 0,
+        weekly_posts: 0,
+        section_cooldown_secs: 30,
+    };
+    let now = Local::now();
+    let activity = vec![
+        (1, now - Duration::seconds(10)),
+        (2, now - Duration::seconds(30)),
+    ];
+    let quota = Quota::build(&config, now, 0, 0, activity);
+    assert_eq!(quota.cooldowns.len(), 1);
+    assert!(quota.check(1, false).is_err());
+    assert!(quota.check(2, true).is_ok());
+    assert!(quota.check(3, true).is_ok());
+
+    let sql = Quota::build_last_activity("did:ckb:alice", now).to_string(PostgresQueryBuilder);
+    assert!(sql.contains("UNION ALL"));
+    assert!(sql.ends_with("GROUP BY \"section_id\""));
+}
@@ -0,0 +1,177 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use color_eyre::{Result, eyre::eyre};
+use sea_query::{DynIden, Expr, ExprTrait, IntoIden, PostgresQueryBuilder, UnionType};
+use sea_query_sqlx::SqlxBinder;
+use serde_json::{Map, Value, json};
+use sqlx::{Pool, Postgres};
+
+use crate::{
+    AppView,
+    api::build_author,
+    db,
+    lexicon::{
+        comment::Comment, like::Like, post::Post, reply::Reply, section_stats::SectionStats,
+    },
+};
+
+/// Authors whose cached stats are rebuilt per batch.
+const BATCH_SIZE: usize = 100;
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// What a recount covers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecountScope {
+    All,
+    Section(i32),
+    Repo(String),
+}
+
+impl RecountScope {
+    fn filter(&self) -> Option<Expr> {
+        match self {
+            Self::All => None,
+            Self::Section(id) => Some(Expr::col("section_id").eq(*id)),
+            Self::Repo(did) => Some(Expr::col("repo").eq(did.as_str())),
+        }
+    }
+}
+
+/// The source tables author stats and section statistics derive from.
+fn source_tables() -> [(&'static str, DynIden); 4] {
+    [
+        ("post", Post::Table.into_iden()),
+        ("comment", Comment::Table.into_iden()),
+        ("reply", Reply::Table.into_iden()),
+        ("like", Like::Table.into_iden()),
+    ]
+}
+
+/// Held while a recount runs; only one may run at a time.
+pub struct RecountGuard(());
+
+impl RecountGuard {
+    pub fn try_acquire() -> Option<Self> {
+        RUNNING
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+            .then_some(Self(()))
+    }
+}
+
+impl Drop for RecountGuard {
+    fn drop(&mut self) {
+        RUNNING.store(false, Ordering::Release);
+    }
+}
+
+pub fn build_row_count(table: DynIden, scope: &RecountScope) -> sea_query::SelectStatement {
+    sea_query::Query::select()
+        .expr(Expr::cust("COUNT(*)"))
+        .from(table)
+        .and_where_option(scope.filter())
+        .take()
+}
+
+/// Everyone who posted, commented, replied or liked within the scope.
+pub fn build_authors(scope: &RecountScope) -> sea_query::SelectStatement {
+    build_authors_matching(scope.filter())
+}
+
+/// Everyone with a post, comment, reply or like matching `filter`, which may
+/// use the `repo`, `section_id` and `created` columns they all have.
+pub fn build_authors_matching(filter: Option<Expr>) -> sea_query::SelectStatement {
+    let mut selects = source_tables().into_iter().map(|(_, table)| {
+        sea_query::Query::select()
+            .column("repo")
+            .from(table)
+            .and_where_option(filter.clone())
+            .take()
+    });
+    let mut authors = selects.next().expect("source tables are not empty");
+    for select in selects {
+        authors.union(UnionType::Distinct, select);
+    }
+    authors
+}
+
+/// Rows of each source table within the scope.
+pub async fn row_counts(db: &Pool<Postgres>, scope: &RecountScope) -> Result<Map<String, Value>> {
+    let mut counts = Map::new();
+    for (name, table) in source_tables() {
+        let (sql, values) = build_row_count(table, scope).build_sqlx(PostgresQueryBuilder);
+        let (count,): (i64,) = db::fetch_one(db, &sql, values)
+            .await
+            .map_err(|e| eyre!("exec sql failed: {e}"))?;
+        counts.insert(name.to_string(), json!(count));
+    }
+    Ok(counts)
+}
+
+/// Rebuilds the cached stats of every author in the scope, recomputes the
+/// stored section counters and drops the cached sections. Everything else
+/// is counted from the source tables on read.
+pub async fn run(state: AppView, scope: RecountScope, _guard: RecountGuard) {
+    let authors = match &scope {
+        RecountScope::Repo(did) => vec![did.clone()],
+        scope => {
+            let (sql, values) = build_authors(scope).build_sqlx(PostgresQueryBuilder);
+            match db::fetch_all::<(String,), _>(&state.db, &sql, values).await {
+                Ok(rows) => rows.into_iter().map(|(did,)| did).collect(),
+                Err(e) => {
+                    error!("recount {scope:?} failed: {e}");
+                    return;
+                }
+            }
+        }
+    };
+
+    info!("recount {scope:?}: {} authors", authors.len());
+    for (i, batch) in authors.chunks(BATCH_SIZE).enumerate() {
+        for did in batch {
+            state.caches.invalidate_author(did).await;
+            build_author(&state, did).await;
+        }
+        info!(
+            "recount {scope:?}: {}/{} authors",
+            i * BATCH_SIZE + batch.len(),
+            authors.len()
+        );
+    }
+    if let Err(e) = SectionStats::refresh(&state.db).await {
+        error!("recount {scope:?}: refresh section stats failed: {e}");
+    }
+    let section_id = match scope {
+        RecountScope::Section(id) => id,
+        _ => 0,
+    };
+    state.caches.invalidate_section(section_id).await;
+    info!("recount {scope:?} done");
+}
+
+#[test]
+fn recount_scopes() {
+    let sql = build_row_count(Post::Table.into_iden(), &RecountScope::Section(3))
+        .to_string(PostgresQueryBuilder);
+    assert_eq!(
+        sql,
+        "SELECT COUNT(*) FROM \"post\" WHERE \"section_id\" = 3"
+    );
+    let sql = build_row_count(Like::Table.into_iden(), &RecountScope::All)
+        .to_string(PostgresQueryBuilder);
+    assert_eq!(sql, "SELECT COUNT(*) FROM \"like\"");
+
+    let sql = build_authors(&RecountScope::Repo("did:ckb:alice".to_string()))
+        .to_string(PostgresQueryBuilder);
+    assert!(sql.starts_with("SELECT \"repo\" FROM \"post\" WHERE \"repo\" = 'did:ckb:alice'"));
+    assert_eq!(sql.matches(" UNION ").count(), 3);
+}
+
+#[test]
+fn one_recount_at_a_time() {
+    let guard = RecountGuard::try_acquire().unwrap();
+    assert!(RecountGuard::try_acquire().is_none());
+    drop(guard);
+    assert!(RecountGuard::try_acquire().is_some());
+}
@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::config::ReadinessConfig;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Connection {
+    #[default]
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// What the firehose subscription is doing, for operators.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RelayerStatus {
+    pub connection: Connection,
+    pub endpoint: String,
+    pub connected_since: Option<DateTime<Local>>,
+    pub last_frame: Option<DateTime<Local>>,
+    /// Seq up to which every commit was handled, where a reconnect resumes.
+    /// It is saved every `cursor_save_every` commits, so a start resumes
+    /// close to where the last run stopped.
+    pub cursor: Option<i64>,
+    /// Commits handled since start.
+    pub commits: u64,
+    /// Record operations of the commits handled since start.
+    pub records: u64,
+    /// Commits and events waiting for a worker.
+    pub queued: u64,
+    /// Connections made since start.
+    pub connections: u64,
+    pub last_error: Option<String>,
+}
+
+/// Shared state of the relayer subscription, written by its task and read
+/// by the status endpoints.
+#[derive(Clone)]
+pub struct RelayerHealth {
+    status: Arc<watch::Sender<RelayerStatus>>,
+    restarts: Arc<watch::Sender<u64>>,
+    shutdown: Arc<watch::Sender<bool>>,
+    config: ReadinessConfig,
+}
+
+impl RelayerHealth {
+    pub fn new(endpoint: &str, config: &ReadinessConfig) -> Self {
+        let (status, _) = watch::channel(RelayerStatus {
+            endpoint: endpoint.to_string(),
+            ..Default::default()
+        });
+        let (restarts, _) = watch::channel(0);
+        let (shutdown, _) = watch::channel(false);
+        Self {
+            status: Arc::new(status),
+            restarts: Arc::new(restarts),
+            shutdown: Arc::new(shutdown),
+            config: config.clone(),
+        }
+    }
+
+    pub fn status(&self) -> RelayerStatus {
+        self.status.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<RelayerStatus> {
+        self.status.subscribe()
+    }
+
+    pub fn connecting(&self) {
+        self.status
+            .send_modify(|status| status.connection = Connection::Connecting);
+    }
+
+    pub fn connected(&self) {
+        self.status.send_modify(|status| {
+            status.connection = Connection::Connected;
+            status.connected_since = Some(Local::now());
+            status.connections += 1;
+        });
+    }
+
+    pub fn received(&self) {
+        self.status
+            .send_modify(|status| status.last_frame = Some(Local::now()));
+    }
+
+    /// Sets the cursor saved by an earlier run, before the first connection.
+    pub fn resume_from(&self, seq: Option<i64>) {
+        self.status.send_modify(|status| status.cursor = seq);
+    }
+
+    pub fn committed(&self, records: usize) {
+        self.status.send_modify(|status| {
+            status.commits += 1;
+            status.records += records as u64;
+        });
+    }
+
+    /// Every commit and event up to `seq` was handled.
+    pub fn handled(&self, seq: i64) {
+        self.status.send_modify(|status| status.cursor = Some(seq));
+    }
+
+    pub fn queued(&self, jobs: usize) {
+        self.status
+            .send_modify(|status| status.queued = jobs as u64);
+    }
+
+    /// Commits and records handled since start.
+    pub fn activity(&self) -> (u64, u64) {
+        let status = self.status.borrow();
+        (status.commits, status.records)
+    }
+
+    pub fn disconnected(&self, error: Option<String>) {
+        self.status.send_modify(|status| {
+            status.connection = Connection::Disconnected;
+            status.connected_since = None;
+            if error.is_some() {
+                status.last_error = error;
+            }
+        });
+    }
+
+    /// Asks the subscription task to drop its connection and connect again.
+    pub fn restart(&self) {
+        self.restarts.send_modify(|n| *n += 1);
+    }
+
+    /// Changes once for every `restart` after this call.
+    pub fn restarts(&self) -> watch::Receiver<u64> {
+        self.restarts.subscribe()
+    }
+
+    /// Asks the subscription task to save its cursor and stop, when the
+    /// appview shuts down.
+    pub fn shut_down(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Resolves once `shut_down` is called, right away if it was already.
+    pub async fn shutting_down(&self) {
+        self.shutdown.subscribe().wait_for(|down| *down).await.ok();
+    }
+
+    /// Connected, and a frame arrived within `relayer_max_lag_secs`; a quiet
+    /// new connection counts from when it was made.
+    pub fn is_healthy(&self, now: DateTime<Local>) -> bool {
+        let status = self.status.borrow();
+        status.connection == Connection::Connected
+            && status
+                .last_frame
+                .max(status.connected_since)
+                .is_some_and(|at| {
+                    (now - at).num_seconds() <= self.config.relayer_max_lag_secs as i64
+                })
+    }
+
+    /// Whether `/readyz` fails while the relayer is not healthy.
+    pub const fn lag_marks_unready(&self) -> bool {
+        self.config.relayer_lag_unready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn healthy_while_frames_arrive() {
+        let health = RelayerHealth::new("wss://relay.example", &ReadinessConfig::default());
+        let now = Local::now();
+        assert!(!health.is_healthy(now));
+
+        health.connected();
+        assert!(health.is_healthy(Local::now()));
+        let max_lag =
+            chrono::Duration::seconds(ReadinessConfig::default().relayer_max_lag_secs as i64);
+        assert!(!health.is_healthy(Local::now() + max_lag + chrono::Duration::seconds(1)));
+
+        health.received();
+        let last_frame = health.status().last_frame.unwrap();
+        assert!(health.is_healthy(last_frame + max_lag));
+
+        health.disconnected(Some("closed".to_string()));
+        assert!(!health.is_healthy(last_frame));
+        // the last error outlives a clean disconnect
+        health.disconnected(None);
+        assert_eq!(health.status().last_error.as_deref(), Some("closed"));
+    }
+}
@@ -1,21 +1,82 @@
-use atrium_api::com::atproto::sync::subscribe_repos::Commit;
+use std::time::Duration;
+
+use atrium_api::com::atproto::sync::subscribe_repos::{Account, Commit, Identity, RepoOp};
 use atrium_repo::{Repository, blockstore::CarStore};
-use color_eyre::Result;
+use color_eyre::{Result, eyre::eyre};
+use sea_query::{Cond, Expr, ExprTrait, IntoIden, PostgresQueryBuilder};
+use sea_query_sqlx::SqlxBinder;
 use serde_json::Value;
-use sqlx::{Executor, query};
+use sqlx::{Pool, Postgres};
 
 use crate::{
     AppView,
-    atproto::{NSID_COMMENT, NSID_LIKE, NSID_POST, NSID_REPLY},
-    lexicon::{comment::Comment, like::Like, post::Post, reply::Reply},
-    relayer::subscription::CommitHandler,
+    atproto::{NSID_COMMENT, NSID_FOLLOW, NSID_LIKE, NSID_POST, NSID_PROFILE, NSID_REPLY},
+    config::ReconnectConfig,
+    db,
+    lexicon::{
+        ThreadError,
+        comment::Comment,
+        follow::Follow,
+        like::Like,
+        notify::Notify,
+        parked_record::{ParkedRecord, ParkedRow},
+        post::Post,
+        profile::Profile,
+        removed_repo::{ACCOUNT_INACTIVE, RemovedRepo, content_tables},
+        reply::Reply,
+        repo_state::RepoState,
+        section::{Section, in_archived_section},
+        status::Status,
+    },
+    limits,
+    relayer::subscription::{CommitHandler, RepoSubscription, Tombstone, keep_subscribed},
 };
 
+pub(crate) mod health;
 pub(crate) mod stream;
 pub(crate) mod subscription;
+pub(crate) mod workers;
+
+/// Keeps the appview subscribed to the relayer at `endpoint` until its
+/// health is shut down. A panic while indexing ends the subscription task;
+/// it is started again after `initial_delay_ms`, from the last cursor.
+pub async fn supervise(bbs: AppView, endpoint: String, config: ReconnectConfig) {
+    let health = bbs.relayer.clone();
+    loop {
+        let endpoint = endpoint.clone();
+        let task = tokio::spawn(keep_subscribed(
+            move |cursor| {
+                let endpoint = endpoint.clone();
+                async move { RepoSubscription::new(&endpoint, cursor).await }
+            },
+            bbs.clone(),
+            health.clone(),
+            config.clone(),
+        ));
+        match task.await {
+            Err(e) if e.is_panic() && !health.is_shut_down() => {
+                error!("Relayer subscription panicked, starting it again: {e}");
+                health.disconnected(Some(e.to_string()));
+                tokio::time::sleep(Duration::from_millis(config.initial_delay_ms)).await;
+            }
+            _ => return,
+        }
+    }
+}
+
+/// Authors who removed their data only have their deletes indexed until
+/// the suppression ends, so replayed or new records do not bring it back.
+fn is_indexed(action: &str, suppressed: bool) -> bool {
+    match action {
+        "delete" => true,
+        "create" | "update" => !suppressed,
+        _ => false,
+    }
+}
 
 impl CommitHandler for AppView {
     async fn handle_commit(&self, commit: &Commit) -> Result<()> {
+        self.maintenance.relayer_resumed().await;
         debug!("Commit: {:?}", commit.commit);
 
         let mut repo = Repository::open(
@@ -24,157 +85,734 @@ impl CommitHandler for AppView {
         )
         .await?;
 
-        let mut posts_to_delete = vec![];
-        let mut comments_to_delete = vec![];
-        let mut replies_to_delete = vec![];
-        let mut likes_to_delete = vec![];
+        RepoState::upsert(
+            &self.db,
+            commit.repo.as_str(),
+            commit.rev.as_str(),
+            &commit.commit.0.to_string(),
+        )
+        .await
+        .map_err(|e| error!("RepoState::upsert failed: {e}"))
+        .ok();
 
+        let suppressed = RemovedRepo::is_suppressed(&self.db, commit.repo.as_str()).await;
+        let mut records = Vec::with_capacity(commit.ops.len());
         for op in &commit.ops {
-            info!("Operation: {:?}", op);
-            match op.action.as_str() {
-                "create" | "update" | "delete" => (),
-                _ => continue,
-            }
-            let mut s = op.path.split('/');
-            let collection = s.next().expect("op.path is empty");
-
-            let repo_str = commit.repo.as_str();
-            let uri = format!("at://{}/{}", repo_str, op.path);
-            if let Ok(Some(record)) = repo.get_raw::<Value>(&op.path).await {
-                debug!("Record: {:?}", record);
+            debug!("Operation: {:?}", op);
+            if !is_indexed(&op.action, suppressed) {
+                debug!("skip {} of {}", op.action, op.path);
                 continue;
-                match collection {
-                    NSID_POST => match op.action.as_str() {
-                        "create" | "update" => {
-                            let cid =
-                                format!("{}", op.cid.clone().map(|cid| cid.0).unwrap_or_default());
-                            info!("{} post: {:?}", op.action, &record);
-                            Post::insert(&self.db, repo_str, &record, &uri, &cid)
-                                .await
-                                .map_err(|e| error!("Post::insert failed: {e}"))
-                                .ok();
-                        }
-                        "delete" => {
-                            posts_to_delete.push(uri.clone());
-                            info!("Marked post for deletion: {}", uri);
-                        }
-                        _ => continue,
-                    },
-                    NSID_COMMENT => match op.action.as_str() {
-                        "create" | "update" => {
-                            let cid =
-                                format!("{}", op.cid.clone().map(|cid| cid.0).unwrap_or_default());
-                            info!("{} comment: {:?}", op.action, &record);
-                            Comment::insert(&self.db, repo_str, &record, &uri, &cid)
-                                .await
-                                .map_err(|e| error!("Comment::insert failed: {e}"))
-                                .ok();
-                        }
-                        "delete" => {
-                            comments_to_delete.push(uri.clone());
-                            info!("Marked comment for deletion: {}", uri);
-                        }
-                        _ => continue,
-                    },
-                    NSID_REPLY => match op.action.as_str() {
-                        "create" | "update" => {
-                            let cid =
-                                format!("{}", op.cid.clone().map(|cid| cid.0).unwrap_or_default());
-                            info!("{} reply: {:?}", op.action, &record);
-                            Reply::insert(&self.db, repo_str, &record, &uri, &cid)
-                                .await
-                                .map_err(|e| error!("Reply::insert failed: {e}"))
-                                .ok();
-                        }
-                        "delete" => {
-                            replies_to_delete.push(uri.clone());
-                            info!("Marked reply for deletion: {}", uri);
-                        }
-                        _ => continue,
-                    },
-                    NSID_LIKE => match op.action.as_str() {
-                        "create" | "update" => {
-                            let cid =
-                                format!("{}", op.cid.clone().map(|cid| cid.0).unwrap_or_default());
-                            info!("{} like: {:?}", op.action, &record);
-                            Like::insert(&self.db, repo_str, &record, &uri, &cid)
-                                .await
-                                .map_err(|e| error!("Like::insert failed: {e}"))
-                                .ok();
-                        }
-                        "delete" => {
-                            likes_to_delete.push(uri.clone());
-                            info!("Marked like for deletion: {}", uri);
-                        }
-                        _ => continue,
-                    },
-                    _ => continue,
+            }
+            // a delete carries no record, only the path it removes
+            let record = if op.action == "delete" {
+                Value::Null
+            } else if let Ok(Some(record)) = repo.get_raw::<Value>(&op.path).await {
+                if let Err(e) = limits::check_record(&record, &self.limits) {
+                    warn!("skip {} of {}: {e}", op.action, op.path);
+                    continue;
                 }
+                debug!("Record: {:?}", record);
+                record
             } else {
                 error!("FAILED: could not find item with operation {}", op.path);
+                continue;
+            };
+            records.push((op, record));
+        }
+        self.index_ops(commit.repo.as_str(), &records).await;
+        Ok(())
+    }
+
+    async fn save_cursor(&self, seq: i64) -> Result<()> {
+        Status::save_firehose_cursor(&self.db, seq).await
+    }
+
+    async fn handle_identity(&self, identity: &Identity) -> Result<()> {
+        let did = identity.did.as_str();
+        debug!("Identity: {did} {:?}", identity.handle);
+        self.caches.invalidate_handle(did).await;
+        self.caches.invalidate_author(did).await;
+        Ok(())
+    }
+
+    async fn handle_account(&self, account: &Account) -> Result<()> {
+        let did = account.did.as_str();
+        match (account.active, account.status.as_deref()) {
+            (true, _) => self.hide_account(did, false).await?,
+            (false, Some("deleted")) => self.purge_account(did).await?,
+            (false, status) => {
+                info!("account {did} is inactive: {status:?}");
+                self.hide_account(did, true).await?;
+            }
+        }
+        self.caches.invalidate_author(did).await;
+        Ok(())
+    }
+
+    async fn handle_tombstone(&self, tombstone: &Tombstone) -> Result<()> {
+        let did = tombstone.did.as_str();
+        self.purge_account(did).await?;
+        self.caches.invalidate_author(did).await;
+        Ok(())
+    }
+}
+
+/// Deletes the rows of `table` whose uri is one of `uris`.
+async fn delete_uris(db: &Pool<Postgres>, table: impl IntoIden, uris: &[String]) -> Result<()> {
+    let (sql, values) = sea_query::Query::delete()
+        .from_table(table)
+        .and_where(Expr::col("uri").is_in(uris.iter().map(String::as_str)))
+        .build_sqlx(PostgresQueryBuilder);
+    db::execute(db, &sql, values).await?;
+    Ok(())
+}
+
+/// The post or comment a comment or reply failed on because it is not
+/// indexed yet, if that is why it failed.
+fn awaited_parent(e: &color_eyre::Report) -> Option<&str> {
+    match e.downcast_ref::<ThreadError>()? {
+        ThreadError::PostNotFound(uri) | ThreadError::CommentNotFound(uri) => Some(uri),
+        _ => None,
+    }
+}
+
+/// Whether the post or comment at `uri` is indexed.
+async fn is_indexed_uri(db: &Pool<Postgres>, uri: &str) -> Result<bool> {
+    let table = if uri.contains(&format!("/{NSID_COMMENT}/")) {
+        Comment::Table.into_iden()
+    } else {
+        Post::Table.into_iden()
+    };
+    let (sql, values) = sea_query::Query::select()
+        .column("uri")
+        .from(table)
+        .and_where(Expr::col("uri").eq(uri))
+        .build_sqlx(PostgresQueryBuilder);
+    let row: Option<(String,)> = db::fetch_optional(db, &sql, values).await?;
+    Ok(row.is_some())
+}
+
+/// Uris deleted by a commit, removed in one statement per table.
+#[derive(Default)]
+struct PendingDeletes {
+    posts: Vec<String>,
+    comments: Vec<String>,
+    replies: Vec<String>,
+    likes: Vec<String>,
+    follows: Vec<String>,
+    /// Repos whose profile record was deleted.
+    profiles: Vec<String>,
+}
+
+impl AppView {
+    /// Hides the posts, comments and replies of an account the relayer
+    /// reported inactive, or shows them again once it is active. Content
+    /// hidden for other reasons is left alone.
+    async fn hide_account(&self, did: &str, hidden: bool) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        for (_, table) in content_tables() {
+            let statement = if hidden {
+                RemovedRepo::build_hide(table, did, ACCOUNT_INACTIVE)
+            } else {
+                RemovedRepo::build_unhide(table, did, ACCOUNT_INACTIVE)
+            };
+            let (sql, values) = statement.build_sqlx(PostgresQueryBuilder);
+            db::execute(&mut *tx, &sql, values).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Deletes all that a deleted account wrote, and the threads of its
+    /// posts with them.
+    async fn purge_account(&self, did: &str) -> Result<()> {
+        let (sql, values) = sea_query::Query::select()
+            .column(Post::Uri)
+            .from(Post::Table)
+            .and_where(Expr::col(Post::Repo).eq(did))
+            .build_sqlx(PostgresQueryBuilder);
+        let posts: Vec<(String,)> = db::fetch_all(&self.db, &sql, values).await?;
+        let posts: Vec<String> = posts.into_iter().map(|(uri,)| uri).collect();
+        if !posts.is_empty() {
+            Post::delete_all(&self.db, &posts).await?;
+        }
+
+        let mut tx = self.db.begin().await?;
+        for table in [
+            Comment::Table.into_iden(),
+            Reply::Table.into_iden(),
+            Like::Table.into_iden(),
+            Follow::Table.into_iden(),
+            Profile::Table.into_iden(),
+        ] {
+            let (sql, values) = sea_query::Query::delete()
+                .from_table(table)
+                .and_where(Expr::col("repo").eq(did))
+                .build_sqlx(PostgresQueryBuilder);
+            db::execute(&mut *tx, &sql, values).await?;
+        }
+        let (sql, values) = sea_query::Query::delete()
+            .from_table(Notify::Table)
+            .cond_where(
+                Cond::any()
+                    .add(Expr::col(Notify::Sender).eq(did))
+                    .add(Expr::col(Notify::Receiver).eq(did)),
+            )
+            .build_sqlx(PostgresQueryBuilder);
+        db::execute(&mut *tx, &sql, values).await?;
+        tx.commit().await?;
+        info!("purged the content of deleted account {did}");
+        Ok(())
+    }
+
+    /// Indexes the ops of one commit in order, then the deletes they
+    /// collected. A failing op is logged and skipped so the rest still apply.
+    async fn index_ops(&self, repo: &str, ops: &[(&RepoOp, Value)]) {
+        let mut deletes = PendingDeletes::default();
+        for (op, record) in ops {
+            let collection = op.path.split('/').next().expect("op.path is empty");
+            let uri = format!("at://{repo}/{}", op.path);
+            if collection == NSID_PROFILE {
+                self.caches.invalidate_author(repo).await;
             }
+            // one failing op must not drop the rest of the commit
+            if let Err(e) = self
+                .index_op(collection, repo, op, &uri, record, &mut deletes)
+                .await
+            {
+                error!("FAILED: {} {}: {e}", op.action, op.path);
+            }
+        }
+
+        if !deletes.posts.is_empty() {
+            Post::delete_all(&self.db, &deletes.posts)
+                .await
+                .map_err(|e| error!("sql execute failed: {e}"))
+                .ok();
+        }
+
+        if !deletes.comments.is_empty() {
+            delete_uris(&self.db, Comment::Table, &deletes.comments)
+                .await
+                .map_err(|e| error!("sql execute failed: {e}"))
+                .ok();
         }
 
-        if !posts_to_delete.is_empty() {
-            self.db
-                .execute(query(&format!(
-                    "DELETE FROM post WHERE uri IN ({})",
-                    posts_to_delete
-                        .iter()
-                        .map(|uri| format!("'{uri}'"))
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                )))
+        if !deletes.replies.is_empty() {
+            delete_uris(&self.db, Reply::Table, &deletes.replies)
                 .await
                 .map_err(|e| error!("sql execute failed: {e}"))
                 .ok();
         }
 
-        if !comments_to_delete.is_empty() {
-            self.db
-                .execute(query(&format!(
-                    "DELETE FROM comment WHERE uri IN ({})",
-                    comments_to_delete
-                        .iter()
-                        .map(|uri| format!("'{uri}'"))
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                )))
+        if !deletes.likes.is_empty() {
+            Like::delete(&self.db, &deletes.likes)
                 .await
                 .map_err(|e| error!("sql execute failed: {e}"))
                 .ok();
         }
 
-        if !replies_to_delete.is_empty() {
-            self.db
-                .execute(query(&format!(
-                    "DELETE FROM reply WHERE uri IN ({})",
-                    replies_to_delete
-                        .iter()
-                        .map(|uri| format!("'{uri}'"))
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                )))
+        if !deletes.follows.is_empty() {
+            delete_uris(&self.db, Follow::Table, &deletes.follows)
                 .await
                 .map_err(|e| error!("sql execute failed: {e}"))
                 .ok();
         }
 
-        if !likes_to_delete.is_empty() {
-            self.db
-                .execute(query(&format!(
-                    "DELETE FROM like WHERE uri IN ({})",
-                    likes_to_delete
-                        .iter()
-                        .map(|uri| format!("'{uri}'"))
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                )))
+        if !deletes.profiles.is_empty() {
+            Profile::delete(&self.db, &deletes.profiles)
                 .await
                 .map_err(|e| error!("sql execute failed: {e}"))
                 .ok();
         }
+    }
 
+    async fn index_op(
+        &self,
+        collection: &str,
+        repo: &str,
+        op: &RepoOp,
+        uri: &str,
+        record: &Value,
+        deletes: &mut PendingDeletes,
+    ) -> Result<()> {
+        let cid = format!("{}", op.cid.clone().map(|cid| cid.0).unwrap_or_default());
+        if op.action == "create" && self.caches.is_replayed_op(uri, &cid).await {
+            debug!("Skipped replayed create: {uri}");
+            return Ok(());
+        }
+        // archived sections take no new content; deletes still go through
+        if op.action == "create"
+            && matches!(
+                collection,
+                NSID_POST | NSID_COMMENT | NSID_REPLY | NSID_LIKE
+            )
+        {
+            let sections = self.caches.sections(Section::all(&self.db)).await?;
+            if in_archived_section(&sections, record) {
+                info!("Skipped create in an archived section: {uri}");
+                return Ok(());
+            }
+        }
+        match (collection, op.action.as_str()) {
+            (NSID_POST, "create" | "update") => {
+                debug!("{} post: {:?}", op.action, record);
+                Post::insert(&self.db, repo, record, uri, &cid)
+                    .await
+                    .map_err(|e| eyre!("Post::insert failed: {e}"))?;
+                self.index_parked(uri).await;
+            }
+            (NSID_POST, "delete") => {
+                deletes.posts.push(uri.to_string());
+                debug!("Marked post for deletion: {}", uri);
+            }
+            (NSID_COMMENT, "create" | "update") => {
+                debug!("{} comment: {:?}", op.action, record);
+                if let Err(e) = Comment::insert(&self.db, repo, record, uri, &cid).await {
+                    return self
+                        .park_or_fail(repo, uri, &cid, record, e)
+                        .await
+                        .map_err(|e| eyre!("Comment::insert failed: {e}"));
+                }
+                self.index_parked(uri).await;
+            }
+            (NSID_COMMENT, "delete") => {
+                deletes.comments.push(uri.to_string());
+                ParkedRecord::delete(&self.db, uri)
+                    .await
+                    .map_err(|e| error!("ParkedRecord::delete failed: {e}"))
+                    .ok();
+                debug!("Marked comment for deletion: {}", uri);
+            }
+            (NSID_REPLY, "create" | "update") => {
+                debug!("{} reply: {:?}", op.action, record);
+                if let Err(e) = Reply::insert(&self.db, repo, record, uri, &cid).await {
+                    return self
+                        .park_or_fail(repo, uri, &cid, record, e)
+                        .await
+                        .map_err(|e| eyre!("Reply::insert failed: {e}"));
+                }
+            }
+            (NSID_REPLY, "delete") => {
+                deletes.replies.push(uri.to_string());
+                ParkedRecord::delete(&self.db, uri)
+                    .await
+                    .map_err(|e| error!("ParkedRecord::delete failed: {e}"))
+                    .ok();
+                debug!("Marked reply for deletion: {}", uri);
+            }
+            (NSID_LIKE, "create" | "update") => {
+                debug!("{} like: {:?}", op.action, record);
+                Like::insert(&self.db, repo, record, uri, &cid)
+                    .await
+                    .map_err(|e| eyre!("Like::insert failed: {e}"))?;
+            }
+            (NSID_LIKE, "delete") => {
+                deletes.likes.push(uri.to_string());
+                debug!("Marked like for deletion: {}", uri);
+            }
+            (NSID_FOLLOW, "create" | "update") => {
+                debug!("{} follow: {:?}", op.action, record);
+                Follow::insert(&self.db, repo, record, uri, &cid)
+                    .await
+                    .map_err(|e| eyre!("Follow::insert failed: {e}"))?;
+            }
+            (NSID_FOLLOW, "delete") => {
+                deletes.follows.push(uri.to_string());
+                debug!("Marked follow for deletion: {}", uri);
+            }
+            (NSID_PROFILE, "create" | "update") => {
+                debug!("{} profile: {:?}", op.action, record);
+                Profile::insert(&self.db, repo, record, uri, &cid)
+                    .await
+                    .map_err(|e| eyre!("Profile::insert failed: {e}"))?;
+            }
+            (NSID_PROFILE, "delete") => {
+                deletes.profiles.push(repo.to_string());
+                debug!("Marked profile for deletion: {}", uri);
+            }
+            _ => return Ok(()),
+        }
+        if op.action == "create" {
+            self.caches.mark_indexed_op(uri, &cid).await;
+        }
+        Ok(())
+    }
+
+    /// Parks a comment or reply whose post or comment is not indexed yet,
+    /// as it may come later from another repo; other errors are returned.
+    async fn park_or_fail(
+        &self,
+        repo: &str,
+        uri: &str,
+        cid: &str,
+        record: &Value,
+        e: color_eyre::Report,
+    ) -> Result<()> {
+        let Some(awaits) = awaited_parent(&e) else {
+            return Err(e);
+        };
+        if self.park(awaits, repo, uri, cid, record).await? {
+            self.index_parked(awaits).await;
+        }
         Ok(())
     }
+
+    /// Parks the record until `awaits` is indexed. Whether `awaits` got
+    /// indexed meanwhile by another worker, which then found nothing parked.
+    async fn park(
+        &self,
+        awaits: &str,
+        repo: &str,
+        uri: &str,
+        cid: &str,
+        record: &Value,
+    ) -> Result<bool> {
+        ParkedRecord::park(&self.db, awaits, repo, uri, cid, record).await?;
+        info!("Parked {uri} until {awaits} is indexed");
+        is_indexed_uri(&self.db, awaits).await
+    }
+
+    /// Indexes the records parked until `uri` got indexed, then those that
+    /// were waiting for them in turn.
+    async fn index_parked(&self, uri: &str) {
+        let mut indexed = vec![uri.to_string()];
+        while let Some(uri) = indexed.pop() {
+            let parked = match ParkedRecord::take(&self.db, &uri).await {
+                Ok(parked) => parked,
+                Err(e) => {
+                    error!("ParkedRecord::take failed: {e}");
+                    continue;
+                }
+            };
+            for row in parked {
+                match self.unpark(&row).await {
+                    Ok(next) => indexed.extend(next),
+                    Err(e) => error!("FAILED: parked {}: {e}", row.uri),
+                }
+            }
+        }
+    }
+
+    /// Indexes a parked record, or parks it again when it still misses a
+    /// parent. The uri whose parked records to index next, if any.
+    async fn unpark(&self, row: &ParkedRow) -> Result<Option<String>> {
+        let record = row.record()?;
+        let inserted = match row.collection() {
+            NSID_COMMENT => Comment::insert(&self.db, &row.repo, &record, &row.uri, &row.cid).await,
+            NSID_REPLY => Reply::insert(&self.db, &row.repo, &record, &row.uri, &row.cid).await,
+            _ => return Ok(None),
+        };
+        match inserted {
+            Ok(()) => {
+                debug!("Indexed parked {}", row.uri);
+                self.caches.mark_indexed_op(&row.uri, &row.cid).await;
+                Ok(Some(row.uri.clone()))
+            }
+            Err(e) => {
+                let Some(awaits) = awaited_parent(&e) else {
+                    return Err(e);
+                };
+                let indexed = self
+                    .park(awaits, &row.repo, &row.uri, &row.cid, &record)
+                    .await?;
+                Ok(indexed.then(|| awaits.to_string()))
+            }
+        }
+    }
+}
+
+#[test]
+fn removed_repos_only_index_deletes() {
+    assert!(is_indexed("create", false));
+    assert!(is_indexed("update", false));
+    assert!(!is_indexed("create", true));
+    assert!(!is_indexed("update", true));
+    assert!(is_indexed("delete", true));
+    assert!(!is_indexed("sync", false));
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn replayed_commits_index_once_past_failing_ops() {
+    use atrium_api::com::atproto::sync::subscribe_repos::RepoOpData;
+    use serde_json::json;
+
+    let db = crate::db::test_pool().await;
+    let post = "at://did:ckb:alice/app.bbs.post/3kpost";
+    let created = chrono::Local::now().to_rfc3339();
+    Post::insert(
+        &db,
+        "did:ckb:alice",
+        &json!({ "section_id": "1", "title": "t", "text": "t", "created": created }),
+        post,
+        "bafy",
+    )
+    .await
+    .unwrap();
+    let state = AppView::for_tests(db.clone());
+    let op = |path: &str| -> RepoOp {
+        RepoOpData {
+            action: "create".to_string(),
+            cid: None,
+            path: path.to_string(),
+            prev: None,
+        }
+        .into()
+    };
+    let (like, comment) = (op("app.bbs.like/3klike"), op("app.bbs.comment/3kcomment"));
+    // the like has no target and fails, the comment after it goes in
+    let ops = [
+        (&like, json!({ "section_id": "1", "created": created })),
+        (
+            &comment,
+            json!({ "section_id": "1", "post": post, "text": "c", "created": created }),
+        ),
+    ];
+    let count = async |table: &str| -> i64 {
+        sqlx::query_scalar(&format!("SELECT count(*) FROM \"{table}\""))
+            .fetch_one(&db)
+            .await
+            .unwrap()
+    };
+
+    for _ in 0..2 {
+        state.index_ops("did:ckb:bob", &ops).await;
+        assert_eq!(count("like").await, 0);
+        assert_eq!(count("comment").await, 1);
+        assert_eq!(count("notify").await, 1);
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn replays_after_a_restart_notify_once() {
+    use atrium_api::com::atproto::sync::subscribe_repos::RepoOpData;
+    use serde_json::json;
+
+    let db = crate::db::test_pool().await;
+    let post = "at://did:ckb:alice/app.bbs.post/3kpost";
+    let created = chrono::Local::now().to_rfc3339();
+    Post::insert(
+        &db,
+        "did:ckb:alice",
+        &json!({ "section_id": "1", "title": "t", "text": "t", "created": created }),
+        post,
+        "bafy",
+    )
+    .await
+    .unwrap();
+    let op = |path: &str| -> RepoOp {
+        RepoOpData {
+            action: "create".to_string(),
+            cid: None,
+            path: path.to_string(),
+            prev: None,
+        }
+        .into()
+    };
+    let (comment, like) = (op("app.bbs.comment/3kcomment"), op("app.bbs.like/3klike"));
+    let ops = [
+        (
+            &comment,
+            json!({ "section_id": "1", "post": post, "text": "c", "created": created }),
+        ),
+        (
+            &like,
+            json!({ "section_id": "1", "to": post, "created": created }),
+        ),
+    ];
+    let count = async |table: &str| -> i64 {
+        sqlx::query_scalar(&format!("SELECT count(*) FROM \"{table}\""))
+            .fetch_one(&db)
+            .await
+            .unwrap()
+    };
+    let bumped = async || -> bool {
+        sqlx::query_scalar("SELECT updated > now() - interval '1 hour' FROM post")
+            .fetch_one(&db)
+            .await
+            .unwrap()
+    };
+
+    AppView::for_tests(db.clone())
+        .index_ops("did:ckb:bob", &ops)
+        .await;
+    assert_eq!(count("notify").await, 2);
+    assert!(bumped().await);
+
+    // a restarted appview has none of the ops it indexed in its caches
+    sqlx::query("UPDATE post SET updated = now() - interval '1 day'")
+        .execute(&db)
+        .await
+        .unwrap();
+    AppView::for_tests(db.clone())
+        .index_ops("did:ckb:bob", &ops)
+        .await;
+    assert_eq!(count("comment").await, 1);
+    assert_eq!(count("like").await, 1);
+    assert_eq!(count("notify").await, 2);
+    assert!(!bumped().await);
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn replies_before_their_post_wait_for_it() {
+    use atrium_api::com::atproto::sync::subscribe_repos::RepoOpData;
+    use serde_json::json;
+
+    let db = crate::db::test_pool().await;
+    let state = AppView::for_tests(db.clone());
+    let op = |path: &str| -> RepoOp {
+        RepoOpData {
+            action: "create".to_string(),
+            cid: None,
+            path: path.to_string(),
+            prev: None,
+        }
+        .into()
+    };
+    let created = chrono::Local::now().to_rfc3339();
+    let post = "at://did:ckb:alice/app.bbs.post/3kpost";
+    let comment = "at://did:ckb:bob/app.bbs.comment/3kcomment";
+    let count = async |table: &str| -> i64 {
+        sqlx::query_scalar(&format!("SELECT count(*) FROM \"{table}\""))
+            .fetch_one(&db)
+            .await
+            .unwrap()
+    };
+
+    // each repo goes to its own worker, so carol's reply and bob's comment
+    // may come before the post of alice they are on
+    let reply = op("app.bbs.reply/3kreply");
+    let record = json!({ "section_id": "1", "post": post, "comment": comment, "to": comment, "text": "r", "created": created });
+    state.index_ops("did:ckb:carol", &[(&reply, record)]).await;
+    let comment_op = op("app.bbs.comment/3kcomment");
+    let record = json!({ "section_id": "1", "post": post, "text": "c", "created": created });
+    state
+        .index_ops("did:ckb:bob", &[(&comment_op, record)])
+        .await;
+    assert_eq!(count("reply").await, 0);
+    assert_eq!(count("comment").await, 0);
+    assert_eq!(count("parked_record").await, 2);
+
+    // the post brings in the comment, and the comment the reply
+    let post_op = op("app.bbs.post/3kpost");
+    let record = json!({ "section_id": "1", "title": "t", "text": "t", "created": created });
+    state
+        .index_ops("did:ckb:alice", &[(&post_op, record)])
+        .await;
+    assert_eq!(count("post").await, 1);
+    assert_eq!(count("comment").await, 1);
+    assert_eq!(count("reply").await, 1);
+    assert_eq!(count("parked_record").await, 0);
+
+    // a parked record its author deleted is not indexed later
+    let orphan = op("app.bbs.comment/3korphan");
+    let record = json!({ "section_id": "1", "post": "at://did:ckb:alice/app.bbs.post/3klater", "text": "c", "created": created });
+    state.index_ops("did:ckb:bob", &[(&orphan, record)]).await;
+    assert_eq!(count("parked_record").await, 1);
+    let delete: RepoOp = RepoOpData {
+        action: "delete".to_string(),
+        cid: None,
+        path: "app.bbs.comment/3korphan".to_string(),
+        prev: None,
+    }
+    .into();
+    state
+        .index_ops("did:ckb:bob", &[(&delete, Value::Null)])
+        .await;
+    assert_eq!(count("parked_record").await, 0);
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL"]
+async fn inactive_accounts_are_hidden_and_deleted_ones_purged() {
+    use sqlx::{Executor, query};
+
+    let db = crate::db::test_pool().await;
+    for sql in [
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft) VALUES ('at://alice/post/1', 'bafy', 'did:plc:alice', 1, 't', 't', false), ('at://bob/post/1', 'bafy', 'did:plc:bob', 1, 't', 't', false)",
+        "INSERT INTO post (uri, cid, repo, section_id, title, text, is_draft, is_disabled, reasons_for_disabled) VALUES ('at://alice/post/2', 'bafy', 'did:plc:alice', 1, 't', 't', false, true, 'spam')",
+        "INSERT INTO comment (uri, cid, repo, section_id, post, text) VALUES ('at://bob/comment/1', 'bafy', 'did:plc:bob', 1, 'at://alice/post/1', 't'), ('at://alice/comment/1', 'bafy', 'did:plc:alice', 1, 'at://bob/post/1', 't')",
+        "INSERT INTO \"like\" (uri, cid, repo, section_id, \"to\") VALUES ('at://alice/like/1', 'bafy', 'did:plc:alice', 1, 'at://bob/post/1')",
+        "INSERT INTO notify (title, sender, receiver, n_type, target_uri) VALUES ('New Comment', 'did:plc:bob', 'did:plc:alice', 0, 'at://alice/post/1')",
+    ] {
+        db.execute(query(sql)).await.unwrap();
+    }
+    let state = AppView::for_tests(db.clone());
+    let account = |active: bool, status: Option<&str>| -> Account {
+        serde_json::from_value(serde_json::json!({
+            "active": active,
+            "did": "did:plc:alice",
+            "seq": 1,
+            "status": status,
+            "time": "2026-01-01T00:00:00.000Z",
+        }))
+        .unwrap()
+    };
+    let posts = async || -> Vec<(String, bool, Option<String>)> {
+        sqlx::query_as("SELECT uri, is_disabled, reasons_for_disabled FROM post ORDER BY uri")
+            .fetch_all(&db)
+            .await
+            .unwrap()
+    };
+    let count = async |table: &str| -> i64 {
+        sqlx::query_scalar(&format!("SELECT count(*) FROM {table}"))
+            .fetch_one(&db)
+            .await
+            .unwrap()
+    };
+
+    state
+        .handle_account(&account(false, Some("deactivated")))
+        .await
+        .unwrap();
+    assert_eq!(
+        posts().await,
+        [
+            (
+                "at://alice/post/1".to_string(),
+                true,
+                Some(ACCOUNT_INACTIVE.to_string())
+            ),
+            (
+                "at://alice/post/2".to_string(),
+                true,
+                Some("spam".to_string())
+            ),
+            ("at://bob/post/1".to_string(), false, None),
+        ]
+    );
+
+    // an active account is shown again, but not what moderators hid
+    state.handle_account(&account(true, None)).await.unwrap();
+    assert_eq!(
+        posts().await,
+        [
+            ("at://alice/post/1".to_string(), false, None),
+            (
+                "at://alice/post/2".to_string(),
+                true,
+                Some("spam".to_string())
+            ),
+            ("at://bob/post/1".to_string(), false, None),
+        ]
+    );
+
+    let tombstone: Tombstone = serde_json::from_value(serde_json::json!({
+        "did": "did:plc:alice",
+        "seq": 2,
+        "time": "2026-01-01T00:00:00.000Z",
+    }))
+    .unwrap();
+    state.handle_tombstone(&tombstone).await.unwrap();
+    assert_eq!(
+        posts().await,
+        [("at://bob/post/1".to_string(), false, None)]
+    );
+    // the comment of bob went with the thread of alice
+    assert_eq!(count("comment").await, 0);
+    assert_eq!(count("\"like\"").await, 0);
+    assert_eq!(count("notify").await, 0);
 }
@@ -1,11 +1,26 @@
-use atrium_api::com::atproto::sync::subscribe_repos::Commit;
+use atrium_api::{
+    com::atproto::sync::subscribe_repos::{Account, Commit, Identity},
+    types::string::{Datetime, Did},
+};
 use color_eyre::{Result, eyre::eyre};
 use futures::StreamExt;
+use serde::Deserialize;
 use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::net::TcpStream;
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async, tungstenite::Message};
+use tokio::sync::mpsc;
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream, connect_async_with_config, tungstenite::Message,
+};
 
-use crate::relayer::stream::Frame;
+use crate::{
+    config::ReconnectConfig,
+    relayer::{
+        health::RelayerHealth,
+        stream::Frame,
+        workers::{Handled, Job, Progress, Workers, work},
+    },
+};
 
 #[trait_variant::make(HttpService: Send)]
 pub trait Subscription {
@@ -14,6 +29,119 @@ pub trait Subscription {
 
 pub trait CommitHandler {
     fn handle_commit(&self, commit: &Commit) -> impl Future<Output = Result<()>>;
+
+    /// The handle, signing key or PDS of a DID changed.
+    fn handle_identity(&self, _identity: &Identity) -> impl Future<Output = Result<()>> {
+        async { Ok(()) }
+    }
+
+    /// An account was activated again, or deactivated, taken down,
+    /// suspended or deleted.
+    fn handle_account(&self, _account: &Account) -> impl Future<Output = Result<()>> {
+        async { Ok(()) }
+    }
+
+    /// A repo was deleted, as told by relayers older than `#account`.
+    fn handle_tombstone(&self, _tombstone: &Tombstone) -> impl Future<Output = Result<()>> {
+        async { Ok(()) }
+    }
+
+    /// Stores the seq of the last handled commit, where the next start
+    /// resumes.
+    fn save_cursor(&self, _seq: i64) -> impl Future<Output = Result<()>> {
+        async { Ok(()) }
+    }
+}
+
+/// The legacy `#tombstone` event, left out of `atrium_api` since relayers
+/// send an `#account` event with status `deleted` instead.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Tombstone {
+    pub did: Did,
+    pub seq: i64,
+    pub time: Datetime,
+}
+
+/// The events of an account besides its commits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RepoEvent {
+    Identity(Identity),
+    Account(Account),
+    Tombstone(Tombstone),
+}
+
+impl RepoEvent {
+    /// Decodes the body of a frame of type `t`; `None` for the types that
+    /// are not account events.
+    fn decode(t: &str, body: &[u8]) -> Result<Option<Self>> {
+        Ok(Some(match t {
+            "#identity" => Self::Identity(serde_ipld_dagcbor::from_slice(body)?),
+            "#account" => Self::Account(serde_ipld_dagcbor::from_slice(body)?),
+            "#tombstone" => Self::Tombstone(serde_ipld_dagcbor::from_slice(body)?),
+            _ => return Ok(None),
+        }))
+    }
+
+    pub(crate) fn seq(&self) -> i64 {
+        match self {
+            Self::Identity(identity) => identity.seq,
+            Self::Account(account) => account.seq,
+            Self::Tombstone(tombstone) => tombstone.seq,
+        }
+    }
+
+    pub(crate) fn did(&self) -> &str {
+        match self {
+            Self::Identity(identity) => identity.did.as_str(),
+            Self::Account(account) => account.did.as_str(),
+            Self::Tombstone(tombstone) => tombstone.did.as_str(),
+        }
+    }
+
+    pub(crate) async fn dispatch(&self, handler: &impl CommitHandler) -> Result<()> {
+        match self {
+            Self::Identity(identity) => handler.handle_identity(identity).await,
+            Self::Account(account) => handler.handle_account(account).await,
+            Self::Tombstone(tombstone) => handler.handle_tombstone(tombstone).await,
+        }
+    }
+}
+
+/// Saves the cursor through the handler every `every` commits rather than
+/// after each one.
+struct CursorSaver {
+    every: u64,
+    unsaved: u64,
+    seq: Option<i64>,
+}
+
+impl CursorSaver {
+    fn new(every: u64) -> Self {
+        Self {
+            every: every.max(1),
+            unsaved: 0,
+            seq: None,
+        }
+    }
+
+    async fn committed(&mut self, handler: &impl CommitHandler, seq: i64) {
+        self.seq = Some(seq);
+        self.unsaved += 1;
+        if self.unsaved >= self.every {
+            self.flush(handler).await;
+        }
+    }
+
+    /// Saves the last seq if it was not yet.
+    async fn flush(&mut self, handler: &impl CommitHandler) {
+        let Some(seq) = self.seq.filter(|_| self.unsaved > 0) else {
+            return;
+        };
+        match handler.save_cursor(seq).await {
+            Ok(()) => self.unsaved = 0,
+            Err(e) => error!("save relayer cursor {seq} failed: {e}"),
+        }
+    }
 }
 
 pub(crate) struct RepoSubscription {
@@ -21,41 +149,746 @@ pub(crate) struct RepoSubscription {
 }
 
 impl RepoSubscription {
-    pub async fn new(relayer: &str) -> Result<Self> {
-        let (stream, _) = connect_async(relayer).await?;
-        info!("Connected to relayer at {relayer}");
+    /// Subscribes at the live tip of `relayer`, or after the commit `cursor`.
+    pub async fn new(relayer: &str, cursor: Option<i64>) -> Result<Self> {
+        let url = with_cursor(relayer, cursor);
+        let (stream, _) = connect_async_with_config(url.as_str(), None, false).await?;
+        info!("Connected to relayer at {url}");
         Ok(RepoSubscription { stream })
     }
+}
 
-    pub async fn run(&mut self, handler: impl CommitHandler) -> Result<()> {
+/// `relayer` with the `cursor` parameter of `subscribeRepos`.
+fn with_cursor(relayer: &str, cursor: Option<i64>) -> String {
+    match cursor {
+        Some(cursor) if relayer.contains('?') => format!("{relayer}&cursor={cursor}"),
+        Some(cursor) => format!("{relayer}?cursor={cursor}"),
+        None => relayer.to_string(),
+    }
+}
+
+impl Subscription for RepoSubscription {
+    async fn next(&mut self) -> Option<Result<Frame>> {
+        loop {
+            match self.stream.next().await? {
+                Ok(Message::Binary(data)) => {
+                    return Some(Frame::try_from(data.iter().as_slice()));
+                }
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => continue,
+                Err(e) => return Some(Err(eyre!(e))),
+            }
+        }
+    }
+}
+
+/// Marks `sub` connected and hands its commits and account events to the
+/// workers of `handler` until the stream fails or closes, or returns `Ok`
+/// when a restart or shutdown is requested; the jobs already queued are
+/// handled before it returns.
+/// Events up to the cursor of `health` were handled on an earlier
+/// connection or run and are skipped when the relayer replays them.
+async fn run(
+    sub: &mut impl Subscription,
+    handler: &impl CommitHandler,
+    health: &RelayerHealth,
+    saver: &mut CursorSaver,
+    config: &ReconnectConfig,
+) -> Result<()> {
+    let mut restarts = health.restarts();
+    let handled = health.status().cursor;
+    health.connected();
+    let (workers, queues) = Workers::new(config.workers, config.worker_queue);
+    let (done, mut finished) = mpsc::unbounded_channel();
+    let mut progress = Progress::default();
+    let read = async {
+        // dropped when reading stops, so the workers drain their queues and end
+        let workers = workers;
         loop {
-            if let Some(message) = self.next().await {
-                match message {
-                    Ok(Frame::Message(Some(t), message)) => {
-                        if t.as_str() == "#commit" {
-                            let commit = serde_ipld_dagcbor::from_reader(message.body.as_slice())?;
-
-                            if let Err(err) = handler.handle_commit(&commit).await {
-                                error!("FAILED: {err:?}");
+            let message = tokio::select! {
+                message = sub.next() => message,
+                Some(job) = finished.recv() => {
+                    settle(job, &mut progress, handler, health, saver).await;
+                    health.queued(workers.queued());
+                    continue;
+                }
+                _ = restarts.changed() => {
+                    info!("Relayer restart requested.");
+                    return Ok(());
+                }
+                _ = health.shutting_down() => return Ok(()),
+            };
+            let job = match message {
+                Some(Ok(Frame::Message(Some(t), message))) => {
+                    health.received();
+                    if t.as_str() == "#commit" {
+                        match serde_ipld_dagcbor::from_reader(message.body.as_slice()) {
+                            Ok(commit) => Job::Commit(Box::new(commit)),
+                            Err(e) => return Err(eyre!(e)),
+                        }
+                    } else {
+                        // an event that does not decode must not drop the connection
+                        match RepoEvent::decode(&t, &message.body) {
+                            Ok(Some(event)) => Job::Event(event),
+                            Ok(None) => continue,
+                            Err(e) => {
+                                warn!("skip {t} frame that does not decode: {e}");
+                                continue;
                             }
                         }
                     }
-                    Ok(Frame::Message(None, _)) | Ok(Frame::Error(_)) => (),
-                    Err(e) => {
-                        return Err(eyre!("error {e}"));
+                }
+                Some(Ok(Frame::Message(None, _)) | Ok(Frame::Error(_))) => {
+                    health.received();
+                    continue;
+                }
+                Some(Err(e)) => {
+                    return Err(eyre!("error {e}"));
+                }
+                None => return Err(eyre!("relayer closed the connection")),
+            };
+            if handled.is_some_and(|seq| job.seq() <= seq) {
+                debug!("skip {} handled before", job.seq());
+                continue;
+            }
+            progress.dispatched(job.seq());
+            workers.dispatch(job).await;
+            health.queued(workers.queued());
+        }
+    };
+    let (result, ()) = tokio::join!(read, work(queues, handler, &done));
+    while let Ok(job) = finished.try_recv() {
+        settle(job, &mut progress, handler, health, saver).await;
+    }
+    health.queued(0);
+    result
+}
+
+/// Counts a job a worker finished, and moves the cursor as far as every
+/// job before it was handled too.
+async fn settle(
+    job: Handled,
+    progress: &mut Progress,
+    handler: &impl CommitHandler,
+    health: &RelayerHealth,
+    saver: &mut CursorSaver,
+) {
+    if let Some(records) = job.records {
+        health.committed(records);
+    }
+    if let Some(cursor) = progress.handled(job.seq) {
+        health.handled(cursor);
+        saver.committed(handler, cursor).await;
+    }
+}
+
+/// Keeps a subscription made by `connect` running and `health` up to date.
+/// `connect` gets the seq of the last handled commit, so a new connection
+/// resumes where the last one stopped; the handler saves it every
+/// `cursor_save_every` commits and when a connection ends. Connects again
+/// right away on a restart, after a growing backoff when the connection
+/// failed; after `max_retries` failures in a row it waits for a restart.
+/// Returns once `health` is shut down, with the cursor saved.
+pub async fn keep_subscribed<S, C>(
+    connect: impl Fn(Option<i64>) -> C,
+    handler: impl CommitHandler,
+    health: RelayerHealth,
+    config: ReconnectConfig,
+) where
+    S: Subscription,
+    C: Future<Output = Result<S>>,
+{
+    let mut failures = 0;
+    let mut saver = CursorSaver::new(config.cursor_save_every);
+    while !health.is_shut_down() {
+        health.connecting();
+        let connected = tokio::select! {
+            connected = connect(health.status().cursor) => connected,
+            _ = health.shutting_down() => break,
+        };
+        let error = match connected {
+            Ok(mut sub) => {
+                failures = 0;
+                let result = run(&mut sub, &handler, &health, &mut saver, &config).await;
+                saver.flush(&handler).await;
+                match result {
+                    Ok(_) => {
+                        health.disconnected(None);
+                        continue;
                     }
+                    Err(e) => e,
                 }
             }
+            Err(e) => e,
+        };
+        error!("{error}");
+        health.disconnected(Some(error.to_string()));
+
+        failures += 1;
+        let mut restarts = health.restarts();
+        if config.max_retries.is_some_and(|max| failures > max) {
+            error!(
+                "Gave up on the relayer after {failures} failed attempts, waiting for a restart."
+            );
+            tokio::select! {
+                _ = restarts.changed() => (),
+                _ = health.shutting_down() => break,
+            }
+            failures = 0;
+            continue;
+        }
+        let delay = backoff(&config, failures, jitter());
+        info!("Reconnecting to relayer in {delay:?}, attempt {failures}...");
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => (),
+            _ = restarts.changed() => (),
+            _ = health.shutting_down() => break,
         }
     }
+    health.disconnected(None);
+    info!("Relayer subscription stopped.");
 }
 
-impl Subscription for RepoSubscription {
-    async fn next(&mut self) -> Option<Result<Frame>> {
-        match self.stream.next().await {
-            Some(Ok(Message::Binary(data))) => Some(Frame::try_from(data.iter().as_slice())),
-            Some(Ok(_)) | None => None,
-            Some(Err(e)) => Some(Err(eyre!(e))),
+/// The wait before reconnect `attempt`, counted from 1: `initial_delay_ms`
+/// doubling up to `max_delay_secs`, less up to half of it by `jitter` in
+/// `[0, 1)` so that appviews dropped together do not come back together.
+fn backoff(config: &ReconnectConfig, attempt: u32, jitter: f64) -> Duration {
+    let delay = Duration::from_millis(config.initial_delay_ms)
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(Duration::from_secs(config.max_delay_secs));
+    delay.mul_f64(1.0 - jitter.clamp(0.0, 1.0) / 2.0)
+}
+
+/// Spread enough for reconnects, from the clock.
+fn jitter() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    f64::from(nanos % 1000) / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use common_x::restful::axum::{body::to_bytes, extract::State, response::IntoResponse};
+    use serde_json::Value;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        AppView,
+        relayer::{
+            health::{Connection, RelayerStatus},
+            stream::MessageFrame,
+        },
+    };
+
+    struct MockSubscription {
+        frames: mpsc::UnboundedReceiver<Result<Frame>>,
+    }
+
+    impl Subscription for MockSubscription {
+        async fn next(&mut self) -> Option<Result<Frame>> {
+            Some(
+                self.frames
+                    .recv()
+                    .await
+                    .unwrap_or_else(|| Err(eyre!("closed"))),
+            )
+        }
+    }
+
+    struct Commits;
+
+    impl CommitHandler for Commits {
+        async fn handle_commit(&self, _commit: &Commit) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    async fn wait_for(health: &RelayerHealth, f: impl Fn(&RelayerStatus) -> bool) {
+        let mut status = health.subscribe();
+        tokio::time::timeout(Duration::from_secs(5), status.wait_for(|status| f(status)))
+            .await
+            .expect("relayer state did not change")
+            .unwrap();
+    }
+
+    async fn relayer_status(state: &AppView) -> Value {
+        let response = crate::api::admin::relayer_status(State(state.clone()))
+            .await
+            .into_response();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut body: Value = serde_json::from_slice(&bytes).unwrap();
+        body["data"].take()
+    }
+
+    #[tokio::test]
+    async fn status_follows_the_subscription() {
+        let health = RelayerHealth::new("wss://relay.example", &Default::default());
+        let state = AppView {
+            relayer: health.clone(),
+            ..AppView::for_tests(
+                sqlx::postgres::PgPoolOptions::new()
+                    .connect_lazy("postgres://127.0.0.1:9/bbs")
+                    .unwrap(),
+            )
+        };
+        let (first, first_rx) = mpsc::unbounded_channel();
+        let (second, second_rx) = mpsc::unbounded_channel();
+        let subscriptions = Mutex::new(VecDeque::from([first_rx, second_rx]));
+        let connect = move |_cursor| {
+            let frames = subscriptions.lock().unwrap().pop_front();
+            async move {
+                frames
+                    .map(|frames| MockSubscription { frames })
+                    .ok_or_else(|| eyre!("no relayer"))
+            }
+        };
+        let task = tokio::spawn(keep_subscribed(
+            connect,
+            Commits,
+            health.clone(),
+            Default::default(),
+        ));
+
+        wait_for(&health, |status| status.connection == Connection::Connected).await;
+        let status = relayer_status(&state).await;
+        assert_eq!(status["connection"], "connected");
+        assert_eq!(status["endpoint"], "wss://relay.example");
+        assert_eq!(status["last_frame"], Value::Null);
+        assert_eq!(status["healthy"], true);
+
+        first
+            .send(Ok(Frame::Message(
+                Some("#identity".to_string()),
+                MessageFrame { body: vec![] },
+            )))
+            .unwrap();
+        wait_for(&health, |status| status.last_frame.is_some()).await;
+        assert_eq!(health.status().commits, 0);
+
+        // a restart drops the connection and connects again at once
+        health.restart();
+        wait_for(&health, |status| {
+            status.connections == 2 && status.connection == Connection::Connected
+        })
+        .await;
+        assert!(first.is_closed());
+
+        second.send(Err(eyre!("broken pipe"))).unwrap();
+        wait_for(&health, |status| status.connection != Connection::Connected).await;
+        let status = relayer_status(&state).await;
+        assert_eq!(status["last_error"], "error broken pipe");
+        assert_eq!(status["healthy"], false);
+
+        task.abort();
+    }
+
+    #[test]
+    fn backoffs_double_up_to_the_max() {
+        let config = ReconnectConfig {
+            initial_delay_ms: 500,
+            max_delay_secs: 10,
+            max_retries: None,
+            cursor_save_every: 100,
+            ..Default::default()
+        };
+        let delays: Vec<_> = (1..=7)
+            .map(|attempt| backoff(&config, attempt, 0.0).as_millis())
+            .collect();
+        assert_eq!(delays, [500, 1000, 2000, 4000, 8000, 10000, 10000]);
+        assert_eq!(backoff(&config, 2, 0.5), Duration::from_millis(750));
+        assert_eq!(backoff(&config, u32::MAX, 0.0), Duration::from_secs(10));
+        assert!((0.0..1.0).contains(&jitter()));
+
+        assert_eq!(
+            with_cursor("wss://relay.example/sub", None),
+            "wss://relay.example/sub"
+        );
+        assert_eq!(
+            with_cursor("wss://relay.example/sub", Some(7)),
+            "wss://relay.example/sub?cursor=7"
+        );
+        assert_eq!(
+            with_cursor("wss://relay.example/sub?x=1", Some(7)),
+            "wss://relay.example/sub?x=1&cursor=7"
+        );
+    }
+
+    struct Seqs(Arc<Mutex<Vec<i64>>>);
+
+    impl CommitHandler for Seqs {
+        async fn handle_commit(&self, commit: &Commit) -> Result<()> {
+            self.0.lock().unwrap().push(commit.seq);
+            Ok(())
+        }
+    }
+
+    fn commit_body(seq: i64) -> Vec<u8> {
+        let commit: Commit = serde_json::from_value(serde_json::json!({
+            "blobs": [],
+            "blocks": [],
+            "commit": { "$link": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm" },
+            "ops": [],
+            "rebase": false,
+            "repo": "did:plc:alice",
+            "rev": "3kaaaaaaaaaa2",
+            "seq": seq,
+            "time": "2026-01-01T00:00:00.000Z",
+            "tooBig": false,
+        }))
+        .unwrap();
+        serde_ipld_dagcbor::to_vec(&commit).unwrap()
+    }
+
+    fn commit_frame(seq: i64) -> Vec<u8> {
+        #[derive(serde::Serialize)]
+        struct Header {
+            op: i64,
+            t: &'static str,
+        }
+
+        let mut frame = serde_ipld_dagcbor::to_vec(&Header {
+            op: 1,
+            t: "#commit",
+        })
+        .unwrap();
+        frame.extend(commit_body(seq));
+        frame
+    }
+
+    /// A relayer that serves each of `connections` in turn, sending the
+    /// commits of that many seqs and closing; returns its url and the
+    /// request uris it got.
+    async fn relayer(connections: Vec<Vec<i64>>) -> (String, Arc<Mutex<Vec<String>>>) {
+        use futures::SinkExt;
+        use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "ws://{}/xrpc/com.atproto.sync.subscribeRepos",
+            listener.local_addr().unwrap()
+        );
+        let uris = Arc::new(Mutex::new(vec![]));
+        let seen = uris.clone();
+        tokio::spawn(async move {
+            for seqs in connections {
+                let (stream, _) = listener.accept().await.unwrap();
+                let seen = seen.clone();
+                let mut ws = tokio_tungstenite::accept_hdr_async(
+                    stream,
+                    move |req: &Request, res: Response| {
+                        seen.lock().unwrap().push(req.uri().to_string());
+                        Ok(res)
+                    },
+                )
+                .await
+                .unwrap();
+                for seq in seqs {
+                    ws.send(Message::Binary(commit_frame(seq).into()))
+                        .await
+                        .unwrap();
+                }
+                ws.close(None).await.ok();
+            }
+        });
+        (url, uris)
+    }
+
+    #[tokio::test]
+    async fn reconnects_and_resumes_after_the_relayer_closes() {
+        // the second connection replays the last commit of the first
+        let (url, uris) = relayer(vec![vec![1, 2], vec![2, 3]]).await;
+        let health = RelayerHealth::new(&url, &Default::default());
+        let seqs = Arc::new(Mutex::new(vec![]));
+        let connect = move |cursor| {
+            let url = url.clone();
+            async move { RepoSubscription::new(&url, cursor).await }
+        };
+        let config = ReconnectConfig {
+            initial_delay_ms: 10,
+            max_delay_secs: 1,
+            max_retries: Some(3),
+            cursor_save_every: 100,
+            ..Default::default()
+        };
+        let task = tokio::spawn(keep_subscribed(
+            connect,
+            Seqs(seqs.clone()),
+            health.clone(),
+            config,
+        ));
+
+        wait_for(&health, |status| status.cursor == Some(3)).await;
+        assert_eq!(*seqs.lock().unwrap(), [1, 2, 3]);
+        assert_eq!(health.status().commits, 3);
+        let uris = uris.lock().unwrap().clone();
+        assert_eq!(
+            uris,
+            [
+                "/xrpc/com.atproto.sync.subscribeRepos",
+                "/xrpc/com.atproto.sync.subscribeRepos?cursor=2",
+            ]
+        );
+        task.abort();
+    }
+
+    struct Saved(Arc<Mutex<Option<i64>>>);
+
+    impl CommitHandler for Saved {
+        async fn handle_commit(&self, _commit: &Commit) -> Result<()> {
+            Ok(())
+        }
+
+        async fn save_cursor(&self, seq: i64) -> Result<()> {
+            *self.0.lock().unwrap() = Some(seq);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn a_restart_resumes_from_the_saved_cursor() {
+        let saved = Arc::new(Mutex::new(None));
+        let subscribe = async |connections: Vec<Vec<i64>>, health: &RelayerHealth| {
+            let (url, uris) = relayer(connections).await;
+            let connect = move |cursor| {
+                let url = url.clone();
+                async move { RepoSubscription::new(&url, cursor).await }
+            };
+            let config = ReconnectConfig {
+                initial_delay_ms: 10,
+                max_delay_secs: 1,
+                max_retries: Some(3),
+                cursor_save_every: 2,
+                ..Default::default()
+            };
+            let task = tokio::spawn(keep_subscribed(
+                connect,
+                Saved(saved.clone()),
+                health.clone(),
+                config,
+            ));
+            (task, uris)
+        };
+
+        // 2 is saved on the way, 3 when the relayer closes
+        let health = RelayerHealth::new("", &Default::default());
+        let (task, _) = subscribe(vec![vec![1, 2, 3]], &health).await;
+        wait_for(&health, |status| status.cursor == Some(3)).await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while *saved.lock().unwrap() != Some(3) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("cursor was not saved");
+        task.abort();
+
+        // a new run starts from what the last one saved
+        let health = RelayerHealth::new("", &Default::default());
+        health.resume_from(*saved.lock().unwrap());
+        let (task, uris) = subscribe(vec![vec![3, 4]], &health).await;
+        wait_for(&health, |status| status.cursor == Some(4)).await;
+        assert_eq!(health.status().commits, 1);
+        assert_eq!(
+            *uris.lock().unwrap(),
+            ["/xrpc/com.atproto.sync.subscribeRepos?cursor=3"]
+        );
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn a_shutdown_saves_the_cursor_and_stops() {
+        let health = RelayerHealth::new("wss://relay.example", &Default::default());
+        let saved = Arc::new(Mutex::new(None));
+        let (frames, frames_rx) = mpsc::unbounded_channel();
+        let subscription = Mutex::new(Some(frames_rx));
+        let connect = move |_cursor| {
+            let frames = subscription.lock().unwrap().take();
+            async move {
+                frames
+                    .map(|frames| MockSubscription { frames })
+                    .ok_or_else(|| eyre!("no relayer"))
+            }
+        };
+        let config = ReconnectConfig {
+            cursor_save_every: 100,
+            ..Default::default()
+        };
+        let task = tokio::spawn(keep_subscribed(
+            connect,
+            Saved(saved.clone()),
+            health.clone(),
+            config,
+        ));
+
+        frames
+            .send(Ok(Frame::Message(
+                Some("#commit".to_string()),
+                MessageFrame {
+                    body: commit_body(7),
+                },
+            )))
+            .unwrap();
+        wait_for(&health, |status| status.cursor == Some(7)).await;
+        assert_eq!(*saved.lock().unwrap(), None);
+
+        health.shut_down();
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .expect("subscription did not stop")
+            .unwrap();
+        assert_eq!(*saved.lock().unwrap(), Some(7));
+        assert_eq!(health.status().connection, Connection::Disconnected);
+
+        // stopped for good, even when shut down before it connects
+        let task = tokio::spawn(keep_subscribed(
+            |_cursor| async { Err::<MockSubscription, _>(eyre!("no relayer")) },
+            Commits,
+            health.clone(),
+            Default::default(),
+        ));
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .expect("subscription did not stop")
+            .unwrap();
+    }
+
+    /// `{"t": "#identity", "op": 1}` and the event of `did:plc:alice`
+    /// taking the handle `alice.example.com`, at seq 11.
+    const IDENTITY_FRAME: &str = "a2617469236964656e74697479626f7001a4636469646d6469643a706c633a616c696365637365710b6474696d657818323032362d30312d30315430303a30303a30302e3030305a6668616e646c6571616c6963652e6578616d706c652e636f6d";
+    /// `{"t": "#account", "op": 1}` and `did:plc:alice` deactivated, at
+    /// seq 12.
+    const ACCOUNT_FRAME: &str = "a2617468236163636f756e74626f7001a5636469646d6469643a706c633a616c696365637365710c6474696d657818323032362d30312d30315430303a30303a30302e3030305a66616374697665f4667374617475736b6465616374697661746564";
+    /// `{"t": "#tombstone", "op": 1}` and `did:plc:alice` deleted, at seq 13.
+    const TOMBSTONE_FRAME: &str = "a261746a23746f6d6273746f6e65626f7001a3636469646d6469643a706c633a616c696365637365710d6474696d657818323032362d30312d30315430303a30303a30302e3030305a";
+
+    fn frame(hex: &str) -> Frame {
+        Frame::try_from(hex::decode(hex).unwrap().as_slice()).unwrap()
+    }
+
+    struct Events(Arc<Mutex<Vec<String>>>);
+
+    impl CommitHandler for Events {
+        async fn handle_commit(&self, commit: &Commit) -> Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("commit {}", commit.seq));
+            Ok(())
+        }
+
+        async fn handle_identity(&self, identity: &Identity) -> Result<()> {
+            let handle = identity.handle.as_ref().map(|handle| handle.as_str());
+            self.0.lock().unwrap().push(format!(
+                "identity {} {}",
+                identity.did.as_str(),
+                handle.unwrap_or_default()
+            ));
+            Ok(())
+        }
+
+        async fn handle_account(&self, account: &Account) -> Result<()> {
+            self.0.lock().unwrap().push(format!(
+                "account {} {} {}",
+                account.did.as_str(),
+                account.active,
+                account.status.as_deref().unwrap_or_default()
+            ));
+            Ok(())
+        }
+
+        async fn handle_tombstone(&self, tombstone: &Tombstone) -> Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("tombstone {}", tombstone.did.as_str()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn account_events_decode() {
+        let Frame::Message(Some(t), message) = frame(ACCOUNT_FRAME) else {
+            panic!("not a message frame");
+        };
+        assert_eq!(t, "#account");
+        let Some(RepoEvent::Account(account)) = RepoEvent::decode(&t, &message.body).unwrap()
+        else {
+            panic!("not an account event");
+        };
+        assert_eq!(account.did.as_str(), "did:plc:alice");
+        assert!(!account.active);
+        assert_eq!(account.status.as_deref(), Some("deactivated"));
+        assert_eq!(account.seq, 12);
+
+        for (hex, seq) in [(IDENTITY_FRAME, 11), (TOMBSTONE_FRAME, 13)] {
+            let Frame::Message(Some(t), message) = frame(hex) else {
+                panic!("not a message frame");
+            };
+            let event = RepoEvent::decode(&t, &message.body).unwrap().unwrap();
+            assert_eq!(event.seq(), seq);
+        }
+        assert_eq!(RepoEvent::decode("#info", &[]).unwrap(), None);
+        assert!(RepoEvent::decode("#account", &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn account_events_reach_the_handler_and_move_the_cursor() {
+        let health = RelayerHealth::new("wss://relay.example", &Default::default());
+        health.resume_from(Some(10));
+        let events = Arc::new(Mutex::new(vec![]));
+        let (frames, frames_rx) = mpsc::unbounded_channel();
+        let subscription = Mutex::new(Some(frames_rx));
+        let connect = move |_cursor| {
+            let frames = subscription.lock().unwrap().take();
+            async move {
+                frames
+                    .map(|frames| MockSubscription { frames })
+                    .ok_or_else(|| eyre!("no relayer"))
+            }
+        };
+        let task = tokio::spawn(keep_subscribed(
+            connect,
+            Events(events.clone()),
+            health.clone(),
+            Default::default(),
+        ));
+
+        // one that does not decode is skipped and keeps the connection
+        frames
+            .send(Ok(Frame::Message(
+                Some("#account".to_string()),
+                MessageFrame { body: vec![] },
+            )))
+            .unwrap();
+        for hex in [IDENTITY_FRAME, ACCOUNT_FRAME, TOMBSTONE_FRAME] {
+            frames.send(Ok(frame(hex))).unwrap();
         }
+        frames
+            .send(Ok(Frame::Message(
+                Some("#commit".to_string()),
+                MessageFrame {
+                    body: commit_body(14),
+                },
+            )))
+            .unwrap();
+        wait_for(&health, |status| status.cursor == Some(14)).await;
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "identity did:plc:alice alice.example.com",
+                "account did:plc:alice false deactivated",
+                "tombstone did:plc:alice",
+                "commit 14",
+            ]
+        );
+        let status = health.status();
+        assert_eq!(status.commits, 1);
+        assert_eq!(status.connections, 1);
+        task.abort();
     }
 }
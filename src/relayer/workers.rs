@@ -0,0 +1,289 @@
+use std::collections::BTreeSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use atrium_api::com::atproto::sync::subscribe_repos::Commit;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::relayer::subscription::{CommitHandler, RepoEvent};
+
+/// A commit or account event, for the worker of its repo.
+#[derive(Debug)]
+pub(crate) enum Job {
+    Commit(Box<Commit>),
+    Event(RepoEvent),
+}
+
+impl Job {
+    pub fn repo(&self) -> &str {
+        match self {
+            Self::Commit(commit) => commit.repo.as_str(),
+            Self::Event(event) => event.did(),
+        }
+    }
+
+    pub fn seq(&self) -> i64 {
+        match self {
+            Self::Commit(commit) => commit.seq,
+            Self::Event(event) => event.seq(),
+        }
+    }
+
+    /// Failures are logged; the job counts as handled either way.
+    async fn handle(&self, handler: &impl CommitHandler) -> Handled {
+        let (result, records) = match self {
+            Self::Commit(commit) => (handler.handle_commit(commit).await, Some(commit.ops.len())),
+            Self::Event(event) => (event.dispatch(handler).await, None),
+        };
+        if let Err(err) = result {
+            error!("FAILED: {} {err:?}", self.seq());
+        }
+        Handled {
+            seq: self.seq(),
+            records,
+        }
+    }
+}
+
+/// A job a worker finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Handled {
+    pub seq: i64,
+    /// Record operations of a commit; `None` for an account event.
+    pub records: Option<usize>,
+}
+
+/// Spreads jobs over workers by repo: the jobs of one repo are handled one
+/// after the other in the order they came, those of different repos at the
+/// same time. Each worker queues a bounded number of jobs, and `dispatch`
+/// waits while the queue of the repo is full, which holds the websocket
+/// reader back.
+pub(crate) struct Workers {
+    queues: Vec<mpsc::Sender<Job>>,
+}
+
+impl Workers {
+    /// `workers` queues of `capacity` jobs each, and their receiving ends
+    /// for `work`.
+    pub fn new(workers: usize, capacity: usize) -> (Self, Vec<mpsc::Receiver<Job>>) {
+        let (queues, receivers) = (0..workers.max(1))
+            .map(|_| mpsc::channel(capacity.max(1)))
+            .unzip();
+        (Self { queues }, receivers)
+    }
+
+    fn worker_of(&self, repo: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        repo.hash(&mut hasher);
+        (hasher.finish() % self.queues.len() as u64) as usize
+    }
+
+    /// Jobs waiting for a worker, over all queues.
+    pub fn queued(&self) -> usize {
+        self.queues
+            .iter()
+            .map(|queue| queue.max_capacity() - queue.capacity())
+            .sum()
+    }
+
+    /// Queues `job` for the worker of its repo. The workers only stop once
+    /// `self` is dropped, so a job is never refused.
+    pub async fn dispatch(&self, job: Job) {
+        let queue = &self.queues[self.worker_of(job.repo())];
+        let job = match queue.try_send(job) {
+            Ok(()) => return,
+            Err(TrySendError::Full(job)) => job,
+            Err(TrySendError::Closed(job)) => {
+                error!("relayer workers stopped, dropped {}", job.seq());
+                return;
+            }
+        };
+        debug!(
+            "worker queue of {} is full, holding the relayer back",
+            job.repo()
+        );
+        if let Err(e) = queue.send(job).await {
+            error!("relayer workers stopped, dropped {}", e.0.seq());
+        }
+    }
+}
+
+/// Runs a worker on each of `queues` until their `Workers` is dropped and
+/// the queues are drained, telling `done` of every job handled.
+pub(crate) async fn work(
+    queues: Vec<mpsc::Receiver<Job>>,
+    handler: &impl CommitHandler,
+    done: &mpsc::UnboundedSender<Handled>,
+) {
+    futures::future::join_all(queues.into_iter().map(|mut queue| async move {
+        while let Some(job) = queue.recv().await {
+            done.send(job.handle(handler).await).ok();
+        }
+    }))
+    .await;
+}
+
+/// The seqs dispatched and not handled yet. Jobs finish out of order, so
+/// the cursor only moves past a seq once every seq before it was handled
+/// too; a restart then never skips a job that was still queued.
+#[derive(Debug, Default)]
+pub(crate) struct Progress {
+    pending: BTreeSet<i64>,
+    handled: BTreeSet<i64>,
+}
+
+impl Progress {
+    pub fn dispatched(&mut self, seq: i64) {
+        self.pending.insert(seq);
+    }
+
+    /// Marks `seq` handled; returns where the cursor moves, if it does.
+    pub fn handled(&mut self, seq: i64) -> Option<i64> {
+        self.pending.remove(&seq);
+        self.handled.insert(seq);
+        let cursor = match self.pending.first() {
+            Some(first) => self.handled.range(..first).next_back(),
+            None => self.handled.last(),
+        }
+        .copied()?;
+        self.handled = self.handled.split_off(&(cursor + 1));
+        Some(cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use color_eyre::Result;
+    use tokio::sync::Notify;
+
+    use super::*;
+
+    fn commit(repo: &str, seq: i64) -> Job {
+        let commit: Commit = serde_json::from_value(serde_json::json!({
+            "blobs": [],
+            "blocks": [],
+            "commit": { "$link": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm" },
+            "ops": [],
+            "rebase": false,
+            "repo": repo,
+            "rev": "3kaaaaaaaaaa2",
+            "seq": seq,
+            "time": "2026-01-01T00:00:00.000Z",
+            "tooBig": false,
+        }))
+        .unwrap();
+        Job::Commit(Box::new(commit))
+    }
+
+    /// Records the seqs it handled per repo; the commits of `slow` wait
+    /// until `release` is notified.
+    struct Recorder {
+        seqs: Arc<Mutex<HashMap<String, Vec<i64>>>>,
+        slow: String,
+        release: Arc<Notify>,
+    }
+
+    impl CommitHandler for Recorder {
+        async fn handle_commit(&self, commit: &Commit) -> Result<()> {
+            let repo = commit.repo.as_str();
+            if repo == self.slow {
+                self.release.notified().await;
+            } else {
+                // finish out of dispatch order now and then
+                tokio::time::sleep(Duration::from_millis((commit.seq % 3) as u64)).await;
+            }
+            self.seqs
+                .lock()
+                .unwrap()
+                .entry(repo.to_string())
+                .or_default()
+                .push(commit.seq);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn repos_are_ordered_and_handled_concurrently() {
+        let (workers, queues) = Workers::new(4, 8);
+        let slow = "did:plc:slow";
+        let repos: Vec<String> = (0..)
+            .map(|i| format!("did:plc:fast{i}"))
+            .filter(|repo| workers.worker_of(repo) != workers.worker_of(slow))
+            .take(2)
+            .collect();
+        let release = Arc::new(Notify::new());
+        let recorder = Recorder {
+            seqs: Default::default(),
+            slow: slow.to_string(),
+            release: release.clone(),
+        };
+        let (done, mut finished) = mpsc::unbounded_channel();
+
+        let dispatch = async {
+            workers.dispatch(commit(slow, 1)).await;
+            for seq in 2..=21 {
+                let repo = &repos[seq as usize % 2];
+                workers.dispatch(commit(repo, seq)).await;
+            }
+            // the slow repo holds up none of the others
+            let mut progress = Progress::default();
+            (1..=21).for_each(|seq| progress.dispatched(seq));
+            for _ in 2..=21 {
+                let handled = tokio::time::timeout(Duration::from_secs(5), finished.recv())
+                    .await
+                    .expect("a slow repo stalled the others")
+                    .unwrap();
+                assert_ne!(handled.seq, 1);
+                assert_eq!(handled.records, Some(0));
+                assert_eq!(progress.handled(handled.seq), None);
+            }
+            release.notify_one();
+            let handled = finished.recv().await.unwrap();
+            assert_eq!(handled.seq, 1);
+            assert_eq!(progress.handled(1), Some(21));
+            drop(workers);
+        };
+        tokio::join!(dispatch, work(queues, &recorder, &done));
+
+        let seqs = recorder.seqs.lock().unwrap();
+        assert_eq!(seqs[slow], [1]);
+        for (i, repo) in repos.iter().enumerate() {
+            let expected: Vec<i64> = (2..=21).filter(|seq| *seq as usize % 2 == i).collect();
+            assert_eq!(seqs[repo], expected, "{repo}");
+        }
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_holds_the_dispatcher_back() {
+        let (workers, mut queues) = Workers::new(1, 2);
+        workers.dispatch(commit("did:plc:alice", 1)).await;
+        workers.dispatch(commit("did:plc:bob", 2)).await;
+        assert_eq!(workers.queued(), 2);
+        let third = workers.dispatch(commit("did:plc:alice", 3));
+        tokio::pin!(third);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), &mut third)
+                .await
+                .is_err()
+        );
+        assert_eq!(queues[0].recv().await.unwrap().seq(), 1);
+        third.await;
+        assert_eq!(workers.queued(), 2);
+    }
+
+    #[test]
+    fn the_cursor_waits_for_earlier_seqs() {
+        let mut progress = Progress::default();
+        for seq in [3, 5, 8] {
+            progress.dispatched(seq);
+        }
+        assert_eq!(progress.handled(5), None);
+        assert_eq!(progress.handled(3), Some(5));
+        progress.dispatched(9);
+        assert_eq!(progress.handled(9), None);
+        assert_eq!(progress.handled(8), Some(9));
+    }
+}
@@ -0,0 +1,384 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::sync::mpsc;
+
+use crate::{
+    AppView,
+    api::author_handle,
+    atproto::{NSID_COMMENT, NSID_POST, NSID_REPLY},
+    lexicon::webhook::{Webhook, WebhookDelivery, WebhookDeliveryRow, WebhookEvent, WebhookRow},
+};
+
+/// Hex HMAC-SHA256 of the request body, keyed with the webhook secret.
+pub const SIGNATURE_HEADER: &str = "X-BBS-Signature";
+pub const EVENT_HEADER: &str = "X-BBS-Event";
+pub const MAX_ATTEMPTS: u32 = 5;
+const BACKOFF: Duration = Duration::from_secs(2);
+const SNIPPET_CHARS: usize = 140;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    pub uri: String,
+    pub post: Option<String>,
+    pub section_id: i32,
+    pub author: String,
+    pub author_handle: Option<String>,
+    pub title: Option<String>,
+    pub snippet: String,
+    pub created: DateTime<Local>,
+}
+
+impl WebhookPayload {
+    /// The creation event of an indexed post, comment or reply record.
+    pub fn created(nsid: &str, repo: &str, uri: &str, record: &Value) -> Option<Self> {
+        let event = match nsid {
+            NSID_POST => WebhookEvent::PostCreated,
+            NSID_COMMENT => WebhookEvent::CommentCreated,
+            NSID_REPLY => WebhookEvent::ReplyCreated,
+            _ => return None,
+        };
+        Some(Self {
+            event,
+            uri: uri.to_string(),
+            post: record["post"].as_str().map(str::to_string),
+            section_id: record["section_id"]
+                .as_str()
+                .and_then(|s| s.parse::<i32>().ok())
+                .unwrap_or_default(),
+            author: repo.to_string(),
+            author_handle: None,
+            title: record["title"].as_str().map(str::to_string),
+            snippet: snippet(record["text"].as_str().unwrap_or_default()),
+            created: Local::now(),
+        })
+    }
+
+    /// A moderator hid or restored content authored by `author`. The
+    /// snippet stays empty: neither hidden content nor the moderator's
+    /// reasons leave the forum.
+    pub fn moderated(uri: &str, section_id: i32, author: &str) -> Self {
+        Self {
+            event: WebhookEvent::Moderated,
+            uri: uri.to_string(),
+            post: None,
+            section_id,
+            author: author.to_string(),
+            author_handle: None,
+            title: None,
+            snippet: String::new(),
+            created: Local::now(),
+        }
+    }
+}
+
+fn snippet(text: &str) -> String {
+    text.chars().take(SNIPPET_CHARS).collect()
+}
+
+/// Queues payloads for the delivery task, so handlers never wait on
+/// webhook endpoints.
+#[derive(Debug, Clone)]
+pub struct Webhooks {
+    tx: mpsc::UnboundedSender<WebhookPayload>,
+}
+
+impl Webhooks {
+    pub fn channel() -> (Self, mpsc::UnboundedReceiver<WebhookPayload>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Self { tx }, rx)
+    }
+
+    pub fn emit(&self, payload: WebhookPayload) {
+        if let Err(e) = self.tx.send(payload) {
+            warn!("webhook delivery task is gone, dropped {:?}", e.0.event);
+        }
+    }
+}
+
+pub async fn run(state: AppView, mut rx: mpsc::UnboundedReceiver<WebhookPayload>) {
+    let client = reqwest::Client::new();
+    while let Some(mut payload) = rx.recv().await {
+        let hooks = match Webhook::matching(&state.db, payload.event, payload.section_id).await {
+            Ok(hooks) => hooks,
+            Err(e) => {
+                error!("select webhooks failed: {e}");
+                continue;
+            }
+        };
+        if hooks.is_empty() {
+            continue;
+        }
+        payload.author_handle = author_handle(&state, &payload.author).await;
+        let body = match serde_json::to_string(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("encode webhook payload failed: {e}");
+                continue;
+            }
+        };
+        for hook in hooks {
+            let db = state.db.clone();
+            let client = client.clone();
+            let body = body.clone();
+            let event = payload.event;
+            tokio::spawn(async move {
+                let delivery = deliver(&client, &hook, event, &body, BACKOFF).await;
+                log_delivery(&db, &hook, event, body, delivery).await;
+            });
+        }
+    }
+}
+
+async fn log_delivery(
+    db: &sqlx::Pool<sqlx::Postgres>,
+    hook: &WebhookRow,
+    event: WebhookEvent,
+    payload: String,
+    delivery: Delivery,
+) {
+    if !delivery.delivered() {
+        warn!(
+            "webhook {} failed after {} attempts: {:?}",
+            hook.id, delivery.attempts, delivery.error
+        );
+    }
+    Webhook::record_result(db, hook.id, delivery.delivered())
+        .await
+        .map_err(|e| error!("Webhook::record_result failed: {e}"))
+        .ok();
+    WebhookDelivery::insert(
+        db,
+        &WebhookDeliveryRow {
+            id: 0,
+            webhook_id: hook.id,
+            event: event.name().to_string(),
+            payload,
+            status_code: delivery.status_code.map(i32::from),
+            attempts: delivery.attempts as i32,
+            error: delivery.error,
+            created: Local::now(),
+        },
+    )
+    .await
+    .map_err(|e| error!("WebhookDelivery::insert failed: {e}"))
+    .ok();
+}
+
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[derive(Debug)]
+pub struct Delivery {
+    pub status_code: Option<u16>,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+impl Delivery {
+    pub const fn delivered(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// POST the signed body, retrying non-2xx responses and transport errors up
+/// to `MAX_ATTEMPTS` times with exponential backoff starting at `backoff`.
+pub async fn deliver(
+    client: &reqwest::Client,
+    hook: &WebhookRow,
+    event: WebhookEvent,
+    body: &str,
+    backoff: Duration,
+) -> Delivery {
+    let signature = sign(&hook.secret, body);
+    let mut delivery = Delivery {
+        status_code: None,
+        attempts: 0,
+        error: None,
+    };
+    while delivery.attempts < MAX_ATTEMPTS {
+        if delivery.attempts > 0 {
+            tokio::time::sleep(backoff * 2u32.pow(delivery.attempts - 1)).await;
+        }
+        delivery.attempts += 1;
+        let result = client
+            .post(&hook.url)
+            .header("Content-Type", "application/json; charset=utf-8")
+            .header(SIGNATURE_HEADER, &signature)
+            .header(EVENT_HEADER, event.name())
+            .body(body.to_string())
+            .timeout(Duration::from_secs(5))
+            .send()
+            .await;
+        match result {
+            Ok(response) => {
+                let status = response.status();
+                delivery.status_code = Some(status.as_u16());
+                if status.is_success() {
+                    delivery.error = None;
+                    return delivery;
+                }
+                delivery.error = Some(format!("endpoint responded {status}"));
+            }
+            Err(e) => {
+                delivery.status_code = None;
+                delivery.error = Some(format!("call webhook failed: {e}"));
+            }
+        }
+    }
+    delivery
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    use common_x::restful::axum::{
+        Router,
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+    };
+
+    use super::*;
+
+    const SECRET: &str = "s3cret";
+
+    /// Answers 500 to the first `fail_first` requests, then checks the
+    /// signature of the body.
+    async fn mock_receiver(fail_first: u32) -> (String, Arc<AtomicU32>) {
+        async fn receive(
+            State((fail_first, calls)): State<(u32, Arc<AtomicU32>)>,
+            headers: HeaderMap,
+            body: String,
+        ) -> StatusCode {
+            if calls.fetch_add(1, Ordering::SeqCst) < fail_first {
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+            let signature = headers
+                .get(SIGNATURE_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            if signature == sign(SECRET, &body) {
+                StatusCode::OK
+            } else {
+                StatusCode::UNAUTHORIZED
+            }
+        }
+
+        let calls = Arc::new(AtomicU32::new(0));
+        let router = Router::new()
+            .route("/hook", post(receive))
+            .with_state((fail_first, calls.clone()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            common_x::restful::axum::serve(listener, router).await.ok();
+        });
+        (format!("http://{addr}/hook"), calls)
+    }
+
+    fn hook(url: String, secret: &str) -> WebhookRow {
+        WebhookRow {
+            id: 1,
+            url,
+            secret: secret.to_string(),
+            events: WebhookEvent::PostCreated.mask(),
+            section_id: 0,
+            enabled: true,
+            failures: 0,
+            updated: Local::now(),
+            created: Local::now(),
+        }
+    }
+
+    fn body() -> String {
+        let record = serde_json::json!({
+            "section_id": "1",
+            "title": "hello",
+            "text": "world",
+        });
+        let payload = WebhookPayload::created(
+            NSID_POST,
+            "did:ckb:alice",
+            "at://did:ckb:alice/app.bbs.post/3mbnwjdssbc27",
+            &record,
+        )
+        .unwrap();
+        serde_json::to_string(&payload).unwrap()
+    }
+
+    #[tokio::test]
+    async fn delivery_is_signed_and_retried() {
+        let (url, calls) = mock_receiver(2).await;
+        let client = reqwest::Client::new();
+        let delivery = deliver(
+            &client,
+            &hook(url, SECRET),
+            WebhookEvent::PostCreated,
+            &body(),
+            Duration::from_millis(1),
+        )
+        .await;
+        assert!(delivery.delivered(), "{delivery:?}");
+        assert_eq!(delivery.status_code, Some(200));
+        assert_eq!(delivery.attempts, 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn delivery_gives_up_after_max_attempts() {
+        // a wrong secret is rejected on every attempt
+        let (url, calls) = mock_receiver(0).await;
+        let client = reqwest::Client::new();
+        let delivery = deliver(
+            &client,
+            &hook(url, "wrong"),
+            WebhookEvent::PostCreated,
+            &body(),
+            Duration::from_millis(1),
+        )
+        .await;
+        assert!(!delivery.delivered());
+        assert_eq!(delivery.status_code, Some(401));
+        assert_eq!(delivery.attempts, MAX_ATTEMPTS);
+        assert_eq!(calls.load(Ordering::SeqCst), MAX_ATTEMPTS);
+    }
+
+    #[test]
+    fn payload_carries_event_and_snippet() {
+        let record = serde_json::json!({
+            "section_id": "2",
+            "post": "at://did:ckb:alice/app.bbs.post/3mbnwjdssbc27",
+            "text": "x".repeat(500),
+        });
+        let payload = WebhookPayload::created(
+            NSID_COMMENT,
+            "did:ckb:bob",
+            "at://did:ckb:bob/app.bbs.comment/3mbnwjdssbc28",
+            &record,
+        )
+        .unwrap();
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["event"], "comment_created");
+        assert_eq!(json["section_id"], 2);
+        assert_eq!(payload.snippet.chars().count(), SNIPPET_CHARS);
+        assert!(WebhookPayload::created("app.bbs.like", "did:ckb:bob", "", &record).is_none());
+
+        let payload =
+            WebhookPayload::moderated("at://did:ckb:bob/app.bbs.comment/3m", 2, "did:ckb:bob");
+        assert!(payload.snippet.is_empty());
+    }
+}